@@ -0,0 +1,174 @@
+use futures_util::FutureExt;
+use crate::cells::{CellTypes, ChunkCell, TextRange};
+use crate::execution::execution::execution_graph::ExecutionNodeId;
+use crate::execution::primitives::operation::{InputItemConfiguration, InputSignature, OperationFn, OperationFnOutput, OperationNode, OutputItemConfiguration, OutputSignature};
+use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue};
+use crate::library::std::chunk::chunk_text;
+
+/// Name the chunks are exposed as when the chunk cell is not named
+const DEFAULT_CHUNKS_NAME: &str = "chunks";
+
+fn chunks_name(cell: &ChunkCell) -> String {
+    cell.name.clone().unwrap_or_else(|| DEFAULT_CHUNKS_NAME.to_string())
+}
+
+/// Chunk cells split text into an array of `{text, index, start_offset, end_offset, heading_path}`.
+/// Given a source the text is chunked whenever that value changes, otherwise a named cell is a
+/// function taking the text as its argument.
+#[tracing::instrument]
+pub fn chunk_cell(execution_state_id: ExecutionNodeId, cell: &ChunkCell, range: &TextRange) -> anyhow::Result<OperationNode> {
+    let mut input_signature = InputSignature::new();
+    let mut output_signature = OutputSignature::new();
+    match (&cell.source, &cell.name) {
+        (Some(source), _) => {
+            input_signature.globals.insert(source.clone(), InputItemConfiguration { ty: None, default: None });
+            output_signature.globals.insert(chunks_name(cell), OutputItemConfiguration::Value);
+        }
+        (None, Some(name)) => {
+            output_signature.functions.insert(name.clone(), OutputItemConfiguration::Function {
+                input_signature: InputSignature::from_args_list(vec!["text"]),
+                emit_event: vec![],
+                trigger_on: vec![],
+            });
+        }
+        (None, None) => anyhow::bail!("Chunk cells need a source to read the text from, or a name to be invoked by"),
+    }
+
+    Ok(OperationNode::new(
+        cell.name.clone(),
+        execution_state_id,
+        input_signature,
+        output_signature,
+        CellTypes::Chunk(cell.clone(), Default::default())
+    ))
+}
+
+/// The text of a value, which is either a string or a document produced by an extract cell
+fn text_of(value: &RkyvSerializedValue) -> anyhow::Result<&str> {
+    match value {
+        RkyvSerializedValue::String(text) => Ok(text),
+        RkyvSerializedValue::Object(document) => match document.get("text") {
+            Some(RkyvSerializedValue::String(text)) => Ok(text),
+            _ => anyhow::bail!("Only documents with a text can be chunked"),
+        },
+        _ => anyhow::bail!("Only strings and documents with a text can be chunked"),
+    }
+}
+
+pub fn chunk_cell_exec(cell: ChunkCell) -> Box<OperationFn> {
+    Box::new(move |_, payload, _, _| {
+        let cell = cell.clone();
+        async move {
+            let field = |key: &str| match &payload {
+                RkyvSerializedValue::Object(payload) => match payload.get(key) {
+                    Some(RkyvSerializedValue::Object(values)) => Some(values.clone()),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let value = if cell.function_invocation.is_some() {
+                field("args").and_then(|args| args.get("0").cloned())
+                    .or_else(|| field("kwargs").and_then(|kwargs| kwargs.get("text").cloned()))
+                    .ok_or_else(|| anyhow::anyhow!("{} takes the text to chunk as its argument", chunks_name(&cell)))?
+            } else if let Some(source) = &cell.source {
+                field("globals").and_then(|globals| globals.get(source).cloned())
+                    .ok_or_else(|| anyhow::anyhow!("No value named {} to chunk", source))?
+            } else {
+                // Evaluating a cell without a source makes its function available
+                return Ok(OperationFnOutput::with_value(
+                    RkyvObjectBuilder::new().insert_string(&chunks_name(&cell), String::from("function")).build()
+                ));
+            };
+
+            let chunks = chunk_text(text_of(&value)?, cell.strategy, cell.size, cell.overlap)?;
+            let chunks = RkyvSerializedValue::Array(chunks.iter().map(|chunk| chunk.to_value()).collect());
+            Ok(OperationFnOutput::with_value(if cell.function_invocation.is_some() {
+                chunks
+            } else {
+                RkyvObjectBuilder::new().insert_value(&chunks_name(&cell), chunks).build()
+            }))
+        }.boxed()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use uuid::Uuid;
+    use crate::cells::{ChunkStrategy, CodeCell, SupportedLanguage};
+    use crate::execution::execution::ExecutionState;
+    use crate::sdk::chidori_runtime_instance::ChidoriRuntimeInstance;
+
+    #[tokio::test]
+    async fn test_chunk_cell_reads_its_source() -> anyhow::Result<()> {
+        let cell = ChunkCell {
+            name: Some("sections".to_string()),
+            source: Some("document".to_string()),
+            strategy: ChunkStrategy::Markdown,
+            ..Default::default()
+        };
+        let op = chunk_cell(Uuid::nil(), &cell, &TextRange::default())?;
+        assert!(op.signature.input_signature.globals.contains_key("document"));
+        // Documents produced by extract cells are chunked by their text
+        let document = RkyvObjectBuilder::new().insert_string("text", "# Notes\n\nWater daily.".to_string());
+        let payload = RkyvObjectBuilder::new()
+            .insert_value("globals", RkyvObjectBuilder::new().insert_object("document", document).build())
+            .build();
+        let output = op.execute(&ExecutionState::new_with_random_id(), payload, None, None).await?;
+        assert_eq!(output.output, Ok(RkyvObjectBuilder::new()
+            .insert_value("sections", RkyvSerializedValue::Array(vec![RkyvObjectBuilder::new()
+                .insert_string("text", "# Notes\n\nWater daily.".to_string())
+                .insert_number("index", 0)
+                .insert_number("start_offset", 0)
+                .insert_number("end_offset", 21)
+                .insert_value("heading_path", RkyvSerializedValue::Array(vec![RkyvSerializedValue::String("Notes".to_string())]))
+                .build()]))
+            .build()));
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_call_chunk_cell_from_python() -> anyhow::Result<()> {
+        let mut env = ChidoriRuntimeInstance::new();
+        env.wait_until_ready().await.unwrap();
+        let (_, id_chunk) = env.upsert_cell(CellTypes::Chunk(ChunkCell {
+            name: Some("split_sentences".to_string()),
+            strategy: ChunkStrategy::Sentences,
+            size: 4,
+            ..Default::default()
+        }, TextRange::default()), Uuid::now_v7()).await?;
+        let (_, id_code) = env.upsert_cell(CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: None,
+            language: SupportedLanguage::PyO3,
+            source_code: String::from(indoc! { r#"
+                chunks = await split_sentences("Water daily. Mulch often.")
+                texts = [chunk["text"] for chunk in chunks]
+                "#}),
+            function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
+        }, TextRange::default()), Uuid::now_v7()).await?;
+        env.step().await?;
+        assert_eq!(
+            env.get_state_at_current_execution_head().state_get_value(&id_chunk),
+            Some(&Ok(RkyvObjectBuilder::new().insert_string("split_sentences", String::from("function")).build()))
+        );
+        env.step().await?;
+        let Some(Ok(RkyvSerializedValue::Object(globals))) = env.get_state_at_current_execution_head().state_get_value(&id_code).cloned() else {
+            panic!("Expected the globals of the code cell");
+        };
+        assert_eq!(globals.get("texts"), Some(&RkyvSerializedValue::Array(vec![
+            RkyvSerializedValue::String("Water daily.".to_string()),
+            RkyvSerializedValue::String("Mulch often.".to_string()),
+        ])));
+        env.shutdown().await;
+        Ok(())
+    }
+}
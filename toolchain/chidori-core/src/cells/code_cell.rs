@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::mpsc::Sender;
@@ -7,7 +8,8 @@ use crate::cells::{CellTypes, CodeCell, SupportedLanguage, TextRange};
 use crate::execution::execution::execution_graph::ExecutionNodeId;
 use crate::execution::execution::ExecutionState;
 use crate::execution::primitives::operation::{AsyncRPCCommunication, InputItemConfiguration, InputSignature, InputType, OperationFn, OperationFnOutput, OperationNode, OutputItemConfiguration, OutputSignature};
-use crate::execution::primitives::serialized_value::RkyvSerializedValue;
+use crate::execution::primitives::serialized_value::{sorted_object_entries, RkyvSerializedValue};
+use crate::library::std::code::runtime_deno::DenoRunOptions;
 
 /// Code cells allow notebooks to evaluate source code in a variety of languages.
 #[tracing::instrument]
@@ -19,7 +21,8 @@ pub fn code_cell(execution_state_id: ExecutionNodeId, cell: &CodeCell, range: &T
                     &cell.source_code,
                 )?;
             let report = chidori_static_analysis::language::python::parse::build_report(&paths);
-            let (input_signature, output_signature) = signatures_from_report(&report);
+            let (input_signature, mut output_signature) = signatures_from_report(&report);
+            output_signature.memory_writes = memory_stores_inserted_into(&cell.source_code);
 
             let cell = cell.clone();
             Ok(OperationNode::new(
@@ -37,7 +40,10 @@ pub fn code_cell(execution_state_id: ExecutionNodeId, cell: &CodeCell, range: &T
                 )?;
             let report = chidori_static_analysis::language::javascript::parse::build_report(&paths);
 
-            let (input_signature, output_signature) = signatures_from_report(&report);
+            let (mut input_signature, mut output_signature) = signatures_from_report(&report);
+            output_signature.memory_writes = memory_stores_inserted_into(&cell.source_code);
+            input_signature.imported_cells =
+                chidori_static_analysis::language::javascript::parse::extract_cell_imports(&cell.source_code)?;
 
             let cell = cell.clone();
             Ok(OperationNode::new(
@@ -51,6 +57,129 @@ pub fn code_cell(execution_state_id: ExecutionNodeId, cell: &CodeCell, range: &T
     }
 }
 
+/// The TypeScript type of an argument of the given type, `any` when it is not annotated
+fn typescript_input_type(ty: &Option<InputType>) -> &'static str {
+    match ty {
+        Some(InputType::String) => "string",
+        Some(InputType::Number) | Some(InputType::Float) => "number",
+        Some(InputType::Boolean) => "boolean",
+        Some(InputType::Array) => "any[]",
+        Some(InputType::Object) => "Record<string, any>",
+        Some(InputType::Function) => "(...args: any[]) => Promise<any>",
+        None => "any",
+    }
+}
+
+/// The TypeScript type of a value, as it is provided to javascript cells
+pub fn typescript_value_type(value: &RkyvSerializedValue) -> String {
+    match value {
+        RkyvSerializedValue::Number(_) | RkyvSerializedValue::Float(_) => "number".to_string(),
+        RkyvSerializedValue::String(_) => "string".to_string(),
+        RkyvSerializedValue::Boolean(_) => "boolean".to_string(),
+        RkyvSerializedValue::Null => "null".to_string(),
+        RkyvSerializedValue::FunctionPointer(_, _) => "(...args: any[]) => Promise<any>".to_string(),
+        RkyvSerializedValue::Array(items) => typescript_array_type(items.iter()),
+        RkyvSerializedValue::Set(items) => typescript_array_type(items.iter()),
+        RkyvSerializedValue::Object(members) => {
+            let members: Vec<String> = sorted_object_entries(members).into_iter()
+                .map(|(key, member)| format!("{}: {}", serde_json::Value::String(key.clone()), typescript_value_type(member)))
+                .collect();
+            format!("{{ {} }}", members.join("; "))
+        }
+        RkyvSerializedValue::StreamPointer(_)
+        | RkyvSerializedValue::Cell(_)
+        | RkyvSerializedValue::Error { .. }
+        | RkyvSerializedValue::Table { .. } => "any".to_string(),
+    }
+}
+
+fn typescript_array_type<'a>(items: impl Iterator<Item = &'a RkyvSerializedValue>) -> String {
+    let element_types: std::collections::BTreeSet<String> = items.map(typescript_value_type).collect();
+    match element_types.len() {
+        0 => "any[]".to_string(),
+        1 => format!("({})[]", element_types.into_iter().next().unwrap()),
+        _ => format!("({})[]", element_types.into_iter().collect::<Vec<_>>().join(" | ")),
+    }
+}
+
+/// The TypeScript type of a function of another cell. Arguments are typed by their annotations,
+/// and only declared when the order they are passed in is known.
+fn typescript_function_type(configuration: &OutputItemConfiguration) -> String {
+    let OutputItemConfiguration::Function { input_signature, .. } = configuration else {
+        return "(...args: any[]) => Promise<any>".to_string();
+    };
+    let ordered = input_signature.arg_order.len() == input_signature.args.len()
+        && input_signature.arg_order.iter().all(|name| input_signature.args.contains_key(name));
+    if !ordered {
+        return "(...args: any[]) => Promise<any>".to_string();
+    }
+    let identifier = regex::Regex::new(r"^[A-Za-z_$][A-Za-z0-9_$]*$").unwrap();
+    let parameters: Vec<String> = input_signature.arg_order.iter().enumerate()
+        .map(|(i, name)| {
+            let ty = typescript_input_type(&input_signature.args[name].ty);
+            let name = if identifier.is_match(name) { name.clone() } else { format!("arg{}", i) };
+            format!("{}: {}", name, ty)
+        })
+        .collect();
+    format!("({}) => Promise<any>", parameters.join(", "))
+}
+
+/// TypeScript declarations of the values and functions of an output signature, as the globals
+/// they are provided to javascript cells as. The signature does not record the types of values, so
+/// they are declared as `any`, see `generate_typescript_types_with_values`.
+pub fn generate_typescript_types(output_sig: &OutputSignature) -> String {
+    generate_typescript_types_with_values(output_sig, &HashMap::new())
+}
+
+/// `generate_typescript_types`, typing values by the value of the same name in `values` where
+/// there is one
+pub fn generate_typescript_types_with_values(output_sig: &OutputSignature, values: &HashMap<String, RkyvSerializedValue>) -> String {
+    let mut declarations: Vec<(&String, String)> = output_sig.globals.keys()
+        .map(|name| (name, values.get(name).map(typescript_value_type).unwrap_or_else(|| "any".to_string())))
+        .chain(output_sig.functions.iter().map(|(name, configuration)| (name, typescript_function_type(configuration))))
+        .collect();
+    if declarations.is_empty() {
+        return String::new();
+    }
+    declarations.sort();
+    let mut source = String::from("declare global {\n");
+    for (name, ty) in declarations {
+        source.push_str(&format!("    const {}: {};\n", name, ty));
+    }
+    source.push_str("}\n");
+    source
+}
+
+/// The values and functions of other operations that the javascript `source_code` refers to,
+/// along with the values they hold in `state`
+fn depended_outputs(state: &ExecutionState, source_code: &str) -> anyhow::Result<(OutputSignature, HashMap<String, RkyvSerializedValue>)> {
+    let paths = chidori_static_analysis::language::javascript::parse::extract_dependencies_js(source_code)?;
+    let report = chidori_static_analysis::language::javascript::parse::build_report(&paths);
+    let mut signature = OutputSignature::new();
+    let mut values = HashMap::new();
+    for op in state.operation_by_id.values().filter(|op| op.id != state.evaluating_operation_id) {
+        let output_signature = &op.signature.output_signature;
+        let outputs = match state.state_get_value(&op.id) {
+            Some(Ok(RkyvSerializedValue::Object(outputs))) => Some(outputs),
+            _ => None,
+        };
+        for (name, configuration) in &output_signature.globals {
+            if report.cell_depended_values.contains_key(name) {
+                signature.globals.insert(name.clone(), configuration.clone());
+                if let Some(value) = outputs.and_then(|outputs| outputs.get(name)) {
+                    values.insert(name.clone(), value.clone());
+                }
+            }
+        }
+        for (name, configuration) in &output_signature.functions {
+            if report.cell_depended_values.contains_key(name) {
+                signature.functions.insert(name.clone(), configuration.clone());
+            }
+        }
+    }
+    Ok((signature, values))
+}
+
 pub(crate) fn code_cell_exec_deno(cell: CodeCell) -> Box<OperationFn> {
     Box::new(move |s, x, _, _| {
         let closure_span = tracing::span!(tracing::Level::INFO, "deno_code_cell");
@@ -58,11 +187,21 @@ pub(crate) fn code_cell_exec_deno(cell: CodeCell) -> Box<OperationFn> {
         let s = s.clone();
         let cell = cell.clone();
         async move {
-            let result = crate::library::std::code::runtime_deno::source_code_run_deno(
+            let source_code = if cell.generate_types {
+                let (signature, values) = depended_outputs(&s, &cell.source_code)?;
+                format!("{}{}", generate_typescript_types_with_values(&signature, &values), cell.source_code)
+            } else {
+                cell.source_code.clone()
+            };
+            let result = crate::library::std::code::runtime_deno::source_code_run_deno_with_options(
                 &s,
-                &cell.source_code,
+                &source_code,
                 &x,
                 &cell.function_invocation,
+                &DenoRunOptions {
+                    package_json: cell.package_json.clone(),
+                    type_check: cell.generate_types,
+                },
             ).await?;
             Ok(OperationFnOutput {
                 has_error: false,
@@ -70,6 +209,10 @@ pub(crate) fn code_cell_exec_deno(cell: CodeCell) -> Box<OperationFn> {
                 output: result.0,
                 stdout: result.1,
                 stderr: result.2,
+                peak_memory_bytes: None,
+                agent_trace: vec![],
+                response_metadata: None,
+                input: None,
             })
         }.boxed()
     })
@@ -82,13 +225,15 @@ pub fn code_cell_exec_python(cell: CodeCell) -> Box<OperationFn> {
         let cell = cell.clone();
         let s = s.clone();
         async move {
-            let result = crate::library::std::code::runtime_pyo3::source_code_run_python(
+            let result = crate::library::std::code::runtime_pyo3::source_code_run_python_with_memory_limit(
                 &s,
                 &cell.source_code,
                 &x,
                 &cell.function_invocation,
                 &None,
                 &None,
+                cell.inspect_globals,
+                cell.oom_limit_bytes,
             ).await?;
             Ok(OperationFnOutput {
                 has_error: false,
@@ -96,11 +241,41 @@ pub fn code_cell_exec_python(cell: CodeCell) -> Box<OperationFn> {
                 output: result.0,
                 stdout: result.1,
                 stderr: result.2,
+                peak_memory_bytes: result.4,
+                agent_trace: vec![],
+                response_metadata: None,
+                input: None,
             })
         }.boxed()
     })
 }
 
+/// Peak memory allocated by running python source to completion, or None if it fails to run.
+/// The source runs without the inputs it would receive as a cell, so this is only an estimate.
+pub fn estimate_python_memory_usage(source: &str) -> Option<usize> {
+    crate::library::std::code::runtime_pyo3::measure_python_peak_memory(source)
+        .ok()
+        .map(|bytes| bytes as usize)
+}
+
+/// The memory stores a cell may insert into, those it opens with `memory("name")` when it calls
+/// `insert` anywhere. Stores are often bound to a variable before inserting, so the calls are not
+/// matched up with each other.
+fn memory_stores_inserted_into(source_code: &str) -> Vec<String> {
+    if !source_code.contains(".insert(") {
+        return vec![];
+    }
+    let pattern = regex::Regex::new(r#"\bmemory\(\s*(?:"([^"]+)"|'([^']+)')\s*\)"#).unwrap();
+    let mut stores = vec![];
+    for captures in pattern.captures_iter(source_code) {
+        let name = captures.get(1).or_else(|| captures.get(2)).unwrap().as_str().to_string();
+        if !stores.contains(&name) {
+            stores.push(name);
+        }
+    }
+    stores
+}
+
 fn signatures_from_report(report: &Report) -> (InputSignature, OutputSignature) {
     let mut input_signature = InputSignature::new();
     for (key, value) in &report.cell_depended_values {
@@ -123,11 +298,13 @@ fn signatures_from_report(report: &Report) -> (InputSignature, OutputSignature)
 
     for (key, value) in &report.triggerable_functions {
         let mut input_signature = InputSignature::new();
-        for (i, arg) in value.arguments.iter().enumerate() {
+        for arg in &value.arguments {
+            let ty = value.argument_types.get(arg).and_then(|annotation| InputType::from_python_annotation(annotation));
             input_signature.args.insert(arg.clone(), InputItemConfiguration {
-                ty: Some(InputType::String),
+                ty,
                 default: None,
             });
+            input_signature.arg_order.push(arg.clone());
         }
 
         output_signature.functions.insert(
@@ -145,9 +322,68 @@ fn signatures_from_report(report: &Report) -> (InputSignature, OutputSignature)
 
 #[cfg(test)]
 mod test {
+    use super::*;
+
     #[tokio::test]
     async fn test_code_cell() {
 
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_estimate_python_memory_usage() {
+        assert!(estimate_python_memory_usage("data = bytearray(20_000_000)").unwrap() >= 20_000_000);
+        assert_eq!(estimate_python_memory_usage("raise ValueError()"), None);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::primitives::serialized_value::RkyvObjectBuilder;
+
+    #[test]
+    fn test_generate_typescript_types_for_multiple_outputs() {
+        let mut output_signature = OutputSignature::new();
+        output_signature.globals.insert("total".to_string(), OutputItemConfiguration::Value);
+        output_signature.globals.insert("label".to_string(), OutputItemConfiguration::Value);
+        output_signature.globals.insert("pending".to_string(), OutputItemConfiguration::Value);
+        let mut add_signature = InputSignature::new();
+        for (name, ty) in [("a", Some(InputType::Number)), ("b", None)] {
+            add_signature.args.insert(name.to_string(), InputItemConfiguration { ty, default: None });
+            add_signature.arg_order.push(name.to_string());
+        }
+        output_signature.functions.insert("add".to_string(), OutputItemConfiguration::Function {
+            input_signature: add_signature,
+            emit_event: vec![],
+            trigger_on: vec![],
+        });
+        output_signature.functions.insert("unordered".to_string(), OutputItemConfiguration::NativeFunction);
+        let values = HashMap::from([
+            ("total".to_string(), RkyvSerializedValue::Number(3)),
+            ("label".to_string(), RkyvObjectBuilder::new()
+                .insert_string("text", "sum".to_string())
+                .insert_value("parts", RkyvSerializedValue::Array(vec![RkyvSerializedValue::Number(1), RkyvSerializedValue::Float(2.0)]))
+                .build()),
+        ]);
+        assert_eq!(generate_typescript_types(&output_signature), indoc::indoc! { r#"
+            declare global {
+                const add: (a: number, b: any) => Promise<any>;
+                const label: any;
+                const pending: any;
+                const total: any;
+                const unordered: (...args: any[]) => Promise<any>;
+            }
+            "#});
+        assert_eq!(generate_typescript_types_with_values(&output_signature, &values), indoc::indoc! { r#"
+            declare global {
+                const add: (a: number, b: any) => Promise<any>;
+                const label: { "parts": (number)[]; "text": string };
+                const pending: any;
+                const total: number;
+                const unordered: (...args: any[]) => Promise<any>;
+            }
+            "#});
+        assert_eq!(generate_typescript_types(&OutputSignature::new()), "");
+        assert_eq!(generate_typescript_types_with_values(&OutputSignature::new(), &values), "");
+    }
+}
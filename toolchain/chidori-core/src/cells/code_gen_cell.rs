@@ -25,13 +25,6 @@ pub fn code_gen_cell(execution_state_id: ExecutionNodeId, cell: &LLMCodeGenCell,
 
 
     let schema = chidori_prompt_format::templating::templates::analyze_referenced_partials(&&req);
-    let mut role_blocks = chidori_prompt_format::templating::templates::extract_roles_from_template(r#"
-{{#system}}
-   You are a developer working on a code generation tool. You have been tasked with creating a function that performs the described functionality.
-   Output only the source code for the function. Do not include examples of running the function.
-{{/system}}
-    "#);
-    role_blocks.extend(chidori_prompt_format::templating::templates::extract_roles_from_template(&&req));
 
     let mut output_signature = OutputSignature::new();
     if let Some(fn_name) = &configuration.function_name {
@@ -53,7 +46,7 @@ pub fn code_gen_cell(execution_state_id: ExecutionNodeId, cell: &LLMCodeGenCell,
 
     // We only require the globals to be passed in if the user has not specified this prompt as a function
     if configuration.function_name.is_none() {
-        for (key, value) in &schema.unwrap().items {
+        for (key, value) in &schema?.items {
             input_signature.globals.insert(
                 key.clone(),
                 InputItemConfiguration {
@@ -72,7 +65,9 @@ pub fn code_gen_cell(execution_state_id: ExecutionNodeId, cell: &LLMCodeGenCell,
     }
 
     match provider {
-        SupportedModelProviders::OpenAI => Ok(OperationNode::new(
+        SupportedModelProviders::OpenAI
+        | SupportedModelProviders::Anthropic
+        | SupportedModelProviders::Ollama => Ok(OperationNode::new(
             name.clone(),
             execution_state_id,
             input_signature,
@@ -83,7 +78,7 @@ pub fn code_gen_cell(execution_state_id: ExecutionNodeId, cell: &LLMCodeGenCell,
     }
 }
 
-pub fn code_gen_cell_exec_openai(cell: LLMCodeGenCell) -> Box<OperationFn> {
+pub fn code_gen_cell_exec_openai(cell: LLMCodeGenCell) -> anyhow::Result<Box<OperationFn>> {
     let LLMCodeGenCell {
         name,
         provider,
@@ -94,11 +89,11 @@ pub fn code_gen_cell_exec_openai(cell: LLMCodeGenCell) -> Box<OperationFn> {
     let is_function_invocation = function_invocation.clone();
     let (frontmatter, req) = chidori_prompt_format::templating::templates::split_frontmatter(&complete_body).map_err(|e| {
         anyhow::Error::msg(e.to_string())
-    }).unwrap();
-    let configuration: LLMCodeGenCellChatConfiguration = serde_yaml::from_str(&frontmatter).unwrap();
-
+    })?;
+    let configuration: LLMCodeGenCellChatConfiguration = serde_yaml::from_str(&frontmatter)?;
 
-    let schema = chidori_prompt_format::templating::templates::analyze_referenced_partials(&&req);
+    // Role extraction expects a template that compiles
+    chidori_prompt_format::templating::templates::analyze_referenced_partials(&req)?;
     let mut role_blocks = chidori_prompt_format::templating::templates::extract_roles_from_template(r#"
 {{#system}}
    You are a developer working on a code generation tool. You have been tasked with creating a function that performs the described functionality.
@@ -106,7 +101,7 @@ pub fn code_gen_cell_exec_openai(cell: LLMCodeGenCell) -> Box<OperationFn> {
 {{/system}}
     "#);
     role_blocks.extend(chidori_prompt_format::templating::templates::extract_roles_from_template(&&req));
-    Box::new(move |s, payload, _, _| {
+    Ok(Box::new(move |s, payload, _, _| {
         let closure_span = tracing::span!(tracing::Level::INFO, "code_generation_cell");
         let _enter = closure_span.enter();
         let role_blocks = role_blocks.clone();
@@ -131,7 +126,11 @@ pub fn code_gen_cell_exec_openai(cell: LLMCodeGenCell) -> Box<OperationFn> {
                 output: Ok(value),
                 stdout: vec![],
                 stderr: vec![],
+                peak_memory_bytes: None,
+                agent_trace: vec![],
+                response_metadata: None,
+                input: None,
             })
         }.boxed()
-    })
+    }))
 }
@@ -0,0 +1,168 @@
+use crate::cells::{CellTypes, ExtractCell, TextRange};
+use crate::execution::execution::execution_graph::ExecutionNodeId;
+use crate::execution::primitives::operation::{InputItemConfiguration, InputSignature, OperationFn, OperationNode, OutputItemConfiguration, OutputSignature};
+
+/// Name the extracted document is exposed as when the extract cell is not named
+const DEFAULT_DOCUMENT_NAME: &str = "document";
+
+fn document_name(cell: &ExtractCell) -> String {
+    cell.name.clone().unwrap_or_else(|| DEFAULT_DOCUMENT_NAME.to_string())
+}
+
+/// Extract cells turn an HTML or PDF document held by another cell into `{text, title, metadata, warning}`.
+/// Given a source the document is extracted whenever that value changes, otherwise a named cell is a
+/// function taking the document as its argument.
+#[tracing::instrument]
+pub fn extract_cell(execution_state_id: ExecutionNodeId, cell: &ExtractCell, range: &TextRange) -> anyhow::Result<OperationNode> {
+    let mut input_signature = InputSignature::new();
+    let mut output_signature = OutputSignature::new();
+    match (&cell.source, &cell.name) {
+        (Some(source), _) => {
+            input_signature.globals.insert(source.clone(), InputItemConfiguration { ty: None, default: None });
+            output_signature.globals.insert(document_name(cell), OutputItemConfiguration::Value);
+        }
+        (None, Some(name)) => {
+            output_signature.functions.insert(name.clone(), OutputItemConfiguration::Function {
+                input_signature: InputSignature::from_args_list(vec!["document"]),
+                emit_event: vec![],
+                trigger_on: vec![],
+            });
+        }
+        (None, None) => anyhow::bail!("Extract cells need a source to read the document from, or a name to be invoked by"),
+    }
+
+    Ok(OperationNode::new(
+        cell.name.clone(),
+        execution_state_id,
+        input_signature,
+        output_signature,
+        CellTypes::Extract(cell.clone(), Default::default())
+    ))
+}
+
+#[cfg(feature = "extract")]
+pub fn extract_cell_exec(cell: ExtractCell) -> anyhow::Result<Box<OperationFn>> {
+    use futures_util::FutureExt;
+    use crate::execution::primitives::operation::OperationFnOutput;
+    use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue};
+    use crate::library::std::extract::{document_bytes, extract_document};
+
+    Ok(Box::new(move |_, payload, _, _| {
+        let cell = cell.clone();
+        async move {
+            let field = |key: &str| match &payload {
+                RkyvSerializedValue::Object(payload) => match payload.get(key) {
+                    Some(RkyvSerializedValue::Object(values)) => Some(values.clone()),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let document = if cell.function_invocation.is_some() {
+                field("args").and_then(|args| args.get("0").cloned())
+                    .or_else(|| field("kwargs").and_then(|kwargs| kwargs.get("document").cloned()))
+                    .ok_or_else(|| anyhow::anyhow!("{} takes the document to extract as its argument", document_name(&cell)))?
+            } else if let Some(source) = &cell.source {
+                field("globals").and_then(|globals| globals.get(source).cloned())
+                    .ok_or_else(|| anyhow::anyhow!("No value named {} to extract", source))?
+            } else {
+                // Evaluating a cell without a source makes its function available
+                return Ok(OperationFnOutput::with_value(
+                    RkyvObjectBuilder::new().insert_string(&document_name(&cell), String::from("function")).build()
+                ));
+            };
+
+            let extracted = extract_document(&document_bytes(&document)?, cell.format, cell.markdown).to_value();
+            Ok(OperationFnOutput::with_value(if cell.function_invocation.is_some() {
+                extracted
+            } else {
+                RkyvObjectBuilder::new().insert_value(&document_name(&cell), extracted).build()
+            }))
+        }.boxed()
+    }))
+}
+
+#[cfg(not(feature = "extract"))]
+pub fn extract_cell_exec(_cell: ExtractCell) -> anyhow::Result<Box<OperationFn>> {
+    anyhow::bail!("extract cells require chidori to be built with the extract feature")
+}
+
+#[cfg(all(test, feature = "extract"))]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use uuid::Uuid;
+    use crate::cells::{CodeCell, SupportedLanguage};
+    use crate::execution::execution::ExecutionState;
+    use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue};
+    use crate::sdk::chidori_runtime_instance::ChidoriRuntimeInstance;
+
+    const ARTICLE: &str = include_str!("../../tests/data/extract/article.html");
+
+    #[tokio::test]
+    async fn test_extract_cell_reads_its_source() -> anyhow::Result<()> {
+        let cell = ExtractCell {
+            name: Some("article".to_string()),
+            source: Some("page".to_string()),
+            ..Default::default()
+        };
+        let op = extract_cell(Uuid::nil(), &cell, &TextRange::default())?;
+        assert!(op.signature.input_signature.globals.contains_key("page"));
+        let payload = RkyvObjectBuilder::new()
+            .insert_value("globals", RkyvObjectBuilder::new().insert_string("page", ARTICLE.to_string()).build())
+            .build();
+        let output = op.execute(&ExecutionState::new_with_random_id(), payload, None, None).await?;
+        let Ok(RkyvSerializedValue::Object(outputs)) = output.output else { panic!("Expected an object") };
+        let Some(RkyvSerializedValue::Object(article)) = outputs.get("article") else { panic!("Expected the extracted article") };
+        assert_eq!(article.get("title"), Some(&RkyvSerializedValue::String("Tending a Rooftop Garden".to_string())));
+        assert_eq!(article.get("warning"), Some(&RkyvSerializedValue::Null));
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_call_extract_cell_from_python() -> anyhow::Result<()> {
+        let mut env = ChidoriRuntimeInstance::new();
+        env.wait_until_ready().await.unwrap();
+        let (_, id_extract) = env.upsert_cell(CellTypes::Extract(ExtractCell {
+            name: Some("extract_text".to_string()),
+            markdown: true,
+            ..Default::default()
+        }, TextRange::default()), Uuid::now_v7()).await?;
+        let (_, id_code) = env.upsert_cell(CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: None,
+            language: SupportedLanguage::PyO3,
+            source_code: String::from(indoc! { r#"
+                page = await extract_text("<html><body><nav>Menu</nav><h2>Notes</h2><p>Water daily.</p></body></html>")
+                text = page["text"]
+                "#}),
+            function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
+        }, TextRange::default()), Uuid::now_v7()).await?;
+        env.step().await?;
+        assert_eq!(
+            env.get_state_at_current_execution_head().state_get_value(&id_extract),
+            Some(&Ok(RkyvObjectBuilder::new().insert_string("extract_text", String::from("function")).build()))
+        );
+        env.step().await?;
+        assert_eq!(
+            env.get_state_at_current_execution_head().state_get_value(&id_code),
+            Some(&Ok(RkyvObjectBuilder::new()
+                .insert_value("page", RkyvObjectBuilder::new()
+                    .insert_string("text", "## Notes\n\nWater daily.".to_string())
+                    .insert_value("title", RkyvSerializedValue::Null)
+                    .insert_value("metadata", RkyvObjectBuilder::new().build())
+                    .insert_value("warning", RkyvSerializedValue::Null)
+                    .build())
+                .insert_string("text", "## Notes\n\nWater daily.".to_string())
+                .build()))
+        );
+        env.shutdown().await;
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use crate::cells::{CellTypes, FileCell, TextRange};
+use crate::execution::execution::execution_graph::ExecutionNodeId;
+use crate::execution::primitives::operation::{InputItemConfiguration, InputSignature, OperationFn, OperationNode, OutputItemConfiguration, OutputSignature};
+
+/// Name the table is exposed as when the file cell is not named
+const DEFAULT_TABLE_NAME: &str = "table";
+
+fn table_name(cell: &FileCell) -> String {
+    cell.name.clone().unwrap_or_else(|| DEFAULT_TABLE_NAME.to_string())
+}
+
+/// File cells read a CSV or Parquet file into a table exposed to downstream cells, or write the
+/// value named by `write` to the file, exposing the table that was written.
+#[tracing::instrument]
+pub fn file_cell(execution_state_id: ExecutionNodeId, cell: &FileCell, range: &TextRange) -> anyhow::Result<OperationNode> {
+    let mut input_signature = InputSignature::new();
+    if let Some(source) = &cell.write {
+        input_signature.globals.insert(source.clone(), InputItemConfiguration { ty: None, default: None });
+    }
+    let mut output_signature = OutputSignature::new();
+    output_signature.globals.insert(table_name(cell), OutputItemConfiguration::Value);
+
+    Ok(OperationNode::new(
+        cell.name.clone(),
+        execution_state_id,
+        input_signature,
+        output_signature,
+        CellTypes::File(cell.clone(), Default::default())
+    ))
+}
+
+#[cfg(feature = "arrow")]
+pub fn file_cell_exec(cell: FileCell) -> anyhow::Result<Box<OperationFn>> {
+    use std::path::PathBuf;
+    use futures_util::FutureExt;
+    use crate::cells::TableFileFormat;
+    use crate::execution::primitives::operation::OperationFnOutput;
+    use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue};
+    use crate::execution::primitives::table::{read_table_file, table_from_rows, write_table_file};
+
+    let path = PathBuf::from(&cell.path);
+    let format = match cell.format {
+        Some(format) => format,
+        None => TableFileFormat::from_path(&path)?,
+    };
+    Ok(Box::new(move |_, payload, _, _| {
+        let cell = cell.clone();
+        let path = path.clone();
+        async move {
+            let table = match &cell.write {
+                None => read_table_file(&path, format)?,
+                Some(source) => {
+                    let value = match &payload {
+                        RkyvSerializedValue::Object(payload) => match payload.get("globals") {
+                            Some(RkyvSerializedValue::Object(globals)) => globals.get(source).cloned(),
+                            _ => None,
+                        },
+                        _ => None,
+                    }.ok_or_else(|| anyhow::anyhow!("No value named {} to write to {}", source, path.display()))?;
+                    let table = match value {
+                        table @ RkyvSerializedValue::Table { .. } => table,
+                        rows => table_from_rows(&rows)?,
+                    };
+                    write_table_file(&table, &path, format)?;
+                    table
+                }
+            };
+            Ok(OperationFnOutput::with_value(RkyvObjectBuilder::new().insert_value(&table_name(&cell), table).build()))
+        }.boxed()
+    }))
+}
+
+#[cfg(not(feature = "arrow"))]
+pub fn file_cell_exec(_cell: FileCell) -> anyhow::Result<Box<OperationFn>> {
+    anyhow::bail!("file cells require chidori to be built with the arrow feature")
+}
@@ -5,15 +5,19 @@ use std::future::Future;
 use std::pin::Pin;
 use std::sync::mpsc::Sender;
 use tokio::runtime;
-use crate::cells::{llm_prompt_cell, CellTypes, LLMPromptCell, LLMPromptCellChatConfiguration, SupportedModelProviders, TextRange};
+use crate::cells::{llm_prompt_cell, CellTypes, LLMPromptCell, LLMPromptCellChatConfiguration, SupportedModelProviders, TemplateEngine, TextRange};
 use crate::execution::primitives::operation::{AsyncRPCCommunication, InputItemConfiguration, InputSignature, InputType, OperationFn, OperationFnOutput, OperationNode, OutputItemConfiguration, OutputSignature};
 use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue as RKV, RkyvSerializedValue, serialized_value_to_json_value};
 use futures_util::FutureExt;
 use crate::execution::execution::execution_graph::ExecutionNodeId;
 use crate::execution::execution::ExecutionState;
+use crate::execution::execution::execution_state::ExecutionStateErrors;
+use crate::execution::execution::stream::find_stream_input;
 use crate::library::std::ai::llm::ai_llm_run_embedding_model;
 
 
+/// Variable of prompts declaring `context_from` that holds the retrieved memories
+pub(crate) const CONTEXT_VARIABLE: &str = "context";
 
 /// LLM Prompt Cells allow notebooks to invoke language models to generate text.
 #[tracing::instrument]
@@ -30,9 +34,6 @@ pub fn llm_prompt_cell(execution_state_id: ExecutionNodeId, cell: &LLMPromptCell
                 anyhow::Error::msg(e.to_string())
             })?;
             let configuration: LLMPromptCellChatConfiguration = serde_yaml::from_str(&frontmatter)?;
-            let role_blocks =
-                chidori_prompt_format::templating::templates::extract_roles_from_template(&&req);
-
 
             let mut output_signature = OutputSignature::new();
             if let Some(fn_name) = &configuration.function_name {
@@ -51,11 +52,10 @@ pub fn llm_prompt_cell(execution_state_id: ExecutionNodeId, cell: &LLMPromptCell
             }
 
             let mut input_signature = InputSignature::new();
-            let schema =
-                chidori_prompt_format::templating::templates::analyze_referenced_partials(&&req);
+            let schema = configuration.engine.unwrap_or_default().analyze_referenced_variables(&req);
             // We only require the globals to be passed in if the user has not specified this prompt as a function
             if configuration.function_name.is_none() {
-                for (key, value) in &schema.unwrap().items {
+                for (key, value) in &schema?.items {
                     input_signature.globals.insert(
                         key.clone(),
                         InputItemConfiguration {
@@ -64,6 +64,20 @@ pub fn llm_prompt_cell(execution_state_id: ExecutionNodeId, cell: &LLMPromptCell
                         },
                     );
                 }
+                // Retrieved context is provided by the memory cell, rendering the query takes its variables instead
+                if let Some(context_from) = &configuration.context_from {
+                    input_signature.globals.remove(CONTEXT_VARIABLE);
+                    input_signature.memory_reads.push(context_from.store.clone());
+                    for key in configuration.engine.unwrap_or_default().analyze_referenced_variables(&context_from.query)?.items.keys() {
+                        input_signature.globals.insert(
+                            key.clone(),
+                            InputItemConfiguration {
+                                ty: Some(InputType::String),
+                                default: None,
+                            },
+                        );
+                    }
+                }
             }
 
             let name = name.clone();
@@ -87,7 +101,9 @@ pub fn llm_prompt_cell(execution_state_id: ExecutionNodeId, cell: &LLMPromptCell
             }
 
             match provider {
-                SupportedModelProviders::OpenAI => Ok(OperationNode::new(
+                SupportedModelProviders::OpenAI
+                | SupportedModelProviders::Anthropic
+                | SupportedModelProviders::Ollama => Ok(OperationNode::new(
                     name.clone(),
                     execution_state_id,
                     input_signature,
@@ -103,7 +119,7 @@ pub fn llm_prompt_cell(execution_state_id: ExecutionNodeId, cell: &LLMPromptCell
     }
 }
 
-pub fn llm_prompt_cell_exec_chat_openai(llm_prompt_cell: LLMPromptCell) -> Box<OperationFn> {
+pub fn llm_prompt_cell_exec_chat_openai(llm_prompt_cell: LLMPromptCell) -> anyhow::Result<Box<OperationFn>> {
     let LLMPromptCell::Chat {
         is_function_invocation,
         name,
@@ -113,12 +129,21 @@ pub fn llm_prompt_cell_exec_chat_openai(llm_prompt_cell: LLMPromptCell) -> Box<O
     } = llm_prompt_cell else { unreachable!() };
     let (frontmatter, req) = chidori_prompt_format::templating::templates::split_frontmatter(&complete_body).map_err(|e| {
         anyhow::Error::msg(e.to_string())
-    }).unwrap();
-    let configuration: LLMPromptCellChatConfiguration = serde_yaml::from_str(&frontmatter).unwrap();
-    let role_blocks =
-        chidori_prompt_format::templating::templates::extract_roles_from_template(&&req);
+    })?;
+    let configuration: LLMPromptCellChatConfiguration = serde_yaml::from_str(&frontmatter)?;
+    let role_blocks = match configuration.engine.unwrap_or_default() {
+        TemplateEngine::Handlebars => {
+            // Role extraction expects a template that compiles
+            chidori_prompt_format::templating::templates::analyze_referenced_partials(&req)?;
+            chidori_prompt_format::templating::templates::extract_roles_from_template(&&req)
+        }
+        TemplateEngine::Jinja => {
+            chidori_prompt_format::templating::jinja::analyze_jinja_referenced_variables(&req)?;
+            chidori_prompt_format::templating::jinja::jinja_template_as_user_message(&req)
+        }
+    };
 
-    Box::new(move |s, payload, _, _| {
+    Ok(Box::new(move |s, payload, _, _| {
         let role_blocks = role_blocks.clone();
         let name = name.clone();
         // TODO: this state should error? or what should this do
@@ -134,7 +159,20 @@ pub fn llm_prompt_cell_exec_chat_openai(llm_prompt_cell: LLMPromptCell) -> Box<O
         let s = s.clone();
         let configuration = configuration.clone();
         async move {
-            let (value, state) = crate::library::std::ai::llm::ai_llm_run_chat_model(
+            if let Some(name) = find_stream_input(&payload) {
+                return Ok(OperationFnOutput {
+                    has_error: true,
+                    execution_state: None,
+                    output: Err(ExecutionStateErrors::StreamInputUnsupported(name)),
+                    stdout: vec![],
+                    stderr: vec![],
+                    peak_memory_bytes: None,
+                    agent_trace: vec![],
+                    response_metadata: None,
+                    input: None,
+                });
+            }
+            let (value, state, response_metadata) = crate::library::std::ai::llm::ai_llm_run_chat_model(
                 &s,
                 payload,
                 role_blocks,
@@ -148,7 +186,102 @@ pub fn llm_prompt_cell_exec_chat_openai(llm_prompt_cell: LLMPromptCell) -> Box<O
                 output: value,
                 stdout: vec![],
                 stderr: vec![],
+                peak_memory_bytes: None,
+                agent_trace: vec![],
+                response_metadata,
+                input: None,
             })
         }.boxed()
-    })
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use uuid::Uuid;
+    use std::collections::HashSet;
+    use crate::cells::{CodeCell, MemoryCell, SupportedLanguage};
+    use crate::library::std::ai::llm::prompt_template_data;
+    use crate::library::std::ai::memory::store::tests::keyword_memory_stores;
+    use crate::library::std::template::TemplateLibrary;
+
+    fn prompt_cell(frontmatter: &str, req: &str) -> LLMPromptCell {
+        let complete_body = format!("---\n{}\n---\n{}", frontmatter, req);
+        LLMPromptCell::Chat {
+            backing_file_reference: None,
+            is_function_invocation: false,
+            configuration: serde_yaml::from_str(frontmatter).unwrap(),
+            name: Some("summary".to_string()),
+            provider: SupportedModelProviders::OpenAI,
+            complete_body,
+            req: req.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_prompt_inputs_follow_the_engine() -> anyhow::Result<()> {
+        let req = "{% for doc in documents %}{{ doc }}{% endfor %}\nSummarize for {{ audience }}";
+        let globals = |frontmatter: &str| -> anyhow::Result<Vec<String>> {
+            let op = llm_prompt_cell(Uuid::nil(), &prompt_cell(frontmatter, req), &TextRange::default())?;
+            let mut globals: Vec<String> = op.signature.input_signature.globals.keys().cloned().collect();
+            globals.sort();
+            Ok(globals)
+        };
+        // Handlebars reads the Jinja statements as text, and `doc` as a global
+        assert_eq!(globals("model: gpt-4o")?, vec!["audience", "doc"]);
+        // Jinja binds `doc` in the loop over `documents`
+        assert_eq!(globals("model: gpt-4o\nengine: jinja")?, vec!["audience", "documents"]);
+        assert!(llm_prompt_cell_exec_chat_openai(prompt_cell("model: gpt-4o\nengine: jinja", req)).is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_context_from_memory_cell() -> anyhow::Result<()> {
+        let frontmatter = "model: gpt-4o\ncontext_from:\n  store: facts\n  query: \"{{question}}\"\n  top_k: 1";
+        let req = "Context: {{context}}\nQuestion: {{question}}";
+        let cell = prompt_cell(frontmatter, req);
+        let op = llm_prompt_cell(Uuid::nil(), &cell, &TextRange::default())?;
+        let globals: Vec<&String> = op.signature.input_signature.globals.keys().collect();
+        assert_eq!(globals, vec!["question"]);
+
+        let mut state = ExecutionState::new_with_random_id();
+        state.memory_stores = Arc::new(keyword_memory_stores());
+        state.cells_by_id.insert(Uuid::now_v7(), CellTypes::Memory(MemoryCell {
+            name: Some("facts".to_string()),
+            ..Default::default()
+        }, TextRange::default()));
+        state.insert_memory("facts", "Python is a popular programming language.".to_string(), Default::default()).await?;
+        state.insert_memory("facts", "The capital of France is Paris.".to_string(), Default::default()).await?;
+
+        let payload = RkyvObjectBuilder::new()
+            .insert_object("globals", RkyvObjectBuilder::new().insert_string("question", "What is the capital of France?".to_string()))
+            .build();
+        let LLMPromptCell::Chat { configuration, .. } = &cell else { unreachable!() };
+        let templates = TemplateLibrary::new();
+        let data = prompt_template_data(&state, &templates, &payload, configuration).await?;
+        assert_eq!(
+            templates.render_source(req, &data)?,
+            "Context: The capital of France is Paris.\nQuestion: What is the capital of France?"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_context_from_is_ordered_after_the_writers_of_its_store() -> anyhow::Result<()> {
+        let code = |source_code: &str| CellTypes::Code(CodeCell {
+            language: SupportedLanguage::PyO3,
+            source_code: source_code.to_string(),
+            ..Default::default()
+        }, TextRange::default());
+        let (writer, reader, prompt) = (Uuid::now_v7(), Uuid::now_v7(), Uuid::now_v7());
+        let state = ExecutionState::new_with_random_id();
+        let (state, _) = state.update_operation(code("import chidori as ch\nnotes = ch.memory(\"facts\")\nawait notes.insert(\"Paris is in France.\")"), writer).await?;
+        let (state, _) = state.update_operation(code("import chidori as ch\nfound = await ch.memory('facts').query('Paris')"), reader).await?;
+        let frontmatter = "model: gpt-4o\ncontext_from:\n  store: facts\n  query: \"{{question}}\"";
+        let cell = prompt_cell(frontmatter, "Context: {{context}}\nQuestion: {{question}}");
+        let (state, _) = state.update_operation(CellTypes::Prompt(cell, TextRange::default()), prompt).await?;
+        assert_eq!(state.dependencies_of(prompt), HashSet::from([writer]));
+        Ok(())
+    }
 }
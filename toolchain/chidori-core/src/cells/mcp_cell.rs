@@ -0,0 +1,203 @@
+use crate::cells::{CellTypes, McpCell, TextRange};
+use crate::execution::execution::execution_graph::ExecutionNodeId;
+use crate::execution::primitives::operation::{OperationFn, OperationNode};
+
+/// Mcp cells connect to a Model Context Protocol server, exposing each of its tools as a function
+/// that takes the arguments of the tool as keyword arguments. Tools can be called from code cells
+/// and imported by prompts like any other function.
+#[cfg(feature = "mcp")]
+#[tracing::instrument]
+pub fn mcp_cell(execution_state_id: ExecutionNodeId, cell: &McpCell, range: &TextRange) -> anyhow::Result<OperationNode> {
+    use crate::execution::primitives::operation::{InputItemConfiguration, InputSignature, InputType, OutputItemConfiguration, OutputSignature};
+    use crate::execution::primitives::serialized_value::RkyvSerializedValue;
+    use crate::library::std::mcp::{discover_tools, optional_arguments};
+
+    let mut output_signature = OutputSignature::new();
+    for tool in discover_tools(cell)? {
+        let optional = optional_arguments(&tool);
+        let mut input_signature = InputSignature::new();
+        for (argument, schema) in tool.input_schema["properties"].as_object().into_iter().flatten() {
+            input_signature.kwargs.insert(argument.clone(), InputItemConfiguration {
+                ty: schema["type"].as_str().and_then(InputType::from_json_schema_type),
+                default: optional.contains(argument).then_some(RkyvSerializedValue::Null),
+            });
+        }
+        output_signature.functions.insert(tool.name, OutputItemConfiguration::Function {
+            input_signature,
+            emit_event: vec![],
+            trigger_on: vec![],
+        });
+    }
+
+    Ok(OperationNode::new(
+        cell.name.clone(),
+        execution_state_id,
+        InputSignature::new(),
+        output_signature,
+        CellTypes::Mcp(cell.clone(), Default::default())
+    ))
+}
+
+#[cfg(not(feature = "mcp"))]
+pub fn mcp_cell(_execution_state_id: ExecutionNodeId, _cell: &McpCell, _range: &TextRange) -> anyhow::Result<OperationNode> {
+    anyhow::bail!("mcp cells require chidori to be built with the mcp feature")
+}
+
+/// Evaluating an mcp cell makes its tools available, as evaluating a cell that defines functions does.
+/// Invoked as a function, it calls the tool named by `function_invocation`.
+#[cfg(feature = "mcp")]
+pub fn mcp_cell_exec(cell: McpCell) -> anyhow::Result<Box<OperationFn>> {
+    use std::collections::HashMap;
+    use futures_util::FutureExt;
+    use crate::execution::execution::execution_state::ExecutionStateErrors;
+    use crate::execution::primitives::operation::OperationFnOutput;
+    use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue};
+    use crate::library::std::mcp::{client_for, tool_arguments, tool_result_to_serialized_value};
+
+    Ok(Box::new(move |_, payload, _, _| {
+        let cell = cell.clone();
+        async move {
+            let client = client_for(&cell).await?;
+            let Some(tool_name) = &cell.function_invocation else {
+                let mut functions = RkyvObjectBuilder::new();
+                for tool in client.list_tools().await? {
+                    functions = functions.insert_string(&tool.name, String::from("function"));
+                }
+                return Ok(OperationFnOutput::with_value(functions.build()));
+            };
+
+            let empty: HashMap<String, RkyvSerializedValue> = HashMap::new();
+            let (args, kwargs) = match &payload {
+                RkyvSerializedValue::Object(payload) => (
+                    match payload.get("args") { Some(RkyvSerializedValue::Object(args)) => args, _ => &empty },
+                    match payload.get("kwargs") { Some(RkyvSerializedValue::Object(kwargs)) => kwargs, _ => &empty },
+                ),
+                _ => (&empty, &empty),
+            };
+            // Positional arguments are matched against the schema of the tool
+            let tool = if args.is_empty() {
+                None
+            } else {
+                client.list_tools().await?.into_iter().find(|tool| &tool.name == tool_name)
+            };
+            let arguments = tool_arguments(tool.as_ref(), args, kwargs)?;
+            let result = client.call_tool(tool_name, arguments).await?;
+            Ok(match tool_result_to_serialized_value(&result) {
+                Ok(value) => OperationFnOutput::with_value(value),
+                Err(message) => OperationFnOutput {
+                    has_error: true,
+                    output: Err(ExecutionStateErrors::AnyhowError(format!("Tool {} failed: {}", tool_name, message))),
+                    ..OperationFnOutput::with_value(RkyvSerializedValue::Null)
+                },
+            })
+        }.boxed()
+    }))
+}
+
+#[cfg(not(feature = "mcp"))]
+pub fn mcp_cell_exec(_cell: McpCell) -> anyhow::Result<Box<OperationFn>> {
+    anyhow::bail!("mcp cells require chidori to be built with the mcp feature")
+}
+
+#[cfg(all(test, feature = "mcp"))]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use uuid::Uuid;
+    use crate::cells::{CodeCell, SupportedLanguage};
+    use crate::execution::primitives::serialized_value::RkyvObjectBuilder;
+    use crate::library::std::ai::llm::{resolve_chat_choices, ChatCompletionChoice, ChatCompletionToolCall, ChatCompletionToolCallFunction};
+    use crate::library::std::mcp::tests::spawn_mock_server;
+    use crate::library::std::mcp::{share_client, McpClient};
+    use crate::sdk::chidori_runtime_instance::ChidoriRuntimeInstance;
+
+    /// An mcp cell whose server is the in-process mock, which provides an `echo` tool
+    async fn mock_mcp_cell() -> anyhow::Result<McpCell> {
+        let cell = McpCell {
+            name: Some("tools".to_string()),
+            command: Some(format!("mock-mcp-server-{}", Uuid::now_v7())),
+            ..Default::default()
+        };
+        let (reader, writer) = spawn_mock_server();
+        share_client(&cell, McpClient::connect_streams(reader, writer).await?);
+        Ok(cell)
+    }
+
+    #[tokio::test]
+    async fn test_mcp_cell_exposes_tools_as_functions() -> anyhow::Result<()> {
+        let cell = mock_mcp_cell().await?;
+        let op = mcp_cell(Uuid::nil(), &cell, &TextRange::default())?;
+        assert!(op.signature.output_signature.functions.contains_key("echo"));
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_call_mcp_tool_from_python() -> anyhow::Result<()> {
+        let mut env = ChidoriRuntimeInstance::new();
+        env.wait_until_ready().await.unwrap();
+        let (_, id_tools) = env.upsert_cell(CellTypes::Mcp(mock_mcp_cell().await?, TextRange::default()), Uuid::now_v7()).await?;
+        let (_, id_code) = env.upsert_cell(CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: None,
+            language: SupportedLanguage::PyO3,
+            source_code: String::from(indoc! { r#"
+                y = await echo(text="hi")
+                "#}),
+            function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
+        }, TextRange::default()), Uuid::now_v7()).await?;
+        env.step().await?;
+        assert_eq!(
+            env.get_state_at_current_execution_head().state_get_value(&id_tools),
+            Some(&Ok(RkyvObjectBuilder::new().insert_string("echo", String::from("function")).build()))
+        );
+        env.step().await?;
+        assert_eq!(
+            env.get_state_at_current_execution_head().state_get_value(&id_code),
+            Some(&Ok(RkyvObjectBuilder::new().insert_string("y", String::from("hi")).build()))
+        );
+        env.shutdown().await;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_prompt_tool_call_dispatches_to_mcp_tool() -> anyhow::Result<()> {
+        let mut env = ChidoriRuntimeInstance::new();
+        env.wait_until_ready().await.unwrap();
+        env.upsert_cell(CellTypes::Mcp(mock_mcp_cell().await?, TextRange::default()), Uuid::now_v7()).await?;
+        env.step().await?;
+
+        // The model responding with a tool call, as a prompt importing `echo` would receive
+        let choices = vec![ChatCompletionChoice {
+            text: None,
+            index: 0,
+            logprobs: None,
+            finish_reason: "tool_calls".to_string(),
+            refusal: None,
+            tool_calls: Some(vec![ChatCompletionToolCall {
+                id: "call_0".to_string(),
+                ty: "function".to_string(),
+                function: ChatCompletionToolCallFunction {
+                    name: Some("echo".to_string()),
+                    arguments: Some(RkyvObjectBuilder::new()
+                        .insert_string("text", String::from("hi"))
+                        .insert_string("suffix", String::from("!"))
+                        .build()),
+                },
+            }]),
+        }];
+        let state = env.get_state_at_current_execution_head();
+        let (result, _) = resolve_chat_choices(&state, choices, Some("reply".to_string()), false).await?;
+        assert_eq!(result, Ok(RkyvObjectBuilder::new()
+            .insert_value("reply", RkyvObjectBuilder::new().insert_string("echo", String::from("hi!")).build())
+            .build()));
+        env.shutdown().await;
+        Ok(())
+    }
+}
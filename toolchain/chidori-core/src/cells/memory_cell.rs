@@ -0,0 +1,28 @@
+use futures_util::FutureExt;
+use crate::cells::{CellTypes, MemoryCell, TextRange};
+use crate::execution::execution::execution_graph::ExecutionNodeId;
+use crate::execution::primitives::operation::{InputSignature, OperationFn, OperationFnOutput, OperationNode, OutputSignature};
+use crate::execution::primitives::serialized_value::RkyvSerializedValue;
+
+/// Memory cells declare a store of embedded values, referred to by the name of the cell, along with
+/// the model values are embedded with and the backend they are kept in.
+#[tracing::instrument]
+pub fn memory_cell(execution_state_id: ExecutionNodeId, cell: &MemoryCell, range: &TextRange) -> anyhow::Result<OperationNode> {
+    if cell.name.is_none() {
+        anyhow::bail!("Memory cells need a name to be referred to by");
+    }
+    Ok(OperationNode::new(
+        cell.name.clone(),
+        execution_state_id,
+        InputSignature::new(),
+        OutputSignature::new(),
+        CellTypes::Memory(cell.clone(), Default::default())
+    ))
+}
+
+/// A memory cell has no value of its own, its store is reached through its name
+pub fn memory_cell_exec(_cell: MemoryCell) -> Box<OperationFn> {
+    Box::new(move |_, _, _, _| {
+        async move { Ok(OperationFnOutput::with_value(RkyvSerializedValue::Null)) }.boxed()
+    })
+}
@@ -2,6 +2,13 @@ pub mod template_cell;
 pub mod code_cell;
 pub mod llm_prompt_cell;
 pub mod code_gen_cell;
+pub mod webhook_cell;
+pub mod web_cell;
+pub mod mcp_cell;
+pub mod file_cell;
+pub mod extract_cell;
+pub mod memory_cell;
+pub mod chunk_cell;
 
 use std::cmp::Ordering;
 use std::collections::HashMap;
@@ -74,6 +81,118 @@ pub struct CodeCell {
     pub language: SupportedLanguage,
     pub source_code: String,
     pub function_invocation: Option<String>,
+    /// Capture every binding left in the namespace after execution as an output, rather than
+    /// only those found by static analysis. Dunder names, callables and modules are excluded.
+    #[serde(default)]
+    pub inspect_globals: bool,
+    /// Replace every value this cell outputs with a placeholder wherever state leaves the runtime.
+    #[serde(default)]
+    pub redact_output: bool,
+    #[serde(default)]
+    pub policy: ExecutionPolicy,
+    /// Abort evaluation once the memory allocated by the cell exceeds this many bytes. Only
+    /// enforced for python, where allocations are traced with `tracemalloc`.
+    #[serde(default)]
+    pub oom_limit_bytes: Option<u64>,
+    /// Evaluate this cell on every step, even when none of its inputs have changed.
+    #[serde(default)]
+    pub always_run: bool,
+    /// Declare the types of the values and functions this cell uses from other cells ahead of its
+    /// source, see `code_cell::generate_typescript_types`, and type check the cell before it is
+    /// evaluated. Only applies to javascript cells.
+    #[serde(default)]
+    pub generate_types: bool,
+    /// Contents of a `package.json` the cell is evaluated alongside, resolving its `npm:` imports
+    /// against packages already in the Deno cache. Only applies to javascript cells.
+    #[serde(default)]
+    pub package_json: Option<String>,
+}
+
+impl Default for CodeCell {
+    /// An unnamed python cell with no source, evaluated with the defaults of the program
+    fn default() -> Self {
+        CodeCell {
+            backing_file_reference: None,
+            name: None,
+            language: SupportedLanguage::PyO3,
+            source_code: String::new(),
+            function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: ExecutionPolicy::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
+        }
+    }
+}
+
+/// Limits on the evaluation of a cell. Unset values fall back to the defaults of the program.
+#[derive(
+    Archive,
+    serde::Serialize,
+    serde::Deserialize,
+    Serialize,
+    Deserialize,
+    Debug,
+    PartialEq,
+    Clone,
+    Default,
+)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Debug))]
+pub struct ExecutionPolicy {
+    /// Evaluations that take longer than this many milliseconds fail
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// Number of additional attempts made after an evaluation fails
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// Outputs larger than this many bytes once serialized are handled according to `overflow`,
+    /// `DEFAULT_MAX_OUTPUT_BYTES` when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overflow: Option<OutputOverflow>,
+}
+
+/// Limit on the serialized size of an output applied when none is configured
+pub const DEFAULT_MAX_OUTPUT_BYTES: u64 = 64 * 1024 * 1024;
+
+impl ExecutionPolicy {
+    /// Values set on this policy, with the remainder taken from `defaults`
+    pub fn or(&self, defaults: &ExecutionPolicy) -> ExecutionPolicy {
+        ExecutionPolicy {
+            timeout_ms: self.timeout_ms.or(defaults.timeout_ms),
+            retries: self.retries.or(defaults.retries),
+            max_output_bytes: self.max_output_bytes.or(defaults.max_output_bytes),
+            overflow: self.overflow.clone().or(defaults.overflow.clone()),
+        }
+    }
+}
+
+/// What happens to an output exceeding the size limit of its cell
+#[derive(
+    Archive,
+    serde::Serialize,
+    serde::Deserialize,
+    Serialize,
+    Deserialize,
+    Debug,
+    PartialEq,
+    Clone,
+    Default,
+)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Debug))]
+#[serde(rename_all = "lowercase")]
+pub enum OutputOverflow {
+    /// The evaluation fails with `OutputTooLarge`
+    #[default]
+    Fail,
+    /// The value is written to disk and cells depending on it receive a reference to the file
+    Spill,
 }
 
 
@@ -84,6 +203,7 @@ serde::Deserialize,
 Serialize,
 Deserialize,
 Debug,
+Default,
 PartialEq,
 Clone,
 )]
@@ -93,7 +213,9 @@ Clone,
 bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: std::error::Error"
 ))]
 #[archive_attr(derive(Debug))]
+#[serde(rename_all = "snake_case")]
 pub enum SupportedMemoryProviders {
+    #[default]
     InMemory,
 }
 
@@ -115,9 +237,28 @@ bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error:
 ))]
 #[archive_attr(derive(Debug))]
 pub struct MemoryCell {
+    #[serde(default)]
     pub name: Option<String>,
-    pub provider: SupportedMemoryProviders,
-    pub embedding_function: String,
+    /// The model values are embedded with when they are stored or queried
+    #[serde(default = "default_embedding_model")]
+    pub embedding_model: String,
+    /// Where the embedded values are stored
+    #[serde(default)]
+    pub backend: SupportedMemoryProviders,
+}
+
+fn default_embedding_model() -> String {
+    "text-embedding-3-small".to_string()
+}
+
+impl Default for MemoryCell {
+    fn default() -> Self {
+        MemoryCell {
+            name: None,
+            embedding_model: default_embedding_model(),
+            backend: SupportedMemoryProviders::default(),
+        }
+    }
 }
 
 
@@ -141,6 +282,34 @@ pub struct TemplateCell {
     pub backing_file_reference: Option<BackingFileReference>,
     pub name: Option<String>,
     pub body: String,
+    #[serde(default)]
+    pub engine: TemplateEngine,
+}
+
+/// The syntax template and prompt bodies are written in
+#[derive(
+    Archive,
+    serde::Serialize,
+    serde::Deserialize,
+    Serialize,
+    Deserialize,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    Clone,
+    Copy,
+)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Debug))]
+#[serde(rename_all = "lowercase")]
+pub enum TemplateEngine {
+    /// Handlebars, `{{#each items}}{{name}}{{/each}}`
+    #[default]
+    Handlebars,
+    /// Jinja2, `{% for item in items %}{{ item.name }}{% endfor %}`
+    Jinja,
 }
 
 #[derive(
@@ -189,6 +358,458 @@ pub struct WebserviceCell {
 }
 
 
+#[derive(
+Archive,
+serde::Serialize,
+serde::Deserialize,
+Serialize,
+Deserialize,
+Debug,
+Default,
+PartialEq,
+Clone,
+)]
+#[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
+#[archive(check_bytes)]
+#[archive_attr(check_bytes(
+bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: std::error::Error"
+))]
+#[archive_attr(derive(Debug))]
+pub enum SignatureAlgorithm {
+    #[default]
+    #[serde(rename = "sha256")]
+    HmacSha256,
+    #[serde(rename = "sha1")]
+    HmacSha1,
+}
+
+/// How requests arriving at the same route at the same time are evaluated
+#[derive(
+Archive,
+serde::Serialize,
+serde::Deserialize,
+Serialize,
+Deserialize,
+Debug,
+Default,
+PartialEq,
+Clone,
+)]
+#[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
+#[archive(check_bytes)]
+#[archive_attr(check_bytes(
+bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: std::error::Error"
+))]
+#[archive_attr(derive(Debug))]
+#[serde(rename_all = "lowercase")]
+pub enum RequestConcurrency {
+    /// Requests are evaluated as they arrive, suited to handlers without side effects
+    #[default]
+    Concurrent,
+    /// Each request is evaluated only once the previous one has been answered, suited to
+    /// handlers that read and write shared state
+    Serial,
+}
+
+/// Middleware run before each request received by a webhook cell is handled, in the order listed
+#[derive(
+Archive,
+serde::Serialize,
+serde::Deserialize,
+Serialize,
+Deserialize,
+Debug,
+PartialEq,
+Clone,
+)]
+#[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
+#[archive(check_bytes)]
+#[archive_attr(check_bytes(
+bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: std::error::Error"
+))]
+#[archive_attr(derive(Debug))]
+#[serde(rename_all = "snake_case")]
+pub enum WebMiddlewareConfig {
+    /// Print the method, path and response status of each request
+    Logging,
+    /// Reject requests without the HTTP basic auth credentials of one of `users`, given by username
+    /// as the name of the environment variable holding their password
+    BasicAuth { users: HashMap<String, String> },
+    /// Allow browsers to make requests from `origins`, or from any origin if it contains `*`
+    Cors { origins: Vec<String> },
+}
+
+/// Limit on the size of the body of a request to a webhook cell applied when none is configured
+pub const DEFAULT_WEBHOOK_MAX_BODY_BYTES: u64 = 2 * 1024 * 1024;
+
+fn default_webhook_max_body_bytes() -> u64 {
+    DEFAULT_WEBHOOK_MAX_BODY_BYTES
+}
+
+fn default_webhook_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_webhook_path() -> String {
+    "/".to_string()
+}
+
+fn default_webhook_signature_header() -> String {
+    "X-Hub-Signature-256".to_string()
+}
+
+#[derive(
+Archive,
+serde::Serialize,
+serde::Deserialize,
+Serialize,
+Deserialize,
+Debug,
+PartialEq,
+Clone,
+)]
+#[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
+#[archive(check_bytes)]
+#[archive_attr(check_bytes(
+bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: std::error::Error"
+))]
+#[archive_attr(derive(Debug))]
+pub struct WebhookCell {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Address the server binds, only the local machine can reach it unless this is changed,
+    /// e.g. to `0.0.0.0` to accept requests on every interface
+    #[serde(default = "default_webhook_host")]
+    pub host: String,
+    pub port: u16,
+    #[serde(default = "default_webhook_path")]
+    pub path: String,
+    /// Name of the environment variable holding the secret requests must be signed with, when one
+    /// is provided, so that it is not written in the program
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_env: Option<String>,
+    #[serde(default = "default_webhook_signature_header")]
+    pub signature_header: String,
+    #[serde(default)]
+    pub signature_algorithm: SignatureAlgorithm,
+    /// Function exposed by another cell that answers each request, the body is passed as its keyword
+    /// arguments and its return value is the response. Returning an object of `status`, `headers`
+    /// and `body` sets those of the response. Requests are then evaluated on their own
+    /// branch of the execution graph rather than delivered to downstream cells.
+    #[serde(default)]
+    pub handler: Option<String>,
+    /// Keep each request answered by `handler` in the execution graph, as a child of the state
+    /// the listener was started in
+    #[serde(default)]
+    pub record_requests: bool,
+    /// Whether simultaneous requests to this route are evaluated concurrently or one at a time
+    #[serde(default)]
+    pub concurrency: RequestConcurrency,
+    /// Requests with a larger body are rejected before they are handled
+    #[serde(default = "default_webhook_max_body_bytes")]
+    pub max_body_bytes: u64,
+    /// Run before each request is handled, in the order listed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub middleware: Vec<WebMiddlewareConfig>,
+    /// Further routes answered by functions of other cells, one per line, see `web_cell::parse_route_table`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub routes: String,
+    /// `routes` parsed when the cell is interpreted, so that they can be described and validated
+    /// without starting the server
+    #[serde(skip)]
+    pub route_table: RouteTable,
+}
+
+/// A route served by a webhook cell, answered by invoking `handler` with `params`
+#[derive(
+Archive,
+serde::Serialize,
+serde::Deserialize,
+Serialize,
+Deserialize,
+Debug,
+Default,
+PartialEq,
+Clone,
+)]
+#[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
+#[archive(check_bytes)]
+#[archive_attr(check_bytes(
+bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: std::error::Error"
+))]
+#[archive_attr(derive(Debug))]
+pub struct Route {
+    pub method: String,
+    pub path: String,
+    pub handler: String,
+    /// Arguments of the handler, the parameters of the path followed by those read from the
+    /// query string or body of the request
+    pub params: Vec<String>,
+    /// Where the route is declared, within the document once the cell has been interpreted
+    pub range: TextRange,
+}
+
+#[derive(
+Archive,
+serde::Serialize,
+serde::Deserialize,
+Serialize,
+Deserialize,
+Debug,
+Default,
+PartialEq,
+Clone,
+)]
+#[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
+#[archive(check_bytes)]
+#[archive_attr(check_bytes(
+bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: std::error::Error"
+))]
+#[archive_attr(derive(Debug))]
+pub struct RouteTable {
+    pub routes: Vec<Route>,
+}
+
+/// Exposes the tools of a Model Context Protocol server as functions of the program. The server is
+/// either started with `command` and spoken to over stdio, or reached at the SSE endpoint `url`.
+#[derive(
+Archive,
+serde::Serialize,
+serde::Deserialize,
+Serialize,
+Deserialize,
+Debug,
+Default,
+PartialEq,
+Clone,
+)]
+#[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
+#[archive(check_bytes)]
+#[archive_attr(check_bytes(
+bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: std::error::Error"
+))]
+#[archive_attr(derive(Debug))]
+pub struct McpCell {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub command: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub url: Option<String>,
+    /// The tool called when the cell is evaluated as a function
+    #[serde(default)]
+    pub function_invocation: Option<String>,
+}
+
+impl McpCell {
+    /// Identifies the server, cells describing the same server share a connection to it
+    pub fn server_description(&self) -> String {
+        match (&self.command, &self.url) {
+            (Some(command), _) => std::iter::once(command.as_str())
+                .chain(self.args.iter().map(|a| a.as_str()))
+                .collect::<Vec<_>>()
+                .join(" "),
+            (None, Some(url)) => url.clone(),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+/// Format of the file of a file cell
+#[derive(
+    Archive,
+    serde::Serialize,
+    serde::Deserialize,
+    Serialize,
+    Deserialize,
+    Debug,
+    PartialEq,
+    Clone,
+    Copy,
+)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Debug))]
+#[serde(rename_all = "lowercase")]
+pub enum TableFileFormat {
+    Csv,
+    Parquet,
+}
+
+/// Reads a CSV or Parquet file into a table, or writes a table produced by another cell to one
+#[derive(
+Archive,
+serde::Serialize,
+serde::Deserialize,
+Serialize,
+Deserialize,
+Debug,
+Default,
+PartialEq,
+Clone,
+)]
+#[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
+#[archive(check_bytes)]
+#[archive_attr(check_bytes(
+bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: std::error::Error"
+))]
+#[archive_attr(derive(Debug))]
+pub struct FileCell {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub path: String,
+    /// Inferred from the extension of the path if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<TableFileFormat>,
+    /// The value to write to the file, either a table or an array of objects. The file is read
+    /// if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write: Option<String>,
+}
+
+
+#[derive(
+    Archive,
+    serde::Serialize,
+    serde::Deserialize,
+    Serialize,
+    Deserialize,
+    Debug,
+    Default,
+    PartialEq,
+    Clone,
+    Copy,
+)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Debug))]
+#[serde(rename_all = "lowercase")]
+pub enum ExtractFormat {
+    Html,
+    Pdf,
+    /// Detected from the content of the document
+    #[default]
+    Auto,
+}
+
+/// Extracts the plain text, title and metadata of an HTML or PDF document produced by another cell
+#[derive(
+Archive,
+serde::Serialize,
+serde::Deserialize,
+Serialize,
+Deserialize,
+Debug,
+Default,
+PartialEq,
+Clone,
+)]
+#[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
+#[archive(check_bytes)]
+#[archive_attr(check_bytes(
+bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: std::error::Error"
+))]
+#[archive_attr(derive(Debug))]
+pub struct ExtractCell {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The value holding the document, either a string or an array of bytes. The extracted document
+    /// is exposed as a value named after the cell. A named cell without a source is exposed as a
+    /// function taking the document instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub format: ExtractFormat,
+    /// Render the headings and lists of HTML documents as markdown rather than plain text
+    #[serde(default)]
+    pub markdown: bool,
+    /// Set when the cell is evaluated as a function, the document is then its first argument
+    #[serde(default)]
+    pub function_invocation: Option<String>,
+}
+
+/// Where chunks of text are split
+#[derive(
+    Archive,
+    serde::Serialize,
+    serde::Deserialize,
+    Serialize,
+    Deserialize,
+    Debug,
+    Default,
+    PartialEq,
+    Clone,
+    Copy,
+)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Debug))]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkStrategy {
+    /// Every chunk has the full size, regardless of the content
+    #[default]
+    Tokens,
+    Sentences,
+    Paragraphs,
+    /// Sections under markdown headings, which are recorded as the heading path of their chunks
+    Markdown,
+}
+
+fn default_chunk_size() -> usize {
+    256
+}
+
+/// Splits text produced by another cell into chunks sized for embedding
+#[derive(
+Archive,
+serde::Serialize,
+serde::Deserialize,
+Serialize,
+Deserialize,
+Debug,
+PartialEq,
+Clone,
+)]
+#[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
+#[archive(check_bytes)]
+#[archive_attr(check_bytes(
+bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: std::error::Error"
+))]
+#[archive_attr(derive(Debug))]
+pub struct ChunkCell {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The value holding the text, either a string or a document produced by an extract cell. The
+    /// chunks are exposed as a value named after the cell. A named cell without a source is exposed
+    /// as a function taking the text instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub strategy: ChunkStrategy,
+    /// Largest chunk in estimated tokens
+    #[serde(default = "default_chunk_size")]
+    pub size: usize,
+    /// Estimated tokens repeated between consecutive chunks
+    #[serde(default)]
+    pub overlap: usize,
+    /// Set when the cell is evaluated as a function, the text is then its first argument
+    #[serde(default)]
+    pub function_invocation: Option<String>,
+}
+
+impl Default for ChunkCell {
+    fn default() -> Self {
+        ChunkCell {
+            name: None,
+            source: None,
+            strategy: ChunkStrategy::default(),
+            size: default_chunk_size(),
+            overlap: 0,
+            function_invocation: None,
+        }
+    }
+}
+
+
 #[derive(
 Archive,
 serde::Serialize,
@@ -217,8 +838,11 @@ pub struct ScheduleCell {
     Serialize,
     Deserialize,
     Debug,
+    Default,
     PartialEq,
     Clone,
+    Eq,
+    Hash,
 )]
 #[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
 #[archive(check_bytes)]
@@ -227,7 +851,24 @@ pub struct ScheduleCell {
 ))]
 #[archive_attr(derive(Debug))]
 pub enum SupportedModelProviders {
+    #[default]
+    #[serde(rename = "openai")]
     OpenAI,
+    #[serde(rename = "anthropic")]
+    Anthropic,
+    #[serde(rename = "ollama")]
+    Ollama,
+}
+
+impl SupportedModelProviders {
+    /// OpenAI compatible endpoint used when a prompt does not set an `api_url`. OpenAI and Anthropic
+    /// models are reached through the LiteLLM proxy, Ollama serves this API itself.
+    pub fn default_api_url(&self) -> &'static str {
+        match self {
+            SupportedModelProviders::OpenAI | SupportedModelProviders::Anthropic => "http://localhost:4000/v1",
+            SupportedModelProviders::Ollama => "http://localhost:11434/v1",
+        }
+    }
 }
 
 
@@ -256,6 +897,64 @@ pub struct EjectionConfig {
 
 
 
+/// Memories retrieved for a prompt before it is sent, available to it as `{{context}}`
+#[derive(
+Archive,
+serde::Serialize,
+serde::Deserialize,
+Serialize,
+Deserialize,
+Debug,
+PartialEq,
+Clone,
+)]
+#[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
+#[archive(check_bytes)]
+#[archive_attr(check_bytes(
+bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: std::error::Error"
+))]
+#[archive_attr(derive(Debug))]
+pub struct ContextFrom {
+    /// Name of the memory cell queried
+    pub store: String,
+    /// Template rendered with the inputs of the prompt into the text the store is queried with
+    pub query: String,
+    #[serde(default = "default_context_top_k")]
+    pub top_k: u32,
+}
+
+fn default_context_top_k() -> u32 {
+    3
+}
+
+/// An HTTP or SOCKS proxy requests to a model provider are sent through, set on a prompt or for
+/// every prompt of a provider with `InteractiveChidoriWrapper::set_llm_proxy`
+#[derive(
+Archive,
+serde::Serialize,
+serde::Deserialize,
+Serialize,
+Deserialize,
+Debug,
+PartialEq,
+Clone,
+)]
+#[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
+#[archive(check_bytes)]
+#[archive_attr(check_bytes(
+bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: std::error::Error"
+))]
+#[archive_attr(derive(Debug))]
+pub struct ProxyConfig {
+    /// e.g. `http://proxy.internal:3128` or `socks5://proxy.internal:1080`
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Name of the environment variable holding the password, so that it is not written in the program
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_env: Option<String>,
+}
+
 #[derive(
 Default,
 Archive,
@@ -281,6 +980,44 @@ pub struct LLMPromptCellChatConfiguration {
     #[serde(rename = "fn")]
     pub(crate) function_name: Option<String>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<SupportedModelProviders>,
+
+    /// The syntax of the prompt, Handlebars if unset. Jinja prompts are sent as a single user message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub engine: Option<TemplateEngine>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redact_output: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_bytes: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overflow: Option<OutputOverflow>,
+
+    /// Set to false to always query the model rather than reuse a cached response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub llm_cache: Option<bool>,
+
+    /// How long a cached response is reused, e.g. `24h`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<String>,
+
+    /// Retrieve memories from a memory cell into the `context` variable of the prompt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_from: Option<ContextFrom>,
+
+    /// Send the request through this proxy rather than the one set for the provider, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyConfig>,
+
     pub model: Option<String>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -303,6 +1040,12 @@ pub struct LLMPromptCellChatConfiguration {
     pub seed: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub top_p: Option<f64>,
+    /// Return the log probability of each generated token, see `ModelChoiceMetadata::logprobs`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    /// Number of the most likely alternatives returned for each generated token, with `logprobs`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<i64>,
 }
 
 #[derive(
@@ -371,6 +1114,10 @@ pub struct LLMCodeGenCellChatConfiguration {
     pub top_p: Option<f64>,
 
     pub language: Option<String>,
+
+    /// Number of times the code may be generated, when generated code fails the prompt is
+    /// re-run with the error as feedback until this is exhausted. Defaults to a single attempt.
+    pub max_attempts: Option<u32>,
 }
 
 #[derive(
@@ -488,6 +1235,12 @@ pub enum CellTypes {
     CodeGen(LLMCodeGenCell, TextRange),
     Prompt(LLMPromptCell, TextRange),
     Template(TemplateCell, TextRange),
+    Webhook(WebhookCell, TextRange),
+    Mcp(McpCell, TextRange),
+    File(FileCell, TextRange),
+    Extract(ExtractCell, TextRange),
+    Memory(MemoryCell, TextRange),
+    Chunk(ChunkCell, TextRange),
 }
 
 impl Eq for CellTypes {
@@ -515,7 +1268,88 @@ impl CellTypes {
                 LLMPromptCell::Completion { .. } => &None,
             },
             CellTypes::Template(c, _) => &c.name,
-            CellTypes::CodeGen(c, _) => &c.name
+            CellTypes::CodeGen(c, _) => &c.name,
+            CellTypes::Webhook(c, _) => &c.name,
+            CellTypes::Mcp(c, _) => &c.name,
+            CellTypes::File(c, _) => &c.name,
+            CellTypes::Extract(c, _) => &c.name,
+            CellTypes::Memory(c, _) => &c.name,
+            CellTypes::Chunk(c, _) => &c.name,
+        }
+    }
+
+    /// Location of the cell within the document it was loaded from
+    pub fn text_range(&self) -> &TextRange {
+        match &self {
+            CellTypes::Code(_, r)
+            | CellTypes::CodeGen(_, r)
+            | CellTypes::Prompt(_, r)
+            | CellTypes::Template(_, r)
+            | CellTypes::Webhook(_, r)
+            | CellTypes::Mcp(_, r)
+            | CellTypes::File(_, r)
+            | CellTypes::Extract(_, r)
+            | CellTypes::Memory(_, r)
+            | CellTypes::Chunk(_, r) => r,
+        }
+    }
+
+    /// This cell with where it is written cleared, so that cells are compared by their definition alone
+    pub fn without_location(&self) -> CellTypes {
+        let clear = |reference: &mut Option<BackingFileReference>| {
+            if let Some(reference) = reference {
+                reference.text_range = None;
+            }
+        };
+        let mut cell = self.clone();
+        match &mut cell {
+            CellTypes::Code(c, r) => { clear(&mut c.backing_file_reference); *r = TextRange::default(); }
+            CellTypes::CodeGen(c, r) => { clear(&mut c.backing_file_reference); *r = TextRange::default(); }
+            CellTypes::Template(c, r) => { clear(&mut c.backing_file_reference); *r = TextRange::default(); }
+            CellTypes::Prompt(c, r) => {
+                if let LLMPromptCell::Chat { backing_file_reference, .. } = c {
+                    clear(backing_file_reference);
+                }
+                *r = TextRange::default();
+            }
+            CellTypes::Webhook(_, r)
+            | CellTypes::Mcp(_, r)
+            | CellTypes::File(_, r)
+            | CellTypes::Extract(_, r)
+            | CellTypes::Memory(_, r)
+            | CellTypes::Chunk(_, r) => *r = TextRange::default(),
+        }
+        cell
+    }
+
+    /// Timeout and retry settings declared on this cell itself
+    pub fn execution_policy(&self) -> ExecutionPolicy {
+        match &self {
+            CellTypes::Code(c, _) => c.policy.clone(),
+            CellTypes::Prompt(LLMPromptCell::Chat { configuration, .. }, _) => ExecutionPolicy {
+                timeout_ms: configuration.timeout_ms,
+                retries: configuration.retries,
+                max_output_bytes: configuration.max_output_bytes,
+                overflow: configuration.overflow.clone(),
+            },
+            _ => ExecutionPolicy::default(),
+        }
+    }
+
+    /// Whether the scheduler evaluates this cell on every step regardless of the freshness of its inputs
+    pub fn always_run(&self) -> bool {
+        match &self {
+            CellTypes::Code(c, _) => c.always_run,
+            _ => false,
+        }
+    }
+
+    /// Whether the outputs of this cell should always be redacted, see `RedactionConfig`
+    pub fn redacts_output(&self) -> bool {
+        match &self {
+            CellTypes::Code(c, _) => c.redact_output,
+            CellTypes::Prompt(LLMPromptCell::Chat { configuration, .. }, _) => configuration.redact_output.unwrap_or(false),
+            _ => false,
         }
     }
 }
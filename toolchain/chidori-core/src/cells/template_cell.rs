@@ -2,7 +2,7 @@ use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::mpsc::Sender;
-use crate::cells::{CellTypes, LLMCodeGenCellChatConfiguration, TemplateCell, TextRange};
+use crate::cells::{CellTypes, LLMCodeGenCellChatConfiguration, TemplateCell, TemplateEngine, TextRange};
 use crate::execution::primitives::operation::{AsyncRPCCommunication, InputItemConfiguration, InputSignature, InputType, OperationFn, OperationFnOutput, OperationNode, OutputItemConfiguration, OutputSignature};
 use crate::execution::primitives::serialized_value::{RkyvSerializedValue as RKV, serialized_value_to_json_value, RkyvSerializedValue};
 
@@ -10,17 +10,25 @@ use futures_util::FutureExt;
 use chidori_prompt_format::templating::templates::{ChatModelRoles, TemplateWithSource};
 use crate::execution::execution::execution_graph::ExecutionNodeId;
 use crate::execution::execution::ExecutionState;
+use crate::execution::execution::execution_state::ExecutionStateErrors;
+use crate::execution::execution::stream::find_stream_input;
+use crate::library::std::template::TemplateLibrary;
 
 /// Template cells leverage the same tooling as LLM Prompt Cells, but are used for more general templating.
 #[tracing::instrument]
 pub fn template_cell(execution_state_id: ExecutionNodeId, cell: &TemplateCell, range: &TextRange) -> anyhow::Result<OperationNode> {
-    let schema =
-        chidori_prompt_format::templating::templates::analyze_referenced_partials(&cell.body);
+    let schema = cell.engine.analyze_referenced_variables(&cell.body);
 
     let mut input_signature = InputSignature::new();
-    for (key, value) in &schema.unwrap().items {
+    for key in schema?.items.keys() {
+        // Templates may reach into structured values, `items.[0].name` depends on the global `items`
+        let root = key.split('.').next().unwrap_or(key);
+        // `params` are the parameters of the branch being evaluated rather than a global
+        if root.is_empty() || root == "this" || root == "params" || root.starts_with('@') {
+            continue;
+        }
         input_signature.globals.insert(
-            key.clone(),
+            root.to_string(),
             InputItemConfiguration {
                 ty: Some(InputType::String),
                 default: None,
@@ -52,11 +60,27 @@ pub fn template_cell(execution_state_id: ExecutionNodeId, cell: &TemplateCell, r
 }
 
 
-pub fn template_cell_exec(body: String) -> Box<OperationFn> {
-    Box::new(move |_, x, _, _| {
+pub fn template_cell_exec(body: String, engine: TemplateEngine) -> Box<OperationFn> {
+    Box::new(move |s, x, _, _| {
         let body = body.clone();
+        // Other template cells of the program are available as partials
+        let templates = TemplateLibrary::from_execution_state(s).strict(false);
+        let params = serialized_value_to_json_value(&s.branch_params);
         async move {
-            let data = if let RKV::Object(m) = x {
+            if let Some(name) = find_stream_input(&x) {
+                return Ok(OperationFnOutput {
+                    has_error: true,
+                    execution_state: None,
+                    output: Err(ExecutionStateErrors::StreamInputUnsupported(name)),
+                    stdout: vec![],
+                    stderr: vec![],
+                    peak_memory_bytes: None,
+                    agent_trace: vec![],
+                    response_metadata: None,
+                    input: None,
+                });
+            }
+            let mut data = if let RKV::Object(m) = x {
                 if let Some(m) = m.get("globals") {
                     serialized_value_to_json_value(m)
                 } else {
@@ -65,8 +89,26 @@ pub fn template_cell_exec(body: String) -> Box<OperationFn> {
             } else {
                 serialized_value_to_json_value(&x)
             };
-            let rendered = chidori_prompt_format::templating::templates::render_template_prompt(&body, &data, &HashMap::new()).unwrap();
-            Ok(OperationFnOutput::with_value(RKV::String(rendered)))
+            if data.is_null() {
+                data = serde_json::json!({});
+            }
+            if let Some(data) = data.as_object_mut() {
+                data.insert("params".to_string(), params);
+            }
+            match templates.render_source_with_engine(&body, &data, engine) {
+                Ok(rendered) => Ok(OperationFnOutput::with_value(RKV::String(rendered))),
+                Err(e) => Ok(OperationFnOutput {
+                    has_error: true,
+                    execution_state: None,
+                    output: Err(ExecutionStateErrors::TemplateRenderFailure(e.to_string())),
+                    stdout: vec![],
+                    stderr: vec![],
+                    peak_memory_bytes: None,
+                    agent_trace: vec![],
+                    response_metadata: None,
+                    input: None,
+                }),
+            }
         }.boxed()
     })
 }
@@ -83,6 +125,7 @@ mod test {
             backing_file_reference: None,
             name: Some("test".to_string()),
             body: "Hello, {{ name }}!".to_string(),
+            engine: Default::default(),
         };
         let op = crate::cells::template_cell::template_cell(Uuid::nil(), &cell, &TextRange::default())?;
         let input = crate::execution::primitives::serialized_value::RkyvSerializedValue::Object(
@@ -92,4 +135,70 @@ mod test {
         assert_eq!(output.output, Ok(crate::execution::primitives::serialized_value::RkyvSerializedValue::String("Hello, !".to_string())));
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_template_cell_reads_branch_params() -> anyhow::Result<()> {
+        use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue as RKV};
+        let cell = crate::cells::TemplateCell {
+            backing_file_reference: None,
+            name: Some("test".to_string()),
+            body: "Tone: {{ params.tone }}".to_string(),
+            engine: Default::default(),
+        };
+        let op = crate::cells::template_cell::template_cell(Uuid::nil(), &cell, &TextRange::default())?;
+        assert!(op.signature.input_signature.is_empty());
+        let mut state = ExecutionState::new_with_random_id();
+        state.branch_params = RkyvObjectBuilder::new().insert_string("tone", "formal".to_string()).build();
+        let output = op.execute(&state, RKV::Object(std::collections::HashMap::new()), None, None).await?;
+        assert_eq!(output.output, Ok(RKV::String("Tone: formal".to_string())));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_template_cell_with_structured_globals() -> anyhow::Result<()> {
+        use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue as RKV};
+        let cell = crate::cells::TemplateCell {
+            backing_file_reference: None,
+            name: Some("test".to_string()),
+            body: "There are {{ items.length }} items, the last is {{ items.[2].name }}".to_string(),
+            engine: Default::default(),
+        };
+        let op = crate::cells::template_cell::template_cell(Uuid::nil(), &cell, &TextRange::default())?;
+        assert_eq!(op.signature.input_signature.globals.keys().collect::<Vec<_>>(), vec!["items"]);
+
+        let items = RKV::Array(["a", "b", "c"].iter().map(|name| {
+            RkyvObjectBuilder::new().insert_string("name", name.to_string()).build()
+        }).collect());
+        let input = RkyvObjectBuilder::new()
+            .insert_value("globals", RkyvObjectBuilder::new().insert_value("items", items).build())
+            .build();
+        let output = op.execute(&ExecutionState::new_with_random_id(), input, None, None).await?;
+        assert_eq!(output.output, Ok(RKV::String("There are 3 items, the last is c".to_string())));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_template_cell_with_each_engine() -> anyhow::Result<()> {
+        use crate::cells::TemplateEngine;
+        use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue as RKV};
+        let input = RkyvObjectBuilder::new()
+            .insert_value("globals", RkyvObjectBuilder::new().insert_string("name", "ada".to_string()).build())
+            .build();
+        for (engine, body, expected) in [
+            (TemplateEngine::Handlebars, "Hello, {{ name }}!", "Hello, ada!"),
+            (TemplateEngine::Jinja, "Hello, {{ name | title }}!", "Hello, Ada!"),
+        ] {
+            let cell = crate::cells::TemplateCell {
+                backing_file_reference: None,
+                name: Some("test".to_string()),
+                body: body.to_string(),
+                engine,
+            };
+            let op = crate::cells::template_cell::template_cell(Uuid::nil(), &cell, &TextRange::default())?;
+            assert_eq!(op.signature.input_signature.globals.keys().collect::<Vec<_>>(), vec!["name"]);
+            let output = op.execute(&ExecutionState::new_with_random_id(), input.clone(), None, None).await?;
+            assert_eq!(output.output, Ok(RKV::String(expected.to_string())));
+        }
+        Ok(())
+    }
 }
\ No newline at end of file
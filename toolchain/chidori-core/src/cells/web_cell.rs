@@ -0,0 +1,326 @@
+//! The route table of webhook cells, parsed when the cell is interpreted so that its routes can be
+//! described and validated without binding a port. Each line of the `routes` of the cell declares
+//! one route as `METHOD PATH HANDLER`, optionally followed by the arguments the handler reads from
+//! the query string or body of the request:
+//!
+//! ```text
+//! GET / index
+//! GET /users/:id get_user
+//! GET /posts/{id}/comments list_comments
+//! POST /add add(a, b)
+//! ```
+//!
+//! Blank lines and lines starting with `#` are ignored.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use crate::cells::{Route, RouteTable, TextRange, WebhookCell};
+use crate::cells::webhook_cell::WebMiddleware;
+use crate::execution::execution::ExecutionState;
+use crate::execution::primitives::operation::{OperationNode, OutputItemConfiguration};
+
+const METHODS: &[&str] = &["GET", "POST", "PUT", "PATCH", "DELETE", "HEAD", "OPTIONS"];
+
+/// A problem with a route, located within the routes of the cell, or within the document once
+/// the cell has been interpreted
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteDiagnostic {
+    pub message: String,
+    pub range: TextRange,
+}
+
+impl fmt::Display for RouteDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at {}..{})", self.message, self.range.start, self.range.end)
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().map_or(false, |c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// The parameter a segment of a path captures, written as `:name` or `{name}`
+fn segment_param(segment: &str) -> Option<&str> {
+    segment.strip_prefix(':')
+        .or_else(|| segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')))
+}
+
+/// Names of the parameters of a path
+fn path_params(path: &str) -> Option<Vec<String>> {
+    let mut params = vec![];
+    for segment in path.split('/') {
+        match segment_param(segment) {
+            Some(param) if is_identifier(param) => params.push(param.to_string()),
+            Some(_) => return None,
+            None if segment.contains(['{', '}', ':']) => return None,
+            None => {}
+        }
+    }
+    Some(params)
+}
+
+/// The path with its parameters unnamed, paths of the same shape are answered by the same route
+fn path_shape(path: &str) -> String {
+    path.split('/')
+        .map(|segment| if segment_param(segment).is_some() { ":" } else { segment })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The path in the syntax of the server, parameters written as `:name`
+pub fn server_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| segment_param(segment).map_or_else(|| segment.to_string(), |param| format!(":{}", param)))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The values of the parameters of the route `pattern` in the requested `path`, if it matches
+pub fn match_path(pattern: &str, path: &str) -> Option<HashMap<String, String>> {
+    let (pattern, path): (Vec<&str>, Vec<&str>) = (pattern.split('/').collect(), path.split('/').collect());
+    if pattern.len() != path.len() {
+        return None;
+    }
+    let mut params = HashMap::new();
+    for (expected, segment) in pattern.into_iter().zip(path) {
+        match segment_param(expected) {
+            Some(param) => { params.insert(param.to_string(), segment.to_string()); }
+            None if expected == segment => {}
+            None => return None,
+        }
+    }
+    Some(params)
+}
+
+/// The handler of a route and the arguments it reads from the request, `handler` or `handler(a, b)`
+fn parse_handler(handler: &str) -> Option<(String, Vec<String>)> {
+    let Some((name, args)) = handler.split_once('(') else {
+        return is_identifier(handler).then(|| (handler.to_string(), vec![]));
+    };
+    let args = args.strip_suffix(')')?;
+    let args: Vec<String> = args.split(',')
+        .map(|arg| arg.trim())
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| arg.to_string())
+        .collect();
+    (is_identifier(name.trim()) && args.iter().all(|arg| is_identifier(arg)))
+        .then(|| (name.trim().to_string(), args))
+}
+
+fn parse_route(line: &str, range: TextRange) -> Result<Route, RouteDiagnostic> {
+    let error = |message: String| RouteDiagnostic { message, range: range.clone() };
+    let mut parts = line.splitn(3, char::is_whitespace);
+    let (Some(method), Some(path), Some(handler)) = (parts.next(), parts.next(), parts.next().map(|h| h.trim())) else {
+        return Err(error(format!("Expected `METHOD PATH HANDLER`, got `{}`", line)));
+    };
+    if !METHODS.contains(&method) {
+        return Err(error(format!("Unsupported method {}, expected one of {}", method, METHODS.join(", "))));
+    }
+    if !path.starts_with('/') {
+        return Err(error(format!("The path {} does not start with /", path)));
+    }
+    let mut params = path_params(path).ok_or_else(|| error(format!("The path {} has a malformed parameter", path)))?;
+    let (handler, args) = parse_handler(handler).ok_or_else(|| error(format!("Malformed handler `{}`", handler)))?;
+    for arg in args {
+        if !params.contains(&arg) {
+            params.push(arg);
+        }
+    }
+    Ok(Route { method: method.to_string(), path: path.to_string(), handler, params, range })
+}
+
+/// Parse the routes of a webhook cell, the ranges of routes and diagnostics are byte offsets into
+/// `routes`. Every invalid line is reported rather than only the first.
+pub fn parse_route_table(routes: &str) -> Result<RouteTable, Vec<RouteDiagnostic>> {
+    let mut table: Vec<Route> = vec![];
+    let mut diagnostics = vec![];
+    let mut offset = 0;
+    for line in routes.split_inclusive('\n') {
+        let start = offset + (line.len() - line.trim_start().len());
+        offset += line.len();
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let range = TextRange { start, end: start + trimmed.len() };
+        match parse_route(trimmed, range) {
+            Ok(route) => {
+                if table.iter().any(|r| r.method == route.method && path_shape(&r.path) == path_shape(&route.path)) {
+                    diagnostics.push(RouteDiagnostic {
+                        message: format!("{} {} is declared more than once", route.method, route.path),
+                        range: route.range,
+                    });
+                } else {
+                    table.push(route);
+                }
+            }
+            Err(diagnostic) => diagnostics.push(diagnostic),
+        }
+    }
+    if diagnostics.is_empty() {
+        Ok(RouteTable { routes: table })
+    } else {
+        Err(diagnostics)
+    }
+}
+
+impl WebhookCell {
+    /// Parse the `routes` of the cell into its `route_table`. Routes may not take over the path
+    /// payloads are delivered to.
+    pub fn parse_routes(&mut self) -> Result<(), Vec<RouteDiagnostic>> {
+        let table = parse_route_table(&self.routes)?;
+        let diagnostics: Vec<RouteDiagnostic> = table.routes.iter()
+            .filter(|route| ["POST", "OPTIONS"].contains(&route.method.as_str()) && path_shape(&route.path) == path_shape(&self.path))
+            .map(|route| RouteDiagnostic {
+                message: format!("{} {} is the path of the webhook itself", route.method, route.path),
+                range: route.range.clone(),
+            })
+            .collect();
+        if !diagnostics.is_empty() {
+            return Err(diagnostics);
+        }
+        self.route_table = table;
+        Ok(())
+    }
+}
+
+/// Run `middleware` before each request served by the webhook cell of `operation_node`, after
+/// the middleware added before it. The `middleware` configured on the cell is added this way when
+/// the operation is constructed. Operations of other cells serve no requests and ignore it.
+pub fn add_middleware(operation_node: &mut OperationNode, middleware: Arc<dyn WebMiddleware>) {
+    operation_node.web_middleware.push(middleware);
+}
+
+impl RouteTable {
+    /// Routes whose handler is not a function defined by any cell of `state`, or takes a different
+    /// number of arguments than the route provides
+    pub fn validate(&self, state: &ExecutionState) -> Vec<RouteDiagnostic> {
+        let mut diagnostics = vec![];
+        for route in &self.routes {
+            let function = state.operation_by_id.values()
+                .find_map(|op| op.signature.output_signature.functions.get(&route.handler));
+            let message = match function {
+                Some(OutputItemConfiguration::Function { input_signature, .. }) => {
+                    let arity = input_signature.args.len();
+                    (arity != route.params.len()).then(|| format!(
+                        "{} {} provides {} arguments to {}, which takes {}",
+                        route.method, route.path, route.params.len(), route.handler, arity
+                    ))
+                }
+                _ => Some(format!("{} {} is handled by {}, which no cell defines", route.method, route.path, route.handler)),
+            };
+            if let Some(message) = message {
+                diagnostics.push(RouteDiagnostic { message, range: route.range.clone() });
+            }
+        }
+        diagnostics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cells::{CellTypes, CodeCell, SupportedLanguage};
+    use crate::cells::webhook_cell::{webhook_cell, Next, WebRequest};
+    use crate::execution::primitives::serialized_value::RkyvSerializedValue;
+    use axum::http::StatusCode;
+    use axum::response::{IntoResponse, Response};
+    use futures_util::future::BoxFuture;
+    use futures_util::FutureExt;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_parse_route_forms() {
+        let table = parse_route_table("GET / index\n\n# Users\nGET /users/:id get_user\n  DELETE /posts/{id}/comments/{comment} remove_comment\nPOST /add add(a, b)\nPUT /users/:id update_user(id, name)\n").unwrap();
+        let summary: Vec<(&str, &str, &str, Vec<&str>)> = table.routes.iter()
+            .map(|r| (r.method.as_str(), r.path.as_str(), r.handler.as_str(), r.params.iter().map(|p| p.as_str()).collect()))
+            .collect();
+        assert_eq!(summary, vec![
+            ("GET", "/", "index", vec![]),
+            ("GET", "/users/:id", "get_user", vec!["id"]),
+            ("DELETE", "/posts/{id}/comments/{comment}", "remove_comment", vec!["id", "comment"]),
+            ("POST", "/add", "add", vec!["a", "b"]),
+            ("PUT", "/users/:id", "update_user", vec!["id", "name"]),
+        ]);
+        assert_eq!(table.routes[2].range, TextRange { start: 47, end: 99 });
+        assert_eq!(server_path("/posts/{id}/comments/:comment"), "/posts/:id/comments/:comment");
+        assert_eq!(match_path("/posts/{id}/comments", "/posts/7/comments"), Some(HashMap::from([("id".to_string(), "7".to_string())])));
+        assert_eq!(match_path("/posts/{id}/comments", "/posts/7"), None);
+    }
+
+    #[test]
+    fn test_invalid_route_lines() {
+        let configuration = "GET / index\nFETCH / index\nGET users list\nGET /a/{b get_a\nGET /\nGET / index\nPOST /add add(a,\nGET /users/{name} get_user\nGET /users/:id get_user\n";
+        let diagnostics = parse_route_table(configuration).unwrap_err();
+        let lines: Vec<&str> = diagnostics.iter().map(|d| &configuration[d.range.start..d.range.end]).collect();
+        assert_eq!(lines, vec!["FETCH / index", "GET users list", "GET /a/{b get_a", "GET /", "GET / index", "POST /add add(a,", "GET /users/:id get_user"]);
+        assert!(diagnostics[0].message.contains("Unsupported method FETCH"));
+        assert!(diagnostics[4].message.contains("declared more than once"));
+
+        let mut cell: WebhookCell = serde_json::from_value(serde_json::json!({
+            "port": 8080,
+            "path": "/hook",
+            "routes": "GET /hook status\nPOST /hook add(a, b)\n",
+        })).unwrap();
+        let diagnostics = cell.parse_routes().unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "POST /hook is the path of the webhook itself");
+    }
+
+    #[test]
+    fn test_validate_flags_missing_handler() -> anyhow::Result<()> {
+        let state = ExecutionState::new_with_random_id();
+        let (state, _) = state.update_operation(CellTypes::Code(CodeCell {
+            name: Some("handlers".to_string()),
+            language: SupportedLanguage::PyO3,
+            source_code: "def add(a, b):\n    return a + b\n".to_string(),
+            ..Default::default()
+        }, TextRange::default()), Uuid::now_v7())?;
+
+        let table = parse_route_table("POST /add add(a, b)\nGET /missing missing\nPOST /one add(a)\n").unwrap();
+        let diagnostics = table.validate(&state);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].message, "GET /missing is handled by missing, which no cell defines");
+        assert_eq!(diagnostics[0].range, table.routes[1].range);
+        assert_eq!(diagnostics[1].message, "POST /one provides 1 arguments to add, which takes 2");
+        Ok(())
+    }
+
+    /// Answers every request without a token itself
+    struct RequireToken;
+
+    impl WebMiddleware for RequireToken {
+        fn handle<'a>(&'a self, req: WebRequest, next: Next) -> BoxFuture<'a, Response> {
+            async move {
+                if req.headers().contains_key("X-Token") {
+                    next.run(req).await
+                } else {
+                    StatusCode::IM_A_TEAPOT.into_response()
+                }
+            }.boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registered_middleware_runs_before_the_webhook() -> anyhow::Result<()> {
+        let cell: WebhookCell = serde_json::from_str(r#"{"port": 0, "path": "/hook", "middleware": ["logging"]}"#)?;
+        let mut node = webhook_cell(Uuid::nil(), &cell, &TextRange::default())?;
+        assert_eq!(node.web_middleware.len(), 1);
+        add_middleware(&mut node, Arc::new(RequireToken));
+
+        let state = ExecutionState::new_with_random_id();
+        node.execute(&state, RkyvSerializedValue::Null, None, None).await?;
+        let address = state.webhook_servers.address(state.evaluating_operation_id).expect("the listener should be started");
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/hook", address))
+            .json(&serde_json::json!({}))
+            .send()
+            .await?;
+        assert_eq!(response.status(), reqwest::StatusCode::IM_A_TEAPOT);
+        state.webhook_servers.stop_all().await;
+        Ok(())
+    }
+}
@@ -0,0 +1,721 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::extract::Query;
+use axum::routing::{get, on, post, MethodFilter};
+use axum::{Json, Router};
+use base64::Engine;
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use hmac::{Hmac, Mac};
+use tracing::{debug, error};
+use sha2::Digest;
+use crate::cells::{CellTypes, RequestConcurrency, Route, SignatureAlgorithm, TextRange, WebMiddlewareConfig, WebhookCell};
+use crate::cells::web_cell::{add_middleware, match_path, server_path};
+use crate::execution::execution::execution_graph::ExecutionNodeId;
+use crate::execution::execution::ExecutionState;
+use crate::execution::primitives::identifiers::OperationId;
+use crate::execution::primitives::operation::{InputSignature, OperationFn, OperationFnOutput, OperationNode, OutputItemConfiguration, OutputSignature};
+use crate::execution::primitives::serialized_value::{json_value_to_serialized_value, serialized_value_to_json_value, RkyvObjectBuilder, RkyvSerializedValue};
+
+/// Name the received payload is exposed as when the webhook cell is not named
+const DEFAULT_WEBHOOK_NAME: &str = "webhook";
+
+/// Answered by every webhook server unless the cell declares the path itself
+const HEALTHZ_PATH: &str = "/healthz";
+
+/// Keys of a handler's return value that describe the HTTP response
+const RESPONSE_FIELDS: &[&str] = &["status", "headers", "body"];
+
+fn payload_name(cell: &WebhookCell) -> String {
+    cell.name.clone().unwrap_or_else(|| DEFAULT_WEBHOOK_NAME.to_string())
+}
+
+/// Webhook cells listen for signed HTTP requests, exposing each verified payload to downstream cells,
+/// or answering it with the cell's handler function when one is configured.
+#[tracing::instrument]
+pub fn webhook_cell(execution_state_id: ExecutionNodeId, cell: &WebhookCell, range: &TextRange) -> anyhow::Result<OperationNode> {
+    let mut output_signature = OutputSignature::new();
+    output_signature.globals.insert(
+        payload_name(cell),
+        OutputItemConfiguration::Value,
+    );
+
+    // Cells built other than by interpreting a document have yet to parse their routes
+    let mut cell = cell.clone();
+    if cell.route_table.routes.is_empty() {
+        cell.parse_routes().map_err(|diagnostics| anyhow::anyhow!("Invalid routes: {}", diagnostics.iter()
+            .map(|d| d.to_string())
+            .collect::<Vec<_>>()
+            .join("; ")))?;
+    }
+
+    let configured_middleware = build_middleware(&cell.middleware)?;
+    let mut node = OperationNode::new(
+        cell.name.clone(),
+        execution_state_id,
+        InputSignature::new(),
+        output_signature,
+        CellTypes::Webhook(cell, Default::default())
+    );
+    for middleware in configured_middleware {
+        add_middleware(&mut node, middleware);
+    }
+    Ok(node)
+}
+
+/// Validates the HMAC of `body` against the value of the signature header. Providers commonly
+/// prefix the hex digest with the algorithm, e.g. `sha256=<digest>`, which must then name the
+/// algorithm of the cell.
+pub fn verify_signature(algorithm: &SignatureAlgorithm, secret: &str, body: &[u8], signature: &str) -> bool {
+    let signature = signature.trim();
+    let digest = match signature.split_once('=') {
+        Some((prefix, digest)) => {
+            let expected_prefix = match algorithm {
+                SignatureAlgorithm::HmacSha256 => "sha256",
+                SignatureAlgorithm::HmacSha1 => "sha1",
+            };
+            if !prefix.eq_ignore_ascii_case(expected_prefix) {
+                return false;
+            }
+            digest
+        }
+        None => signature,
+    };
+    let Ok(expected) = hex::decode(digest) else {
+        return false;
+    };
+    match algorithm {
+        SignatureAlgorithm::HmacSha256 => {
+            let Ok(mut mac) = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()) else { return false; };
+            mac.update(body);
+            mac.verify_slice(&expected).is_ok()
+        }
+        SignatureAlgorithm::HmacSha1 => {
+            let Ok(mut mac) = Hmac::<sha1::Sha1>::new_from_slice(secret.as_bytes()) else { return false; };
+            mac.update(body);
+            mac.verify_slice(&expected).is_ok()
+        }
+    }
+}
+
+/// A request received by a webhook cell, with its body read in full
+pub type WebRequest = axum::http::Request<Bytes>;
+
+type Endpoint = Arc<dyn Fn(WebRequest) -> BoxFuture<'static, Response> + Send + Sync>;
+
+/// Middleware wraps the requests served by a webhook cell, e.g. to log, authenticate, or add
+/// headers to them. Each either answers the request itself or passes it on with `next.run`.
+pub trait WebMiddleware: Send + Sync {
+    fn handle<'a>(&'a self, req: WebRequest, next: Next) -> BoxFuture<'a, Response>;
+}
+
+/// The remainder of a middleware chain, ending with the webhook's own handling of the request
+#[derive(Clone)]
+pub struct Next {
+    middleware: Arc<[Arc<dyn WebMiddleware>]>,
+    position: usize,
+    endpoint: Endpoint,
+}
+
+impl Next {
+    fn new(middleware: Arc<[Arc<dyn WebMiddleware>]>, endpoint: Endpoint) -> Self {
+        Next { middleware, position: 0, endpoint }
+    }
+
+    pub fn run(self, req: WebRequest) -> BoxFuture<'static, Response> {
+        let Some(middleware) = self.middleware.get(self.position).cloned() else {
+            return (self.endpoint)(req);
+        };
+        let next = Next { position: self.position + 1, ..self };
+        async move { middleware.handle(req, next).await }.boxed()
+    }
+}
+
+/// The middleware configured on a webhook cell, in the order it runs
+pub fn build_middleware(configuration: &[WebMiddlewareConfig]) -> anyhow::Result<Vec<Arc<dyn WebMiddleware>>> {
+    configuration.iter().map(|middleware| -> anyhow::Result<Arc<dyn WebMiddleware>> {
+        Ok(match middleware {
+            WebMiddlewareConfig::Logging => Arc::new(LoggingMiddleware),
+            WebMiddlewareConfig::BasicAuth { users } => Arc::new(BasicAuthMiddleware(users.iter()
+                .map(|(username, password_env)| {
+                    let password = std::env::var(password_env)
+                        .map_err(|_| anyhow::anyhow!("The password variable {} of {} is not set", password_env, username))?;
+                    Ok((username.clone(), password))
+                })
+                .collect::<anyhow::Result<_>>()?)),
+            WebMiddlewareConfig::Cors { origins } => Arc::new(CorsMiddleware(origins.clone())),
+        })
+    }).collect()
+}
+
+/// Prints the method, path and response status of each request to stdout
+pub struct LoggingMiddleware;
+
+impl WebMiddleware for LoggingMiddleware {
+    fn handle<'a>(&'a self, req: WebRequest, next: Next) -> BoxFuture<'a, Response> {
+        async move {
+            let (method, path) = (req.method().clone(), req.uri().path().to_string());
+            let response = next.run(req).await;
+            println!("{} {} {}", method, path, response.status().as_u16());
+            response
+        }.boxed()
+    }
+}
+
+/// Rejects requests without HTTP basic auth credentials matching one of the usernames and passwords
+pub struct BasicAuthMiddleware(pub HashMap<String, String>);
+
+impl BasicAuthMiddleware {
+    fn is_authorized(&self, headers: &HeaderMap) -> bool {
+        let credentials = headers.get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Basic "))
+            .and_then(|v| base64::engine::general_purpose::STANDARD.decode(v.trim()).ok())
+            .and_then(|v| String::from_utf8(v).ok());
+        let Some((username, password)) = credentials.as_deref().and_then(|c| c.split_once(':')) else {
+            return false;
+        };
+        self.0.get(username).map_or(false, |expected| constant_time_eq(expected.as_bytes(), password.as_bytes()))
+    }
+}
+
+/// Compares digests of the values, which are of equal length, without stopping at the first
+/// difference, so that the time taken does not reveal how much of a guessed password is correct
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let (a, b) = (sha2::Sha256::digest(a), sha2::Sha256::digest(b));
+    a.iter().zip(b.iter()).fold(0u8, |difference, (x, y)| difference | (x ^ y)) == 0
+}
+
+impl WebMiddleware for BasicAuthMiddleware {
+    fn handle<'a>(&'a self, req: WebRequest, next: Next) -> BoxFuture<'a, Response> {
+        if !self.is_authorized(req.headers()) {
+            let response = (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Basic")], "Unauthorized").into_response();
+            return async move { response }.boxed();
+        }
+        next.run(req)
+    }
+}
+
+/// Allows requests from the listed origins, or any origin if the list contains `*`, to be made by
+/// browsers. Preflight requests are answered without reaching the handler.
+pub struct CorsMiddleware(pub Vec<String>);
+
+impl WebMiddleware for CorsMiddleware {
+    fn handle<'a>(&'a self, req: WebRequest, next: Next) -> BoxFuture<'a, Response> {
+        async move {
+            let origin = req.headers().get(header::ORIGIN).cloned()
+                .filter(|origin| self.0.iter().any(|allowed| allowed == "*" || origin.as_bytes() == allowed.as_bytes()));
+            let mut response = if req.method() == Method::OPTIONS {
+                StatusCode::NO_CONTENT.into_response()
+            } else {
+                next.run(req).await
+            };
+            if let Some(origin) = origin {
+                let headers = response.headers_mut();
+                headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+                headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_static("POST, OPTIONS"));
+                headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, HeaderValue::from_static("*"));
+                headers.insert(header::VARY, HeaderValue::from_static("Origin"));
+            }
+            response
+        }.boxed()
+    }
+}
+
+/// The servers started by webhook cells, at most one per cell. Evaluating a webhook cell again
+/// stops its previous server before binding its port, and servers of cells no longer in the
+/// program are stopped, see `ChidoriRuntimeInstance::set_execution_head`. Each server also answers
+/// `GET /healthz`, reporting whether the last step of the runtime failed.
+#[derive(Default)]
+pub struct WebhookServers {
+    servers: Mutex<HashMap<OperationId, WebhookServer>>,
+    last_step_failed: Arc<AtomicBool>,
+}
+
+struct WebhookServer {
+    address: SocketAddr,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl fmt::Debug for WebhookServers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let servers = self.servers.lock().unwrap();
+        f.debug_map().entries(servers.iter().map(|(op_id, server)| (op_id, server.address))).finish()
+    }
+}
+
+impl WebhookServers {
+    /// The address the server of the cell is listening on, if it is running
+    pub fn address(&self, operation_id: OperationId) -> Option<SocketAddr> {
+        self.servers.lock().unwrap().get(&operation_id).map(|server| server.address)
+    }
+
+    /// Record the outcome of a step of the runtime, for `/healthz`
+    pub fn record_step(&self, failed: bool) {
+        self.last_step_failed.store(failed, Ordering::SeqCst);
+    }
+
+    /// Stop the server of the cell, returning once its port has been released
+    pub async fn stop(&self, operation_id: OperationId) {
+        let server = self.servers.lock().unwrap().remove(&operation_id);
+        if let Some(server) = server {
+            server.task.abort();
+            let _ = server.task.await;
+        }
+    }
+
+    /// Stop the servers of the cells for which `keep` is false
+    pub fn retain(&self, keep: impl Fn(&OperationId) -> bool) {
+        self.servers.lock().unwrap().retain(|operation_id, server| {
+            let keep = keep(operation_id);
+            if !keep {
+                server.task.abort();
+            }
+            keep
+        });
+    }
+
+    /// Stop every server, returning once their ports have been released
+    pub async fn stop_all(&self) {
+        let servers: Vec<_> = self.servers.lock().unwrap().drain().collect();
+        for (_, server) in servers {
+            server.task.abort();
+            let _ = server.task.await;
+        }
+    }
+
+    /// Bind the port of `cell` and serve `app` on it, in place of any server the cell had
+    async fn start(&self, operation_id: OperationId, cell: &WebhookCell, app: Router) -> anyhow::Result<SocketAddr> {
+        self.stop(operation_id).await;
+        let declares_healthz = cell.path == HEALTHZ_PATH || cell.route_table.routes.iter().any(|route| route.path == HEALTHZ_PATH);
+        let app = if declares_healthz {
+            app
+        } else {
+            let last_step_failed = self.last_step_failed.clone();
+            app.route(HEALTHZ_PATH, get(move || healthz(last_step_failed.clone())))
+        };
+        let tcp_listener = tokio::net::TcpListener::bind((cell.host.as_str(), cell.port)).await?;
+        let address = tcp_listener.local_addr()?;
+        let task = tokio::spawn(async move {
+            if let Err(e) = axum::serve(tcp_listener, app).await {
+                debug!("Webhook listener stopped: {:?}", e);
+            }
+        });
+        if let Some(previous) = self.servers.lock().unwrap().insert(operation_id, WebhookServer { address, task }) {
+            previous.task.abort();
+        }
+        Ok(address)
+    }
+}
+
+async fn healthz(last_step_failed: Arc<AtomicBool>) -> Response {
+    if last_step_failed.load(Ordering::SeqCst) {
+        (StatusCode::SERVICE_UNAVAILABLE, Json(serde_json::json!({"status": "error", "last_step_failed": true}))).into_response()
+    } else {
+        Json(serde_json::json!({"status": "ok", "last_step_failed": false})).into_response()
+    }
+}
+
+struct WebhookListener {
+    cell: WebhookCell,
+    /// The value of the `secret_env` of the cell, read when the listener is started
+    secret: Option<String>,
+    /// Run, in the order they were added, before each request is handled
+    middleware: Arc<[Arc<dyn WebMiddleware>]>,
+    operation_id: OperationId,
+    /// Each received payload branches from the state the listener was started in
+    execution_state: ExecutionState,
+    /// Held while a request is evaluated when the route is configured to be serial
+    serial: tokio::sync::Mutex<()>,
+}
+
+/// Handlers may return an object of `status`, and optionally `headers` and `body`, to control the
+/// response. Any other value is the body of a 200 response. String bodies are sent as they are,
+/// other values as json.
+fn handler_response(value: RkyvSerializedValue) -> Response {
+    let structured = match &value {
+        RkyvSerializedValue::Object(fields) => fields.contains_key("status")
+            && fields.keys().all(|k| RESPONSE_FIELDS.contains(&k.as_str())),
+        _ => false,
+    };
+    if !structured {
+        return Json(serialized_value_to_json_value(&value)).into_response();
+    }
+    let RkyvSerializedValue::Object(mut fields) = value else { unreachable!() };
+
+    let status = match fields.remove("status") {
+        Some(RkyvSerializedValue::Number(code)) => u16::try_from(code).ok().and_then(|code| StatusCode::from_u16(code).ok()),
+        _ => None,
+    };
+    let Some(status) = status else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Handler returned an invalid status").into_response();
+    };
+    let mut response = match fields.remove("body") {
+        None | Some(RkyvSerializedValue::Null) => status.into_response(),
+        Some(RkyvSerializedValue::String(body)) => (status, body).into_response(),
+        Some(body) => (status, Json(serialized_value_to_json_value(&body))).into_response(),
+    };
+    if let Some(RkyvSerializedValue::Object(headers)) = fields.remove("headers") {
+        for (name, value) in headers {
+            let value = match value {
+                RkyvSerializedValue::String(value) => value,
+                value => serialized_value_to_json_value(&value).to_string(),
+            };
+            match (HeaderName::try_from(name.as_str()), HeaderValue::try_from(value)) {
+                (Ok(name), Ok(value)) => { response.headers_mut().append(name, value); }
+                _ => return (StatusCode::INTERNAL_SERVER_ERROR, "Handler returned an invalid header").into_response(),
+            }
+        }
+    }
+    response
+}
+
+/// Read the body of `request` and pass it through the middleware of the listener to `endpoint`
+async fn serve_with_middleware(listener: &WebhookListener, request: axum::extract::Request, endpoint: Endpoint) -> Response {
+    let (parts, body) = request.into_parts();
+    let limit = usize::try_from(listener.cell.max_body_bytes).unwrap_or(usize::MAX);
+    let body = match axum::body::to_bytes(body, limit).await {
+        Ok(body) => body,
+        Err(_) => return (StatusCode::PAYLOAD_TOO_LARGE, "Request body too large").into_response(),
+    };
+    Next::new(listener.middleware.clone(), endpoint)
+        .run(WebRequest::from_parts(parts, body))
+        .await
+}
+
+async fn receive_webhook(
+    State(listener): State<Arc<WebhookListener>>,
+    request: axum::extract::Request,
+) -> Response {
+    let endpoint_listener = listener.clone();
+    let endpoint: Endpoint = Arc::new(move |request: WebRequest| {
+        let listener = endpoint_listener.clone();
+        async move { handle_webhook(&listener, request).await }.boxed()
+    });
+    serve_with_middleware(&listener, request, endpoint).await
+}
+
+async fn receive_route(listener: Arc<WebhookListener>, route: Arc<Route>, request: axum::extract::Request) -> Response {
+    let endpoint_listener = listener.clone();
+    let endpoint: Endpoint = Arc::new(move |request: WebRequest| {
+        let (listener, route) = (endpoint_listener.clone(), route.clone());
+        async move { handle_route(&listener, &route, request).await }.boxed()
+    });
+    serve_with_middleware(&listener, request, endpoint).await
+}
+
+/// Values of the path and query string are read as json when they parse as such, e.g. numbers
+fn request_value(value: &str) -> RkyvSerializedValue {
+    serde_json::from_str::<serde_json::Value>(value)
+        .map(|value| json_value_to_serialized_value(&value))
+        .unwrap_or_else(|_| RkyvSerializedValue::String(value.to_string()))
+}
+
+/// Answer a request to one of the routes of the cell by invoking its handler with the parameters
+/// of the route, taken from the path, the query string, or the json body of the request in that order
+async fn handle_route(listener: &WebhookListener, route: &Route, request: WebRequest) -> Response {
+    let path_params = match_path(&route.path, request.uri().path()).unwrap_or_default();
+    let query: HashMap<String, String> = match request.uri().query() {
+        Some(_) => match Query::try_from_uri(request.uri()) {
+            Ok(Query(query)) => query,
+            Err(_) => return (StatusCode::BAD_REQUEST, "Malformed query string").into_response(),
+        },
+        None => HashMap::new(),
+    };
+    let body = if request.body().is_empty() {
+        serde_json::Map::new()
+    } else {
+        match serde_json::from_slice::<serde_json::Value>(request.body()) {
+            Ok(serde_json::Value::Object(body)) => body,
+            _ => return (StatusCode::BAD_REQUEST, "Expected a JSON object").into_response(),
+        }
+    };
+
+    let mut kwargs = RkyvObjectBuilder::new();
+    for param in &route.params {
+        let value = match (path_params.get(param), query.get(param), body.get(param)) {
+            (Some(value), _, _) | (None, Some(value), _) => request_value(value),
+            (None, None, Some(value)) => json_value_to_serialized_value(value),
+            (None, None, None) => return (StatusCode::BAD_REQUEST, format!("Missing argument {}", param)).into_response(),
+        };
+        kwargs = kwargs.insert_value(param, value);
+    }
+
+    let _serial = match listener.cell.concurrency {
+        RequestConcurrency::Serial => Some(listener.serial.lock().await),
+        RequestConcurrency::Concurrent => None,
+    };
+    let args = RkyvObjectBuilder::new().insert_value("kwargs", kwargs.build()).build();
+    match listener.execution_state.respond_to_request(listener.operation_id, &route.handler, args, listener.cell.record_requests).await {
+        Ok(Ok(response)) => handler_response(response),
+        Ok(Err(e)) => handler_failure(&route.handler, e),
+        Err(e) => handler_failure(&route.handler, e),
+    }
+}
+
+/// Failures of the handler are logged rather than returned, they may describe the program
+fn handler_failure(handler: &str, error: impl fmt::Display) -> Response {
+    error!("The handler {} failed to answer a request: {}", handler, error);
+    (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response()
+}
+
+async fn handle_webhook(listener: &WebhookListener, request: WebRequest) -> Response {
+    // Other methods are only routed here for middleware to answer, such as CORS preflight requests
+    if request.method() != Method::POST {
+        return StatusCode::METHOD_NOT_ALLOWED.into_response();
+    }
+    let (headers, body) = (request.headers(), request.body());
+    if let Some(secret) = &listener.secret {
+        let signature = headers
+            .get(listener.cell.signature_header.as_str())
+            .and_then(|v| v.to_str().ok());
+        match signature {
+            Some(signature) if verify_signature(&listener.cell.signature_algorithm, secret, body, signature) => {}
+            _ => return (StatusCode::UNAUTHORIZED, "Invalid signature").into_response(),
+        }
+    }
+
+    let payload = match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(value @ serde_json::Value::Object(_)) => json_value_to_serialized_value(&value),
+        _ => return (StatusCode::BAD_REQUEST, "Expected a JSON object").into_response(),
+    };
+
+    let _serial = match listener.cell.concurrency {
+        RequestConcurrency::Serial => Some(listener.serial.lock().await),
+        RequestConcurrency::Concurrent => None,
+    };
+
+    if let Some(handler) = &listener.cell.handler {
+        let args = RkyvObjectBuilder::new().insert_value("kwargs", payload).build();
+        return match listener.execution_state.respond_to_request(listener.operation_id, handler, args, listener.cell.record_requests).await {
+            Ok(Ok(response)) => handler_response(response),
+            Ok(Err(e)) => handler_failure(handler, e),
+            Err(e) => handler_failure(handler, e),
+        };
+    }
+
+    let value = RkyvObjectBuilder::new()
+        .insert_value(&payload_name(&listener.cell), payload)
+        .build();
+    listener.execution_state.receive_webhook_payload(listener.operation_id, value).await;
+    StatusCode::OK.into_response()
+}
+
+fn webhook_secret(cell: &WebhookCell) -> anyhow::Result<Option<String>> {
+    cell.secret_env.as_ref()
+        .map(|name| std::env::var(name).map_err(|_| anyhow::anyhow!("The webhook secret variable {} is not set", name)))
+        .transpose()
+}
+
+/// `middleware` is that of the operation, see `web_cell::add_middleware`
+pub fn webhook_cell_exec(cell: WebhookCell, middleware: Vec<Arc<dyn WebMiddleware>>) -> anyhow::Result<Box<OperationFn>> {
+    let middleware: Arc<[Arc<dyn WebMiddleware>]> = middleware.into();
+    Ok(Box::new(move |s, _, _, _| {
+        let secret = match webhook_secret(&cell) {
+            Ok(secret) => secret,
+            Err(e) => return async move { Err(e) }.boxed(),
+        };
+        let listener = Arc::new(WebhookListener {
+            cell: cell.clone(),
+            secret,
+            middleware: middleware.clone(),
+            operation_id: s.evaluating_operation_id,
+            execution_state: s.clone(),
+            serial: tokio::sync::Mutex::new(()),
+        });
+        let servers = s.webhook_servers.clone();
+        async move {
+            let cell = &listener.cell;
+            let mut app = Router::new()
+                .route(&cell.path, post(receive_webhook).options(receive_webhook));
+            for route in &cell.route_table.routes {
+                let method = MethodFilter::try_from(Method::from_bytes(route.method.as_bytes())?)?;
+                let route = Arc::new(route.clone());
+                app = app.route(&server_path(&route.path), on(method, move |State(listener): State<Arc<WebhookListener>>, request: axum::extract::Request| {
+                    receive_route(listener, route.clone(), request)
+                }));
+            }
+            let app = app.with_state(listener.clone());
+            servers.start(listener.operation_id, cell, app).await?;
+            // No payload has been received yet, downstream cells wait for the first delivery
+            Ok(OperationFnOutput::with_value(RkyvSerializedValue::Null))
+        }.boxed()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = b"The quick brown fox jumps over the lazy dog";
+
+    #[test]
+    fn test_verify_signature_sha256() {
+        let signature = "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8";
+        assert!(verify_signature(&SignatureAlgorithm::HmacSha256, "key", BODY, signature));
+        assert!(verify_signature(&SignatureAlgorithm::HmacSha256, "key", BODY, &signature[7..]));
+        assert!(!verify_signature(&SignatureAlgorithm::HmacSha256, "other", BODY, signature));
+        assert!(!verify_signature(&SignatureAlgorithm::HmacSha256, "key", b"tampered", signature));
+        assert!(!verify_signature(&SignatureAlgorithm::HmacSha256, "key", BODY, "sha256=not-hex"));
+        // The digest is right but labelled with another algorithm
+        assert!(!verify_signature(&SignatureAlgorithm::HmacSha256, "key", BODY, &format!("sha1={}", &signature[7..])));
+    }
+
+    #[test]
+    fn test_handler_response() {
+        let response = handler_response(RkyvObjectBuilder::new()
+            .insert_number("status", 404)
+            .insert_value("headers", RkyvObjectBuilder::new().insert_string("Set-Cookie", "session=1".to_string()).build())
+            .insert_string("body", "Not found".to_string())
+            .build());
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers().get("set-cookie").unwrap(), "session=1");
+
+        // Objects with fields other than those of a response are returned as the body
+        let response = handler_response(RkyvObjectBuilder::new()
+            .insert_number("status", 404)
+            .insert_number("count", 1)
+            .build());
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(handler_response(RkyvSerializedValue::Number(3)).status(), StatusCode::OK);
+
+        let response = handler_response(RkyvObjectBuilder::new().insert_number("status", 1000).build());
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    fn ok_endpoint() -> Endpoint {
+        Arc::new(|_| async { StatusCode::OK.into_response() }.boxed())
+    }
+
+    fn request(authorization: Option<&str>) -> WebRequest {
+        let mut builder = axum::http::Request::builder().method(Method::POST).uri("/webhook");
+        if let Some(authorization) = authorization {
+            builder = builder.header(header::AUTHORIZATION, authorization);
+        }
+        builder.body(Bytes::new()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_basic_auth_rejects_unauthorized_requests() {
+        let auth: Arc<dyn WebMiddleware> = Arc::new(BasicAuthMiddleware(HashMap::from([
+            ("ada".to_string(), "secret".to_string()),
+        ])));
+        let middleware: Arc<[Arc<dyn WebMiddleware>]> = vec![Arc::new(LoggingMiddleware) as Arc<dyn WebMiddleware>, auth].into();
+        let run = |authorization: Option<&str>| Next::new(middleware.clone(), ok_endpoint()).run(request(authorization));
+
+        let response = run(None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().contains_key(header::WWW_AUTHENTICATE));
+        // "ada:wrong" and a malformed header
+        assert_eq!(run(Some("Basic YWRhOndyb25n")).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(run(Some("Bearer token")).await.status(), StatusCode::UNAUTHORIZED);
+        // "ada:secret"
+        assert_eq!(run(Some("Basic YWRhOnNlY3JldA==")).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_cors_allows_configured_origins() {
+        let middleware: Arc<[Arc<dyn WebMiddleware>]> = vec![
+            Arc::new(CorsMiddleware(vec!["https://example.com".to_string()])) as Arc<dyn WebMiddleware>,
+        ].into();
+        let mut preflight = request(None);
+        *preflight.method_mut() = Method::OPTIONS;
+        preflight.headers_mut().insert(header::ORIGIN, HeaderValue::from_static("https://example.com"));
+        let response = Next::new(middleware.clone(), ok_endpoint()).run(preflight).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "https://example.com");
+
+        let mut other = request(None);
+        other.headers_mut().insert(header::ORIGIN, HeaderValue::from_static("https://other.com"));
+        let response = Next::new(middleware, ok_endpoint()).run(other).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[test]
+    fn test_build_middleware_from_configuration() {
+        std::env::set_var("CHIDORI_TEST_WEBHOOK_PASSWORD", "secret");
+        let cell: WebhookCell = serde_json::from_value(serde_json::json!({
+            "port": 8080,
+            "middleware": ["logging", {"basic_auth": {"users": {"ada": "CHIDORI_TEST_WEBHOOK_PASSWORD"}}}],
+        })).unwrap();
+        assert_eq!(cell.max_body_bytes, crate::cells::DEFAULT_WEBHOOK_MAX_BODY_BYTES);
+        assert_eq!(build_middleware(&cell.middleware).unwrap().len(), 2);
+
+        let unset = [WebMiddlewareConfig::BasicAuth { users: HashMap::from([
+            ("ada".to_string(), "CHIDORI_TEST_WEBHOOK_PASSWORD_UNSET".to_string()),
+        ]) }];
+        assert!(build_middleware(&unset).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_request_body_over_limit_is_rejected() {
+        let cell: WebhookCell = serde_json::from_str(r#"{"port": 8080, "max_body_bytes": 16}"#).unwrap();
+        let listener = Arc::new(WebhookListener {
+            cell,
+            secret: None,
+            middleware: vec![].into(),
+            operation_id: uuid::Uuid::nil(),
+            execution_state: ExecutionState::new_with_random_id(),
+            serial: tokio::sync::Mutex::new(()),
+        });
+        let request = axum::http::Request::builder().method(Method::POST).uri("/")
+            .body(axum::body::Body::from(BODY))
+            .unwrap();
+        let response = receive_webhook(State(listener), request).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_healthz_reports_the_last_step() -> anyhow::Result<()> {
+        let servers = WebhookServers::default();
+        let cell: WebhookCell = serde_json::from_str(r#"{"port": 0, "path": "/hook"}"#)?;
+        let address = servers.start(uuid::Uuid::nil(), &cell, Router::new()).await?;
+        let url = format!("http://{}/healthz", address);
+
+        let response = reqwest::get(&url).await?;
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.json::<serde_json::Value>().await?, serde_json::json!({"status": "ok", "last_step_failed": false}));
+
+        servers.record_step(true);
+        let response = reqwest::get(&url).await?;
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.json::<serde_json::Value>().await?["status"], "error");
+        servers.stop_all().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handler_failures_are_not_returned() {
+        let cell: WebhookCell = serde_json::from_str(r#"{"port": 8080, "handler": "missing"}"#).unwrap();
+        let listener = Arc::new(WebhookListener {
+            cell,
+            secret: None,
+            middleware: vec![].into(),
+            operation_id: uuid::Uuid::nil(),
+            execution_state: ExecutionState::new_with_random_id(),
+            serial: tokio::sync::Mutex::new(()),
+        });
+        let request = axum::http::Request::builder().method(Method::POST).uri("/")
+            .body(axum::body::Body::from("{}"))
+            .unwrap();
+        let response = receive_webhook(State(listener), request).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"Internal server error");
+    }
+
+    #[test]
+    fn test_verify_signature_sha1() {
+        let signature = "sha1=de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9";
+        assert!(verify_signature(&SignatureAlgorithm::HmacSha1, "key", BODY, signature));
+        assert!(!verify_signature(&SignatureAlgorithm::HmacSha256, "key", BODY, signature));
+    }
+}
@@ -1,5 +1,9 @@
 use std::collections::{HashMap, HashSet, VecDeque};
 use crate::execution::execution::execution_state::{CloseReason, DependencyGraphMutation, EnclosedState, ExecutionState};
+use crate::execution::execution::html_report::{cell_source, evaluation_duration, render_html_report, HtmlReportOptions};
+use crate::execution::execution::repro::{write_repro_bundle, ReproManifest};
+use crate::execution::execution::stream::{collect_stream_ids, remove_stream};
+use crate::utils::redaction::RedactionConfig;
 use std::fmt;
 use std::fmt::Debug;
 use std::future::Future;
@@ -33,6 +37,7 @@ use tokio::sync::oneshot::Receiver;
 use uuid::Uuid;
 use crate::cells::CellTypes;
 use crate::execution::primitives::operation::OperationFnOutput;
+use crate::utils::diff::{diff_values, ValueDiff};
 use tokio::sync::mpsc::{Sender, channel};
 use tracing::debug;
 // TODO: update all of these identifies to include a "space" they're within
@@ -52,6 +57,25 @@ pub type ExecutionGraphSendPayload = (ExecutionState, Option<tokio::sync::onesho
 
 pub type ExecutionGraphDiGraphSet = DiGraphMap<ExecutionNodeId, ExecutionState>;
 
+/// Labels attached to edges of the dependency graph by `ExecutionGraph::annotate_edge`,
+/// keyed by the operation depended on and the operation depending on it.
+pub type EdgeAnnotations = HashMap<(OperationId, OperationId), Vec<String>>;
+
+/// An edge of the dependency graph along with the labels attached to it
+pub type AnnotatedDependencyEdge = (OperationId, OperationId, Vec<DependencyReference>, Vec<String>);
+
+
+/// How the states at the heads of two branches of the execution graph differ, see `ExecutionGraph::compare_branches`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BranchComparison {
+    /// Changes from the parameters of the first branch to those of the second
+    pub params: ValueDiff,
+    /// Changes to the output of each operation whose output differs, ordered by operation
+    pub outputs: Vec<(OperationId, ValueDiff)>,
+}
+
+/// Number of ancestors of the execution head retained by `ExecutionGraph::compact` unless configured
+pub const DEFAULT_COMPACTION_DEPTH: usize = 10;
 
 /// This models the network of reactive relationships between different components.
 ///
@@ -86,6 +110,15 @@ pub struct ExecutionGraph {
     /// execution states maintain a value that indicates the head location within this queue
     /// that they've processed thus far.
     pub chat_message_queue: Vec<String>,
+
+    /// Labels for edges of the dependency graph, these apply to every state in the graph
+    edge_annotations: EdgeAnnotations,
+
+    /// Parameters set on states of the graph, for the states evaluated from them
+    branch_params: HashMap<ExecutionNodeId, RkyvSerializedValue>,
+
+    /// Number of ancestors of the execution head retained by `compact`
+    compaction_depth: usize,
 }
 
 impl std::fmt::Debug for ExecutionGraph {
@@ -180,6 +213,9 @@ impl ExecutionGraph {
             execution_node_id_to_state: state_id_to_state,
             execution_graph,
             chat_message_queue: vec![],
+            edge_annotations: HashMap::new(),
+            branch_params: HashMap::new(),
+            compaction_depth: DEFAULT_COMPACTION_DEPTH,
             execution_state_sender: execution_event_tx,
             execution_state_receiver: Some(execution_event_rx)
         }
@@ -193,47 +229,339 @@ impl ExecutionGraph {
         self.cancellation_notify.notify_one();
     }
 
+    /// Add a state that was not produced by evaluation, such as one restored from a checkpoint,
+    /// as a child of its parent state
+    pub fn insert_state(&self, state: ExecutionState) {
+        self.execution_node_id_to_state.insert(state.chronology_id, state.clone());
+        self.execution_graph.lock().unwrap().add_edge(state.parent_state_chronology_id, state.chronology_id, state);
+    }
+
     #[tracing::instrument]
     pub fn get_execution_graph_elements(&self) -> Vec<(ChronologyId, ChronologyId)>  {
         let execution_graph = self.execution_graph.lock().unwrap();
         execution_graph.deref().all_edges().map(|x| (x.0, x.1)).collect()
     }
 
+    /// Attach a label to the edge from `from` to the operation `to` that depends on it, e.g. "provides x".
+    /// Labels are shown alongside the edge wherever the dependency graph is rendered.
+    pub fn annotate_edge(&mut self, from: OperationId, to: OperationId, label: String) {
+        let labels = self.edge_annotations.entry((from, to)).or_default();
+        if !labels.contains(&label) {
+            labels.push(label);
+        }
+    }
+
+    pub fn get_edge_annotations(&self) -> &EdgeAnnotations {
+        &self.edge_annotations
+    }
+
+    /// The edges of the dependency graph of the state at `id`, with the labels attached to each
+    pub fn get_dependency_graph_elements(&self, id: ExecutionNodeId) -> Vec<AnnotatedDependencyEdge> {
+        self.get_state_at_id(id)
+            .map(|state| self.annotate_dependency_graph(&state))
+            .unwrap_or_default()
+    }
+
+    /// The edges of the dependency graph of `state`, with the labels attached to each
+    pub fn annotate_dependency_graph(&self, state: &ExecutionState) -> Vec<AnnotatedDependencyEdge> {
+        state.get_dependency_graph_flattened().into_iter()
+            .map(|(from, to, references)| {
+                let labels = self.edge_annotations.get(&(from, to)).cloned().unwrap_or_default();
+                (from, to, references, labels)
+            })
+            .collect()
+    }
+
+    /// The dependency graph of the state at `id` in the DOT format, edges are labelled with their annotations
+    pub fn export_dependency_graph_dot(&self, id: ExecutionNodeId) -> Option<String> {
+        self.get_state_at_id(id).map(|state| state.dependency_graph_dot(&self.edge_annotations))
+    }
+
     pub fn get_state_at_id(&self, id: ExecutionNodeId) -> Option<ExecutionState> {
         self.execution_node_id_to_state.get(&id).map(|x| x.clone())
     }
 
-    /// Performs a depth first traversal of the execution graph to resolve the combined
-    /// state at a given node.
-    // #[tracing::instrument]
-    // pub fn get_merged_state_history(&self, endpoint: &ExecutionNodeId) -> MergedStateHistory {
-    //     println!("Getting merged state history for id {:?}", &endpoint);
-    //     let execution_graph = self.execution_graph.lock();
-    //     let graph = execution_graph.as_ref().unwrap();
-    //     let mut dfs = Dfs::new(graph.deref(), endpoint.clone());
-    //     let root = Uuid::nil();
-    //     let mut queue = vec![endpoint.clone()];
-    //     while let Some(node) = dfs.next(graph.deref()) {
-    //         if node == root {
-    //             break;
-    //         }
-    //         for predecessor in graph.neighbors_directed(node, Direction::Incoming) {
-    //             if !dfs.discovered.is_visited(&predecessor) {
-    //                 queue.push(predecessor);
-    //                 dfs.stack.push(predecessor);
-    //             }
-    //         }
-    //     }
-    //
-    //     let mut merged_state = HashMap::new();
-    //     for predecessor in queue {
-    //         let state = self.get_state_at_id(predecessor).unwrap();
-    //         for (k, v) in state.state.iter() {
-    //             merged_state.insert(*k, (predecessor, v.clone()));
-    //         }
-    //     }
-    //     MergedStateHistory(merged_state)
-    // }
+    /// The operations ready to be evaluated from the given state, partitioned into groups that are safe
+    /// to evaluate concurrently, see `ExecutionState::concurrent_execution_groups`. Empty if the state
+    /// is not in the graph or its operations could not be inspected.
+    pub fn get_concurrent_execution_groups_for_step(&self, state_id: ExecutionNodeId) -> Vec<Vec<OperationId>> {
+        let Some(state) = self.get_state_at_id(state_id) else {
+            return vec![];
+        };
+        state.concurrent_execution_groups().unwrap_or_else(|e| {
+            debug!("Failed to determine concurrent execution groups at {:?}: {:?}", state_id, e);
+            vec![]
+        })
+    }
+
+    /// Returns the states along the path from `from` to `to` in the execution graph, inclusive
+    /// of both ends. Returns an empty Vec if `to` is not reachable from `from`.
+    pub fn get_states_in_range(&self, from: ExecutionNodeId, to: ExecutionNodeId) -> Vec<ExecutionNodeId> {
+        let execution_graph = self.execution_graph.lock().unwrap();
+        let graph = execution_graph.deref();
+        if !graph.contains_node(from) || !graph.contains_node(to) {
+            return vec![];
+        }
+
+        // Breadth first search, tracking how we reached each node so the path can be rebuilt
+        let mut came_from: HashMap<ExecutionNodeId, ExecutionNodeId> = HashMap::new();
+        let mut visited = HashSet::from([from]);
+        let mut queue = VecDeque::from([from]);
+        while let Some(node) = queue.pop_front() {
+            if node == to {
+                let mut path = vec![to];
+                let mut current = to;
+                while let Some(prev) = came_from.get(&current) {
+                    path.push(*prev);
+                    current = *prev;
+                }
+                path.reverse();
+                return path;
+            }
+            for next in graph.neighbors_directed(node, Direction::Outgoing) {
+                if visited.insert(next) {
+                    came_from.insert(next, node);
+                    queue.push_back(next);
+                }
+            }
+        }
+        vec![]
+    }
+
+    /// Returns the path of states from the root of the execution graph to `id`, inclusive of
+    /// both ends. Returns an empty Vec if `id` is not in the graph.
+    pub fn ancestry(&self, id: ExecutionNodeId) -> Vec<ExecutionNodeId> {
+        let execution_graph = self.execution_graph.lock().unwrap();
+        let graph = execution_graph.deref();
+        if !graph.contains_node(id) {
+            return vec![];
+        }
+
+        // Every state has a single parent, walk them back to the root
+        let mut path = vec![id];
+        let mut visited = HashSet::from([id]);
+        let mut current = id;
+        while let Some(parent) = graph.neighbors_directed(current, Direction::Incoming).next() {
+            if !visited.insert(parent) {
+                break;
+            }
+            path.push(parent);
+            current = parent;
+        }
+        path.reverse();
+        path
+    }
+
+    /// Set the parameters of the branch continuing from `leaf`, an object of named values. They are
+    /// read by the states evaluated from `leaf` and carried by those to their own descendants, so
+    /// setting different parameters before each evaluation from a state forks it into branches
+    /// that differ only by their parameters.
+    pub fn set_branch_params(&mut self, leaf: ExecutionNodeId, params: RkyvSerializedValue) -> anyhow::Result<()> {
+        if !matches!(params, RkyvSerializedValue::Object(_)) {
+            return Err(anyhow!("Branch parameters must be an object of named values, received {:?}", params));
+        }
+        self.branch_params.insert(leaf, params);
+        Ok(())
+    }
+
+    /// The parameters in effect at the state `id`, those it was evaluated with overridden key by
+    /// key by any set on it with `set_branch_params`
+    pub fn get_branch_params(&self, id: ExecutionNodeId) -> RkyvSerializedValue {
+        let mut params = match self.get_state_at_id(id).map(|state| state.branch_params) {
+            Some(RkyvSerializedValue::Object(params)) => params,
+            _ => HashMap::new(),
+        };
+        if let Some(RkyvSerializedValue::Object(set_at_state)) = self.branch_params.get(&id) {
+            params.extend(set_at_state.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        RkyvSerializedValue::Object(params)
+    }
+
+    /// Compare the parameters and the outputs of operations at two states, typically the heads of
+    /// two branches forked from the same state. Returns None if either state is not in the graph.
+    pub fn compare_branches(&self, a: ExecutionNodeId, b: ExecutionNodeId) -> Option<BranchComparison> {
+        let state_a = self.get_state_at_id(a)?;
+        let state_b = self.get_state_at_id(b)?;
+        let mut operations: Vec<OperationId> = state_a.cells_by_id.keys()
+            .chain(state_b.cells_by_id.keys())
+            .copied()
+            .collect();
+        operations.sort();
+        operations.dedup();
+
+        let output_of = |state: &ExecutionState, op_id: &OperationId| match state.state_get_value(op_id) {
+            Some(Ok(value)) => value.clone(),
+            _ => RkyvSerializedValue::Null,
+        };
+        let outputs = operations.into_iter()
+            .filter_map(|op_id| {
+                let diff = diff_values(&output_of(&state_a, &op_id), &output_of(&state_b, &op_id));
+                (!diff.is_empty()).then_some((op_id, diff))
+            })
+            .collect();
+        Some(BranchComparison {
+            params: diff_values(&self.get_branch_params(a), &self.get_branch_params(b)),
+            outputs,
+        })
+    }
+
+    /// Renders every state recorded in this graph as a self-contained html document.
+    pub fn render_html_report(&self, options: &HtmlReportOptions) -> String {
+        let states: Vec<ExecutionState> = self.execution_node_id_to_state
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect();
+        render_html_report(&states, options)
+    }
+
+    /// Writes the html report for this graph to `path`, see `render_html_report`.
+    pub fn export_html_report(&self, path: &std::path::Path, options: &HtmlReportOptions) -> anyhow::Result<()> {
+        std::fs::write(path, self.render_html_report(options))?;
+        Ok(())
+    }
+
+    /// Writes a bundle to `path` holding what is needed to reproduce the failure at `node` offline,
+    /// loaded again with `InteractiveChidoriWrapper::load_repro`. The failure is that of the operations
+    /// with a failed output at `node`, or of those evaluated next when a step from `node` failed.
+    /// Only those cells and the cells they depend on are included, along with the outputs recorded
+    /// for the latter with `redaction` applied. See the `repro` module for the layout of bundles.
+    pub fn export_repro(&self, node: ExecutionNodeId, path: &std::path::Path, redaction: &RedactionConfig) -> anyhow::Result<ReproManifest> {
+        let state = self.get_state_at_id(node)
+            .ok_or_else(|| anyhow!("State {} is not part of the execution graph", node))?;
+        write_repro_bundle(&state, path, redaction)
+    }
+
+    /// Serialize the execution graph in the node-link format read by networkx's
+    /// `json_graph.node_link_graph`, for analysis of runs in Python. Each node is a state, annotated
+    /// with the cell it evaluated, its execution counter and how long that evaluation took.
+    pub fn export_to_networkx_json(&self) -> String {
+        let mut states: Vec<ExecutionState> = self.execution_node_id_to_state
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect();
+        states.sort_by_key(|s| s.chronology_id);
+        let nodes: Vec<serde_json::Value> = states.iter().map(|state| {
+            let name = state.evaluating_name.clone()
+                .or_else(|| state.evaluating_cell.as_ref().and_then(|c| c.name().clone()));
+            serde_json::json!({
+                "id": state.chronology_id.to_string(),
+                "name": name,
+                "language": state.evaluating_cell.as_ref().map(|c| cell_source(c).0),
+                "execution_count": state.exec_counter,
+                "last_duration_ms": evaluation_duration(state).map(|d| d.as_secs_f64() * 1000.0),
+            })
+        }).collect();
+
+        let mut edges = self.get_execution_graph_elements();
+        edges.sort();
+        let links: Vec<serde_json::Value> = edges.into_iter()
+            .map(|(source, target)| serde_json::json!({
+                "source": source.to_string(),
+                "target": target.to_string(),
+            }))
+            .collect();
+
+        serde_json::json!({
+            "directed": true,
+            "multigraph": false,
+            "graph": {},
+            "nodes": nodes,
+            "links": links,
+        }).to_string()
+    }
+
+    /// The output of every operation at the state `endpoint`, each alongside the state along its
+    /// ancestry that produced it.
+    pub fn get_merged_state_history(&self, endpoint: &ExecutionNodeId) -> MergedStateHistory {
+        let mut merged_state: HashMap<OperationId, (ExecutionNodeId, Arc<OperationFnOutput>)> = HashMap::new();
+        for id in self.ancestry(*endpoint) {
+            let Some(state) = self.get_state_at_id(id) else { continue };
+            for (op_id, output) in state.state.iter() {
+                // States carry forward the outputs of their parents, only a changed output was produced here
+                let unchanged = merged_state.get(op_id).map_or(false, |(_, previous)| Arc::ptr_eq(previous, output));
+                if !unchanged {
+                    merged_state.insert(*op_id, (id, output.clone()));
+                }
+            }
+        }
+        MergedStateHistory(merged_state)
+    }
+
+    /// Every evaluation of `operation_id` along the ancestry of `endpoint`, oldest first, each
+    /// alongside the state that produced it. Outputs carry the input they were evaluated with.
+    pub fn operation_history(&self, operation_id: OperationId, endpoint: &ExecutionNodeId) -> Vec<(ExecutionNodeId, Arc<OperationFnOutput>)> {
+        let mut history: Vec<(ExecutionNodeId, Arc<OperationFnOutput>)> = vec![];
+        for id in self.ancestry(*endpoint) {
+            let Some(output) = self.get_state_at_id(id).and_then(|state| state.state.get(&operation_id).cloned()) else { continue };
+            if history.last().map_or(true, |(_, previous)| !Arc::ptr_eq(previous, &output)) {
+                history.push((id, output));
+            }
+        }
+        history
+    }
+
+    /// Set the number of ancestors of the execution head retained by `compact`
+    pub fn set_compaction_depth(&mut self, depth: usize) {
+        self.compaction_depth = depth;
+    }
+
+    /// Discard every state other than `head` and its nearest ancestors, up to the compaction depth,
+    /// much like `git gc`. The oldest retained state becomes a child of the root, so that the
+    /// retained states form the whole history of the graph. This is lossy, discarded states can
+    /// no longer be reverted to.
+    pub fn compact(&mut self, head: ExecutionNodeId) -> anyhow::Result<()> {
+        let retained: Vec<ExecutionNodeId> = if head.is_nil() {
+            vec![]
+        } else {
+            let ancestry = self.ancestry(head);
+            if ancestry.is_empty() {
+                return Err(anyhow!("State {} is not part of the execution graph", head));
+            }
+            let skip = ancestry.len().saturating_sub(self.compaction_depth + 1);
+            ancestry.into_iter().skip(skip).filter(|id| !id.is_nil()).collect()
+        };
+
+        let mut compacted = DiGraphMap::new();
+        let mut parent = Uuid::nil();
+        for id in &retained {
+            let mut state = self.get_state_at_id(*id)
+                .ok_or_else(|| anyhow!("State {} is in the execution graph but was never recorded", id))?;
+            state.parent_state_chronology_id = parent;
+            self.execution_node_id_to_state.insert(*id, state.clone());
+            compacted.add_edge(parent, *id, state);
+            parent = *id;
+        }
+        let retained: HashSet<ExecutionNodeId> = retained.into_iter().collect();
+        let discarded_streams = self.referenced_streams(|id| !id.is_nil() && !retained.contains(id));
+        self.execution_node_id_to_state.retain(|id, _| id.is_nil() || retained.contains(id));
+        self.branch_params.retain(|id, _| retained.contains(id));
+        *self.execution_graph.lock().unwrap() = compacted;
+
+        // Streams are only removed once no retained state points to them
+        let retained_streams = self.referenced_streams(|_| true);
+        for id in discarded_streams.difference(&retained_streams) {
+            if let Err(e) = remove_stream(id) {
+                debug!("Failed to remove stream {}: {:?}", id, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Ids of the streams pointed to by the outputs, or recorded inputs, of the states matching `filter`
+    fn referenced_streams(&self, filter: impl Fn(&ExecutionNodeId) -> bool) -> HashSet<String> {
+        let mut ids = HashSet::new();
+        for entry in self.execution_node_id_to_state.iter().filter(|entry| filter(entry.key())) {
+            for output in entry.value().state.values() {
+                if let Ok(value) = &output.output {
+                    collect_stream_ids(value, &mut ids);
+                }
+                if let Some(input) = &output.input {
+                    collect_stream_ids(&input.value, &mut ids);
+                }
+            }
+        }
+        ids
+    }
 
     #[tracing::instrument]
     pub async fn push_message(
@@ -336,6 +664,10 @@ mod tests {
             output: Ok(arg0),
             stdout: vec![],
             stderr: vec![],
+            peak_memory_bytes: None,
+            agent_trace: vec![],
+            response_metadata: None,
+            input: None,
         });
         state.state_insert(id_b, OperationFnOutput {
             has_error: false,
@@ -343,6 +675,10 @@ mod tests {
             output: Ok(arg1),
             stdout: vec![],
             stderr: vec![],
+            peak_memory_bytes: None,
+            agent_trace: vec![],
+            response_metadata: None,
+            input: None,
         });
         let (_, new_state, _) = ExecutionGraph::immutable_external_step_execution(state.clone()).await?;
         assert!(new_state.state_get_value(&id_c).is_some());
@@ -351,6 +687,71 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_recorded_input_follows_upstream_changes() -> anyhow::Result<()> {
+        let db = ExecutionGraph::new();
+        let state = ExecutionState::new_with_random_id();
+        let (id_a, state) = state.upsert_operation(OperationNode::new(
+                    None,
+                    Uuid::nil(),
+                    InputSignature::new(),
+                    OutputSignature::new(),
+                    Box::new(|_, _args, _, _| async move { Ok(OperationFnOutput::with_value(RSV::Number(1))) }.boxed()),
+                ),
+                                                    Uuid::now_v7());
+        let (id_b, state) = state.upsert_operation(OperationNode::new(
+                    None,
+                    Uuid::nil(),
+                    InputSignature::new(),
+                    OutputSignature::new(),
+                    Box::new(|_, _args, _, _| async move { Ok(OperationFnOutput::with_value(RSV::Number(2))) }.boxed()),
+                ),
+                                                    Uuid::now_v7());
+        let (id_c, state) = state.upsert_operation(OperationNode::new(
+                    None,
+                    Uuid::nil(),
+                    InputSignature::from_args_list(vec!["a", "b"]),
+                    OutputSignature::new(),
+                    Box::new(|_, _args, _, _| async move { Ok(OperationFnOutput::with_value(RSV::Null)) }.boxed()),
+                ),
+                                                    Uuid::now_v7());
+        let state = state.apply_dependency_graph_mutations(vec![DependencyGraphMutation::Create {
+            operation_id: id_c,
+            depends_on: vec![
+                (id_a, DependencyReference::Positional(0)),
+                (id_b, DependencyReference::Positional(1)),
+            ],
+        }]);
+
+        // The upstream value of a differs between the two evaluations of c
+        let mut first_state = state.clone();
+        first_state.state_insert(id_a, OperationFnOutput::with_value(RSV::Number(1)));
+        first_state.state_insert(id_b, OperationFnOutput::with_value(RSV::Number(2)));
+        let mut second_state = state.clone();
+        second_state.state_insert(id_a, OperationFnOutput::with_value(RSV::Number(5)));
+        second_state.state_insert(id_b, OperationFnOutput::with_value(RSV::Number(2)));
+        let (_, first, _) = ExecutionGraph::immutable_external_step_execution(first_state).await?;
+        let (_, mut second, _) = ExecutionGraph::immutable_external_step_execution(second_state).await?;
+
+        let consumed = |output: &OperationFnOutput| -> Option<(RSV, RSV)> {
+            let RSV::Object(input) = &output.input.as_ref()?.value else { return None };
+            let RSV::Object(args) = input.get("args")? else { return None };
+            Some((args.get("0")?.clone(), args.get("1")?.clone()))
+        };
+
+        // Recorded as part of the history of c, the second evaluation following the first
+        second.parent_state_chronology_id = first.chronology_id;
+        db.insert_state(first.clone());
+        db.insert_state(second.clone());
+        let history = db.operation_history(id_c, &second.chronology_id);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].0, first.chronology_id);
+        assert_eq!(consumed(&history[0].1), Some((RSV::Number(1), RSV::Number(2))));
+        assert_eq!(history[1].0, second.chronology_id);
+        assert_eq!(consumed(&history[1].1), Some((RSV::Number(5), RSV::Number(2))));
+        Ok(())
+    }
+
     /*
     Testing the traverse of the dependency graph. Validating that execution of the graph moves through
     the graph as expected.
@@ -1100,6 +1501,103 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_get_states_in_range_linear_execution() {
+        let db = ExecutionGraph::new();
+        let ids: Vec<ExecutionNodeId> = std::iter::once(Uuid::nil())
+            .chain((0..5).map(|_| Uuid::now_v7()))
+            .collect();
+        {
+            let mut graph = db.execution_graph.lock().unwrap();
+            for pair in ids.windows(2) {
+                graph.add_edge(pair[0], pair[1], ExecutionState::new_with_random_id());
+            }
+        }
+
+        assert_eq!(db.get_states_in_range(ids[1], ids[5]), ids[1..].to_vec());
+        assert_eq!(db.get_states_in_range(ids[2], ids[2]), vec![ids[2]]);
+        // History only moves forward
+        assert!(db.get_states_in_range(ids[5], ids[1]).is_empty());
+        assert!(db.get_states_in_range(ids[1], Uuid::now_v7()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_compact_retains_recent_history() -> anyhow::Result<()> {
+        let mut db = ExecutionGraph::new();
+        let op_ids: Vec<OperationId> = (0..15).map(|_| Uuid::now_v7()).collect();
+        let mut ids = vec![];
+        let mut state = ExecutionState::new_with_random_id();
+        state.chronology_id = Uuid::nil();
+        for (i, op_id) in op_ids.iter().enumerate() {
+            let mut next = state.clone();
+            next.chronology_id = Uuid::now_v7();
+            next.parent_state_chronology_id = state.chronology_id;
+            next.state_insert(*op_id, OperationFnOutput::with_value(RSV::Number(i as i32)));
+            db.insert_state(next.clone());
+            ids.push(next.chronology_id);
+            state = next;
+        }
+        let head = *ids.last().unwrap();
+        let before = db.get_merged_state_history(&head);
+        assert_eq!(before.0[&op_ids[3]].0, ids[3]);
+
+        db.set_compaction_depth(4);
+        db.compact(head)?;
+        assert_eq!(db.ancestry(head), std::iter::once(Uuid::nil()).chain(ids[10..].iter().copied()).collect::<Vec<_>>());
+        assert!(db.get_state_at_id(ids[9]).is_none());
+        assert_eq!(db.get_state_at_id(ids[10]).unwrap().parent_state_chronology_id, Uuid::nil());
+
+        // Outputs produced by retained states are still attributed to them
+        let after = db.get_merged_state_history(&head);
+        for (i, op_id) in op_ids.iter().enumerate().skip(11) {
+            assert_eq!(after.0[op_id].0, ids[i]);
+            assert_eq!(after.0[op_id].1.output, Ok(RSV::Number(i as i32)));
+        }
+        // Outputs of discarded states are carried by the oldest retained state
+        assert_eq!(after.0[&op_ids[3]].0, ids[10]);
+        assert_eq!(after.0[&op_ids[3]].1.output, before.0[&op_ids[3]].1.output);
+        assert!(db.compact(Uuid::now_v7()).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compact_removes_streams_of_discarded_states() -> anyhow::Result<()> {
+        use crate::execution::execution::stream::{stream_manifest, StreamWriter};
+        let mut db = ExecutionGraph::new();
+        let op_id = Uuid::now_v7();
+        let retained_op_id = Uuid::now_v7();
+        let mut streams = vec![];
+        let mut ids = vec![];
+        let mut state = ExecutionState::new_with_random_id();
+        state.chronology_id = Uuid::nil();
+        for i in 0..4 {
+            let mut writer = StreamWriter::new();
+            writer.write(RSV::Number(i))?;
+            let pointer = writer.finish()?;
+            streams.push(writer.id().to_string());
+            let mut next = state.clone();
+            next.chronology_id = Uuid::now_v7();
+            next.parent_state_chronology_id = state.chronology_id;
+            // The first stream stays in the state of another operation
+            let op = if i == 0 { retained_op_id } else { op_id };
+            next.state_insert(op, OperationFnOutput::with_value(pointer));
+            db.insert_state(next.clone());
+            ids.push(next.chronology_id);
+            state = next;
+        }
+
+        db.set_compaction_depth(0);
+        db.compact(*ids.last().unwrap())?;
+        assert!(stream_manifest(&streams[0]).is_some());
+        assert!(stream_manifest(&streams[1]).is_none());
+        assert!(stream_manifest(&streams[2]).is_none());
+        assert!(stream_manifest(&streams[3]).is_some());
+        for id in [&streams[0], &streams[3]] {
+            crate::execution::execution::stream::remove_stream(id)?;
+        }
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_get_execution_graph_elements_empty() {
         let db = ExecutionGraph::new();
@@ -1236,4 +1734,77 @@ mod tests {
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_concurrent_execution_groups_separate_writers_of_the_same_output() -> anyhow::Result<()> {
+        let db = ExecutionGraph::new();
+        let mut state = ExecutionState::new_with_random_id();
+        let mut ids = vec![];
+        for source in ["x = 1", "x = 2", "y = 3"] {
+            let cell = CellTypes::Code(crate::cells::CodeCell {
+                backing_file_reference: None,
+                name: None,
+                language: crate::cells::SupportedLanguage::PyO3,
+                source_code: source.to_string(),
+                function_invocation: None,
+                inspect_globals: false,
+                redact_output: false,
+                policy: Default::default(),
+                oom_limit_bytes: None,
+                always_run: false,
+                generate_types: false,
+                package_json: None,
+            }, crate::cells::TextRange::default());
+            let op = state.get_operation_from_cell_type(&cell)?;
+            let (id, new_state) = state.upsert_operation(op, Uuid::now_v7())?;
+            ids.push(id);
+            state = new_state;
+        }
+        let state_id = state.chronology_id;
+        db.execution_node_id_to_state.insert(state_id, state);
+
+        assert_eq!(
+            db.get_concurrent_execution_groups_for_step(state_id),
+            vec![vec![ids[0], ids[2]], vec![ids[1]]]
+        );
+        assert!(db.get_concurrent_execution_groups_for_step(Uuid::now_v7()).is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_edge_annotations_are_included_with_the_dependency_graph() -> anyhow::Result<()> {
+        let mut db = ExecutionGraph::new();
+        let id_a = Uuid::now_v7();
+        let id_b = Uuid::now_v7();
+        let id_c = Uuid::now_v7();
+        let state = ExecutionState::new_with_random_id().apply_dependency_graph_mutations(vec![
+            DependencyGraphMutation::Create {
+                operation_id: id_b,
+                depends_on: vec![(id_a, DependencyReference::Global("x".to_string()))],
+            },
+            DependencyGraphMutation::Create {
+                operation_id: id_c,
+                depends_on: vec![(id_b, DependencyReference::FunctionInvocation("add".to_string()))],
+            },
+        ]);
+        let state_id = state.chronology_id;
+        db.execution_node_id_to_state.insert(state_id, state);
+
+        db.annotate_edge(id_a, id_b, "provides x".to_string());
+        db.annotate_edge(id_a, id_b, "provides x".to_string());
+        db.annotate_edge(id_b, id_c, "calls add".to_string());
+
+        let edges: HashSet<_> = db.get_dependency_graph_elements(state_id).into_iter().collect();
+        let expected: HashSet<_> = vec![
+            (id_a, id_b, vec![DependencyReference::Global("x".to_string())], vec!["provides x".to_string()]),
+            (id_b, id_c, vec![DependencyReference::FunctionInvocation("add".to_string())], vec!["calls add".to_string()]),
+        ].into_iter().collect();
+        assert_eq!(edges, expected);
+
+        let dot = db.export_dependency_graph_dot(state_id).unwrap();
+        assert!(dot.contains("label=\"provides x\""));
+        assert!(dot.contains("label=\"calls add\""));
+        assert!(db.get_dependency_graph_elements(Uuid::now_v7()).is_empty());
+        Ok(())
+    }
+}
@@ -1,11 +1,11 @@
 use crate::execution::primitives::identifiers::{DependencyReference, OperationId};
-use crate::execution::primitives::operation::{InputSignature, OperationFnOutput, OperationNode, OutputItemConfiguration};
-use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue};
+use crate::execution::primitives::operation::{InputSignature, OperationFnOutput, OperationNode, OutputItemConfiguration, OutputSignature, RecordedInput};
+use crate::execution::primitives::serialized_value::{serialize_to_vec, RkyvObjectBuilder, RkyvSerializedValue};
 use im::{HashMap as ImHashMap, HashSet as ImHashSet};
 
 use indexmap::set::IndexSet;
 use indoc::indoc;
-use petgraph::dot::Dot;
+use petgraph::dot::{Config, Dot};
 use petgraph::graphmap::DiGraphMap;
 use petgraph::Direction;
 use std::collections::{HashMap, HashSet, VecDeque};
@@ -24,10 +24,20 @@ use anyhow::Error;
 use tokio::sync::oneshot;
 use futures_util::FutureExt;
 use tokio::sync::oneshot::error::TryRecvError;
-use tracing::debug;
+use tracing::{debug, info};
 use uuid::Uuid;
-use crate::cells::{CellTypes, CodeCell, LLMPromptCell};
-use crate::execution::execution::execution_graph::{ExecutionGraphSendPayload, ExecutionNodeId, ChronologyId};
+use crate::cells::{CellTypes, CodeCell, ExecutionPolicy, LLMPromptCell, MemoryCell, OutputOverflow, ProxyConfig, SupportedModelProviders, DEFAULT_MAX_OUTPUT_BYTES};
+use crate::execution::execution::spill::{spill_directory, spill_output};
+use crate::execution::execution::execution_graph::{ExecutionGraphSendPayload, ExecutionNodeId, ChronologyId, EdgeAnnotations};
+use crate::utils::prompt_audit::PromptAuditLog;
+use crate::library::std::ai::llm::cache::ResponseCache;
+use crate::library::std::ai::llm::ModelResponseMetadata;
+use crate::library::std::code::runtime_deno::DenoModuleConfig;
+use crate::cells::webhook_cell::WebhookServers;
+use crate::cells::web_cell::RouteDiagnostic;
+use crate::utils::diff::diff_values;
+use crate::library::std::ai::memory::store::{MemoryMatch, MemoryQuery, MemoryStores};
+use crate::execution::primitives::agent_trace::{AgentTrace, AgentTraceSink, AgentTraceStepKind};
 
 pub enum OperationExecutionStatusOption {
     Running,
@@ -40,6 +50,45 @@ pub enum OperationExecutionStatus {
     ExecutionEvent(ExecutionNodeId, OperationId, OperationExecutionStatusOption),
 }
 
+/// A problem found when validating a set of cell definitions before applying them
+#[derive(Debug, Clone, PartialEq)]
+pub enum DefinitionIssue {
+    InvalidCell { cell: String, error: String },
+    NamingCollision(String),
+    /// The cell depends on a symbol that was produced before the edit and is no longer produced
+    UnresolvedSymbol { cell: String, symbol: String },
+    DependencyCycle { cells: Vec<String> },
+    /// A route of a webhook cell is handled by a function no cell defines, or one of another arity
+    InvalidRoute { cell: String, diagnostic: RouteDiagnostic },
+}
+
+impl fmt::Display for DefinitionIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DefinitionIssue::InvalidCell { cell, error } => write!(f, "cell {} is invalid: {}", cell, error),
+            DefinitionIssue::NamingCollision(error) => write!(f, "{}", error),
+            DefinitionIssue::UnresolvedSymbol { cell, symbol } => write!(f, "cell {} depends on {}, which is no longer defined", cell, symbol),
+            DefinitionIssue::DependencyCycle { cells } => write!(f, "cells {} depend on each other", cells.join(", ")),
+            DefinitionIssue::InvalidRoute { cell, diagnostic } => write!(f, "cell {} has an invalid route: {}", cell, diagnostic),
+        }
+    }
+}
+
+/// The reasons a set of cell definitions was rejected, in which case none of them were applied
+#[derive(Debug, Clone, PartialEq)]
+pub struct DefinitionValidationReport {
+    pub issues: Vec<DefinitionIssue>,
+}
+
+impl fmt::Display for DefinitionValidationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let issues: Vec<String> = self.issues.iter().map(|i| i.to_string()).collect();
+        write!(f, "the edited cells were not applied: {}", issues.join("; "))
+    }
+}
+
+impl std::error::Error for DefinitionValidationReport {}
+
 #[derive(Debug)]
 pub enum DependencyGraphMutation {
     Create {
@@ -51,6 +100,34 @@ pub enum DependencyGraphMutation {
     },
 }
 
+impl DependencyGraphMutation {
+    /// A sentence describing the mutation for audit logs, e.g. "Set operation 3 to depend on 1
+    /// through global 'x'" or "Removed operation 2"
+    pub fn describe(&self) -> String {
+        match self {
+            DependencyGraphMutation::Create { operation_id, depends_on } if depends_on.is_empty() => {
+                format!("Set operation {} to depend on no other operation", operation_id)
+            }
+            DependencyGraphMutation::Create { operation_id, depends_on } => {
+                let dependencies: Vec<String> = depends_on.iter()
+                    .map(|(source, reference)| {
+                        let through = match reference {
+                            DependencyReference::Positional(index) => format!("positional argument {}", index),
+                            DependencyReference::Keyword(name) => format!("keyword argument '{}'", name),
+                            DependencyReference::Global(name) => format!("global '{}'", name),
+                            DependencyReference::FunctionInvocation(name) => format!("function '{}'", name),
+                            DependencyReference::Ordering => "ordering".to_string(),
+                        };
+                        format!("{} through {}", source, through)
+                    })
+                    .collect();
+                format!("Set operation {} to depend on {}", operation_id, dependencies.join(", "))
+            }
+            DependencyGraphMutation::Delete { operation_id } => format!("Removed operation {}", operation_id),
+        }
+    }
+}
+
 #[derive(thiserror::Error, Debug, PartialOrd, PartialEq, Clone, Serialize)]
 pub enum ExecutionStateErrors {
     #[error("the execution of this graph has reached a fixed point and will not continue without outside influence")]
@@ -61,6 +138,18 @@ pub enum ExecutionStateErrors {
     Unknown(String),
     #[error("Anyhow Error: {0}")]
     AnyhowError(String),
+    #[error("exceeded the maximum function invocation depth of {0}: {1}")]
+    InvocationDepthExceeded(usize, String),
+    #[error("evaluation did not complete within {0}ms")]
+    CellExecutionTimeout(u64),
+    #[error("{0}")]
+    TemplateRenderFailure(String),
+    #[error("evaluation exceeded its memory limit of {0} bytes")]
+    MemoryLimitExceeded(u64),
+    #[error("output of {size} bytes exceeds the limit of {limit} bytes")]
+    OutputTooLarge { size: u64, limit: u64 },
+    #[error("{0} is a stream, which this cell cannot take as an input, iterate over it in a code cell instead")]
+    StreamInputUnsupported(String),
 }
 
 impl From<anyhow::Error> for ExecutionStateErrors {
@@ -70,16 +159,86 @@ impl From<anyhow::Error> for ExecutionStateErrors {
 }
 
 
+fn cell_label(cell: &CellTypes, op_id: OperationId) -> String {
+    cell.name().clone().unwrap_or_else(|| op_id.to_string())
+}
+
+/// Default limit on the depth of nested function invocations across cells
+pub const DEFAULT_MAX_INVOCATION_DEPTH: usize = 32;
+
 #[derive(Debug, Clone)]
 pub struct FunctionMetadata {
-    operation_id: OperationId,
+    pub(crate) operation_id: OperationId,
     pub(crate) input_signature: InputSignature,
 }
 
+/// Progress of the generate-execute-fix loop of a code generation cell
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CodeGenRetryState {
+    /// Operations defined by the most recent generation, these are replaced on each attempt
+    pub generated_operations: Vec<OperationId>,
+    pub attempts: u32,
+    pub max_attempts: u32,
+    /// Error from executing the generated code, fed back into the prompt on the next attempt
+    pub last_error: Option<String>,
+}
+
 pub struct OperationRunningStatus {
     running: bool
 }
 
+/// Hook invoked with the fully resolved inputs of an operation immediately before it executes,
+/// the returned value is what the operation is executed with.
+pub type InputResolutionHook = dyn Fn(OperationId, RkyvSerializedValue) -> RkyvSerializedValue + Send + Sync;
+
+/// Receives the output of each operation as soon as it has been evaluated, including functions
+/// dispatched while another operation is still being evaluated.
+pub type OperationCompletionSink = dyn Fn(OperationId, &OperationFnOutput) + Send + Sync;
+
+/// A function implemented in Rust by the embedder, callable by name from code cells.
+pub type NativeFunction = dyn Fn(RkyvSerializedValue) -> RkyvSerializedValue + Send + Sync;
+
+/// Set once the step evaluating a state is cancelled, see `ChidoriRuntimeInstance::spawn_step`.
+/// States evaluated outside of a step are never cancelled.
+#[derive(Clone, Default)]
+pub struct Cancellation(Option<tokio::sync::watch::Receiver<bool>>);
+
+impl Cancellation {
+    pub fn new(receiver: tokio::sync::watch::Receiver<bool>) -> Self {
+        Self(Some(receiver))
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.as_ref().map_or(false, |receiver| *receiver.borrow())
+    }
+
+    /// Call `on_cancel` from another thread once cancelled, unless the returned watch is dropped
+    /// first. Runtimes evaluating a cell on their own thread use this to interrupt it.
+    pub fn on_cancel(&self, on_cancel: impl FnOnce() + Send + 'static) -> Option<CancellationWatch> {
+        let mut receiver = self.0.clone()?;
+        let (done, finished) = oneshot::channel::<()>();
+        std::thread::spawn(move || {
+            futures::executor::block_on(async move {
+                tokio::select! {
+                    biased;
+                    _ = finished => {}
+                    cancelled = receiver.wait_for(|cancelled| *cancelled) => {
+                        if cancelled.is_ok() {
+                            on_cancel();
+                        }
+                    }
+                }
+            })
+        });
+        Some(CancellationWatch { _done: done })
+    }
+}
+
+/// Stops watching for cancellation once dropped, see `Cancellation::on_cancel`
+pub struct CancellationWatch {
+    _done: oneshot::Sender<()>,
+}
+
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub enum CloseReason {
     Failure,
@@ -110,6 +269,15 @@ pub struct ExecutionState {
 
     pub exec_counter: usize,
     pub stack: VecDeque<ExecutionNodeId>,
+
+    /// Names of the functions currently being invoked across cells, outermost first
+    pub invocation_chain: Vec<String>,
+
+    /// Dispatching a function fails once `invocation_chain` would grow beyond this length
+    pub max_invocation_depth: usize,
+
+    /// Timeout and retry settings of the program, for cells that do not declare their own
+    pub default_execution_policy: ExecutionPolicy,
     pub parent_state_chronology_id: ChronologyId,
 
     pub external_event_queue_head: usize,
@@ -129,12 +297,71 @@ pub struct ExecutionState {
     /// Channel sender used to update the execution graph and resume execution
     pub graph_sender: Option<Arc<tokio::sync::mpsc::Sender<ExecutionGraphSendPayload>>>,
 
+    /// Optional hook used to observe or override the inputs of operations before they execute
+    pub input_resolution_hook: Option<Arc<InputResolutionHook>>,
+
+    /// Transcript sink for prompts sent by LLM cells evaluated from this state
+    pub prompt_audit: Option<Arc<PromptAuditLog>>,
+
+    /// Responses of models reused by LLM cells evaluated from this state
+    pub llm_cache: Option<Arc<ResponseCache>>,
+
+    /// Functions registered by the embedder, invoked from python as `ch.native(name, args)`
+    /// and from javascript as `await native(name, args)`
+    pub native_functions: ImHashMap<String, Arc<NativeFunction>>,
+
+    /// Contents of the memory cells, queried from python as `ch.memory(name).query(...)`, from
+    /// javascript as `memory(name).query(...)` and by prompt cells declaring `context_from`
+    pub memory_stores: Arc<MemoryStores>,
+
+    /// The evaluation of each operation whose memory inserts are visible from this state. Evaluating
+    /// an operation again replaces the inserts of its earlier evaluation, and states from before an
+    /// evaluation do not see its inserts.
+    pub memory_writes: ImHashMap<OperationId, ChronologyId>,
+
+    /// Import map and allowed hosts of the javascript cells evaluated from this state
+    pub deno_modules: DenoModuleConfig,
+
+    /// Servers started by the webhook cells of the program, one per cell
+    pub webhook_servers: Arc<WebhookServers>,
+
+    /// Set once the step evaluating this state is cancelled, runtimes stop the cell they evaluate
+    pub cancellation: Cancellation,
+
+    /// Proxies the requests of prompts are sent through, by their provider
+    pub llm_proxies: ImHashMap<SupportedModelProviders, ProxyConfig>,
+
+    /// The prose documenting each cell in the program it was loaded from, given to models as the
+    /// description of the functions of the cell when it is exposed as a tool
+    pub cell_descriptions: ImHashMap<OperationId, String>,
+
+    /// Receives the reasoning steps recorded by operations evaluated from this state as they happen
+    pub agent_trace_sink: Option<Arc<AgentTraceSink>>,
+
+    /// Receives the output of each operation evaluated from this state as it completes
+    pub completion_sink: Option<Arc<OperationCompletionSink>>,
+
+    /// Steps recorded by the operation being evaluated from this state, see `AgentTrace`
+    pub agent_trace: Option<AgentTrace>,
+
+    /// Parameters of the branch of the execution graph this state is on, read by cells with
+    /// `ch.param(name, default)`. See `ExecutionGraph::set_branch_params`
+    pub branch_params: RkyvSerializedValue,
+
     /// Queue of operations to evaluate
     pub exec_queue: VecDeque<OperationId>,
 
     /// Map of operation_id -> output value of that operation
     pub state: ImHashMap<OperationId, Arc<OperationFnOutput>>,
 
+    /// Map of operation_id -> ephemeral value (progress indicators, partial streaming chunks).
+    /// These are visible on this state but are cleared by the next step and never carried into history.
+    pub state_transient: ImHashMap<OperationId, RkyvSerializedValue>,
+
+    /// Map of code generation operation_id -> retry progress. Kept on the state so that reverting
+    /// to an earlier state also rewinds the generate-execute-fix loop.
+    pub code_gen_retries: ImHashMap<OperationId, CodeGenRetryState>,
+
     /// Values that were introduced specifically by this state being evaluated, used to identity most recent changes
     pub fresh_values: IndexSet<OperationId>,
 
@@ -201,6 +428,9 @@ impl Default for ExecutionState {
             resolving_execution_node_state_id: Uuid::now_v7(),
             chronology_id: Uuid::now_v7(),
             stack: Default::default(),
+            invocation_chain: vec![],
+            max_invocation_depth: DEFAULT_MAX_INVOCATION_DEPTH,
+            default_execution_policy: ExecutionPolicy::default(),
             parent_state_chronology_id: Uuid::nil(),
             evaluating_operation_id: Uuid::nil(),
             evaluating_name: None,
@@ -210,8 +440,25 @@ impl Default for ExecutionState {
             evaluating_enclosed_state: Default::default(),
             evaluated_mutation_of_cell: None,
             graph_sender: None,
+            input_resolution_hook: None,
+            prompt_audit: None,
+            llm_cache: None,
+            native_functions: Default::default(),
+            memory_stores: Default::default(),
+            memory_writes: Default::default(),
+            deno_modules: Default::default(),
+            webhook_servers: Default::default(),
+            cancellation: Default::default(),
+            llm_proxies: Default::default(),
+            cell_descriptions: Default::default(),
+            agent_trace_sink: None,
+            completion_sink: None,
+            agent_trace: None,
+            branch_params: RkyvSerializedValue::Object(HashMap::new()),
             exec_queue: VecDeque::new(),
             state: Default::default(),
+            state_transient: Default::default(),
+            code_gen_retries: Default::default(),
             fresh_values: Default::default(),
             operation_name_to_id: Default::default(),
             operation_by_id: Default::default(),
@@ -312,21 +559,68 @@ impl ExecutionState {
         self.state.get(operation_id).map(|x| x.as_ref()).map(|o| &o.output)
     }
 
+    /// What the model provider reported about the response that produced the current value of an
+    /// operation, for prompt cells
+    pub fn response_metadata(&self, operation_id: &OperationId) -> Option<&ModelResponseMetadata> {
+        self.state_get(operation_id).and_then(|output| output.response_metadata.as_ref())
+    }
+
+    /// The operations whose values differ from those in `prev`, because they were produced, removed
+    /// or changed since, in order of their ids. Values shared with `prev` are not compared, making
+    /// this cheaper than diffing the states when only which values changed is needed.
+    pub fn get_changed_ops_since(&self, prev: &ExecutionState) -> Vec<OperationId> {
+        let mut changed: Vec<OperationId> = self.state.iter()
+            .filter(|(op_id, output)| match prev.state.get(op_id) {
+                None => true,
+                Some(prev_output) => !Arc::ptr_eq(prev_output, output) && match (&prev_output.output, &output.output) {
+                    (Ok(prev_value), Ok(value)) => !diff_values(prev_value, value).is_empty(),
+                    (prev_output, output) => prev_output != output,
+                },
+            })
+            .map(|(op_id, _)| *op_id)
+            .chain(prev.state.keys().filter(|op_id| !self.state.contains_key(op_id)).copied())
+            .collect();
+        changed.sort();
+        changed
+    }
+
     #[tracing::instrument]
     pub fn state_insert(&mut self, operation_id: OperationId, value: OperationFnOutput) {
         self.state.insert(operation_id, Arc::new(value));
         self.has_been_set.insert(operation_id);
     }
 
+    /// Sets a value for this operation that is visible in the current state but is not retained
+    /// once execution advances to the next step.
+    #[tracing::instrument]
+    pub fn state_set_transient(&mut self, operation_id: OperationId, value: RkyvSerializedValue) {
+        self.state_transient.insert(operation_id, value);
+    }
+
+    pub fn state_get_transient(&self, operation_id: &OperationId) -> Option<&RkyvSerializedValue> {
+        self.state_transient.get(operation_id)
+    }
+
     #[cfg(test)]
     pub fn render_dependency_graph(&self) {
         println!("================ Dependency graph ================");
-        println!(
+        println!("{}", self.dependency_graph_dot(&HashMap::new()));
+    }
+
+    /// The dependency graph in the DOT format. Edges are labelled with the annotations given for
+    /// them, or with the values they carry when they have none.
+    pub fn dependency_graph_dot(&self, edge_annotations: &EdgeAnnotations) -> String {
+        format!(
             "{:?}",
             Dot::with_attr_getters(
                 &self.get_dependency_graph(),
-                &[],
-                &|_, e| String::new(), // Edge attributes, assuming you don't need to modify this
+                &[Config::EdgeNoLabel],
+                &|_, e| {
+                    match edge_annotations.get(&(e.0, e.1)) {
+                        Some(labels) if !labels.is_empty() => format!("label={:?}", labels.join(", ")),
+                        _ => format!("label={:?}", format!("{:?}", e.2)),
+                    }
+                },
                 &|_, n| {
                     // Node attributes
                     if let Some(op) = self.operation_by_id.get(n.1) {
@@ -339,7 +633,7 @@ impl ExecutionState {
                     }
                 }
             )
-        );
+        )
     }
 
     #[tracing::instrument]
@@ -366,12 +660,71 @@ impl ExecutionState {
         graph
     }
 
+    /// The given operations along with every operation they transitively depend on.
+    pub fn get_execution_subgraph(&self, roots: &[OperationId]) -> HashSet<OperationId> {
+        let dependency_graph = self.get_dependency_graph();
+        let mut subgraph = HashSet::new();
+        let mut queue: Vec<OperationId> = roots.to_vec();
+        while let Some(op_id) = queue.pop() {
+            if !subgraph.insert(op_id) {
+                continue;
+            }
+            if dependency_graph.contains_node(op_id) {
+                queue.extend(dependency_graph.neighbors_directed(op_id, Direction::Incoming));
+            }
+        }
+        subgraph
+    }
+
+    /// Every operation that transitively depends on the given one, which are the operations that
+    /// recompute when it changes. The operation itself is only included when part of a cycle.
+    pub fn dependents_of(&self, op_id: OperationId) -> HashSet<OperationId> {
+        self.transitive_neighbors(op_id, Direction::Outgoing)
+    }
+
+    /// Every operation the given one transitively depends on
+    pub fn dependencies_of(&self, op_id: OperationId) -> HashSet<OperationId> {
+        self.transitive_neighbors(op_id, Direction::Incoming)
+    }
+
+    fn transitive_neighbors(&self, op_id: OperationId, direction: Direction) -> HashSet<OperationId> {
+        let dependency_graph = self.get_dependency_graph();
+        let mut reached = HashSet::new();
+        if !dependency_graph.contains_node(op_id) {
+            return reached;
+        }
+        let mut queue: Vec<OperationId> = dependency_graph.neighbors_directed(op_id, direction).collect();
+        while let Some(next) = queue.pop() {
+            if reached.insert(next) {
+                queue.extend(dependency_graph.neighbors_directed(next, direction));
+            }
+        }
+        reached
+    }
+
+    /// Cell definitions of every operation as of this state, ordered by operation id
+    /// (operation ids are time ordered, so this is the order operations were introduced in).
+    pub fn get_cells_in_operation_order(&self) -> Vec<(OperationId, CellTypes)> {
+        let mut cells: Vec<(OperationId, CellTypes)> = self.cells_by_id
+            .iter()
+            .map(|(id, cell)| (*id, cell.clone()))
+            .collect();
+        cells.sort_by(|a, b| a.0.cmp(&b.0));
+        cells
+    }
+
     pub fn get_operation_from_cell_type(&self, cell: &CellTypes) -> anyhow::Result<OperationNode> {
         let op = match cell {
             CellTypes::Code(c, r) => crate::cells::code_cell::code_cell(self.chronology_id.clone(), c, r),
             CellTypes::Prompt(c, r) => crate::cells::llm_prompt_cell::llm_prompt_cell(self.chronology_id.clone(), c, r),
             CellTypes::Template(c, r) => crate::cells::template_cell::template_cell(self.chronology_id.clone(), c, r),
             CellTypes::CodeGen(c, r) => crate::cells::code_gen_cell::code_gen_cell(self.chronology_id.clone(), c, r),
+            CellTypes::Webhook(c, r) => crate::cells::webhook_cell::webhook_cell(self.chronology_id.clone(), c, r),
+            CellTypes::Mcp(c, r) => crate::cells::mcp_cell::mcp_cell(self.chronology_id.clone(), c, r),
+            CellTypes::File(c, r) => crate::cells::file_cell::file_cell(self.chronology_id.clone(), c, r),
+            CellTypes::Extract(c, r) => crate::cells::extract_cell::extract_cell(self.chronology_id.clone(), c, r),
+            CellTypes::Memory(c, r) => crate::cells::memory_cell::memory_cell(self.chronology_id.clone(), c, r),
+            CellTypes::Chunk(c, r) => crate::cells::chunk_cell::chunk_cell(self.chronology_id.clone(), c, r),
         }?;
         Ok(op)
     }
@@ -388,6 +741,189 @@ impl ExecutionState {
         Ok((final_state, op_id))
     }
 
+    /// Apply the definitions of several cells as a single revision of the state, see `apply_cell_mutations`.
+    pub async fn update_operations(
+        &self,
+        cells: Vec<(CellTypes, OperationId)>,
+    ) -> anyhow::Result<ExecutionState> {
+        let final_state = self.apply_cell_mutations(cells)?;
+        self.send_new_state_to_graph_and_pause_with_oneshot(&mut final_state.clone()).await;
+        Ok(final_state)
+    }
+
+    /// Upsert the given cells together, producing one new revision of the state. The combined result
+    /// is validated first, if any cell is invalid or the edit would break dependencies that held before
+    /// it, nothing is applied and the problems are reported instead.
+    pub fn apply_cell_mutations(
+        &self,
+        cells: Vec<(CellTypes, OperationId)>,
+    ) -> Result<ExecutionState, DefinitionValidationReport> {
+        let mut issues = vec![];
+        let mut mutated = vec![];
+        let mut s = self.create_new_revision_of_execution_state();
+        s.evaluating_enclosed_state = EnclosedState::SelfContained;
+        for (cell, op_id) in cells {
+            let mut operation_node = match self.get_operation_from_cell_type(&cell) {
+                Ok(operation_node) => operation_node,
+                Err(e) => {
+                    issues.push(DefinitionIssue::InvalidCell { cell: cell_label(&cell, op_id), error: e.to_string() });
+                    continue;
+                }
+            };
+            if let Some(name) = &operation_node.name {
+                if !s.operation_name_to_id.contains_key(name) {
+                    s.operation_name_to_id.insert(name.clone(), op_id);
+                }
+            }
+            operation_node.id = op_id;
+            // A redefined cell is evaluated again, even when it has no inputs
+            s.has_been_set.remove(&op_id);
+            s.cells_by_id.insert(op_id, cell.clone());
+            s.evaluated_mutation_of_cell = Some((op_id, cell));
+            s.operation_by_id.insert(op_id, operation_node);
+            s.exec_queue.push_back(op_id);
+            mutated.push(op_id);
+        }
+        s.update_callable_functions();
+        match Self::assign_dependencies_to_operations(&s) {
+            Ok(mutations) => s = s.apply_dependency_graph_mutations(mutations),
+            Err(e) => issues.push(DefinitionIssue::NamingCollision(e.to_string())),
+        }
+
+        if issues.is_empty() {
+            issues.extend(s.newly_unresolved_symbols(self));
+            issues.extend(s.newly_introduced_cycles(self));
+            issues.extend(s.invalid_routes(&mutated));
+        }
+        if issues.is_empty() {
+            Ok(s)
+        } else {
+            Err(DefinitionValidationReport { issues })
+        }
+    }
+
+    fn available_symbols(&self) -> HashSet<String> {
+        self.operation_by_id.values()
+            .flat_map(|op| {
+                let output_signature = &op.signature.output_signature;
+                output_signature.globals.keys().chain(output_signature.functions.keys()).cloned()
+            })
+            .collect()
+    }
+
+    fn newly_unresolved_symbols(&self, previous: &ExecutionState) -> Vec<DefinitionIssue> {
+        let previously_available = previous.available_symbols();
+        let mut available = OutputSignature::new();
+        for op in self.operation_by_id.values() {
+            available.globals.extend(op.signature.output_signature.globals.clone());
+            available.functions.extend(op.signature.output_signature.functions.clone());
+        }
+        let mut issues = vec![];
+        for (op_id, op) in self.operation_by_id.iter() {
+            for symbol in op.signature.input_signature.missing_inputs(&available) {
+                if previously_available.contains(&symbol) {
+                    issues.push(DefinitionIssue::UnresolvedSymbol { cell: cell_label(&op.cell, *op_id), symbol });
+                }
+            }
+        }
+        issues
+    }
+
+    fn invalid_routes(&self, op_ids: &[OperationId]) -> Vec<DefinitionIssue> {
+        op_ids.iter()
+            .filter_map(|op_id| match &self.operation_by_id.get(op_id)?.cell {
+                cell @ CellTypes::Webhook(webhook, _) => Some((cell_label(cell, *op_id), webhook.route_table.validate(self))),
+                _ => None,
+            })
+            .flat_map(|(cell, diagnostics)| diagnostics.into_iter()
+                .map(move |diagnostic| DefinitionIssue::InvalidRoute { cell: cell.clone(), diagnostic }))
+            .collect()
+    }
+
+    fn newly_introduced_cycles(&self, previous: &ExecutionState) -> Vec<DefinitionIssue> {
+        let cycles = |state: &ExecutionState| -> HashSet<Vec<OperationId>> {
+            petgraph::algo::tarjan_scc(&state.get_dependency_graph())
+                .into_iter()
+                .filter(|component| component.len() > 1)
+                .map(|mut component| {
+                    component.sort();
+                    component
+                })
+                .collect()
+        };
+        let previous_cycles = cycles(previous);
+        cycles(self)
+            .into_iter()
+            .filter(|component| !previous_cycles.contains(component))
+            .map(|component| DefinitionIssue::DependencyCycle {
+                cells: component.iter()
+                    .map(|op_id| match self.cells_by_id.get(op_id) {
+                        Some(cell) => cell_label(cell, *op_id),
+                        None => op_id.to_string(),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Apply the cells produced by a code generation operation. Cells from a previous attempt are
+    /// replaced in place, reusing their operation ids, and the attempt is recorded.
+    pub async fn apply_generated_cells(
+        &self,
+        generating_operation_id: OperationId,
+        cells: Vec<CellTypes>,
+        max_attempts: u32,
+    ) -> anyhow::Result<ExecutionState> {
+        let previous = self.code_gen_retries.get(&generating_operation_id).cloned().unwrap_or_default();
+        let mut new_state = self.clone();
+        let mut generated_operations = vec![];
+        for (idx, cell) in cells.into_iter().enumerate() {
+            let op_id = previous.generated_operations.get(idx).copied().unwrap_or_else(Uuid::now_v7);
+            let (s, op_id) = new_state.update_operation(cell, op_id).await?;
+            new_state = s;
+            generated_operations.push(op_id);
+        }
+        new_state.code_gen_retries.insert(generating_operation_id, CodeGenRetryState {
+            generated_operations,
+            attempts: previous.attempts + 1,
+            max_attempts,
+            last_error: None,
+        });
+        Ok(new_state)
+    }
+
+    /// Bring the operations generated while evaluating this state's operation into this state
+    fn adopt_generated_operations(&mut self, generated_state: &ExecutionState) -> anyhow::Result<()> {
+        let Some(retry) = generated_state.code_gen_retries.get(&self.evaluating_operation_id) else {
+            return Ok(());
+        };
+        for op_id in &retry.generated_operations {
+            if let Some(op) = generated_state.operation_by_id.get(op_id) {
+                if let Some(name) = &op.name {
+                    self.operation_name_to_id.insert(name.clone(), *op_id);
+                }
+                self.cells_by_id.insert(*op_id, op.cell.clone());
+                self.operation_by_id.insert(*op_id, op.clone());
+                self.has_been_set.remove(op_id);
+                self.exec_queue.push_back(*op_id);
+            }
+        }
+        self.code_gen_retries.insert(self.evaluating_operation_id, retry.clone());
+        self.update_callable_functions();
+        let mutations = Self::assign_dependencies_to_operations(self)?;
+        *self = self.apply_dependency_graph_mutations(mutations);
+        Ok(())
+    }
+
+    /// The code generation operation that should regenerate if the given operation fails
+    fn code_gen_retry_for_failure(&self, failed_operation_id: OperationId) -> Option<OperationId> {
+        self.code_gen_retries
+            .iter()
+            .find(|(_, retry)| retry.generated_operations.contains(&failed_operation_id))
+            .filter(|(_, retry)| retry.attempts < retry.max_attempts)
+            .map(|(id, _)| *id)
+    }
+
     #[tracing::instrument]
     fn assign_dependencies_to_operations(new_state: &ExecutionState) -> anyhow::Result<Vec<DependencyGraphMutation>> {
         let (available_values, available_functions) = Self::extract_available_values_and_functions(new_state)?;
@@ -424,6 +960,20 @@ impl ExecutionState {
                 }
                 // unsatisfied_dependencies.push(value_name.clone())
             }
+            for cell_name in input_signature.imported_cells.iter() {
+                if let Some(source_cell_id) = new_state.operation_name_to_id.get(cell_name) {
+                    if source_cell_id != destination_cell_id {
+                        accum.push((*source_cell_id, DependencyReference::Ordering));
+                    }
+                }
+            }
+            for store in input_signature.memory_reads.iter() {
+                for (source_cell_id, source) in new_state.operation_by_id.iter() {
+                    if source_cell_id != destination_cell_id && source.signature.output_signature.memory_writes.contains(store) {
+                        accum.push((*source_cell_id, DependencyReference::Ordering));
+                    }
+                }
+            }
             if accum.len() > 0 {
                 mutations.push(DependencyGraphMutation::Create {
                     operation_id: destination_cell_id.clone(),
@@ -476,6 +1026,8 @@ impl ExecutionState {
                 op_id
             });
         operation_node.id = op_id;
+        // A redefined cell is evaluated again, even when it has no inputs
+        s.has_been_set.remove(&op_id);
         s.cells_by_id.insert(op_id, operation_node.cell.clone());
         s.evaluated_mutation_of_cell = Some((op_id, operation_node.cell.clone()));
         s.operation_by_id.insert(op_id, operation_node);
@@ -495,6 +1047,7 @@ impl ExecutionState {
     ) -> Self {
         let mut s = self.clone();
         for mutation in mutations {
+            info!(target: "chidori_core::audit", "{} at state {}", mutation.describe(), s.chronology_id);
             match mutation {
                 DependencyGraphMutation::Create {
                     operation_id,
@@ -544,19 +1097,30 @@ impl ExecutionState {
     /// Invoke a function made available by the execution state, this accepts arguments derived in the context
     /// of a parent function's scope. This targets a specific function by name that we've identified a dependence on.
     // TODO: this should create a coroutine that yields with the result of the function invocation
-    #[tracing::instrument(parent = parent_span_id.clone(), skip(self, payload))]
+    ///
+    /// Invocations may nest, a function invoking a function in another cell. No locks are held while
+    /// awaiting the invoked function, each level works from its own snapshot of the state.
+    #[tracing::instrument(parent = parent_span_id.clone(), skip(self, payload), fields(invocation_chain = tracing::field::Empty))]
     pub async fn dispatch(&self, function_name: &str, payload: RkyvSerializedValue, parent_span_id: Option<tracing::Id>) -> anyhow::Result<(Result<RkyvSerializedValue, ExecutionStateErrors>, ExecutionState)> {
         debug!("Running dispatch {:?}", function_name);
 
+        let mut invocation_chain = self.invocation_chain.clone();
+        invocation_chain.push(function_name.to_string());
+        tracing::Span::current().record("invocation_chain", &tracing::field::display(invocation_chain.join(" -> ")));
+        if invocation_chain.len() > self.max_invocation_depth {
+            return Ok((Err(ExecutionStateErrors::InvocationDepthExceeded(self.max_invocation_depth, invocation_chain.join(" -> "))), self.clone()));
+        }
+
         // Store the invocation payload into an execution state and record this before executing
         let mut before_execution_state = self.create_new_revision_of_execution_state();
         before_execution_state.stack.push_back(self.resolving_execution_node_state_id);
+        before_execution_state.invocation_chain = invocation_chain;
 
-        let meta = self.function_name_to_metadata.get(function_name).map(|meta| {
-            meta
-        }).expect("Failed to find named function");
+        let meta = self.function_name_to_metadata.get(function_name)
+            .ok_or_else(|| anyhow::anyhow!("Failed to find named function {}", function_name))?;
 
-        let cell = before_execution_state.cells_by_id.get(&meta.operation_id).unwrap();
+        let cell = before_execution_state.cells_by_id.get(&meta.operation_id)
+            .ok_or_else(|| anyhow::anyhow!("Function {} refers to a cell that does not exist", function_name))?;
         // modify code cell to indicate execution of the target function
         // reconstruction of the cell
         let op = Self::cell_to_function_invocation(cell, function_name.to_string())?;
@@ -569,15 +1133,19 @@ impl ExecutionState {
 
         // invocation of the operation
         // TODO: the total arg payload here does not include necessary function calls for this cell itself
+        let payload = self.apply_input_resolution_hook(meta.operation_id, payload);
         /// Receiver that we pass to the exec for it to capture oneshot RPC communication
         let result = op.execute(&before_execution_state, payload, None, None).await?;
+        self.report_completion(meta.operation_id, &result);
 
         // State that indicates in resolution of execution of this dispatched function
         // Add result into a new execution state
         let mut after_execution_state = before_execution_state
             .close_and_set_chronological_parent(&result.execution_state.as_ref().unwrap_or(&before_execution_state));
+        after_execution_state.agent_trace = None;
 
         after_execution_state.stack.pop_back();
+        after_execution_state.invocation_chain.pop();
         after_execution_state.state_insert(Uuid::max(), result.clone());
         after_execution_state.fresh_values.insert(Uuid::max());
         self.send_new_state_to_graph_and_pause_with_oneshot(&mut after_execution_state).await;
@@ -585,6 +1153,73 @@ impl ExecutionState {
         Ok((result.output, after_execution_state))
     }
 
+    fn report_completion(&self, operation_id: OperationId, result: &OperationFnOutput) {
+        if let Some(sink) = &self.completion_sink {
+            sink(operation_id, result);
+        }
+    }
+
+    /// The value of a parameter of the branch this state is on, if it has been set
+    pub fn branch_param(&self, name: &str) -> Option<&RkyvSerializedValue> {
+        match &self.branch_params {
+            RkyvSerializedValue::Object(params) => params.get(name),
+            _ => None,
+        }
+    }
+
+    /// Invoke a native function registered by the embedder
+    pub fn call_native_function(&self, name: &str, args: RkyvSerializedValue) -> anyhow::Result<RkyvSerializedValue> {
+        let f = self.native_functions.get(name)
+            .ok_or_else(|| anyhow::anyhow!("No native function named {} has been registered", name))?;
+        Ok(f(args))
+    }
+
+    /// The native functions available to cells evaluated from this state, as an output signature
+    pub fn native_function_signature(&self) -> OutputSignature {
+        let mut signature = OutputSignature::new();
+        for name in self.native_functions.keys() {
+            signature.functions.insert(name.clone(), OutputItemConfiguration::NativeFunction);
+        }
+        signature
+    }
+
+    fn memory_cell(&self, name: &str) -> anyhow::Result<MemoryCell> {
+        self.cells_by_id.values()
+            .find_map(|cell| match cell {
+                CellTypes::Memory(cell, _) if cell.name.as_deref() == Some(name) => Some(cell.clone()),
+                _ => None,
+            })
+            .ok_or_else(|| anyhow::anyhow!("No memory cell named {}", name))
+    }
+
+    /// Memories are embedded by the OpenAI embedding models, and so through the proxy of that provider
+    fn embedding_proxy(&self) -> Option<ProxyConfig> {
+        self.llm_proxies.get(&SupportedModelProviders::OpenAI).cloned()
+    }
+
+    /// The evaluation memories inserted from this state belong to. Functions dispatched from a cell
+    /// insert on behalf of the evaluation of that cell.
+    fn memory_evaluation(&self) -> ChronologyId {
+        self.stack.front().copied().unwrap_or(self.resolving_execution_node_state_id)
+    }
+
+    /// Embed `text` into the store of the memory cell with the given name, returning its id
+    pub async fn insert_memory(&self, name: &str, text: String, metadata: serde_json::Map<String, serde_json::Value>) -> anyhow::Result<u64> {
+        let cell = self.memory_cell(name)?;
+        self.memory_stores.insert(&cell, text, metadata, self.memory_evaluation(), self.embedding_proxy()).await
+    }
+
+    /// The `top_k` memories of the memory cell with the given name most similar to the query, see `MemoryStores::query`.
+    /// Only the memories inserted by the evaluations recorded in `memory_writes`, or by the evaluation
+    /// in progress, are visible.
+    pub async fn query_memory(&self, name: &str, query: MemoryQuery, top_k: usize, filter: &serde_json::Map<String, serde_json::Value>) -> anyhow::Result<Vec<MemoryMatch>> {
+        let cell = self.memory_cell(name)?;
+        let evaluations: HashSet<ChronologyId> = self.memory_writes.values().copied()
+            .chain(std::iter::once(self.memory_evaluation()))
+            .collect();
+        self.memory_stores.query(&cell, query, top_k, filter, &evaluations, self.embedding_proxy()).await
+    }
+
     fn cell_to_function_invocation(cell: &CellTypes, clone_function_name: String) -> Result<OperationNode, Error> {
         let mut op = match cell {
             CellTypes::Code(c, r) => {
@@ -604,13 +1239,98 @@ impl ExecutionState {
                     }
                 }
             }
+            CellTypes::Mcp(c, r) => {
+                let mut c = c.clone();
+                c.function_invocation = Some(clone_function_name.to_string());
+                crate::cells::mcp_cell::mcp_cell(Uuid::nil(), &c, &r)?
+            }
+            CellTypes::Extract(c, r) => {
+                let mut c = c.clone();
+                c.function_invocation = Some(clone_function_name.to_string());
+                crate::cells::extract_cell::extract_cell(Uuid::nil(), &c, &r)?
+            }
+            CellTypes::Chunk(c, r) => {
+                let mut c = c.clone();
+                c.function_invocation = Some(clone_function_name.to_string());
+                crate::cells::chunk_cell::chunk_cell(Uuid::nil(), &c, &r)?
+            }
             _ => {
-                unreachable!("Unsupported cell type");
+                return Err(anyhow::anyhow!("Functions can only be invoked on code, prompt, mcp, extract and chunk cells"));
             }
         };
         Ok(op)
     }
 
+    /// Record a reasoning step of the operation being evaluated from this state, if any
+    pub fn record_agent_trace_step(&self, kind: AgentTraceStepKind, content: impl Into<String>) {
+        if let Some(agent_trace) = &self.agent_trace {
+            agent_trace.record(kind, content);
+        }
+    }
+
+    /// Records a payload received by a webhook cell's listener as the output of that cell, on a new
+    /// branch of the execution graph from this state. Downstream cells see the payload as fresh.
+    pub async fn receive_webhook_payload(&self, operation_id: OperationId, payload: RkyvSerializedValue) -> ExecutionState {
+        let mut new_state = self.create_new_revision_of_execution_state();
+        new_state.evaluating_enclosed_state = EnclosedState::SelfContained;
+        new_state.evaluating_operation_id = operation_id;
+        new_state.evaluating_name = self.cells_by_id.get(&operation_id).and_then(|cell| cell.name().clone());
+        new_state.evaluating_cell = self.cells_by_id.get(&operation_id).cloned();
+        new_state.fresh_values.insert(operation_id);
+        new_state.state_insert(operation_id, OperationFnOutput::with_value(payload));
+        new_state.value_freshness_map.insert(operation_id, new_state.exec_counter);
+        self.send_new_state_to_graph_and_pause_with_oneshot(&mut new_state).await;
+        new_state
+    }
+
+    /// Whether this state was produced by a webhook cell receiving a payload
+    pub fn is_webhook_delivery(&self) -> bool {
+        self.evaluating_enclosed_state == EnclosedState::SelfContained
+            && self.evaluating_fn.is_none()
+            && matches!(self.evaluating_cell, Some(CellTypes::Webhook(..)))
+    }
+
+    /// Answer a request to a webhook cell by invoking its handler on a branch of this state, so that
+    /// concurrent requests do not observe one another. The evaluation of the handler is discarded,
+    /// when `record` is set the request and its response are kept as a child of this state. Recorded
+    /// requests do not move the execution head.
+    pub async fn respond_to_request(&self, operation_id: OperationId, handler: &str, payload: RkyvSerializedValue, record: bool) -> anyhow::Result<Result<RkyvSerializedValue, ExecutionStateErrors>> {
+        let mut branch = self.clone();
+        branch.graph_sender = None;
+        let (response, _) = branch.dispatch(handler, payload.clone(), None).await?;
+
+        if record {
+            let mut request_state = self.create_new_revision_of_execution_state();
+            request_state.evaluating_enclosed_state = EnclosedState::SelfContained;
+            request_state.evaluating_operation_id = operation_id;
+            request_state.evaluating_name = self.cells_by_id.get(&operation_id).and_then(|cell| cell.name().clone());
+            request_state.evaluating_cell = self.cells_by_id.get(&operation_id).cloned();
+            // Marks the state as a function evaluation, which is never made the execution head
+            request_state.evaluating_fn = Some(handler.to_string());
+            request_state.evaluating_arguments = Some(payload);
+            request_state.state_insert(operation_id, OperationFnOutput {
+                has_error: response.is_err(),
+                execution_state: None,
+                output: response.clone(),
+                stdout: vec![],
+                stderr: vec![],
+                peak_memory_bytes: None,
+                agent_trace: vec![],
+                response_metadata: None,
+                input: None,
+            });
+            self.send_new_state_to_graph_and_pause_with_oneshot(&mut request_state).await;
+        }
+        Ok(response)
+    }
+
+    /// Whether this state records a request answered by the handler of a webhook cell
+    pub fn is_recorded_request(&self) -> bool {
+        self.evaluating_enclosed_state == EnclosedState::SelfContained
+            && self.evaluating_fn.is_some()
+            && matches!(self.evaluating_cell, Some(CellTypes::Webhook(..)))
+    }
+
     async fn send_new_state_to_graph_and_pause_with_oneshot(&self, mut execution_state: &mut ExecutionState) {
         if let Some(graph_sender) = self.graph_sender.as_ref() {
             let (oneshot_sender, mut oneshot_receiver) = tokio::sync::oneshot::channel();
@@ -621,6 +1341,13 @@ impl ExecutionState {
         }
     }
 
+    fn apply_input_resolution_hook(&self, operation_id: OperationId, payload: RkyvSerializedValue) -> RkyvSerializedValue {
+        match &self.input_resolution_hook {
+            Some(hook) => hook(operation_id, payload),
+            None => payload,
+        }
+    }
+
     fn get_operation_node(&self, operation_id: OperationId) -> anyhow::Result<&OperationNode> {
         let op = self.operation_by_id
             .get(&operation_id)
@@ -704,34 +1431,188 @@ impl ExecutionState {
                 }
             };
 
-            // Get operation node and check validity
-            let op_node = self.get_operation_node(next_operation_id)?;
-            let signature = &op_node.signature.input_signature;
-
-            // Skip if already run with no dependencies
-            if signature.is_empty() && self.has_been_set.contains(&next_operation_id) {
+            let Some(inputs) = self.ready_operation_inputs(next_operation_id)? else {
                 continue;
+            };
+            return self.stage_operation(next_operation_id, inputs, exec_queue);
+        }
+    }
+
+    /// A new revision of this state that is about to evaluate the given operation
+    fn stage_operation(&self, operation_id: OperationId, inputs: OperationInputs, exec_queue: VecDeque<OperationId>) -> anyhow::Result<ExecutionState> {
+        let op_node = self.get_operation_node(operation_id)?;
+        let mut new_state = self.create_new_revision_of_execution_state();
+        new_state.evaluating_operation_id = operation_id;
+        new_state.evaluating_name = op_node.name.clone();
+        new_state.evaluating_arguments = Some(inputs.to_serialized_value());
+        new_state.exec_queue = exec_queue;
+        Ok(new_state)
+    }
+
+    /// The inputs the operation would be evaluated with from this state, or None if it is not ready
+    fn ready_operation_inputs(&self, operation_id: OperationId) -> anyhow::Result<Option<OperationInputs>> {
+        let op_node = self.get_operation_node(operation_id)?;
+        let signature = &op_node.signature.input_signature;
+
+        // Code generation awaiting a retry, and cells pinned to every step, run regardless of the
+        // freshness of their inputs
+        let retry_pending = self.code_gen_retries
+            .get(&operation_id)
+            .map_or(false, |retry| retry.last_error.is_some());
+        let runs_regardless = retry_pending || op_node.cell.always_run();
+
+        // Skip if already run with no dependencies
+        if !runs_regardless && signature.is_empty() && self.has_been_set.contains(&operation_id) {
+            return Ok(None);
+        }
+
+        // Skip if no new inputs available
+        if !runs_regardless && !signature.is_empty() && !self.has_fresher_inputs(operation_id)? {
+            return Ok(None);
+        }
+
+        // Prepare and validate inputs
+        let inputs = self.prepare_operation_inputs(signature, operation_id, self.get_dependency_graph())?;
+        if !signature.check_input_against_signature(&inputs) {
+            return Ok(None);
+        }
+        Ok(Some(inputs))
+    }
+
+    /// Every operation that is ready to be evaluated from this state, in the order they are considered
+    pub fn ready_operations(&self) -> anyhow::Result<Vec<OperationId>> {
+        let mut operation_ids: Vec<OperationId> = self.cells_by_id.keys().copied().collect();
+        operation_ids.sort();
+        let mut ready = vec![];
+        for operation_id in operation_ids {
+            if self.ready_operation_inputs(operation_id)?.is_some() {
+                ready.push(operation_id);
             }
+        }
+        Ok(ready)
+    }
 
-            // Skip if no new inputs available
-            if !signature.is_empty() && !self.has_fresher_inputs(next_operation_id)? {
-                continue;
+    /// Partitions the operations ready at this state into groups that may be evaluated concurrently.
+    /// Operations writing the same output name are placed in different groups, each operation joins
+    /// the first group it does not conflict with.
+    pub fn concurrent_execution_groups(&self) -> anyhow::Result<Vec<Vec<OperationId>>> {
+        let mut groups: Vec<(Vec<OperationId>, HashSet<String>)> = vec![];
+        for operation_id in self.ready_operations()? {
+            let output_signature = &self.get_operation_node(operation_id)?.signature.output_signature;
+            let outputs: HashSet<String> = output_signature.globals.keys()
+                .chain(output_signature.functions.keys())
+                .cloned()
+                .collect();
+            match groups.iter_mut().find(|(_, written)| written.is_disjoint(&outputs)) {
+                Some((members, written)) => {
+                    members.push(operation_id);
+                    written.extend(outputs);
+                }
+                None => groups.push((vec![operation_id], outputs)),
             }
+        }
+        Ok(groups.into_iter().map(|(members, _)| members).collect())
+    }
 
-            // Prepare and validate inputs
-            let inputs = self.prepare_operation_inputs(signature, next_operation_id, self.get_dependency_graph())?;
-            if !signature.check_input_against_signature(&inputs) {
-                continue;
+    /// Settings of the cell take precedence over the program defaults, which take precedence
+    /// over no timeout and no retries.
+    pub fn execution_policy_for(&self, cell: &CellTypes) -> ExecutionPolicy {
+        cell.execution_policy().or(&self.default_execution_policy)
+    }
+
+    /// Evaluate an operation, bounded by its timeout and attempted again on failure up to its
+    /// number of retries. The result of the final attempt is returned.
+    async fn execute_with_policy(
+        &self,
+        op_node: &OperationNode,
+        state: &ExecutionState,
+        args: RkyvSerializedValue,
+    ) -> anyhow::Result<OperationFnOutput> {
+        let policy = self.execution_policy_for(&op_node.cell);
+        let attempts = policy.retries.unwrap_or(0) + 1;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let execution = op_node.execute(state, args.clone(), None, None);
+            let result = match policy.timeout_ms {
+                Some(timeout_ms) => match tokio::time::timeout(std::time::Duration::from_millis(timeout_ms), execution).await {
+                    Ok(result) => result,
+                    Err(_) => Ok(OperationFnOutput {
+                        has_error: true,
+                        execution_state: None,
+                        output: Err(ExecutionStateErrors::CellExecutionTimeout(timeout_ms)),
+                        stdout: vec![],
+                        stderr: vec![],
+                        peak_memory_bytes: None,
+                        agent_trace: vec![],
+                        response_metadata: None,
+                        input: None,
+                    }),
+                },
+                None => execution.await,
+            };
+            let failed = match &result {
+                Ok(output) => output.has_error || output.output.is_err(),
+                Err(_) => true,
+            };
+            if !failed || attempt >= attempts {
+                return result;
             }
+            debug!("Attempt {} of {} failed for operation {:?}, retrying", attempt, attempts, op_node.name);
+        }
+    }
 
-            // Create and stage new execution state
-            let mut new_state = self.create_new_revision_of_execution_state();
-            new_state.evaluating_operation_id = next_operation_id;
-            new_state.evaluating_name = op_node.name.clone();
-            new_state.evaluating_arguments = Some(inputs.to_serialized_value());
-            new_state.exec_queue = exec_queue;
-            return Ok(new_state);
+    /// Outputs larger than the limit of their cell once serialized either fail the evaluation or
+    /// are spilled to disk, so that they are never retained in state or sent to the graph.
+    fn enforce_output_limit(&self, operation_id: OperationId, cell: &CellTypes, mut result: OperationFnOutput) -> OperationFnOutput {
+        let Ok(value) = &result.output else { return result };
+        let policy = self.execution_policy_for(cell);
+        let limit = policy.max_output_bytes.unwrap_or(DEFAULT_MAX_OUTPUT_BYTES);
+        let serialized = serialize_to_vec(value);
+        let size = serialized.len() as u64;
+        if size <= limit {
+            return result;
         }
+        debug!("Output of operation {:?} is {} bytes, exceeding its limit of {}", operation_id, size, limit);
+        result.output = match policy.overflow.unwrap_or_default() {
+            OutputOverflow::Fail => Err(ExecutionStateErrors::OutputTooLarge { size, limit }),
+            OutputOverflow::Spill => spill_output(&spill_directory(), operation_id, value, Some(&serialized))
+                .map_err(ExecutionStateErrors::from),
+        };
+        result.has_error = result.output.is_err();
+        result
+    }
+
+    /// The operation each argument of an operation is taken from, laid out as `RecordedInput::producers`
+    fn input_producers(&self, operation_id: OperationId) -> HashMap<String, HashMap<String, OperationId>> {
+        let mut producers: HashMap<String, HashMap<String, OperationId>> = HashMap::new();
+        for (from, _, argument_indices) in self.get_dependency_graph().edges_directed(operation_id, Direction::Incoming) {
+            for argument_index in argument_indices {
+                let (section, name) = match argument_index {
+                    DependencyReference::Positional(pos) => ("args", pos.to_string()),
+                    DependencyReference::Keyword(kw) => ("kwargs", kw.clone()),
+                    DependencyReference::Global(name) => ("globals", name.clone()),
+                    DependencyReference::FunctionInvocation(_) | DependencyReference::Ordering => continue,
+                };
+                producers.entry(section.to_string()).or_default().insert(name, from);
+            }
+        }
+        producers
+    }
+
+    /// The arguments an operation is about to be evaluated with, as recorded alongside its output.
+    /// Arguments larger than the output limit of the cell are spilled to disk rather than retained.
+    fn record_input(&self, operation_id: OperationId, cell: &CellTypes, args: &RkyvSerializedValue) -> Option<RecordedInput> {
+        let limit = self.execution_policy_for(cell).max_output_bytes.unwrap_or(DEFAULT_MAX_OUTPUT_BYTES);
+        let serialized = serialize_to_vec(args);
+        let value = if serialized.len() as u64 <= limit {
+            args.clone()
+        } else {
+            spill_output(&spill_directory(), operation_id, args, Some(&serialized))
+                .map_err(|e| debug!("Failed to spill the input of operation {:?}: {:?}", operation_id, e))
+                .ok()?
+        };
+        Some(RecordedInput { value, producers: self.input_producers(operation_id) })
     }
 
     #[tracing::instrument]
@@ -739,10 +1620,37 @@ impl ExecutionState {
         &self,
     ) -> anyhow::Result<(ExecutionState, Vec<(OperationId, OperationFnOutput)>)> {
         debug!("Running step_execution for state {:?}", self.chronology_id);
-        // 1. Initialize state and prepare for execution
-        let mut before_execution_state = self.determine_next_operation()?;
+        let before_execution_state = self.determine_next_operation()?;
+        self.evaluate_staged_operation(before_execution_state).await
+    }
+
+    /// Evaluate exactly one operation, the first of `ready_operations`, regardless of where the
+    /// round robin of `step_execution` has reached. This gives debuggers a deterministic order
+    /// to step through operations in.
+    pub async fn micro_step_execution(
+        &self,
+    ) -> anyhow::Result<(ExecutionState, Vec<(OperationId, OperationFnOutput)>)> {
+        debug!("Running micro_step_execution for state {:?}", self.chronology_id);
+        let operation_id = *self.ready_operations()?
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("No operation is ready to be evaluated"))?;
+        let inputs = self.ready_operation_inputs(operation_id)?
+            .ok_or_else(|| anyhow::anyhow!("Operation {:?} is no longer ready to be evaluated", operation_id))?;
+        let mut exec_queue = self.exec_queue.clone();
+        exec_queue.retain(|id| *id != operation_id);
+        let before_execution_state = self.stage_operation(operation_id, inputs, exec_queue)?;
+        self.evaluate_staged_operation(before_execution_state).await
+    }
+
+    #[tracing::instrument(skip(self, before_execution_state), fields(operation_id = %before_execution_state.evaluating_operation_id))]
+    async fn evaluate_staged_operation(
+        &self,
+        mut before_execution_state: ExecutionState,
+    ) -> anyhow::Result<(ExecutionState, Vec<(OperationId, OperationFnOutput)>)> {
+        // 1. Prepare for execution
+        before_execution_state.state_transient.clear();
         let operation_id = before_execution_state.evaluating_operation_id.clone();
-        let args = before_execution_state.evaluating_arguments.take().unwrap();
+        let args = before_execution_state.evaluating_arguments.clone().unwrap();
 
         // 2. Update operation node info
         let op_node = self.get_operation_node(operation_id)?;
@@ -752,7 +1660,30 @@ impl ExecutionState {
         self.send_new_state_to_graph_and_pause_with_oneshot(&mut before_execution_state).await;
 
         // 4. Execute the operation
-        let result = op_node.execute(&mut before_execution_state, args, None, None).await?;
+        let args = self.apply_input_resolution_hook(operation_id, args);
+        let input = self.record_input(operation_id, &op_node.cell, &args);
+        let agent_trace = AgentTrace::new(operation_id, self.agent_trace_sink.clone());
+        before_execution_state.agent_trace = Some(agent_trace.clone());
+        let retry_on_failure = self.code_gen_retry_for_failure(operation_id);
+        let result = match self.execute_with_policy(&op_node, &before_execution_state, args).await {
+            Ok(result) => result,
+            Err(err) if retry_on_failure.is_some() => OperationFnOutput {
+                has_error: true,
+                execution_state: None,
+                output: Err(ExecutionStateErrors::CellExecutionUnexpectedFailure(before_execution_state.chronology_id, err.to_string())),
+                stdout: vec![],
+                stderr: vec![],
+                peak_memory_bytes: None,
+                agent_trace: vec![],
+                response_metadata: None,
+                input: None,
+            },
+            Err(err) => return Err(err),
+        };
+        let mut result = self.enforce_output_limit(operation_id, &op_node.cell, result);
+        result.agent_trace = agent_trace.steps();
+        result.input = input;
+        self.report_completion(operation_id, &result);
 
         // 5. Update state with execution results
         // If the result of the execution returned a new execution state
@@ -760,9 +1691,33 @@ impl ExecutionState {
         // that new state.
         let mut after_execution_state = before_execution_state
             .close_and_set_chronological_parent(&result.execution_state.as_ref().unwrap_or(&before_execution_state));
+        after_execution_state.agent_trace = None;
+
+        // Generated code failed, record the error and regenerate it rather than storing the failure
+        if let (Some(generating_operation_id), true) = (retry_on_failure, result.has_error || result.output.is_err()) {
+            let error = match &result.output {
+                Err(e) => e.to_string(),
+                Ok(_) => result.stderr.join("\n"),
+            };
+            if let Some(retry) = after_execution_state.code_gen_retries.get_mut(&generating_operation_id) {
+                retry.last_error = Some(error);
+            }
+            after_execution_state.exec_queue.push_front(generating_operation_id);
+            self.send_new_state_to_graph_and_pause_with_oneshot(&mut after_execution_state).await;
+            return Ok((after_execution_state, vec![(operation_id, result)]));
+        }
+
+        // A retry has been consumed by this evaluation, and any code it produced is brought into the graph
+        if let Some(retry) = after_execution_state.code_gen_retries.get_mut(&operation_id) {
+            retry.last_error = None;
+        }
+        if let Some(generated_state) = &result.execution_state {
+            after_execution_state.adopt_generated_operations(generated_state)?;
+        }
 
         // 6. Finalize state
         after_execution_state.fresh_values.insert(operation_id.clone());
+        after_execution_state.memory_writes.insert(operation_id, after_execution_state.resolving_execution_node_state_id);
         after_execution_state.state_insert(operation_id.clone(), result.clone());
         after_execution_state.value_freshness_map.insert(operation_id.clone(), after_execution_state.exec_counter);
 
@@ -779,6 +1734,25 @@ mod tests {
     use crate::cells::CodeCell;
     use crate::execution::primitives::operation::{InputItemConfiguration, InputType, OutputSignature, Signature, TriggerConfiguration};
 
+    fn code(source_code: &str) -> CellTypes {
+        CellTypes::Code(CodeCell {
+            language: SupportedLanguage::PyO3,
+            source_code: source_code.to_string(),
+            ..Default::default()
+        }, TextRange::default())
+    }
+
+    /// Step `state` until no operation is ready, giving up after ten steps
+    async fn settle(mut state: ExecutionState) -> anyhow::Result<ExecutionState> {
+        for _ in 0..10 {
+            if state.ready_operations()?.is_empty() {
+                break;
+            }
+            state = state.step_execution().await?.0;
+        }
+        Ok(state)
+    }
+
     #[test]
     fn test_state_insert_and_get_value() {
         let mut exec_state = ExecutionState::new_with_random_id();
@@ -790,6 +1764,10 @@ mod tests {
             output: Ok(value),
             stdout: vec![],
             stderr: vec![],
+            peak_memory_bytes: None,
+            agent_trace: vec![],
+            response_metadata: None,
+            input: None,
         };
         exec_state.state_insert(operation_id, value.clone());
 
@@ -833,8 +1811,182 @@ mod tests {
         assert!(exec_state.dependency_map.get(&operation_id).is_none());
     }
 
+    #[test]
+    fn test_describe_dependency_graph_mutations() {
+        let operation_id = Uuid::now_v7();
+        let source = Uuid::now_v7();
+        let create = DependencyGraphMutation::Create {
+            operation_id,
+            depends_on: vec![
+                (source, DependencyReference::Global("x".to_string())),
+                (source, DependencyReference::FunctionInvocation("add".to_string())),
+            ],
+        };
+        assert_eq!(
+            create.describe(),
+            format!("Set operation {} to depend on {} through global 'x', {} through function 'add'", operation_id, source, source)
+        );
+        let delete = DependencyGraphMutation::Delete { operation_id };
+        assert_eq!(delete.describe(), format!("Removed operation {}", operation_id));
+        assert!(DependencyGraphMutation::Create { operation_id, depends_on: vec![] }.describe().contains(&operation_id.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_transient_state_cleared_on_next_step() -> anyhow::Result<()> {
+        let state = ExecutionState::new_with_random_id();
+        let cell = CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: None,
+            language: SupportedLanguage::PyO3,
+            source_code: String::from("x = 1"),
+            function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
+        }, TextRange::default());
+        let op = state.get_operation_from_cell_type(&cell)?;
+        let (op_id, mut state) = state.upsert_operation(op, Uuid::now_v7())?;
+
+        state.state_set_transient(op_id, RkyvSerializedValue::String("progress".to_string()));
+        assert_eq!(state.state_get_transient(&op_id), Some(&RkyvSerializedValue::String("progress".to_string())));
+        assert!(state.state_get_value(&op_id).is_none());
+
+        let (state, _) = state.step_execution().await?;
+        assert!(state.state_get_transient(&op_id).is_none());
+        assert!(state.state_transient.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_changed_ops_since() -> anyhow::Result<()> {
+        let mut state = ExecutionState::new_with_random_id();
+        let mut op_ids = vec![];
+        for source_code in ["a = 1", "b = a + 1", "c = a - a", "d = 4", "e = 5"] {
+            let (next, op_id) = state.update_operation(code(source_code), Uuid::now_v7()).await?;
+            state = next;
+            op_ids.push(op_id);
+        }
+        let (first_step, _) = state.step_execution().await?;
+        assert_eq!(first_step.get_changed_ops_since(&state), vec![op_ids[0]]);
+        let settled = settle(first_step).await?;
+        assert_eq!(settled.get_changed_ops_since(&state), op_ids);
+
+        // Changing `a` evaluates it and both of its dependents again, but `c` keeps its value. The
+        // edit is applied as instances reload edited cells.
+        let state = settled.update_operations(vec![(code("a = 2"), op_ids[0])]).await?;
+        let stepped = settle(state).await?;
+        assert_eq!(stepped.state_get_value(&op_ids[1]), Some(&Ok(RkyvObjectBuilder::new().insert_number("b", 3).build())));
+        assert_eq!(stepped.get_changed_ops_since(&settled), vec![op_ids[0], op_ids[1]]);
+        assert!(stepped.get_changed_ops_since(&stepped).is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_memory_inserts_follow_the_evaluations_of_a_state() -> anyhow::Result<()> {
+        let mut state = ExecutionState::new_with_random_id();
+        state.memory_stores = Arc::new(crate::library::std::ai::memory::store::tests::keyword_memory_stores());
+        let (fact, writer) = (Uuid::now_v7(), Uuid::now_v7());
+        let stored = |state: ExecutionState| async move {
+            let matches = state.query_memory("facts", MemoryQuery::Text("Paris".to_string()), 10, &Default::default()).await?;
+            anyhow::Ok(matches.into_iter().filter_map(|m| m.text).collect::<Vec<_>>())
+        };
+
+        let (state, _) = state.update_operation(CellTypes::Memory(MemoryCell {
+            name: Some("facts".to_string()),
+            ..Default::default()
+        }, TextRange::default()), Uuid::now_v7()).await?;
+        let (state, _) = state.update_operation(code("fact = \"The capital of France is Paris.\""), fact).await?;
+        let before_insert = state.clone();
+        let (state, _) = state.update_operation(code("import chidori as ch\nawait ch.memory(\"facts\").insert(fact)"), writer).await?;
+        let first_insert = settle(state).await?;
+        assert_eq!(stored(first_insert.clone()).await?, vec!["The capital of France is Paris."]);
+        assert!(stored(before_insert).await?.is_empty());
+
+        // Evaluating the writer again replaces its memory, and the earlier state is unaffected
+        let (state, _) = first_insert.update_operation(code("fact = \"The Eiffel Tower is located in Paris.\""), fact).await?;
+        let second_insert = settle(state).await?;
+        assert_eq!(stored(second_insert).await?, vec!["The Eiffel Tower is located in Paris."]);
+        assert_eq!(stored(first_insert).await?, vec!["The capital of France is Paris."]);
+        Ok(())
+    }
+
     // TODO: add a test that demonstrates multiple edges from the same node, filling multiple values
 
+    #[tokio::test]
+    async fn test_code_gen_retry_with_error_feedback() -> anyhow::Result<()> {
+        let state = ExecutionState::new_with_random_id();
+        let code_gen_id = Uuid::now_v7();
+        let (mut state, _) = state.update_operation(CellTypes::CodeGen(crate::cells::LLMCodeGenCell {
+            backing_file_reference: None,
+            function_invocation: false,
+            configuration: crate::cells::LLMCodeGenCellChatConfiguration {
+                max_attempts: Some(2),
+                ..Default::default()
+            },
+            name: None,
+            provider: crate::cells::SupportedModelProviders::OpenAI,
+            req: "Generate a cell that assigns 42 to y".to_string(),
+            complete_body: "---\nmax_attempts: 2\n---\nGenerate a cell that assigns 42 to y".to_string(),
+        }, TextRange::default()), code_gen_id).await?;
+        // Stands in for the code generation having already been run once
+        state.state_insert(code_gen_id, OperationFnOutput::with_value(RkyvSerializedValue::Null));
+
+        // First generation produces code that fails
+        let state = state.apply_generated_cells(code_gen_id, vec![CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: None,
+            language: SupportedLanguage::PyO3,
+            source_code: "raise ValueError(\"bad generation\")".to_string(),
+            function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
+        }, TextRange::default())], 2).await?;
+        let generated_id = state.code_gen_retries.get(&code_gen_id).unwrap().generated_operations[0];
+
+        let (state, _) = state.step_execution().await?;
+        let retry = state.code_gen_retries.get(&code_gen_id).unwrap();
+        assert_eq!(retry.attempts, 1);
+        assert!(retry.last_error.as_ref().unwrap().contains("bad generation"));
+        assert_eq!(state.state_get_value(&generated_id), None);
+        assert_eq!(state.determine_next_operation()?.evaluating_operation_id, code_gen_id);
+
+        // Second (mocked) generation fixes the code, replacing the failed cell
+        let state = state.apply_generated_cells(code_gen_id, vec![CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: None,
+            language: SupportedLanguage::PyO3,
+            source_code: "y = 42".to_string(),
+            function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
+        }, TextRange::default())], 2).await?;
+        let retry = state.code_gen_retries.get(&code_gen_id).unwrap();
+        assert_eq!(retry.attempts, 2);
+        assert_eq!(retry.last_error, None);
+        assert_eq!(retry.generated_operations, vec![generated_id]);
+
+        let (state, _) = state.step_execution().await?;
+        assert_eq!(
+            state.state_get_value(&generated_id),
+            Some(&Ok(RkyvObjectBuilder::new().insert_number("y", 42).build()))
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_async_execution_at_a_state() {
         let mut exec_state = ExecutionState::new_with_random_id();
@@ -868,6 +2020,13 @@ mod tests {
             language: SupportedLanguage::PyO3,
             source_code: String::from("y = x + 1"),
             function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
         }, Default::default());
 
         let id_a = Uuid::now_v7();
@@ -929,6 +2088,13 @@ mod tests {
             language: SupportedLanguage::PyO3,
             source_code: "def test_fn(): return 2".to_string(),
             function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
         }, TextRange::default());
 
         let id_a = Uuid::now_v7();
@@ -945,6 +2111,141 @@ mod tests {
         assert_eq!(result.unwrap(), RkyvSerializedValue::Number(2));
     }
 
+    #[tokio::test]
+    async fn test_dispatch_exceeding_max_invocation_depth() -> anyhow::Result<()> {
+        let mut state = ExecutionState::new_with_random_id();
+        let mut op_node = OperationNode::default();
+        op_node.cell = CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: None,
+            language: SupportedLanguage::PyO3,
+            source_code: "def test_fn(): return 2".to_string(),
+            function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
+        }, TextRange::default());
+
+        let id_a = Uuid::now_v7();
+        let (op_id, mut new_state) = state.upsert_operation(op_node, id_a)?;
+        new_state.function_name_to_metadata.insert("test_fn".to_string(), FunctionMetadata {
+            operation_id: op_id,
+            input_signature: InputSignature::new(),
+        });
+        new_state.max_invocation_depth = 2;
+        new_state.invocation_chain = vec!["a".to_string(), "b".to_string()];
+
+        let (result, _) = new_state.dispatch("test_fn", RkyvSerializedValue::Null, None).await?;
+        match result {
+            Err(ExecutionStateErrors::InvocationDepthExceeded(depth, chain)) => {
+                assert_eq!(depth, 2);
+                assert_eq!(chain, "a -> b -> test_fn");
+            }
+            other => panic!("Expected the invocation depth to be exceeded, got {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_native_functions() {
+        let mut state = ExecutionState::new_with_random_id();
+        state.native_functions.insert("double".to_string(), Arc::new(|args| match args {
+            RkyvSerializedValue::Number(n) => RkyvSerializedValue::Number(n * 2),
+            _ => RkyvSerializedValue::Null,
+        }));
+
+        assert_eq!(state.call_native_function("double", RkyvSerializedValue::Number(4)).unwrap(), RkyvSerializedValue::Number(8));
+        assert!(state.call_native_function("missing", RkyvSerializedValue::Null).is_err());
+        assert!(matches!(
+            state.native_function_signature().functions.get("double"),
+            Some(OutputItemConfiguration::NativeFunction)
+        ));
+    }
+
+    #[test]
+    fn test_cells_inherit_the_program_execution_policy() {
+        let code_cell = |policy: ExecutionPolicy| CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: None,
+            language: SupportedLanguage::PyO3,
+            source_code: "x = 1".to_string(),
+            function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy,
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
+        }, TextRange::default());
+
+        // Without any configuration cells have no timeout and are not retried
+        let mut state = ExecutionState::new_with_random_id();
+        assert_eq!(state.execution_policy_for(&code_cell(ExecutionPolicy::default())), ExecutionPolicy::default());
+
+        state.default_execution_policy = ExecutionPolicy { timeout_ms: Some(5000), retries: Some(2), max_output_bytes: Some(1024), ..Default::default() };
+        assert_eq!(
+            state.execution_policy_for(&code_cell(ExecutionPolicy::default())),
+            ExecutionPolicy { timeout_ms: Some(5000), retries: Some(2), max_output_bytes: Some(1024), ..Default::default() }
+        );
+        assert_eq!(
+            state.execution_policy_for(&code_cell(ExecutionPolicy { timeout_ms: Some(100), overflow: Some(OutputOverflow::Spill), ..Default::default() })),
+            ExecutionPolicy { timeout_ms: Some(100), retries: Some(2), max_output_bytes: Some(1024), overflow: Some(OutputOverflow::Spill) }
+        );
+    }
+
+    async fn step_oversized_output(overflow: OutputOverflow) -> anyhow::Result<(OperationId, ExecutionState)> {
+        let state = ExecutionState::new_with_random_id();
+        let cell = CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: None,
+            language: SupportedLanguage::PyO3,
+            source_code: String::from("x = 'a' * 10000"),
+            function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: ExecutionPolicy { max_output_bytes: Some(1024), overflow: Some(overflow), ..Default::default() },
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
+        }, TextRange::default());
+        let op = state.get_operation_from_cell_type(&cell)?;
+        let (op_id, state) = state.upsert_operation(op, Uuid::now_v7())?;
+        let (state, _) = state.step_execution().await?;
+        Ok((op_id, state))
+    }
+
+    #[tokio::test]
+    async fn test_oversized_output_fails() -> anyhow::Result<()> {
+        let (op_id, state) = step_oversized_output(OutputOverflow::Fail).await?;
+        match state.state_get_value(&op_id) {
+            Some(Err(ExecutionStateErrors::OutputTooLarge { size, limit })) => {
+                assert!(*size > 10000);
+                assert_eq!(*limit, 1024);
+            }
+            other => panic!("Expected the output to be rejected, got {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_oversized_output_spills_to_disk() -> anyhow::Result<()> {
+        let (op_id, state) = step_oversized_output(OutputOverflow::Spill).await?;
+        let Some(Ok(RkyvSerializedValue::Object(output))) = state.state_get_value(&op_id) else {
+            panic!("Expected the output to be replaced by references");
+        };
+        assert_eq!(
+            crate::execution::execution::spill::load_spilled_output(&output["x"])?,
+            RkyvSerializedValue::String("a".repeat(10000))
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_get_dependency_graph() {
         let mut state = ExecutionState::new_with_random_id();
@@ -960,6 +2261,27 @@ mod tests {
         assert!(graph.contains_edge(id_c, id_b));
     }
 
+    #[test]
+    fn test_transitive_dependents_and_dependencies() {
+        let mut state = ExecutionState::new_with_random_id();
+        let head = Uuid::now_v7();
+        let middle = Uuid::now_v7();
+        let tail = Uuid::now_v7();
+        let unrelated = Uuid::now_v7();
+        // tail reads from middle, which reads from head
+        state.dependency_map.insert(middle, IndexSet::from_iter(vec![(head, DependencyReference::Global("x".to_string()))]));
+        state.dependency_map.insert(tail, IndexSet::from_iter(vec![(middle, DependencyReference::Global("y".to_string()))]));
+        state.dependency_map.insert(unrelated, IndexSet::new());
+
+        assert_eq!(state.dependents_of(head), HashSet::from([middle, tail]));
+        assert_eq!(state.dependents_of(middle), HashSet::from([tail]));
+        assert!(state.dependents_of(tail).is_empty());
+        assert_eq!(state.dependencies_of(tail), HashSet::from([middle, head]));
+        assert!(state.dependencies_of(head).is_empty());
+        assert!(state.dependents_of(unrelated).is_empty());
+        assert!(state.dependents_of(Uuid::now_v7()).is_empty());
+    }
+
     #[test]
     fn test_input_signature_check() {
         let mut exec_state = ExecutionState::new_with_random_id();
@@ -973,6 +2295,7 @@ mod tests {
                     ty: Some(InputType::String),
                     default: None,
                 })]),
+                arg_order: vec!["0".to_string()],
                 kwargs: HashMap::from([("kwarg1".to_string(), InputItemConfiguration {
                     ty: Some(InputType::String),
                     default: None,
@@ -981,10 +2304,13 @@ mod tests {
                     ty: Some(InputType::String),
                     default: None,
                 })]),
+                imported_cells: vec![],
+                memory_reads: vec![],
             },
             output_signature: OutputSignature {
                 globals: HashMap::new(),
                 functions: HashMap::new(),
+                memory_writes: vec![],
             },
         };
 
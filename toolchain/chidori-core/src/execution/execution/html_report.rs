@@ -0,0 +1,411 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+use uuid::Uuid;
+use crate::cells::{CellTypes, LLMPromptCell, SupportedLanguage};
+use crate::execution::execution::execution_state::{EnclosedState, ExecutionState};
+use crate::execution::primitives::identifiers::OperationId;
+use crate::execution::primitives::operation::OperationFnOutput;
+use crate::execution::primitives::serialized_value::{serialized_value_to_json_value, RkyvSerializedValue};
+use crate::library::std::ai::llm::{format_cost_usd, model_cost_usd};
+use crate::utils::redaction::RedactionConfig;
+
+const REPORT_TEMPLATE: &str = include_str!("html_report/report.html");
+const REPORT_STYLE: &str = include_str!("html_report/report.css");
+const REPORT_SCRIPT: &str = include_str!("html_report/report.js");
+
+/// Options controlling the content of an exported html report
+#[derive(Debug, Clone)]
+pub struct HtmlReportOptions {
+    pub title: String,
+    /// Replace the prompts and responses of LLM calls with a placeholder
+    pub redact_llm_content: bool,
+    /// Values longer than this are truncated, with the complete value in an expandable section
+    pub max_value_length: usize,
+    /// Applied to the inputs and outputs of every step
+    pub redaction: RedactionConfig,
+}
+
+impl Default for HtmlReportOptions {
+    fn default() -> Self {
+        Self {
+            title: "Chidori run report".to_string(),
+            redact_llm_content: false,
+            max_value_length: 500,
+            redaction: RedactionConfig::default(),
+        }
+    }
+}
+
+const REDACTED: &str = "[redacted]";
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn uuid_v7_time(id: &Uuid) -> Option<Duration> {
+    id.get_timestamp().map(|ts| {
+        let (secs, nanos) = ts.to_unix();
+        Duration::new(secs, nanos)
+    })
+}
+
+fn render_value(value: &RkyvSerializedValue, options: &HtmlReportOptions) -> String {
+    let json = serde_json::to_string_pretty(&serialized_value_to_json_value(value)).unwrap_or_default();
+    render_text(&json, options)
+}
+
+fn render_text(text: &str, options: &HtmlReportOptions) -> String {
+    if text.chars().count() <= options.max_value_length {
+        return format!("<pre>{}</pre>", escape_html(text));
+    }
+    let preview: String = text.chars().take(options.max_value_length).collect();
+    format!(
+        "<details><summary><pre>{}…</pre></summary><pre>{}</pre></details>",
+        escape_html(&preview),
+        escape_html(text)
+    )
+}
+
+pub(crate) fn cell_source(cell: &CellTypes) -> (&'static str, String) {
+    match cell {
+        CellTypes::Code(c, _) => match c.language {
+            SupportedLanguage::PyO3 => ("python", c.source_code.clone()),
+            SupportedLanguage::Deno => ("javascript", c.source_code.clone()),
+        },
+        CellTypes::Prompt(LLMPromptCell::Chat { complete_body, .. }, _) => ("prompt", complete_body.clone()),
+        CellTypes::Prompt(LLMPromptCell::Completion { req }, _) => ("prompt", req.clone()),
+        CellTypes::CodeGen(c, _) => ("prompt", c.complete_body.clone()),
+        CellTypes::Template(c, _) => ("html", c.body.clone()),
+        CellTypes::Webhook(c, _) => ("webhook", format!("POST :{}{}", c.port, c.path)),
+        CellTypes::Mcp(c, _) => ("mcp", c.server_description()),
+        CellTypes::File(c, _) => ("file", c.path.clone()),
+        CellTypes::Extract(c, _) => ("extract", c.source.clone().unwrap_or_default()),
+        CellTypes::Memory(c, _) => ("memory", c.embedding_model.clone()),
+        CellTypes::Chunk(c, _) => ("chunk", c.source.clone().unwrap_or_default()),
+    }
+}
+
+/// How long the evaluation closed by this state took. Both the opening and closing state ids are
+/// v7 uuids, and so encode when they were created.
+pub(crate) fn evaluation_duration(state: &ExecutionState) -> Option<Duration> {
+    if !matches!(state.evaluating_enclosed_state, EnclosedState::Close(_)) {
+        return None;
+    }
+    match (uuid_v7_time(&state.resolving_execution_node_state_id), uuid_v7_time(&state.chronology_id)) {
+        (Some(start), Some(end)) => end.checked_sub(start),
+        _ => None,
+    }
+}
+
+/// Substitute the `{{name}}` placeholders of `template` in one pass, so that text resembling a
+/// placeholder within substituted content is left as it is
+fn fill_template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        filled.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let value = after.find("}}").and_then(|end| {
+            values.iter().find(|(name, _)| *name == &after[..end]).map(|(_, value)| (end, value))
+        });
+        match value {
+            Some((end, value)) => {
+                filled.push_str(value);
+                rest = &after[end + 2..];
+            }
+            None => {
+                filled.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// Calls and tokens reported by the provider for one model over the run
+#[derive(Default)]
+struct ModelUsage {
+    calls: usize,
+    prompt_tokens: usize,
+    completion_tokens: usize,
+}
+
+impl ModelUsage {
+    fn cost_usd(&self, model: &str) -> f64 {
+        model_cost_usd(Some(model), self.prompt_tokens, self.completion_tokens)
+    }
+}
+
+fn is_llm_cell(cell: &CellTypes) -> bool {
+    matches!(cell, CellTypes::Prompt(..) | CellTypes::CodeGen(..))
+}
+
+/// A completed evaluation of an operation found in the execution graph
+struct ReportStep<'a> {
+    state: &'a ExecutionState,
+    operation_id: OperationId,
+    cell: &'a CellTypes,
+    output: &'a OperationFnOutput,
+    duration: Option<Duration>,
+}
+
+impl<'a> ReportStep<'a> {
+    fn from_state(state: &'a ExecutionState) -> Option<Self> {
+        if !matches!(state.evaluating_enclosed_state, EnclosedState::Close(_)) {
+            return None;
+        }
+        let operation_id = state.evaluating_operation_id;
+        let cell = state.evaluating_cell.as_ref()?;
+        let output = state.state.get(&operation_id)?.as_ref();
+        let duration = evaluation_duration(state);
+        Some(ReportStep { state, operation_id, cell, output, duration })
+    }
+
+    fn is_error(&self) -> bool {
+        self.output.has_error || self.output.output.is_err()
+    }
+}
+
+/// Render a self-contained html document describing the cells and evaluation of the given states
+pub fn render_html_report(states: &[ExecutionState], options: &HtmlReportOptions) -> String {
+    let mut states: Vec<&ExecutionState> = states.iter().collect();
+    states.sort_by_key(|s| s.chronology_id);
+
+    let steps: Vec<ReportStep> = states.iter().filter_map(|s| ReportStep::from_state(s)).collect();
+
+    // Cells as they were defined at the end of the run
+    let mut cells_html = String::new();
+    if let Some(latest) = states.last() {
+        for (op_id, cell) in latest.get_cells_in_operation_order() {
+            let (language, source) = cell_source(&cell);
+            let name = cell.name().clone().unwrap_or_else(|| op_id.to_string());
+            let description = latest.cell_descriptions.get(&op_id)
+                .map(|d| format!("<p class=\"description\">{}</p>", escape_html(d)))
+                .unwrap_or_default();
+            let _ = write!(
+                cells_html,
+                "<div class=\"cell\"><h3>{}</h3>{}<pre><code data-language=\"{}\">{}</code></pre></div>\n",
+                escape_html(&name),
+                description,
+                language,
+                escape_html(&source)
+            );
+        }
+    }
+
+    let mut timeline_html = String::new();
+    let mut total_duration = Duration::ZERO;
+    let mut llm_calls = 0;
+    let mut errors = 0;
+    let mut usage_by_model: BTreeMap<String, ModelUsage> = BTreeMap::new();
+    for step in &steps {
+        let is_llm = is_llm_cell(step.cell);
+        if is_llm {
+            llm_calls += 1;
+        }
+        if step.is_error() {
+            errors += 1;
+        }
+        total_duration += step.duration.unwrap_or_default();
+
+        let name = step.state.evaluating_name.clone()
+            .or_else(|| step.state.evaluating_fn.clone())
+            .unwrap_or_else(|| step.operation_id.to_string());
+        let duration = step.duration.map(|d| format!("{:?}", d)).unwrap_or_default();
+        let _ = write!(
+            timeline_html,
+            "<div class=\"step{}\"><h3>{}<span class=\"duration\">{}</span></h3>\n",
+            if step.is_error() { " error" } else { "" },
+            escape_html(&name),
+            escape_html(&duration)
+        );
+
+        if is_llm {
+            let (_, prompt) = cell_source(step.cell);
+            let response = match &step.output.output {
+                Ok(v) => {
+                    let v = options.redaction.redact_cell_output(Some(step.cell), v);
+                    serde_json::to_string_pretty(&serialized_value_to_json_value(&v)).unwrap_or_default()
+                }
+                Err(e) => e.to_string(),
+            };
+            let (prompt, response) = if options.redact_llm_content {
+                (REDACTED.to_string(), REDACTED.to_string())
+            } else {
+                (prompt, response)
+            };
+            let _ = write!(
+                timeline_html,
+                "<div class=\"llm\"><h4>Prompt</h4>{}<h4>Response</h4>{}</div>\n",
+                render_text(&prompt, options),
+                render_text(&response, options)
+            );
+            if let Some(metadata) = &step.output.response_metadata {
+                let prompt_tokens = metadata.usage.prompt_tokens.max(0) as usize;
+                let completion_tokens = metadata.usage.completion_tokens.max(0) as usize;
+                let usage = usage_by_model.entry(metadata.model.clone()).or_default();
+                usage.calls += 1;
+                usage.prompt_tokens += prompt_tokens;
+                usage.completion_tokens += completion_tokens;
+                let _ = write!(
+                    timeline_html,
+                    "<p class=\"usage\">{} · {} prompt tokens · {} completion tokens · {}</p>\n",
+                    escape_html(&metadata.model),
+                    prompt_tokens,
+                    completion_tokens,
+                    escape_html(&format_cost_usd(model_cost_usd(Some(&metadata.model), prompt_tokens, completion_tokens)))
+                );
+            }
+        } else {
+            if let Some(arguments) = &step.state.evaluating_arguments {
+                let arguments = options.redaction.redact(arguments);
+                let _ = write!(timeline_html, "<h4>Inputs</h4>{}\n", render_value(&arguments, options));
+            }
+            if let Ok(value) = &step.output.output {
+                let value = options.redaction.redact_cell_output(Some(step.cell), value);
+                let _ = write!(timeline_html, "<h4>Output</h4>{}\n", render_value(&value, options));
+            }
+        }
+
+        if let Err(e) = &step.output.output {
+            let _ = write!(timeline_html, "<h4>Error</h4><div class=\"error-text\">{}</div>\n", render_text(&e.to_string(), options));
+        }
+        if !step.output.stdout.is_empty() {
+            let _ = write!(timeline_html, "<h4>stdout</h4>{}\n", render_text(&step.output.stdout.join("\n"), options));
+        }
+        if !step.output.stderr.is_empty() {
+            let _ = write!(timeline_html, "<h4>stderr</h4>{}\n", render_text(&step.output.stderr.join("\n"), options));
+        }
+        if !step.output.agent_trace.is_empty() {
+            let hidden = is_llm && options.redact_llm_content;
+            timeline_html.push_str("<h4>Agent trace</h4><ol class=\"agent-trace\">\n");
+            for trace_step in &step.output.agent_trace {
+                let content = if hidden { REDACTED.to_string() } else { options.redaction.redact_trace_step(Some(step.cell), trace_step).content };
+                let _ = write!(
+                    timeline_html,
+                    "<li><span class=\"trace-kind\">{}</span>{}</li>\n",
+                    trace_step.kind,
+                    render_text(&content, options)
+                );
+            }
+            timeline_html.push_str("</ol>\n");
+        }
+        timeline_html.push_str("</div>\n");
+    }
+
+    let total_tokens: usize = usage_by_model.values().map(|u| u.prompt_tokens + u.completion_tokens).sum();
+    let total_cost: f64 = usage_by_model.iter().map(|(model, usage)| usage.cost_usd(model)).sum();
+    let summary_html = format!(
+        "<div class=\"summary\"><div><strong>{}</strong>operations executed</div><div><strong>{}</strong>LLM calls</div><div><strong>{}</strong>errors</div><div><strong>{:?}</strong>total time</div><div><strong>{}</strong>tokens</div><div><strong>{}</strong>estimated cost</div></div>",
+        steps.len(),
+        llm_calls,
+        errors,
+        total_duration,
+        total_tokens,
+        escape_html(&format_cost_usd(total_cost))
+    );
+
+    let mut usage_html = String::new();
+    if usage_by_model.is_empty() {
+        usage_html.push_str("<p>No model was queried.</p>\n");
+    } else {
+        usage_html.push_str("<table class=\"usage\"><tr><th>Model</th><th>Calls</th><th>Prompt tokens</th><th>Completion tokens</th><th>Estimated cost</th></tr>\n");
+        for (model, usage) in &usage_by_model {
+            let _ = write!(
+                usage_html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                escape_html(model),
+                usage.calls,
+                usage.prompt_tokens,
+                usage.completion_tokens,
+                escape_html(&format_cost_usd(usage.cost_usd(model)))
+            );
+        }
+        usage_html.push_str("</table>\n");
+    }
+
+    fill_template(REPORT_TEMPLATE, &[
+        ("title", &escape_html(&options.title)),
+        ("style", REPORT_STYLE),
+        ("script", REPORT_SCRIPT),
+        ("summary", &summary_html),
+        ("usage", &usage_html),
+        ("cells", &cells_html),
+        ("timeline", &timeline_html),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cells::{LLMPromptCellChatConfiguration, SupportedModelProviders, TextRange};
+    use crate::execution::execution::execution_state::CloseReason;
+    use crate::library::std::ai::llm::{ModelResponseMetadata, Usage};
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(escape_html("<a href=\"x\">&</a>"), "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;");
+    }
+
+    #[test]
+    fn test_long_values_are_truncated_into_expandable_sections() {
+        let options = HtmlReportOptions { max_value_length: 5, ..Default::default() };
+        assert_eq!(render_text("short", &options), "<pre>short</pre>");
+        let rendered = render_text("much longer", &options);
+        assert!(rendered.starts_with("<details><summary><pre>much …</pre></summary>"));
+        assert!(rendered.contains("<pre>much longer</pre>"));
+    }
+
+    #[test]
+    fn test_placeholders_in_content_are_not_substituted() {
+        let filled = fill_template("<h1>{{title}}</h1>{{cells}}{{unknown}}", &[("title", "{{cells}}"), ("cells", "x = 1")]);
+        assert_eq!(filled, "<h1>{{cells}}</h1>x = 1{{unknown}}");
+    }
+
+    #[test]
+    fn test_report_includes_usage_and_cost() {
+        let op_id = Uuid::now_v7();
+        let cell = CellTypes::Prompt(LLMPromptCell::Chat {
+            is_function_invocation: false,
+            configuration: LLMPromptCellChatConfiguration::default(),
+            name: Some("greeting".to_string()),
+            provider: SupportedModelProviders::OpenAI,
+            complete_body: "Say {{timeline}}".to_string(),
+            req: "Say {{timeline}}".to_string(),
+            backing_file_reference: None,
+        }, TextRange::default());
+        let mut state = ExecutionState::new_with_random_id();
+        state.evaluating_enclosed_state = EnclosedState::Close(CloseReason::Complete);
+        state.evaluating_operation_id = op_id;
+        state.evaluating_cell = Some(cell);
+        state.state_insert(op_id, OperationFnOutput {
+            response_metadata: Some(ModelResponseMetadata {
+                id: "response".to_string(),
+                model: "gpt-4o".to_string(),
+                choices: vec![],
+                usage: Usage { prompt_tokens: 1000, completion_tokens: 500, total_tokens: 1500 },
+                cache_key: None,
+            }),
+            ..OperationFnOutput::with_value(RkyvSerializedValue::String("hello".to_string()))
+        });
+
+        let report = render_html_report(&[state], &HtmlReportOptions::default());
+        assert!(report.contains("<td>gpt-4o</td><td>1</td><td>1000</td><td>500</td>"));
+        assert!(report.contains("<strong>1500</strong>tokens"));
+        // The prompt is shown as written rather than replaced by the timeline
+        assert!(report.contains("Say {{timeline}}"));
+    }
+}
@@ -0,0 +1,21 @@
+// Minimal syntax highlighting so that the report has no external dependencies
+(function () {
+  var keywords = {
+    python: ["def", "return", "import", "from", "as", "if", "elif", "else", "for", "while", "in", "not", "and", "or", "class", "with", "async", "await", "try", "except", "finally", "raise", "lambda", "None", "True", "False", "pass", "yield"],
+    javascript: ["const", "let", "var", "function", "return", "import", "from", "export", "if", "else", "for", "while", "class", "new", "async", "await", "try", "catch", "finally", "throw", "null", "undefined", "true", "false"]
+  };
+  var blocks = document.querySelectorAll("pre code[data-language]");
+  blocks.forEach(function (block) {
+    var words = keywords[block.getAttribute("data-language")];
+    if (!words) {
+      return;
+    }
+    var pattern = new RegExp("(#[^\\n]*|//[^\\n]*)|(\"[^\"\\n]*\"|'[^'\\n]*')|\\b(" + words.join("|") + ")\\b|\\b(\\d+(?:\\.\\d+)?)\\b", "g");
+    block.innerHTML = block.innerHTML.replace(pattern, function (match, comment, str, kw, num) {
+      if (comment) { return "<span class=\"comment\">" + comment + "</span>"; }
+      if (str) { return "<span class=\"str\">" + str + "</span>"; }
+      if (kw) { return "<span class=\"kw\">" + kw + "</span>"; }
+      return "<span class=\"num\">" + num + "</span>";
+    });
+  });
+})();
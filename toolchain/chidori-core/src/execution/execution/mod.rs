@@ -1,5 +1,9 @@
 pub mod execution_graph;
 pub mod execution_state;
+pub mod html_report;
+pub mod repro;
+pub mod spill;
+pub mod stream;
 
 
 use crate::execution::primitives::identifiers::{OperationId};
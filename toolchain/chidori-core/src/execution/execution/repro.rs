@@ -0,0 +1,265 @@
+//! Bundles for sharing minimal reproductions of failures, see `ExecutionGraph::export_repro`. A
+//! bundle is a zip archive holding
+//! - `manifest.json`, describing the failure, see `ReproManifest`
+//! - `cells.md`, the failing cells and the cells they depend on, as a program
+//! - `recording.rkyv`, the outputs recorded for the cells the failing ones depend on and the
+//!   execution policy of the run
+//! - `llm_cache/`, the model responses to the requests of the included cells, replayed in place
+//!   of the providers
+//!
+//! Recorded outputs are kept by the position of their cell in `cells.md`, since operations are
+//! assigned new ids when the bundle is loaded.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use rkyv::Deserialize as RkyvDeserialize;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+use crate::cells::{CellTypes, ExecutionPolicy};
+use crate::execution::execution::execution_graph::ExecutionNodeId;
+use crate::execution::execution::ExecutionState;
+use crate::execution::primitives::identifiers::OperationId;
+use crate::execution::primitives::serialized_value::RkyvSerializedValue;
+use crate::library::std::ai::llm::cache::ResponseCache;
+use crate::library::std::ai::llm::ChatCompletionRes;
+use crate::sdk::checkpoint::{Checkpoint, CheckpointedState};
+use crate::sdk::md::{documented_cell_to_markdown, extract_code_blocks, interpret_documented_code_block, DocumentedCell};
+use crate::utils::redaction::{RedactionConfig, REDACTED};
+
+/// Bumped when the layout of bundles changes, bundles of other versions are not loaded
+pub const REPRO_BUNDLE_VERSION: u32 = 1;
+
+const MANIFEST_FILE: &str = "manifest.json";
+const CELLS_FILE: &str = "cells.md";
+const RECORDING_FILE: &str = "recording.rkyv";
+const LLM_CACHE_DIRECTORY: &str = "llm_cache/";
+
+/// A cell that failed at the exported state
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReproFailure {
+    pub cell: Option<String>,
+    /// The error of its output, None when the cell fails while evaluating from the exported state
+    /// rather than having recorded its failure there
+    pub error: Option<String>,
+}
+
+/// Describes the contents of a bundle
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReproManifest {
+    pub version: u32,
+    /// The exported state, which the recorded outputs are restored as
+    pub node: ExecutionNodeId,
+    pub failures: Vec<ReproFailure>,
+    pub cells: usize,
+    pub recorded_values: usize,
+    pub recorded_responses: usize,
+    /// Whether a redaction was applied to the recorded outputs and errors
+    pub redacted: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+struct ReproRecording {
+    default_execution_policy: ExecutionPolicy,
+    /// Outputs by the position of the cell that produced them in `cells.md`
+    values: Vec<(u32, RkyvSerializedValue)>,
+}
+
+/// The operations to reproduce at `state`, those holding a failed output, or otherwise those that
+/// evaluate next, for states a failing step was taken from
+fn failing_operations(state: &ExecutionState) -> anyhow::Result<Vec<OperationId>> {
+    let mut failed: Vec<OperationId> = state.state.iter()
+        .filter(|(_, output)| output.has_error || output.output.is_err())
+        .map(|(op_id, _)| *op_id)
+        .collect();
+    failed.sort();
+    if !failed.is_empty() {
+        return Ok(failed);
+    }
+    let ready = state.ready_operations()?;
+    if ready.is_empty() {
+        anyhow::bail!("State {} holds no failed operation and has none left to evaluate", state.chronology_id);
+    }
+    Ok(ready)
+}
+
+/// The responses stored by the cache of `state` for the requests the given operations made, by their
+/// file name, redacted as the outputs of the cells that requested them are
+fn recorded_responses(state: &ExecutionState, operations: &[OperationId], redaction: &RedactionConfig) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    let Some(cache) = state.llm_cache.as_ref() else {
+        return Ok(vec![]);
+    };
+    let mut responses = vec![];
+    for op_id in operations {
+        let Some(key) = state.response_metadata(op_id).and_then(|metadata| metadata.cache_key.as_ref()) else { continue };
+        let Some(response) = cache.get_stored::<ChatCompletionRes>(key) else { continue };
+        let response = redact_response(response, state.cells_by_id.get(op_id), redaction);
+        responses.push((ResponseCache::file_name(key), ResponseCache::entry(&response)?));
+    }
+    responses.sort();
+    responses.dedup_by(|a, b| a.0 == b.0);
+    Ok(responses)
+}
+
+/// Redact the text a model responded with, and the arguments of the tools it called, as the
+/// output of `cell` is
+fn redact_response(mut response: ChatCompletionRes, cell: Option<&CellTypes>, redaction: &RedactionConfig) -> ChatCompletionRes {
+    let redact_text = |text: &String| match redaction.redact_cell_output(cell, &RkyvSerializedValue::String(text.clone())) {
+        RkyvSerializedValue::String(text) => text,
+        _ => REDACTED.to_string(),
+    };
+    let redacts_output = cell.map_or(false, |cell| cell.redacts_output());
+    for choice in &mut response.choices {
+        choice.text = choice.text.as_ref().map(redact_text);
+        choice.refusal = choice.refusal.as_ref().map(redact_text);
+        // Log probabilities hold each generated token
+        if redacts_output || !redaction.is_empty() {
+            choice.logprobs = None;
+        }
+        for tool_call in choice.tool_calls.iter_mut().flatten() {
+            tool_call.function.arguments = tool_call.function.arguments.as_ref()
+                .map(|arguments| redaction.redact_cell_output(cell, arguments));
+        }
+    }
+    response
+}
+
+/// Write the bundle reproducing the failure at `state` to `path`
+pub(crate) fn write_repro_bundle(state: &ExecutionState, path: &Path, redaction: &RedactionConfig) -> anyhow::Result<ReproManifest> {
+    let failing = failing_operations(state)?;
+    let mut operations: Vec<OperationId> = state.get_execution_subgraph(&failing).into_iter().collect();
+    operations.sort();
+
+    let mut blocks = vec![];
+    let mut values = vec![];
+    for op_id in &operations {
+        let Some(cell) = state.cells_by_id.get(op_id) else { continue };
+        let description = state.cell_descriptions.get(op_id).map(|d| d.as_str());
+        let Some(block) = documented_cell_to_markdown(cell, description)? else { continue };
+        let position = blocks.len() as u32;
+        blocks.push(block);
+        if failing.contains(op_id) {
+            continue;
+        }
+        if let Some(Ok(value)) = state.state_get_value(op_id) {
+            values.push((position, redaction.redact_cell_output(Some(cell), value)));
+        }
+    }
+
+    let failures = failing.iter().map(|op_id| ReproFailure {
+        cell: state.cells_by_id.get(op_id).and_then(|cell| cell.name().clone()),
+        error: state.state_get_value(op_id)
+            .and_then(|output| output.as_ref().err())
+            .map(|e| redaction.redact_text(&e.to_string())),
+    }).collect();
+    let responses = recorded_responses(state, &operations, redaction)?;
+    let manifest = ReproManifest {
+        version: REPRO_BUNDLE_VERSION,
+        node: state.chronology_id,
+        failures,
+        cells: blocks.len(),
+        recorded_values: values.len(),
+        recorded_responses: responses.len(),
+        redacted: !redaction.is_empty(),
+    };
+    let recording = ReproRecording {
+        default_execution_policy: state.default_execution_policy.clone(),
+        values,
+    };
+    let recording = rkyv::to_bytes::<_, 4096>(&recording)
+        .map_err(|e| anyhow::anyhow!("Failed to serialize the recorded values: {}", e))?;
+
+    let mut zip = ZipWriter::new(std::fs::File::create(path)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file(MANIFEST_FILE, options)?;
+    zip.write_all(&serde_json::to_vec_pretty(&manifest)?)?;
+    zip.start_file(CELLS_FILE, options)?;
+    zip.write_all(blocks.join("\n").as_bytes())?;
+    zip.start_file(RECORDING_FILE, options)?;
+    zip.write_all(&recording)?;
+    for (name, contents) in &responses {
+        zip.start_file(format!("{}{}", LLM_CACHE_DIRECTORY, name), options)?;
+        zip.write_all(contents)?;
+    }
+    zip.finish()?;
+    Ok(manifest)
+}
+
+/// The contents of a bundle, ready to be loaded as a program
+#[derive(Debug)]
+pub struct ReproBundle {
+    pub manifest: ReproManifest,
+    pub cells: Vec<DocumentedCell>,
+    pub default_execution_policy: ExecutionPolicy,
+    /// Holds the recorded outputs as the exported state, restored once the cells are loaded
+    pub checkpoint: Checkpoint,
+    /// The recorded model responses, extracted to a temporary directory
+    pub llm_cache: ResponseCache,
+}
+
+fn read_file(archive: &mut ZipArchive<std::fs::File>, name: &str) -> anyhow::Result<Vec<u8>> {
+    let mut file = archive.by_name(name)
+        .map_err(|e| anyhow::anyhow!("The bundle has no {}: {}", name, e))?;
+    let mut contents = vec![];
+    file.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+impl ReproBundle {
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let mut archive = ZipArchive::new(std::fs::File::open(path)?)?;
+        let manifest: ReproManifest = serde_json::from_slice(&read_file(&mut archive, MANIFEST_FILE)?)?;
+        if manifest.version != REPRO_BUNDLE_VERSION {
+            anyhow::bail!("{} is a version {} bundle, only version {} is supported", path.display(), manifest.version, REPRO_BUNDLE_VERSION);
+        }
+
+        let markdown = String::from_utf8(read_file(&mut archive, CELLS_FILE)?)?;
+        let mut cells = vec![];
+        for block in extract_code_blocks(&markdown) {
+            cells.extend(interpret_documented_code_block(&block, None)?);
+        }
+
+        let mut bytes = rkyv::AlignedVec::new();
+        bytes.extend_from_slice(&read_file(&mut archive, RECORDING_FILE)?);
+        let recording: ReproRecording = rkyv::check_archived_root::<ReproRecording>(&bytes)
+            .map_err(|e| anyhow::anyhow!("The recorded values of {} are malformed: {}", path.display(), e))?
+            .deserialize(&mut rkyv::Infallible)?;
+        let outputs = recording.values.into_iter()
+            .map(|(position, value)| {
+                let cell = cells.get(position as usize)
+                    .ok_or_else(|| anyhow::anyhow!("A value is recorded for cell {}, which the bundle does not have", position))?;
+                Ok((cell.cell.clone(), value))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let checkpoint = Checkpoint {
+            head_id: manifest.node,
+            run_id: None,
+            states: HashMap::from([(manifest.node, CheckpointedState { id: manifest.node, parent_id: Uuid::nil(), outputs })]),
+        };
+
+        // Removed once the cache, and every clone of it, is dropped
+        let directory: PathBuf = std::env::temp_dir().join(format!("chidori-repro-{}", Uuid::now_v7()));
+        std::fs::create_dir_all(&directory)?;
+        let llm_cache = ResponseCache::temporary(&directory).replay_only();
+        let responses: Vec<String> = archive.file_names()
+            .filter(|name| name.starts_with(LLM_CACHE_DIRECTORY))
+            .map(|name| name.to_string())
+            .collect();
+        for name in responses {
+            let file_name = Path::new(&name).file_name()
+                .ok_or_else(|| anyhow::anyhow!("{} is not a recorded response", name))?;
+            std::fs::write(directory.join(file_name), read_file(&mut archive, &name)?)?;
+        }
+
+        Ok(ReproBundle {
+            manifest,
+            cells,
+            default_execution_policy: recording.default_execution_policy,
+            checkpoint,
+            llm_cache,
+        })
+    }
+}
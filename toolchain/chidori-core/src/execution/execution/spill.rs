@@ -0,0 +1,102 @@
+//! Outputs exceeding the size limit of their cell with `overflow: spill` are written to disk,
+//! cells depending on them receive a reference to the file in place of the value.
+
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+use crate::execution::primitives::identifiers::OperationId;
+use crate::execution::primitives::serialized_value::{deserialize_from_buf, serialize_to_vec, RkyvObjectBuilder, RkyvSerializedValue};
+
+/// Key of the object that stands in for a spilled value
+pub const SPILLED_OUTPUT_KEY: &str = "spilled_output";
+
+/// Directory spilled outputs are written to
+pub fn spill_directory() -> PathBuf {
+    std::env::temp_dir().join("chidori").join("spill")
+}
+
+fn spill_bytes(directory: &Path, operation_id: OperationId, bytes: &[u8]) -> anyhow::Result<RkyvSerializedValue> {
+    std::fs::create_dir_all(directory)?;
+    let path = directory.join(format!("{}_{}.rkyv", operation_id, Uuid::now_v7()));
+    std::fs::write(&path, bytes)?;
+    Ok(RkyvObjectBuilder::new()
+        .insert_string(SPILLED_OUTPUT_KEY, path.to_string_lossy().to_string())
+        .build())
+}
+
+/// Write `value` to disk, returning the reference that replaces it. The members of an object are
+/// written separately and keep their keys, so that dependents still resolve the names they read.
+/// `serialized` is `value` already serialized, if available.
+pub fn spill_output(directory: &Path, operation_id: OperationId, value: &RkyvSerializedValue, serialized: Option<&[u8]>) -> anyhow::Result<RkyvSerializedValue> {
+    match value {
+        RkyvSerializedValue::Object(members) => {
+            let mut builder = RkyvObjectBuilder::new();
+            for (key, member) in members {
+                builder = builder.insert_value(key, spill_bytes(directory, operation_id, &serialize_to_vec(member))?);
+            }
+            Ok(builder.build())
+        }
+        _ => match serialized {
+            Some(bytes) => spill_bytes(directory, operation_id, bytes),
+            None => spill_bytes(directory, operation_id, &serialize_to_vec(value)),
+        }
+    }
+}
+
+/// The file holding a spilled value, if `value` is a reference to one
+pub fn spilled_output_path(value: &RkyvSerializedValue) -> Option<PathBuf> {
+    let RkyvSerializedValue::Object(members) = value else { return None };
+    if members.len() != 1 {
+        return None;
+    }
+    match members.get(SPILLED_OUTPUT_KEY) {
+        Some(RkyvSerializedValue::String(path)) => Some(PathBuf::from(path)),
+        _ => None,
+    }
+}
+
+/// Read back the value a spill reference points to
+pub fn load_spilled_output(reference: &RkyvSerializedValue) -> anyhow::Result<RkyvSerializedValue> {
+    let path = spilled_output_path(reference)
+        .ok_or_else(|| anyhow::anyhow!("The value is not a reference to a spilled output"))?;
+    read_spilled_value(&path)
+}
+
+/// Write `value` to the file at `path`, to be read back with `read_spilled_value`
+pub fn write_spilled_value(path: &Path, value: &RkyvSerializedValue) -> anyhow::Result<()> {
+    std::fs::write(path, serialize_to_vec(value))?;
+    Ok(())
+}
+
+/// Read back a value written to disk by `spill_output` or `write_spilled_value`
+pub fn read_spilled_value(path: &Path) -> anyhow::Result<RkyvSerializedValue> {
+    let mut bytes = rkyv::AlignedVec::new();
+    bytes.extend_from_slice(&std::fs::read(path)?);
+    Ok(deserialize_from_buf(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spill_output_round_trip() -> anyhow::Result<()> {
+        let directory = std::env::temp_dir().join(format!("chidori-spill-{}", Uuid::now_v7()));
+        let operation_id = Uuid::now_v7();
+
+        let value = RkyvSerializedValue::String("a".repeat(1000));
+        let reference = spill_output(&directory, operation_id, &value, None)?;
+        assert!(spilled_output_path(&reference).unwrap().starts_with(&directory));
+        assert_eq!(load_spilled_output(&reference)?, value);
+
+        // Members of an object are each replaced by a reference under their own key
+        let object = RkyvObjectBuilder::new().insert_value("x", value.clone()).build();
+        let RkyvSerializedValue::Object(members) = spill_output(&directory, operation_id, &object, None)? else {
+            panic!("Expected an object of references");
+        };
+        assert_eq!(load_spilled_output(&members["x"])?, value);
+
+        assert!(load_spilled_output(&RkyvSerializedValue::Number(1)).is_err());
+        std::fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+}
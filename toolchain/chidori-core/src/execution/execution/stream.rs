@@ -0,0 +1,292 @@
+//! Large collections are streamed between cells rather than held in state. A producing cell writes
+//! items to a `StreamWriter`, which spills them to disk in chunks, and state holds only a
+//! `StreamPointer` to the stream. Consuming cells read it back a chunk at a time with a `StreamReader`.
+//!
+//! Each stream is a directory named by its id, holding its chunks and a manifest of them, so that
+//! pointers in restored state still resolve in a later process. A stream is removed once no
+//! retained state points to it, see `ExecutionGraph::compact`.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::execution::execution::spill::{read_spilled_value, write_spilled_value};
+use crate::execution::primitives::serialized_value::RkyvSerializedValue;
+
+/// Number of items written to each chunk of a stream
+pub const STREAM_CHUNK_ITEMS: usize = 1000;
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Directory the streams are written to, one directory per stream
+pub fn stream_directory() -> PathBuf {
+    std::env::temp_dir().join("chidori").join("streams")
+}
+
+/// The chunks of a stream and how they have been used
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StreamManifest {
+    /// File names of the chunks, in the directory of the stream
+    pub chunks: Vec<String>,
+    pub item_count: usize,
+    /// Number of chunks read back, across all readers of the stream in this process
+    #[serde(skip)]
+    pub chunks_read: usize,
+    /// Most items of the stream held in memory at once, by its writer or by one of its readers
+    pub peak_resident_items: usize,
+}
+
+/// Manifests of the streams used by this process, read from disk on first use
+static STREAMS: Lazy<DashMap<String, StreamManifest>> = Lazy::new(DashMap::new);
+
+/// The directory of the stream `id`. Ids arrive from cells, only uuids are accepted so that they
+/// cannot name a path outside of `stream_directory()`.
+fn stream_path(id: &str) -> anyhow::Result<PathBuf> {
+    let id = Uuid::parse_str(id).map_err(|_| anyhow::anyhow!("{:?} is not a stream id", id))?;
+    Ok(stream_directory().join(id.to_string()))
+}
+
+fn write_manifest(id: &str, manifest: &StreamManifest) -> anyhow::Result<()> {
+    std::fs::write(stream_path(id)?.join(MANIFEST_FILE), serde_json::to_vec(manifest)?)?;
+    Ok(())
+}
+
+fn open_manifest(id: &str) -> anyhow::Result<dashmap::mapref::one::RefMut<'static, String, StreamManifest>> {
+    if let Some(manifest) = STREAMS.get_mut(id) {
+        return Ok(manifest);
+    }
+    let contents = std::fs::read(stream_path(id)?.join(MANIFEST_FILE))
+        .map_err(|_| anyhow::anyhow!("No stream with id {}", id))?;
+    let manifest: StreamManifest = serde_json::from_slice(&contents)?;
+    Ok(STREAMS.entry(id.to_string()).or_insert(manifest))
+}
+
+/// The manifest of the stream `id`, if it was written by this or an earlier process
+pub fn stream_manifest(id: &str) -> Option<StreamManifest> {
+    open_manifest(id).ok().map(|manifest| manifest.clone())
+}
+
+fn record_resident_items(id: &str, items: usize) {
+    if let Some(mut manifest) = STREAMS.get_mut(id) {
+        manifest.peak_resident_items = manifest.peak_resident_items.max(items);
+    }
+}
+
+/// Remove the chunks and manifest of the stream `id`
+pub fn remove_stream(id: &str) -> anyhow::Result<()> {
+    STREAMS.remove(id);
+    let path = stream_path(id)?;
+    if path.exists() {
+        std::fs::remove_dir_all(path)?;
+    }
+    Ok(())
+}
+
+/// Writes the items of a stream to disk, `STREAM_CHUNK_ITEMS` at a time
+#[derive(Debug)]
+pub struct StreamWriter {
+    id: String,
+    buffer: Vec<RkyvSerializedValue>,
+    finished: bool,
+}
+
+impl StreamWriter {
+    pub fn new() -> Self {
+        let id = Uuid::now_v7().to_string();
+        STREAMS.insert(id.clone(), StreamManifest::default());
+        Self { id, buffer: vec![], finished: false }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn write(&mut self, item: RkyvSerializedValue) -> anyhow::Result<()> {
+        if self.finished {
+            anyhow::bail!("Stream {} has already been finished", self.id);
+        }
+        self.buffer.push(item);
+        record_resident_items(&self.id, self.buffer.len());
+        if self.buffer.len() >= STREAM_CHUNK_ITEMS {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        let items = std::mem::take(&mut self.buffer);
+        let mut manifest = STREAMS.get_mut(&self.id)
+            .ok_or_else(|| anyhow::anyhow!("Stream {} is not open", self.id))?;
+        let directory = stream_path(&self.id)?;
+        std::fs::create_dir_all(&directory)?;
+        if !items.is_empty() {
+            let name = format!("{}.rkyv", manifest.chunks.len());
+            manifest.item_count += items.len();
+            write_spilled_value(&directory.join(&name), &RkyvSerializedValue::Array(items))?;
+            manifest.chunks.push(name);
+        }
+        write_manifest(&self.id, &manifest)
+    }
+
+    /// Write out the remaining items, returning the pointer that stands in for the stream in state.
+    /// Finishing a stream again returns the same pointer.
+    pub fn finish(&mut self) -> anyhow::Result<RkyvSerializedValue> {
+        if !self.finished {
+            self.flush()?;
+            self.finished = true;
+        }
+        Ok(RkyvSerializedValue::StreamPointer(self.id.clone()))
+    }
+}
+
+/// Read chunk `index` of the stream `id`, or None past its last chunk
+pub fn read_stream_chunk(id: &str, index: usize) -> anyhow::Result<Option<Vec<RkyvSerializedValue>>> {
+    let path = {
+        let manifest = open_manifest(id)?;
+        match manifest.chunks.get(index) {
+            Some(name) => stream_path(id)?.join(name),
+            None => return Ok(None),
+        }
+    };
+    let RkyvSerializedValue::Array(items) = read_spilled_value(&path)? else {
+        anyhow::bail!("Chunk {} of stream {} is malformed", index, id);
+    };
+    if let Some(mut manifest) = STREAMS.get_mut(id) {
+        manifest.chunks_read += 1;
+    }
+    record_resident_items(id, items.len());
+    Ok(Some(items))
+}
+
+/// Iterates over the items of a stream, holding one chunk in memory at a time
+#[derive(Debug)]
+pub struct StreamReader {
+    id: String,
+    next_chunk: usize,
+    items: std::vec::IntoIter<RkyvSerializedValue>,
+}
+
+impl StreamReader {
+    pub fn new(id: &str) -> Self {
+        Self { id: id.to_string(), next_chunk: 0, items: vec![].into_iter() }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl Iterator for StreamReader {
+    type Item = anyhow::Result<RkyvSerializedValue>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.items.next() {
+                return Some(Ok(item));
+            }
+            match read_stream_chunk(&self.id, self.next_chunk) {
+                Ok(Some(items)) => {
+                    self.next_chunk += 1;
+                    self.items = items.into_iter();
+                }
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Add the ids of the streams `value` points to to `ids`
+pub fn collect_stream_ids(value: &RkyvSerializedValue, ids: &mut HashSet<String>) {
+    match value {
+        RkyvSerializedValue::StreamPointer(id) => {
+            ids.insert(id.clone());
+        }
+        RkyvSerializedValue::Array(items) => items.iter().for_each(|item| collect_stream_ids(item, ids)),
+        RkyvSerializedValue::Set(items) => items.iter().for_each(|item| collect_stream_ids(item, ids)),
+        RkyvSerializedValue::Object(members) => members.values().for_each(|member| collect_stream_ids(member, ids)),
+        _ => {}
+    }
+}
+
+fn contains_stream(value: &RkyvSerializedValue) -> bool {
+    match value {
+        RkyvSerializedValue::StreamPointer(_) => true,
+        RkyvSerializedValue::Array(items) => items.iter().any(contains_stream),
+        RkyvSerializedValue::Set(items) => items.iter().any(contains_stream),
+        RkyvSerializedValue::Object(members) => members.values().any(contains_stream),
+        _ => false,
+    }
+}
+
+/// The name of the first input in the payload of an operation that holds a stream, for cells that
+/// need their inputs in full
+pub fn find_stream_input(payload: &RkyvSerializedValue) -> Option<String> {
+    let RkyvSerializedValue::Object(payload) = payload else { return None };
+    let mut names: Vec<&String> = ["globals", "kwargs"].iter()
+        .filter_map(|key| match payload.get(*key) {
+            Some(RkyvSerializedValue::Object(inputs)) => Some(inputs),
+            _ => None,
+        })
+        .flat_map(|inputs| inputs.iter().filter(|(_, value)| contains_stream(value)).map(|(name, _)| name))
+        .collect();
+    names.sort();
+    names.first().map(|name| name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::primitives::serialized_value::RkyvObjectBuilder;
+
+    #[test]
+    fn test_stream_round_trip_in_chunks() -> anyhow::Result<()> {
+        let mut writer = StreamWriter::new();
+        for i in 0..2500 {
+            writer.write(RkyvSerializedValue::Number(i))?;
+        }
+        let pointer = writer.finish()?;
+        assert_eq!(pointer, RkyvSerializedValue::StreamPointer(writer.id().to_string()));
+        assert!(writer.write(RkyvSerializedValue::Null).is_err());
+
+        let items = StreamReader::new(writer.id()).collect::<anyhow::Result<Vec<_>>>()?;
+        assert_eq!(items, (0..2500).map(RkyvSerializedValue::Number).collect::<Vec<_>>());
+        let manifest = stream_manifest(writer.id()).unwrap();
+        assert_eq!(manifest.chunks.len(), 3);
+        assert_eq!(manifest.item_count, 2500);
+        assert_eq!(manifest.chunks_read, 3);
+        assert_eq!(manifest.peak_resident_items, STREAM_CHUNK_ITEMS);
+
+        let payload = RkyvObjectBuilder::new()
+            .insert_value("globals", RkyvObjectBuilder::new()
+                .insert_number("count", 1)
+                .insert_value("records", pointer)
+                .build())
+            .build();
+        assert_eq!(find_stream_input(&payload), Some("records".to_string()));
+        remove_stream(writer.id())?;
+        assert!(stream_manifest(writer.id()).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_is_read_from_its_manifest_on_disk() -> anyhow::Result<()> {
+        let mut writer = StreamWriter::new();
+        for i in 0..1500 {
+            writer.write(RkyvSerializedValue::Number(i))?;
+        }
+        writer.finish()?;
+        // As in a later process, which has not opened the stream
+        STREAMS.remove(writer.id());
+
+        let items = StreamReader::new(writer.id()).collect::<anyhow::Result<Vec<_>>>()?;
+        assert_eq!(items.len(), 1500);
+        assert_eq!(stream_manifest(writer.id()).unwrap().item_count, 1500);
+        remove_stream(writer.id())?;
+        assert!(read_stream_chunk(writer.id(), 0).is_err());
+        assert!(read_stream_chunk("../spill", 0).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+//! The reasoning of an agent, recorded step by step as a cell is evaluated. Prompts record the
+//! tool calls they make and the answer they arrive at, code cells record steps with `ch.trace_step`.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::execution::primitives::identifiers::OperationId;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentTraceStepKind {
+    Thought,
+    ToolCall,
+    ToolResult,
+    FinalAnswer,
+}
+
+impl FromStr for AgentTraceStepKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "thought" => Ok(AgentTraceStepKind::Thought),
+            "tool_call" => Ok(AgentTraceStepKind::ToolCall),
+            "tool_result" => Ok(AgentTraceStepKind::ToolResult),
+            "final_answer" => Ok(AgentTraceStepKind::FinalAnswer),
+            _ => anyhow::bail!("Unknown kind of trace step {}, expected one of thought, tool_call, tool_result or final_answer", s),
+        }
+    }
+}
+
+impl fmt::Display for AgentTraceStepKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AgentTraceStepKind::Thought => "thought",
+            AgentTraceStepKind::ToolCall => "tool_call",
+            AgentTraceStepKind::ToolResult => "tool_result",
+            AgentTraceStepKind::FinalAnswer => "final_answer",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgentTraceStep {
+    pub kind: AgentTraceStepKind,
+    pub content: String,
+    /// Milliseconds since the unix epoch at which the step was recorded
+    pub timestamp: u64,
+}
+
+/// Receives each step as it is recorded, along with the operation recording it
+pub type AgentTraceSink = dyn Fn(OperationId, &AgentTraceStep) + Send + Sync;
+
+/// The steps recorded during one evaluation of an operation, in the order they were recorded
+#[derive(Clone)]
+pub struct AgentTrace {
+    operation_id: OperationId,
+    steps: Arc<Mutex<Vec<AgentTraceStep>>>,
+    sink: Option<Arc<AgentTraceSink>>,
+}
+
+impl fmt::Debug for AgentTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AgentTrace")
+            .field("operation_id", &self.operation_id)
+            .field("steps", &self.steps.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl AgentTrace {
+    pub fn new(operation_id: OperationId, sink: Option<Arc<AgentTraceSink>>) -> Self {
+        Self { operation_id, steps: Default::default(), sink }
+    }
+
+    pub fn record(&self, kind: AgentTraceStepKind, content: impl Into<String>) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        let step = AgentTraceStep { kind, content: content.into(), timestamp };
+        if let Some(sink) = &self.sink {
+            sink(self.operation_id, &step);
+        }
+        self.steps.lock().unwrap().push(step);
+    }
+
+    pub fn steps(&self) -> Vec<AgentTraceStep> {
+        self.steps.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_recorded_steps_are_forwarded_in_order() {
+        let forwarded = Arc::new(Mutex::new(vec![]));
+        let sink_forwarded = forwarded.clone();
+        let operation_id = Uuid::now_v7();
+        let trace = AgentTrace::new(operation_id, Some(Arc::new(move |id, step: &AgentTraceStep| {
+            sink_forwarded.lock().unwrap().push((id, step.kind.clone()));
+        })));
+        trace.record(AgentTraceStepKind::Thought, "Look up the weather");
+        trace.record(AgentTraceStepKind::FinalAnswer, "Sunny");
+
+        let steps = trace.steps();
+        assert_eq!(steps.iter().map(|s| s.kind.clone()).collect::<Vec<_>>(), vec![AgentTraceStepKind::Thought, AgentTraceStepKind::FinalAnswer]);
+        assert!(steps[0].timestamp <= steps[1].timestamp);
+        assert_eq!(*forwarded.lock().unwrap(), vec![(operation_id, AgentTraceStepKind::Thought), (operation_id, AgentTraceStepKind::FinalAnswer)]);
+        assert_eq!("tool_call".parse::<AgentTraceStepKind>().unwrap(), AgentTraceStepKind::ToolCall);
+        assert!("musing".parse::<AgentTraceStepKind>().is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::{BTreeSet, HashMap};
+use anyhow::anyhow;
+use arrow2::array::{Array, BooleanArray, Float32Array, Float64Array, Int32Array, Int64Array, NullArray, StructArray, Utf8Array};
+use arrow2::bitmap::Bitmap;
+use arrow2::datatypes::{DataType, Field};
+use crate::execution::primitives::serialized_value::RkyvSerializedValue;
+
+impl RkyvSerializedValue {
+    /// Convert an array of homogeneous values into a columnar arrow array. Numbers become `Int64`,
+    /// floats `Float64`, strings `LargeUtf8`, booleans `Boolean` and objects a `Struct` with one
+    /// field per key, sorted by name. `Null` elements are carried as nulls in the validity bitmap.
+    pub fn to_arrow_array(&self) -> anyhow::Result<Box<dyn Array>> {
+        let RkyvSerializedValue::Array(values) = self else {
+            return Err(anyhow!("Only arrays can be converted to arrow arrays, got {:?}", self));
+        };
+        let Some(first) = values.iter().find(|v| !matches!(v, RkyvSerializedValue::Null)) else {
+            return Ok(Box::new(NullArray::new(DataType::Null, values.len())));
+        };
+        let heterogeneous = || anyhow!("Arrow arrays must be homogeneous, found {:?} alongside {:?}", first, values);
+
+        match first {
+            RkyvSerializedValue::Number(_) => {
+                let column = values.iter().map(|v| match v {
+                    RkyvSerializedValue::Number(n) => Ok(Some(*n as i64)),
+                    RkyvSerializedValue::Null => Ok(None),
+                    _ => Err(heterogeneous()),
+                }).collect::<anyhow::Result<Vec<_>>>()?;
+                Ok(Box::new(Int64Array::from(column)))
+            }
+            RkyvSerializedValue::Float(_) => {
+                let column = values.iter().map(|v| match v {
+                    RkyvSerializedValue::Float(f) => Ok(Some(*f as f64)),
+                    RkyvSerializedValue::Null => Ok(None),
+                    _ => Err(heterogeneous()),
+                }).collect::<anyhow::Result<Vec<_>>>()?;
+                Ok(Box::new(Float64Array::from(column)))
+            }
+            RkyvSerializedValue::String(_) => {
+                let column = values.iter().map(|v| match v {
+                    RkyvSerializedValue::String(s) => Ok(Some(s.as_str())),
+                    RkyvSerializedValue::Null => Ok(None),
+                    _ => Err(heterogeneous()),
+                }).collect::<anyhow::Result<Vec<_>>>()?;
+                Ok(Box::new(Utf8Array::<i64>::from(column)))
+            }
+            RkyvSerializedValue::Boolean(_) => {
+                let column = values.iter().map(|v| match v {
+                    RkyvSerializedValue::Boolean(b) => Ok(Some(*b)),
+                    RkyvSerializedValue::Null => Ok(None),
+                    _ => Err(heterogeneous()),
+                }).collect::<anyhow::Result<Vec<_>>>()?;
+                Ok(Box::new(BooleanArray::from(column)))
+            }
+            RkyvSerializedValue::Object(_) => {
+                let mut keys = BTreeSet::new();
+                for v in values {
+                    match v {
+                        RkyvSerializedValue::Object(o) => keys.extend(o.keys()),
+                        RkyvSerializedValue::Null => {}
+                        _ => return Err(heterogeneous()),
+                    }
+                }
+                let mut fields = vec![];
+                let mut columns = vec![];
+                for key in keys {
+                    let column = RkyvSerializedValue::Array(values.iter().map(|v| match v {
+                        RkyvSerializedValue::Object(o) => o.get(key).cloned().unwrap_or(RkyvSerializedValue::Null),
+                        _ => RkyvSerializedValue::Null,
+                    }).collect()).to_arrow_array()?;
+                    fields.push(Field::new(key.clone(), column.data_type().clone(), true));
+                    columns.push(column);
+                }
+                let validity = values.iter()
+                    .map(|v| !matches!(v, RkyvSerializedValue::Null))
+                    .collect::<Bitmap>();
+                Ok(Box::new(StructArray::try_new(DataType::Struct(fields), columns, Some(validity))?))
+            }
+            v => Err(anyhow!("Values of this type cannot be converted to arrow arrays: {:?}", v)),
+        }
+    }
+
+    /// Convert an arrow array back into an array of values. Integers are narrowed to `Number` and
+    /// floats to `Float`. Nulls, and elements of unsupported data types, become `Null`.
+    pub fn from_arrow_array(arr: &dyn Array) -> RkyvSerializedValue {
+        fn column<A: 'static, T>(arr: &dyn Array, values: impl Fn(&A) -> Vec<Option<T>>, f: impl Fn(T) -> RkyvSerializedValue) -> Vec<RkyvSerializedValue> {
+            let arr = arr.as_any().downcast_ref::<A>().expect("Array matches its data type");
+            values(arr).into_iter().map(|v| v.map_or(RkyvSerializedValue::Null, &f)).collect()
+        }
+
+        let values = match arr.data_type() {
+            DataType::Int64 => column(arr, |a: &Int64Array| a.iter().map(|v| v.copied()).collect(), |v| RkyvSerializedValue::Number(v as i32)),
+            DataType::Int32 => column(arr, |a: &Int32Array| a.iter().map(|v| v.copied()).collect(), RkyvSerializedValue::Number),
+            DataType::Float64 => column(arr, |a: &Float64Array| a.iter().map(|v| v.copied()).collect(), |v| RkyvSerializedValue::Float(v as f32)),
+            DataType::Float32 => column(arr, |a: &Float32Array| a.iter().map(|v| v.copied()).collect(), RkyvSerializedValue::Float),
+            DataType::LargeUtf8 => column(arr, |a: &Utf8Array<i64>| a.iter().map(|v| v.map(str::to_string)).collect(), RkyvSerializedValue::String),
+            DataType::Utf8 => column(arr, |a: &Utf8Array<i32>| a.iter().map(|v| v.map(str::to_string)).collect(), RkyvSerializedValue::String),
+            DataType::Boolean => column(arr, |a: &BooleanArray| a.iter().collect(), RkyvSerializedValue::Boolean),
+            DataType::Struct(_) => {
+                let arr = arr.as_any().downcast_ref::<StructArray>().expect("Array matches its data type");
+                let columns: Vec<(&String, Vec<RkyvSerializedValue>)> = arr.fields().iter()
+                    .zip(arr.values())
+                    .map(|(field, values)| match RkyvSerializedValue::from_arrow_array(values.as_ref()) {
+                        RkyvSerializedValue::Array(values) => (&field.name, values),
+                        _ => unreachable!("Arrow arrays always convert to arrays"),
+                    })
+                    .collect();
+                (0..arr.len()).map(|i| {
+                    if arr.is_null(i) {
+                        return RkyvSerializedValue::Null;
+                    }
+                    RkyvSerializedValue::Object(columns.iter()
+                        .map(|(name, values)| ((*name).clone(), values[i].clone()))
+                        .collect::<HashMap<_, _>>())
+                }).collect()
+            }
+            _ => vec![RkyvSerializedValue::Null; arr.len()],
+        };
+        RkyvSerializedValue::Array(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::primitives::serialized_value::RkyvObjectBuilder;
+
+    fn round_trip(value: RkyvSerializedValue, data_type: DataType) {
+        let arr = value.to_arrow_array().unwrap();
+        assert_eq!(arr.data_type(), &data_type);
+        assert_eq!(RkyvSerializedValue::from_arrow_array(arr.as_ref()), value);
+    }
+
+    #[test]
+    fn test_int64_round_trip() {
+        round_trip(RkyvSerializedValue::Array(vec![
+            RkyvSerializedValue::Number(1),
+            RkyvSerializedValue::Null,
+            RkyvSerializedValue::Number(-3),
+        ]), DataType::Int64);
+    }
+
+    #[test]
+    fn test_float64_round_trip() {
+        round_trip(RkyvSerializedValue::Array(vec![
+            RkyvSerializedValue::Float(1.5),
+            RkyvSerializedValue::Float(-0.25),
+        ]), DataType::Float64);
+    }
+
+    #[test]
+    fn test_large_utf8_round_trip() {
+        round_trip(RkyvSerializedValue::Array(vec![
+            RkyvSerializedValue::String("a".to_string()),
+            RkyvSerializedValue::Null,
+            RkyvSerializedValue::String("".to_string()),
+        ]), DataType::LargeUtf8);
+    }
+
+    #[test]
+    fn test_boolean_round_trip() {
+        round_trip(RkyvSerializedValue::Array(vec![
+            RkyvSerializedValue::Boolean(true),
+            RkyvSerializedValue::Boolean(false),
+        ]), DataType::Boolean);
+    }
+
+    #[test]
+    fn test_struct_round_trip() {
+        let value = RkyvSerializedValue::Array(vec![
+            RkyvObjectBuilder::new()
+                .insert_number("age", 36)
+                .insert_string("name", "Ada".to_string())
+                .build(),
+            RkyvObjectBuilder::new()
+                .insert_value("age", RkyvSerializedValue::Null)
+                .insert_string("name", "Alan".to_string())
+                .build(),
+        ]);
+        round_trip(value, DataType::Struct(vec![
+            Field::new("age", DataType::Int64, true),
+            Field::new("name", DataType::LargeUtf8, true),
+        ]));
+    }
+
+    #[test]
+    fn test_heterogeneous_arrays_are_rejected() {
+        let value = RkyvSerializedValue::Array(vec![
+            RkyvSerializedValue::Number(1),
+            RkyvSerializedValue::String("1".to_string()),
+        ]);
+        assert!(value.to_arrow_array().is_err());
+        assert!(RkyvSerializedValue::Number(1).to_arrow_array().is_err());
+    }
+}
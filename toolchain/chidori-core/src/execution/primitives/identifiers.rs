@@ -2,6 +2,9 @@ use uuid::Uuid;
 
 pub type OperationId = Uuid;
 
+/// Identifies one run of a runtime instance, see `ChidoriRuntimeInstance::run_id`
+pub type RunId = Uuid;
+
 // TODO: we will want to intern these strings for performance reasons
 #[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub enum DependencyReference {
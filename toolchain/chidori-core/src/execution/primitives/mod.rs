@@ -1,3 +1,8 @@
 pub mod identifiers;
 pub mod operation;
+pub mod agent_trace;
 pub mod serialized_value;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "arrow")]
+pub mod table;
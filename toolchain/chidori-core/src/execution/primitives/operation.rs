@@ -6,22 +6,60 @@ use std::fmt;
 use std::future::Future;
 use std::ops::{Deref, DerefMut};
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 use std::sync::mpsc::{Receiver, Sender};
+use std::time::Duration;
 use tokio::sync::oneshot;
 use futures_util::FutureExt;
 use tracing::{Level, span};
 use uuid::Uuid;
 use crate::cells::{CellTypes, CodeCell, SupportedLanguage, TextRange};
+use crate::cells::webhook_cell::WebMiddleware;
 use crate::execution::execution::execution_graph::ExecutionNodeId;
 use crate::execution::execution::execution_state::{ExecutionStateErrors, OperationInputs};
 use crate::execution::execution::ExecutionState;
 use crate::execution::primitives::identifiers::OperationId;
+use crate::execution::primitives::agent_trace::AgentTraceStep;
+use crate::library::std::ai::llm::ModelResponseMetadata;
 // args, kwargs, locals and their configurations
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum InputType {
     String,
     Function,
+    Number,
+    Float,
+    Boolean,
+    Array,
+    Object,
+}
+
+impl InputType {
+    /// The type of an input annotated with a python type, e.g. `x: int`
+    pub fn from_python_annotation(annotation: &str) -> Option<InputType> {
+        match annotation {
+            "str" => Some(InputType::String),
+            "int" => Some(InputType::Number),
+            "float" => Some(InputType::Float),
+            "bool" => Some(InputType::Boolean),
+            "list" | "tuple" => Some(InputType::Array),
+            "dict" => Some(InputType::Object),
+            _ => None,
+        }
+    }
+
+    /// The type of an input described by a JSON schema `type`, e.g. `integer`
+    pub fn from_json_schema_type(schema_type: &str) -> Option<InputType> {
+        match schema_type {
+            "string" => Some(InputType::String),
+            "integer" => Some(InputType::Number),
+            "number" => Some(InputType::Float),
+            "boolean" => Some(InputType::Boolean),
+            "array" => Some(InputType::Array),
+            "object" => Some(InputType::Object),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -34,16 +72,27 @@ pub struct InputItemConfiguration {
 #[derive(Debug, Clone)]
 pub struct InputSignature {
     pub args: HashMap<String, InputItemConfiguration>,
+    /// Names of `args` in the order they are declared, when the declaration records one
+    pub arg_order: Vec<String>,
     pub kwargs: HashMap<String, InputItemConfiguration>,
     pub globals: HashMap<String, InputItemConfiguration>,
+    /// Names of the cells this operation imports as modules. It depends on their source rather
+    /// than on a value they output, and is evaluated again when they are.
+    pub imported_cells: Vec<String>,
+    /// Names of the memory stores this operation retrieves from. It is evaluated again after the
+    /// operations inserting into them are.
+    pub memory_reads: Vec<String>,
 }
 
 impl InputSignature {
     pub fn new() -> Self {
         Self {
             args: HashMap::new(),
+            arg_order: vec![],
             kwargs: HashMap::new(),
             globals: HashMap::new(),
+            imported_cells: vec![],
+            memory_reads: vec![],
         }
     }
 
@@ -54,13 +103,38 @@ impl InputSignature {
         }
         Self {
             args: args_map,
+            arg_order: (0..args.len()).map(|i| format!("{}", i)).collect(),
             kwargs: HashMap::new(),
             globals: HashMap::new(),
+            imported_cells: vec![],
+            memory_reads: vec![],
         }
     }
 
     pub fn is_empty(&self) -> bool {
-        self.args.is_empty() && self.kwargs.is_empty() && self.globals.is_empty()
+        self.args.is_empty() && self.kwargs.is_empty() && self.globals.is_empty() && self.imported_cells.is_empty() && self.memory_reads.is_empty()
+    }
+
+    fn required_globals(&self) -> impl Iterator<Item = &String> {
+        self.globals.iter()
+            .filter(|(_, config)| config.default.is_none())
+            .map(|(key, _)| key)
+    }
+
+    /// True if every required global of this signature is also a global of `other`
+    pub fn is_subset_of(&self, other: &InputSignature) -> bool {
+        self.required_globals().all(|key| other.globals.contains_key(key))
+    }
+
+    /// Names of the required globals of this signature that are not provided as either
+    /// a value or a function by `available`, sorted.
+    pub fn missing_inputs(&self, available: &OutputSignature) -> Vec<String> {
+        let mut missing: Vec<String> = self.required_globals()
+            .filter(|key| !available.globals.contains_key(*key) && !available.functions.contains_key(*key))
+            .cloned()
+            .collect();
+        missing.sort();
+        missing
     }
 
     #[tracing::instrument]
@@ -153,6 +227,8 @@ pub enum OutputItemConfiguration {
         emit_event: Vec<String>,
         trigger_on: Vec<String>,
     },
+    /// Implemented in Rust by the embedder, see `ExecutionState::native_functions`
+    NativeFunction,
     #[default]
     Value
 }
@@ -168,6 +244,8 @@ pub struct OutputSignatureFunction {
 pub struct OutputSignature {
     pub globals: HashMap<String, OutputItemConfiguration>,
     pub functions: HashMap<String, OutputItemConfiguration>,
+    /// Names of the memory stores this operation inserts into
+    pub memory_writes: Vec<String>,
 }
 
 impl OutputSignature {
@@ -175,6 +253,7 @@ impl OutputSignature {
         Self {
             globals: HashMap::new(),
             functions: HashMap::new(),
+            memory_writes: vec![],
         }
     }
 }
@@ -196,12 +275,16 @@ impl Signature {
             trigger_on: TriggerConfiguration::OnChange,
             input_signature: InputSignature {
                 args: HashMap::new(),
+                arg_order: vec![],
                 kwargs: HashMap::new(),
                 globals: HashMap::new(),
+                imported_cells: vec![],
+                memory_reads: vec![],
             },
             output_signature: OutputSignature {
                 globals: HashMap::new(),
                 functions: HashMap::new(),
+                memory_writes: vec![],
             },
         }
     }
@@ -260,7 +343,26 @@ pub struct OperationFnOutput {
     pub execution_state: Option<ExecutionState>,
     pub output: Result<RkyvSerializedValue, ExecutionStateErrors>,
     pub stdout: Vec<String>,
-    pub stderr: Vec<String>
+    pub stderr: Vec<String>,
+    /// Peak memory allocated during evaluation, for runtimes able to measure it
+    pub peak_memory_bytes: Option<u64>,
+    /// Reasoning steps recorded during evaluation, see `AgentTrace`
+    pub agent_trace: Vec<AgentTraceStep>,
+    /// What the model provider reported about its response, for operations that query a model
+    pub response_metadata: Option<ModelResponseMetadata>,
+    /// The arguments the operation was evaluated with, once its dependencies were resolved. Recorded
+    /// so that an evaluation can be reproduced exactly, see `ExecutionGraph::operation_history`.
+    pub input: Option<RecordedInput>,
+}
+
+/// The arguments an operation was evaluated with, along with the operations that produced them,
+/// so that each argument is redacted by the rules of the cell it came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedInput {
+    pub value: RkyvSerializedValue,
+    /// The operation each argument was taken from, by the section of the arguments holding it
+    /// (`args`, `kwargs` or `globals`) and then its name
+    pub producers: HashMap<String, HashMap<String, OperationId>>,
 }
 
 impl OperationFnOutput {
@@ -270,7 +372,11 @@ impl OperationFnOutput {
             execution_state: None,
             output: Ok(value),
             stdout: Vec::new(),
-            stderr: Vec::new()
+            stderr: Vec::new(),
+            peak_memory_bytes: None,
+            agent_trace: vec![],
+            response_metadata: None,
+            input: None,
         }
     }
 }
@@ -301,6 +407,99 @@ pub type OperationFn = dyn Fn(
 
 
 
+/// How long to wait between the attempts of a retried operation
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackoffStrategy {
+    /// Retry immediately
+    None,
+    Fixed(Duration),
+    /// Wait `initial` after the first failure, growing by `multiplier` with every further failure up to `max`
+    Exponential { initial: Duration, multiplier: f64, max: Duration },
+}
+
+impl BackoffStrategy {
+    /// The wait after the given failed attempt, counting from 1
+    pub fn delay(&self, attempt: u8) -> Duration {
+        match self {
+            BackoffStrategy::None => Duration::ZERO,
+            BackoffStrategy::Fixed(delay) => *delay,
+            BackoffStrategy::Exponential { initial, multiplier, max } => {
+                // Computed in seconds so that a delay overflowing a Duration is capped rather than panicking
+                let factor = multiplier.powi(attempt.saturating_sub(1) as i32);
+                let seconds = (initial.as_secs_f64() * factor).min(max.as_secs_f64());
+                Duration::try_from_secs_f64(seconds).unwrap_or(*max)
+            }
+        }
+    }
+
+    /// Exponential backoff must not shrink, nor grow by a multiplier that is not a number
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let BackoffStrategy::Exponential { multiplier, .. } = self {
+            if !multiplier.is_finite() || *multiplier < 1.0 {
+                anyhow::bail!("The backoff multiplier must be a finite number of at least 1, got {}", multiplier);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Re-execution of an operation that fails, see `OperationNode::with_retry`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Total number of executions, including the first
+    max_attempts: u8,
+    backoff: BackoffStrategy,
+}
+
+impl RetryPolicy {
+    /// Fails when the backoff is invalid, see `BackoffStrategy::validate`
+    pub fn new(max_attempts: u8, backoff: BackoffStrategy) -> anyhow::Result<Self> {
+        backoff.validate()?;
+        Ok(RetryPolicy { max_attempts, backoff })
+    }
+
+    pub fn max_attempts(&self) -> u8 {
+        self.max_attempts
+    }
+
+    pub fn backoff(&self) -> &BackoffStrategy {
+        &self.backoff
+    }
+}
+
+/// Wrap `operation` so that it is executed again while it fails, up to the attempts of `policy`.
+/// An execution fails when it returns an error or its output is one. Only the first attempt
+/// receives the async communication channel, which can't be shared across attempts.
+pub fn retry_operation(operation: Box<OperationFn>, policy: RetryPolicy) -> Box<OperationFn> {
+    let operation = Arc::new(Mutex::new(operation));
+    Box::new(move |state, payload, intermediate_output_channel_tx, async_communication_channel| {
+        let operation = operation.clone();
+        let policy = policy.clone();
+        let state = state.clone();
+        async move {
+            let mut async_communication_channel = async_communication_channel;
+            let mut attempt = 0;
+            loop {
+                attempt += 1;
+                let execution = {
+                    let operation = operation.lock().unwrap();
+                    operation(&state, payload.clone(), intermediate_output_channel_tx.clone(), async_communication_channel.take())
+                };
+                let result = execution.await;
+                let failed = match &result {
+                    Ok(output) => output.has_error || output.output.is_err(),
+                    Err(_) => true,
+                };
+                if !failed || attempt >= policy.max_attempts {
+                    return result;
+                }
+                warn!("Attempt {} of {} failed, retrying", attempt, policy.max_attempts);
+                tokio::time::sleep(policy.backoff.delay(attempt)).await;
+            }
+        }.boxed()
+    })
+}
+
 #[derive(Clone)]
 pub struct OperationNode {
     pub(crate) id: OperationId,
@@ -309,6 +508,10 @@ pub struct OperationNode {
     pub cell: CellTypes,
     /// Signature of the inputs and outputs of this node
     pub(crate) signature: Signature,
+    /// Re-execution of the operation when it fails, see `with_retry`
+    pub(crate) retry: Option<RetryPolicy>,
+    /// Run before each request served by a webhook cell, see `web_cell::add_middleware`
+    pub(crate) web_middleware: Vec<Arc<dyn WebMiddleware>>,
 }
 
 impl core::hash::Hash for OperationNode {
@@ -346,8 +549,17 @@ impl Default for OperationNode {
                 language: SupportedLanguage::PyO3,
                 source_code: "".to_string(),
                 function_invocation: None,
+                inspect_globals: false,
+                redact_output: false,
+                policy: Default::default(),
+                oom_limit_bytes: None,
+                always_run: false,
+                generate_types: false,
+                package_json: None,
             }, TextRange::default()),
             signature: Signature::new(),
+            retry: None,
+            web_middleware: vec![],
             // operation: Box::new(|_, x, _, _| async move { Ok(OperationFnOutput::with_value(x)) }.boxed()),
         }
     }
@@ -370,6 +582,12 @@ impl OperationNode {
         node
     }
 
+    /// Execute the operation again when it fails, as described by `policy`
+    pub fn with_retry(mut self, policy: RetryPolicy) -> OperationNode {
+        self.retry = Some(policy);
+        self
+    }
+
     #[tracing::instrument]
     pub(crate) fn execute(
         &self,
@@ -378,14 +596,17 @@ impl OperationNode {
         intermediate_output_channel_tx: Option<Sender<(ExecutionNodeId, RkyvSerializedValue)>>,
         async_communication_channel: Option<AsyncRPCCommunication>,
     ) -> Pin<Box<dyn Future<Output=anyhow::Result<OperationFnOutput>> + Send>> {
+        // The executable closure is only constructed once the operation is run, upserting a cell
+        // only analyzes its signatures. Failures to construct surface as an error of this execution.
+        let construction = tracing::trace_span!("construct_operation", operation_id = %self.id).entered();
         let closure = match &self.cell {
             CellTypes::Code(code_cell, _) => {
                 match code_cell.language {
                     SupportedLanguage::PyO3 => {
-                        crate::cells::code_cell::code_cell_exec_python(code_cell.clone())
+                        Ok(crate::cells::code_cell::code_cell_exec_python(code_cell.clone()))
                     }
                     SupportedLanguage::Deno => {
-                        crate::cells::code_cell::code_cell_exec_deno(code_cell.clone())
+                        Ok(crate::cells::code_cell::code_cell_exec_deno(code_cell.clone()))
                     }
                 }
             }
@@ -395,8 +616,38 @@ impl OperationNode {
             CellTypes::Prompt(llm_prompt_cell, _) => {
                 crate::cells::llm_prompt_cell::llm_prompt_cell_exec_chat_openai(llm_prompt_cell.clone())
             }
-            CellTypes::Template(crate::cells::TemplateCell {body, ..}, _) => {
-                crate::cells::template_cell::template_cell_exec(body.clone())
+            CellTypes::Template(crate::cells::TemplateCell {body, engine, ..}, _) => {
+                Ok(crate::cells::template_cell::template_cell_exec(body.clone(), *engine))
+            }
+            CellTypes::Webhook(webhook_cell, _) => {
+                crate::cells::webhook_cell::webhook_cell_exec(webhook_cell.clone(), self.web_middleware.clone())
+            }
+            CellTypes::Mcp(mcp_cell, _) => {
+                crate::cells::mcp_cell::mcp_cell_exec(mcp_cell.clone())
+            }
+            CellTypes::File(file_cell, _) => {
+                crate::cells::file_cell::file_cell_exec(file_cell.clone())
+            }
+            CellTypes::Extract(extract_cell, _) => {
+                crate::cells::extract_cell::extract_cell_exec(extract_cell.clone())
+            }
+            CellTypes::Memory(memory_cell, _) => {
+                Ok(crate::cells::memory_cell::memory_cell_exec(memory_cell.clone()))
+            }
+            CellTypes::Chunk(chunk_cell, _) => {
+                Ok(crate::cells::chunk_cell::chunk_cell_exec(chunk_cell.clone()))
+            }
+        };
+        drop(construction);
+        let closure = match closure {
+            Ok(closure) => match &self.retry {
+                Some(policy) => retry_operation(closure, policy.clone()),
+                None => closure,
+            },
+            Err(err) => {
+                let cell_name = self.cell.name().clone().unwrap_or_else(|| self.id.to_string());
+                let err = err.context(format!("Failed to construct operation for cell {}", cell_name));
+                return async move { Err(err) }.boxed();
             }
         };
 
@@ -427,6 +678,146 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_construction_error_surfaces_on_first_execution() -> anyhow::Result<()> {
+        let body = "---\nfn: broken_prompt\n---\n{{#user}}Hello {{/system}}";
+        let cell = crate::cells::LLMPromptCell::Chat {
+            backing_file_reference: None,
+            is_function_invocation: false,
+            configuration: Default::default(),
+            name: Some("broken".to_string()),
+            provider: crate::cells::SupportedModelProviders::OpenAI,
+            complete_body: body.to_string(),
+            req: "{{#user}}Hello {{/system}}".to_string(),
+        };
+
+        // Signature analysis succeeds, the operation isn't built until it runs
+        let node = crate::cells::llm_prompt_cell::llm_prompt_cell(Uuid::nil(), &cell, &TextRange::default())?;
+        let result = node.execute(&ExecutionState::new_with_random_id(), RkyvSerializedValue::Null, None, None).await;
+        let err = result.err().expect("construction of the operation should fail");
+        assert!(err.to_string().contains("Failed to construct operation for cell broken"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retry_flaky_operation() -> anyhow::Result<()> {
+        use std::sync::atomic::{AtomicU8, Ordering};
+        let calls = Arc::new(AtomicU8::new(0));
+        let flaky = |calls: Arc<AtomicU8>| -> Box<OperationFn> {
+            Box::new(move |_, _, _, _| {
+                let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                async move {
+                    if call < 3 {
+                        Err(anyhow::anyhow!("Flaky failure {}", call))
+                    } else {
+                        Ok(OperationFnOutput::with_value(RkyvSerializedValue::Number(call as i32)))
+                    }
+                }.boxed()
+            })
+        };
+        let policy = RetryPolicy::new(3, BackoffStrategy::Fixed(Duration::from_millis(1)))?;
+        let operation = retry_operation(flaky(calls.clone()), policy.clone());
+        let result = operation(&ExecutionState::new_with_random_id(), RkyvSerializedValue::Null, None, None).await?;
+        assert_eq!(result.output, Ok(RkyvSerializedValue::Number(3)));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // With fewer attempts the last failure is returned
+        let calls = Arc::new(AtomicU8::new(0));
+        let operation = retry_operation(flaky(calls.clone()), RetryPolicy::new(2, policy.backoff().clone())?);
+        let err = operation(&ExecutionState::new_with_random_id(), RkyvSerializedValue::Null, None, None).await.unwrap_err();
+        assert_eq!(err.to_string(), "Flaky failure 2");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_exponential_backoff_is_capped() {
+        let backoff = BackoffStrategy::Exponential {
+            initial: Duration::from_millis(100),
+            multiplier: 2.0,
+            max: Duration::from_millis(300),
+        };
+        assert_eq!(backoff.delay(1), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2), Duration::from_millis(200));
+        assert_eq!(backoff.delay(3), Duration::from_millis(300));
+        // Growth beyond what a Duration holds is capped as well
+        assert_eq!(backoff.delay(u8::MAX), Duration::from_millis(300));
+        assert_eq!(BackoffStrategy::None.delay(3), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_retry_rejects_invalid_multipliers() {
+        let exponential = |multiplier: f64| RetryPolicy::new(
+            3,
+            BackoffStrategy::Exponential { initial: Duration::from_millis(100), multiplier, max: Duration::from_secs(1) },
+        );
+        for multiplier in [f64::NAN, f64::INFINITY, 0.5, -2.0] {
+            assert!(exponential(multiplier).is_err(), "{}", multiplier);
+        }
+        let policy = exponential(1.0).unwrap();
+        assert_eq!(OperationNode::default().with_retry(policy.clone()).retry, Some(policy));
+    }
+
+    fn input_signature(required: &[&str], optional: &[&str]) -> InputSignature {
+        let mut signature = InputSignature::new();
+        for key in required {
+            signature.globals.insert(key.to_string(), InputItemConfiguration::default());
+        }
+        for key in optional {
+            signature.globals.insert(key.to_string(), InputItemConfiguration {
+                ty: None,
+                default: Some(RkyvSerializedValue::Null),
+            });
+        }
+        signature
+    }
+
+    fn output_signature(globals: &[&str], functions: &[&str]) -> OutputSignature {
+        let mut signature = OutputSignature::new();
+        for key in globals {
+            signature.globals.insert(key.to_string(), OutputItemConfiguration::Value);
+        }
+        for key in functions {
+            signature.functions.insert(key.to_string(), OutputItemConfiguration::default());
+        }
+        signature
+    }
+
+    #[test]
+    fn test_input_signature_subset_exact_match() {
+        let a = input_signature(&["x", "y"], &[]);
+        let b = input_signature(&["x", "y"], &[]);
+        assert!(a.is_subset_of(&b));
+        assert!(b.is_subset_of(&a));
+        assert!(a.missing_inputs(&output_signature(&["x"], &["y"])).is_empty());
+    }
+
+    #[test]
+    fn test_input_signature_subset_of_superset() {
+        let a = input_signature(&["x"], &["z"]);
+        let b = input_signature(&["x", "y"], &[]);
+        assert!(a.is_subset_of(&b));
+        assert!(a.missing_inputs(&output_signature(&["x", "y"], &[])).is_empty());
+    }
+
+    #[test]
+    fn test_input_signature_not_subset_of_subset() {
+        let a = input_signature(&["x", "y"], &[]);
+        let b = input_signature(&["x"], &[]);
+        assert!(!a.is_subset_of(&b));
+        assert_eq!(a.missing_inputs(&output_signature(&["x"], &[])), vec!["y".to_string()]);
+    }
+
+    #[test]
+    fn test_input_signature_intersection() {
+        let a = input_signature(&["x", "y"], &[]);
+        let b = input_signature(&["y", "z"], &[]);
+        assert!(!a.is_subset_of(&b));
+        assert!(!b.is_subset_of(&a));
+        assert_eq!(a.missing_inputs(&output_signature(&["y", "z"], &[])), vec!["x".to_string()]);
+        assert_eq!(a.missing_inputs(&OutputSignature::new()), vec!["x".to_string(), "y".to_string()]);
+    }
+
     #[test]
     fn test_execute_without_operation() {
         let mut node = OperationNode::default();
@@ -448,8 +839,17 @@ mod tests {
                 language: SupportedLanguage::PyO3,
                 source_code: "".to_string(),
                 function_invocation: None,
+                inspect_globals: false,
+                redact_output: false,
+                policy: Default::default(),
+                oom_limit_bytes: None,
+                always_run: false,
+                generate_types: false,
+                package_json: None,
             }, TextRange::default()),
             signature: Signature::new(),
+            retry: None,
+            web_middleware: vec![],
             // operation: Box::new(|_, p: RkyvSerializedValue, _, async_rpccommunication: Option<AsyncRPCCommunication>| async move {
             //     let mut state = 0;
             //     let mut async_rpccommunication: AsyncRPCCommunication = async_rpccommunication.unwrap();
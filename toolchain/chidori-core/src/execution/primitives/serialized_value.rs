@@ -17,7 +17,8 @@ use std::hash::Hasher;
 ))]
 #[archive_attr(derive(Debug))]
 pub enum RkyvSerializedValue {
-    StreamPointer(u32),
+    /// Id of a stream written to disk, see `execution::stream`
+    StreamPointer(String),
 
     /// Function pointers are to a specific cell and function name
     FunctionPointer(usize, String),
@@ -25,7 +26,26 @@ pub enum RkyvSerializedValue {
     Cell(CellTypes),
 
     // TODO: add Embedding
-    // TODO: add Error
+
+    /// An error returned as an ordinary value, for downstream cells to inspect rather than
+    /// aborting evaluation. `kind` names the sort of error, e.g. the class of a python exception.
+    Error {
+        kind: String,
+        message: String,
+    },
+
+    /// A table held columnar on disk as an Arrow IPC file, see `execution::primitives::table`.
+    /// State holds only where it is, its schema and its first rows.
+    Table {
+        path: String,
+        /// Name and arrow data type of each column, in order
+        schema: Vec<(String, String)>,
+        row_count: u32,
+        #[omit_bounds]
+        #[archive_attr(omit_bounds)]
+        preview: Vec<RkyvSerializedValue>,
+    },
+
     Set(
 
         #[omit_bounds]
@@ -52,6 +72,17 @@ pub enum RkyvSerializedValue {
     ),
 }
 
+/// Entries of an object ordered by key. Objects are backed by a `HashMap`, anything that
+/// renders them should iterate in this order so that output is reproducible.
+pub fn sorted_object_entries(map: &HashMap<String, RkyvSerializedValue>) -> Vec<(&String, &RkyvSerializedValue)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by_key(|(k, _)| *k);
+    entries
+}
+
+/// Key of the object that stands in for a `StreamPointer` in json, see `execution::stream`
+pub const STREAM_POINTER_KEY: &str = "__chidori_stream__";
+
 pub struct RkyvObjectBuilder {
     object: HashMap<String, RkyvSerializedValue>,
 }
@@ -125,6 +156,20 @@ impl std::cmp::PartialEq for RkyvSerializedValue {
                     _ => unreachable!()
                 }
             }
+            RkyvSerializedValue::Error { kind, message } => {
+                match other {
+                    RkyvSerializedValue::Error { kind: kk, message: mm } => { kind == kk && message == mm }
+                    _ => unreachable!()
+                }
+            }
+            RkyvSerializedValue::Table { path, schema, row_count, preview } => {
+                match other {
+                    RkyvSerializedValue::Table { path: pp, schema: ss, row_count: rr, preview: vv } => {
+                        path == pp && schema == ss && row_count == rr && preview == vv
+                    }
+                    _ => unreachable!()
+                }
+            }
             RkyvSerializedValue::Set(a) => {
                 match other {
                     RkyvSerializedValue::Set(aa) => { a == aa }
@@ -194,6 +239,13 @@ impl std::hash::Hash for RkyvSerializedValue {
             RkyvSerializedValue::Cell(cell_type) => {
                 unimplemented!();
             }
+            RkyvSerializedValue::Error { kind, message } => {
+                kind.hash(state);
+                message.hash(state);
+            }
+            RkyvSerializedValue::Table { path, .. } => {
+                path.hash(state);
+            }
             RkyvSerializedValue::Set(set) => {
                 for item in set {
                     item.hash(state);
@@ -249,6 +301,20 @@ impl std::fmt::Display for RkyvSerializedValue {
             RkyvSerializedValue::StreamPointer(_) => write!(f, "StreamPointer"),
             RkyvSerializedValue::FunctionPointer(_, _) => write!(f, "FunctionPointer"),
             RkyvSerializedValue::Cell(_) => write!(f, "Cell"),
+            RkyvSerializedValue::Error { kind, .. } => write!(f, "Error({})", kind),
+            RkyvSerializedValue::Table { schema, row_count, preview, .. } => {
+                let columns: Vec<String> = schema.iter()
+                    .map(|(name, data_type)| format!("{}: {}", name, data_type))
+                    .collect();
+                let rows: Vec<String> = preview.iter()
+                    .map(|row| serialized_value_to_json_value(row).to_string())
+                    .collect();
+                write!(f, "Table[{} rows]{{{}}}", row_count, columns.join(", "))?;
+                for row in rows {
+                    write!(f, "\n  {}", row)?;
+                }
+                Ok(())
+            }
             RkyvSerializedValue::Float(_) => write!(f, "Float"),
             RkyvSerializedValue::Number(_) => write!(f, "Number"),
             RkyvSerializedValue::String(_) => write!(f, "String"),
@@ -259,8 +325,8 @@ impl std::fmt::Display for RkyvSerializedValue {
                 write!(f, "Array[{}]", shapes.join(", "))
             }
             RkyvSerializedValue::Object(map) => {
-                let shapes: Vec<String> = map
-                    .iter()
+                let shapes: Vec<String> = sorted_object_entries(map)
+                    .into_iter()
                     .map(|(key, value)| format!("{}: {}", key, value))
                     .collect();
                 write!(f, "Object{{{}}}", shapes.join(", "))
@@ -307,18 +373,33 @@ pub fn serialized_value_to_json_value(v: &RkyvSerializedValue) -> chidori_prompt
                 .collect(),
         ),
         RkyvSerializedValue::Object(a) => Value::Object(
-            a.iter()
+            sorted_object_entries(a)
+                .into_iter()
                 .map(|(k, v)| (k.clone(), serialized_value_to_json_value(v)))
                 .collect(),
         ),
         RkyvSerializedValue::FunctionPointer(_, _) => Value::Null,
-        RkyvSerializedValue::StreamPointer(_) => Value::Null,
+        RkyvSerializedValue::StreamPointer(id) => serde_json::json!({ STREAM_POINTER_KEY: id }),
         RkyvSerializedValue::Cell(_) => Value::Null,
+        RkyvSerializedValue::Error { kind, message } => serde_json::json!({
+            "error": { "kind": kind, "message": message }
+        }),
+        RkyvSerializedValue::Table { schema, row_count, preview, .. } => serde_json::json!({
+            "table": {
+                "schema": schema.iter()
+                    .map(|(name, data_type)| serde_json::json!({ "name": name, "type": data_type }))
+                    .collect::<Vec<_>>(),
+                "row_count": row_count,
+                "preview": preview.iter().map(serialized_value_to_json_value).collect::<Vec<_>>(),
+            }
+        }),
         RkyvSerializedValue::Null => Value::Null,
         RkyvSerializedValue::Set(a) => {
-            a.iter()
+            let mut items: Vec<Value> = a.iter()
                 .map(|v| serialized_value_to_json_value(v))
-                .collect()
+                .collect();
+            items.sort_by_cached_key(|v| v.to_string());
+            Value::Array(items)
         }
     }
 }
@@ -342,6 +423,9 @@ pub fn json_value_to_serialized_value(jval: &Value) -> RkyvSerializedValue {
                 .map(|v| json_value_to_serialized_value(v))
                 .collect(),
         ),
+        Value::Object(o) if o.len() == 1 && o.get(STREAM_POINTER_KEY).map_or(false, |id| id.is_string()) => {
+            RkyvSerializedValue::StreamPointer(o[STREAM_POINTER_KEY].as_str().unwrap().to_string())
+        }
         Value::Object(o) => {
             let mut map = HashMap::new();
             for (k, v) in o {
@@ -354,6 +438,166 @@ pub fn json_value_to_serialized_value(jval: &Value) -> RkyvSerializedValue {
     }
 }
 
+// MessagePack extension types of the variants without a counterpart in the MessagePack data model
+const MSGPACK_EXT_STREAM_POINTER: i8 = 1;
+const MSGPACK_EXT_FUNCTION_POINTER: i8 = 2;
+const MSGPACK_EXT_CELL: i8 = 3;
+const MSGPACK_EXT_SET: i8 = 4;
+const MSGPACK_EXT_ERROR: i8 = 5;
+const MSGPACK_EXT_TABLE: i8 = 6;
+
+fn serialized_value_to_msgpack_value(v: &RkyvSerializedValue) -> anyhow::Result<rmpv::Value> {
+    Ok(match v {
+        RkyvSerializedValue::Null => rmpv::Value::Nil,
+        RkyvSerializedValue::Boolean(b) => rmpv::Value::Boolean(*b),
+        RkyvSerializedValue::Number(n) => rmpv::Value::from(*n),
+        RkyvSerializedValue::Float(f) => rmpv::Value::F32(*f),
+        RkyvSerializedValue::String(s) => rmpv::Value::from(s.as_str()),
+        RkyvSerializedValue::Array(a) => rmpv::Value::Array(
+            a.iter().map(serialized_value_to_msgpack_value).collect::<anyhow::Result<_>>()?
+        ),
+        RkyvSerializedValue::Object(o) => rmpv::Value::Map(
+            sorted_object_entries(o)
+                .into_iter()
+                .map(|(k, v)| Ok((rmpv::Value::from(k.as_str()), serialized_value_to_msgpack_value(v)?)))
+                .collect::<anyhow::Result<_>>()?
+        ),
+        RkyvSerializedValue::StreamPointer(id) => rmpv::Value::Ext(MSGPACK_EXT_STREAM_POINTER, id.as_bytes().to_vec()),
+        RkyvSerializedValue::FunctionPointer(cell, name) => rmpv::Value::Ext(
+            MSGPACK_EXT_FUNCTION_POINTER,
+            rmp_serde::to_vec(&(cell, name))?,
+        ),
+        RkyvSerializedValue::Cell(cell) => rmpv::Value::Ext(MSGPACK_EXT_CELL, rmp_serde::to_vec_named(cell)?),
+        RkyvSerializedValue::Error { kind, message } => rmpv::Value::Ext(
+            MSGPACK_EXT_ERROR,
+            rmp_serde::to_vec(&(kind, message))?,
+        ),
+        RkyvSerializedValue::Table { path, schema, row_count, preview } => {
+            let preview = preview.iter().map(serialized_value_to_msgpack).collect::<anyhow::Result<Vec<_>>>()?;
+            rmpv::Value::Ext(MSGPACK_EXT_TABLE, rmp_serde::to_vec(&(path, schema, row_count, preview))?)
+        }
+        RkyvSerializedValue::Set(items) => {
+            let mut items = items.iter().map(serialized_value_to_msgpack).collect::<anyhow::Result<Vec<_>>>()?;
+            items.sort();
+            let mut buf = vec![];
+            rmpv::encode::write_value(&mut buf, &rmpv::Value::Array(items.into_iter().map(rmpv::Value::Binary).collect()))?;
+            rmpv::Value::Ext(MSGPACK_EXT_SET, buf)
+        }
+    })
+}
+
+fn msgpack_value_to_serialized_value(v: rmpv::Value) -> anyhow::Result<RkyvSerializedValue> {
+    Ok(match v {
+        rmpv::Value::Nil => RkyvSerializedValue::Null,
+        rmpv::Value::Boolean(b) => RkyvSerializedValue::Boolean(b),
+        rmpv::Value::Integer(n) => {
+            let n = n.as_i64().and_then(|n| i32::try_from(n).ok())
+                .ok_or_else(|| anyhow::anyhow!("Integer {} does not fit in a number", n))?;
+            RkyvSerializedValue::Number(n)
+        }
+        rmpv::Value::F32(f) => RkyvSerializedValue::Float(f),
+        rmpv::Value::F64(f) => RkyvSerializedValue::Float(f as f32),
+        rmpv::Value::String(s) => RkyvSerializedValue::String(
+            s.into_str().ok_or_else(|| anyhow::anyhow!("String is not valid UTF-8"))?
+        ),
+        rmpv::Value::Array(a) => RkyvSerializedValue::Array(
+            a.into_iter().map(msgpack_value_to_serialized_value).collect::<anyhow::Result<_>>()?
+        ),
+        rmpv::Value::Map(m) => {
+            let mut map = HashMap::new();
+            for (k, v) in m {
+                let k = match k {
+                    rmpv::Value::String(k) => k.into_str().ok_or_else(|| anyhow::anyhow!("Object key is not valid UTF-8"))?,
+                    k => anyhow::bail!("Object keys must be strings, found {}", k),
+                };
+                map.insert(k, msgpack_value_to_serialized_value(v)?);
+            }
+            RkyvSerializedValue::Object(map)
+        }
+        rmpv::Value::Ext(MSGPACK_EXT_STREAM_POINTER, bytes) => {
+            let id = String::from_utf8(bytes).map_err(|_| anyhow::anyhow!("Malformed stream pointer"))?;
+            RkyvSerializedValue::StreamPointer(id)
+        }
+        rmpv::Value::Ext(MSGPACK_EXT_FUNCTION_POINTER, bytes) => {
+            let (cell, name): (usize, String) = rmp_serde::from_slice(&bytes)?;
+            RkyvSerializedValue::FunctionPointer(cell, name)
+        }
+        rmpv::Value::Ext(MSGPACK_EXT_CELL, bytes) => RkyvSerializedValue::Cell(rmp_serde::from_slice(&bytes)?),
+        rmpv::Value::Ext(MSGPACK_EXT_ERROR, bytes) => {
+            let (kind, message): (String, String) = rmp_serde::from_slice(&bytes)?;
+            RkyvSerializedValue::Error { kind, message }
+        }
+        rmpv::Value::Ext(MSGPACK_EXT_TABLE, bytes) => {
+            let (path, schema, row_count, preview): (String, Vec<(String, String)>, u32, Vec<Vec<u8>>) = rmp_serde::from_slice(&bytes)?;
+            let preview = preview.iter().map(|row| msgpack_to_serialized_value(row)).collect::<anyhow::Result<_>>()?;
+            RkyvSerializedValue::Table { path, schema, row_count, preview }
+        }
+        rmpv::Value::Ext(MSGPACK_EXT_SET, bytes) => {
+            let rmpv::Value::Array(items) = rmpv::decode::read_value(&mut bytes.as_slice())? else {
+                anyhow::bail!("Malformed set");
+            };
+            let mut set = HashSet::new();
+            for item in items {
+                let rmpv::Value::Binary(item) = item else { anyhow::bail!("Malformed set") };
+                set.insert(msgpack_to_serialized_value(&item)?);
+            }
+            RkyvSerializedValue::Set(set)
+        }
+        rmpv::Value::Ext(kind, _) => anyhow::bail!("Unknown MessagePack extension type {}", kind),
+        rmpv::Value::Binary(_) => anyhow::bail!("Binary MessagePack values are not supported"),
+    })
+}
+
+/// Encode a value as MessagePack, a more compact wire format than json. Unlike the json
+/// conversion every variant is preserved, those without a MessagePack counterpart are
+/// written as extension types.
+pub fn serialized_value_to_msgpack(v: &RkyvSerializedValue) -> anyhow::Result<Vec<u8>> {
+    let mut buf = vec![];
+    rmpv::encode::write_value(&mut buf, &serialized_value_to_msgpack_value(v)?)?;
+    Ok(buf)
+}
+
+/// Decode a value encoded by `serialized_value_to_msgpack`
+pub fn msgpack_to_serialized_value(bytes: &[u8]) -> anyhow::Result<RkyvSerializedValue> {
+    let mut reader = bytes;
+    let value = rmpv::decode::read_value(&mut reader)?;
+    if !reader.is_empty() {
+        anyhow::bail!("{} trailing bytes after MessagePack value", reader.len());
+    }
+    msgpack_value_to_serialized_value(value)
+}
+
+impl RkyvSerializedValue {
+    /// The name of the kind of value this is, for showing to users alongside it
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            RkyvSerializedValue::StreamPointer(_) => "Stream",
+            RkyvSerializedValue::FunctionPointer(..) => "Function",
+            RkyvSerializedValue::Cell(_) => "Cell",
+            RkyvSerializedValue::Error { .. } => "Error",
+            RkyvSerializedValue::Table { .. } => "Table",
+            RkyvSerializedValue::Set(_) => "Set",
+            RkyvSerializedValue::Float(_) => "Float",
+            RkyvSerializedValue::Number(_) => "Number",
+            RkyvSerializedValue::String(_) => "String",
+            RkyvSerializedValue::Boolean(_) => "Boolean",
+            RkyvSerializedValue::Null => "Null",
+            RkyvSerializedValue::Array(_) => "Array",
+            RkyvSerializedValue::Object(_) => "Object",
+        }
+    }
+
+    /// See `serialized_value_to_msgpack`
+    pub fn to_msgpack(&self) -> anyhow::Result<Vec<u8>> {
+        serialized_value_to_msgpack(self)
+    }
+
+    /// See `msgpack_to_serialized_value`
+    pub fn from_msgpack(bytes: &[u8]) -> anyhow::Result<Self> {
+        msgpack_to_serialized_value(bytes)
+    }
+}
+
 // Implementing Serialize for RkyvSerializedValue
 impl SerdeSerialize for RkyvSerializedValue {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -437,6 +681,25 @@ mod tests {
         round_trip(value);
     }
 
+    #[test]
+    fn test_error() {
+        let value = RkyvSerializedValue::Error {
+            kind: "ValueError".to_string(),
+            message: "not a number".to_string(),
+        };
+        round_trip(value.clone());
+        assert_eq!(
+            serialized_value_to_json_value(&value),
+            serde_json::json!({ "error": { "kind": "ValueError", "message": "not a number" } })
+        );
+    }
+
+    #[test]
+    fn test_stream_pointer_json_round_trip() {
+        let value = RkyvSerializedValue::StreamPointer(uuid::Uuid::now_v7().to_string());
+        assert_eq!(json_value_to_serialized_value(&serialized_value_to_json_value(&value)), value);
+    }
+
     #[test]
     fn test_object() {
         let mut map = HashMap::new();
@@ -448,6 +711,136 @@ mod tests {
         round_trip(value);
     }
 
+    #[test]
+    fn test_object_key_order_is_stable() {
+        let keys = ["zeta", "alpha", "mu", "beta", "omega", "gamma", "delta", "epsilon"];
+        let build = |keys: &mut dyn Iterator<Item = &&str>| {
+            keys.fold(RkyvObjectBuilder::new(), |b, k| b.insert_string(k, k.to_uppercase()))
+                .insert_value("set", RkyvSerializedValue::Set(HashSet::from([
+                    RkyvSerializedValue::Number(3),
+                    RkyvSerializedValue::Number(1),
+                    RkyvSerializedValue::Number(2),
+                ])))
+                .build()
+        };
+        let expected = r#"{"alpha":"ALPHA","beta":"BETA","delta":"DELTA","epsilon":"EPSILON","gamma":"GAMMA","mu":"MU","omega":"OMEGA","set":[1,2,3],"zeta":"ZETA"}"#;
+        for _ in 0..10 {
+            let forward = build(&mut keys.iter());
+            let reversed = build(&mut keys.iter().rev());
+            assert_eq!(serde_json::to_string(&serialized_value_to_json_value(&forward)).unwrap(), expected);
+            assert_eq!(serde_json::to_string(&reversed).unwrap(), expected);
+            assert_eq!(forward.to_string(), reversed.to_string());
+        }
+    }
+
+    #[test]
+    fn test_msgpack_round_trip() {
+        let values = vec![
+            RkyvSerializedValue::StreamPointer(uuid::Uuid::now_v7().to_string()),
+            RkyvSerializedValue::FunctionPointer(3, "add".to_string()),
+            RkyvSerializedValue::Cell(CellTypes::Template(crate::cells::TemplateCell {
+                backing_file_reference: None,
+                name: Some("greeting".to_string()),
+                body: "Hello {{name}}".to_string(),
+                engine: Default::default(),
+            }, Default::default())),
+            RkyvSerializedValue::Set(HashSet::from([
+                RkyvSerializedValue::Number(1),
+                RkyvSerializedValue::String("two".to_string()),
+            ])),
+            RkyvSerializedValue::Error { kind: "ValueError".to_string(), message: "not a number".to_string() },
+            RkyvSerializedValue::Table {
+                path: "people.arrow".to_string(),
+                schema: vec![("name".to_string(), "LargeUtf8".to_string())],
+                row_count: 1,
+                preview: vec![RkyvObjectBuilder::new().insert_string("name", "Ada".to_string()).build()],
+            },
+            RkyvSerializedValue::Float(1.5),
+            RkyvSerializedValue::Number(-42),
+            RkyvSerializedValue::String("Hello".to_string()),
+            RkyvSerializedValue::Boolean(true),
+            RkyvSerializedValue::Null,
+            RkyvSerializedValue::Array(vec![RkyvSerializedValue::Number(1), RkyvSerializedValue::Null]),
+            RkyvObjectBuilder::new()
+                .insert_string("key", "value".to_string())
+                .insert_value("nested", RkyvObjectBuilder::new().insert_boolean("flag", false).build())
+                .build(),
+        ];
+        for value in values {
+            let bytes = serialized_value_to_msgpack(&value).unwrap();
+            assert_eq!(msgpack_to_serialized_value(&bytes).unwrap(), value);
+        }
+        assert!(msgpack_to_serialized_value(&[0xc1]).is_err());
+    }
+
+    #[test]
+    fn test_type_name() {
+        let values = [
+            (RkyvSerializedValue::StreamPointer(uuid::Uuid::now_v7().to_string()), "Stream"),
+            (RkyvSerializedValue::FunctionPointer(0, "add".to_string()), "Function"),
+            (RkyvSerializedValue::Cell(CellTypes::Template(crate::cells::TemplateCell {
+                backing_file_reference: None,
+                name: None,
+                body: String::new(),
+                engine: Default::default(),
+            }, Default::default())), "Cell"),
+            (RkyvSerializedValue::Error { kind: "ValueError".to_string(), message: String::new() }, "Error"),
+            (RkyvSerializedValue::Table { path: String::new(), schema: vec![], row_count: 0, preview: vec![] }, "Table"),
+            (RkyvSerializedValue::Set(HashSet::new()), "Set"),
+            (RkyvSerializedValue::Float(1.5), "Float"),
+            (RkyvSerializedValue::Number(1), "Number"),
+            (RkyvSerializedValue::String("a".to_string()), "String"),
+            (RkyvSerializedValue::Boolean(false), "Boolean"),
+            (RkyvSerializedValue::Null, "Null"),
+            (RkyvSerializedValue::Array(vec![]), "Array"),
+            (RkyvSerializedValue::Object(HashMap::new()), "Object"),
+        ];
+        for (value, name) in values {
+            assert_eq!(value.type_name(), name);
+        }
+    }
+
+    #[test]
+    fn test_msgpack_round_trip_of_nested_value() {
+        let value = RkyvObjectBuilder::new()
+            .insert_value("rows", RkyvSerializedValue::Array(vec![
+                RkyvObjectBuilder::new()
+                    .insert_string("name", "Ada".to_string())
+                    .insert_value("tags", RkyvSerializedValue::Set(HashSet::from([
+                        RkyvSerializedValue::String("math".to_string()),
+                        RkyvSerializedValue::Array(vec![RkyvSerializedValue::Number(1), RkyvSerializedValue::Float(0.5)]),
+                    ])))
+                    .build(),
+                RkyvSerializedValue::Set(HashSet::new()),
+                RkyvSerializedValue::Null,
+            ]))
+            .insert_value("meta", RkyvObjectBuilder::new()
+                .insert_number("count", 2)
+                .insert_value("empty", RkyvSerializedValue::Object(HashMap::new()))
+                .build())
+            .build();
+        let bytes = value.to_msgpack().unwrap();
+        assert_eq!(RkyvSerializedValue::from_msgpack(&bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn test_msgpack_is_smaller_than_json() {
+        let record = |i: i32| RkyvObjectBuilder::new()
+            .insert_number("id", i)
+            .insert_string("name", format!("user {}", i))
+            .insert_boolean("active", i % 2 == 0)
+            .insert_value("scores", RkyvSerializedValue::Array((0..10).map(|s| RkyvSerializedValue::Number(s * 1000)).collect()))
+            .insert_value("location", RkyvObjectBuilder::new()
+                .insert_value("lat", RkyvSerializedValue::Float(51.5))
+                .insert_value("lon", RkyvSerializedValue::Float(-0.12))
+                .build())
+            .build();
+        let value = RkyvSerializedValue::Array((0..50).map(record).collect());
+        let msgpack = serialized_value_to_msgpack(&value).unwrap();
+        let json = serde_json::to_vec(&serialized_value_to_json_value(&value)).unwrap();
+        assert!(msgpack.len() < json.len(), "{} bytes of MessagePack, {} of json", msgpack.len(), json.len());
+    }
+
     #[test]
     fn test_serialize_to_vec() {
         let value = RkyvSerializedValue::String("Hello".to_string());
@@ -0,0 +1,221 @@
+//! Tables are held columnar on disk as Arrow IPC files, state holds only a `Table` value naming the
+//! file along with its schema and first rows. CSV and Parquet files are read into and written from
+//! tables, and tables convert to and from columns or rows of values for the runtimes.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use anyhow::anyhow;
+use arrow2::array::{Array, StructArray};
+use arrow2::chunk::Chunk;
+use arrow2::datatypes::{DataType, Field, Schema};
+use arrow2::io::{csv, ipc, parquet};
+use uuid::Uuid;
+use crate::cells::TableFileFormat;
+use crate::execution::primitives::serialized_value::RkyvSerializedValue;
+
+/// Number of rows of a table held in state for previews
+pub const TABLE_PREVIEW_ROWS: usize = 5;
+
+/// Number of rows of a CSV file read into each chunk of a table
+const CSV_CHUNK_ROWS: usize = 1024;
+
+/// Directory the contents of tables are written to
+pub fn table_directory() -> PathBuf {
+    std::env::temp_dir().join("chidori").join("tables")
+}
+
+impl TableFileFormat {
+    pub fn from_path(path: &Path) -> anyhow::Result<Self> {
+        match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("csv") => Ok(TableFileFormat::Csv),
+            Some("parquet") => Ok(TableFileFormat::Parquet),
+            _ => Err(anyhow!("Cannot infer the format of {}, expected a .csv or .parquet file", path.display())),
+        }
+    }
+}
+
+type Chunks = Vec<Chunk<Box<dyn Array>>>;
+
+fn chunk_rows(schema: &Schema, chunk: &Chunk<Box<dyn Array>>) -> anyhow::Result<Vec<RkyvSerializedValue>> {
+    let rows = StructArray::try_new(DataType::Struct(schema.fields.clone()), chunk.arrays().to_vec(), None)?;
+    match RkyvSerializedValue::from_arrow_array(&rows) {
+        RkyvSerializedValue::Array(rows) => Ok(rows),
+        _ => unreachable!("Arrow arrays always convert to arrays"),
+    }
+}
+
+/// Write the chunks of a table to disk, returning the value that stands in for it
+pub fn store_table(schema: &Schema, chunks: &[Chunk<Box<dyn Array>>]) -> anyhow::Result<RkyvSerializedValue> {
+    let directory = table_directory();
+    std::fs::create_dir_all(&directory)?;
+    let path = directory.join(format!("{}.arrow", Uuid::now_v7()));
+    let options = ipc::write::WriteOptions { compression: None };
+    let mut writer = ipc::write::FileWriter::try_new(File::create(&path)?, schema.clone(), None, options)?;
+    for chunk in chunks {
+        writer.write(chunk, None)?;
+    }
+    writer.finish()?;
+
+    let mut preview = vec![];
+    for chunk in chunks {
+        if preview.len() >= TABLE_PREVIEW_ROWS {
+            break;
+        }
+        let remaining = TABLE_PREVIEW_ROWS - preview.len();
+        preview.extend(chunk_rows(schema, chunk)?.into_iter().take(remaining));
+    }
+    Ok(RkyvSerializedValue::Table {
+        path: path.to_string_lossy().to_string(),
+        schema: schema.fields.iter().map(|f| (f.name.clone(), format!("{:?}", f.data_type))).collect(),
+        row_count: chunks.iter().map(|c| c.len()).sum::<usize>() as u32,
+        preview,
+    })
+}
+
+/// Read back the schema and chunks of a table
+pub fn load_table(table: &RkyvSerializedValue) -> anyhow::Result<(Schema, Chunks)> {
+    let RkyvSerializedValue::Table { path, .. } = table else {
+        return Err(anyhow!("Expected a Table, got {} {}", table.type_name(), table));
+    };
+    let mut file = File::open(path)?;
+    let metadata = ipc::read::read_file_metadata(&mut file)?;
+    let schema = metadata.schema.clone();
+    let chunks = ipc::read::FileReader::new(file, metadata, None, None).collect::<Result<Vec<_>, _>>()?;
+    Ok((schema, chunks))
+}
+
+/// Every row of a table as an object keyed by column name
+pub fn table_to_rows(table: &RkyvSerializedValue) -> anyhow::Result<RkyvSerializedValue> {
+    let (schema, chunks) = load_table(table)?;
+    let mut rows = vec![];
+    for chunk in &chunks {
+        rows.extend(chunk_rows(&schema, chunk)?);
+    }
+    Ok(RkyvSerializedValue::Array(rows))
+}
+
+/// Each column of a table as an array of its values, in order
+pub fn table_to_columns(table: &RkyvSerializedValue) -> anyhow::Result<Vec<(String, RkyvSerializedValue)>> {
+    let (schema, chunks) = load_table(table)?;
+    Ok(schema.fields.iter().enumerate().map(|(i, field)| {
+        let values = chunks.iter().flat_map(|chunk| match RkyvSerializedValue::from_arrow_array(chunk.arrays()[i].as_ref()) {
+            RkyvSerializedValue::Array(values) => values,
+            _ => unreachable!("Arrow arrays always convert to arrays"),
+        }).collect();
+        (field.name.clone(), RkyvSerializedValue::Array(values))
+    }).collect())
+}
+
+/// Build a table from columns of values, each converted as by `to_arrow_array`
+pub fn table_from_columns(columns: Vec<(String, RkyvSerializedValue)>) -> anyhow::Result<RkyvSerializedValue> {
+    let mut fields = vec![];
+    let mut arrays = vec![];
+    for (name, values) in columns {
+        let array = values.to_arrow_array()?;
+        fields.push(Field::new(name, array.data_type().clone(), true));
+        arrays.push(array);
+    }
+    store_table(&Schema::from(fields), &[Chunk::try_new(arrays)?])
+}
+
+/// Build a table from an array of objects, with a column for each key
+pub fn table_from_rows(rows: &RkyvSerializedValue) -> anyhow::Result<RkyvSerializedValue> {
+    let array = rows.to_arrow_array()?;
+    let rows = array.as_any().downcast_ref::<StructArray>()
+        .ok_or_else(|| anyhow!("Tables can only be built from arrays of objects"))?;
+    store_table(&Schema::from(rows.fields().to_vec()), &[Chunk::try_new(rows.values().to_vec())?])
+}
+
+/// Read a CSV or Parquet file into a table. The types of the columns of a CSV file are inferred.
+pub fn read_table_file(path: &Path, format: TableFileFormat) -> anyhow::Result<RkyvSerializedValue> {
+    match format {
+        TableFileFormat::Csv => {
+            let mut reader = csv::read::ReaderBuilder::new().from_path(path)?;
+            let (fields, _) = csv::read::infer_schema(&mut reader, None, true, &csv::read::infer)?;
+            let mut rows = vec![csv::read::ByteRecord::default(); CSV_CHUNK_ROWS];
+            let mut chunks = vec![];
+            loop {
+                let rows_read = csv::read::read_rows(&mut reader, 0, &mut rows)?;
+                if rows_read == 0 {
+                    break;
+                }
+                chunks.push(csv::read::deserialize_batch(&rows[..rows_read], &fields, None, 0, csv::read::deserialize_column)?);
+            }
+            store_table(&Schema::from(fields), &chunks)
+        }
+        TableFileFormat::Parquet => {
+            let mut file = File::open(path)?;
+            let metadata = parquet::read::read_metadata(&mut file)?;
+            let schema = parquet::read::infer_schema(&metadata)?;
+            let chunks = parquet::read::FileReader::new(file, metadata.row_groups, schema.clone(), None, None, None)
+                .collect::<Result<Vec<_>, _>>()?;
+            store_table(&schema, &chunks)
+        }
+    }
+}
+
+/// Write a table to a CSV or Parquet file
+pub fn write_table_file(table: &RkyvSerializedValue, path: &Path, format: TableFileFormat) -> anyhow::Result<()> {
+    let (schema, chunks) = load_table(table)?;
+    match format {
+        TableFileFormat::Csv => {
+            let mut file = File::create(path)?;
+            let options = csv::write::SerializeOptions::default();
+            let names: Vec<&str> = schema.fields.iter().map(|f| f.name.as_str()).collect();
+            csv::write::write_header(&mut file, &names, &options)?;
+            for chunk in &chunks {
+                csv::write::write_chunk(&mut file, chunk, &options)?;
+            }
+        }
+        TableFileFormat::Parquet => {
+            let options = parquet::write::WriteOptions {
+                write_statistics: true,
+                compression: parquet::write::CompressionOptions::Uncompressed,
+                version: parquet::write::Version::V2,
+                data_pagesize_limit: None,
+            };
+            let encodings = schema.fields.iter()
+                .map(|f| parquet::write::transverse(&f.data_type, |_| parquet::write::Encoding::Plain))
+                .collect();
+            let row_groups = parquet::write::RowGroupIterator::try_new(chunks.into_iter().map(Ok), &schema, options, encodings)?;
+            let mut writer = parquet::write::FileWriter::try_new(File::create(path)?, schema.clone(), options)?;
+            for group in row_groups {
+                writer.write(group?)?;
+            }
+            writer.end(None)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::primitives::serialized_value::RkyvObjectBuilder;
+
+    #[test]
+    fn test_csv_and_parquet_round_trip() -> anyhow::Result<()> {
+        let directory = std::env::temp_dir().join(format!("chidori-table-{}", Uuid::now_v7()));
+        std::fs::create_dir_all(&directory)?;
+        let csv_path = directory.join("people.csv");
+        std::fs::write(&csv_path, "name,age\nAda,36\nAlan,41\nGrace,85\n")?;
+
+        let table = read_table_file(&csv_path, TableFileFormat::from_path(&csv_path)?)?;
+        let RkyvSerializedValue::Table { schema, row_count, preview, .. } = &table else { panic!("Expected a table") };
+        assert_eq!(schema, &vec![("name".to_string(), "Utf8".to_string()), ("age".to_string(), "Int64".to_string())]);
+        assert_eq!(*row_count, 3);
+        assert_eq!(preview[0], RkyvObjectBuilder::new().insert_string("name", "Ada".to_string()).insert_number("age", 36).build());
+
+        let parquet_path = directory.join("people.parquet");
+        write_table_file(&table, &parquet_path, TableFileFormat::Parquet)?;
+        let read_back = read_table_file(&parquet_path, TableFileFormat::Parquet)?;
+        assert_eq!(table_to_rows(&read_back)?, table_to_rows(&table)?);
+        assert_eq!(table_to_columns(&read_back)?[1], ("age".to_string(), RkyvSerializedValue::Array(vec![
+            RkyvSerializedValue::Number(36),
+            RkyvSerializedValue::Number(41),
+            RkyvSerializedValue::Number(85),
+        ])));
+        std::fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+}
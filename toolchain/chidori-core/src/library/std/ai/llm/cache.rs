@@ -0,0 +1,415 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::Instrument;
+use sha2::{Digest, Sha256};
+use crate::cells::LLMPromptCellChatConfiguration;
+use crate::library::std::ai::llm::{ChatCompletionReq, ChatCompletionRes, ChatModelBatch, EmbeddingModel, EmbeddingReq, Usage};
+
+/// How long a cached response is reused when a cell does not set a `ttl`
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Settings of the prompt configuration that change how the model is invoked by Chidori, rather
+/// than what the model is asked, and so do not distinguish cached responses.
+const UNCACHED_CONFIGURATION_KEYS: &[&str] = &["fn", "import", "redact_output", "timeout_ms", "retries", "llm_cache", "ttl"];
+
+/// Where the responses of a project's models are kept
+pub fn cache_directory(project: &Path) -> PathBuf {
+    project.join(".chidori").join("llm_cache")
+}
+
+/// Parses durations such as `30s`, `15m`, `24h` or `7d`, a bare number is a count of seconds.
+/// Durations too long to represent are rejected along with malformed ones.
+pub fn parse_ttl(ttl: &str) -> Option<Duration> {
+    let ttl = ttl.trim();
+    let split = ttl.find(|c: char| !c.is_ascii_digit()).unwrap_or(ttl.len());
+    let (amount, unit) = ttl.split_at(split);
+    let amount: u64 = amount.parse().ok()?;
+    let seconds = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    amount.checked_mul(seconds).map(Duration::from_secs)
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry<T> {
+    /// Milliseconds since the unix epoch at which the response was stored
+    stored_at_ms: u64,
+    value: T,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Responses of models persisted to disk, keyed by everything that was sent to the model, so that
+/// identical prompts are not paid for again across runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResponseCache {
+    directory: PathBuf,
+    ttl: Duration,
+    /// Only answer from the stored responses, requests without one fail rather than reach the model
+    replay_only: bool,
+    /// Set when the directory only exists for this cache, removing it once the last clone is dropped
+    temporary: Option<Arc<TemporaryDirectory>>,
+}
+
+#[derive(Debug, PartialEq)]
+struct TemporaryDirectory(PathBuf);
+
+impl Drop for TemporaryDirectory {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+impl ResponseCache {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        ResponseCache {
+            directory: directory.into(),
+            ttl: DEFAULT_CACHE_TTL,
+            replay_only: false,
+            temporary: None,
+        }
+    }
+
+    /// A cache in a directory of its own, which is removed along with the cache
+    pub fn temporary(directory: impl Into<PathBuf>) -> Self {
+        let directory = directory.into();
+        ResponseCache {
+            temporary: Some(Arc::new(TemporaryDirectory(directory.clone()))),
+            ..Self::new(directory)
+        }
+    }
+
+    /// The cache of the project in the given directory
+    pub fn for_project(project: &Path) -> Self {
+        Self::new(cache_directory(project))
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Serve requests only from the stored responses regardless of their age, such as those
+    /// recorded in a reproduction bundle, so that no request reaches a model
+    pub fn replay_only(mut self) -> Self {
+        self.replay_only = true;
+        self
+    }
+
+    pub fn is_replay_only(&self) -> bool {
+        self.replay_only
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn key(request: &impl Serialize) -> anyhow::Result<String> {
+        let serialized = serde_json::to_vec(request)?;
+        Ok(hex::encode(Sha256::digest(&serialized)))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.directory.join(Self::file_name(key))
+    }
+
+    /// The stored value if it is younger than `ttl`, or of any age when replaying
+    pub fn get<T: DeserializeOwned>(&self, key: &str, ttl: Duration) -> Option<T> {
+        let contents = std::fs::read(self.path(key)).ok()?;
+        let entry: CacheEntry<T> = serde_json::from_slice(&contents).ok()?;
+        if !self.replay_only && now_ms().saturating_sub(entry.stored_at_ms) >= ttl.as_millis() as u64 {
+            return None;
+        }
+        Some(entry.value)
+    }
+
+    pub fn put<T: Serialize>(&self, key: &str, value: &T) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.directory)?;
+        std::fs::write(self.path(key), Self::entry(value)?)?;
+        Ok(())
+    }
+
+    /// The stored value regardless of its age
+    pub fn get_stored<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let contents = std::fs::read(self.path(key)).ok()?;
+        serde_json::from_slice::<CacheEntry<T>>(&contents).ok().map(|entry| entry.value)
+    }
+
+    /// The contents of the file `value` is stored in, as stored now
+    pub fn entry<T: Serialize>(value: &T) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&CacheEntry { stored_at_ms: now_ms(), value })?)
+    }
+
+    /// The name of the file the value of `key` is stored in
+    pub fn file_name(key: &str) -> String {
+        format!("{}.json", key)
+    }
+
+    /// Remove every stored response, returning how many there were
+    pub fn clear(&self) -> anyhow::Result<usize> {
+        if !self.directory.exists() {
+            return Ok(0);
+        }
+        let mut removed = 0;
+        for entry in std::fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |e| e == "json") {
+                std::fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Wraps a model, answering requests from the cache when an identical one was made within the TTL.
+/// Responses served from the cache report no usage.
+pub struct CachedModel<'a, M> {
+    inner: M,
+    cache: Option<&'a ResponseCache>,
+    ttl: Duration,
+}
+
+impl<'a, M> CachedModel<'a, M> {
+    /// Caching is skipped when there is no cache, or when the cell configuration sets `llm_cache: false`
+    /// unless the cache is replay only. A `ttl` in the configuration overrides that of the cache.
+    pub fn new(inner: M, cache: Option<&'a ResponseCache>, configuration: Option<&LLMPromptCellChatConfiguration>) -> Self {
+        let enabled = configuration.and_then(|c| c.llm_cache).unwrap_or(true);
+        let ttl = configuration
+            .and_then(|c| c.ttl.as_deref())
+            .and_then(parse_ttl)
+            .or(cache.map(|c| c.ttl))
+            .unwrap_or(DEFAULT_CACHE_TTL);
+        CachedModel {
+            inner,
+            cache: cache.filter(|cache| enabled || cache.replay_only),
+            ttl,
+        }
+    }
+}
+
+/// The key the response to a chat request is stored under
+pub fn chat_request_key(req: &ChatCompletionReq) -> anyhow::Result<String> {
+    let mut request = serde_json::to_value(req)?;
+    if let Some(config) = request.get_mut("config").and_then(|c| c.as_object_mut()) {
+        for key in UNCACHED_CONFIGURATION_KEYS {
+            config.remove(*key);
+        }
+    }
+    ResponseCache::key(&("chat", request))
+}
+
+fn replay_miss(cache: &ResponseCache) -> String {
+    format!("No response to this request was recorded in {}, and responses are only replayed", cache.directory.display())
+}
+
+#[async_trait]
+impl<'a, M: ChatModelBatch + Send + Sync> ChatModelBatch for CachedModel<'a, M> {
+    async fn batch(&self, chat_completion_req: ChatCompletionReq) -> Result<ChatCompletionRes, String> {
+        let Some(cache) = self.cache else {
+            return self.inner.batch(chat_completion_req).await;
+        };
+        let key = chat_request_key(&chat_completion_req).map_err(|e| e.to_string())?;
+        if let Some(mut res) = cache.get::<ChatCompletionRes>(&key, self.ttl) {
+            let _span = tracing::info_span!("llm_cache_hit", key = key.as_str()).entered();
+            res.usage = Usage::default();
+            return Ok(res);
+        }
+        if cache.replay_only {
+            return Err(replay_miss(cache));
+        }
+        let res = self.inner.batch(chat_completion_req)
+            .instrument(tracing::info_span!("llm_cache_miss", key = key.as_str()))
+            .await?;
+        if let Err(e) = cache.put(&key, &res) {
+            tracing::warn!("Failed to cache model response: {:?}", e);
+        }
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl<'a, M: EmbeddingModel + Send + Sync> EmbeddingModel for CachedModel<'a, M> {
+    async fn embed(&self, embedding_req: EmbeddingReq) -> Result<Vec<f32>, String> {
+        let Some(cache) = self.cache else {
+            return self.inner.embed(embedding_req).await;
+        };
+        let key = ResponseCache::key(&("embedding", &embedding_req)).map_err(|e| e.to_string())?;
+        if let Some(embedding) = cache.get::<Vec<f32>>(&key, self.ttl) {
+            let _span = tracing::info_span!("llm_cache_hit", key = key.as_str()).entered();
+            return Ok(embedding);
+        }
+        if cache.replay_only {
+            return Err(replay_miss(cache));
+        }
+        let embedding = self.inner.embed(embedding_req)
+            .instrument(tracing::info_span!("llm_cache_miss", key = key.as_str()))
+            .await?;
+        if let Err(e) = cache.put(&key, &embedding) {
+            tracing::warn!("Failed to cache embedding: {:?}", e);
+        }
+        Ok(embedding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::library::std::ai::llm::{ChatCompletionChoice, MessageRole, TemplateMessage};
+    use uuid::Uuid;
+
+    #[derive(Default)]
+    struct CountingModel {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ChatModelBatch for CountingModel {
+        async fn batch(&self, _: ChatCompletionReq) -> Result<ChatCompletionRes, String> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(ChatCompletionRes {
+                id: "mock".to_string(),
+                object: "chat.completion".to_string(),
+                created: 0,
+                model: "mock".to_string(),
+                choices: vec![ChatCompletionChoice {
+                    text: Some("Hello".to_string()),
+                    index: 0,
+                    logprobs: None,
+                    finish_reason: "".to_string(),
+                    refusal: None,
+                    tool_calls: None,
+                }],
+                usage: Usage { prompt_tokens: 5, completion_tokens: 1, total_tokens: 6 },
+            })
+        }
+    }
+
+    fn request(content: &str, retries: Option<u32>) -> ChatCompletionReq {
+        let mut req = ChatCompletionReq {
+            template_messages: vec![TemplateMessage {
+                role: MessageRole::User,
+                content: content.to_string(),
+                name: None,
+                function_call: None,
+            }],
+            ..ChatCompletionReq::default()
+        };
+        req.config.retries = retries;
+        req
+    }
+
+    fn temporary_cache() -> ResponseCache {
+        ResponseCache::new(std::env::temp_dir().join(format!("chidori-llm-cache-{}", Uuid::now_v7())))
+    }
+
+    #[test]
+    fn test_parse_ttl() {
+        assert_eq!(parse_ttl("24h"), Some(Duration::from_secs(86_400)));
+        assert_eq!(parse_ttl("15m"), Some(Duration::from_secs(900)));
+        assert_eq!(parse_ttl("7d"), Some(Duration::from_secs(604_800)));
+        assert_eq!(parse_ttl("30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_ttl("soon"), None);
+        assert_eq!(parse_ttl("3w"), None);
+        assert_eq!(parse_ttl(&format!("{}d", u64::MAX)), None);
+    }
+
+    #[tokio::test]
+    async fn test_identical_prompts_are_answered_from_cache() -> anyhow::Result<()> {
+        let cache = temporary_cache();
+        let model = CachedModel::new(CountingModel::default(), Some(&cache), None);
+        let first = model.batch(request("Say hello", None)).await.unwrap();
+        // Settings that do not reach the model share the cached response
+        let second = model.batch(request("Say hello", Some(3))).await.unwrap();
+        assert_eq!(model.inner.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(second.choices[0].text, first.choices[0].text);
+        assert_eq!(first.usage.total_tokens, 6);
+        assert_eq!(second.usage.total_tokens, 0);
+
+        model.batch(request("Say goodbye", None)).await.unwrap();
+        assert_eq!(model.inner.calls.load(Ordering::SeqCst), 2);
+
+        assert_eq!(cache.clear()?, 2);
+        model.batch(request("Say hello", None)).await.unwrap();
+        assert_eq!(model.inner.calls.load(Ordering::SeqCst), 3);
+        std::fs::remove_dir_all(cache.directory())?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cached_responses_expire_after_ttl() -> anyhow::Result<()> {
+        let cache = temporary_cache().with_ttl(Duration::from_millis(100));
+        let model = CachedModel::new(CountingModel::default(), Some(&cache), None);
+        model.batch(request("Say hello", None)).await.unwrap();
+        model.batch(request("Say hello", None)).await.unwrap();
+        assert_eq!(model.inner.calls.load(Ordering::SeqCst), 1);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        model.batch(request("Say hello", None)).await.unwrap();
+        assert_eq!(model.inner.calls.load(Ordering::SeqCst), 2);
+        std::fs::remove_dir_all(cache.directory())?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cell_configuration_overrides_cache() -> anyhow::Result<()> {
+        let cache = temporary_cache();
+        let disabled = LLMPromptCellChatConfiguration { llm_cache: Some(false), ..Default::default() };
+        let model = CachedModel::new(CountingModel::default(), Some(&cache), Some(&disabled));
+        model.batch(request("Say hello", None)).await.unwrap();
+        model.batch(request("Say hello", None)).await.unwrap();
+        assert_eq!(model.inner.calls.load(Ordering::SeqCst), 2);
+        assert_eq!(cache.clear()?, 0);
+
+        let expiring = LLMPromptCellChatConfiguration { ttl: Some("0s".to_string()), ..Default::default() };
+        let model = CachedModel::new(CountingModel::default(), Some(&cache), Some(&expiring));
+        model.batch(request("Say hello", None)).await.unwrap();
+        model.batch(request("Say hello", None)).await.unwrap();
+        assert_eq!(model.inner.calls.load(Ordering::SeqCst), 2);
+        std::fs::remove_dir_all(cache.directory())?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_only_cache_never_reaches_the_model() -> anyhow::Result<()> {
+        let cache = temporary_cache().with_ttl(Duration::from_millis(1));
+        CachedModel::new(CountingModel::default(), Some(&cache), None).batch(request("Say hello", None)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let replay = cache.clone().replay_only();
+        let disabled = LLMPromptCellChatConfiguration { llm_cache: Some(false), ..Default::default() };
+        let model = CachedModel::new(CountingModel::default(), Some(&replay), Some(&disabled));
+        let replayed = model.batch(request("Say hello", None)).await.unwrap();
+        assert_eq!(replayed.choices[0].text.as_deref(), Some("Hello"));
+        assert!(model.batch(request("Say goodbye", None)).await.is_err());
+        assert_eq!(model.inner.calls.load(Ordering::SeqCst), 0);
+        std::fs::remove_dir_all(cache.directory())?;
+        Ok(())
+    }
+    #[tokio::test]
+    async fn test_temporary_cache_is_removed_with_its_last_clone() -> anyhow::Result<()> {
+        let directory = std::env::temp_dir().join(format!("chidori-llm-cache-{}", Uuid::now_v7()));
+        let cache = ResponseCache::temporary(&directory);
+        CachedModel::new(CountingModel::default(), Some(&cache), None).batch(request("Say hello", None)).await.unwrap();
+        let key = chat_request_key(&request("Say hello", None))?;
+        let replay = cache.clone().replay_only();
+        drop(cache);
+        assert!(replay.get_stored::<ChatCompletionRes>(&key).is_some());
+        drop(replay);
+        assert!(!directory.exists());
+        Ok(())
+    }
+}
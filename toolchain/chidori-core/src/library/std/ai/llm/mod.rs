@@ -1,4 +1,5 @@
 pub mod openai;
+pub mod cache;
 
 use async_trait::async_trait;
 use futures_util::stream::Stream;
@@ -7,24 +8,29 @@ use serde_json::Value;
 use std::collections::HashMap;
 use std::env;
 use std::pin::Pin;
-use std::sync::{Arc, Mutex};
 use tracing::debug;
 use uuid::Uuid;
-use chidori_prompt_format::templating::templates::{ChatModelRoles, TemplateWithSource};
-use crate::cells::{LLMCodeGenCellChatConfiguration, LLMPromptCellChatConfiguration, TextRange};
-use crate::execution::execution::execution_state::ExecutionStateErrors;
+use chidori_prompt_format::templating::templates::{analyze_template, ChatModelRoles, TemplateWithSource};
+use crate::cells::{CellTypes, LLMCodeGenCellChatConfiguration, LLMPromptCell, LLMPromptCellChatConfiguration, ProxyConfig, SupportedModelProviders, TextRange};
+use crate::execution::execution::execution_state::{CodeGenRetryState, ExecutionStateErrors};
 use crate::execution::execution::ExecutionState;
-use crate::execution::primitives::operation::InputSignature;
+use crate::execution::primitives::operation::{InputSignature, InputType};
 use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue, serialized_value_to_json_value};
+use crate::execution::primitives::agent_trace::AgentTraceStepKind;
+use crate::library::std::ai::llm::cache::{chat_request_key, CachedModel};
 use crate::library::std::ai::llm::openai::OpenAIChatModel;
 use crate::sdk::md::interpret_markdown_code_block;
+use crate::library::std::template::TemplateLibrary;
+use crate::library::std::ai::memory::store::MemoryQuery;
+use crate::cells::llm_prompt_cell::CONTEXT_VARIABLE;
+use crate::utils::prompt_audit::{PromptAuditMessage, PromptAuditRecord};
 
 #[derive(Debug)]
 pub enum LLMErrors {
     ConnectionError(String),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Usage {
     pub prompt_tokens: i32,
     pub completion_tokens: i32,
@@ -41,11 +47,116 @@ impl Default for Usage {
     }
 }
 
+impl ProxyConfig {
+    fn password(&self) -> anyhow::Result<Option<String>> {
+        self.password_env.as_ref()
+            .map(|name| env::var(name).map_err(|_| anyhow::anyhow!("The proxy password variable {} is not set", name)))
+            .transpose()
+    }
+
+    pub fn to_reqwest(&self) -> anyhow::Result<reqwest::Proxy> {
+        let proxy = reqwest::Proxy::all(&self.url)?;
+        Ok(match &self.username {
+            Some(username) => proxy.basic_auth(username, &self.password()?.unwrap_or_default()),
+            None => proxy,
+        })
+    }
+}
+
+/// The proxy requests of a prompt are sent through, the one it sets, or otherwise the one set for
+/// its provider
+pub fn resolve_proxy(execution_state: &ExecutionState, configuration: &LLMPromptCellChatConfiguration) -> Option<ProxyConfig> {
+    configuration.proxy.clone().or_else(|| {
+        execution_state.llm_proxies.get(&configuration.provider.clone().unwrap_or_default()).cloned()
+    })
+}
+
+/// Price in USD per million prompt and completion tokens, matched against model names by prefix
+/// in order so that more specific names come first
+const MODEL_PRICES_PER_MILLION_TOKENS: &[(&str, f64, f64)] = &[
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4o", 5.0, 15.0),
+    ("gpt-4-turbo", 10.0, 30.0),
+    ("gpt-4", 30.0, 60.0),
+    ("gpt-3.5-turbo", 0.5, 1.5),
+    ("claude-3-5-sonnet", 3.0, 15.0),
+    ("claude-3-opus", 15.0, 75.0),
+    ("claude-3-sonnet", 3.0, 15.0),
+    ("claude-3-haiku", 0.25, 1.25),
+];
+
+/// Model whose pricing is assumed for cells that do not name a model or name one we do not know
+const DEFAULT_PRICED_MODEL: &str = "gpt-3.5-turbo";
+
+/// Completion tokens assumed for cells that do not set `max_tokens`
+const DEFAULT_COMPLETION_TOKENS: usize = 256;
+
+/// Estimated cost in USD of a single request to `model`
+pub fn model_cost_usd(model: Option<&str>, prompt_tokens: usize, completion_tokens: usize) -> f64 {
+    let price = |model: &str| MODEL_PRICES_PER_MILLION_TOKENS.iter().find(|(prefix, _, _)| model.starts_with(prefix));
+    let (_, prompt_price, completion_price) = model.and_then(price)
+        .or_else(|| price(DEFAULT_PRICED_MODEL))
+        .expect("The default model is priced");
+    (prompt_tokens as f64 * prompt_price + completion_tokens as f64 * completion_price) / 1_000_000.0
+}
+
+/// Estimated cost in USD of evaluating a cell once with a prompt of `prompt_tokens`, None for
+/// cells that do not query a model
+pub fn estimated_cost_usd(cell: &CellTypes, prompt_tokens: usize) -> Option<f64> {
+    let (model, max_tokens) = match cell {
+        CellTypes::Prompt(LLMPromptCell::Chat { configuration, .. }, _) => (&configuration.model, configuration.max_tokens),
+        CellTypes::Prompt(LLMPromptCell::Completion { .. }, _) => (&None, None),
+        CellTypes::CodeGen(cell, _) => (&cell.configuration.model, cell.configuration.max_tokens),
+        _ => return None,
+    };
+    let completion_tokens = max_tokens.map_or(DEFAULT_COMPLETION_TOKENS, |max_tokens| max_tokens.max(0) as usize);
+    Some(model_cost_usd(model.as_deref(), prompt_tokens, completion_tokens))
+}
+
+/// Estimated cost in USD of evaluating each cell that queries a model once, with prompt sizes
+/// estimated from their templates
+pub fn estimated_cells_cost_usd<'a>(cells: impl IntoIterator<Item = &'a CellTypes>) -> f64 {
+    cells.into_iter()
+        .filter_map(|cell| {
+            let req = match cell {
+                CellTypes::Prompt(LLMPromptCell::Chat { req, .. }, _) | CellTypes::Prompt(LLMPromptCell::Completion { req }, _) => req,
+                CellTypes::CodeGen(cell, _) => &cell.req,
+                _ => return None,
+            };
+            estimated_cost_usd(cell, analyze_template(req).estimated_tokens)
+        })
+        .sum()
+}
+
+/// A cost for display, costs under a cent are shown as `< $0.01`
+pub fn format_cost_usd(cost: f64) -> String {
+    if cost < 0.01 {
+        "< $0.01".to_string()
+    } else {
+        format!("${:.2}", cost)
+    }
+}
+
 pub struct LLMStream {
     response: Pin<Box<dyn Stream<Item = Result<bytes::Bytes, reqwest::Error>> + Send>>,
     buffer: String,
     first_chunk: bool,
     usage: Usage,
+    /// Log probabilities of the tokens received so far, when requested with `logprobs`
+    logprobs: Vec<Value>,
+    refusal: Option<String>,
+}
+
+impl LLMStream {
+    /// Log probabilities of the tokens received so far, in the shape of those of a batch response
+    pub fn logprobs(&self) -> Option<Value> {
+        (!self.logprobs.is_empty()).then(|| serde_json::json!({ "content": self.logprobs }))
+    }
+
+    /// Explanation given by the model so far when it declines to answer
+    pub fn refusal(&self) -> Option<&str> {
+        self.refusal.as_deref()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -81,6 +192,7 @@ pub struct Function {
 #[serde(rename_all = "lowercase")]
 pub enum JSONSchemaType {
     Object,
+    Integer,
     Number,
     String,
     Array,
@@ -111,6 +223,75 @@ pub struct Tool {
     function: Function,
 }
 
+impl Tool {
+    /// A tool exposing the function of the given name, its parameters described by its input signature
+    pub fn from_function(name: &str, input_signature: InputSignature) -> Tool {
+        Tool {
+            tool_type: "function".to_string(),
+            function: Function {
+                name: name.to_string(),
+                description: None,
+                parameters: FunctionParameters {
+                    schema_type: JSONSchemaType::Object,
+                    properties: Some(input_signature_to_json_properties(input_signature)),
+                    required: None,
+                },
+            },
+        }
+    }
+
+    pub fn with_description(mut self, description: Option<String>) -> Tool {
+        self.function.description = description;
+        self
+    }
+
+    /// The tool as the JSON expected in the `tools` parameter of the OpenAI chat completions api
+    pub fn to_json(&self) -> Value {
+        let mut parameters = serde_json::json!({ "type": self.function.parameters.schema_type });
+        if let Some(properties) = &self.function.parameters.properties {
+            parameters["properties"] = json_schema_properties_to_json(properties);
+        }
+        if let Some(required) = &self.function.parameters.required {
+            parameters["required"] = serde_json::json!(required);
+        }
+        let mut function = serde_json::json!({
+            "name": self.function.name,
+            "parameters": parameters,
+        });
+        if let Some(description) = &self.function.description {
+            function["description"] = serde_json::json!(description);
+        }
+        serde_json::json!({ "type": self.tool_type, "function": function })
+    }
+}
+
+fn json_schema_properties_to_json(properties: &HashMap<String, Box<JSONSchemaDefine>>) -> Value {
+    Value::Object(properties.iter().map(|(name, define)| (name.clone(), json_schema_define_to_json(define))).collect())
+}
+
+fn json_schema_define_to_json(define: &JSONSchemaDefine) -> Value {
+    let mut json = serde_json::json!({});
+    if let Some(schema_type) = &define.schema_type {
+        json["type"] = serde_json::json!(schema_type);
+    }
+    if let Some(description) = &define.description {
+        json["description"] = serde_json::json!(description);
+    }
+    if let Some(enum_values) = &define.enum_values {
+        json["enum"] = serde_json::json!(enum_values);
+    }
+    if let Some(properties) = &define.properties {
+        json["properties"] = json_schema_properties_to_json(properties);
+    }
+    if let Some(required) = &define.required {
+        json["required"] = serde_json::json!(required);
+    }
+    if let Some(items) = &define.items {
+        json["items"] = json_schema_define_to_json(items);
+    }
+    json
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub enum ToolChoiceType {
     None,
@@ -132,6 +313,17 @@ impl Default for ChatCompletionReq {
             config: LLMPromptCellChatConfiguration {
                 import: None,
                 function_name: None,
+                provider: None,
+                engine: None,
+                redact_output: None,
+                timeout_ms: None,
+                retries: None,
+                max_output_bytes: None,
+                overflow: None,
+                llm_cache: None,
+                ttl: None,
+                context_from: None,
+                proxy: None,
                 model: Some(String::from("gpt-3.5-turbo")),
                 api_url: None,
                 frequency_penalty: None,
@@ -143,6 +335,8 @@ impl Default for ChatCompletionReq {
                 user: None,
                 seed: None,
                 top_p: None,
+                logprobs: None,
+                top_logprobs: None,
             },
             template_messages: Vec::new(),
             tool_choice: None,
@@ -170,6 +364,9 @@ pub struct ChatCompletionChoice {
     pub index: i32,
     pub logprobs: Option<Value>,
     pub finish_reason: String,
+    /// Explanation given by the model when it declines to answer
+    #[serde(default)]
+    pub refusal: Option<String>,
     pub tool_calls: Option<Vec<ChatCompletionToolCall>>,
 }
 
@@ -184,6 +381,66 @@ pub struct ChatCompletionRes {
     pub usage: Usage,
 }
 
+/// What the provider reported about a completion beyond its text, kept on the output of the prompt
+/// cell that requested it, see `ExecutionState::response_metadata`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelResponseMetadata {
+    pub id: String,
+    /// The model that produced the response, which may be more specific than the one requested
+    pub model: String,
+    pub choices: Vec<ModelChoiceMetadata>,
+    /// Tokens the provider reports the request used
+    #[serde(default)]
+    pub usage: Usage,
+    /// The key the request is stored under in the response cache, see `ResponseCache`
+    #[serde(default)]
+    pub cache_key: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelChoiceMetadata {
+    pub index: i32,
+    /// Why generation stopped, `length` when it reached `max_tokens`
+    pub finish_reason: String,
+    /// Whether the model declined to answer, or the provider filtered its answer
+    pub refused: bool,
+    pub refusal: Option<String>,
+    /// Log probabilities of the generated tokens, when the provider returned them
+    pub logprobs: Option<Value>,
+}
+
+impl ModelResponseMetadata {
+    pub fn from_response(res: &ChatCompletionRes) -> Self {
+        ModelResponseMetadata {
+            id: res.id.clone(),
+            model: res.model.clone(),
+            choices: res.choices.iter().map(|choice| ModelChoiceMetadata {
+                index: choice.index,
+                finish_reason: choice.finish_reason.clone(),
+                refused: choice.refusal.is_some() || choice.finish_reason == "content_filter",
+                refusal: choice.refusal.clone(),
+                logprobs: choice.logprobs.clone(),
+            }).collect(),
+            usage: res.usage.clone(),
+            cache_key: None,
+        }
+    }
+
+    /// The finish reason of the first choice
+    pub fn finish_reason(&self) -> Option<&str> {
+        self.choices.first().map(|choice| choice.finish_reason.as_str())
+    }
+
+    /// Whether any choice was cut off by the token limit
+    pub fn is_truncated(&self) -> bool {
+        self.choices.iter().any(|choice| choice.finish_reason == "length")
+    }
+
+    pub fn is_refused(&self) -> bool {
+        self.choices.iter().any(|choice| choice.refused)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EmbeddingReq {
     content: String,
@@ -253,7 +510,11 @@ pub async fn ai_llm_run_embedding_model(
 ) -> RkyvSerializedValue {
     let api_key = env::var("OPENAI_API_KEY").unwrap().to_string();
     let api_url_v1: &str = "https://api.openai.com/v1";
-    let model = OpenAIChatModel::new(api_url_v1.to_string(), api_key);
+    let proxy = execution_state.llm_proxies.get(&SupportedModelProviders::OpenAI).cloned();
+    let Ok(embedding_model) = OpenAIChatModel::new(api_url_v1.to_string(), api_key).with_proxy(proxy) else {
+        return RkyvSerializedValue::Null;
+    };
+    let model = CachedModel::new(embedding_model, execution_state.llm_cache.as_deref(), None);
     let data = template_data_payload_from_rkyv(&payload);
     let result = model.embed(EmbeddingReq {
         content: chidori_prompt_format::templating::templates::render_template_prompt(&template.source, &data, &HashMap::new()).unwrap(),
@@ -278,31 +539,44 @@ pub async fn ai_llm_run_embedding_model(
     }
 }
 
-fn input_signature_to_json_properties(input_signature: InputSignature) -> HashMap<String, Box<JSONSchemaDefine>> {
-    let mut properties = HashMap::new();
-    for (k, v) in input_signature.args {
-        properties.insert(k, Box::new(JSONSchemaDefine {
-            schema_type: Some(JSONSchemaType::String),
-            description: None,
-            enum_values: None,
-            properties: None,
-            required: None,
-            items: None,
-        }));
+/// Embed each of `texts` with an OpenAI embedding model, sending the requests through `proxy`
+pub async fn ai_llm_embed_texts(model: &str, texts: Vec<String>, proxy: Option<ProxyConfig>) -> anyhow::Result<Vec<Vec<f32>>> {
+    let api_key = env::var("OPENAI_API_KEY")
+        .map_err(|_| anyhow::anyhow!("OPENAI_API_KEY must be set to embed text with {}", model))?;
+    let client = OpenAIChatModel::new("https://api.openai.com/v1".to_string(), api_key).with_proxy(proxy)?;
+    let mut embeddings = Vec::with_capacity(texts.len());
+    for content in texts {
+        embeddings.push(client.embed(EmbeddingReq {
+            content,
+            model: model.to_string(),
+            frequency_penalty: None,
+            max_tokens: None,
+            presence_penalty: None,
+            stop: None,
+        }).await.map_err(anyhow::Error::msg)?);
     }
-    for (k, v) in input_signature.kwargs {
-        properties.insert(k, Box::new(JSONSchemaDefine {
-            schema_type: Some(JSONSchemaType::String),
-            description: None,
-            enum_values: None,
-            properties: None,
-            required: None,
-            items: None,
-        }));
+    Ok(embeddings)
+}
+
+fn input_type_to_json_schema_type(ty: &Option<InputType>) -> JSONSchemaType {
+    match ty {
+        Some(InputType::Number) => JSONSchemaType::Integer,
+        Some(InputType::Float) => JSONSchemaType::Number,
+        Some(InputType::Boolean) => JSONSchemaType::Boolean,
+        Some(InputType::Array) => JSONSchemaType::Array,
+        Some(InputType::Object) => JSONSchemaType::Object,
+        // Inputs of unknown type are described as strings
+        Some(InputType::String) | Some(InputType::Function) | None => JSONSchemaType::String,
     }
-    for (k, v) in input_signature.globals {
+}
+
+fn input_signature_to_json_properties(input_signature: InputSignature) -> HashMap<String, Box<JSONSchemaDefine>> {
+    let mut properties = HashMap::new();
+    for (k, v) in input_signature.args.into_iter()
+        .chain(input_signature.kwargs)
+        .chain(input_signature.globals) {
         properties.insert(k, Box::new(JSONSchemaDefine {
-            schema_type: Some(JSONSchemaType::String),
+            schema_type: Some(input_type_to_json_schema_type(&v.ty)),
             description: None,
             enum_values: None,
             properties: None,
@@ -313,6 +587,18 @@ fn input_signature_to_json_properties(input_signature: InputSignature) -> HashMa
     properties
 }
 
+/// The text of every choice in a response, tool calls are written as `name(arguments)`
+fn audit_response_text(res: &ChatCompletionRes) -> String {
+    res.choices.iter().map(|choice| match &choice.tool_calls {
+        Some(tool_calls) => tool_calls.iter().map(|call| format!(
+            "{}({})",
+            call.function.name.as_deref().unwrap_or_default(),
+            call.function.arguments.as_ref().map(|a| serialized_value_to_json_value(a).to_string()).unwrap_or_default()
+        )).collect::<Vec<_>>().join("\n"),
+        None => choice.text.clone().unwrap_or_default(),
+    }).collect::<Vec<_>>().join("\n")
+}
+
 pub async fn ai_llm_run_chat_model(
     execution_state: &ExecutionState,
     payload: RkyvSerializedValue,
@@ -320,30 +606,65 @@ pub async fn ai_llm_run_chat_model(
     name: Option<String>,
     is_function_invocation: bool,
     configuration: LLMPromptCellChatConfiguration
-) -> anyhow::Result<(Result<RkyvSerializedValue, ExecutionStateErrors>, Option<ExecutionState>)> {
+) -> anyhow::Result<(Result<RkyvSerializedValue, ExecutionStateErrors>, Option<ExecutionState>, Option<ModelResponseMetadata>)> {
+    let api_url_v1 = configuration.api_url.clone()
+        .unwrap_or_else(|| configuration.provider.clone().unwrap_or_default().default_api_url().to_string());
+    let model = CachedModel::new(
+        OpenAIChatModel::new(api_url_v1, "".to_string()).with_proxy(resolve_proxy(execution_state, &configuration))?,
+        execution_state.llm_cache.as_deref(),
+        Some(&configuration),
+    );
+    ai_llm_run_chat_model_with(&model, execution_state, payload, role_blocks, name, is_function_invocation, configuration).await
+}
+
+/// Run a prompt against the given model, along with what the provider reported about its response
+pub async fn ai_llm_run_chat_model_with(
+    model: &(impl ChatModelBatch + Sync),
+    execution_state: &ExecutionState,
+    payload: RkyvSerializedValue,
+    role_blocks: Vec<(ChatModelRoles, Option<TemplateWithSource>)>,
+    name: Option<String>,
+    is_function_invocation: bool,
+    configuration: LLMPromptCellChatConfiguration
+) -> anyhow::Result<(Result<RkyvSerializedValue, ExecutionStateErrors>, Option<ExecutionState>, Option<ModelResponseMetadata>)> {
     debug!("Executing ai_llm_run_chat_model");
     let mut template_messages: Vec<TemplateMessage> = Vec::new();
-    let data = template_data_payload_from_rkyv(&payload);
+    // Prompts may include the program's template cells as partials
+    let templates = TemplateLibrary::from_execution_state(execution_state).strict(false);
+    let data = match prompt_template_data(execution_state, &templates, &payload, &configuration).await {
+        Ok(data) => data,
+        Err(e) => return Ok((Err(ExecutionStateErrors::AnyhowError(e.to_string())), None, None)),
+    };
 
     for (a, b) in &role_blocks.clone() {
+        let content = match templates.render_source_with_engine(&b.as_ref().unwrap().source, &data, configuration.engine.unwrap_or_default()) {
+            Ok(content) => content,
+            Err(e) => return Ok((Err(ExecutionStateErrors::TemplateRenderFailure(e.to_string())), None, None)),
+        };
         template_messages.push(TemplateMessage {
             role: match a {
                 ChatModelRoles::User => MessageRole::User,
                 ChatModelRoles::System => MessageRole::System,
                 ChatModelRoles::Assistant => MessageRole::Assistant,
             },
-            content: chidori_prompt_format::templating::templates::render_template_prompt(&b.as_ref().unwrap().source, &data, &HashMap::new()).unwrap(),
+            content,
             name: None,
             function_call: None,
         });
     }
 
     let tools = infer_tool_usage_from_imports(execution_state, &configuration.import);
+    let audit_record = execution_state.prompt_audit.as_ref().map(|_| PromptAuditRecord::new(
+        execution_state.evaluating_operation_id,
+        name.clone(),
+        configuration.model.clone(),
+        template_messages.iter().map(|m| PromptAuditMessage {
+            role: format!("{:?}", m.role).to_lowercase(),
+            content: m.content.clone(),
+        }).collect(),
+    ));
 
-    let api_url_v1 = configuration.api_url.clone();
-    let c = crate::library::std::ai::llm::openai::OpenAIChatModel::new(api_url_v1.unwrap_or("http://localhost:4000/v1".to_string()), "".to_string());
-
-    let result = c.batch(ChatCompletionReq {
+    let req = ChatCompletionReq {
         config: configuration.clone(),
         template_messages,
         tool_choice: None,
@@ -352,39 +673,93 @@ pub async fn ai_llm_run_chat_model(
         } else {
             Some(tools)
         },
-    }).await;
+    };
+    let cache_key = chat_request_key(&req)?;
+    let result = model.batch(req).await;
 
-    if let Err(e) = result {
-        return Ok((Result::Err(ExecutionStateErrors::AnyhowError(e)), None))
+    if let (Some(log), Some(mut record)) = (&execution_state.prompt_audit, audit_record) {
+        match &result {
+            Ok(res) => record.response = Some(audit_response_text(res)),
+            Err(e) => record.error = Some(e.clone()),
+        }
+        log.record(record)?;
     }
-    let Ok(ChatCompletionRes { choices, .. }) = result else { unreachable!() };
 
+    let res = match result {
+        Ok(res) => res,
+        Err(e) => return Ok((Result::Err(ExecutionStateErrors::AnyhowError(e)), None, None)),
+    };
+    let metadata = ModelResponseMetadata { cache_key: Some(cache_key), ..ModelResponseMetadata::from_response(&res) };
+    let (output, state) = resolve_chat_choices(execution_state, res.choices, name, is_function_invocation).await?;
+    Ok((output, state, Some(metadata)))
+}
+
+/// The data a prompt is rendered with, the inputs of the cell along with the memories retrieved
+/// into `context` when the prompt declares `context_from`
+pub async fn prompt_template_data(
+    execution_state: &ExecutionState,
+    templates: &TemplateLibrary,
+    payload: &RkyvSerializedValue,
+    configuration: &LLMPromptCellChatConfiguration,
+) -> anyhow::Result<Value> {
+    let mut data = template_data_payload_from_rkyv(payload);
+    let Some(context_from) = &configuration.context_from else {
+        return Ok(data);
+    };
+    let query = templates.render_source_with_engine(&context_from.query, &data, configuration.engine.unwrap_or_default())?;
+    let matches = execution_state.query_memory(
+        &context_from.store,
+        MemoryQuery::Text(query),
+        context_from.top_k as usize,
+        &serde_json::Map::new(),
+    ).await?;
+    let context = matches.iter().filter_map(|m| m.text.as_deref()).collect::<Vec<_>>().join("\n\n");
+    if !data.is_object() {
+        data = Value::Object(Default::default());
+    }
+    data.as_object_mut().unwrap().insert(CONTEXT_VARIABLE.to_string(), Value::String(context));
+    Ok(data)
+}
 
-    let execution_state_handle = Arc::new(Mutex::new(execution_state.clone()));
+/// The output of a prompt cell from the choices of the model, dispatching the tool calls they request.
+/// Tool calls are dispatched in sequence, each continuing from the state the previous resolved to.
+pub async fn resolve_chat_choices(
+    execution_state: &ExecutionState,
+    choices: Vec<ChatCompletionChoice>,
+    name: Option<String>,
+    is_function_invocation: bool,
+) -> anyhow::Result<(Result<RkyvSerializedValue, ExecutionStateErrors>, Option<ExecutionState>)> {
+    let mut current_execution_state = execution_state.clone();
     let mut results = vec![];
     for choice in choices {
         let mut result_map = HashMap::new();
         match choice.tool_calls {
             Some(tool_calls) => {
+                // Text accompanying tool calls is the reasoning that led to them
+                if let Some(text) = choice.text.as_ref().filter(|text| !text.trim().is_empty()) {
+                    execution_state.record_agent_trace_step(AgentTraceStepKind::Thought, text.clone());
+                }
                 for tool_call in tool_calls {
                     if let Some(function_name) = tool_call.function.name {
                         let args = tool_call.function.arguments.unwrap_or(RkyvSerializedValue::Null);
+                        execution_state.record_agent_trace_step(
+                            AgentTraceStepKind::ToolCall,
+                            format!("{}({})", function_name, serialized_value_to_json_value(&args)),
+                        );
                         let args = RkyvObjectBuilder::new().insert_value("kwargs", args).build();
 
 
-                        let mut new_exec_state = {
-                            let mut exec_state = execution_state_handle.lock().unwrap();
-                            let exec_state_clone = exec_state.clone();
-                            exec_state_clone
-                        };
-                        let (dispatch_result, mut result_execution_state) = new_exec_state.dispatch(&function_name, args, None).await?;
+                        let (dispatch_result, result_execution_state) = current_execution_state.dispatch(&function_name, args, None).await?;
+                        execution_state.record_agent_trace_step(AgentTraceStepKind::ToolResult, match &dispatch_result {
+                            Ok(value) => serialized_value_to_json_value(value).to_string(),
+                            Err(e) => e.to_string(),
+                        });
 
                         if !dispatch_result.is_ok() {
                             return Ok((dispatch_result, Some(result_execution_state)));
                         }
 
-                        let mut exec_state = execution_state_handle.lock().unwrap();
-                        std::mem::swap(&mut *exec_state, &mut result_execution_state);
+                        current_execution_state = result_execution_state;
 
                         result_map.insert(function_name, dispatch_result.unwrap());
                     }
@@ -397,6 +772,7 @@ pub async fn ai_llm_run_chat_model(
                 results.push(result);
             }
             None => {
+                execution_state.record_agent_trace_step(AgentTraceStepKind::FinalAnswer, choice.text.clone().unwrap_or_default());
                 let result = if is_function_invocation {
                     RkyvSerializedValue::String(choice.text.as_ref().unwrap().clone())
                 } else {
@@ -416,8 +792,7 @@ pub async fn ai_llm_run_chat_model(
     } else {
         RkyvSerializedValue::Array(results)
     };
-    let mut exec_state = execution_state_handle.lock().unwrap().clone();
-    Ok((Ok(out), Some(exec_state)))
+    Ok((Ok(out), Some(current_execution_state)))
 }
 
 pub async fn ai_llm_code_generation_chat_model(
@@ -430,6 +805,7 @@ pub async fn ai_llm_code_generation_chat_model(
 ) -> anyhow::Result<(RkyvSerializedValue, Option<ExecutionState>)> {
     let mut template_messages: Vec<TemplateMessage> = Vec::new();
     let data = template_data_payload_from_rkyv(&payload);
+    let templates = TemplateLibrary::from_execution_state(execution_state).strict(false);
 
     for (a, b) in &role_blocks.clone() {
         template_messages.push(TemplateMessage {
@@ -438,19 +814,43 @@ pub async fn ai_llm_code_generation_chat_model(
                 ChatModelRoles::System => MessageRole::System,
                 ChatModelRoles::Assistant => MessageRole::Assistant,
             },
-            content: chidori_prompt_format::templating::templates::render_template_prompt(&b.as_ref().unwrap().source, &data, &HashMap::new()).unwrap(),
+            content: templates.render_source(&b.as_ref().unwrap().source, &data)?,
+            name: None,
+            function_call: None,
+        });
+    }
+
+    // Previously generated code failed, ask for a corrected version
+    let generating_operation_id = execution_state.evaluating_operation_id;
+    if let Some(CodeGenRetryState { last_error: Some(error), .. }) = execution_state.code_gen_retries.get(&generating_operation_id) {
+        template_messages.push(TemplateMessage {
+            role: MessageRole::User,
+            content: format!("The code you previously generated failed with the following error. Respond with a corrected version.\n{}", error),
             name: None,
             function_call: None,
         });
     }
 
-    let api_url_v1 = configuration.api_url.unwrap_or("http://localhost:4000/v1".to_string());
-    let c = crate::library::std::ai::llm::openai::OpenAIChatModel::new(api_url_v1, "".to_string());
+    let api_url_v1 = configuration.api_url.clone().unwrap_or("http://localhost:4000/v1".to_string());
+    // Code generation is served by the OpenAI compatible endpoint, and so through its proxy
+    let proxy = execution_state.llm_proxies.get(&SupportedModelProviders::OpenAI).cloned();
+    let c = CachedModel::new(OpenAIChatModel::new(api_url_v1, "".to_string()).with_proxy(proxy)?, execution_state.llm_cache.as_deref(), None);
 
     let result = c.batch(ChatCompletionReq {
         config: LLMPromptCellChatConfiguration {
             import: None,
             function_name: None,
+            provider: None,
+            engine: None,
+            redact_output: None,
+            timeout_ms: None,
+            retries: None,
+            max_output_bytes: None,
+            overflow: None,
+            llm_cache: None,
+            ttl: None,
+            context_from: None,
+            proxy: None,
             model: configuration.model.clone(),
             api_url: None,
             frequency_penalty: configuration.frequency_penalty.clone(),
@@ -462,6 +862,8 @@ pub async fn ai_llm_code_generation_chat_model(
             user: configuration.user.clone(),
             seed: configuration.seed.clone(),
             top_p: configuration.top_p.clone(),
+            logprobs: None,
+            top_logprobs: None,
         },
         template_messages,
         tool_choice: None,
@@ -473,8 +875,6 @@ pub async fn ai_llm_code_generation_chat_model(
         for choice in choices {
             let text = choice.text.as_ref().unwrap().clone();
             println!("Code generation cell run, returning this payload: {}", &text);
-            let mut new_execution_state = execution_state.clone();
-
             let mut cells = vec![];
             crate::sdk::md::extract_code_blocks(&text)
                 .iter()
@@ -482,10 +882,11 @@ pub async fn ai_llm_code_generation_chat_model(
                 .for_each(|block| { cells.push(block); });
             cells.sort();
 
-            for cell in cells {
-                let (s, _) = new_execution_state.update_operation(cell, Uuid::now_v7()).await?;
-                new_execution_state = s;
-            }
+            let new_execution_state = execution_state.apply_generated_cells(
+                generating_operation_id,
+                cells,
+                configuration.max_attempts.unwrap_or(1),
+            ).await?;
 
             return Ok((RkyvSerializedValue::String(text.clone()), Some(new_execution_state)));
         }
@@ -502,18 +903,8 @@ pub fn infer_tool_usage_from_imports(execution_state: &ExecutionState, imports:
         let mut imports = imports.clone();
         for import in imports {
             let function = execution_state.function_name_to_metadata.get(&import).unwrap();
-            tools.push(Tool {
-                tool_type: "function".to_string(),
-                function: Function {
-                    name: import.to_string(),
-                    description: None,
-                    parameters: FunctionParameters {
-                        schema_type: JSONSchemaType::Object,
-                        properties: Some(input_signature_to_json_properties(function.input_signature.clone())),
-                        required: None,
-                    },
-                },
-            });
+            let description = execution_state.cell_descriptions.get(&function.operation_id).cloned();
+            tools.push(Tool::from_function(&import, function.input_signature.clone()).with_description(description));
         }
     }
     tools
@@ -541,7 +932,12 @@ mod test {
     use uuid::Uuid;
     use crate::cells::{CellTypes, CodeCell, LLMPromptCellChatConfiguration, SupportedLanguage, TextRange};
     use crate::execution::execution::ExecutionState;
-    use crate::library::std::ai::llm::infer_tool_usage_from_imports;
+    use crate::library::std::ai::llm::{ai_llm_run_chat_model_with, infer_tool_usage_from_imports, resolve_chat_choices, ChatCompletionChoice, ChatCompletionReq, ChatCompletionRes, ChatCompletionToolCall, ChatCompletionToolCallFunction, ChatModelBatch, Usage};
+    use async_trait::async_trait;
+    use chidori_prompt_format::templating::jinja::jinja_template_as_user_message;
+    use crate::execution::primitives::operation::OperationFnOutput;
+    use crate::execution::primitives::agent_trace::{AgentTrace, AgentTraceStepKind};
+    use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue};
 
     #[tokio::test]
     async fn test_tool_usage_inference() -> anyhow::Result<()> {
@@ -559,6 +955,13 @@ mod test {
                             return 100 + await demo_second_function_call()
                         "#}),
             function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
         }, TextRange::default()), id_a)?;
         let (mut state, _) = state.update_operation(CellTypes::Code(CodeCell {
             backing_file_reference: None,
@@ -569,6 +972,13 @@ mod test {
                             return a + b + c + d
                         "#}),
             function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
         }, TextRange::default()), id_b)?;
 
         insta::with_settings!({
@@ -586,4 +996,274 @@ mod test {
         });
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn tool_call(name: &str, arguments: RkyvSerializedValue) -> ChatCompletionToolCall {
+        ChatCompletionToolCall {
+            id: format!("call_{}", name),
+            ty: "function".to_string(),
+            function: ChatCompletionToolCallFunction { name: Some(name.to_string()), arguments: Some(arguments) },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_call_loop_records_agent_trace() -> anyhow::Result<()> {
+        let state = ExecutionState::new_with_random_id();
+        let (mut state, _) = state.update_operation(CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: None,
+            language: SupportedLanguage::PyO3,
+            source_code: String::from(indoc! {r#"
+                        def add(x, y):
+                            return x + y
+
+                        def shout(text):
+                            return text.upper()
+                        "#}),
+            function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
+        }, TextRange::default()), Uuid::now_v7())?;
+        let trace = AgentTrace::new(Uuid::now_v7(), None);
+        state.agent_trace = Some(trace.clone());
+
+        // The model calls both tools in one response
+        let choices = vec![ChatCompletionChoice {
+            text: None,
+            index: 0,
+            logprobs: None,
+            finish_reason: "tool_calls".to_string(),
+            refusal: None,
+            tool_calls: Some(vec![
+                tool_call("add", RkyvObjectBuilder::new().insert_number("x", 2).insert_number("y", 3).build()),
+                tool_call("shout", RkyvObjectBuilder::new().insert_string("text", "hi".to_string()).build()),
+            ]),
+        }];
+        let (result, _) = resolve_chat_choices(&state, choices, Some("agent".to_string()), false).await?;
+        assert_eq!(result, Ok(RkyvObjectBuilder::new()
+            .insert_value("agent", RkyvObjectBuilder::new()
+                .insert_number("add", 5)
+                .insert_string("shout", "HI".to_string())
+                .build())
+            .build()));
+
+        let steps = trace.steps();
+        assert_eq!(
+            steps.iter().map(|step| step.kind.clone()).collect::<Vec<_>>(),
+            vec![AgentTraceStepKind::ToolCall, AgentTraceStepKind::ToolResult, AgentTraceStepKind::ToolCall, AgentTraceStepKind::ToolResult]
+        );
+        assert_eq!(steps[0].content, r#"add({"x":2,"y":3})"#);
+        assert_eq!(steps[1].content, "5");
+        assert_eq!(steps[3].content, r#""HI""#);
+        Ok(())
+    }
+
+    struct TruncatingModel;
+
+    #[async_trait]
+    impl ChatModelBatch for TruncatingModel {
+        async fn batch(&self, _: ChatCompletionReq) -> Result<ChatCompletionRes, String> {
+            Ok(ChatCompletionRes {
+                id: "chatcmpl-1".to_string(),
+                object: "chat.completion".to_string(),
+                created: 0,
+                model: "gpt-4o-2024-08-06".to_string(),
+                choices: vec![ChatCompletionChoice {
+                    text: Some("Once upon a".to_string()),
+                    index: 0,
+                    logprobs: Some(serde_json::json!({"content": [{"token": "Once", "logprob": -0.1}]})),
+                    finish_reason: "length".to_string(),
+                    refusal: None,
+                    tool_calls: None,
+                }],
+                usage: Usage::default(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_response_metadata_is_kept_on_the_output() -> anyhow::Result<()> {
+        let mut state = ExecutionState::new_with_random_id();
+        let role_blocks = jinja_template_as_user_message("Tell me a story");
+        let configuration = LLMPromptCellChatConfiguration { model: Some("gpt-4o".to_string()), max_tokens: Some(3), ..Default::default() };
+        let (output, _, metadata) = ai_llm_run_chat_model_with(
+            &TruncatingModel, &state, RkyvSerializedValue::Null, role_blocks, Some("story".to_string()), false, configuration
+        ).await?;
+        assert_eq!(output, Ok(RkyvObjectBuilder::new().insert_string("story", "Once upon a".to_string()).build()));
+
+        let id = Uuid::now_v7();
+        state.state_insert(id, OperationFnOutput {
+            response_metadata: metadata,
+            ..OperationFnOutput::with_value(output.unwrap())
+        });
+        let metadata = state.response_metadata(&id).expect("metadata of the prompt");
+        assert_eq!(metadata.finish_reason(), Some("length"));
+        assert!(metadata.is_truncated());
+        assert!(!metadata.is_refused());
+        assert_eq!(metadata.model, "gpt-4o-2024-08-06");
+        assert!(metadata.choices[0].logprobs.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_chat_model_routes_through_proxy() -> anyhow::Result<()> {
+        use base64::Engine;
+        use std::sync::{Arc, Mutex};
+        use crate::cells::ProxyConfig;
+        use crate::library::std::ai::llm::openai::OpenAIChatModel;
+        use crate::library::std::ai::llm::{MessageRole, TemplateMessage};
+
+        // The proxy answers requests itself, recording the target and credentials of each
+        let received: Arc<Mutex<Vec<(String, Option<String>)>>> = Arc::new(Mutex::new(vec![]));
+        let recorded = received.clone();
+        let app = axum::Router::new().fallback(move |request: axum::extract::Request| {
+            let recorded = recorded.clone();
+            async move {
+                let credentials = request.headers().get("proxy-authorization")
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| value.to_string());
+                recorded.lock().unwrap().push((request.uri().to_string(), credentials));
+                axum::Json(serde_json::json!({
+                    "id": "chatcmpl-1",
+                    "object": "chat.completion",
+                    "created": 0,
+                    "model": "gpt-3.5-turbo",
+                    "choices": [{ "index": 0, "message": { "role": "assistant", "content": "hello" }, "finish_reason": "stop" }],
+                    "usage": { "prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2 },
+                }))
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let proxy_url = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        std::env::set_var("CHIDORI_TEST_PROXY_PASSWORD", "secret");
+        let proxy = ProxyConfig {
+            url: proxy_url,
+            username: Some("chidori".to_string()),
+            password_env: Some("CHIDORI_TEST_PROXY_PASSWORD".to_string()),
+        };
+        // The endpoint does not resolve, the response can only come from the proxy
+        let model = OpenAIChatModel::new("http://llm.invalid/v1".to_string(), "".to_string()).with_proxy(Some(proxy))?;
+        let res = model.batch(ChatCompletionReq {
+            template_messages: vec![TemplateMessage {
+                role: MessageRole::User,
+                content: "Say hello".to_string(),
+                name: None,
+                function_call: None,
+            }],
+            ..ChatCompletionReq::default()
+        }).await.map_err(anyhow::Error::msg)?;
+        assert_eq!(res.choices[0].text, Some("hello".to_string()));
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0, "http://llm.invalid/v1/chat/completions");
+        let expected = format!("Basic {}", base64::engine::general_purpose::STANDARD.encode("chidori:secret"));
+        assert_eq!(received[0].1, Some(expected));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch_requests_and_maps_logprobs_and_refusal() -> anyhow::Result<()> {
+        use std::sync::{Arc, Mutex};
+        use crate::library::std::ai::llm::openai::OpenAIChatModel;
+
+        let received: Arc<Mutex<Vec<serde_json::Value>>> = Arc::new(Mutex::new(vec![]));
+        let recorded = received.clone();
+        let app = axum::Router::new().fallback(move |axum::Json(body): axum::Json<serde_json::Value>| {
+            let recorded = recorded.clone();
+            async move {
+                recorded.lock().unwrap().push(body);
+                axum::Json(serde_json::json!({
+                    "id": "chatcmpl-1",
+                    "object": "chat.completion",
+                    "created": 0,
+                    "model": "gpt-3.5-turbo",
+                    "choices": [{
+                        "index": 0,
+                        "message": { "role": "assistant", "content": null, "refusal": "I can't help with that." },
+                        "logprobs": { "content": [{ "token": "I", "logprob": -0.2, "top_logprobs": [] }] },
+                        "finish_reason": "stop",
+                    }],
+                    "usage": { "prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2 },
+                }))
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let api_url = format!("http://{}/v1", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let model = OpenAIChatModel::new(api_url, "".to_string());
+        let res = model.batch(ChatCompletionReq {
+            config: LLMPromptCellChatConfiguration { logprobs: Some(true), top_logprobs: Some(2), ..Default::default() },
+            ..ChatCompletionReq::default()
+        }).await.map_err(anyhow::Error::msg)?;
+        assert_eq!(res.choices[0].refusal.as_deref(), Some("I can't help with that."));
+        assert_eq!(res.choices[0].logprobs, Some(serde_json::json!({ "content": [{ "token": "I", "logprob": -0.2, "top_logprobs": [] }] })));
+        assert!(ModelResponseMetadata::from_response(&res).is_refused());
+
+        let received = received.lock().unwrap();
+        assert_eq!(received[0]["logprobs"], serde_json::json!(true));
+        assert_eq!(received[0]["top_logprobs"], serde_json::json!(2));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_https_chat_model_tunnels_through_proxy() -> anyhow::Result<()> {
+        use base64::Engine;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use crate::cells::ProxyConfig;
+        use crate::library::std::ai::llm::openai::OpenAIChatModel;
+
+        // The proxy records the head of the request that opens the tunnel, then refuses it
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let proxy_url = format!("http://{}", listener.local_addr()?);
+        let head = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await?;
+            let mut head = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+                let read = socket.read(&mut buf).await?;
+                if read == 0 { break; }
+                head.extend_from_slice(&buf[..read]);
+            }
+            socket.write_all(b"HTTP/1.1 403 Forbidden\r\ncontent-length: 0\r\n\r\n").await?;
+            anyhow::Ok(String::from_utf8_lossy(&head).to_string())
+        });
+
+        std::env::set_var("CHIDORI_TEST_TUNNEL_PASSWORD", "secret");
+        let proxy = ProxyConfig {
+            url: proxy_url,
+            username: Some("chidori".to_string()),
+            password_env: Some("CHIDORI_TEST_TUNNEL_PASSWORD".to_string()),
+        };
+        let model = OpenAIChatModel::new("https://llm.invalid/v1".to_string(), "".to_string()).with_proxy(Some(proxy))?;
+        assert!(model.batch(ChatCompletionReq::default()).await.is_err());
+
+        let head = head.await??;
+        assert!(head.starts_with("CONNECT llm.invalid:443 "), "{}", head);
+        let expected = format!("Basic {}", base64::engine::general_purpose::STANDARD.encode("chidori:secret"));
+        assert!(head.lines().any(|line| line.eq_ignore_ascii_case(&format!("proxy-authorization: {}", expected))), "{}", head);
+        Ok(())
+    }
+
+    #[test]
+    fn test_prompt_proxy_overrides_provider_proxy() {
+        use crate::cells::{ProxyConfig, SupportedModelProviders};
+        use crate::library::std::ai::llm::resolve_proxy;
+
+        let proxy = |url: &str| ProxyConfig { url: url.to_string(), username: None, password_env: None };
+        let mut state = ExecutionState::new_with_random_id();
+        state.llm_proxies.insert(SupportedModelProviders::Ollama, proxy("socks5://ollama.proxy:1080"));
+        let ollama = LLMPromptCellChatConfiguration { provider: Some(SupportedModelProviders::Ollama), ..Default::default() };
+        assert_eq!(resolve_proxy(&state, &ollama), Some(proxy("socks5://ollama.proxy:1080")));
+        assert_eq!(resolve_proxy(&state, &LLMPromptCellChatConfiguration::default()), None);
+        let own = LLMPromptCellChatConfiguration { proxy: Some(proxy("http://prompt.proxy:3128")), ..ollama };
+        assert_eq!(resolve_proxy(&state, &own), Some(proxy("http://prompt.proxy:3128")));
+    }
+}
@@ -7,7 +7,7 @@ use crate::library::std::ai::llm::openai::OpenAIChatModel;
 use crate::library::std::ai::llm::{ChatCompletionReq, ChatCompletionRes, ChatModelBatch, JSONSchemaDefine, JSONSchemaType, Tool, ToolChoiceType};
 
 use openai_api_rs::v1::chat_completion::{
-    ChatCompletionMessage, ChatCompletionRequest, MessageRole,
+    ChatCompletionMessage, ChatCompletionRequest, ChatCompletionResponse, MessageRole,
 };
 use crate::cells::LLMPromptCellChatConfiguration;
 use crate::execution::primitives::serialized_value::json_value_to_serialized_value;
@@ -42,53 +42,64 @@ impl ChatModelBatch for OpenAIChatModel {
             }
         }
 
-        let req = Self::chat_completion_req_to_openai_req(&chat_completion_req);
-        self.client
-            .chat_completion(req)
-            .await
-            .map(|res| {
-                ChatCompletionRes {
-                id: res.id,
-                object: res.object,
-                created: res.created,
-                model: res.model,
-                choices: res
-                    .choices
-                    .iter()
-                    .map(|c| llm::ChatCompletionChoice {
-                        text: c.message.content.clone(),
-                        index: 0,
-                        logprobs: None,
-                        finish_reason: "".to_string(),
-                        tool_calls: c.message.tool_calls.clone().map(|tool_calls| {
-                            tool_calls
-                                .iter()
-                                .map(|tool_call| {
-                                    llm::ChatCompletionToolCall {
-                                        id: tool_call.id.clone(),
-                                        ty: "function".to_string(),
-                                        function: llm::ChatCompletionToolCallFunction {
-                                            name: tool_call.function.name.clone(),
-                                            arguments: tool_call.function.arguments
-                                                .as_ref()
-                                                .map(|x| {dbg!(&x); x })
-                                                .map(|x| x.as_str())
-                                                .map(|x| serde_json::from_str(x).unwrap())
-                                                .map(|x| json_value_to_serialized_value(&x)),
-                                        }
+        let body = Self::chat_completion_req_to_body(&chat_completion_req, false);
+        let raw = self.post::<serde_json::Value>("chat/completions", &body).await?;
+        let res: ChatCompletionResponse = serde_json::from_value(raw.clone())
+            .map_err(|error| format!("API response error: {}", error))?;
+        // openai_api_rs does not model the log probabilities or refusal of a choice
+        let raw_choice = |index: usize| raw.get("choices").and_then(|choices| choices.get(index));
+        let logprobs = |index: usize| raw_choice(index)
+            .and_then(|choice| choice.get("logprobs"))
+            .filter(|logprobs| !logprobs.is_null())
+            .cloned();
+        let refusal = |index: usize| raw_choice(index)
+            .and_then(|choice| choice.get("message"))
+            .and_then(|message| message.get("refusal"))
+            .and_then(|refusal| refusal.as_str())
+            .map(|refusal| refusal.to_string());
+        Ok(ChatCompletionRes {
+            id: res.id,
+            object: res.object,
+            created: res.created,
+            model: res.model,
+            choices: res
+                .choices
+                .iter()
+                .enumerate()
+                .map(|(i, c)| llm::ChatCompletionChoice {
+                    text: c.message.content.clone(),
+                    index: c.index as i32,
+                    logprobs: logprobs(i),
+                    finish_reason: c.finish_reason.as_ref().map_or(String::new(), |reason| format!("{:?}", reason)),
+                    refusal: refusal(i),
+                    tool_calls: c.message.tool_calls.clone().map(|tool_calls| {
+                        tool_calls
+                            .iter()
+                            .map(|tool_call| {
+                                llm::ChatCompletionToolCall {
+                                    id: tool_call.id.clone(),
+                                    ty: "function".to_string(),
+                                    function: llm::ChatCompletionToolCallFunction {
+                                        name: tool_call.function.name.clone(),
+                                        arguments: tool_call.function.arguments
+                                            .as_ref()
+                                            .map(|x| {dbg!(&x); x })
+                                            .map(|x| x.as_str())
+                                            .map(|x| serde_json::from_str(x).unwrap())
+                                            .map(|x| json_value_to_serialized_value(&x)),
                                     }
-                                })
-                                .collect()
-                        }),
-                    })
-                    .collect(),
-                usage: llm::Usage {
-                    prompt_tokens: res.usage.prompt_tokens,
-                    completion_tokens: res.usage.completion_tokens,
-                    total_tokens: res.usage.total_tokens,
-                },
-            }})
-            .map_err(|e| e.to_string())
+                                }
+                            })
+                            .collect()
+                    }),
+                })
+                .collect(),
+            usage: llm::Usage {
+                prompt_tokens: res.usage.prompt_tokens,
+                completion_tokens: res.usage.completion_tokens,
+                total_tokens: res.usage.total_tokens,
+            },
+        })
     }
 }
 
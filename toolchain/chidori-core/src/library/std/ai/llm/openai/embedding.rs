@@ -27,11 +27,12 @@ impl EmbeddingModel for OpenAIChatModel {
             dimensions: None,
             user: None,
         };
-        self.client
-            .embedding(req)
-            .await
-            .map(|res| res.data.first().unwrap().embedding.clone())
-            .map_err(|e| e.to_string())
+        self.post::<EmbeddingResponse>("embeddings", &req)
+            .await?
+            .data
+            .first()
+            .map(|embedding| embedding.embedding.clone())
+            .ok_or_else(|| "The response contained no embedding".to_string())
     }
 }
 
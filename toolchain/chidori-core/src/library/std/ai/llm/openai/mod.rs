@@ -3,24 +3,59 @@ pub mod streaming;
 mod embedding;
 
 use std::collections::HashMap;
-use openai_api_rs::v1::api::OpenAIClient;
 use std::env;
 use openai_api_rs::v1::chat_completion::{ChatCompletionMessage, ChatCompletionRequest, MessageRole};
-use crate::cells::LLMPromptCellChatConfiguration;
+use crate::cells::{LLMPromptCellChatConfiguration, ProxyConfig};
 use crate::library::std::ai::llm;
 use crate::library::std::ai::llm::{ChatCompletionReq, JSONSchemaDefine, JSONSchemaType, Tool, ToolChoiceType};
 
 pub struct OpenAIChatModel {
     api_url: String,
     api_key: String,
-    client: OpenAIClient,
+    proxy: Option<ProxyConfig>,
 }
 
 impl OpenAIChatModel {
     // TODO: remove api_key parameter, expect usage of a proxy
     pub fn new(api_url: String, api_key: String) -> Self {
-        let client = OpenAIClient::new_with_endpoint(api_url.clone(), api_key.clone());
-        Self { api_url, client, api_key }
+        Self { api_url, api_key, proxy: None }
+    }
+
+    /// Send requests through the given proxy
+    pub fn with_proxy(mut self, proxy: Option<ProxyConfig>) -> anyhow::Result<Self> {
+        // Resolve the credentials of the proxy now, rather than failing on the first request
+        proxy.as_ref().map(|proxy| proxy.to_reqwest()).transpose()?;
+        self.proxy = proxy;
+        Ok(self)
+    }
+
+    /// A client routed through the proxy of this model, for plain http and https endpoints alike.
+    /// The client of openai_api_rs is not used since it only proxies plain http requests.
+    fn http_client(&self) -> anyhow::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(proxy.to_reqwest()?);
+        }
+        Ok(builder.build()?)
+    }
+
+    /// Post `body` to `path` under the endpoint of this model, parsing the response as `T`
+    async fn post<T: serde::de::DeserializeOwned>(&self, path: &str, body: &impl serde::Serialize) -> Result<T, String> {
+        let client = self.http_client().map_err(|e| e.to_string())?;
+        let response = client
+            .post(format!("{}/{}", self.api_url, path))
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(body)
+            .send()
+            .await
+            .map_err(|error| format!("API request error: {}", error))?;
+        let status = response.status();
+        if status.is_success() {
+            response.json::<T>().await.map_err(|error| format!("API response error: {}", error))
+        } else {
+            let error_text = response.text().await.unwrap_or_else(|_| String::from("Unknown error"));
+            Err(format!("{}: {}", status, error_text))
+        }
     }
 
     pub fn chat_completion_req_to_openai_req(chat_completion_req: &ChatCompletionReq) -> ChatCompletionRequest {
@@ -61,6 +96,25 @@ impl OpenAIChatModel {
         }
     }
 
+    /// The body of a chat completion request, with the options openai_api_rs does not model
+    pub fn chat_completion_req_to_body(chat_completion_req: &ChatCompletionReq, stream: bool) -> serde_json::Value {
+        let mut req = Self::chat_completion_req_to_openai_req(chat_completion_req);
+        if stream {
+            req.stream = Some(true);
+        }
+        let mut body = serde_json::to_value(&req).unwrap_or_default();
+        let config = &chat_completion_req.config;
+        if let Some(body) = body.as_object_mut() {
+            if let Some(logprobs) = config.logprobs {
+                body.insert("logprobs".to_string(), logprobs.into());
+            }
+            if let Some(top_logprobs) = config.top_logprobs {
+                body.insert("top_logprobs".to_string(), top_logprobs.into());
+            }
+        }
+        body
+    }
+
 }
 
 
@@ -69,6 +123,8 @@ fn our_json_schema_type_to_openai(schema_type: JSONSchemaType) -> openai_api_rs:
     match schema_type {
         JSONSchemaType::Object => openai_api_rs::v1::chat_completion::JSONSchemaType::Object,
         JSONSchemaType::Number => openai_api_rs::v1::chat_completion::JSONSchemaType::Number,
+        // openai_api_rs has no integer type, integers are described as numbers
+        JSONSchemaType::Integer => openai_api_rs::v1::chat_completion::JSONSchemaType::Number,
         JSONSchemaType::String => openai_api_rs::v1::chat_completion::JSONSchemaType::String,
         JSONSchemaType::Array => openai_api_rs::v1::chat_completion::JSONSchemaType::Array,
         JSONSchemaType::Null => openai_api_rs::v1::chat_completion::JSONSchemaType::Null,
@@ -6,7 +6,7 @@ use futures_util::stream::Stream;
 use openai_api_rs::v1::chat_completion::ChatCompletionMessage;
 use openai_api_rs::v1::chat_completion::ChatCompletionRequest;
 use openai_api_rs::v1::chat_completion::MessageRole;
-use reqwest::{Client, Response};
+use reqwest::Response;
 use serde_json::Value;
 use std::pin::Pin;
 use std::task::{Context, Poll};
@@ -15,9 +15,8 @@ use std::task::{Context, Poll};
 impl ChatModelStream for OpenAIChatModel {
     async fn stream(&self, chat_completion_req: ChatCompletionReq) -> Result<LLMStream, String> {
         let api_url = &self.api_url;
-        let client = Client::new();
-        let mut req = Self::chat_completion_req_to_openai_req(&chat_completion_req);
-        req.stream = Some(true);
+        let client = self.http_client().map_err(|e| e.to_string())?;
+        let req = Self::chat_completion_req_to_body(&chat_completion_req, true);
         let response: Response = match client
             .post(api_url)
             .header("Content-Type", "application/json")
@@ -36,6 +35,8 @@ impl ChatModelStream for OpenAIChatModel {
                 buffer: String::new(),
                 first_chunk: true,
                 usage: Usage::default(),
+                logprobs: vec![],
+                refusal: None,
             })
         } else {
             let error_text = response
@@ -74,6 +75,18 @@ impl Stream for LLMStream {
                         Ok(json) => {
                             if let Some(choices) = json.get("choices") {
                                 if let Some(choice) = choices.get(0) {
+                                    if let Some(tokens) = choice.get("logprobs")
+                                        .and_then(|logprobs| logprobs.get("content"))
+                                        .and_then(|content| content.as_array())
+                                    {
+                                        self.logprobs.extend(tokens.iter().cloned());
+                                    }
+                                    if let Some(refusal) = choice.get("delta")
+                                        .and_then(|delta| delta.get("refusal"))
+                                        .and_then(|refusal| refusal.as_str())
+                                    {
+                                        self.refusal.get_or_insert_with(String::new).push_str(refusal);
+                                    }
                                     if let Some(content) =
                                         choice.get("delta").and_then(|delta| delta.get("content"))
                                     {
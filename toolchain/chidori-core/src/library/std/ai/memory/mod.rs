@@ -4,6 +4,7 @@ use async_trait::async_trait;
 
 pub mod in_memory;
 pub mod qdrant;
+pub mod store;
 
 // Define a custom error type for our vector database interactions
 
@@ -0,0 +1,279 @@
+//! The stores declared by memory cells. Code cells insert into and query a store by the name of its
+//! cell, `ch.memory("notes").query("watering", top_k=3)`, and prompt cells retrieve context from one
+//! with `context_from`.
+//!
+//! Every memory records the evaluation that inserted it, and a state sees only the memories of the
+//! evaluations it records in `ExecutionState::memory_writes`. Evaluating a cell again therefore
+//! replaces its memories rather than adding to them, and reverting to an earlier state hides the
+//! memories inserted after it.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use futures_util::FutureExt;
+use serde_json::{Map, Value};
+use crate::cells::{MemoryCell, ProxyConfig};
+use crate::execution::execution::execution_graph::ChronologyId;
+use crate::execution::primitives::serialized_value::{json_value_to_serialized_value, serialized_value_to_json_value, RkyvObjectBuilder, RkyvSerializedValue};
+use crate::library::std::ai::llm::ai_llm_embed_texts;
+
+/// Embeds texts with the given model, one vector per text, sending requests through the proxy if any
+pub type Embedder = dyn Fn(String, Vec<String>, Option<ProxyConfig>) -> Pin<Box<dyn Future<Output = anyhow::Result<Vec<Vec<f32>>>> + Send>> + Send + Sync;
+
+/// What a store is searched with, text is embedded with the model of the store
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemoryQuery {
+    Text(String),
+    Vector(Vec<f32>),
+}
+
+impl MemoryQuery {
+    pub fn from_value(value: &RkyvSerializedValue) -> anyhow::Result<Self> {
+        match value {
+            RkyvSerializedValue::String(text) => Ok(MemoryQuery::Text(text.clone())),
+            RkyvSerializedValue::Array(values) => values.iter().map(|value| match value {
+                RkyvSerializedValue::Float(f) => Ok(*f),
+                RkyvSerializedValue::Number(n) => Ok(*n as f32),
+                _ => anyhow::bail!("Vectors to query memory with may only contain numbers"),
+            }).collect::<anyhow::Result<Vec<f32>>>().map(MemoryQuery::Vector),
+            _ => anyhow::bail!("Memory is queried with either text or a vector"),
+        }
+    }
+}
+
+/// A stored memory similar to a query
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryMatch {
+    pub id: u64,
+    /// Cosine similarity of the memory to the query
+    pub score: f32,
+    pub text: Option<String>,
+    pub metadata: Map<String, Value>,
+}
+
+impl MemoryMatch {
+    pub fn to_value(&self) -> RkyvSerializedValue {
+        RkyvObjectBuilder::new()
+            .insert_number("id", self.id as i32)
+            .insert_value("score", RkyvSerializedValue::Float(self.score))
+            .insert_value("text", self.text.clone().map_or(RkyvSerializedValue::Null, RkyvSerializedValue::String))
+            .insert_value("metadata", json_value_to_serialized_value(&Value::Object(self.metadata.clone())))
+            .build()
+    }
+}
+
+/// Metadata given as a value, which must be an object when present
+pub fn metadata_from_value(value: &RkyvSerializedValue) -> anyhow::Result<Map<String, Value>> {
+    match serialized_value_to_json_value(value) {
+        Value::Null => Ok(Map::new()),
+        Value::Object(metadata) => Ok(metadata),
+        _ => anyhow::bail!("Memory metadata and filters must be objects"),
+    }
+}
+
+struct StoredMemory {
+    id: u64,
+    /// The evaluation that inserted this memory
+    evaluation: ChronologyId,
+    vector: Vec<f32>,
+    text: Option<String>,
+    metadata: Map<String, Value>,
+}
+
+#[derive(Default)]
+struct Store {
+    memories: Vec<StoredMemory>,
+    next_id: u64,
+}
+
+/// The contents of every memory cell of a program, shared by the states evaluated from it
+pub struct MemoryStores {
+    stores: Mutex<HashMap<String, Store>>,
+    embedder: Arc<Embedder>,
+}
+
+impl fmt::Debug for MemoryStores {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stores = self.stores.lock().unwrap();
+        f.debug_map().entries(stores.iter().map(|(name, store)| (name, store.memories.len()))).finish()
+    }
+}
+
+impl Default for MemoryStores {
+    fn default() -> Self {
+        Self::with_embedder(|model, texts, proxy| async move { ai_llm_embed_texts(&model, texts, proxy).await }.boxed())
+    }
+}
+
+impl MemoryStores {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores that embed text with `embedder` rather than the OpenAI embedding models
+    pub fn with_embedder(
+        embedder: impl Fn(String, Vec<String>, Option<ProxyConfig>) -> Pin<Box<dyn Future<Output = anyhow::Result<Vec<Vec<f32>>>> + Send>> + Send + Sync + 'static
+    ) -> Self {
+        MemoryStores {
+            stores: Default::default(),
+            embedder: Arc::new(embedder),
+        }
+    }
+
+    async fn embed(&self, cell: &MemoryCell, text: String, proxy: Option<ProxyConfig>) -> anyhow::Result<Vec<f32>> {
+        (self.embedder)(cell.embedding_model.clone(), vec![text], proxy).await?
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("{} produced no embedding", cell.embedding_model))
+    }
+
+    fn store_name(cell: &MemoryCell) -> anyhow::Result<String> {
+        cell.name.clone().ok_or_else(|| anyhow::anyhow!("Memory cells need a name to be stored in"))
+    }
+
+    /// Embed `text` and keep it in the store of `cell` along with its metadata, returning its id.
+    /// The memory belongs to `evaluation`, requests to the embedding model are sent through `proxy`.
+    pub async fn insert(&self, cell: &MemoryCell, text: String, metadata: Map<String, Value>, evaluation: ChronologyId, proxy: Option<ProxyConfig>) -> anyhow::Result<u64> {
+        let name = Self::store_name(cell)?;
+        let vector = self.embed(cell, text.clone(), proxy).await?;
+        let mut stores = self.stores.lock().unwrap();
+        let store = stores.entry(name).or_default();
+        store.next_id += 1;
+        let id = store.next_id;
+        store.memories.push(StoredMemory { id, evaluation, vector, text: Some(text), metadata });
+        Ok(id)
+    }
+
+    /// The `top_k` memories of the store of `cell` most similar to the query, most similar first.
+    /// Only memories inserted by one of `evaluations`, with every key of `filter` set to the same value
+    /// in their metadata, are considered.
+    pub async fn query(&self, cell: &MemoryCell, query: MemoryQuery, top_k: usize, filter: &Map<String, Value>, evaluations: &HashSet<ChronologyId>, proxy: Option<ProxyConfig>) -> anyhow::Result<Vec<MemoryMatch>> {
+        let name = Self::store_name(cell)?;
+        let vector = match query {
+            MemoryQuery::Text(text) => self.embed(cell, text, proxy).await?,
+            MemoryQuery::Vector(vector) => vector,
+        };
+        let stores = self.stores.lock().unwrap();
+        let Some(store) = stores.get(&name) else {
+            return Ok(vec![]);
+        };
+        let mut matches: Vec<MemoryMatch> = store.memories.iter()
+            .filter(|memory| evaluations.contains(&memory.evaluation))
+            .filter(|memory| filter.iter().all(|(key, value)| memory.metadata.get(key) == Some(value)))
+            .map(|memory| MemoryMatch {
+                id: memory.id,
+                score: cosine_similarity(&vector, &memory.vector),
+                text: memory.text.clone(),
+                metadata: memory.metadata.clone(),
+            })
+            .collect();
+        matches.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.id.cmp(&b.id)));
+        matches.truncate(top_k);
+        Ok(matches)
+    }
+}
+
+/// Zero when either vector has no magnitude or their dimensions differ
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let magnitude = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let magnitudes = magnitude(a) * magnitude(b);
+    if magnitudes == 0.0 { 0.0 } else { dot / magnitudes }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    const KEYWORDS: &[&str] = &["paris", "france", "python", "tower", "capital"];
+
+    /// Embeds text as the number of times each keyword occurs in it
+    pub(crate) fn keyword_memory_stores() -> MemoryStores {
+        MemoryStores::with_embedder(|_, texts, _| async move {
+            Ok(texts.iter().map(|text| {
+                let text = text.to_lowercase();
+                KEYWORDS.iter().map(|keyword| text.matches(keyword).count() as f32).collect()
+            }).collect())
+        }.boxed())
+    }
+
+    fn store() -> MemoryCell {
+        MemoryCell { name: Some("facts".to_string()), ..Default::default() }
+    }
+
+    async fn insert_facts(stores: &MemoryStores, evaluation: ChronologyId) -> anyhow::Result<()> {
+        for (text, topic) in [
+            ("The capital of France is Paris.", "geography"),
+            ("Python is a popular programming language.", "programming"),
+            ("The Eiffel Tower is located in Paris.", "landmarks"),
+        ] {
+            let metadata = json!({"topic": topic}).as_object().unwrap().clone();
+            stores.insert(&store(), text.to_string(), metadata, evaluation, None).await?;
+        }
+        Ok(())
+    }
+
+    fn texts(matches: &[MemoryMatch]) -> Vec<&str> {
+        matches.iter().map(|m| m.text.as_deref().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_query_orders_by_similarity() -> anyhow::Result<()> {
+        let stores = keyword_memory_stores();
+        let evaluation = Uuid::now_v7();
+        insert_facts(&stores, evaluation).await?;
+        let evaluations = HashSet::from([evaluation]);
+
+        let matches = stores.query(&store(), MemoryQuery::Text("Paris tower".to_string()), 3, &Map::new(), &evaluations, None).await?;
+        assert_eq!(texts(&matches), vec![
+            "The Eiffel Tower is located in Paris.",
+            "The capital of France is Paris.",
+            "Python is a popular programming language.",
+        ]);
+        assert!((matches[0].score - 1.0).abs() < 1e-6);
+        assert_eq!(matches[2].score, 0.0);
+        assert_eq!(matches[0].metadata.get("topic"), Some(&json!("landmarks")));
+
+        let matches = stores.query(&store(), MemoryQuery::Text("Paris tower".to_string()), 1, &Map::new(), &evaluations, None).await?;
+        assert_eq!(texts(&matches), vec!["The Eiffel Tower is located in Paris."]);
+
+        let matches = stores.query(&store(), MemoryQuery::Vector(vec![0.0, 0.0, 1.0, 0.0, 0.0]), 1, &Map::new(), &evaluations, None).await?;
+        assert_eq!(texts(&matches), vec!["Python is a popular programming language."]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_with_metadata_filter() -> anyhow::Result<()> {
+        let stores = keyword_memory_stores();
+        let evaluation = Uuid::now_v7();
+        insert_facts(&stores, evaluation).await?;
+        let evaluations = HashSet::from([evaluation]);
+        let filter = json!({"topic": "geography"}).as_object().unwrap().clone();
+        let matches = stores.query(&store(), MemoryQuery::Text("Paris tower".to_string()), 5, &filter, &evaluations, None).await?;
+        assert_eq!(texts(&matches), vec!["The capital of France is Paris."]);
+
+        let unknown = MemoryCell { name: Some("unknown".to_string()), ..Default::default() };
+        assert!(stores.query(&unknown, MemoryQuery::Text("Paris".to_string()), 5, &Map::new(), &evaluations, None).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_sees_only_the_given_evaluations() -> anyhow::Result<()> {
+        let stores = keyword_memory_stores();
+        let (first, second) = (Uuid::now_v7(), Uuid::now_v7());
+        insert_facts(&stores, first).await?;
+        insert_facts(&stores, second).await?;
+
+        let query = || MemoryQuery::Text("Paris".to_string());
+        let matches = stores.query(&store(), query(), 10, &Map::new(), &HashSet::from([second]), None).await?;
+        assert_eq!(matches.len(), 3);
+        assert!(stores.query(&store(), query(), 10, &Map::new(), &HashSet::new(), None).await?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,334 @@
+//! Splitting text into chunks sized for embedding. Sizes are estimated tokens, counted with the
+//! same characters per token rule as prompt templates.
+
+use chidori_prompt_format::templating::templates::CHARACTERS_PER_TOKEN;
+use crate::cells::ChunkStrategy;
+use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue};
+
+/// Byte offsets of a part of the chunked text, always on character boundaries
+type Span = (usize, usize);
+
+/// Sentences end with these when followed by whitespace
+const SENTENCE_TERMINATORS: &[char] = &['.', '!', '?', '…'];
+/// Sentences end with these regardless of what follows, as is usual in CJK text
+const FULLWIDTH_TERMINATORS: &[char] = &['。', '！', '？'];
+/// Belong to the sentence they follow, `"Done."`
+const CLOSING_PUNCTUATION: &[char] = &['"', '\'', ')', ']', '”', '’', '»'];
+
+/// A part of the chunked text. Offsets count characters rather than bytes, so that they index
+/// the text the same way in Python, and `end_offset` is exclusive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub text: String,
+    pub index: usize,
+    pub start_offset: usize,
+    pub end_offset: usize,
+    /// Titles of the markdown headings the chunk is nested under, outermost first
+    pub heading_path: Vec<String>,
+}
+
+impl Chunk {
+    pub fn to_value(&self) -> RkyvSerializedValue {
+        RkyvObjectBuilder::new()
+            .insert_string("text", self.text.clone())
+            .insert_number("index", self.index as i32)
+            .insert_number("start_offset", self.start_offset as i32)
+            .insert_number("end_offset", self.end_offset as i32)
+            .insert_value("heading_path", RkyvSerializedValue::Array(
+                self.heading_path.iter().map(|title| RkyvSerializedValue::String(title.clone())).collect()
+            ))
+            .build()
+    }
+}
+
+/// Split `text` into chunks of at most `size` tokens, with consecutive chunks sharing up to `overlap`
+/// tokens. Boundary strategies keep sentences, paragraphs or sections whole where they fit, those that
+/// don't are split at fixed positions.
+pub fn chunk_text(text: &str, strategy: ChunkStrategy, size: usize, overlap: usize) -> anyhow::Result<Vec<Chunk>> {
+    if size == 0 {
+        anyhow::bail!("Chunks need a size of at least one token");
+    }
+    if overlap >= size {
+        anyhow::bail!("The overlap of chunks ({} tokens) must be smaller than their size ({} tokens)", overlap, size);
+    }
+    let max_chars = size * CHARACTERS_PER_TOKEN;
+    let overlap_chars = overlap * CHARACTERS_PER_TOKEN;
+    let whole = (0, text.len());
+    let spans: Vec<(Span, Vec<String>)> = match strategy {
+        ChunkStrategy::Tokens => fixed_spans(text, whole, max_chars, overlap_chars)
+            .into_iter().map(|span| (span, vec![])).collect(),
+        ChunkStrategy::Sentences => pack_segments(text, &sentence_segments(text, whole), max_chars, overlap_chars)
+            .into_iter().map(|span| (span, vec![])).collect(),
+        ChunkStrategy::Paragraphs => pack_segments(text, &paragraph_segments(text, whole), max_chars, overlap_chars)
+            .into_iter().map(|span| (span, vec![])).collect(),
+        // Sections are chunked on their own, a chunk never spans two headings
+        ChunkStrategy::Markdown => markdown_sections(text).into_iter()
+            .flat_map(|(section, heading_path)| {
+                pack_segments(text, &paragraph_segments(text, section), max_chars, overlap_chars)
+                    .into_iter().map(move |span| (span, heading_path.clone()))
+            })
+            .collect(),
+    };
+
+    let char_starts: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+    let char_offset = |byte: usize| char_starts.partition_point(|&start| start < byte);
+    Ok(spans.into_iter().enumerate().map(|(index, ((start, end), heading_path))| Chunk {
+        text: text[start..end].to_string(),
+        index,
+        start_offset: char_offset(start),
+        end_offset: char_offset(end),
+        heading_path,
+    }).collect())
+}
+
+fn char_len(text: &str, (start, end): Span) -> usize {
+    text[start..end].chars().count()
+}
+
+/// Spans of `max_chars` characters covering `span`, each starting `max_chars - overlap_chars`
+/// characters after the one before
+fn fixed_spans(text: &str, span: Span, max_chars: usize, overlap_chars: usize) -> Vec<Span> {
+    let boundaries: Vec<usize> = text[span.0..span.1].char_indices()
+        .map(|(i, _)| span.0 + i)
+        .chain(std::iter::once(span.1))
+        .collect();
+    let characters = boundaries.len() - 1;
+    let step = max_chars - overlap_chars;
+    let mut spans = vec![];
+    let mut start = 0;
+    while start < characters {
+        let end = (start + max_chars).min(characters);
+        spans.push((boundaries[start], boundaries[end]));
+        if end == characters {
+            break;
+        }
+        start += step;
+    }
+    spans
+}
+
+/// Group consecutive segments into spans of at most `max_chars` characters. The last segments of
+/// a span are repeated at the start of the next while they fit within `overlap_chars`.
+fn pack_segments(text: &str, segments: &[Span], max_chars: usize, overlap_chars: usize) -> Vec<Span> {
+    let mut spans = vec![];
+    let mut first = 0;
+    while first < segments.len() {
+        let mut last = first;
+        while last + 1 < segments.len() && char_len(text, (segments[first].0, segments[last + 1].1)) <= max_chars {
+            last += 1;
+        }
+        let span = (segments[first].0, segments[last].1);
+        if char_len(text, span) > max_chars {
+            spans.extend(fixed_spans(text, span, max_chars, overlap_chars));
+        } else {
+            spans.push(span);
+        }
+        if last + 1 == segments.len() {
+            break;
+        }
+        let mut next = last + 1;
+        while next - 1 > first && char_len(text, (segments[next - 1].0, segments[last].1)) <= overlap_chars {
+            next -= 1;
+        }
+        first = next;
+    }
+    spans
+}
+
+/// `span` without its surrounding whitespace, None when it is only whitespace
+fn trimmed(text: &str, (start, end): Span) -> Option<Span> {
+    let slice = &text[start..end];
+    let leading = slice.len() - slice.trim_start().len();
+    let trailing = slice.len() - slice.trim_end().len();
+    (leading < slice.len()).then_some((start + leading, end - trailing))
+}
+
+/// Paragraphs of `span`, separated by blank lines
+fn paragraph_segments(text: &str, span: Span) -> Vec<Span> {
+    let mut segments = vec![];
+    let mut paragraph_start = span.0;
+    let mut offset = span.0;
+    for line in text[span.0..span.1].split_inclusive('\n') {
+        if line.trim().is_empty() {
+            segments.extend(trimmed(text, (paragraph_start, offset)));
+            paragraph_start = offset + line.len();
+        }
+        offset += line.len();
+    }
+    segments.extend(trimmed(text, (paragraph_start, span.1)));
+    segments
+}
+
+/// Sentences of `span`, which never cross a paragraph
+fn sentence_segments(text: &str, span: Span) -> Vec<Span> {
+    let is_terminator = |c: char| SENTENCE_TERMINATORS.contains(&c) || FULLWIDTH_TERMINATORS.contains(&c);
+    let mut segments = vec![];
+    for paragraph in paragraph_segments(text, span) {
+        let mut sentence_start = paragraph.0;
+        let mut chars = text[paragraph.0..paragraph.1].char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            if !is_terminator(c) {
+                continue;
+            }
+            let mut fullwidth = FULLWIDTH_TERMINATORS.contains(&c);
+            let mut end = paragraph.0 + i + c.len_utf8();
+            // Runs of terminators and closing quotes end the same sentence, `Really?!"`
+            while let Some(&(j, next)) = chars.peek() {
+                if !is_terminator(next) && !CLOSING_PUNCTUATION.contains(&next) {
+                    break;
+                }
+                fullwidth |= FULLWIDTH_TERMINATORS.contains(&next);
+                end = paragraph.0 + j + next.len_utf8();
+                chars.next();
+            }
+            // `3.5` and `e.g.x` don't end a sentence
+            if fullwidth || chars.peek().map_or(true, |&(_, next)| next.is_whitespace()) {
+                segments.extend(trimmed(text, (sentence_start, end)));
+                sentence_start = end;
+            }
+        }
+        segments.extend(trimmed(text, (sentence_start, paragraph.1)));
+    }
+    segments
+}
+
+/// The sections of a markdown document, each starting at a heading and running until the next,
+/// along with the titles of the headings it is nested under. Headings in code blocks are ignored.
+fn markdown_sections(text: &str) -> Vec<(Span, Vec<String>)> {
+    let mut sections = vec![];
+    let mut headings: Vec<(usize, String)> = vec![];
+    let mut section_start = 0;
+    let mut offset = 0;
+    let mut in_code_block = false;
+    for line in text.split_inclusive('\n') {
+        let content = line.trim_start();
+        if content.starts_with("```") || content.starts_with("~~~") {
+            in_code_block = !in_code_block;
+        } else if let Some((level, title)) = heading(line).filter(|_| !in_code_block) {
+            sections.push(((section_start, offset), headings.iter().map(|(_, title)| title.clone()).collect()));
+            headings.retain(|(parent, _)| *parent < level);
+            headings.push((level, title));
+            section_start = offset;
+        }
+        offset += line.len();
+    }
+    sections.push(((section_start, text.len()), headings.into_iter().map(|(_, title)| title).collect()));
+    sections
+}
+
+/// The level and title of an ATX heading, `## Title`
+fn heading(line: &str) -> Option<(usize, String)> {
+    let line = line.trim_end();
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if level == 0 || level > 6 {
+        return None;
+    }
+    let title = &line[level..];
+    if !title.is_empty() && !title.starts_with([' ', '\t']) {
+        return None;
+    }
+    Some((level, title.trim().trim_end_matches('#').trim_end().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUIDE: &str = include_str!("../../../tests/data/chunk/guide.md");
+
+    fn assert_offsets_map_back(text: &str, chunks: &[Chunk]) {
+        let characters: Vec<char> = text.chars().collect();
+        for (index, chunk) in chunks.iter().enumerate() {
+            assert_eq!(chunk.index, index);
+            let original: String = characters[chunk.start_offset..chunk.end_offset].iter().collect();
+            assert_eq!(original, chunk.text);
+        }
+    }
+
+    fn texts(chunks: &[Chunk]) -> Vec<&str> {
+        chunks.iter().map(|chunk| chunk.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_fixed_token_chunks_overlap() -> anyhow::Result<()> {
+        let chunks = chunk_text(GUIDE, ChunkStrategy::Tokens, 8, 2)?;
+        assert_offsets_map_back(GUIDE, &chunks);
+        assert_eq!(chunks.first().unwrap().start_offset, 0);
+        assert_eq!(chunks.last().unwrap().end_offset, GUIDE.chars().count());
+        for pair in chunks.windows(2) {
+            let (previous, next) = (&pair[0], &pair[1]);
+            assert_eq!(previous.text.chars().count(), 32);
+            assert_eq!(next.start_offset, previous.start_offset + 24);
+            let shared: String = previous.text.chars().skip(24).collect();
+            assert!(next.text.starts_with(&shared), "{:?} should start with {:?}", next.text, shared);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sentence_chunks() -> anyhow::Result<()> {
+        let text = "Water early. Évitez l'eau froide en été! 水は朝にやりましょう。夕方は避けてください。 Is it 3.5 litres? Yes.";
+        let chunks = chunk_text(text, ChunkStrategy::Sentences, 7, 0)?;
+        assert_offsets_map_back(text, &chunks);
+        assert_eq!(texts(&chunks), vec![
+            "Water early.",
+            "Évitez l'eau froide en été!",
+            "水は朝にやりましょう。夕方は避けてください。",
+            "Is it 3.5 litres? Yes.",
+        ]);
+
+        // Whole sentences are repeated between chunks
+        let chunks = chunk_text("One. Two. Three. Four.", ChunkStrategy::Sentences, 3, 2)?;
+        assert_eq!(texts(&chunks), vec!["One. Two.", "Two. Three.", "Three. Four."]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_paragraph_chunks() -> anyhow::Result<()> {
+        let text = "First paragraph.\n\nSecond one\nspans lines.\n\n\nThird.";
+        let chunks = chunk_text(text, ChunkStrategy::Paragraphs, 6, 0)?;
+        assert_offsets_map_back(text, &chunks);
+        assert_eq!(texts(&chunks), vec!["First paragraph.", "Second one\nspans lines.", "Third."]);
+
+        let chunks = chunk_text(text, ChunkStrategy::Paragraphs, 100, 0)?;
+        assert_eq!(texts(&chunks), vec![text]);
+
+        // Paragraphs larger than a chunk are split
+        let chunks = chunk_text(GUIDE, ChunkStrategy::Paragraphs, 5, 1)?;
+        assert_offsets_map_back(GUIDE, &chunks);
+        assert!(chunks.iter().all(|chunk| chunk.text.chars().count() <= 20));
+        Ok(())
+    }
+
+    #[test]
+    fn test_markdown_chunks_follow_headings() -> anyhow::Result<()> {
+        let chunks = chunk_text(GUIDE, ChunkStrategy::Markdown, 64, 0)?;
+        assert_offsets_map_back(GUIDE, &chunks);
+        let heading_paths: Vec<Vec<&str>> = chunks.iter()
+            .map(|chunk| chunk.heading_path.iter().map(|title| title.as_str()).collect())
+            .collect();
+        assert_eq!(heading_paths, vec![
+            vec!["Garden Guide"],
+            vec!["Garden Guide", "Watering"],
+            vec!["Garden Guide", "Soil"],
+            vec!["Garden Guide", "Soil", "Compost"],
+            vec!["Garden Guide", "Soil", "Mulch"],
+        ]);
+        assert!(chunks[1].text.starts_with("## Watering") && chunks[1].text.ends_with("summer."));
+        assert!(chunks[3].text.contains("# Not a heading"));
+
+        // Small chunks split sections without crossing headings
+        let chunks = chunk_text(GUIDE, ChunkStrategy::Markdown, 12, 0)?;
+        assert_offsets_map_back(GUIDE, &chunks);
+        assert!(chunks.iter().any(|chunk| chunk.text == "## Watering"));
+        assert!(chunks.iter().all(|chunk| !chunk.text.contains("\n## ")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_sizes() {
+        assert!(chunk_text(GUIDE, ChunkStrategy::Tokens, 0, 0).is_err());
+        assert!(chunk_text(GUIDE, ChunkStrategy::Tokens, 4, 4).is_err());
+        assert!(chunk_text("", ChunkStrategy::Tokens, 4, 0).unwrap().is_empty());
+    }
+}
@@ -8,7 +8,7 @@ use std::sync::{Arc, Mutex};
 use crate::execution::primitives::serialized_value::{
     json_value_to_serialized_value, RkyvObjectBuilder, RkyvSerializedValue,
 };
-use chidori_static_analysis::language::javascript::parse::{build_report, extract_dependencies_js};
+use chidori_static_analysis::language::javascript::parse::{build_report, extract_cell_imports, extract_dependencies_js};
 use deno_core::_ops::{RustToV8, RustToV8NoScope};
 use deno_core::v8::{Global, Handle, HandleScope};
 use std::collections::HashMap;
@@ -26,9 +26,12 @@ use pyo3::{Py, PyAny};
 use pyo3::types::{IntoPyDict, PyTuple};
 use tokio::runtime::{Builder, Runtime};
 use tracing::{debug, Id, Span};
-use crate::cells::{CellTypes, CodeCell, LLMPromptCell};
+use crate::cells::{CellTypes, CodeCell, LLMPromptCell, SupportedLanguage};
 use crate::execution::execution::execution_state::{EnclosedState, ExecutionStateErrors};
 use crate::execution::execution::ExecutionState;
+use crate::execution::execution::stream::{read_stream_chunk, StreamWriter};
+use crate::utils::diff::{diff_values, ValueChange};
+use crate::library::std::ai::memory::store::{metadata_from_value, MemoryMatch, MemoryQuery};
 
 
 fn serde_v8_to_rkyv(
@@ -66,6 +69,10 @@ struct MyOpState {
         String,
         FunctionConstructorState,
     >,
+    /// Streams opened by `Chidori.stream()`, finished once the cell completes
+    stream_writers: HashMap<String, StreamWriter>,
+    /// Terminates the evaluation of the cell once the step evaluating it is cancelled
+    isolate_handle: Arc<Mutex<Option<v8::IsolateHandle>>>,
 }
 
 #[op2]
@@ -82,6 +89,122 @@ fn op_assert_eq(
     }
 }
 
+#[op2]
+#[serde]
+fn op_diff(
+    #[serde] a: RkyvSerializedValue,
+    #[serde] b: RkyvSerializedValue,
+) -> Result<Vec<ValueChange>, AnyError> {
+    Ok(diff_values(&a, &b).changes)
+}
+
+/// Call a Rust function registered by the embedder, see `ExecutionState::native_functions`
+#[op2]
+#[serde]
+fn op_call_native(
+    state: Rc<RefCell<OpState>>,
+    #[string] name: String,
+    #[serde] args: RkyvSerializedValue,
+) -> Result<RkyvSerializedValue, AnyError> {
+    let op_state = state.borrow();
+    let my_op_state: &Arc<Mutex<MyOpState>> = (*op_state).borrow();
+    let my_op_state = my_op_state.lock().unwrap();
+    let exec_state = my_op_state.execution_state_handle.lock().unwrap();
+    exec_state.call_native_function(&name, args)
+}
+
+/// A parameter of the branch being evaluated, see `ExecutionGraph::set_branch_params`
+#[op2]
+#[serde]
+fn op_branch_param(
+    state: Rc<RefCell<OpState>>,
+    #[string] name: String,
+) -> Result<Option<RkyvSerializedValue>, AnyError> {
+    let op_state = state.borrow();
+    let my_op_state: &Arc<Mutex<MyOpState>> = (*op_state).borrow();
+    let my_op_state = my_op_state.lock().unwrap();
+    let exec_state = my_op_state.execution_state_handle.lock().unwrap();
+    Ok(exec_state.branch_param(&name).cloned())
+}
+
+/// The state of the executing cell, cloned so that no borrow is held across an await
+fn cloned_execution_state(state: &Rc<RefCell<OpState>>) -> ExecutionState {
+    let op_state = state.borrow();
+    let my_op_state: &Arc<Mutex<MyOpState>> = (*op_state).borrow();
+    let my_op_state = my_op_state.lock().unwrap();
+    let exec_state = my_op_state.execution_state_handle.lock().unwrap();
+    exec_state.clone()
+}
+
+/// The memories of a memory cell most similar to text or a vector, see `ExecutionState::query_memory`
+#[op2(async, reentrant)]
+#[serde]
+async fn op_memory_query(
+    state: Rc<RefCell<OpState>>,
+    #[string] name: String,
+    #[serde] query: RkyvSerializedValue,
+    #[serde] top_k: usize,
+    #[serde] filter: RkyvSerializedValue,
+) -> Result<RkyvSerializedValue, AnyError> {
+    let exec_state = cloned_execution_state(&state);
+    let query = MemoryQuery::from_value(&query)?;
+    let matches = exec_state.query_memory(&name, query, top_k, &metadata_from_value(&filter)?).await?;
+    Ok(RkyvSerializedValue::Array(matches.iter().map(MemoryMatch::to_value).collect()))
+}
+
+/// Embed and store text in a memory cell, see `ExecutionState::insert_memory`
+#[op2(async, reentrant)]
+#[serde]
+async fn op_memory_insert(
+    state: Rc<RefCell<OpState>>,
+    #[string] name: String,
+    #[string] text: String,
+    #[serde] metadata: RkyvSerializedValue,
+) -> Result<u64, AnyError> {
+    let exec_state = cloned_execution_state(&state);
+    Ok(exec_state.insert_memory(&name, text, metadata_from_value(&metadata)?).await?)
+}
+
+/// Open a stream for the executing cell to write to, see `StreamWriter`
+#[op2]
+#[serde]
+fn op_stream_open(
+    state: Rc<RefCell<OpState>>,
+) -> Result<String, AnyError> {
+    let op_state = state.borrow();
+    let my_op_state: &Arc<Mutex<MyOpState>> = (*op_state).borrow();
+    let mut my_op_state = my_op_state.lock().unwrap();
+    let writer = StreamWriter::new();
+    let id = writer.id().to_string();
+    my_op_state.stream_writers.insert(id.clone(), writer);
+    Ok(id)
+}
+
+#[op2]
+#[serde]
+fn op_stream_write(
+    state: Rc<RefCell<OpState>>,
+    #[string] id: String,
+    #[serde] item: RkyvSerializedValue,
+) -> Result<(), AnyError> {
+    let op_state = state.borrow();
+    let my_op_state: &Arc<Mutex<MyOpState>> = (*op_state).borrow();
+    let mut my_op_state = my_op_state.lock().unwrap();
+    let writer = my_op_state.stream_writers.get_mut(&id)
+        .ok_or_else(|| anyhow::anyhow!("Stream {} was not opened by this cell", id))?;
+    writer.write(item)
+}
+
+/// Read one chunk of a stream produced by another cell, see `read_stream_chunk`
+#[op2]
+#[serde]
+fn op_stream_read_chunk(
+    #[string] id: String,
+    #[serde] index: usize,
+) -> Result<Option<Vec<RkyvSerializedValue>>, AnyError> {
+    read_stream_chunk(&id, index)
+}
+
 #[op2]
 #[serde]
 fn op_console_log(
@@ -301,10 +424,31 @@ fn op_set_globals<'scope>(
     let my_op_state: &Arc<Mutex<MyOpState>> = op_state.borrow();
     let mut my_op_state = my_op_state.lock().unwrap();
 
+    // Registered before checking for cancellation, so that a cancellation after the check terminates the isolate
+    *my_op_state.isolate_handle.lock().unwrap() = Some(scope.thread_safe_handle());
+    if my_op_state.execution_state_handle.lock().unwrap().cancellation.is_cancelled() {
+        return Err(anyhow::anyhow!("The evaluation of the cell was cancelled"));
+    }
+
+    // Streams are provided as async iterators over their items
+    let mut js_code = String::new();
+
     // put globals into the global scope before invoking
     if let RkyvSerializedValue::Object(ref payload_map) = my_op_state.payload {
         if let Some(RkyvSerializedValue::Object(globals_map)) = payload_map.get("globals") {
             for (key, value) in globals_map {
+                if let RkyvSerializedValue::StreamPointer(id) = value {
+                    js_code.push_str(&format!("globalThis.{key} = Chidori.readStream({id:?});\n"));
+                    continue;
+                }
+                // Tables are provided as arrays of rows
+                #[cfg(feature = "arrow")]
+                let rows = match value {
+                    RkyvSerializedValue::Table { .. } => Some(crate::execution::primitives::table::table_to_rows(value)?),
+                    _ => None,
+                };
+                #[cfg(feature = "arrow")]
+                let value = rows.as_ref().unwrap_or(value);
                 let key = deno_core::v8::String::new(scope, key).unwrap();
                 if let Ok(value) = match deno_core::_ops::RustToV8Fallible::to_v8_fallible(
                     deno_core::_ops::RustToV8Marker::<deno_core::_ops::SerdeMarker, _>::from(
@@ -336,7 +480,6 @@ fn op_set_globals<'scope>(
 
     // create shims for functions that are referred to
     // TODO: differentiate async vs sync functions
-    let mut js_code = String::new();
     for (function_name, function) in
     create_function_shims(&my_op_state.execution_state_handle, &my_op_state.cell_depended_values, my_op_state.parent_span_id.clone()).unwrap()
     {
@@ -419,7 +562,156 @@ fn js_args_to_rkyv(args: Vec<RkyvSerializedValue>, kwargs: Option<HashMap<String
 
 
 
-#[tracing::instrument]
+/// The source of every named Deno cell, each can be imported by other cells as `cell:<name>`
+fn importable_cell_sources(execution_state: &ExecutionState) -> HashMap<String, String> {
+    execution_state.cells_by_id.values()
+        .filter_map(|cell| match cell {
+            CellTypes::Code(CodeCell { name: Some(name), language: SupportedLanguage::Deno, source_code, .. }, _) => {
+                Some((name.clone(), source_code.clone()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The cells imported by `source_code`, directly or through the cells it imports, with each cell
+/// following the cells it imports. Fails on an import of a cell that does not exist, or on a cycle.
+fn resolve_cell_imports(
+    importing_cell: Option<&String>,
+    source_code: &str,
+    cell_sources: &HashMap<String, String>,
+) -> anyhow::Result<Vec<String>> {
+    fn visit(
+        name: &String,
+        cell_sources: &HashMap<String, String>,
+        import_path: &mut Vec<String>,
+        resolved: &mut Vec<String>,
+    ) -> anyhow::Result<()> {
+        if let Some(position) = import_path.iter().position(|cell| cell == name) {
+            let mut cycle = import_path[position..].to_vec();
+            cycle.push(name.clone());
+            anyhow::bail!("Circular import between cells {}", cycle.join(" -> "));
+        }
+        if resolved.contains(name) {
+            return Ok(());
+        }
+        let source_code = cell_sources.get(name)
+            .ok_or_else(|| anyhow::anyhow!("Imported cell:{} but there is no Deno cell named {}", name, name))?;
+        import_path.push(name.clone());
+        for import in extract_cell_imports(source_code)? {
+            visit(&import, cell_sources, import_path, resolved)?;
+        }
+        import_path.pop();
+        resolved.push(name.clone());
+        Ok(())
+    }
+
+    let mut import_path: Vec<String> = importing_cell.into_iter().cloned().collect();
+    let mut resolved = vec![];
+    for import in extract_cell_imports(source_code)? {
+        visit(&import, cell_sources, &mut import_path, &mut resolved)?;
+    }
+    Ok(resolved)
+}
+
+/// Resolution of the modules imported by javascript cells and the hosts they may reach
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DenoModuleConfig {
+    /// An import map (https://github.com/WICG/import-maps) applied to the specifiers imported by
+    /// cells, its relative addresses resolve against the directory of the file
+    pub import_map: Option<PathBuf>,
+    /// Hosts, optionally with a port, that cells may fetch from or import remote modules from. Any
+    /// host may be reached when this is unset, none when it is empty.
+    pub allowed_hosts: Option<Vec<String>>,
+    /// The Deno cache (`DENO_DIR`) npm packages and remote modules are resolved from, the default
+    /// cache of the user when unset
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl DenoModuleConfig {
+    /// The `imports` of the import map, by the specifier they map
+    fn imports(&self) -> anyhow::Result<HashMap<String, String>> {
+        let Some(path) = &self.import_map else {
+            return Ok(HashMap::new());
+        };
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read the import map {}: {}", path.display(), e))?;
+        let import_map: serde_json::Value = serde_json::from_str(&contents)
+            .map_err(|e| anyhow::anyhow!("The import map {} is not valid json: {}", path.display(), e))?;
+        Ok(import_map.get("imports")
+            .and_then(|imports| imports.as_object())
+            .map(|imports| imports.iter()
+                .filter_map(|(specifier, address)| Some((specifier.clone(), address.as_str()?.to_string())))
+                .collect())
+            .unwrap_or_default())
+    }
+
+    fn is_allowed_host(&self, url: &ModuleSpecifier) -> bool {
+        let Some(allowed_hosts) = &self.allowed_hosts else {
+            return true;
+        };
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host_with_port = url.port_or_known_default().map(|port| format!("{}:{}", host, port));
+        allowed_hosts.iter().any(|allowed| allowed == host || Some(allowed) == host_with_port.as_ref())
+    }
+
+    /// Fails if `source_code` imports a remote module from a host that is not allowed, after
+    /// applying the import map
+    fn check_remote_imports(&self, source_code: &str) -> anyhow::Result<()> {
+        if self.allowed_hosts.is_none() {
+            return Ok(());
+        }
+        let imports = self.imports()?;
+        let pattern = regex::Regex::new(r#"(?:\bfrom|\bimport)\s*\(?\s*["']([^"']+)["']"#).unwrap();
+        for captures in pattern.captures_iter(source_code) {
+            let specifier = &captures[1];
+            let resolved = resolve_mapped_specifier(specifier, &imports);
+            let Ok(url) = ModuleSpecifier::parse(&resolved) else { continue };
+            if matches!(url.scheme(), "http" | "https") && !self.is_allowed_host(&url) {
+                anyhow::bail!("Importing {} is not allowed, {} is not an allowed host", specifier, url.host_str().unwrap_or_default());
+            }
+        }
+        Ok(())
+    }
+
+    /// Network access granted to the Deno runtime, in the form of `--allow-net`
+    fn allow_net(&self) -> Option<Vec<String>> {
+        match &self.allowed_hosts {
+            // An empty list allows every host
+            None => Some(vec![]),
+            Some(hosts) if hosts.is_empty() => None,
+            Some(hosts) => Some(hosts.clone()),
+        }
+    }
+}
+
+/// The address `specifier` is mapped to by `imports`, matching it exactly or by the longest
+/// prefix ending in a `/`, as import maps do
+fn resolve_mapped_specifier(specifier: &str, imports: &HashMap<String, String>) -> String {
+    if let Some(address) = imports.get(specifier) {
+        return address.clone();
+    }
+    imports.iter()
+        .filter(|(prefix, _)| prefix.ends_with('/') && specifier.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(prefix, address)| format!("{}{}", address, &specifier[prefix.len()..]))
+        .unwrap_or_else(|| specifier.to_string())
+}
+
+/// Points `cell:<name>` specifiers at the virtual modules registered for those cells
+fn rewrite_cell_specifiers(source_code: &str, cell_modules: &HashMap<String, ModuleSpecifier>) -> String {
+    let pattern = regex::Regex::new(r#""cell:([\w-]+)"|'cell:([\w-]+)'"#).unwrap();
+    pattern.replace_all(source_code, |captures: &regex::Captures| {
+        let name = captures.get(1).or_else(|| captures.get(2)).unwrap().as_str();
+        match cell_modules.get(name) {
+            Some(specifier) => format!("\"{}\"", specifier),
+            None => captures[0].to_string(),
+        }
+    }).into_owned()
+}
+
 pub async fn source_code_run_deno(
     execution_state: &ExecutionState,
     source_code: &String,
@@ -430,10 +722,104 @@ pub async fn source_code_run_deno(
     Vec<String>,
     Vec<String>,
     ExecutionState
+)> {
+    source_code_run_deno_with_options(execution_state, source_code, payload, function_invocation, &DenoRunOptions::default()).await
+}
+
+/// How javascript source is evaluated, see `source_code_run_deno_with_options`
+#[derive(Debug, Clone, Default)]
+pub struct DenoRunOptions {
+    /// Contents of a `package.json` the source is evaluated alongside
+    pub package_json: Option<String>,
+    /// Type check the source before evaluating it, failing on any type error
+    pub type_check: bool,
+}
+
+/// Ambient declarations of the globals the setup script of the runtime provides, so that type
+/// checked source can refer to them
+const RUNTIME_TYPE_DECLARATIONS: &str = r#"export {};
+declare global {
+    type ChidoriStream = { write(item: unknown): void };
+    const Chidori: {
+        assertEq(a: unknown, b: unknown): boolean;
+        saveValue(value: unknown): void;
+        saveOutput(object: Record<string, unknown>): void;
+        diff(a: unknown, b: unknown): any;
+        param<T>(name: string, defaultValue?: T): T;
+        stream(): ChidoriStream;
+        streamFrom(iterable: AsyncIterable<unknown> | Iterable<unknown>): Promise<ChidoriStream>;
+        readStream(id: unknown): AsyncIterable<any>;
+    };
+    function native(name: string, args?: unknown): Promise<any>;
+    function memory(name: string): {
+        query(query: string, options?: { top_k?: number; filter?: Record<string, unknown> | null }): Promise<any[]>;
+        insert(text: string, metadata?: Record<string, unknown> | null): Promise<void>;
+    };
+    function op_invoke_function(f: unknown): any;
+    var module: { exports: Record<string, any> };
+}
+"#;
+
+/// A directory holding the `package.json` of a cell, removed once the cell has been evaluated
+struct PackageDirectory(PathBuf);
+
+impl PackageDirectory {
+    /// Writes `package.json` alongside an empty `deno.json`, so that the directory is discovered
+    /// as the workspace of the cell and its packages resolve against it
+    fn create(package_json: &str) -> anyhow::Result<Self> {
+        serde_json::from_str::<serde_json::Value>(package_json)
+            .map_err(|e| anyhow::anyhow!("The package_json of the cell is not valid json: {}", e))?;
+        let directory = std::env::temp_dir().join(format!("chidori-deno-{}", uuid::Uuid::now_v7()));
+        std::fs::create_dir_all(&directory)?;
+        let package_directory = PackageDirectory(directory);
+        std::fs::write(package_directory.0.join("package.json"), package_json)?;
+        std::fs::write(package_directory.0.join("deno.json"), "{}")?;
+        Ok(package_directory)
+    }
+
+    fn path(&self) -> String {
+        self.0.to_string_lossy().into_owned()
+    }
+
+    /// Paths a cell evaluated alongside this package may read. Reads are limited to the package,
+    /// and to the directory of the import map so that the local modules it maps still resolve.
+    fn read_permissions(&self, module_config: &DenoModuleConfig) -> Vec<String> {
+        let mut paths = vec![self.path()];
+        if let Some(import_map) = &module_config.import_map {
+            let directory = import_map.parent().filter(|directory| !directory.as_os_str().is_empty());
+            paths.push(directory.map_or_else(|| ".".to_string(), |directory| directory.to_string_lossy().into_owned()));
+        }
+        paths
+    }
+}
+
+impl Drop for PackageDirectory {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Run javascript source as `source_code_run_deno` does. When `package_json` is set, it is written
+/// to a temporary directory the source is evaluated in, with reads limited to that directory, so
+/// that `npm:` imports resolve against packages already in the Deno cache without network access.
+/// When `type_check` is set, the source is checked as TypeScript before it is evaluated.
+#[tracing::instrument]
+pub async fn source_code_run_deno_with_options(
+    execution_state: &ExecutionState,
+    source_code: &String,
+    payload: &RkyvSerializedValue,
+    function_invocation: &Option<String>,
+    options: &DenoRunOptions,
+) -> anyhow::Result<(
+    Result<RkyvSerializedValue, ExecutionStateErrors>,
+    Vec<String>,
+    Vec<String>,
+    ExecutionState
 )> {
     let execution_state = execution_state.clone();
     let source_code = source_code.clone();
     let function_invocation = function_invocation.clone();
+    let DenoRunOptions { package_json, type_check } = options.clone();
     let payload = payload.clone();
     let (tx, rx) = std::sync::mpsc::channel();
     // let (tx, rx) = tokio::sync::oneshot::channel();
@@ -459,6 +845,7 @@ pub async fn source_code_run_deno(
             });
 
             let execution_state_handle = Arc::new(Mutex::new(execution_state.clone()));
+            let isolate_handle: Arc<Mutex<Option<v8::IsolateHandle>>> = Default::default();
             let my_op_state = Arc::new(Mutex::new(MyOpState {
                 parent_span_id: current_span_id,
                 stdout: vec![],
@@ -467,7 +854,9 @@ pub async fn source_code_run_deno(
                 payload: payload.clone(),
                 cell_depended_values,
                 functions: Default::default(),
-                execution_state_handle
+                stream_writers: Default::default(),
+                execution_state_handle,
+                isolate_handle: isolate_handle.clone(),
             }));
 
             let my_op_state_clone = my_op_state.clone();
@@ -478,11 +867,19 @@ pub async fn source_code_run_deno(
                         op_set_globals(),
                         op_call_rust(),
                         op_assert_eq(),
+                        op_diff(),
+                        op_call_native(),
+                        op_branch_param(),
+                        op_memory_query(),
+                        op_memory_insert(),
                         op_save_result(),
                         op_save_result_object(),
                         op_invoke_function(),
                         op_console_log(),
                         op_console_err(),
+                        op_stream_open(),
+                        op_stream_write(),
+                        op_stream_read_chunk(),
                     ])
                 )),
                 op_state_fn: Some(Box::new(move |state| {
@@ -503,9 +900,22 @@ pub async fn source_code_run_deno(
           const op_invoke_function = Deno.core.ops.op_invoke_function;
           const op_console_log = Deno.core.ops.op_console_log;
           const op_console_err = Deno.core.ops.op_console_err;
+          const op_diff = Deno.core.ops.op_diff;
+          const op_call_native = Deno.core.ops.op_call_native;
+          const op_branch_param = Deno.core.ops.op_branch_param;
+          const op_memory_query = Deno.core.ops.op_memory_query;
+          const op_memory_insert = Deno.core.ops.op_memory_insert;
+          const op_stream_open = Deno.core.ops.op_stream_open;
+          const op_stream_write = Deno.core.ops.op_stream_write;
+          const op_stream_read_chunk = Deno.core.ops.op_stream_read_chunk;
 
           globalThis.op_invoke_function = op_invoke_function;
           globalThis.op_call_rust = op_call_rust;
+          globalThis.native = async (name, args) => op_call_native(name, args ?? null);
+          globalThis.memory = (name) => ({
+              query: async (query, { top_k = 5, filter = null } = {}) => op_memory_query(name, query, top_k, filter),
+              insert: async (text, metadata = null) => op_memory_insert(name, text, metadata),
+          });
 
           function argsToMessage(...args) {
               return args.map((arg) => JSON.stringify(arg)).join(" ");
@@ -531,6 +941,43 @@ pub async fn source_code_run_deno(
               },
               saveOutput: (object) => {
                   op_save_result_object(object);
+              },
+              diff: (a, b) => {
+                  return op_diff(a, b);
+              },
+              param: (name, defaultValue) => {
+                  return op_branch_param(name) ?? defaultValue;
+              },
+              // A stream is represented by its id, its methods are not enumerable so that
+              // exporting it exports only the stream
+              stream: () => {
+                  const writer = { __chidori_stream__: op_stream_open() };
+                  Object.defineProperty(writer, "write", {
+                      value: (item) => op_stream_write(writer.__chidori_stream__, item),
+                  });
+                  return writer;
+              },
+              streamFrom: async (iterable) => {
+                  const writer = globalThis.Chidori.stream();
+                  for await (const item of iterable) {
+                      writer.write(item);
+                  }
+                  return writer;
+              },
+              readStream: (id) => {
+                  const reader = { __chidori_stream__: id };
+                  Object.defineProperty(reader, Symbol.asyncIterator, {
+                      value: async function* () {
+                          for (let index = 0; ; index++) {
+                              const chunk = op_stream_read_chunk(id, index);
+                              if (chunk === null) {
+                                  return;
+                              }
+                              yield* chunk;
+                          }
+                      },
+                  });
+                  return reader;
               }
           };
 
@@ -566,6 +1013,9 @@ pub async fn source_code_run_deno(
             // Set global variables, provide specialized ops
             let source = if let Some(func_name) = function_invocation {
                 let mut source = String::new();
+                if type_check {
+                    source.push_str(RUNTIME_TYPE_DECLARATIONS);
+                }
                 source.push_str("\n");
                 source.push_str(&source_code);
                 source.push_str("\n");
@@ -577,8 +1027,11 @@ pub async fn source_code_run_deno(
                 source
             } else {
                 let mut source = String::new();
+                if type_check {
+                    source.push_str(RUNTIME_TYPE_DECLARATIONS);
+                }
                 source.push_str("\n");
-                source.push_str("export const chidoriResult = {};");
+                source.push_str("export const chidoriResult: Record<string, unknown> = {};");
                 source.push_str("\n");
                 source.push_str(&source_code);
                 for (name, report_item) in &report.triggerable_functions {
@@ -604,25 +1057,56 @@ pub async fn source_code_run_deno(
             };
 
 
+            let module_config = &execution_state.deno_modules;
             let mut flags = deno::args::Flags::default();
             // TODO: give user control over this in configuration
             // TODO: allow_net is causing this to block our execution entirely
-            flags.permissions.allow_net = Some(vec![]);
+            flags.permissions.allow_net = module_config.allow_net();
+            flags.import_map_path = module_config.import_map.as_ref().map(|path| path.to_string_lossy().into_owned());
+            flags.cache_path = module_config.cache_dir.clone();
             flags.permissions.allow_env = Some(vec![]);
             flags.permissions.allow_read = Some(vec![]);
             flags.permissions.allow_write = Some(vec![]);
             flags.permissions.allow_run = Some(vec![]);
+            if type_check {
+                flags.type_check_mode = deno::args::TypeCheckMode::Local;
+            }
+            let package_directory = package_json.as_deref().map(PackageDirectory::create).transpose()?;
+            if let Some(directory) = &package_directory {
+                flags.config_flag = deno::args::ConfigFlag::Path(directory.0.join("deno.json").to_string_lossy().into_owned());
+                flags.permissions.allow_read = Some(directory.read_permissions(module_config));
+            }
             let factory = deno::factory::CliFactory::from_flags(Arc::new(flags));
             let cli_options = factory.cli_options()?;
             let file_fetcher = factory.file_fetcher()?;
             let main_module = cli_options.resolve_main_module()?;
 
+            // Named Deno cells are registered as modules alongside the main module, they are
+            // registered again each run so that imports see the current source of a cell
+            let cell_sources = importable_cell_sources(&execution_state);
+            let mut cell_modules = HashMap::new();
+            let imported_cells = resolve_cell_imports(execution_state.evaluating_name.as_ref(), &source_code, &cell_sources)?;
+            module_config.check_remote_imports(&source_code)?;
+            for name in &imported_cells {
+                module_config.check_remote_imports(&cell_sources[name])?;
+            }
+            for name in &imported_cells {
+                cell_modules.insert(name.clone(), main_module.join(&format!("./__chidori_cells__/{}.ts", name))?);
+            }
+            for name in &imported_cells {
+                file_fetcher.insert_memory_files(File {
+                    specifier: cell_modules[name].clone(),
+                    maybe_headers: None,
+                    source: rewrite_cell_specifiers(&cell_sources[name], &cell_modules).into_bytes().into(),
+                });
+            }
+
             // Save a fake file into file fetcher cache
             // to allow module access by TS compiler.
             file_fetcher.insert_memory_files(File {
                 specifier: main_module.clone(),
                 maybe_headers: None,
-                source: source.clone().into_bytes().into(),
+                source: rewrite_cell_specifiers(&source, &cell_modules).into_bytes().into(),
             });
 
             let permissions = PermissionsContainer::new(Permissions::from_options(
@@ -635,8 +1119,14 @@ pub async fn source_code_run_deno(
                 .build()
                 .expect("Failed to create Tokio runtime");
 
+            let _cancellation_watch = execution_state.cancellation.on_cancel(move || {
+                if let Some(handle) = isolate_handle.lock().unwrap().as_ref() {
+                    handle.terminate_execution();
+                }
+            });
+
             // Use the newly created single-threaded runtime to run our async code
-            runtime.block_on(async {
+            let run_result = runtime.block_on(async {
                 let worker_factory = factory.create_cli_main_worker_factory().await?;
                 let mut worker = worker_factory
                     .create_custom_worker(
@@ -650,12 +1140,19 @@ pub async fn source_code_run_deno(
 
                 let exit_code = worker.run().await?;
                 Ok::<(), anyhow::Error>(())
-            }).map_err(|e| {
+            });
+            if execution_state.cancellation.is_cancelled() {
+                anyhow::bail!("The evaluation of the cell was cancelled");
+            }
+            run_result.map_err(|e| {
                 // TODO: map error
                 dbg!(&e);
                 e
             })?;
             let mut my_op_state = my_op_state.lock().unwrap();
+            for writer in my_op_state.stream_writers.values_mut() {
+                writer.finish()?;
+            }
             let output = Ok(my_op_state.output.clone().unwrap_or(RkyvSerializedValue::Null));
             let execution_state = my_op_state.execution_state_handle.lock().unwrap().clone();
             let stdout = my_op_state.stdout.clone();
@@ -721,6 +1218,13 @@ mod tests {
                                 "#
                                 }),
                 function_invocation: None,
+                inspect_globals: false,
+                redact_output: false,
+                policy: Default::default(),
+                oom_limit_bytes: None,
+                always_run: false,
+                generate_types: false,
+                package_json: None,
             }, TextRange::default()), id_a)?;
         let result = source_code_run_deno(
             &state,
@@ -805,6 +1309,57 @@ mod tests {
             )
         );
     }
+    #[tokio::test]
+    async fn test_source_code_run_deno_diff() {
+        let source_code = String::from("const changes = Chidori.diff({a: [1, 2]}, {a: [1]});");
+        let result = source_code_run_deno(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None).await;
+        let change = RkyvObjectBuilder::new()
+            .insert_string("kind", "removed".to_string())
+            .insert_value("path", RkyvSerializedValue::Array(vec![RkyvSerializedValue::String("a".to_string()), RkyvSerializedValue::Number(1)]))
+            .insert_number("value", 2)
+            .build();
+        assert_eq!(
+            result.unwrap().0,
+            Ok(RkyvObjectBuilder::new().insert_value("changes", RkyvSerializedValue::Array(vec![change])).build())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_source_code_run_deno_native_function() {
+        let mut state = ExecutionState::new_with_random_id();
+        state.native_functions.insert("add".to_string(), Arc::new(|args| match args {
+            RkyvSerializedValue::Array(a) => match (&a[0], &a[1]) {
+                (RkyvSerializedValue::Number(x), RkyvSerializedValue::Number(y)) => RkyvSerializedValue::Number(x + y),
+                _ => RkyvSerializedValue::Null,
+            },
+            _ => RkyvSerializedValue::Null,
+        }));
+        let source_code = String::from(r#"const total = await native("add", [2, 3]);"#);
+        let result = source_code_run_deno(&state, &source_code, &RkyvSerializedValue::Null, &None).await;
+        assert_eq!(result.unwrap().0, Ok(RkyvObjectBuilder::new().insert_number("total", 5).build()));
+    }
+
+    #[tokio::test]
+    async fn test_source_code_run_deno_query_memory() {
+        let mut state = ExecutionState::new_with_random_id();
+        state.memory_stores = Arc::new(crate::library::std::ai::memory::store::tests::keyword_memory_stores());
+        state.cells_by_id.insert(uuid::Uuid::now_v7(), CellTypes::Memory(crate::cells::MemoryCell {
+            name: Some("facts".to_string()),
+            ..Default::default()
+        }, Default::default()));
+        let source_code = String::from(r#"
+            await memory("facts").insert("The capital of France is Paris.", {topic: "geography"});
+            await memory("facts").insert("Python is a popular programming language.", {topic: "programming"});
+            const matches = await memory("facts").query([0, 0, 1, 0, 0], {top_k: 1});
+            const closest = matches.map((m) => m.metadata.topic);
+        "#);
+        let result = source_code_run_deno(&state, &source_code, &RkyvSerializedValue::Null, &None).await;
+        let RkyvSerializedValue::Object(outputs) = result.unwrap().0.unwrap() else { panic!("expected an object") };
+        assert_eq!(outputs.get("closest"), Some(&RkyvSerializedValue::Array(vec![
+            RkyvSerializedValue::String("programming".to_string()),
+        ])));
+    }
+
     #[tokio::test]
     async fn test_source_code_run_deno_expose_global_variables() {
         let source_code = String::from("const x = 30;");
@@ -882,6 +1437,60 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_import_map_resolves_module_to_local_file() -> anyhow::Result<()> {
+        let directory = std::env::temp_dir().join(format!("chidori-import-map-{}", Uuid::now_v7()));
+        std::fs::create_dir_all(&directory)?;
+        std::fs::write(directory.join("greetings.ts"), "export function greet(name: string) { return `Hello ${name}`; }\n")?;
+        std::fs::write(directory.join("import_map.json"), r#"{ "imports": { "greetings": "./greetings.ts" } }"#)?;
+        let mut state = ExecutionState::new_with_random_id();
+        state.deno_modules = DenoModuleConfig {
+            import_map: Some(directory.join("import_map.json")),
+            allowed_hosts: Some(vec!["deno.land".to_string()]),
+            ..Default::default()
+        };
+
+        let source_code = String::from(r#"import { greet } from "greetings";
+const message = greet("Ada");"#);
+        let result = source_code_run_deno(&state, &source_code, &RkyvSerializedValue::Null, &None).await;
+        let RkyvSerializedValue::Object(outputs) = result?.0.unwrap() else { panic!("expected an object") };
+        assert_eq!(outputs.get("message"), Some(&RkyvSerializedValue::String("Hello Ada".to_string())));
+
+        let source_code = String::from(r#"import { greet } from "https://example.com/greetings.ts";"#);
+        let err = source_code_run_deno(&state, &source_code, &RkyvSerializedValue::Null, &None).await
+            .err().expect("importing from a host that is not allowed should fail");
+        assert!(err.to_string().contains("example.com is not an allowed host"), "{}", err);
+        std::fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_circular_cell_imports_are_an_error() -> anyhow::Result<()> {
+        let state = ExecutionState::new_with_random_id();
+        let deno_cell = |name: &str, source_code: &str| CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: Some(name.to_string()),
+            language: SupportedLanguage::Deno,
+            source_code: source_code.to_string(),
+            function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
+        }, TextRange::default());
+        let (state, _) = state.update_operation(deno_cell("a", "import { b } from \"cell:b\";\nexport const a = 1;"), Uuid::now_v7())?;
+        let (state, _) = state.update_operation(deno_cell("b", "import { a } from \"cell:a\";\nexport const b = 2;"), Uuid::now_v7())?;
+
+        let source_code = String::from(r#"import { a } from "cell:a";"#);
+        let result = source_code_run_deno(&state, &source_code, &RkyvSerializedValue::Null, &None).await;
+        let err = result.err().expect("a cycle of imports should fail to run");
+        assert!(err.to_string().contains("Circular import between cells a -> b -> a"), "{}", err);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_typescript_basic() {
         let source_code = String::from("const x: number = 42;");
@@ -989,4 +1598,70 @@ mod tests {
             )
         );
     }
+
+    fn copy_directory(from: &std::path::Path, to: &std::path::Path) -> anyhow::Result<()> {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                copy_directory(&entry.path(), &to.join(entry.file_name()))?;
+            } else {
+                std::fs::copy(entry.path(), to.join(entry.file_name()))?;
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_npm_import_resolves_against_package_json() -> anyhow::Result<()> {
+        // A Deno cache holding only the package the cell depends on, so that it resolves offline
+        let directory = std::env::temp_dir().join(format!("chidori-deno-dir-{}", Uuid::now_v7()));
+        copy_directory(&std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/deno_dir"), &directory.join("deno_dir"))?;
+        std::fs::write(directory.join("greetings.ts"), "export function greet(name: string) { return `Hello ${name}`; }\n")?;
+        std::fs::write(directory.join("import_map.json"), r#"{ "imports": { "greetings": "./greetings.ts" } }"#)?;
+        let mut state = ExecutionState::new_with_random_id();
+        state.deno_modules = DenoModuleConfig {
+            import_map: Some(directory.join("import_map.json")),
+            allowed_hosts: Some(vec![]),
+            cache_dir: Some(directory.join("deno_dir")),
+        };
+
+        // Both the npm package and the module mapped by the import map resolve
+        let source_code = String::from(r#"import { add } from "npm:chidori-fixture-add";
+import { greet } from "greetings";
+export const sum = add(2, 3);
+export const message = greet("Ada");"#);
+        let options = DenoRunOptions {
+            package_json: Some(r#"{ "dependencies": { "chidori-fixture-add": "^1.0.0" } }"#.to_string()),
+            ..Default::default()
+        };
+        let result = source_code_run_deno_with_options(&state, &source_code, &RkyvSerializedValue::Null, &None, &options).await?;
+        assert_eq!(result.0, Ok(RkyvObjectBuilder::new()
+            .insert_number("sum", 5)
+            .insert_string("message", "Hello Ada".to_string())
+            .build()));
+        std::fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_package_read_permissions_include_the_import_map() -> anyhow::Result<()> {
+        let package = PackageDirectory::create("{}")?;
+        assert_eq!(package.read_permissions(&DenoModuleConfig::default()), vec![package.path()]);
+        let module_config = DenoModuleConfig {
+            import_map: Some(PathBuf::from("/project/import_map.json")),
+            ..Default::default()
+        };
+        assert_eq!(package.read_permissions(&module_config), vec![package.path(), "/project".to_string()]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_invalid_package_json_fails_to_run() {
+        let source_code = String::from("export const x = 1;");
+        let options = DenoRunOptions { package_json: Some("{ dependencies".to_string()), ..Default::default() };
+        let err = source_code_run_deno_with_options(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None, &options).await
+            .err().expect("a malformed package_json should fail to run");
+        assert!(err.to_string().contains("The package_json of the cell is not valid json"), "{}", err);
+    }
 }
@@ -7,21 +7,24 @@ use chidori_static_analysis::language::python::parse::{
 
 use futures_util::FutureExt;
 use pyo3::prelude::*;
-use pyo3::types::{IntoPyDict, PyCFunction, PyDict, PyList, PySet, PyTuple};
+use pyo3::types::{IntoPyDict, PyCFunction, PyDict, PyList, PySet, PyTuple, PyType};
 use std::sync::mpsc::{self, Sender};
 
-use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue};
+use crate::execution::execution::stream::{StreamReader, StreamWriter};
+use crate::execution::primitives::serialized_value::{json_value_to_serialized_value, sorted_object_entries, RkyvObjectBuilder, RkyvSerializedValue};
 use std::collections::{HashMap, HashSet};
 use std::future::Future;
 use std::{env, mem};
 use std::sync::{Arc, Mutex};
 use anyhow::Error;
 use once_cell::sync::OnceCell;
-use pyo3_asyncio::generic;
 use tokio::runtime::Runtime;
 use chidori_static_analysis::language::Report;
 use crate::cells::{CellTypes, CodeCell, LLMPromptCell};
 use crate::execution::execution::ExecutionState;
+use crate::library::std::template::TemplateLibrary;
+use crate::library::std::ai::memory::store::{metadata_from_value, MemoryMatch, MemoryQuery};
+use im::HashMap as ImHashMap;
 
 use std::path::{Path, PathBuf};
 use std::process::Command;
@@ -38,7 +41,8 @@ use regex::Regex;
 use sha1::{Sha1, Digest};
 use tracing::{debug, Id, Span};
 use uuid::Uuid;
-use crate::execution::execution::execution_state::{EnclosedState, ExecutionStateErrors};
+use crate::execution::execution::execution_state::{EnclosedState, ExecutionStateErrors, NativeFunction};
+use crate::execution::primitives::agent_trace::{AgentTrace, AgentTraceStepKind};
 
 static SOURCE_CODE_RUN_COUNTER: Lazy<AtomicUsize> = Lazy::new(|| AtomicUsize::new(0));
 static CURRENT_PYTHON_EXECUTION_ID: Lazy<AtomicUsize> = Lazy::new(|| AtomicUsize::new(0));
@@ -95,9 +99,9 @@ fn get_or_create_default_venv(v: &PythonVersionInfo) -> anyhow::Result<PathBuf>
 }
 
 
-fn pyany_to_rkyv_serialized_value(p: &PyAny) -> RkyvSerializedValue {
-    match p.get_type().name() {
-        Ok(s) => match s {
+fn pyany_to_rkyv_serialized_value(p: &Bound<'_, PyAny>) -> RkyvSerializedValue {
+    match p.get_type().name().and_then(|name| name.extract::<String>()) {
+        Ok(s) => match s.as_str() {
             "int" => {
                 let val = p.extract::<i32>().unwrap();
                 RkyvSerializedValue::Number(val)
@@ -118,7 +122,7 @@ fn pyany_to_rkyv_serialized_value(p: &PyAny) -> RkyvSerializedValue {
                 let list = p.downcast::<PyList>().unwrap();
                 let arr = list
                     .iter()
-                    .map(|item| pyany_to_rkyv_serialized_value(item))
+                    .map(|item| pyany_to_rkyv_serialized_value(&item))
                     .collect();
                 RkyvSerializedValue::Array(arr)
             }
@@ -126,7 +130,7 @@ fn pyany_to_rkyv_serialized_value(p: &PyAny) -> RkyvSerializedValue {
                 let list = p.downcast::<PyTuple>().unwrap();
                 let arr = list
                     .iter()
-                    .map(|item| pyany_to_rkyv_serialized_value(item))
+                    .map(|item| pyany_to_rkyv_serialized_value(&item))
                     .collect();
                 RkyvSerializedValue::Array(arr)
             }
@@ -135,7 +139,7 @@ fn pyany_to_rkyv_serialized_value(p: &PyAny) -> RkyvSerializedValue {
                 let mut map = HashMap::new();
                 for (key, value) in dict {
                     let key_string = key.extract::<String>().unwrap();
-                    map.insert(key_string, pyany_to_rkyv_serialized_value(value));
+                    map.insert(key_string, pyany_to_rkyv_serialized_value(&value));
                 }
                 RkyvSerializedValue::Object(map)
             }
@@ -143,16 +147,51 @@ fn pyany_to_rkyv_serialized_value(p: &PyAny) -> RkyvSerializedValue {
                 let pyset = p.downcast::<PySet>().unwrap();
                 let mut set = HashSet::new();
                 for value in pyset {
-                    set.insert(pyany_to_rkyv_serialized_value(value));
+                    set.insert(pyany_to_rkyv_serialized_value(&value));
                 }
                 RkyvSerializedValue::Set(set)
             }
             "NoneType" => {
                 RkyvSerializedValue::Null
             },
+            "StreamWriter" => {
+                let mut writer = p.extract::<PyRefMut<PyStreamWriter>>().unwrap();
+                stream_result_to_value(writer.writer.finish())
+            },
+            "StreamIterator" => {
+                let iterator = p.extract::<PyRef<PyStreamIterator>>().unwrap();
+                RkyvSerializedValue::StreamPointer(iterator.reader.id().to_string())
+            },
+            // pandas and polars dataframes are stored as tables, column by column
+            #[cfg(feature = "arrow")]
+            "DataFrame" => {
+                let columns = p.getattr("columns").and_then(|columns| columns.iter()?.map(|name| {
+                    let name = name?;
+                    let values = p.get_item(&name)?.call_method0("to_list")?;
+                    Ok((name.extract::<String>()?, pyany_to_rkyv_serialized_value(&values)))
+                }).collect::<PyResult<Vec<_>>>());
+                match columns.map_err(anyhow::Error::from).and_then(crate::execution::primitives::table::table_from_columns) {
+                    Ok(table) => table,
+                    Err(e) => RkyvSerializedValue::Error { kind: "TableError".to_string(), message: e.to_string() },
+                }
+            },
+            // Generators are drained into a stream rather than materialized
+            "generator" => {
+                let mut writer = StreamWriter::new();
+                let written = p.iter().map_err(anyhow::Error::from).and_then(|items| {
+                    for item in items {
+                        writer.write(pyany_to_rkyv_serialized_value(&item?))?;
+                    }
+                    writer.finish()
+                });
+                stream_result_to_value(written)
+            },
             "Future" => {
                 RkyvSerializedValue::Null
             },
+            _ if p.is_instance_of::<pyo3::exceptions::PyBaseException>() => {
+                RkyvSerializedValue::Error { kind: s.to_string(), message: p.str().map(|m| m.to_string()).unwrap_or_default() }
+            }
             x @ _  => {
                 panic!("Py03 marshalling unsupported type: {}", x);
                 RkyvSerializedValue::Null
@@ -169,7 +208,7 @@ fn rkyv_serialized_value_to_pyany(py: Python, value: &RkyvSerializedValue) -> Py
         RkyvSerializedValue::String(s) => s.into_py(py),
         RkyvSerializedValue::Boolean(b) => b.into_py(py),
         RkyvSerializedValue::Array(a) => {
-            let py_list = PyList::empty(py);
+            let py_list = PyList::empty_bound(py);
             for item in a {
                 let py_item = rkyv_serialized_value_to_pyany(py, item);
                 py_list.append(py_item).unwrap();
@@ -177,14 +216,28 @@ fn rkyv_serialized_value_to_pyany(py: Python, value: &RkyvSerializedValue) -> Py
             py_list.into_py(py)
         }
         RkyvSerializedValue::Object(o) => {
-            let py_dict = PyDict::new(py);
-            for (key, value) in o {
+            let py_dict = PyDict::new_bound(py);
+            for (key, value) in sorted_object_entries(o) {
                 let py_value = rkyv_serialized_value_to_pyany(py, value);
                 py_dict.set_item(key, py_value).unwrap();
             }
             py_dict.into_py(py)
         }
         RkyvSerializedValue::Null => py.None(),
+        #[cfg(feature = "arrow")]
+        RkyvSerializedValue::Table { .. } => table_to_dataframe(py, value).unwrap_or_else(|_| py.None()),
+        RkyvSerializedValue::StreamPointer(id) => {
+            Py::new(py, PyStreamIterator { reader: StreamReader::new(id) }).unwrap().into_py(py)
+        }
+        // Errors are restored as an instance of the builtin exception of the same name, if any
+        RkyvSerializedValue::Error { kind, message } => {
+            let class = py.import_bound("builtins").ok()
+                .and_then(|builtins| builtins.getattr(kind.as_str()).ok())
+                .and_then(|class| class.downcast_into::<PyType>().ok())
+                .filter(|class| class.is_subclass_of::<pyo3::exceptions::PyException>().unwrap_or(false))
+                .unwrap_or_else(|| py.get_type_bound::<pyo3::exceptions::PyException>());
+            class.call1((message,)).map(|e| e.into_py(py)).unwrap_or_else(|_| py.None())
+        }
         // TODO: Handle other types
         _ => py.None(),
     }
@@ -201,11 +254,252 @@ impl RkyvSerializedValue {
     }
 }
 
+/// A table as a pandas DataFrame, or a polars DataFrame if pandas is not installed. Without either
+/// the table is provided as a list of rows.
+#[cfg(feature = "arrow")]
+fn table_to_dataframe(py: Python, table: &RkyvSerializedValue) -> anyhow::Result<PyObject> {
+    use crate::execution::primitives::table::{table_to_columns, table_to_rows};
+    let Some(dataframes) = ["pandas", "polars"].iter().find_map(|module| py.import_bound(*module).ok()) else {
+        return Ok(rkyv_serialized_value_to_pyany(py, &table_to_rows(table)?));
+    };
+    let columns = PyDict::new_bound(py);
+    for (name, values) in table_to_columns(table)? {
+        columns.set_item(name, rkyv_serialized_value_to_pyany(py, &values))?;
+    }
+    Ok(dataframes.call_method1("DataFrame", (columns,))?.into_py(py))
+}
+
+fn stream_result_to_value(result: anyhow::Result<RkyvSerializedValue>) -> RkyvSerializedValue {
+    result.unwrap_or_else(|e| RkyvSerializedValue::Error { kind: "StreamError".to_string(), message: e.to_string() })
+}
+
+/// Writer of a stream, as returned by `ch.stream()`. Bound to a global it is reported as the stream.
+#[pyclass(name = "StreamWriter")]
+struct PyStreamWriter {
+    writer: StreamWriter,
+}
+
+#[pymethods]
+impl PyStreamWriter {
+    fn write(&mut self, item: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.writer.write(pyany_to_rkyv_serialized_value(item))
+            .map_err(|e| pyo3::exceptions::PyIOError::new_err(e.to_string()))
+    }
+}
+
+/// Iterator over a stream produced by another cell, reading its chunks as they are reached
+#[pyclass(name = "StreamIterator")]
+struct PyStreamIterator {
+    reader: StreamReader,
+}
+
+#[pymethods]
+impl PyStreamIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>, py: Python) -> PyResult<Option<PyObject>> {
+        match slf.reader.next() {
+            Some(Ok(item)) => Ok(Some(rkyv_serialized_value_to_pyany(py, &item))),
+            Some(Err(e)) => Err(pyo3::exceptions::PyIOError::new_err(e.to_string())),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Open a stream to write items to, e.g. `records = ch.stream()` followed by `records.write(item)`.
+/// Items are spilled to disk in chunks, cells depending on `records` iterate over them lazily.
+#[pyfunction]
+fn stream() -> PyStreamWriter {
+    PyStreamWriter { writer: StreamWriter::new() }
+}
+
 #[pyfunction]
 fn identity_function(py: Python, arg: PyObject) -> PyResult<PyObject> {
     Ok(arg)
 }
 
+/// Values bound in a namespace after execution that should be reported as outputs of a cell.
+/// Dunder names, callables, modules and the names in `excluded` are skipped.
+fn inspect_globals_after_execution(namespace: &Bound<'_, PyDict>, excluded: &HashSet<String>) -> HashMap<String, RkyvSerializedValue> {
+    namespace.iter().filter_map(|(key, value)| {
+        let name: String = key.extract().ok()?;
+        let is_dunder = name.starts_with("__") && name.ends_with("__");
+        if is_dunder || excluded.contains(&name) || value.is_callable() || value.is_instance_of::<PyModule>() {
+            return None;
+        }
+        Some((name, pyany_to_rkyv_serialized_value(&value)))
+    }).collect()
+}
+
+#[pyfunction]
+fn capture_bindings(exec_id: usize, namespace: &Bound<'_, PyDict>, excluded: Vec<String>) {
+    let excluded = excluded.into_iter().collect();
+    let output_c = PYTHON_OUTPUT_MAP.clone();
+    let output = output_c.entry(exec_id).or_insert(DashMap::new());
+    for (name, value) in inspect_globals_after_execution(namespace, &excluded) {
+        output.insert(name, value);
+    }
+}
+
+/// Compare two values, returning the list of changes between them. See `utils::diff::diff_values`.
+#[pyfunction]
+fn diff(py: Python, a: &Bound<'_, PyAny>, b: &Bound<'_, PyAny>) -> PyResult<PyObject> {
+    let diff = crate::utils::diff::diff_values(&pyany_to_rkyv_serialized_value(a), &pyany_to_rkyv_serialized_value(b));
+    let changes = serde_json::to_value(&diff.changes)
+        .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+    Ok(rkyv_serialized_value_to_pyany(py, &json_value_to_serialized_value(&changes)))
+}
+
+/// Name of the global through which `render` finds the templates of the executing program
+const TEMPLATE_LIBRARY_GLOBAL: &str = "__chidori_templates__";
+
+/// Name of the global through which `native` finds the functions registered by the embedder
+const NATIVE_FUNCTIONS_GLOBAL: &str = "__chidori_native_functions__";
+
+/// Name of the global through which `trace_step` records the steps of the executing operation
+const AGENT_TRACE_GLOBAL: &str = "__chidori_agent_trace__";
+
+/// Name of the global through which `param` finds the parameters of the branch being evaluated
+const BRANCH_PARAMS_GLOBAL: &str = "__chidori_branch_params__";
+
+/// Name of the global through which `memory` finds the memory cells of the executing program
+const MEMORY_STORES_GLOBAL: &str = "__chidori_memory_stores__";
+
+/// Name of the global holding the event loop the body and functions of a cell run on
+const EVENT_LOOP_GLOBAL: &str = "__chidori_event_loop__";
+
+#[pyclass]
+struct BranchParamsHandle {
+    params: RkyvSerializedValue,
+}
+
+#[pyclass]
+struct AgentTraceHandle {
+    trace: AgentTrace,
+}
+
+#[pyclass]
+struct TemplateLibraryHandle {
+    library: TemplateLibrary,
+}
+
+#[pyclass]
+struct NativeFunctionsHandle {
+    functions: ImHashMap<String, Arc<NativeFunction>>,
+}
+
+#[pyclass]
+struct MemoryStoresHandle {
+    state: ExecutionState,
+}
+
+/// The store of a memory cell, returned by `ch.memory(name)`
+#[pyclass]
+struct MemoryStoreHandle {
+    state: ExecutionState,
+    name: String,
+}
+
+fn memory_argument_error(e: anyhow::Error) -> PyErr {
+    pyo3::exceptions::PyValueError::new_err(e.to_string())
+}
+
+#[pymethods]
+impl MemoryStoreHandle {
+    /// The `top_k` most similar memories to text or a vector, as `{id, score, text, metadata}`, e.g.
+    /// `await ch.memory("notes").query("watering", top_k=3, filter={"topic": "garden"})`
+    #[pyo3(signature = (query, top_k = 5, filter = None))]
+    fn query<'py>(&self, py: Python<'py>, query: &Bound<'py, PyAny>, top_k: usize, filter: Option<&Bound<'py, PyAny>>) -> PyResult<Bound<'py, PyAny>> {
+        let query = MemoryQuery::from_value(&pyany_to_rkyv_serialized_value(query)).map_err(memory_argument_error)?;
+        let filter = filter.map_or(Ok(Default::default()), |filter| metadata_from_value(&pyany_to_rkyv_serialized_value(filter)))
+            .map_err(memory_argument_error)?;
+        let state = self.state.clone();
+        let name = self.name.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            let matches = state.query_memory(&name, query, top_k, &filter).await.map_err(AnyhowErrWrapper)?;
+            let matches = RkyvSerializedValue::Array(matches.iter().map(MemoryMatch::to_value).collect());
+            Ok(Python::with_gil(|py| rkyv_serialized_value_to_pyany(py, &matches)))
+        })
+    }
+
+    /// Embed and store text along with its metadata, resolving to the id of the memory
+    #[pyo3(signature = (text, metadata = None))]
+    fn insert<'py>(&self, py: Python<'py>, text: String, metadata: Option<&Bound<'py, PyAny>>) -> PyResult<Bound<'py, PyAny>> {
+        let metadata = metadata.map_or(Ok(Default::default()), |metadata| metadata_from_value(&pyany_to_rkyv_serialized_value(metadata)))
+            .map_err(memory_argument_error)?;
+        let state = self.state.clone();
+        let name = self.name.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            let id = state.insert_memory(&name, text, metadata).await.map_err(AnyhowErrWrapper)?;
+            Ok(id)
+        })
+    }
+}
+
+/// Look up a value injected into the globals of the code calling a host function
+fn caller_global<'py>(py: Python<'py>, name: &str) -> PyResult<Bound<'py, PyAny>> {
+    // Native functions do not push a frame, so this is the frame of the caller
+    let caller_globals = py.import_bound("sys")?.call_method1("_getframe", (0,))?.getattr("f_globals")?;
+    caller_globals.get_item(name)
+        .map_err(|_| pyo3::exceptions::PyRuntimeError::new_err("this function is only available to code run by chidori"))
+}
+
+/// Render one of the program's template cells by name with the keyword arguments as its data,
+/// e.g. `ch.render("email_template", user=user)`. See `TemplateLibrary`.
+#[pyfunction]
+#[pyo3(signature = (name, **kwargs))]
+fn render(py: Python, name: &str, kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<String> {
+    let handle: PyRef<TemplateLibraryHandle> = caller_global(py, TEMPLATE_LIBRARY_GLOBAL)?.extract()?;
+    let data = kwargs.map_or(RkyvSerializedValue::Object(HashMap::new()), |kwargs| pyany_to_rkyv_serialized_value(kwargs.as_any()));
+    handle.library.render(name, &data)
+        .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+}
+
+/// Call a Rust function registered by the embedder, e.g. `ch.native("add", [1, 2])`.
+/// See `ChidoriRuntimeInstance::register_native_function`.
+#[pyfunction]
+#[pyo3(signature = (name, args = None))]
+fn native(py: Python, name: &str, args: Option<&Bound<'_, PyAny>>) -> PyResult<PyObject> {
+    let handle: PyRef<NativeFunctionsHandle> = caller_global(py, NATIVE_FUNCTIONS_GLOBAL)?.extract()?;
+    let f = handle.functions.get(name)
+        .ok_or_else(|| pyo3::exceptions::PyKeyError::new_err(format!("No native function named {} has been registered", name)))?;
+    let args = args.map_or(RkyvSerializedValue::Null, pyany_to_rkyv_serialized_value);
+    Ok(rkyv_serialized_value_to_pyany(py, &f(args)))
+}
+
+/// The store of the memory cell with the given name, e.g. `ch.memory("notes").query("watering")`.
+/// See `MemoryStores`.
+#[pyfunction]
+fn memory(py: Python, name: &str) -> PyResult<MemoryStoreHandle> {
+    let handle: PyRef<MemoryStoresHandle> = caller_global(py, MEMORY_STORES_GLOBAL)?.extract()?;
+    Ok(MemoryStoreHandle { state: handle.state.clone(), name: name.to_string() })
+}
+
+/// Read a parameter of the branch being evaluated, e.g. `ch.param("temperature", 0.7)`, returning
+/// the default if it has not been set. See `ExecutionGraph::set_branch_params`.
+#[pyfunction]
+#[pyo3(signature = (name, default = None))]
+fn param(py: Python, name: &str, default: Option<PyObject>) -> PyResult<PyObject> {
+    let handle: PyRef<BranchParamsHandle> = caller_global(py, BRANCH_PARAMS_GLOBAL)?.extract()?;
+    Ok(match &handle.params {
+        RkyvSerializedValue::Object(params) if params.contains_key(name) => rkyv_serialized_value_to_pyany(py, &params[name]),
+        _ => default.unwrap_or_else(|| py.None()),
+    })
+}
+
+/// Record a reasoning step of the executing cell, e.g. `ch.trace_step("thought", "Check the forecast first")`.
+/// The kind is one of thought, tool_call, tool_result or final_answer. See `AgentTrace`.
+#[pyfunction]
+fn trace_step(py: Python, kind: &str, content: &str) -> PyResult<()> {
+    let handle: PyRef<AgentTraceHandle> = caller_global(py, AGENT_TRACE_GLOBAL)?.extract()?;
+    let kind: AgentTraceStepKind = kind.parse()
+        .map_err(|e: anyhow::Error| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+    handle.trace.record(kind, content);
+    Ok(())
+}
+
 #[pyfunction]
 fn on_event(py: Python, arg: PyObject) -> PyResult<PyObject> {
     let identity_func = wrap_pyfunction!(identity_function, py)?;
@@ -283,6 +577,61 @@ impl LoggingToChannel {
 }
 
 
+/// Trace function installed with `sys.settrace` that aborts evaluation with a `MemoryError` once
+/// the memory traced by `tracemalloc` exceeds the limit of the cell.
+#[pyclass]
+struct MemoryLimitTracer {
+    limit_bytes: u64,
+    get_traced_memory: PyObject,
+    exceeded: bool,
+}
+
+#[pymethods]
+impl MemoryLimitTracer {
+    fn __call__(mut slf: PyRefMut<'_, Self>, _frame: &Bound<'_, PyAny>, _event: &str, _arg: &Bound<'_, PyAny>) -> PyResult<Py<Self>> {
+        let py = slf.py();
+        let (current, _): (u64, u64) = slf.get_traced_memory.bind(py).call0()?.extract()?;
+        if current > slf.limit_bytes {
+            slf.exceeded = true;
+            return Err(pyo3::exceptions::PyMemoryError::new_err(format!("memory limit of {} bytes exceeded", slf.limit_bytes)));
+        }
+        // Also the local trace function, so that every line of each frame is checked
+        Ok(slf.into())
+    }
+}
+
+/// Begin tracing allocations, returns false if they were already being traced by an enclosing evaluation
+fn start_tracing_allocations(py: Python) -> PyResult<bool> {
+    let tracemalloc = py.import_bound("tracemalloc")?;
+    if tracemalloc.call_method0("is_tracing")?.extract::<bool>()? {
+        return Ok(false);
+    }
+    tracemalloc.call_method0("start")?;
+    Ok(true)
+}
+
+/// The peak of traced memory, tracing is stopped if it was begun by `start_tracing_allocations`
+fn finish_tracing_allocations(py: Python, started: bool) -> PyResult<u64> {
+    let tracemalloc = py.import_bound("tracemalloc")?;
+    let (_, peak): (u64, u64) = tracemalloc.call_method0("get_traced_memory")?.extract()?;
+    if started {
+        tracemalloc.call_method0("stop")?;
+    }
+    Ok(peak)
+}
+
+/// Peak memory allocated while running `source` in an empty namespace, as measured by `tracemalloc`
+pub fn measure_python_peak_memory(source: &str) -> anyhow::Result<u64> {
+    pyo3::prepare_freethreaded_python();
+    Python::with_gil(|py| {
+        let started = start_tracing_allocations(py)?;
+        let result = py.run_bound(source, Some(&PyDict::new_bound(py)), None);
+        let peak = finish_tracing_allocations(py, started)?;
+        result?;
+        Ok(peak)
+    })
+}
+
 #[derive(Debug)]
 pub struct AnyhowErrWrapper(anyhow::Error);
 
@@ -297,7 +646,6 @@ impl std::convert::From<AnyhowErrWrapper> for PyErr {
 
 
 
-#[tracing::instrument]
 pub async fn source_code_run_python(
     execution_state: &ExecutionState,
     source_code: &String,
@@ -305,7 +653,35 @@ pub async fn source_code_run_python(
     function_invocation: &Option<String>,
     virtualenv_path: &Option<String>,
     requirements_dir: &Option<String>,
+    inspect_globals: bool,
 ) -> anyhow::Result<(Result<RkyvSerializedValue, ExecutionStateErrors>, Vec<String>, Vec<String>, ExecutionState)> {
+    let (output, stdout, stderr, execution_state, _) = source_code_run_python_with_memory_limit(
+        execution_state,
+        source_code,
+        payload,
+        function_invocation,
+        virtualenv_path,
+        requirements_dir,
+        inspect_globals,
+        None,
+    ).await?;
+    Ok((output, stdout, stderr, execution_state))
+}
+
+/// Run python source as `source_code_run_python` does, additionally reporting the peak memory allocated
+/// while the source is evaluated. Evaluation is aborted with `ExecutionStateErrors::MemoryLimitExceeded`
+/// once allocations exceed `oom_limit_bytes`.
+#[tracing::instrument]
+pub async fn source_code_run_python_with_memory_limit(
+    execution_state: &ExecutionState,
+    source_code: &String,
+    payload: &RkyvSerializedValue,
+    function_invocation: &Option<String>,
+    virtualenv_path: &Option<String>,
+    requirements_dir: &Option<String>,
+    inspect_globals: bool,
+    oom_limit_bytes: Option<u64>,
+) -> anyhow::Result<(Result<RkyvSerializedValue, ExecutionStateErrors>, Vec<String>, Vec<String>, ExecutionState, Option<u64>)> {
 
     // Capture the current span's ID
     let current_span_id = Span::current().id();
@@ -321,7 +697,14 @@ pub async fn source_code_run_python(
     let dependencies = extract_dependencies_python(&source_code)?;
     let report = build_report(&dependencies);
 
+    let templates = TemplateLibrary::from_execution_state(execution_state);
+    let native_functions = execution_state.native_functions.clone();
+    let agent_trace = execution_state.agent_trace.clone();
+    let branch_params = execution_state.branch_params.clone();
+    let memory_state = execution_state.clone();
+    let cancellation = execution_state.cancellation.clone();
     let execution_state = Arc::new(Mutex::new(execution_state.clone()));
+    let mut peak_memory_bytes = None;
     let result =  Python::with_gil(|py| {
         let v = py.version_info();
 
@@ -340,24 +723,32 @@ pub async fn source_code_run_python(
 
 
         // TODO: this was causing a deadlock
-        let current_event_loop = pyo3_asyncio::tokio::get_current_loop(py);
+        let current_event_loop = pyo3_async_runtimes::tokio::get_current_loop(py);
         // Initialize our event loop if one is not already established
         let event_loop = if current_event_loop.is_err() {
-            let asyncio = py.import("asyncio")?;
+            let asyncio = py.import_bound("asyncio")?;
             let event_loop = asyncio.call_method0("new_event_loop")?;
-            asyncio.call_method1("set_event_loop", (event_loop,))?;
+            asyncio.call_method1("set_event_loop", (&event_loop,))?;
             event_loop
         } else {
             current_event_loop.unwrap()
         };
 
         // Configure locals and globals passed to evaluation
-        let globals = PyDict::new(py);
-        create_external_function_shims(&execution_state, &report, py, globals, current_span_id.clone())?;
-        create_internal_proxy_shims(&execution_state, &report, py, globals, current_span_id)?;
+        let globals = PyDict::new_bound(py);
+        create_external_function_shims(&execution_state, &report, py, &globals, current_span_id.clone())?;
+        create_internal_proxy_shims(&execution_state, &report, py, &globals, current_span_id)?;
+        globals.set_item(TEMPLATE_LIBRARY_GLOBAL, Py::new(py, TemplateLibraryHandle { library: templates.clone() })?)?;
+        globals.set_item(NATIVE_FUNCTIONS_GLOBAL, Py::new(py, NativeFunctionsHandle { functions: native_functions.clone() })?)?;
+        globals.set_item(BRANCH_PARAMS_GLOBAL, Py::new(py, BranchParamsHandle { params: branch_params.clone() })?)?;
+        globals.set_item(MEMORY_STORES_GLOBAL, Py::new(py, MemoryStoresHandle { state: memory_state.clone() })?)?;
+        globals.set_item(EVENT_LOOP_GLOBAL, &event_loop)?;
+        if let Some(trace) = &agent_trace {
+            globals.set_item(AGENT_TRACE_GLOBAL, Py::new(py, AgentTraceHandle { trace: trace.clone() })?)?;
+        }
 
 
-        let sys = py.import("sys")?;
+        let sys = py.import_bound("sys")?;
 
         // Get Python version from PyO3
         let v = py.version_info();
@@ -383,21 +774,29 @@ pub async fn source_code_run_python(
         if py_modules.get_item("chidori").is_err() {
             // We assume this will only happen once for the Python GIL instance (per this Rust process)
             // so this is treated as an initialization handler.
-            let chidori_module = PyModule::new(py, "chidori")?;
-            chidori_module.add_function(wrap_pyfunction!(on_event, chidori_module)?)?;
-            chidori_module.add_function(wrap_pyfunction!(identity_function, chidori_module)?)?;
-            let chidori_set_value = PyCFunction::new_closure(
+            let chidori_module = PyModule::new_bound(py, "chidori")?;
+            chidori_module.add_function(wrap_pyfunction!(on_event, &chidori_module)?)?;
+            chidori_module.add_function(wrap_pyfunction!(identity_function, &chidori_module)?)?;
+            chidori_module.add_function(wrap_pyfunction!(diff, &chidori_module)?)?;
+            chidori_module.add_function(wrap_pyfunction!(render, &chidori_module)?)?;
+            chidori_module.add_function(wrap_pyfunction!(native, &chidori_module)?)?;
+            chidori_module.add_function(wrap_pyfunction!(param, &chidori_module)?)?;
+            chidori_module.add_function(wrap_pyfunction!(memory, &chidori_module)?)?;
+            chidori_module.add_function(wrap_pyfunction!(trace_step, &chidori_module)?)?;
+            chidori_module.add_function(wrap_pyfunction!(stream, &chidori_module)?)?;
+            chidori_module.add_function(wrap_pyfunction!(capture_bindings, &chidori_module)?)?;
+            let chidori_set_value = PyCFunction::new_closure_bound(
                 py,
                 None,
                 None,
-                move |args: &PyTuple, kwargs: Option<&PyDict>| {
+                move |args: &Bound<'_, PyTuple>, kwargs: Option<&Bound<'_, PyDict>>| {
                     if args.len() == 3 {
                         let id: usize = args.get_item(0).unwrap().extract::<usize>().unwrap();
                         let name: String = args.get_item(1).unwrap().extract::<String>().unwrap();
                         let output_c = PYTHON_OUTPUT_MAP.clone();
                         let output = output_c.entry(id).or_insert(DashMap::new());
                         let value = args.get_item(2).unwrap(); // Keep as PyAny
-                        output.insert(name, pyany_to_rkyv_serialized_value(value));
+                        output.insert(name, pyany_to_rkyv_serialized_value(&value));
                     }
                 },
             )?;
@@ -433,6 +832,22 @@ sys.stderr.set_exec_id({exec_id})
         initial_source_code.push_str("\n");
         initial_source_code.push_str(&source_code.clone());
 
+        if inspect_globals && function_invocation.is_none() {
+            // Values provided as inputs are bound in the same namespace, they are not outputs of this cell
+            let mut inputs = vec![];
+            if let RkyvSerializedValue::Object(ref payload_map) = payload {
+                if let Some(RkyvSerializedValue::Object(globals_map)) = payload_map.get("globals") {
+                    inputs.extend(globals_map.keys().map(|k| format!("{:?}", k)));
+                }
+            }
+            initial_source_code.push_str("\n");
+            initial_source_code.push_str(&format!(
+                r#"chidori.capture_bindings({exec_id}, locals(), [{inputs}])"#,
+                exec_id = exec_id,
+                inputs = inputs.join(", ")
+            ));
+        }
+
         // If any instances of these lines are located, skip wrapping anything because the code will initialize its own async runtime.
         let does_contain_async_runtime = initial_source_code
             .lines()
@@ -496,7 +911,9 @@ sys.stderr.flush()
                 ));
             }
             let indent_all_source_code = initial_source_code.lines().map(|line| format!("    {}", line)).collect::<Vec<_>>().join("\n");
-            // Wrap all of our code in a top level async wrapper
+            // Wrap all of our code in a top level async wrapper. It runs on the event loop of the
+            // cell rather than one created by `asyncio.run`, which would be closed once the body
+            // completes, leaving functions invoked later on a different loop than their state.
             format!(r#"
 import asyncio
 import chidori
@@ -504,12 +921,53 @@ async def __wrapper():
 {}
     sys.stdout.flush()
     sys.stderr.flush()
-asyncio.run(__wrapper())
-        "#, indent_all_source_code)
+{event_loop}.run_until_complete(__wrapper())
+        "#, indent_all_source_code, event_loop = EVENT_LOOP_GLOBAL)
         };
 
+        // Allocations are traced while the source runs, to report peak usage and enforce the memory limit
+        let started_tracing = start_tracing_allocations(py)?;
+        let tracer = match oom_limit_bytes {
+            Some(limit_bytes) => {
+                let get_traced_memory = py.import_bound("tracemalloc")?.getattr("get_traced_memory")?.unbind();
+                let tracer = Py::new(py, MemoryLimitTracer { limit_bytes, get_traced_memory, exceeded: false })?;
+                sys.call_method1("settrace", (tracer.clone_ref(py),))?;
+                Some(tracer)
+            }
+            None => None,
+        };
+
+        // Cancelling the step raises KeyboardInterrupt in this thread at its next instruction,
+        // which ends the evaluation unless it is blocked in a single call
+        let thread_id: std::os::raw::c_ulong = py.import_bound("threading")?.call_method0("get_ident")?.extract()?;
+        let _cancellation_watch = cancellation.on_cancel(move || {
+            Python::with_gil(|_| unsafe {
+                pyo3::ffi::PyThreadState_SetAsyncExc(thread_id as std::os::raw::c_long, pyo3::ffi::PyExc_KeyboardInterrupt);
+            });
+        });
+
         // Important: this is the point of initial execution of the source code
-        py.run(&complete_code, Some(globals), None)?;
+        let run_result = py.run_bound(&complete_code, Some(&globals), None);
+
+        if tracer.is_some() {
+            sys.call_method1("settrace", (py.None(),))?;
+        }
+        peak_memory_bytes = Some(finish_tracing_allocations(py, started_tracing)?);
+        if cancellation.is_cancelled() {
+            PYTHON_OUTPUT_MAP.remove(&exec_id);
+            return Err(anyhow!("The evaluation of the cell was cancelled"));
+        }
+        if let Some(tracer) = &tracer {
+            let tracer = tracer.borrow(py);
+            if tracer.exceeded {
+                PYTHON_OUTPUT_MAP.remove(&exec_id);
+                let limit_bytes = tracer.limit_bytes;
+                return Ok(Box::pin(async move {
+                    Err(ExecutionStateErrors::MemoryLimitExceeded(limit_bytes))
+                }) as Pin<Box<dyn Future<Output = Result<RkyvSerializedValue, ExecutionStateErrors>> + Send>>);
+            }
+        }
+        run_result?;
 
         // With the source environment established, we can now invoke specific methods provided by this node
         return match function_invocation {
@@ -562,49 +1020,41 @@ asyncio.run(__wrapper())
                         }
                     }
 
-                    let args = PyTuple::new(py, &args);
-                    let kwargs = kwargs.into_iter().into_py_dict(py);
+                    let args = PyTuple::new_bound(py, &args);
+                    let kwargs = kwargs.into_iter().into_py_dict_bound(py);
 
-                    let result = py_func.call(args, Some(kwargs)).map_err(|e| {
+                    let result = py_func.call(args, Some(&kwargs)).map_err(|e| {
                         dbg!(&e);
                         e
                     })?;
-                    if result.get_type().name().unwrap() == "coroutine" {
-                        // If the function is a coroutine, we need to await it
+                    let result = collect_async_generator(py, result)?;
+                    if is_awaitable(py, &result)? {
+                        // Coroutines, and futures or tasks returned by the function, are awaited
                         let is_running = event_loop.call_method0("is_running")?.extract::<bool>()?;
-                        let (fut, result, needs_await) = if !is_running {
+                        let (fut, result) = if !is_running {
                             // If not running, run the event loop
                             let py_any = event_loop.call_method1("run_until_complete", (result,))?;
-                            (None, Some(py_any.into_py(py)), false)
+                            (None, Some(py_any.unbind()))
                         } else {
-                            // If already running, prepare to use pyo3_asyncio
-                            println!("Event loop is already running, using pyo3_asyncio");
-                            let future = pyo3_asyncio::tokio::into_future(result)?;
-                            (Some(future), None, true)
+                            // If already running the coroutine is awaited from tokio
+                            debug!("Event loop is already running, awaiting the coroutine with pyo3_async_runtimes");
+                            let future = pyo3_async_runtimes::tokio::into_future(result)?;
+                            (Some(future), None)
                         };
 
-
-                        // let f = pyo3_asyncio::tokio::into_future(result)?;
                         Ok(Box::pin(async move {
-                            println!("waiting the python coroutine");
                             let final_result = if let Some(fut) = fut {
                                 fut.await.map_err(|e| ExecutionStateErrors::Unknown(e.to_string()))?
                             } else {
                                 result.unwrap()
                             };
 
-                            Ok(Python::with_gil(|py| {
-                                let py_any: &PyAny = final_result.as_ref(py);
-                                pyany_to_rkyv_serialized_value(py_any)
-                            }))
+                            Ok(Python::with_gil(|py| pyany_to_rkyv_serialized_value(final_result.bind(py))))
                         }) as Pin<Box<dyn Future<Output = Result<RkyvSerializedValue, ExecutionStateErrors>> + Send>>)
                     } else {
-                        let result: PyObject = result.into_py(py);
+                        let result: PyObject = result.unbind();
                         Ok(Box::pin(async move {
-                            Ok(Python::with_gil(|py| {
-                                let py_any: &PyAny = result.as_ref(py);
-                                pyany_to_rkyv_serialized_value(py_any)
-                            }))
+                            Ok(Python::with_gil(|py| pyany_to_rkyv_serialized_value(result.bind(py))))
                         }) as Pin<Box<dyn Future<Output=Result<RkyvSerializedValue, ExecutionStateErrors>> + Send>>)
                     }
                 } else {
@@ -619,7 +1069,7 @@ asyncio.run(__wrapper())
             let execution_state = execution_state.lock().unwrap().clone();
             let (_, output_stdout) = PYTHON_LOGGING_BUFFER_STDOUT.remove(&exec_id).unwrap_or((0, vec![]));
             let (_, output_stderr) = PYTHON_LOGGING_BUFFER_STDERR.remove(&exec_id).unwrap_or((0, vec![]));
-            Ok((awaited_result, output_stdout, output_stderr, execution_state))
+            Ok((awaited_result, output_stdout, output_stderr, execution_state, peak_memory_bytes))
         }
         Err(e) => {
             return Err(anyhow::anyhow!(e.to_string()));
@@ -628,7 +1078,28 @@ asyncio.run(__wrapper())
 }
 
 
-fn create_internal_proxy_shims(execution_state_handle: &Arc<Mutex<ExecutionState>>, report: &Report, py: Python, globals: &PyDict, parent_span_id: Option<tracing::Id>) -> Result<(), Error> {
+/// Consumes async generators, yielding their values to the caller
+const ASYNC_GENERATOR_HELPERS: &str = r#"
+async def collect(generator):
+    return [value async for value in generator]
+"#;
+
+fn is_awaitable(py: Python, value: &Bound<'_, PyAny>) -> PyResult<bool> {
+    py.import_bound("inspect")?.call_method1("isawaitable", (value,))?.is_truthy()
+}
+
+/// An async generator returned by a function becomes a coroutine of the list of the values it
+/// yields, other values are returned as they are
+fn collect_async_generator<'py>(py: Python<'py>, value: Bound<'py, PyAny>) -> PyResult<Bound<'py, PyAny>> {
+    if !py.import_bound("inspect")?.call_method1("isasyncgen", (&value,))?.is_truthy()? {
+        return Ok(value);
+    }
+    PyModule::from_code_bound(py, ASYNC_GENERATOR_HELPERS, "chidori_async_generators.py", "chidori_async_generators")?
+        .getattr("collect")?
+        .call1((value,))
+}
+
+fn create_internal_proxy_shims(execution_state_handle: &Arc<Mutex<ExecutionState>>, report: &Report, py: Python, globals: &Bound<'_, PyDict>, parent_span_id: Option<tracing::Id>) -> Result<(), Error> {
     // Create shims for the functions declared within this file,
     // when a hashed reference to a function is invoked, we invoke the actual function internally
     // but through our dispatch system, producing execution states.
@@ -646,12 +1117,12 @@ fn create_internal_proxy_shims(execution_state_handle: &Arc<Mutex<ExecutionState
     Ok(())
 }
 
-fn create_python_dispatch_closure(py: Python, clone_function_name: String, execution_state_handle: Arc<Mutex<ExecutionState>>, parent_span_id: Option<Id>) -> Result<&PyCFunction, Error> {
-    let closure_callable = PyCFunction::new_closure(
+fn create_python_dispatch_closure(py: Python<'_>, clone_function_name: String, execution_state_handle: Arc<Mutex<ExecutionState>>, parent_span_id: Option<Id>) -> Result<Bound<'_, PyCFunction>, Error> {
+    let closure_callable = PyCFunction::new_closure_bound(
         py,
         None, // name
         None, // doc
-        move |args: &PyTuple, kwargs: Option<&PyDict>| -> PyResult<PyObject> {
+        move |args: &Bound<'_, PyTuple>, kwargs: Option<&Bound<'_, PyDict>>| -> PyResult<PyObject> {
             let total_arg_payload = python_args_to_rkyv(args, kwargs)?;
             let clone_function_name = clone_function_name.clone();
             let parent_span_id = parent_span_id.clone();
@@ -666,7 +1137,7 @@ fn create_python_dispatch_closure(py: Python, clone_function_name: String, execu
 
             // All function calls across cells are forced to be async
             let execution_state_handle = execution_state_handle.clone();
-            pyo3_asyncio::tokio::future_into_py(py, async move {
+            pyo3_async_runtimes::tokio::future_into_py(py, async move {
                 let (result, mut result_execution_state) = new_exec_state
                     .dispatch(&clone_function_name, total_arg_payload, parent_span_id.clone())
                     .await.map_err(|e| AnyhowErrWrapper(e))?;
@@ -684,13 +1155,13 @@ fn create_python_dispatch_closure(py: Python, clone_function_name: String, execu
                         Err(PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!("{:}", e)))
                     }
                 }
-            }).map(|x| x.into())
+            }).map(|x| x.unbind())
         },
     )?;
     Ok(closure_callable)
 }
 
-fn create_external_function_shims(execution_state_handle: &Arc<Mutex<ExecutionState>>, report: &Report, py: Python, globals: &PyDict, parent_span_id: Option<tracing::Id>) -> Result<(), Error> {
+fn create_external_function_shims(execution_state_handle: &Arc<Mutex<ExecutionState>>, report: &Report, py: Python, globals: &Bound<'_, PyDict>, parent_span_id: Option<tracing::Id>) -> Result<(), Error> {
     // Create shims for functions that are referred to, we look at what functions are being provided
     // and create shims for matches between the function name provided and the identifiers referred to.
 
@@ -717,7 +1188,7 @@ fn create_external_function_shims(execution_state_handle: &Arc<Mutex<ExecutionSt
     Ok(())
 }
 
-fn python_args_to_rkyv(args: &PyTuple, kwargs: Option<&PyDict>) -> Result<RkyvSerializedValue, PyErr> {
+fn python_args_to_rkyv(args: &Bound<'_, PyTuple>, kwargs: Option<&Bound<'_, PyDict>>) -> Result<RkyvSerializedValue, PyErr> {
     let total_arg_payload = RkyvObjectBuilder::new();
     let total_arg_payload =
         total_arg_payload.insert_value("args", {
@@ -725,7 +1196,7 @@ fn python_args_to_rkyv(args: &PyTuple, kwargs: Option<&PyDict>) -> Result<RkyvSe
             for (i, a) in args.iter().enumerate() {
                 m.insert(
                     format!("{}", i),
-                    pyany_to_rkyv_serialized_value(a),
+                    pyany_to_rkyv_serialized_value(&a),
                 );
             }
             RkyvSerializedValue::Object(m)
@@ -736,7 +1207,7 @@ fn python_args_to_rkyv(args: &PyTuple, kwargs: Option<&PyDict>) -> Result<RkyvSe
             let mut m = HashMap::new();
             for (i, a) in kwargs.iter() {
                 let k: String = i.extract()?;
-                m.insert(k, pyany_to_rkyv_serialized_value(a));
+                m.insert(k, pyany_to_rkyv_serialized_value(&a));
             }
             RkyvSerializedValue::Object(m)
         })
@@ -859,7 +1330,7 @@ x = 12 + y
 li = [x, y]
         "#,
         );
-        let result = source_code_run_python(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None, &None, &None).await;
+        let result = source_code_run_python(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None, &None, &None, false).await;
         assert_eq!(
             result.unwrap(),
             (
@@ -888,7 +1359,7 @@ li = [x, y]
 print("testing")
         "#,
         );
-        let result = source_code_run_python(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None, &None, &None).await;
+        let result = source_code_run_python(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None, &None, &None, false).await;
         assert_eq!(
             result.unwrap(),
             (
@@ -917,10 +1388,171 @@ def example():
                                             &Some("example".to_string()),
                                             &None,
                                             &None,
+                                            false,
         ).await;
         assert_eq!(result.unwrap(), (Ok(RkyvSerializedValue::Number(20)), vec![], vec![]));
     }
 
+    #[tokio::test]
+    async fn test_inspect_globals_captures_all_bindings() {
+        let source_code = String::from(
+            r#"
+x = 5; y = 10
+def ignored(): pass
+        "#,
+        );
+        let payload = RkyvObjectBuilder::new()
+            .insert_object("globals", RkyvObjectBuilder::new().insert_number("input", 1))
+            .build();
+        let result = source_code_run_python(&ExecutionState::new_with_random_id(), &source_code, &payload, &None, &None, &None, true).await;
+        let (output, _, _, _) = result.unwrap();
+        let RkyvSerializedValue::Object(output) = output.unwrap() else { panic!("Expected an object") };
+        assert_eq!(output.get("x"), Some(&RkyvSerializedValue::Number(5)));
+        assert_eq!(output.get("y"), Some(&RkyvSerializedValue::Number(10)));
+        assert_eq!(output.get("ignored"), Some(&RkyvSerializedValue::String("function".to_string())));
+        assert!(!output.contains_key("input"));
+        assert!(!output.contains_key("sys"));
+
+        let result = source_code_run_python(&ExecutionState::new_with_random_id(), &String::from("x = 5; y = 10"), &RkyvSerializedValue::Null, &None, &None, &None, false).await;
+        assert_eq!(
+            result.unwrap().0,
+            Ok(RkyvObjectBuilder::new().insert_number("x", 5).insert_number("y", 10).build())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_diff_values_from_python() {
+        let source_code = String::from(
+            r#"
+import chidori as ch
+
+changes = ch.diff({"a": 1, "b": [1, 2]}, {"a": 2, "b": [1, 2]})
+        "#,
+        );
+        let result = source_code_run_python(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None, &None, &None, false).await;
+        let change = RkyvObjectBuilder::new()
+            .insert_string("kind", "modified".to_string())
+            .insert_value("path", RkyvSerializedValue::Array(vec![RkyvSerializedValue::String("a".to_string())]))
+            .insert_number("old", 1)
+            .insert_number("new", 2)
+            .build();
+        assert_eq!(
+            result.unwrap().0,
+            Ok(RkyvObjectBuilder::new().insert_value("changes", RkyvSerializedValue::Array(vec![change])).build())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_render_template_from_python() {
+        let mut state = ExecutionState::new_with_random_id();
+        state.cells_by_id.insert(Uuid::now_v7(), CellTypes::Template(crate::cells::TemplateCell {
+            backing_file_reference: None,
+            name: Some("email_template".to_string()),
+            body: "Dear {{user.name}},".to_string(),
+            engine: Default::default(),
+        }, Default::default()));
+        let source_code = String::from(
+            r#"
+import chidori as ch
+
+out = ch.render("email_template", user={"name": "Ada"})
+try:
+    ch.render("email_template", user={})
+except ValueError as e:
+    error = str(e)
+        "#,
+        );
+        let result = source_code_run_python(&state, &source_code, &RkyvSerializedValue::Null, &None, &None, &None, false).await;
+        let RkyvSerializedValue::Object(outputs) = result.unwrap().0.unwrap() else { panic!("expected an object") };
+        assert_eq!(outputs.get("out"), Some(&RkyvSerializedValue::String("Dear Ada,".to_string())));
+        let Some(RkyvSerializedValue::String(error)) = outputs.get("error") else { panic!("expected an error") };
+        assert!(error.contains("`user.name`"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_call_native_function_from_python() {
+        let mut state = ExecutionState::new_with_random_id();
+        state.native_functions.insert("add".to_string(), Arc::new(|args| match args {
+            RkyvSerializedValue::Array(a) => match (&a[0], &a[1]) {
+                (RkyvSerializedValue::Number(x), RkyvSerializedValue::Number(y)) => RkyvSerializedValue::Number(x + y),
+                _ => RkyvSerializedValue::Null,
+            },
+            _ => RkyvSerializedValue::Null,
+        }));
+        let source_code = String::from(
+            r#"
+import chidori as ch
+
+total = ch.native("add", [2, 3])
+        "#,
+        );
+        let result = source_code_run_python(&state, &source_code, &RkyvSerializedValue::Null, &None, &None, &None, false).await;
+        assert_eq!(result.unwrap().0, Ok(RkyvObjectBuilder::new().insert_number("total", 5).build()));
+    }
+
+    #[tokio::test]
+    async fn test_read_branch_params_from_python() {
+        let mut state = ExecutionState::new_with_random_id();
+        state.branch_params = RkyvObjectBuilder::new().insert_number("retries", 3).build();
+        let source_code = String::from(
+            r#"
+import chidori as ch
+
+retries = ch.param("retries", 1)
+flag = ch.param("flag", "off")
+        "#,
+        );
+        let result = source_code_run_python(&state, &source_code, &RkyvSerializedValue::Null, &None, &None, &None, false).await;
+        assert_eq!(result.unwrap().0, Ok(RkyvObjectBuilder::new()
+            .insert_number("retries", 3)
+            .insert_string("flag", "off".to_string())
+            .build()));
+    }
+
+    #[tokio::test]
+    async fn test_query_memory_from_python() {
+        let mut state = ExecutionState::new_with_random_id();
+        state.memory_stores = Arc::new(crate::library::std::ai::memory::store::tests::keyword_memory_stores());
+        state.cells_by_id.insert(Uuid::now_v7(), CellTypes::Memory(crate::cells::MemoryCell {
+            name: Some("facts".to_string()),
+            ..Default::default()
+        }, Default::default()));
+        let source_code = String::from(indoc! { r#"
+            import chidori as ch
+
+            facts = ch.memory("facts")
+            await facts.insert("The capital of France is Paris.", {"topic": "geography"})
+            await facts.insert("The Eiffel Tower is located in Paris.", {"topic": "landmarks"})
+            closest = [m["text"] for m in await facts.query("Paris tower", top_k=2)]
+            filtered = [m["metadata"]["topic"] for m in await facts.query("Paris tower", filter={"topic": "geography"})]
+        "#});
+        let result = source_code_run_python(&state, &source_code, &RkyvSerializedValue::Null, &None, &None, &None, false).await;
+        let RkyvSerializedValue::Object(outputs) = result.unwrap().0.unwrap() else { panic!("expected an object") };
+        assert_eq!(outputs.get("closest"), Some(&RkyvSerializedValue::Array(vec![
+            RkyvSerializedValue::String("The Eiffel Tower is located in Paris.".to_string()),
+            RkyvSerializedValue::String("The capital of France is Paris.".to_string()),
+        ])));
+        assert_eq!(outputs.get("filtered"), Some(&RkyvSerializedValue::Array(vec![
+            RkyvSerializedValue::String("geography".to_string()),
+        ])));
+    }
+
+    #[tokio::test]
+    async fn test_memory_limit_aborts_python_evaluation() {
+        let source_code = String::from(indoc! { r#"
+            data = bytearray(50_000_000)
+            size = len(data)
+        "#});
+        let result = source_code_run_python_with_memory_limit(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None, &None, &None, false, Some(10_000_000)).await.unwrap();
+        assert_eq!(result.0, Err(ExecutionStateErrors::MemoryLimitExceeded(10_000_000)));
+        assert!(result.4.unwrap() >= 50_000_000);
+
+        // Within the limit evaluation completes and reports its peak usage
+        let result = source_code_run_python_with_memory_limit(&ExecutionState::new_with_random_id(), &source_code, &RkyvSerializedValue::Null, &None, &None, &None, false, Some(100_000_000)).await.unwrap();
+        assert_eq!(result.0, Ok(RkyvObjectBuilder::new().insert_number("size", 50_000_000).build()));
+        assert!(result.4.unwrap() >= 50_000_000);
+    }
+
     #[tokio::test]
     async fn test_execution_of_internal_function_with_arguments() {
         let source_code = String::from(
@@ -940,6 +1572,7 @@ def example(x):
                                             &Some("example".to_string()),
                                             &None,
                                             &None,
+                                            false,
         ).await;
         assert_eq!(result.unwrap(), (Ok(RkyvSerializedValue::Number(25)), vec![], vec![]));
     }
@@ -962,6 +1595,13 @@ a = 20 + await demo()
                             return 100
                         "#}),
             function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
         }, TextRange::default()), id_a)?;
         let result = source_code_run_python(&state,
                                             &source_code,
@@ -971,6 +1611,7 @@ a = 20 + await demo()
                                             &None,
                                             &None,
                                             &None,
+                                            false,
         ).await;
         assert_eq!(
             result.unwrap(),
@@ -998,6 +1639,13 @@ a = 20 + await demo()
                             return 100
                         "#}),
             function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
         }, TextRange::default()), id_a)?;
         let result = source_code_run_python(&state,
                                             &String::from( r#"data = await demo()"#, ),
@@ -1008,6 +1656,7 @@ a = 20 + await demo()
                                             &None,
                                             &None,
                                             &None,
+                                            false,
         ).await;
         assert_eq!(
             result.unwrap(),
@@ -1020,6 +1669,93 @@ a = 20 + await demo()
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_awaiting_async_function_invocation() {
+        let source_code = String::from(
+            r#"
+import asyncio
+
+async def main():
+    return await asyncio.sleep(0)
+
+async def example():
+    await main()
+    return await asyncio.sleep(0, result=42)
+        "#,
+        );
+        let result = source_code_run_python(&ExecutionState::new_with_random_id(),
+                                            &source_code,
+                                            &RkyvSerializedValue::Null,
+                                            &Some("main".to_string()),
+                                            &None,
+                                            &None,
+                                            false,
+        ).await;
+        assert_eq!(result.unwrap(), (Ok(RkyvSerializedValue::Null), vec![], vec![]));
+        let result = source_code_run_python(&ExecutionState::new_with_random_id(),
+                                            &source_code,
+                                            &RkyvSerializedValue::Null,
+                                            &Some("example".to_string()),
+                                            &None,
+                                            &None,
+                                            false,
+        ).await;
+        assert_eq!(result.unwrap(), (Ok(RkyvSerializedValue::Number(42)), vec![], vec![]));
+    }
+
+    #[tokio::test]
+    async fn test_async_generator_function_invocation() {
+        let source_code = String::from(
+            r#"
+import asyncio
+
+async def example(count):
+    for i in range(count):
+        await asyncio.sleep(0)
+        yield i * 10
+        "#,
+        );
+        let result = source_code_run_python(&ExecutionState::new_with_random_id(),
+                                            &source_code,
+                                            &RkyvObjectBuilder::new()
+                                                .insert_object("args", RkyvObjectBuilder::new().insert_number("0", 3))
+                                                .build(),
+                                            &Some("example".to_string()),
+                                            &None,
+                                            &None,
+                                            false,
+        ).await;
+        assert_eq!(result.unwrap(), (Ok(RkyvSerializedValue::Array(vec![
+            RkyvSerializedValue::Number(0),
+            RkyvSerializedValue::Number(10),
+            RkyvSerializedValue::Number(20),
+        ])), vec![], vec![]));
+    }
+
+    #[tokio::test]
+    async fn test_module_state_shares_the_event_loop_of_functions() {
+        let source_code = String::from(
+            r#"
+import asyncio
+
+queue = asyncio.Queue()
+await queue.put("ready")
+
+async def example():
+    return await queue.get()
+        "#,
+        );
+        let result = source_code_run_python(&ExecutionState::new_with_random_id(),
+                                            &source_code,
+                                            &RkyvSerializedValue::Null,
+                                            &Some("example".to_string()),
+                                            &None,
+                                            &None,
+                                            false,
+        ).await;
+        assert_eq!(result.unwrap(), (Ok(RkyvSerializedValue::String("ready".to_string())), vec![], vec![]));
+    }
+
     #[tokio::test]
     async fn test_chain_of_multiple_dependent_python_functions() -> anyhow::Result<()> {
         // TODO: this should validate that we can invoke a function that depends on another function
@@ -1041,6 +1777,13 @@ data = await demo()
                             return 100 + await demo_second_function_call()
                         "#}),
             function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
         }, TextRange::default()), id_a)?;
         let id_b = Uuid::now_v7();
         let (state, _) = state.update_operation(CellTypes::Code(CodeCell {
@@ -1054,6 +1797,13 @@ data = await demo()
                             return 100
                         "#}),
             function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
         }, TextRange::default()), id_b)?;
         let result = source_code_run_python(&state,
                                             &source_code,
@@ -1062,6 +1812,7 @@ data = await demo()
                                             &None,
                                             &None,
                                             &None,
+                                            false,
         ).await;
         assert_eq!(
             result.unwrap(),
@@ -1098,6 +1849,13 @@ data = await demo()
                             return 100 + await function_b()
                         "#}),
             function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
         }, TextRange::default()), id_a)?;
         let source_code = String::from(
             r#"data = await function_c()"#,
@@ -1146,6 +1904,7 @@ data = await demo()
             &None,
             &None,
             &None,
+            false,
         ).await;
         cancellation_notify.notify_one();
         assert_eq!(
@@ -1168,7 +1927,7 @@ data = await demo()
         // Helper function to check OperationFnOutput
         fn check_operation_output(output: &Arc<OperationFnOutput>, expected_value: i64) -> bool {
             match output.as_ref() {
-                OperationFnOutput { has_error: false, execution_state: None, output: output_value, stdout, stderr } => {
+                OperationFnOutput { has_error: false, execution_state: None, output: output_value, stdout, stderr, .. } => {
                     matches!(output_value, Ok(RkyvSerializedValue::Number(n)) if *n == expected_value as i32)
                         && stdout.is_empty()
                         && stderr.is_empty()
@@ -1228,6 +1987,7 @@ unittest.TextTestRunner().run(unittest.TestLoader().loadTestsFromTestCase(TestMa
                                             &None,
                                             &None,
                                             &None,
+                                            false,
         ).await;
         let (result, _, stderr) = result.unwrap();
         dbg!(&stderr);
@@ -1262,6 +2022,7 @@ unittest.TextTestRunner().run(unittest.TestLoader().loadTestsFromTestCase(TestMa
                                             &None,
                                             &None,
                                             &None,
+                                            false,
         ).await;
         let (result, _, stderr) = result.unwrap();
         dbg!(&stderr);
@@ -1290,6 +2051,7 @@ def example(x):
                                             &Some("example".to_string()),
                                             &None,
                                             &None,
+                                            false,
         ).await;
         assert_eq!(result.unwrap(), (Ok(RkyvSerializedValue::Number(1)), vec![], vec![]));
         let result = source_code_run_python(&ExecutionState::new_with_random_id(),
@@ -1300,6 +2062,7 @@ def example(x):
                                             &Some("example".to_string()),
                                             &None,
                                             &None,
+                                            false,
         ).await;
         assert_eq!(result.unwrap(), (Ok(RkyvSerializedValue::Number(2)), vec![], vec![]));
     }
@@ -1322,6 +2085,7 @@ fn example():
             &Some("example".to_string()),
             &None,
             &None,
+            false,
         ).await;
         match result {
             Ok(_) => {panic!("Must return error.")}
@@ -1348,6 +2112,7 @@ raise ValueError("Raising a python error")
             &Some("example".to_string()),
             &None,
             &None,
+            false,
         ).await;
         match result {
             Ok(_) => {panic!("Must return error.")}
@@ -0,0 +1,279 @@
+//! Plain text extraction from HTML and PDF documents. HTML is reduced to its headings, paragraphs,
+//! lists and preformatted blocks, leaving out navigation, scripts and other page furniture. PDF text
+//! is laid out by pdf-extract, the title and metadata are read from the document information
+//! dictionary. Documents that fail to extract degrade to their raw text, with a warning saying why.
+
+use std::collections::HashMap;
+use ego_tree::NodeRef;
+use scraper::{ElementRef, Html, Node, Selector};
+use crate::cells::ExtractFormat;
+use crate::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue};
+
+/// Elements whose content is never part of the text of a page
+const SKIPPED_ELEMENTS: &[&str] = &[
+    "nav", "script", "style", "noscript", "header", "footer", "aside", "form", "iframe", "svg", "template", "button",
+];
+
+/// Elements extracted as a block of text, their descendants are not visited separately
+const BLOCK_ELEMENTS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6", "p", "li", "pre", "blockquote", "td", "th"];
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ExtractedDocument {
+    pub text: String,
+    pub title: Option<String>,
+    pub metadata: HashMap<String, String>,
+    /// Why the document could not be extracted, in which case `text` is its raw content
+    pub warning: Option<String>,
+}
+
+impl ExtractedDocument {
+    pub fn to_value(&self) -> RkyvSerializedValue {
+        let mut metadata = RkyvObjectBuilder::new();
+        for (key, value) in &self.metadata {
+            metadata = metadata.insert_string(key, value.clone());
+        }
+        let optional = |value: &Option<String>| value.clone().map_or(RkyvSerializedValue::Null, RkyvSerializedValue::String);
+        RkyvObjectBuilder::new()
+            .insert_string("text", self.text.clone())
+            .insert_value("title", optional(&self.title))
+            .insert_value("metadata", metadata.build())
+            .insert_value("warning", optional(&self.warning))
+            .build()
+    }
+
+    fn degraded(document: &[u8], warning: String) -> Self {
+        ExtractedDocument {
+            text: String::from_utf8_lossy(document).into_owned(),
+            warning: Some(warning),
+            ..Default::default()
+        }
+    }
+}
+
+/// The bytes of a document held in a value, either a string or an array of bytes
+pub fn document_bytes(value: &RkyvSerializedValue) -> anyhow::Result<Vec<u8>> {
+    match value {
+        RkyvSerializedValue::String(s) => Ok(s.as_bytes().to_vec()),
+        RkyvSerializedValue::Array(items) => items.iter().map(|item| match item {
+            RkyvSerializedValue::Number(n) => u8::try_from(*n)
+                .map_err(|_| anyhow::anyhow!("{} is not a byte", n)),
+            other => Err(anyhow::anyhow!("Expected an array of bytes, found {:?}", other)),
+        }).collect(),
+        other => Err(anyhow::anyhow!("Documents are extracted from strings or arrays of bytes, found {:?}", other)),
+    }
+}
+
+/// The format of a document, from its first bytes. Documents that are neither PDF nor HTML are
+/// taken to be plain text already.
+fn detect_format(document: &[u8]) -> Option<ExtractFormat> {
+    let start = String::from_utf8_lossy(&document[..document.len().min(1024)]).trim_start().to_ascii_lowercase();
+    if start.starts_with("%pdf") {
+        Some(ExtractFormat::Pdf)
+    } else if start.starts_with('<') {
+        Some(ExtractFormat::Html)
+    } else {
+        None
+    }
+}
+
+pub fn extract_document(document: &[u8], format: ExtractFormat, markdown: bool) -> ExtractedDocument {
+    let format = match format {
+        ExtractFormat::Auto => detect_format(document),
+        format => Some(format),
+    };
+    match format {
+        Some(ExtractFormat::Html) => match std::str::from_utf8(document) {
+            Ok(html) => extract_html(html, markdown),
+            Err(e) => ExtractedDocument::degraded(document, format!("The document is not valid UTF-8: {}", e)),
+        },
+        Some(ExtractFormat::Pdf) => extract_pdf(document)
+            .unwrap_or_else(|e| ExtractedDocument::degraded(document, format!("Failed to extract the PDF: {}", e))),
+        _ => ExtractedDocument {
+            text: String::from_utf8_lossy(document).into_owned(),
+            ..Default::default()
+        },
+    }
+}
+
+/// Collapse runs of whitespace, as a browser would when rendering the text
+fn normalize_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub fn extract_html(html: &str, markdown: bool) -> ExtractedDocument {
+    let document = Html::parse_document(html);
+    let select = |selector: &str| Selector::parse(selector).expect("Selectors are valid");
+
+    let mut metadata = HashMap::new();
+    for meta in document.select(&select("meta[content]")) {
+        let key = meta.value().attr("name").or_else(|| meta.value().attr("property"));
+        if let (Some(key), Some(content)) = (key, meta.value().attr("content")) {
+            metadata.insert(key.to_ascii_lowercase(), content.to_string());
+        }
+    }
+    if let Some(lang) = document.root_element().value().attr("lang") {
+        metadata.insert("language".to_string(), lang.to_string());
+    }
+
+    let title = document.select(&select("title")).next()
+        .or_else(|| document.select(&select("h1")).next())
+        .map(|title| normalize_whitespace(&title.text().collect::<String>()))
+        .filter(|title| !title.is_empty());
+
+    let body = document.select(&select("body")).next().unwrap_or_else(|| document.root_element());
+    let mut blocks = vec![];
+    collect_blocks(*body, markdown, &mut blocks);
+    // Pages without any recognizable blocks keep all of their visible text
+    if blocks.is_empty() {
+        let mut text = String::new();
+        collect_text(*body, &mut text);
+        blocks.push(normalize_whitespace(&text));
+    }
+
+    ExtractedDocument {
+        text: blocks.join("\n\n").trim().to_string(),
+        title,
+        metadata,
+        warning: None,
+    }
+}
+
+fn collect_blocks(node: NodeRef<Node>, markdown: bool, blocks: &mut Vec<String>) {
+    for child in node.children() {
+        let Some(element) = ElementRef::wrap(child) else { continue };
+        let name = element.value().name();
+        if SKIPPED_ELEMENTS.contains(&name) {
+            continue;
+        }
+        if !BLOCK_ELEMENTS.contains(&name) {
+            collect_blocks(child, markdown, blocks);
+            continue;
+        }
+        let mut text = String::new();
+        collect_text(child, &mut text);
+        let block = if name == "pre" {
+            text.trim_end().to_string()
+        } else {
+            normalize_whitespace(&text)
+        };
+        if block.is_empty() {
+            continue;
+        }
+        blocks.push(match (markdown, name) {
+            (false, _) => block,
+            (true, "pre") => format!("```\n{}\n```", block),
+            (true, "li") => format!("- {}", block),
+            (true, "blockquote") => format!("> {}", block),
+            (true, heading) if heading.starts_with('h') => {
+                let level = heading[1..].parse::<usize>().unwrap_or(1);
+                format!("{} {}", "#".repeat(level), block)
+            }
+            (true, _) => block,
+        });
+    }
+}
+
+/// The text beneath a node, leaving out that of skipped elements
+fn collect_text(node: NodeRef<Node>, text: &mut String) {
+    for child in node.children() {
+        match child.value() {
+            Node::Text(t) => text.push_str(t),
+            Node::Element(element) if !SKIPPED_ELEMENTS.contains(&element.name()) => {
+                if element.name() == "br" {
+                    text.push('\n');
+                }
+                collect_text(child, text);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A text string of the document information, UTF-16BE when it opens with a byte order mark and
+/// otherwise PDFDocEncoding, which agrees with Latin-1 on the characters used in practice
+fn decode_text_string(value: &lopdf::Object) -> Option<String> {
+    let bytes = value.as_str().ok()?;
+    match bytes.strip_prefix(&[0xFE, 0xFF]) {
+        Some(utf16) => {
+            let units: Vec<u16> = utf16.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect();
+            String::from_utf16(&units).ok()
+        }
+        None => Some(bytes.iter().map(|&b| b as char).collect()),
+    }
+}
+
+pub fn extract_pdf(document: &[u8]) -> anyhow::Result<ExtractedDocument> {
+    let text = pdf_extract::extract_text_from_mem(document)
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+
+    let pdf = lopdf::Document::load_mem(document)?;
+    let mut title = None;
+    let mut metadata = HashMap::new();
+    if let Ok(info) = pdf.trailer.get_deref(b"Info", &pdf).and_then(|info| info.as_dict()) {
+        for (key, value) in info.iter() {
+            let Some(value) = decode_text_string(value) else { continue };
+            let key = String::from_utf8_lossy(key).to_ascii_lowercase();
+            if key == "title" {
+                title = Some(value).filter(|title| !title.trim().is_empty());
+            } else {
+                metadata.insert(key, value);
+            }
+        }
+    }
+
+    Ok(ExtractedDocument {
+        text: text.trim().to_string(),
+        title,
+        metadata,
+        warning: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARTICLE: &str = include_str!("../../../tests/data/extract/article.html");
+    const REPORT: &[u8] = include_bytes!("../../../tests/data/extract/report.pdf");
+
+    #[test]
+    fn test_extract_html_keeps_headings_and_paragraphs() {
+        let extracted = extract_document(ARTICLE.as_bytes(), ExtractFormat::Auto, false);
+        assert_eq!(extracted.title.as_deref(), Some("Tending a Rooftop Garden"));
+        assert_eq!(extracted.metadata.get("author").map(String::as_str), Some("Ada Gardner"));
+        assert_eq!(extracted.metadata.get("language").map(String::as_str), Some("en"));
+        assert!(extracted.text.starts_with("Tending a Rooftop Garden\n\nRooftops get more sun"));
+        assert!(extracted.text.contains("Watering"));
+        assert!(!extracted.text.contains("Home"));
+        assert!(!extracted.text.contains("trackVisit"));
+        assert_eq!(extracted.warning, None);
+    }
+
+    #[test]
+    fn test_extract_html_as_markdown() {
+        let extracted = extract_html(ARTICLE, true);
+        assert!(extracted.text.contains("# Tending a Rooftop Garden"));
+        assert!(extracted.text.contains("## Watering"));
+        assert!(extracted.text.contains("- Tomatoes"));
+    }
+
+    #[test]
+    fn test_extract_pdf_text_and_title() {
+        let extracted = extract_document(REPORT, ExtractFormat::Auto, false);
+        assert_eq!(extracted.warning, None);
+        assert_eq!(extracted.title.as_deref(), Some("Quarterly Harvest Report"));
+        assert_eq!(extracted.metadata.get("author").map(String::as_str), Some("Ada Gardner"));
+        assert!(extracted.text.contains("Tomato yield rose by twelve percent"));
+    }
+
+    #[test]
+    fn test_failed_extraction_degrades_to_raw_text() {
+        let extracted = extract_document(b"not a pdf at all", ExtractFormat::Pdf, false);
+        assert_eq!(extracted.text, "not a pdf at all");
+        assert!(extracted.warning.is_some());
+
+        let plain = extract_document(b"just some notes", ExtractFormat::Auto, false);
+        assert_eq!(plain.text, "just some notes");
+        assert_eq!(plain.warning, None);
+    }
+}
@@ -0,0 +1,515 @@
+//! Client of the Model Context Protocol. Mcp cells connect to a server over stdio or SSE, list the
+//! tools it provides and call them on behalf of the cells invoking them.
+
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use futures_util::{Stream, StreamExt};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, oneshot};
+use tracing::debug;
+use crate::cells::McpCell;
+use crate::execution::primitives::serialized_value::{json_value_to_serialized_value, serialized_value_to_json_value, RkyvSerializedValue};
+
+/// Version of the protocol requested during the handshake
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// How long discovering the tools of a server may take, including starting it
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct McpTool {
+    pub name: String,
+    pub description: Option<String>,
+    /// JSON schema of the arguments of the tool
+    pub input_schema: Value,
+}
+
+impl McpTool {
+    /// Names of the arguments the tool requires, in the order the schema lists them
+    pub fn required_arguments(&self) -> Vec<String> {
+        self.input_schema["required"].as_array()
+            .map(|required| required.iter().filter_map(|r| r.as_str().map(String::from)).collect())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Default)]
+struct Connection {
+    pending: Mutex<HashMap<u64, oneshot::Sender<Result<Value, String>>>>,
+    closed: Mutex<Option<String>>,
+}
+
+impl Connection {
+    fn receive(&self, message: Value) {
+        let Some(id) = message["id"].as_u64() else {
+            debug!("Ignoring MCP message {}", message);
+            return;
+        };
+        let Some(response) = self.pending.lock().unwrap().remove(&id) else {
+            debug!("Ignoring MCP message without a pending request {}", message);
+            return;
+        };
+        let result = match message.get("error") {
+            Some(error) => Err(error["message"].as_str().map(String::from).unwrap_or_else(|| error.to_string())),
+            None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+        };
+        let _ = response.send(result);
+    }
+
+    /// Record why the connection ended, and fail the requests still awaiting a response
+    fn close(&self, reason: String) {
+        *self.closed.lock().unwrap() = Some(reason);
+        self.pending.lock().unwrap().clear();
+    }
+
+    fn closed_reason(&self) -> String {
+        self.closed.lock().unwrap().clone().unwrap_or_else(|| "The MCP server closed the connection".to_string())
+    }
+}
+
+/// A connection to an MCP server. The transport is driven by its own thread, so that a client can be
+/// shared by cells running on any runtime; it is shut down when the client is dropped.
+pub struct McpClient {
+    outgoing: mpsc::UnboundedSender<Value>,
+    connection: Arc<Connection>,
+    next_id: AtomicU64,
+}
+
+impl McpClient {
+    fn spawn<F, Fut>(transport: F) -> Self
+    where
+        F: FnOnce(mpsc::UnboundedReceiver<Value>, Arc<Connection>) -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>>,
+    {
+        let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+        let connection = Arc::new(Connection::default());
+        let transport_connection = connection.clone();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to create Tokio runtime");
+            let reason = match runtime.block_on(transport(outgoing_rx, transport_connection.clone())) {
+                Ok(()) => "The MCP server closed the connection".to_string(),
+                Err(e) => format!("The connection to the MCP server failed: {}", e),
+            };
+            transport_connection.close(reason);
+        });
+        Self { outgoing, connection, next_id: AtomicU64::new(1) }
+    }
+
+    /// Connect to the server described by `cell` and complete the handshake
+    pub async fn connect(cell: &McpCell) -> anyhow::Result<Self> {
+        let client = if let Some(command) = cell.command.clone() {
+            let args = cell.args.clone();
+            Self::spawn(move |outgoing, connection| async move {
+                let mut child = tokio::process::Command::new(&command)
+                    .args(&args)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::inherit())
+                    .kill_on_drop(true)
+                    .spawn()?;
+                let stdin = child.stdin.take().expect("stdin is piped");
+                let stdout = child.stdout.take().expect("stdout is piped");
+                serve_lines(stdout, stdin, outgoing, connection).await
+            })
+        } else if let Some(url) = cell.url.clone() {
+            Self::spawn(move |outgoing, connection| serve_sse(url, outgoing, connection))
+        } else {
+            anyhow::bail!("An mcp cell requires either a command or a url");
+        };
+        client.initialize().await?;
+        Ok(client)
+    }
+
+    /// Connect to a server exchanging newline delimited messages over the given streams
+    pub async fn connect_streams<R, W>(reader: R, writer: W) -> anyhow::Result<Self>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let client = Self::spawn(move |outgoing, connection| serve_lines(reader, writer, outgoing, connection));
+        client.initialize().await?;
+        Ok(client)
+    }
+
+    async fn initialize(&self) -> anyhow::Result<()> {
+        self.request("initialize", json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": { "name": "chidori", "version": env!("CARGO_PKG_VERSION") },
+        })).await?;
+        self.notify("notifications/initialized", json!({}))
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.outgoing.is_closed()
+    }
+
+    async fn request(&self, method: &str, params: Value) -> anyhow::Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (response, response_rx) = oneshot::channel();
+        self.connection.pending.lock().unwrap().insert(id, response);
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        if self.outgoing.send(message).is_err() {
+            self.connection.pending.lock().unwrap().remove(&id);
+            anyhow::bail!(self.connection.closed_reason());
+        }
+        match response_rx.await {
+            Ok(Ok(result)) => Ok(result),
+            Ok(Err(error)) => anyhow::bail!("MCP request {} failed: {}", method, error),
+            Err(_) => anyhow::bail!(self.connection.closed_reason()),
+        }
+    }
+
+    fn notify(&self, method: &str, params: Value) -> anyhow::Result<()> {
+        self.outgoing.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+            .map_err(|_| anyhow::anyhow!(self.connection.closed_reason()))
+    }
+
+    pub async fn list_tools(&self) -> anyhow::Result<Vec<McpTool>> {
+        let mut tools = vec![];
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let result = self.request("tools/list", params).await?;
+            for tool in result["tools"].as_array().into_iter().flatten() {
+                let Some(name) = tool["name"].as_str() else { continue };
+                tools.push(McpTool {
+                    name: name.to_string(),
+                    description: tool["description"].as_str().map(String::from),
+                    input_schema: tool.get("inputSchema").cloned().unwrap_or_else(|| json!({ "type": "object" })),
+                });
+            }
+            match result["nextCursor"].as_str() {
+                Some(next) => cursor = Some(next.to_string()),
+                None => return Ok(tools),
+            }
+        }
+    }
+
+    /// Call a tool, returning the result as the server sent it
+    pub async fn call_tool(&self, name: &str, arguments: Value) -> anyhow::Result<Value> {
+        self.request("tools/call", json!({ "name": name, "arguments": arguments })).await
+    }
+}
+
+async fn serve_lines<R, W>(
+    reader: R,
+    mut writer: W,
+    mut outgoing: mpsc::UnboundedReceiver<Value>,
+    connection: Arc<Connection>,
+) -> anyhow::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut lines = BufReader::new(reader).lines();
+    loop {
+        tokio::select! {
+            message = outgoing.recv() => {
+                let Some(message) = message else { return Ok(()) };
+                let mut line = serde_json::to_vec(&message)?;
+                line.push(b'\n');
+                writer.write_all(&line).await?;
+                writer.flush().await?;
+            }
+            line = lines.next_line() => {
+                let Some(line) = line? else { return Ok(()) };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str(&line) {
+                    Ok(message) => connection.receive(message),
+                    Err(e) => debug!("Ignoring malformed MCP message: {}", e),
+                }
+            }
+        }
+    }
+}
+
+struct SseEvent {
+    event: String,
+    data: String,
+}
+
+/// Server-sent events parsed from a byte stream
+struct SseEvents<S> {
+    stream: S,
+    buffer: String,
+}
+
+impl<S, B> SseEvents<S>
+where
+    S: Stream<Item = reqwest::Result<B>> + Unpin,
+    B: AsRef<[u8]>,
+{
+    async fn next(&mut self) -> anyhow::Result<Option<SseEvent>> {
+        loop {
+            if let Some(end) = self.buffer.find("\n\n") {
+                let block: String = self.buffer.drain(..end + 2).collect();
+                let mut event = SseEvent { event: "message".to_string(), data: String::new() };
+                for line in block.lines() {
+                    if let Some(name) = line.strip_prefix("event:") {
+                        event.event = name.trim().to_string();
+                    } else if let Some(data) = line.strip_prefix("data:") {
+                        if !event.data.is_empty() {
+                            event.data.push('\n');
+                        }
+                        event.data.push_str(data.strip_prefix(' ').unwrap_or(data));
+                    }
+                }
+                return Ok(Some(event));
+            }
+            match self.stream.next().await {
+                Some(chunk) => self.buffer.push_str(&String::from_utf8_lossy(chunk?.as_ref()).replace("\r\n", "\n")),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+/// The SSE transport: responses arrive on an event stream, which first announces the endpoint
+/// messages are to be posted to
+async fn serve_sse(
+    url: String,
+    mut outgoing: mpsc::UnboundedReceiver<Value>,
+    connection: Arc<Connection>,
+) -> anyhow::Result<()> {
+    let client = reqwest::Client::new();
+    let response = client.get(&url)
+        .header("Accept", "text/event-stream")
+        .send().await?
+        .error_for_status()?;
+    let mut events = SseEvents { stream: response.bytes_stream(), buffer: String::new() };
+    let endpoint = loop {
+        match events.next().await? {
+            Some(event) if event.event == "endpoint" => break reqwest::Url::parse(&url)?.join(event.data.trim())?,
+            Some(_) => continue,
+            None => anyhow::bail!("The MCP server closed the event stream before announcing its endpoint"),
+        }
+    };
+    loop {
+        tokio::select! {
+            message = outgoing.recv() => {
+                let Some(message) = message else { return Ok(()) };
+                client.post(endpoint.clone()).json(&message).send().await?.error_for_status()?;
+            }
+            event = events.next() => {
+                let Some(event) = event? else { return Ok(()) };
+                if event.event != "message" {
+                    continue;
+                }
+                match serde_json::from_str(&event.data) {
+                    Ok(message) => connection.receive(message),
+                    Err(e) => debug!("Ignoring malformed MCP message: {}", e),
+                }
+            }
+        }
+    }
+}
+
+fn clients() -> &'static Mutex<HashMap<String, Arc<McpClient>>> {
+    static CLIENTS: OnceLock<Mutex<HashMap<String, Arc<McpClient>>>> = OnceLock::new();
+    CLIENTS.get_or_init(Default::default)
+}
+
+/// The connection to the server of `cell`. Cells describing the same server share a connection,
+/// which is re-established if the server has gone away.
+pub async fn client_for(cell: &McpCell) -> anyhow::Result<Arc<McpClient>> {
+    let key = cell.server_description();
+    let existing = clients().lock().unwrap().get(&key).filter(|client| !client.is_closed()).cloned();
+    if let Some(client) = existing {
+        return Ok(client);
+    }
+    let client = Arc::new(McpClient::connect(cell).await?);
+    clients().lock().unwrap().insert(key, client.clone());
+    Ok(client)
+}
+
+/// Use `client` for every cell describing the same server as `cell`, e.g. a server running in process
+pub fn share_client(cell: &McpCell, client: McpClient) {
+    clients().lock().unwrap().insert(cell.server_description(), Arc::new(client));
+}
+
+/// The tools provided by the server of `cell`. The signature of a cell is determined synchronously,
+/// so the connection is made from a thread of its own.
+pub fn discover_tools(cell: &McpCell) -> anyhow::Result<Vec<McpTool>> {
+    let cell = cell.clone();
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        runtime.block_on(async {
+            tokio::time::timeout(DISCOVERY_TIMEOUT, async { client_for(&cell).await?.list_tools().await })
+                .await
+                .map_err(|_| anyhow::anyhow!("Timed out listing the tools of MCP server {}", cell.server_description()))?
+        })
+    })
+        .join()
+        .map_err(|_| anyhow::anyhow!("Listing the tools of an MCP server panicked"))?
+}
+
+/// Arguments of a tool call from the args and kwargs of a function invocation. Positional
+/// arguments are matched to the required arguments of the tool in order.
+pub fn tool_arguments(tool: Option<&McpTool>, args: &HashMap<String, RkyvSerializedValue>, kwargs: &HashMap<String, RkyvSerializedValue>) -> anyhow::Result<Value> {
+    let mut arguments = serde_json::Map::new();
+    if !args.is_empty() {
+        let tool = tool.ok_or_else(|| anyhow::anyhow!("Positional arguments require the tool to declare its arguments"))?;
+        let required = tool.required_arguments();
+        let mut positions: Vec<usize> = args.keys().filter_map(|k| k.parse().ok()).collect();
+        positions.sort();
+        for position in positions {
+            let name = required.get(position)
+                .ok_or_else(|| anyhow::anyhow!("Tool {} takes {} positional arguments", tool.name, required.len()))?;
+            arguments.insert(name.clone(), serialized_value_to_json_value(&args[&position.to_string()]));
+        }
+    }
+    for (name, value) in kwargs {
+        arguments.insert(name.clone(), serialized_value_to_json_value(value));
+    }
+    Ok(Value::Object(arguments))
+}
+
+/// The value a tool call resolves to. Structured content is preferred, otherwise a single text
+/// item is returned as a string and anything else as the list of content items.
+pub fn tool_result_to_serialized_value(result: &Value) -> Result<RkyvSerializedValue, String> {
+    let content = result["content"].as_array().cloned().unwrap_or_default();
+    if result["isError"].as_bool().unwrap_or(false) {
+        let messages: Vec<&str> = content.iter().filter_map(|item| item["text"].as_str()).collect();
+        return Err(messages.join("\n"));
+    }
+    if let Some(structured) = result.get("structuredContent") {
+        return Ok(json_value_to_serialized_value(structured));
+    }
+    match content.as_slice() {
+        [item] if item["type"] == "text" => Ok(RkyvSerializedValue::String(item["text"].as_str().unwrap_or_default().to_string())),
+        items => Ok(RkyvSerializedValue::Array(items.iter().map(json_value_to_serialized_value).collect())),
+    }
+}
+
+/// Names of the arguments in a tool schema that may be omitted
+pub fn optional_arguments(tool: &McpTool) -> HashSet<String> {
+    let required: HashSet<String> = tool.required_arguments().into_iter().collect();
+    tool.input_schema["properties"].as_object()
+        .map(|properties| properties.keys().filter(|k| !required.contains(*k)).cloned().collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::io::{duplex, DuplexStream};
+
+    fn mock_response(request: &Value) -> Option<Value> {
+        let id = request.get("id")?.clone();
+        let result = match request["method"].as_str()? {
+            "initialize" => json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "mock", "version": "0" },
+            }),
+            "tools/list" => json!({ "tools": [{
+                "name": "echo",
+                "description": "Respond with the given text",
+                "inputSchema": {
+                    "type": "object",
+                    "properties": { "text": { "type": "string" }, "suffix": { "type": "string" } },
+                    "required": ["text"],
+                },
+            }]}),
+            "tools/call" => {
+                let arguments = &request["params"]["arguments"];
+                match arguments["text"].as_str() {
+                    Some(text) => json!({ "content": [{
+                        "type": "text",
+                        "text": format!("{}{}", text, arguments["suffix"].as_str().unwrap_or_default()),
+                    }]}),
+                    None => json!({ "content": [{ "type": "text", "text": "text is required" }], "isError": true }),
+                }
+            }
+            _ => return Some(json!({ "jsonrpc": "2.0", "id": id, "error": { "code": -32601, "message": "Method not found" } })),
+        };
+        Some(json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+    }
+
+    /// A server providing an `echo` tool, running in process on a thread of its own. Returns the
+    /// streams a client reads from and writes to.
+    pub(crate) fn spawn_mock_server() -> (DuplexStream, DuplexStream) {
+        let (client_reader, mut server_writer) = duplex(4096);
+        let (server_reader, client_writer) = duplex(4096);
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            runtime.block_on(async move {
+                let mut lines = BufReader::new(server_reader).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let Ok(request) = serde_json::from_str::<Value>(&line) else { continue };
+                    if let Some(response) = mock_response(&request) {
+                        let mut line = serde_json::to_vec(&response).unwrap();
+                        line.push(b'\n');
+                        if server_writer.write_all(&line).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            });
+        });
+        (client_reader, client_writer)
+    }
+
+    #[tokio::test]
+    async fn test_list_and_call_tools() -> anyhow::Result<()> {
+        let (reader, writer) = spawn_mock_server();
+        let client = McpClient::connect_streams(reader, writer).await?;
+
+        let tools = client.list_tools().await?;
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0].name, "echo");
+        assert_eq!(tools[0].required_arguments(), vec!["text".to_string()]);
+        assert_eq!(optional_arguments(&tools[0]), HashSet::from(["suffix".to_string()]));
+
+        let result = client.call_tool("echo", json!({ "text": "hi" })).await?;
+        assert_eq!(tool_result_to_serialized_value(&result), Ok(RkyvSerializedValue::String("hi".to_string())));
+
+        let result = client.call_tool("echo", json!({})).await?;
+        assert_eq!(tool_result_to_serialized_value(&result), Err("text is required".to_string()));
+
+        assert!(client.request("resources/list", json!({})).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tool_arguments() -> anyhow::Result<()> {
+        let tool = McpTool {
+            name: "echo".to_string(),
+            description: None,
+            input_schema: json!({ "type": "object", "required": ["text", "suffix"] }),
+        };
+        let args = HashMap::from([
+            ("0".to_string(), RkyvSerializedValue::String("hi".to_string())),
+            ("1".to_string(), RkyvSerializedValue::String("!".to_string())),
+        ]);
+        assert_eq!(tool_arguments(Some(&tool), &args, &HashMap::new())?, json!({ "text": "hi", "suffix": "!" }));
+
+        let kwargs = HashMap::from([("text".to_string(), RkyvSerializedValue::String("hi".to_string()))]);
+        assert_eq!(tool_arguments(None, &HashMap::new(), &kwargs)?, json!({ "text": "hi" }));
+        assert!(tool_arguments(None, &args, &HashMap::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_structured_tool_results() {
+        let result = json!({ "content": [], "structuredContent": { "sum": 3 } });
+        let RkyvSerializedValue::Object(members) = tool_result_to_serialized_value(&result).unwrap() else {
+            panic!("Expected structured content to become an object");
+        };
+        assert_eq!(members["sum"], RkyvSerializedValue::Number(3));
+    }
+}
@@ -1,3 +1,9 @@
 pub mod ai;
+pub mod chunk;
 pub mod code;
+pub mod template;
+#[cfg(feature = "mcp")]
+pub mod mcp;
+#[cfg(feature = "extract")]
+pub mod extract;
 mod scheduling;
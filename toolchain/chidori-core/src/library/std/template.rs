@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use chidori_prompt_format::templating::jinja::{analyze_jinja_referenced_variables, render_named_jinja_template};
+use chidori_prompt_format::templating::templates::{analyze_referenced_partials, render_named_template, SchemaItem};
+pub use chidori_prompt_format::templating::templates::TemplateRenderError;
+use crate::cells::{CellTypes, TemplateEngine};
+use crate::execution::execution::ExecutionState;
+use crate::execution::primitives::serialized_value::{serialized_value_to_json_value, RkyvSerializedValue};
+
+/// Name under which templates passed as source rather than by name are registered
+const INLINE_TEMPLATE_NAME: &str = "inline";
+
+impl TemplateEngine {
+    /// The variables a template written for this engine expects to be given
+    pub fn analyze_referenced_variables(&self, source: &str) -> anyhow::Result<SchemaItem> {
+        match self {
+            TemplateEngine::Handlebars => analyze_referenced_partials(source),
+            TemplateEngine::Jinja => analyze_jinja_referenced_variables(source),
+        }
+    }
+
+    fn render(&self, name: &str, source: &str, data: &serde_json::Value, partials: &HashMap<String, String>, strict: bool) -> Result<String, TemplateRenderError> {
+        match self {
+            TemplateEngine::Handlebars => render_named_template(name, source, data, partials, strict),
+            TemplateEngine::Jinja => render_named_jinja_template(name, source, data, partials, strict),
+        }
+    }
+}
+
+/// The named templates of a program, used to render templates from cells, host functions and
+/// embedders alike. Every template may include any other written for the same engine, with
+/// `{{> name}}` in Handlebars and `{% include "name" %}` in Jinja.
+#[derive(Debug, Clone)]
+pub struct TemplateLibrary {
+    templates: HashMap<TemplateEngine, HashMap<String, String>>,
+    strict: bool,
+}
+
+impl Default for TemplateLibrary {
+    fn default() -> Self {
+        TemplateLibrary {
+            templates: HashMap::new(),
+            strict: true,
+        }
+    }
+}
+
+impl TemplateLibrary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Collect the named template cells among the given cells
+    pub fn from_cells<'a>(cells: impl IntoIterator<Item = &'a CellTypes>) -> Self {
+        let mut library = Self::new();
+        for cell in cells {
+            if let CellTypes::Template(template, _) = cell {
+                if let Some(name) = &template.name {
+                    library.insert_with_engine(name, &template.body, template.engine);
+                }
+            }
+        }
+        library
+    }
+
+    /// The templates defined by the cells of the given state
+    pub fn from_execution_state(state: &ExecutionState) -> Self {
+        Self::from_cells(state.cells_by_id.values())
+    }
+
+    pub fn insert(&mut self, name: &str, body: &str) {
+        self.insert_with_engine(name, body, TemplateEngine::Handlebars);
+    }
+
+    pub fn insert_with_engine(&mut self, name: &str, body: &str, engine: TemplateEngine) {
+        for templates in self.templates.values_mut() {
+            templates.remove(name);
+        }
+        self.templates.entry(engine).or_default().insert(name.to_string(), body.to_string());
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.get_with_engine(name).map(|(source, _)| source)
+    }
+
+    fn get_with_engine(&self, name: &str) -> Option<(&str, TemplateEngine)> {
+        self.templates.iter()
+            .find_map(|(engine, templates)| templates.get(name).map(|source| (source.as_str(), *engine)))
+    }
+
+    fn partials(&self, engine: TemplateEngine) -> &HashMap<String, String> {
+        static EMPTY: std::sync::OnceLock<HashMap<String, String>> = std::sync::OnceLock::new();
+        self.templates.get(&engine).unwrap_or_else(|| EMPTY.get_or_init(HashMap::new))
+    }
+
+    /// Whether a variable missing from the data is an error, defaults to true. When disabled
+    /// missing variables render as empty.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Render the template with the given name against `data`
+    pub fn render(&self, name: &str, data: &RkyvSerializedValue) -> Result<String, TemplateRenderError> {
+        self.render_json(name, &serialized_value_to_json_value(data))
+    }
+
+    pub fn render_json(&self, name: &str, data: &serde_json::Value) -> Result<String, TemplateRenderError> {
+        let Some((source, engine)) = self.get_with_engine(name) else {
+            return Err(TemplateRenderError::UnknownTemplate { template: name.to_string() });
+        };
+        engine.render(name, source, data, self.partials(engine), self.strict)
+    }
+
+    /// Render Handlebars source that is not itself part of the library, such as the body of a prompt
+    pub fn render_source(&self, source: &str, data: &serde_json::Value) -> Result<String, TemplateRenderError> {
+        self.render_source_with_engine(source, data, TemplateEngine::Handlebars)
+    }
+
+    pub fn render_source_with_engine(&self, source: &str, data: &serde_json::Value, engine: TemplateEngine) -> Result<String, TemplateRenderError> {
+        engine.render(INLINE_TEMPLATE_NAME, source, data, self.partials(engine), self.strict)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cells::{TemplateCell, TextRange};
+    use crate::execution::primitives::serialized_value::RkyvObjectBuilder;
+
+    fn template_cell(name: &str, body: &str) -> CellTypes {
+        CellTypes::Template(TemplateCell {
+            backing_file_reference: None,
+            name: Some(name.to_string()),
+            body: body.to_string(),
+            engine: Default::default(),
+        }, TextRange::default())
+    }
+
+    #[test]
+    fn test_render_named_template_with_partials() {
+        let cells = vec![
+            template_cell("email_template", "Dear {{user.name}},\n{{> signature}}"),
+            template_cell("signature", "Regards, {{sender}}"),
+        ];
+        let library = TemplateLibrary::from_cells(&cells);
+        let data = RkyvObjectBuilder::new()
+            .insert_object("user", RkyvObjectBuilder::new().insert_string("name", "Ada".to_string()))
+            .insert_string("sender", "Chidori".to_string())
+            .build();
+        assert_eq!(library.render("email_template", &data), Ok("Dear Ada,\nRegards, Chidori".to_string()));
+    }
+
+    #[test]
+    fn test_missing_variable_reports_its_path() {
+        let library = TemplateLibrary::from_cells(&vec![template_cell("email_template", "Dear {{user.name}}")]);
+        let data = RkyvObjectBuilder::new()
+            .insert_object("user", RkyvObjectBuilder::new())
+            .build();
+        let err = library.render("email_template", &data).unwrap_err();
+        assert!(matches!(
+            &err,
+            TemplateRenderError::MissingVariable { template, path: Some(path), .. } if template == "email_template" && path == "user.name"
+        ), "{:?}", err);
+        assert!(err.to_string().contains("`user.name`"));
+
+        // Lenient libraries render missing values as empty
+        assert_eq!(library.clone().strict(false).render("email_template", &data), Ok("Dear ".to_string()));
+    }
+
+    #[test]
+    fn test_same_template_under_both_engines() {
+        let source = "{% if vip %}Welcome back{% endif %} {{ user.name }}";
+        let data = RkyvObjectBuilder::new()
+            .insert_object("user", RkyvObjectBuilder::new().insert_string("name", "Ada".to_string()))
+            .insert_value("vip", RkyvSerializedValue::Boolean(true))
+            .build();
+        let mut library = TemplateLibrary::new();
+        library.insert_with_engine("greeting", source, TemplateEngine::Jinja);
+        assert_eq!(library.render("greeting", &data), Ok("Welcome back Ada".to_string()));
+        // Handlebars has no statement syntax, the Jinja tags are kept as text
+        library.insert("greeting", source);
+        assert_eq!(library.render("greeting", &data), Ok("{% if vip %}Welcome back{% endif %} Ada".to_string()));
+
+        let mut variables: Vec<String> = TemplateEngine::Jinja.analyze_referenced_variables(source).unwrap().items.into_keys().collect();
+        variables.sort();
+        assert_eq!(variables, vec!["user".to_string(), "vip".to_string()]);
+        let variables: Vec<String> = TemplateEngine::Handlebars.analyze_referenced_variables(source).unwrap().items.into_keys().collect();
+        assert_eq!(variables, vec!["user".to_string()]);
+    }
+
+    #[test]
+    fn test_unknown_template() {
+        let library = TemplateLibrary::new();
+        assert!(matches!(
+            library.render("missing", &RkyvSerializedValue::Null),
+            Err(TemplateRenderError::UnknownTemplate { template }) if template == "missing"
+        ));
+    }
+}
@@ -18,6 +18,11 @@ use tracing::info;
 pub use uuid;
 use chidori_core::sdk::interactive_chidori_wrapper::InteractiveChidoriWrapper;
 use chidori_core::sdk::chidori_runtime_instance::PlaybackState;
+use chidori_core::execution::execution::html_report::HtmlReportOptions;
+use chidori_core::utils::redaction::RedactionConfig;
+use chidori_core::sdk::interactive_chidori_wrapper::parse_md_directory;
+use chidori_core::library::std::template::TemplateLibrary;
+use chidori_core::library::std::ai::llm::cache::ResponseCache;
 pub use chidori_static_analysis;
 pub use chidori_prompt_format;
 
@@ -35,6 +40,68 @@ enum Commands {
         /// Path to the configuration file
         #[arg(short, long)]
         load: PathBuf,
+        /// Keep the responses of models under the program directory and reuse them across runs
+        #[arg(long)]
+        llm_cache: bool,
+    },
+    /// Run the application to completion and write an html report of the run
+    Report {
+        /// Path to the configuration file
+        #[arg(short, long)]
+        load: PathBuf,
+        /// Path of the html file to write
+        #[arg(short, long, default_value = "chidori-report.html")]
+        output: PathBuf,
+        /// Omit LLM prompts and responses from the report
+        #[arg(long)]
+        redact: bool,
+        /// Keep the responses of models under the program directory and reuse them across runs
+        #[arg(long)]
+        llm_cache: bool,
+        /// Also write a json summary of the run to this path, see `RunSummary`
+        #[arg(long)]
+        summary: Option<PathBuf>,
+        /// Redact the values of object keys with this name, may be repeated
+        #[arg(long = "redact-key")]
+        redact_keys: Vec<String>,
+        /// Redact string values matching this regular expression, may be repeated
+        #[arg(long = "redact-pattern")]
+        redact_patterns: Vec<String>,
+    },
+    /// Render one of the template cells of a program and print the result
+    Render {
+        /// Path to the directory of the program
+        path: PathBuf,
+        /// Name of the template cell to render
+        #[arg(short, long)]
+        template: String,
+        /// Path to a json file holding the data to render the template with
+        #[arg(short, long)]
+        data: Option<PathBuf>,
+    },
+    /// Export the cells, recorded values and model responses needed to reproduce a failure into a
+    /// bundle, see `ExecutionGraph::export_repro`
+    Repro {
+        /// Path to the directory of the program, or to the directory of its checkpoints, which
+        /// are recovered before running the program
+        path: PathBuf,
+        /// Id of the state to reproduce, defaults to the state the run stopped at
+        #[arg(long)]
+        node: Option<uuid::Uuid>,
+        /// Path of the bundle to write
+        #[arg(short, long, default_value = "chidori-repro.zip")]
+        output: PathBuf,
+        /// Redact the values of object keys with this name, may be repeated
+        #[arg(long = "redact-key")]
+        redact_keys: Vec<String>,
+        /// Redact string values matching this regular expression, may be repeated
+        #[arg(long = "redact-pattern")]
+        redact_patterns: Vec<String>,
+    },
+    /// Manage the cached responses of models
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
     },
     // /// Run tests
     // Test {
@@ -56,7 +123,17 @@ enum Commands {
     // },
 }
 
-async fn run_command(run_directory: &PathBuf) -> anyhow::Result<()> {
+#[derive(Subcommand)]
+enum CacheCommands {
+    /// Remove every cached response of a program
+    Clear {
+        /// Path to the directory of the program
+        #[arg(default_value = ".")]
+        path: PathBuf,
+    },
+}
+
+async fn run_command(run_directory: &PathBuf, llm_cache: bool) -> anyhow::Result<()> {
     let runtime = tokio::runtime::Handle::current();
 
     let (trace_event_sender, trace_event_receiver) = mpsc::channel();
@@ -66,6 +143,10 @@ async fn run_command(run_directory: &PathBuf) -> anyhow::Result<()> {
         trace_event_sender,
         runtime_event_sender,
     );
+    // The instance is created before the directory is loaded, so the cache is given explicitly
+    if llm_cache {
+        chidori.set_llm_cache(ResponseCache::for_project(run_directory));
+    }
 
     let run_directory_clone = run_directory.clone();
     runtime.spawn(async move {
@@ -95,14 +176,121 @@ async fn run_command(run_directory: &PathBuf) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Upper bound on the number of steps taken when producing a report, in case of cycles
+const REPORT_MAX_STEPS: usize = 10_000;
+
+async fn report_command(run_directory: &PathBuf, output: &PathBuf, summary: Option<&PathBuf>, redact: bool, llm_cache: bool, redaction: RedactionConfig) -> anyhow::Result<()> {
+    let mut chidori = InteractiveChidoriWrapper::new();
+    chidori.set_redaction(redaction.clone());
+    chidori.set_project_llm_cache(llm_cache);
+    chidori.load_md_directory(run_directory)?;
+    let mut instance = chidori.get_instance()?;
+    instance.reload_cells().await?;
+
+    // Stepping fails once there are no remaining operations to evaluate
+    for _ in 0..REPORT_MAX_STEPS {
+        if let Err(e) = instance.step().await {
+            info!("Run finished: {}", e);
+            break;
+        }
+    }
+
+    let options = HtmlReportOptions {
+        redact_llm_content: redact,
+        redaction,
+        ..HtmlReportOptions::default()
+    };
+    instance.db.export_html_report(output, &options)?;
+    info!("Wrote report to {:?}", output);
+    if let Some(summary) = summary {
+        std::fs::write(summary, serde_json::to_string_pretty(&instance.run_summary()?)?)?;
+        info!("Wrote run summary to {:?}", summary);
+    }
+    Ok(())
+}
+
+async fn repro_command(path: &PathBuf, node: Option<uuid::Uuid>, output: &PathBuf, redaction: RedactionConfig) -> anyhow::Result<()> {
+    // The project of a checkpoint directory is two levels up, see `checkpoint_directory`
+    let project = if path.ends_with(".chidori/checkpoints") {
+        path.parent().and_then(|p| p.parent()).map(|p| p.to_path_buf()).unwrap_or_default()
+    } else {
+        path.clone()
+    };
+    let mut chidori = InteractiveChidoriWrapper::new();
+    chidori.load_md_directory(&project)?;
+    if let Some(recovered) = chidori.recover_latest_checkpoint()? {
+        info!("Recovered a checkpoint of {} steps", recovered.step_count);
+    }
+    let mut instance = chidori.get_instance()?;
+    instance.reload_cells().await?;
+
+    // Stepping fails once there are no remaining operations to evaluate, or a cell fails
+    for _ in 0..REPORT_MAX_STEPS {
+        if let Err(e) = instance.step().await {
+            info!("Run stopped: {}", e);
+            break;
+        }
+    }
+
+    let node = node.unwrap_or(instance.execution_head_state_id);
+    let manifest = instance.db.export_repro(node, output, &redaction)?;
+    println!("Wrote a reproduction of {} cells at {} to {:?}", manifest.cells, node, output);
+    Ok(())
+}
+
+fn render_command(path: &PathBuf, template: &str, data: Option<&PathBuf>) -> anyhow::Result<()> {
+    let cells = parse_md_directory(path, None)?;
+    let library = TemplateLibrary::from_cells(&cells);
+    let data = match data {
+        Some(data) => serde_json::from_str(&std::fs::read_to_string(data)?)?,
+        None => serde_json::json!({}),
+    };
+    println!("{}", library.render_json(template, &data)?);
+    Ok(())
+}
+
+fn cache_clear_command(path: &PathBuf) -> anyhow::Result<()> {
+    let cache = ResponseCache::for_project(path);
+    let removed = cache.clear()?;
+    println!("Removed {} cached responses from {:?}", removed, cache.directory());
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()>{
     let cli = Cli::parse();
 
     match &cli.command {
-        Some(Commands::Run { load }) => {
+        Some(Commands::Run { load, llm_cache }) => {
             info!("Running Chidori with target src directory: {:?}", load);
-            run_command(load).await
+            run_command(load, *llm_cache).await
+        }
+        Some(Commands::Report { load, output, summary, redact, llm_cache, redact_keys, redact_patterns }) => {
+            info!("Generating report for target src directory: {:?}", load);
+            let mut redaction = RedactionConfig::new();
+            for key in redact_keys {
+                redaction = redaction.with_key(key);
+            }
+            for pattern in redact_patterns {
+                redaction = redaction.with_pattern(pattern)?;
+            }
+            report_command(load, output, summary.as_ref(), *redact, *llm_cache, redaction).await
+        }
+        Some(Commands::Repro { path, node, output, redact_keys, redact_patterns }) => {
+            let mut redaction = RedactionConfig::new();
+            for key in redact_keys {
+                redaction = redaction.with_key(key);
+            }
+            for pattern in redact_patterns {
+                redaction = redaction.with_pattern(pattern)?;
+            }
+            repro_command(path, *node, output, redaction).await
+        }
+        Some(Commands::Render { path, template, data }) => {
+            render_command(path, template, data.as_ref())
+        }
+        Some(Commands::Cache { command: CacheCommands::Clear { path } }) => {
+            cache_clear_command(path)
         }
         // Some(Commands::Test { test_dir, verbose }) => {
         //     println!("Running tests in directory: {:?}", test_dir);
@@ -0,0 +1,396 @@
+//! Checkpoints let a session be recovered after the process running it crashes. As evaluation
+//! produces new states, the outputs of each are appended to the `.chidori/checkpoints` directory
+//! of the loaded project in an incremental file holding only the states since the last one. The
+//! incremental files are periodically compacted into one.
+//!
+//! Outputs are recorded alongside the cell that produced them rather than by operation id, since
+//! operations are assigned new ids when the program is loaded again. Outputs of cells that have
+//! since been edited are not restored. Each file records the run of the instance that wrote it.
+//!
+//! Outputs are redacted by the redaction of the instance before they are written, unless
+//! checkpoints opt out to restore exactly, see `CheckpointConfig::unredacted`.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use rkyv::Deserialize;
+use crate::cells::CellTypes;
+use crate::execution::execution::execution_graph::{ExecutionGraph, ExecutionNodeId};
+use crate::execution::execution::execution_state::{CloseReason, EnclosedState};
+use crate::execution::execution::ExecutionState;
+use crate::execution::primitives::identifiers::RunId;
+use crate::execution::primitives::operation::OperationFnOutput;
+use crate::execution::primitives::serialized_value::RkyvSerializedValue;
+use crate::utils::redaction::RedactionConfig;
+
+const CHECKPOINT_FILE_PREFIX: &str = "checkpoint-";
+const CHECKPOINT_FILE_EXTENSION: &str = "rkyv";
+
+/// Number of incremental files kept before they are compacted into one
+pub const DEFAULT_COMPACT_AFTER_FILES: usize = 8;
+
+/// Directory the checkpoints of the project in the given directory are written to
+pub fn checkpoint_directory(project: &Path) -> PathBuf {
+    project.join(".chidori").join("checkpoints")
+}
+
+/// When checkpoints are written. A checkpoint is written once either threshold is reached, and
+/// only when there are states that have not been written yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckpointConfig {
+    /// Write a checkpoint once this much time has passed since the last
+    pub interval: Option<Duration>,
+    /// Write a checkpoint once this many states have been produced since the last
+    pub every_states: Option<usize>,
+    pub compact_after_files: usize,
+    /// Whether outputs are redacted before they are written. Redacted values are restored as
+    /// their placeholders.
+    pub redact: bool,
+}
+
+impl CheckpointConfig {
+    pub fn every_states(states: usize) -> Self {
+        Self { interval: None, every_states: Some(states), compact_after_files: DEFAULT_COMPACT_AFTER_FILES, redact: true }
+    }
+
+    pub fn interval(interval: Duration) -> Self {
+        Self { interval: Some(interval), every_states: None, compact_after_files: DEFAULT_COMPACT_AFTER_FILES, redact: true }
+    }
+
+    /// Write outputs as they are, so that they round-trip through the checkpoint
+    pub fn unredacted(mut self) -> Self {
+        self.redact = false;
+        self
+    }
+
+    pub fn with_compact_after_files(mut self, files: usize) -> Self {
+        self.compact_after_files = files.max(1);
+        self
+    }
+}
+
+/// The outputs of a state, each alongside the cell that produced it. Only successful outputs are
+/// recorded, operations that failed evaluate again after recovery.
+#[derive(Debug, PartialEq, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+pub struct CheckpointedState {
+    pub id: ExecutionNodeId,
+    pub parent_id: ExecutionNodeId,
+    pub outputs: Vec<(CellTypes, RkyvSerializedValue)>,
+}
+
+impl CheckpointedState {
+    fn from_state(state: &ExecutionState, redaction: Option<&RedactionConfig>) -> Self {
+        let outputs = state.state.iter()
+            .filter_map(|(op_id, output)| {
+                let cell = state.cells_by_id.get(op_id)?;
+                let value = output.output.as_ref().ok()?;
+                let value = match redaction {
+                    Some(redaction) => redaction.redact_cell_output(Some(cell), value),
+                    None => value.clone(),
+                };
+                Some((cell.clone(), value))
+            })
+            .collect();
+        Self { id: state.chronology_id, parent_id: state.parent_state_chronology_id, outputs }
+    }
+}
+
+/// The contents of one checkpoint file, the states written since the previous file and the
+/// execution head at the time it was written
+#[derive(Debug, PartialEq, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+struct CheckpointFile {
+    head_id: ExecutionNodeId,
+    run_id: Option<RunId>,
+    states: Vec<CheckpointedState>,
+}
+
+/// Every checkpointed state of a session, merged from its checkpoint files
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub head_id: ExecutionNodeId,
+    /// The run that wrote the most recent file
+    pub run_id: Option<RunId>,
+    pub states: HashMap<ExecutionNodeId, CheckpointedState>,
+}
+
+impl Checkpoint {
+    /// A checkpoint holding only the given state as its head, unredacted since it is never written
+    pub fn of_state(state: &ExecutionState, run_id: Option<RunId>) -> Self {
+        let head = CheckpointedState::from_state(state, None);
+        Checkpoint { head_id: head.id, run_id, states: HashMap::from([(head.id, head)]) }
+    }
+
+    pub fn head(&self) -> Option<&CheckpointedState> {
+        self.states.get(&self.head_id)
+    }
+
+    /// Number of checkpointed states from the first evaluated state up to the head
+    pub fn step_count(&self) -> usize {
+        let mut count = 0;
+        let mut id = self.head_id;
+        while let Some(state) = self.states.get(&id) {
+            count += 1;
+            if count > self.states.len() {
+                break;
+            }
+            id = state.parent_id;
+        }
+        count
+    }
+
+    /// A state following `state` that holds the outputs of the checkpointed head, for those of its
+    /// cells that are unchanged other than where they are written. The restored state keeps the id
+    /// of the checkpointed head.
+    pub fn restore_onto(&self, state: &ExecutionState) -> anyhow::Result<ExecutionState> {
+        let head = self.head()
+            .ok_or_else(|| anyhow::anyhow!("Checkpoint does not contain its head state {}", self.head_id))?;
+        let mut restored = state.clone();
+        restored.chronology_id = head.id;
+        restored.parent_state_chronology_id = state.chronology_id;
+        restored.evaluating_enclosed_state = EnclosedState::Close(CloseReason::Complete);
+        restored.evaluating_fn = None;
+        restored.state_transient.clear();
+        for (op_id, cell) in state.cells_by_id.iter() {
+            let cell = cell.without_location();
+            if let Some((_, value)) = head.outputs.iter().find(|(checkpointed, _)| checkpointed.without_location() == cell) {
+                restored.state_insert(*op_id, OperationFnOutput::with_value(value.clone()));
+            }
+        }
+        Ok(restored)
+    }
+}
+
+fn checkpoint_files(directory: &Path) -> anyhow::Result<Vec<(u64, PathBuf)>> {
+    if !directory.exists() {
+        return Ok(vec![]);
+    }
+    let mut files = vec![];
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.extension().map_or(true, |ext| ext != CHECKPOINT_FILE_EXTENSION) {
+            continue;
+        }
+        let sequence = path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.strip_prefix(CHECKPOINT_FILE_PREFIX))
+            .and_then(|sequence| sequence.parse().ok());
+        if let Some(sequence) = sequence {
+            files.push((sequence, path));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn checkpoint_file_path(directory: &Path, sequence: u64) -> PathBuf {
+    directory.join(format!("{}{:08}.{}", CHECKPOINT_FILE_PREFIX, sequence, CHECKPOINT_FILE_EXTENSION))
+}
+
+fn read_checkpoint_file(path: &Path) -> anyhow::Result<CheckpointFile> {
+    let mut bytes = rkyv::AlignedVec::new();
+    bytes.extend_from_slice(&std::fs::read(path)?);
+    let archived = rkyv::check_archived_root::<CheckpointFile>(&bytes)
+        .map_err(|e| anyhow::anyhow!("Checkpoint {} is malformed: {}", path.display(), e))?;
+    Ok(archived.deserialize(&mut rkyv::Infallible)?)
+}
+
+fn write_checkpoint_file(path: &Path, file: &CheckpointFile) -> anyhow::Result<()> {
+    let bytes = rkyv::to_bytes::<_, 4096>(file)
+        .map_err(|e| anyhow::anyhow!("Failed to serialize checkpoint: {}", e))?;
+    // Written aside and moved into place, so a crash while writing leaves no partial checkpoint
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, &bytes)?;
+    std::fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// The checkpoint of the most recent session in `directory`, None if there is none
+pub fn read_latest_checkpoint(directory: &Path) -> anyhow::Result<Option<Checkpoint>> {
+    let mut checkpoint: Option<Checkpoint> = None;
+    for (_, path) in checkpoint_files(directory)? {
+        let file = read_checkpoint_file(&path)?;
+        let checkpoint = checkpoint.get_or_insert_with(|| Checkpoint { head_id: file.head_id, run_id: file.run_id, states: HashMap::new() });
+        checkpoint.head_id = file.head_id;
+        checkpoint.run_id = file.run_id;
+        checkpoint.states.extend(file.states.into_iter().map(|state| (state.id, state)));
+    }
+    Ok(checkpoint.filter(|checkpoint| checkpoint.head().is_some()))
+}
+
+/// Replace the checkpoint files in `directory` with a single file holding all of their states
+pub fn compact_checkpoints(directory: &Path) -> anyhow::Result<()> {
+    let files = checkpoint_files(directory)?;
+    let Some((last_sequence, _)) = files.last() else { return Ok(()) };
+    let Some(checkpoint) = read_latest_checkpoint(directory)? else { return Ok(()) };
+    let mut states: Vec<_> = checkpoint.states.into_values().collect();
+    states.sort_by_key(|state| state.id);
+    write_checkpoint_file(&checkpoint_file_path(directory, *last_sequence), &CheckpointFile { head_id: checkpoint.head_id, run_id: checkpoint.run_id, states })?;
+    for (sequence, path) in &files {
+        if sequence != last_sequence {
+            std::fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes checkpoints of the states of an execution graph as they are produced
+#[derive(Debug)]
+pub struct Checkpointer {
+    config: CheckpointConfig,
+    directory: PathBuf,
+    written: HashSet<ExecutionNodeId>,
+    last_written_at: Instant,
+    next_sequence: u64,
+}
+
+impl Checkpointer {
+    /// Checkpoints continue those already in `directory`, so that a recovered session is
+    /// recoverable again
+    pub fn new(config: CheckpointConfig, directory: PathBuf) -> anyhow::Result<Self> {
+        let next_sequence = checkpoint_files(&directory)?.last().map_or(0, |(sequence, _)| sequence + 1);
+        Ok(Self { config, directory, written: HashSet::new(), last_written_at: Instant::now(), next_sequence })
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Treat the given states as written, e.g. those restored from a checkpoint
+    pub fn mark_written(&mut self, ids: impl IntoIterator<Item = ExecutionNodeId>) {
+        self.written.extend(ids);
+    }
+
+    /// Write the states of `graph` that have not been written yet if a threshold has been reached,
+    /// recording the run that produced them and redacting their outputs with `redaction` unless
+    /// opted out. Returns whether a checkpoint was written.
+    pub fn checkpoint_if_due(&mut self, graph: &ExecutionGraph, head_id: ExecutionNodeId, run_id: RunId, redaction: &RedactionConfig) -> anyhow::Result<bool> {
+        let mut pending: Vec<ExecutionState> = graph.execution_node_id_to_state.iter()
+            .filter(|entry| !entry.key().is_nil() && !self.written.contains(entry.key()))
+            .map(|entry| entry.value().clone())
+            .collect();
+        if pending.is_empty() {
+            return Ok(false);
+        }
+        let due_by_count = self.config.every_states.map_or(false, |states| pending.len() >= states);
+        let due_by_time = self.config.interval.map_or(false, |interval| self.last_written_at.elapsed() >= interval);
+        if !due_by_count && !due_by_time {
+            return Ok(false);
+        }
+
+        pending.sort_by_key(|state| state.chronology_id);
+        std::fs::create_dir_all(&self.directory)?;
+        let redaction = Some(redaction).filter(|_| self.config.redact);
+        let file = CheckpointFile {
+            head_id,
+            run_id: Some(run_id),
+            states: pending.iter().map(|state| CheckpointedState::from_state(state, redaction)).collect(),
+        };
+        write_checkpoint_file(&checkpoint_file_path(&self.directory, self.next_sequence), &file)?;
+        self.next_sequence += 1;
+        self.written.extend(pending.iter().map(|state| state.chronology_id));
+        self.last_written_at = Instant::now();
+
+        if checkpoint_files(&self.directory)?.len() > self.config.compact_after_files {
+            compact_checkpoints(&self.directory)?;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+    use crate::cells::{TemplateCell, TextRange};
+    use crate::execution::primitives::serialized_value::RkyvObjectBuilder;
+
+    fn layout_cell(range: TextRange) -> CellTypes {
+        CellTypes::Template(TemplateCell {
+            backing_file_reference: None,
+            name: Some("layout".to_string()),
+            body: "<p>{{body}}</p>".to_string(),
+            engine: Default::default(),
+        }, range)
+    }
+
+    fn checkpointed_state(id: ExecutionNodeId, parent_id: ExecutionNodeId, value: i32) -> CheckpointedState {
+        CheckpointedState { id, parent_id, outputs: vec![(layout_cell(TextRange::default()), RkyvSerializedValue::Number(value))] }
+    }
+
+    #[test]
+    fn test_outputs_are_restored_to_moved_cells() -> anyhow::Result<()> {
+        let head = Uuid::now_v7();
+        let checkpoint = Checkpoint { head_id: head, run_id: None, states: HashMap::from([(head, checkpointed_state(head, Uuid::nil(), 1))]) };
+        let op_id = Uuid::now_v7();
+        let mut state = ExecutionState::new_with_random_id();
+        // The cell was written further down the document after the checkpoint
+        state.cells_by_id.insert(op_id, layout_cell(TextRange { start: 40, end: 80 }));
+        let restored = checkpoint.restore_onto(&state)?;
+        assert_eq!(restored.state_get_value(&op_id), Some(&Ok(RkyvSerializedValue::Number(1))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkpoints_are_redacted_unless_opted_out() -> anyhow::Result<()> {
+        let redaction = RedactionConfig::new().with_key("email");
+        let record = RkyvObjectBuilder::new()
+            .insert_string("name", "Ada".to_string())
+            .insert_string("email", "ada@example.com".to_string())
+            .build();
+        let op_id = Uuid::now_v7();
+        let mut state = ExecutionState::new_with_random_id();
+        state.chronology_id = Uuid::now_v7();
+        state.parent_state_chronology_id = Uuid::nil();
+        state.cells_by_id.insert(op_id, layout_cell(TextRange::default()));
+        state.state_insert(op_id, OperationFnOutput::with_value(record.clone()));
+        let graph = ExecutionGraph::new();
+        graph.insert_state(state.clone());
+
+        for (config, expected) in [
+            (CheckpointConfig::every_states(1), redaction.redact(&record)),
+            (CheckpointConfig::every_states(1).unredacted(), record.clone()),
+        ] {
+            let directory = std::env::temp_dir().join(format!("chidori-checkpoints-{}", Uuid::now_v7()));
+            let mut checkpointer = Checkpointer::new(config, directory.clone())?;
+            assert!(checkpointer.checkpoint_if_due(&graph, state.chronology_id, Uuid::now_v7(), &redaction)?);
+            let checkpoint = read_latest_checkpoint(&directory)?.unwrap();
+            assert_eq!(checkpoint.head().unwrap().outputs, vec![(layout_cell(TextRange::default()), expected)]);
+            std::fs::remove_dir_all(&directory)?;
+        }
+        assert_ne!(redaction.redact(&record), record);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compacting_checkpoints_preserves_states() -> anyhow::Result<()> {
+        let directory = std::env::temp_dir().join(format!("chidori-checkpoints-{}", Uuid::now_v7()));
+        std::fs::create_dir_all(&directory)?;
+        let first = Uuid::now_v7();
+        let second = Uuid::now_v7();
+        let run_id = Uuid::now_v7();
+        write_checkpoint_file(&checkpoint_file_path(&directory, 0), &CheckpointFile {
+            head_id: first,
+            run_id: None,
+            states: vec![checkpointed_state(first, Uuid::nil(), 1)],
+        })?;
+        write_checkpoint_file(&checkpoint_file_path(&directory, 1), &CheckpointFile {
+            head_id: second,
+            run_id: Some(run_id),
+            states: vec![checkpointed_state(second, first, 2)],
+        })?;
+
+        let checkpoint = read_latest_checkpoint(&directory)?.unwrap();
+        assert_eq!(checkpoint.head_id, second);
+        assert_eq!(checkpoint.run_id, Some(run_id));
+        assert_eq!(checkpoint.step_count(), 2);
+
+        compact_checkpoints(&directory)?;
+        assert_eq!(checkpoint_files(&directory)?.len(), 1);
+        assert_eq!(read_latest_checkpoint(&directory)?, Some(checkpoint));
+        assert_eq!(Checkpointer::new(CheckpointConfig::every_states(1), directory.clone())?.next_sequence, 2);
+        std::fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+}
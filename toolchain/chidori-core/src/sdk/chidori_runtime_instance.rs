@@ -1,37 +1,95 @@
-use std::collections::HashSet;
-use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{mpsc, Arc};
-use tokio::sync::mpsc::Receiver as TokioReceiver;
+use std::collections::{HashSet, VecDeque};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use tokio::sync::mpsc::{Receiver as TokioReceiver, UnboundedReceiver, UnboundedSender};
 use no_deadlocks::Mutex;
 use std::fmt;
 use uuid::Uuid;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use anyhow::anyhow;
 use dashmap::mapref::one::Ref;
-use tracing::{debug, info};
-use crate::cells::CellTypes;
+use im::HashMap as ImHashMap;
+use tracing::{debug, info, Instrument};
+use crate::cells::{CellTypes, ExecutionPolicy, ProxyConfig, SupportedModelProviders};
+use crate::cells::webhook_cell::WebhookServers;
 use crate::execution::execution::execution_graph::{ExecutionGraph, ExecutionNodeId};
-use crate::execution::execution::execution_state::{EnclosedState};
+use crate::execution::execution::execution_state::{Cancellation, DefinitionValidationReport, EnclosedState, InputResolutionHook, NativeFunction, OperationCompletionSink, DEFAULT_MAX_INVOCATION_DEPTH};
 use crate::execution::execution::ExecutionState;
-use crate::execution::primitives::identifiers::OperationId;
-use crate::execution::primitives::operation::OperationFnOutput;
+use crate::execution::primitives::identifiers::{OperationId, RunId};
+use crate::execution::primitives::agent_trace::{AgentTraceSink, AgentTraceStep};
+use crate::execution::primitives::operation::{OperationFnOutput, OutputItemConfiguration, RecordedInput};
 use crate::execution::primitives::serialized_value::RkyvSerializedValue;
+use crate::library::std::template::TemplateLibrary;
 use crate::sdk::interactive_chidori_wrapper::{EventsFromRuntime, SharedState};
 use crate::sdk::interactive_chidori_wrapper::CellHolder;
+use crate::sdk::checkpoint::{Checkpoint, Checkpointer};
+use crate::sdk::event_subscriptions::{EventKind, EventSubscribers};
+use crate::sdk::run_summary::RunSummary;
+use crate::utils::prompt_audit::PromptAuditLog;
+use crate::library::std::ai::memory::store::MemoryStores;
+use crate::library::std::ai::llm::cache::ResponseCache;
+use crate::library::std::ai::llm::Tool;
+use crate::library::std::code::runtime_deno::DenoModuleConfig;
+use crate::utils::redaction::RedactionConfig;
 use crate::utils::telemetry::TraceEvents;
 
+/// Default limit on the steps taken waiting for the graph to settle. Cells that `always_run` keep
+/// an operation ready on every step, so waiting for nothing to be ready would never end.
+pub const DEFAULT_MAX_SETTLE_STEPS: usize = 1000;
+
 /// Instanced environments are not Send and live on a single thread.
 /// They execute their operations across multiple threads, but individual OperationNodes
 /// must remain on the given thread they're initialized on.
 pub struct ChidoriRuntimeInstance {
-    pub env_rx: Receiver<UserInteractionMessage>,
+    pub env_rx: UnboundedReceiver<UserInteractionMessage>,
     pub db: ExecutionGraph,
     pub execution_head_state_id: ExecutionNodeId,
     pub playback_state: PlaybackState,
-    pub runtime_event_sender: Option<Sender<EventsFromRuntime>>,
+    /// Clients subscribed to the events of this instance, shared with the wrapper that created it
+    pub runtime_events: EventSubscribers,
     pub trace_event_sender: Option<Sender<TraceEvents>>,
     pub shared_state: Arc<Mutex<SharedState>>,
     pub rx_execution_states: TokioReceiver<ExecutionState>,
+    pub input_resolution_hook: Option<Arc<InputResolutionHook>>,
+    pub auto_play: bool,
+    /// When set, only these operations are loaded into the instance, see `get_instance_for_subgraph`
+    pub scoped_operations: Option<HashSet<OperationId>>,
+    /// Limit on the depth of nested function invocations across cells during a step
+    pub max_invocation_depth: usize,
+    /// Limit on the steps taken waiting for the graph to settle, see `await_output`
+    pub max_settle_steps: usize,
+    /// Applied to every value emitted to clients of this instance
+    pub redaction: RedactionConfig,
+    /// Report the wall-clock duration of every step to clients, see `EventsFromRuntime::StepTiming`
+    pub benchmark_mode: bool,
+    /// Timeout and retry settings for cells that do not declare their own
+    pub default_execution_policy: ExecutionPolicy,
+    /// Transcript of every prompt sent and response received, see `PromptAuditLog`
+    pub prompt_audit: Option<Arc<PromptAuditLog>>,
+    /// Responses of models reused across runs, see `ResponseCache`
+    pub llm_cache: Option<Arc<ResponseCache>>,
+    /// Rust functions callable from code cells, see `register_native_function`
+    pub native_functions: ImHashMap<String, Arc<NativeFunction>>,
+    /// Contents of the memory cells of this instance, see `MemoryStores`
+    pub memory_stores: Arc<MemoryStores>,
+    /// Resolution of the modules imported by javascript cells, see `DenoModuleConfig`
+    pub deno_modules: DenoModuleConfig,
+    /// Servers started by the webhook cells of this instance, see `WebhookServers`
+    pub webhook_servers: Arc<WebhookServers>,
+    /// Proxies the requests of prompts are sent through, by their provider
+    pub llm_proxies: ImHashMap<SupportedModelProviders, ProxyConfig>,
+    /// Writes checkpoints of the states produced by this instance, see `Checkpointer`
+    pub checkpointer: Option<Checkpointer>,
+    /// Restored once the cells of this instance are next reloaded, see `restore_checkpoint`
+    pub pending_checkpoint: Option<Checkpoint>,
+    /// Names of the cells execution pauses before, see `pause_at_breakpoint`
+    pub breakpoints: HashSet<String>,
+    /// The state execution last paused at for a breakpoint, resuming from it evaluates the cell
+    pub breakpoint_hit_at: Option<ExecutionNodeId>,
+    /// Identifies this run in its events, trace spans and checkpoints, see `run_id`
+    pub run_id: RunId,
+    /// The run this instance was forked from, see `InteractiveChidoriWrapper::fork_instance`
+    pub parent_run_id: Option<RunId>,
 }
 
 impl std::fmt::Debug for ChidoriRuntimeInstance {
@@ -43,66 +101,210 @@ impl std::fmt::Debug for ChidoriRuntimeInstance {
 
 impl ChidoriRuntimeInstance {
     pub fn new() -> ChidoriRuntimeInstance {
-        let (tx, rx) = mpsc::channel();
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
         let mut db = ExecutionGraph::new();
         let execution_event_rx = db.take_execution_event_receiver();
         let state_id = Uuid::nil();
         let playback_state = PlaybackState::Paused;
+        let run_id = Uuid::now_v7();
 
         ChidoriRuntimeInstance {
             env_rx: rx,
             db,
             execution_head_state_id: state_id,
-            runtime_event_sender: None,
+            runtime_events: EventSubscribers::new().for_run(run_id),
             trace_event_sender: None,
             playback_state,
             shared_state: Arc::new(Mutex::new(SharedState::new())),
             rx_execution_states: execution_event_rx,
+            input_resolution_hook: None,
+            auto_play: false,
+            scoped_operations: None,
+            max_invocation_depth: DEFAULT_MAX_INVOCATION_DEPTH,
+            max_settle_steps: DEFAULT_MAX_SETTLE_STEPS,
+            redaction: RedactionConfig::default(),
+            benchmark_mode: false,
+            default_execution_policy: ExecutionPolicy::default(),
+            prompt_audit: None,
+            llm_cache: None,
+            native_functions: Default::default(),
+            memory_stores: Default::default(),
+            deno_modules: Default::default(),
+            webhook_servers: Default::default(),
+            llm_proxies: Default::default(),
+            checkpointer: None,
+            pending_checkpoint: None,
+            breakpoints: HashSet::new(),
+            breakpoint_hit_at: None,
+            run_id,
+            parent_run_id: None,
         }
     }
 
+    /// The id generated when this instance was created, attached to the events it emits, the
+    /// trace spans of its steps and the checkpoints it writes
+    pub fn run_id(&self) -> RunId {
+        self.run_id
+    }
+
+    pub fn parent_run_id(&self) -> Option<RunId> {
+        self.parent_run_id
+    }
+
+    /// A summary of the run up to the execution head, with the redaction of this instance applied
+    pub fn run_summary(&self) -> anyhow::Result<RunSummary> {
+        RunSummary::from_graph(&self.db, self.execution_head_state_id, self.run_id, &self.redaction)
+    }
+
+    /// The span the steps of this instance are evaluated within, spans nested in it inherit its run
+    fn step_span(&self, execution_head_state_id: ExecutionNodeId) -> tracing::Span {
+        tracing::info_span!("instance_step", run_id = %self.run_id, prev_execution_id = %execution_head_state_id)
+    }
+
+    /// Register a hook that receives the fully resolved inputs of each cell just before it runs,
+    /// the returned value replaces those inputs. Useful for fault injection and fixture overrides.
+    pub fn on_resolve_input(&mut self, hook: Box<dyn Fn(OperationId, RkyvSerializedValue) -> RkyvSerializedValue + Send + Sync>) {
+        self.input_resolution_hook = Some(Arc::from(hook));
+    }
+
+    /// Expose a Rust function to code cells, python cells call it as `ch.native(name, args)` and
+    /// javascript cells as `await native(name, args)`. Replaces any function of the same name.
+    pub fn register_native_function(&mut self, name: &str, f: impl Fn(RkyvSerializedValue) -> RkyvSerializedValue + Send + Sync + 'static) {
+        self.native_functions.insert(name.to_string(), Arc::new(f));
+    }
+
     // TODO: reload_cells needs to diff the mutations that live on the current branch, with the state
     //       that we see in the shared state when this event is fired.
+    /// Apply every edited cell as a single revision of the execution graph. If the edited cells are
+    /// rejected, the previous definitions remain in place and a `ReloadRejected` event is emitted.
     pub async fn reload_cells(&mut self) -> anyhow::Result<()> {
         debug!("Reloading cells");
+        let (cells_to_upsert, pending) = self.cells_pending_reload();
+        let result = if pending.is_empty() {
+            None
+        } else {
+            let state = self.get_state_at_current_execution_head_result()?.clone();
+            Some(state.update_operations(pending).await)
+        };
+        self.apply_reload(cells_to_upsert, result)?;
+        if let Some(checkpoint) = self.pending_checkpoint.take() {
+            self.restore_checkpoint(&checkpoint)?;
+        }
+        Ok(())
+    }
+
+    /// Continue from the head of a checkpoint of an earlier session, the outputs it holds for cells
+    /// that are unchanged are restored into a new execution head. Returns the id of that head.
+    pub fn restore_checkpoint(&mut self, checkpoint: &Checkpoint) -> anyhow::Result<ExecutionNodeId> {
+        let restored = checkpoint.restore_onto(&self.get_state_at_current_execution_head_result()?.clone())?;
+        let head_id = restored.chronology_id;
+        if let Some(checkpointer) = self.checkpointer.as_mut() {
+            checkpointer.mark_written(checkpoint.states.keys().copied());
+        }
+        self.db.insert_state(restored.clone());
+        self.push_update_to_client(&restored);
+        self.set_execution_head(&restored);
+        Ok(head_id)
+    }
+
+    /// Discard the history of the execution graph beyond the recent ancestors of the execution head,
+    /// see `ExecutionGraph::compact`. Clients are sent the graph that remains.
+    pub fn compact(&mut self) -> anyhow::Result<()> {
+        self.db.compact(self.execution_head_state_id)?;
+        self.runtime_events.send_with(EventKind::ExecutionGraphUpdated, None, || {
+            EventsFromRuntime::ExecutionGraphUpdated(self.db.get_execution_graph_elements())
+        });
+        Ok(())
+    }
+
+    /// The editor cells in scope of this instance, and those of them that have been edited
+    fn cells_pending_reload(&self) -> (Vec<CellHolder>, Vec<(CellTypes, OperationId)>) {
         let cells_to_upsert: Vec<_> = {
             let shared_state = self.shared_state.lock().unwrap();
-            shared_state.editor_cells.values().map(|cell| cell.clone()).collect()
+            shared_state.editor_cells.values()
+                .filter(|cell| self.scoped_operations.as_ref().map_or(true, |ops| ops.contains(&cell.op_id)))
+                .map(|cell| cell.clone())
+                .collect()
         };
+        let pending = cells_to_upsert.iter()
+            .filter(|cell_holder| cell_holder.needs_update)
+            .map(|cell_holder| (cell_holder.cell.clone(), cell_holder.op_id))
+            .collect();
+        (cells_to_upsert, pending)
+    }
 
-        // unlock shared_state
-        let mut ids = vec![];
-        for cell_holder in cells_to_upsert {
-            if cell_holder.needs_update {
-                ids.push((self.upsert_cell(cell_holder.cell.clone(), cell_holder.op_id).await?, cell_holder));
-            } else {
-                // TODO: remove these unwraps and handle this better
-                ids.push(((cell_holder.applied_at.unwrap(), cell_holder.op_id), cell_holder));
-            }
+    /// Start a reload in the background, its result is delivered to the run loop as a
+    /// `BackgroundEvent::ReloadCompleted`. Returns false if there was nothing to evaluate and the
+    /// reload was applied immediately.
+    fn spawn_reload(&mut self, background_tx: UnboundedSender<BackgroundEvent>) -> anyhow::Result<bool> {
+        debug!("Reloading cells in the background");
+        let (cells_to_upsert, pending) = self.cells_pending_reload();
+        if pending.is_empty() {
+            self.apply_reload(cells_to_upsert, None)?;
+            return Ok(false);
         }
+        let mut state = self.get_state_at_current_execution_head_result()?.clone();
+        state.cell_descriptions = self.cell_descriptions();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
+            let result = runtime.block_on(state.update_operations(pending));
+            let _ = background_tx.send(BackgroundEvent::ReloadCompleted(cells_to_upsert, result));
+        });
+        Ok(true)
+    }
+
+    /// Record the outcome of evaluating edited cells, `result` is None when no cell was edited.
+    fn apply_reload(&mut self, cells_to_upsert: Vec<CellHolder>, result: Option<anyhow::Result<ExecutionState>>) -> anyhow::Result<()> {
+        let applied_at = match result {
+            None => None,
+            Some(Ok(final_state)) => {
+                self.push_update_to_client(&final_state);
+                self.set_execution_head(&final_state);
+                self.runtime_events.send(EventsFromRuntime::ReloadApplied(final_state.chronology_id));
+                Some(final_state.chronology_id)
+            }
+            Some(Err(e)) => {
+                let report = e.downcast::<DefinitionValidationReport>()?;
+                info!("Rejected reload of cells: {}", report);
+                self.runtime_events.send(EventsFromRuntime::ReloadRejected(report));
+                return Ok(());
+            }
+        };
 
         // lock again and update
         let mut shared_state = self.shared_state.lock().unwrap();
-        for ((applied_at, op_id), cell_holder) in ids {
+        for cell_holder in cells_to_upsert {
+            let op_id = cell_holder.op_id;
+            let needs_update = cell_holder.needs_update;
             shared_state.editor_cells.insert(op_id, cell_holder);
             shared_state.editor_cells.entry(op_id).and_modify(|cell| {
-                cell.applied_at = Some(applied_at.clone());
-                cell.op_id = op_id;
+                if needs_update {
+                    cell.applied_at = applied_at;
+                }
                 cell.needs_update = false;
             });
         }
 
-        if let Some(sender) = self.runtime_event_sender.as_mut() {
-            sender.send(EventsFromRuntime::EditorCellsUpdated(shared_state.editor_cells.clone())).unwrap();
+        self.runtime_events.send_with(EventKind::EditorCellsUpdated, None, || EventsFromRuntime::EditorCellsUpdated(shared_state.editor_cells.clone()));
+        drop(shared_state);
+
+        if self.auto_play && matches!(self.playback_state, PlaybackState::Paused) {
+            self.set_playback_state(PlaybackState::Running);
         }
         Ok(())
     }
 
     pub async fn shutdown(&mut self) {
         info!("Shutting down Chidori runtime.");
+        self.webhook_servers.stop_all().await;
         self.db.shutdown().await;
     }
 
+    /// The address the webhook cell is receiving requests on, once it has been evaluated
+    pub fn webhook_address(&self, op_id: OperationId) -> Option<std::net::SocketAddr> {
+        self.webhook_servers.address(op_id)
+    }
+
 
     // #[tracing::instrument]
     pub async fn wait_until_ready(&mut self) -> anyhow::Result<()> {
@@ -112,98 +314,278 @@ impl ChidoriRuntimeInstance {
     }
 
 
-    /// Entrypoint for execution of an instanced environment, handles messages from the host
+    /// Entrypoint for execution of an instanced environment, handles messages from the host.
+    /// Steps and reloads run in the background, so messages are handled as soon as they arrive.
+    /// Each step evaluates a single operation, so a Pause takes effect before the next operation
+    /// starts, and a Shutdown additionally cancels the steps in flight and returns.
     // #[tracing::instrument]
     pub async fn run(&mut self, initial_playback_state: PlaybackState) -> anyhow::Result<()> {
+        self.run_loop(initial_playback_state, MessageSource::Live).await
+    }
+
+    /// Replay a recorded session of user messages through the run loop, for deterministic tests of
+    /// interactive workflows. Each message is handled once the step or reload before it completes,
+    /// so playback moves one step per message while it is not paused. Once every message is handled,
+    /// steps continue until playback is paused or no operation is ready, failing after
+    /// `max_settle_steps` steps or at the first step that fails.
+    pub async fn replay_user_interactions(&mut self, messages: Vec<UserInteractionMessage>) -> anyhow::Result<()> {
+        let playback_state = self.playback_state.clone();
+        self.run_loop(playback_state, MessageSource::Replay(VecDeque::from(messages))).await
+    }
+
+    async fn run_loop(&mut self, initial_playback_state: PlaybackState, mut source: MessageSource) -> anyhow::Result<()> {
         println!("Starting instanced environment");
         self.set_playback_state(initial_playback_state);
 
         // Reload cells to make sure we're up-to-date
         self.reload_cells().await?;
+        // States produced by steps taken outside of the loop
+        self.receive_pending_execution_states();
 
-        let executing_states = Arc::new(Mutex::new(HashSet::new()));
-        // Create a channel for error notifications
-        let (error_tx, mut error_rx) = tokio::sync::mpsc::channel(32);
+        // Completions of steps and reloads running on other threads
+        let (background_tx, mut background_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut executing_states = HashSet::new();
+        let mut reload_in_progress = false;
+        let mut reload_requested = false;
+        let mut steps_taken = 0;
+        // Set once the instance shuts down, steps in flight stop awaiting their operations
+        let (cancel_tx, cancel_rx) = tokio::sync::watch::channel(false);
 
         loop {
-            // Handle user interactions first for responsiveness
-            if let Ok(message) = self.env_rx.try_recv() {
-                println!("Received message from user: {:?}", message);
-                self.handle_user_interaction_message(message).await?;
+            // Steps wait for reloads, so that edits apply between steps rather than beneath one
+            let reload_pending = reload_in_progress || reload_requested;
+            if !reload_pending && !matches!(self.playback_state, PlaybackState::Paused) {
+                let execution_head_state_id = self.execution_head_state_id;
+                // A step from this state may already be in progress
+                // A replayed session ends rather than stepping once nothing is ready
+                let replay_settled = matches!(source, MessageSource::Replay(_))
+                    && self.get_state_at_current_execution_head_result()?.ready_operations()?.is_empty();
+                if !replay_settled && !executing_states.contains(&execution_head_state_id) && !self.pause_at_breakpoint(false)? {
+                    if matches!(source, MessageSource::Replay(_)) {
+                        if steps_taken == self.max_settle_steps {
+                            anyhow::bail!("The replayed session did not settle within {} steps", self.max_settle_steps);
+                        }
+                        steps_taken += 1;
+                    }
+                    executing_states.insert(execution_head_state_id);
+                    if matches!(self.playback_state, PlaybackState::Step) {
+                        self.set_playback_state(PlaybackState::Paused);
+                    }
+                    println!("Will eval step, inserting eval state {:?}", &execution_head_state_id);
+                    self.spawn_step(execution_head_state_id, background_tx.clone(), cancel_rx.clone(), false)?;
+                }
             }
 
-            // Check for execution errors
-            if let Ok(error) = error_rx.try_recv() {
-                // println!("Received execution error: {:?}", error);
-                self.set_playback_state(PlaybackState::Paused);
-                // TODO: notify the client about the error
-                // self.push_update_to_client(&ExecutionState::Error(error));
-            }
+            // Replayed messages are taken once the work before them has completed
+            let idle = executing_states.is_empty() && !reload_in_progress && !reload_requested;
+            let replayed = match &mut source {
+                MessageSource::Replay(messages) if idle => match messages.pop_front() {
+                    Some(message) => Some(message),
+                    None if matches!(self.playback_state, PlaybackState::Paused) => return Ok(()),
+                    None if self.get_state_at_current_execution_head_result()?.ready_operations()?.is_empty() => return Ok(()),
+                    None => None,
+                },
+                _ => None,
+            };
+            let live = matches!(source, MessageSource::Live);
 
-            // Receives the results of execution during progression of ExecutionStates
-            if let Ok(state) = self.rx_execution_states.try_recv() {
-                println!("InstancedEnvironment received an execution event {:?}", &state.chronology_id);
-                self.push_update_to_client(&state);
-                self.set_execution_head(&state);
-            }
+            let message = match replayed {
+                Some(message) => message,
+                None => tokio::select! {
+                    Some(message) = self.env_rx.recv(), if live => message,
+                    Some(event) = background_rx.recv() => {
+                        match event {
+                            BackgroundEvent::StepCompleted(state_id, result) => {
+                                executing_states.remove(&state_id);
+                                // The step has produced all of its states, the next builds on them
+                                self.receive_pending_execution_states();
+                                if let Err(err) = result {
+                                    // println!("Received execution error: {:?}", err);
+                                    self.set_playback_state(PlaybackState::Paused);
+                                    if !live {
+                                        return Err(err);
+                                    }
+                                    // TODO: notify the client about the error
+                                }
+                                if executing_states.is_empty() && !reload_in_progress && std::mem::take(&mut reload_requested) {
+                                    // The reload builds on the states produced by the step
+                                    reload_in_progress = self.spawn_reload(background_tx.clone())?;
+                                }
+                            }
+                            BackgroundEvent::ReloadCompleted(cells_to_upsert, result) => {
+                                self.apply_reload(cells_to_upsert, Some(result))?;
+                                reload_in_progress = false;
+                                // Edits made while the reload was evaluating
+                                if std::mem::take(&mut reload_requested) {
+                                    reload_in_progress = self.spawn_reload(background_tx.clone())?;
+                                }
+                            }
+                        }
+                        continue;
+                    }
+                    // Receives the results of execution during progression of ExecutionStates
+                    Some(state) = self.rx_execution_states.recv() => {
+                        self.receive_execution_state(state);
+                        continue;
+                    }
+                    else => return Ok(()),
+                },
+            };
 
-            {
-                if matches!(self.playback_state, PlaybackState::Paused) {
-                    continue;
+            println!("Received message from user: {:?}", message);
+            match message {
+                UserInteractionMessage::ReloadCells if reload_in_progress || !executing_states.is_empty() => {
+                    // Applied once the reload or step underway completes
+                    reload_requested = true;
+                }
+                UserInteractionMessage::ReloadCells => {
+                    reload_in_progress = self.spawn_reload(background_tx.clone())?;
                 }
-                if matches!(self.playback_state, PlaybackState::Step) {
+                UserInteractionMessage::MicroStep => {
+                    // Evaluates a single operation and leaves playback paused
                     self.set_playback_state(PlaybackState::Paused);
+                    let execution_head_state_id = self.execution_head_state_id;
+                    if !reload_in_progress && !reload_requested
+                        && !executing_states.contains(&execution_head_state_id)
+                        && !self.pause_at_breakpoint(true)? {
+                        executing_states.insert(execution_head_state_id);
+                        self.spawn_step(execution_head_state_id, background_tx.clone(), cancel_rx.clone(), true)?;
+                    }
                 }
-                let execution_head_state_id = self.execution_head_state_id;
-
-                // Acquire lock and check if we're already executing this state
-                let mut executing_states_instance = executing_states.lock().unwrap();
-                if !executing_states_instance.contains(&execution_head_state_id) {
-                    println!("Will eval step, inserting eval state {:?}", &execution_head_state_id);
-                    executing_states_instance.insert(execution_head_state_id);
-                    drop(executing_states_instance); // Release lock before spawning task
-
-                    // Spawn the progression of the given step in a separate task
-                    let executing_states = Arc::clone(&executing_states);
-                    let error_tx = error_tx.clone();
-                    let state = self.get_state_at_current_execution_head_result()?.clone();
-
-                    std::thread::spawn(move || {
-                        // Create a new tokio runtime for this thread
-                        let runtime = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
-
-                        // Enter the runtime context
-                        let _guard = runtime.enter();
-
-                        // Execute the async block on this runtime
-                        runtime.block_on(async {
-                            let result = state.step_execution().await;
-                            // Clear execution
-                            let mut executing_states_lock = executing_states.lock().unwrap();
-                            executing_states_lock.remove(&execution_head_state_id);
-                            drop(executing_states_lock);
-
-                            match result {
-                                Err(err) => {
-                                    // Send the error through the channel
-                                    if let Err(send_err) = error_tx.send(err).await {
-                                        eprintln!("Failed to send error through channel: {:?}", send_err);
-                                    }
-                                }
-                                Ok(_) => {},
-                            }
-                        });
-                    });
+                UserInteractionMessage::Shutdown => {
+                    self.set_playback_state(PlaybackState::Paused);
+                    let _ = cancel_tx.send(true);
+                    self.shutdown().await;
+                    return Ok(());
                 }
+                message => self.handle_user_interaction_message(message).await?,
             }
         }
     }
 
+    /// Move the execution head to a state produced by evaluation, notifying clients
+    fn receive_execution_state(&mut self, state: ExecutionState) {
+        println!("InstancedEnvironment received an execution event {:?}", &state.chronology_id);
+        if state.is_webhook_delivery() {
+            self.runtime_events.send(EventsFromRuntime::WebhookReceived(state.evaluating_operation_id, state.evaluating_name.clone()));
+        }
+        self.push_update_to_client(&state);
+        self.set_execution_head(&state);
+    }
+
+    /// Receive the states already produced by evaluation, a completed step has produced all of its
+    /// states by the time it reports its completion
+    fn receive_pending_execution_states(&mut self) {
+        while let Ok(state) = self.rx_execution_states.try_recv() {
+            self.receive_execution_state(state);
+        }
+    }
+
+    /// Forwards the reasoning steps of operations to clients as they are recorded, redacted as the
+    /// outputs of the given cells are
+    fn agent_trace_sink(&self, cells: ImHashMap<OperationId, CellTypes>) -> Option<Arc<AgentTraceSink>> {
+        let subscribers = self.runtime_events.clone();
+        if !subscribers.wants(EventKind::AgentTraceStep, None) {
+            return None;
+        }
+        let redaction = self.redaction.clone();
+        Some(Arc::new(move |op_id, step: &AgentTraceStep| {
+            subscribers.send_with(EventKind::AgentTraceStep, Some(op_id), || {
+                EventsFromRuntime::AgentTraceStep(op_id, redaction.redact_trace_step(cells.get(&op_id), step))
+            });
+        }))
+    }
+
+    /// Reports each operation evaluated from a state to clients as soon as it completes, so that
+    /// they can show progress without waiting on the state change at the end of the step
+    fn completion_sink(&self, cells: ImHashMap<OperationId, CellTypes>) -> Option<Arc<OperationCompletionSink>> {
+        let subscribers = self.runtime_events.clone();
+        if !subscribers.wants(EventKind::OperationCompleted, None) {
+            return None;
+        }
+        let redaction = self.redaction.clone();
+        Some(Arc::new(move |op_id, result: &OperationFnOutput| {
+            subscribers.send_with(EventKind::OperationCompleted, Some(op_id), || {
+                let output = result.output.as_ref()
+                    .map(|value| redaction.redact_cell_output(cells.get(&op_id), value))
+                    .map_err(|e| e.clone());
+                EventsFromRuntime::OperationCompleted { op_id, output }
+            });
+        }))
+    }
+
+    /// The descriptions of the editor cells, by their operation
+    fn cell_descriptions(&self) -> ImHashMap<OperationId, String> {
+        let shared_state = self.shared_state.lock().unwrap();
+        shared_state.editor_cells.values()
+            .filter_map(|holder| holder.description.clone().map(|description| (holder.op_id, description)))
+            .collect()
+    }
+
+    /// The state at the execution head, carrying the settings of this instance into the step evaluated from it
+    fn prepare_state_for_step(&self) -> anyhow::Result<ExecutionState> {
+        let mut state = self.get_state_at_current_execution_head_result()?.clone();
+        state.cell_descriptions = self.cell_descriptions();
+        state.input_resolution_hook = self.input_resolution_hook.clone();
+        state.max_invocation_depth = self.max_invocation_depth;
+        state.default_execution_policy = self.default_execution_policy.clone();
+        state.prompt_audit = self.prompt_audit.clone();
+        state.llm_cache = self.llm_cache.clone();
+        state.native_functions = self.native_functions.clone();
+        state.memory_stores = self.memory_stores.clone();
+        state.deno_modules = self.deno_modules.clone();
+        state.webhook_servers = self.webhook_servers.clone();
+        state.llm_proxies = self.llm_proxies.clone();
+        state.agent_trace_sink = self.agent_trace_sink(state.cells_by_id.clone());
+        state.completion_sink = self.completion_sink(state.cells_by_id.clone());
+        state.branch_params = self.db.get_branch_params(self.execution_head_state_id);
+        Ok(state)
+    }
+
+    /// Evaluate the step from the given state on its own thread, reporting its completion to the run loop.
+    /// Once `cancellation` is set the cell being evaluated is interrupted, and the step is abandoned
+    /// without reporting its outputs.
+    fn spawn_step(&mut self, execution_head_state_id: ExecutionNodeId, background_tx: UnboundedSender<BackgroundEvent>, mut cancellation: tokio::sync::watch::Receiver<bool>, micro_step: bool) -> anyhow::Result<()> {
+        let mut state = self.prepare_state_for_step()?;
+        state.cancellation = Cancellation::new(cancellation.clone());
+        let timing_sender = Some(self.runtime_events.clone()).filter(|_| self.benchmark_mode);
+        let span = self.step_span(execution_head_state_id);
+
+        std::thread::spawn(move || {
+            // Create a new tokio runtime for this thread
+            let runtime = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
+
+            // Enter the runtime context
+            let _guard = runtime.enter();
+
+            // Execute the async block on this runtime
+            runtime.block_on(async {
+                let started_at = Instant::now();
+                let step = async {
+                    if micro_step {
+                        state.micro_step_execution().await
+                    } else {
+                        state.step_execution().await
+                    }
+                };
+                let result = tokio::select! {
+                    result = step => result,
+                    _ = cancellation.wait_for(|cancelled| *cancelled) => {
+                        Err(anyhow!("The step from {} was cancelled as the instance shut down", execution_head_state_id))
+                    }
+                };
+                if let Some(sender) = timing_sender {
+                    sender.send(EventsFromRuntime::StepTiming(execution_head_state_id, started_at.elapsed()));
+                }
+                let _ = background_tx.send(BackgroundEvent::StepCompleted(execution_head_state_id, result.map(|_| ())));
+            }.instrument(span));
+        });
+        Ok(())
+    }
+
     fn set_playback_state(&mut self, playback_state: PlaybackState) {
         self.playback_state = playback_state.clone();
-        if let Some(sender ) = self.runtime_event_sender.as_mut() {
-            sender.send(EventsFromRuntime::PlaybackState(playback_state)).unwrap();
-        }
+        self.runtime_events.send(EventsFromRuntime::PlaybackState(playback_state));
     }
 
     async fn handle_user_interaction_message(&mut self, message: UserInteractionMessage) -> Result<(), anyhow::Error> {
@@ -217,27 +599,7 @@ impl ChidoriRuntimeInstance {
             },
             UserInteractionMessage::RevertToState(id) => {
                 if let Some(id) = id {
-                    self.execution_head_state_id = id;
-                    let sender = self.runtime_event_sender.as_mut().unwrap();
-                    // let merged_state = self.db.get_merged_state_history(&id);
-                    // sender.send(EventsFromRuntime::ExecutionStateChange(merged_state)).unwrap();
-                    sender.send(EventsFromRuntime::UpdateExecutionHead(id)).unwrap();
-
-                    if let Some(state) = self.db.get_state_at_id(self.execution_head_state_id) {
-                        let mut cells = vec![];
-                        // TODO: keep a separate mapping of cells so we don't need to lock operations
-                        for (id, cell) in state.cells_by_id.iter() {
-                            cells.push(CellHolder {
-                                cell: cell.clone(),
-                                op_id: id.clone(),
-                                applied_at: None,
-                                needs_update: false,
-                            });
-                        }
-                        let mut ss = self.shared_state.lock().unwrap();
-                        ss.at_execution_state_cells = cells.clone();
-                        sender.send(EventsFromRuntime::ExecutionStateCellsViewUpdated(cells)).unwrap();
-                    }
+                    self.revert_to_state(id);
                 }
             },
             UserInteractionMessage::Shutdown => {
@@ -253,9 +615,22 @@ impl ChidoriRuntimeInstance {
                     cell.op_id = op_id;
                     cell.needs_update = false;
                 });
-                if let Some(sender) = self.runtime_event_sender.as_mut() {
-                    sender.send(EventsFromRuntime::EditorCellsUpdated(shared_state.editor_cells.clone())).unwrap();
-                }
+                self.runtime_events.send_with(EventKind::EditorCellsUpdated, None, || EventsFromRuntime::EditorCellsUpdated(shared_state.editor_cells.clone()));
+            }
+            UserInteractionMessage::MicroStep => {
+                self.micro_step().await?;
+            }
+            UserInteractionMessage::SetBenchmarkMode(enabled) => {
+                self.benchmark_mode = enabled;
+            }
+            UserInteractionMessage::SetBreakpoint(name) => {
+                self.breakpoints.insert(name);
+            }
+            UserInteractionMessage::ClearBreakpoint(name) => {
+                self.breakpoints.remove(&name);
+            }
+            UserInteractionMessage::SetBranchParams { leaf, params } => {
+                self.db.set_branch_params(leaf, params)?;
             }
             UserInteractionMessage::PushChatMessage(msg) => {
                 self.db.push_message(msg).await?;
@@ -263,6 +638,12 @@ impl ChidoriRuntimeInstance {
             UserInteractionMessage::RunCellInIsolation(cell, args) => {
                 // self.db.execute_operation_in_isolation(&cell.cell, args).await?;
             }
+            UserInteractionMessage::RestoreCheckpoint(checkpoint) => {
+                self.restore_checkpoint(&checkpoint)?;
+            }
+            UserInteractionMessage::FetchStatesAt(ids) => {
+                self.send_states_at(&ids);
+            }
             UserInteractionMessage::Reset => {
                 self.db = ExecutionGraph::new();
                 self.set_playback_state(PlaybackState::Paused);
@@ -275,6 +656,63 @@ impl ChidoriRuntimeInstance {
         Ok(())
     }
 
+    /// Move the execution head to a previously evaluated state
+    pub fn revert_to_state(&mut self, id: ExecutionNodeId) {
+        self.execution_head_state_id = id;
+        // self.runtime_events.send(EventsFromRuntime::ExecutionStateChange(self.db.get_merged_state_history(&id)));
+        self.runtime_events.send(EventsFromRuntime::UpdateExecutionHead(id));
+        if let Some(state) = self.db.get_state_at_id(id) {
+            self.push_execution_state_cells_view(&state);
+        }
+    }
+
+    /// Send the states at `ids` to the client in one event, with their outputs redacted. Ids that
+    /// are not in the execution graph are omitted.
+    fn send_states_at(&self, ids: &[ExecutionNodeId]) {
+        self.runtime_events.send_with(EventKind::StatesAtIds, None, || {
+            EventsFromRuntime::StatesAtIds(ids.iter()
+                .filter_map(|id| self.db.get_state_at_id(*id).map(|state| (*id, self.redacted_state(state))))
+                .collect())
+        });
+    }
+
+    /// A copy of `state` with the outputs, and the inputs they were evaluated with, redacted
+    fn redacted_state(&self, mut state: ExecutionState) -> ExecutionState {
+        for (op_id, output) in state.state.clone().iter() {
+            let cell = state.cells_by_id.get(op_id);
+            let mut output = (**output).clone();
+            output.output = output.output.map(|value| self.redaction.redact_cell_output(cell, &value));
+            output.agent_trace = output.agent_trace.iter().map(|step| self.redaction.redact_trace_step(cell, step)).collect();
+            output.input = output.input.map(|input| RecordedInput {
+                value: self.redaction.redact_recorded_input(&input, &state.cells_by_id),
+                ..input
+            });
+            state.state.insert(*op_id, Arc::new(output));
+        }
+        state
+    }
+
+    /// Publish the cell definitions as they were at the given state, this is what produced
+    /// the outputs visible at that state rather than what is currently in the editor.
+    fn push_execution_state_cells_view(&mut self, state: &ExecutionState) {
+        let cells: Vec<CellHolder> = state.get_cells_in_operation_order()
+            .into_iter()
+            .map(|(op_id, cell)| CellHolder {
+                cell,
+                op_id,
+                applied_at: Some(state.chronology_id),
+                needs_update: false,
+                description: state.cell_descriptions.get(&op_id).cloned(),
+                registry_url: None,
+            })
+            .collect();
+        {
+            let mut shared_state = self.shared_state.lock().unwrap();
+            shared_state.at_execution_state_cells = cells.clone();
+        }
+        self.runtime_events.send(EventsFromRuntime::ExecutionStateCellsViewUpdated(cells));
+    }
+
     pub fn get_state_at_current_execution_head_result(&self) -> anyhow::Result<Ref<ExecutionNodeId, ExecutionState>> {
         let state = if let Some(state) = self.db.execution_node_id_to_state.get(&self.execution_head_state_id) {
             state
@@ -285,6 +723,13 @@ impl ChidoriRuntimeInstance {
         Ok(state)
     }
 
+    /// Render one of the template cells of the program at the current execution head, the same
+    /// way template and prompt cells do. Errors are a `TemplateRenderError` and may be downcast.
+    pub fn render_template(&self, name: &str, data: RkyvSerializedValue) -> anyhow::Result<String> {
+        let state = self.get_state_at_current_execution_head_result()?;
+        Ok(TemplateLibrary::from_execution_state(&state).render(name, &data)?)
+    }
+
     #[cfg(test)]
     pub fn get_state_at_current_execution_head(&self) -> ExecutionState {
         self.db.get_state_at_id(self.execution_head_state_id).unwrap()
@@ -295,12 +740,32 @@ impl ChidoriRuntimeInstance {
         // Execution heads can only be Completed states, not states still evaluating
         if matches!(&state.evaluating_enclosed_state, EnclosedState::Close(_)) || (&state).evaluating_enclosed_state == EnclosedState::SelfContained {
             if state.evaluating_fn.is_none() {
-                if let Some(sender) = self.runtime_event_sender.as_mut() {
-                    sender.send(EventsFromRuntime::UpdateExecutionHead((&state).chronology_id)).unwrap();
+                let previous_head = self.db.get_state_at_id(self.execution_head_state_id);
+                self.runtime_events.send(EventsFromRuntime::UpdateExecutionHead((&state).chronology_id));
+                {
+                    let mut shared_state = self.shared_state.lock().unwrap();
+                    shared_state.execution_state_head_id = (&state).chronology_id;
                 }
-                let mut shared_state = self.shared_state.lock().unwrap();
-                shared_state.execution_state_head_id = (&state).chronology_id;
                 self.execution_head_state_id = (&state).chronology_id;
+                // Cells that are no longer webhooks at the head stop receiving requests
+                self.webhook_servers.retain(|op_id| matches!(state.cells_by_id.get(op_id), Some(CellTypes::Webhook(..))));
+                match previous_head.filter(|previous| previous.cells_by_id == state.cells_by_id) {
+                    // When only values changed, clients are told which rather than sent every cell again
+                    Some(previous) => {
+                        let changed = state.get_changed_ops_since(&previous);
+                        if !changed.is_empty() {
+                            self.runtime_events.send_with(EventKind::OperationsChanged, None, || {
+                                EventsFromRuntime::OperationsChanged(state.chronology_id, changed)
+                            });
+                        }
+                    }
+                    None => self.push_execution_state_cells_view(state),
+                }
+                if let Some(checkpointer) = self.checkpointer.as_mut() {
+                    if let Err(e) = checkpointer.checkpoint_if_due(&self.db, state.chronology_id, self.run_id, &self.redaction) {
+                        info!("Failed to write checkpoint to {:?}: {}", checkpointer.directory(), e);
+                    }
+                }
             }
         }
     }
@@ -308,37 +773,163 @@ impl ChidoriRuntimeInstance {
     fn push_update_to_client(&mut self, state: &ExecutionState) {
         let state_id = state.chronology_id;
         println!("Resulted in state with id {:?}", &state_id);
-        if let Some(sender) = self.runtime_event_sender.as_mut() {
-            sender.send(EventsFromRuntime::DefinitionGraphUpdated(state.get_dependency_graph_flattened())).unwrap();
-            let mut cells = vec![];
-            for (op_id, cell ) in state.cells_by_id.iter() {
-                cells.push(CellHolder {
-                    cell: cell.clone(),
-                    op_id: op_id.clone(),
-                    applied_at: Some(state.chronology_id),
-                    needs_update: false,
-                });
-            }
-            sender.send(EventsFromRuntime::ExecutionStateCellsViewUpdated(cells)).unwrap();
-            sender.send(EventsFromRuntime::ExecutionGraphUpdated(self.db.get_execution_graph_elements())).unwrap();
-            // sender.send(EventsFromRuntime::ExecutionStateChange(self.db.get_merged_state_history(&state_id))).unwrap();
-        }
+        let events = &self.runtime_events;
+        events.send_with(EventKind::DefinitionGraphUpdated, None, || {
+            EventsFromRuntime::DefinitionGraphUpdated(self.db.annotate_dependency_graph(state))
+        });
+        events.send_with(EventKind::TransientStateChange, None, || {
+            let transient_state = state.state_transient.iter()
+                .map(|(op_id, value)| (*op_id, self.redaction.redact_cell_output(state.cells_by_id.get(op_id), value)))
+                .collect();
+            EventsFromRuntime::TransientStateChange(state_id, transient_state)
+        });
+        events.send_with(EventKind::ExecutionGraphUpdated, None, || {
+            EventsFromRuntime::ExecutionGraphUpdated(self.db.get_execution_graph_elements())
+        });
+        // events.send(EventsFromRuntime::ExecutionStateChange(self.db.get_merged_state_history(&state_id)));
     }
 
     /// Increment the execution graph by one step
     #[tracing::instrument]
     pub async fn step(&mut self) -> anyhow::Result<Vec<(OperationId, OperationFnOutput)>> {
+        self.step_with_granularity(false).await
+    }
+
+    /// Evaluate exactly one operation, chosen deterministically from those ready at the execution
+    /// head, see `ExecutionState::micro_step_execution`. Fails if no operation is ready.
+    #[tracing::instrument]
+    pub async fn micro_step(&mut self) -> anyhow::Result<Vec<(OperationId, OperationFnOutput)>> {
+        self.step_with_granularity(true).await
+    }
+
+    /// Pause playback if a cell with a breakpoint is about to be evaluated from the execution head,
+    /// sending `EventsFromRuntime::BreakpointHit`. Only the operation a micro step would evaluate is
+    /// considered for micro steps. Execution pauses once at each state, so resuming evaluates the cell.
+    fn pause_at_breakpoint(&mut self, micro_step: bool) -> anyhow::Result<bool> {
+        let exec_head = self.execution_head_state_id;
+        if self.breakpoints.is_empty() || self.breakpoint_hit_at == Some(exec_head) {
+            return Ok(false);
+        }
+        let Some(state) = self.db.get_state_at_id(exec_head) else {
+            return Ok(false);
+        };
+        let mut ready = state.ready_operations()?;
+        if micro_step {
+            ready.truncate(1);
+        }
+        let hit = ready.into_iter().find_map(|op_id| {
+            let name = state.cells_by_id.get(&op_id)?.name().clone()?;
+            self.breakpoints.contains(&name).then_some((op_id, name))
+        });
+        let Some((op_id, name)) = hit else {
+            return Ok(false);
+        };
+        info!("Paused at breakpoint {} before {:?}", name, op_id);
+        self.breakpoint_hit_at = Some(exec_head);
+        self.set_playback_state(PlaybackState::Paused);
+        self.runtime_events.send_with(EventKind::BreakpointHit, Some(op_id), || EventsFromRuntime::BreakpointHit { op_id, name });
+        Ok(true)
+    }
+
+    async fn step_with_granularity(&mut self, micro_step: bool) -> anyhow::Result<Vec<(OperationId, OperationFnOutput)>> {
+        if self.pause_at_breakpoint(micro_step)? {
+            return Ok(vec![]);
+        }
         let exec_head = self.execution_head_state_id;
         println!("======================= Executing state with id {:?} ======================", &exec_head);
-        let (state, outputs) = {
-            let state = self.get_state_at_current_execution_head_result()?;
-            state.step_execution().await?
+        let span = self.step_span(exec_head);
+        let result = async {
+            let state = self.prepare_state_for_step()?;
+            let started_at = Instant::now();
+            let result = if micro_step {
+                state.micro_step_execution().await?
+            } else {
+                state.step_execution().await?
+            };
+            if self.benchmark_mode {
+                self.runtime_events.send(EventsFromRuntime::StepTiming(exec_head, started_at.elapsed()));
+            }
+            anyhow::Ok(result)
+        }.instrument(span).await;
+        let failed = match &result {
+            Ok((_, outputs)) => outputs.iter().any(|(_, output)| output.has_error || output.output.is_err()),
+            Err(_) => true,
         };
+        self.webhook_servers.record_step(failed);
+        let (state, outputs) = result?;
         self.push_update_to_client(&state);
         self.set_execution_head(&state);
         Ok(outputs)
     }
 
+    /// Step the graph until the given operation has output at the execution head, and return it.
+    /// Fails rather than waiting forever when the operation cannot be reached, because nothing is
+    /// left to evaluate before it has run, it has failed, or it has not run within `max_settle_steps`.
+    pub async fn await_output(&mut self, op_id: OperationId) -> anyhow::Result<RkyvSerializedValue> {
+        let mut steps = 0;
+        loop {
+            let state = self.get_state_at_current_execution_head_result()?.clone();
+            if !state.cells_by_id.contains_key(&op_id) {
+                anyhow::bail!("Operation {:?} is not part of the program", op_id);
+            }
+            match state.state_get_value(&op_id) {
+                Some(Ok(value)) => return Ok(value.clone()),
+                Some(Err(e)) => return Err(e.clone().into()),
+                None => {}
+            }
+            if state.ready_operations()?.is_empty() {
+                anyhow::bail!("Operation {:?} can never be evaluated, the values it depends on are not produced", op_id);
+            }
+            if steps == self.max_settle_steps {
+                anyhow::bail!("Operation {:?} was not evaluated within {} steps", op_id, self.max_settle_steps);
+            }
+            self.step().await?;
+            steps += 1;
+        }
+    }
+
+    /// `await_output` for the cell with the given name, or the cell that defines a value of that name
+    pub async fn await_named_output(&mut self, name: &str) -> anyhow::Result<RkyvSerializedValue> {
+        let op_id = {
+            let state = self.get_state_at_current_execution_head_result()?;
+            let by_cell_name = state.cells_by_id.iter()
+                .find(|(_, cell)| cell.name().as_deref() == Some(name))
+                .map(|(op_id, _)| *op_id);
+            by_cell_name.or_else(|| state.operation_by_id.iter()
+                .find(|(_, op)| op.signature.output_signature.globals.contains_key(name))
+                .map(|(op_id, _)| *op_id))
+        };
+        let op_id = op_id.ok_or_else(|| anyhow::anyhow!("No cell named or defining {}", name))?;
+        self.await_output(op_id).await
+    }
+
+    /// The functions defined by a cell as tools, in the form expected by the `tools` parameter of
+    /// the OpenAI chat completions api. Parameters are typed by their annotations where present,
+    /// and each function is described by the description of the cell.
+    pub fn tool_schema(&self, op_id: OperationId) -> anyhow::Result<serde_json::Value> {
+        let state = self.get_state_at_current_execution_head_result()?;
+        let op = state.operation_by_id.get(&op_id)
+            .ok_or_else(|| anyhow::anyhow!("No cell with the id {:?}", op_id))?;
+        let mut functions: Vec<_> = op.signature.output_signature.functions.iter().collect();
+        if functions.is_empty() {
+            anyhow::bail!("Cell {:?} does not define any functions", op_id);
+        }
+        functions.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let description = self.cell_descriptions().get(&op_id).cloned();
+        Ok(serde_json::Value::Array(functions.into_iter().filter_map(|(name, configuration)| match configuration {
+            OutputItemConfiguration::Function { input_signature, .. } => Some(Tool::from_function(name, input_signature.clone())
+                .with_description(description.clone())
+                .to_json()),
+            _ => None,
+        }).collect()))
+    }
+
+    /// The cells of the current execution head as a markdown document, in the order they were added
+    pub fn to_markdown(&self) -> anyhow::Result<String> {
+        let cells = self.get_state_at_current_execution_head_result()?.get_cells_in_operation_order();
+        crate::sdk::md::cells_to_markdown(cells.iter().map(|(_, cell)| cell))
+    }
+
     /// Add a cell into the execution graph
     #[tracing::instrument]
     pub async fn upsert_cell(&mut self, cell: CellTypes, op_id: OperationId) -> anyhow::Result<(ExecutionNodeId, OperationId)> {
@@ -358,6 +949,20 @@ impl ChidoriRuntimeInstance {
     fn schedule() {}
 }
 
+/// Where the run loop of an instance takes the messages of the user from
+enum MessageSource {
+    /// Messages sent to `env_rx`, handled as they arrive
+    Live,
+    /// A recorded session, see `replay_user_interactions`
+    Replay(VecDeque<UserInteractionMessage>),
+}
+
+/// Work completed off the run loop of an instance
+enum BackgroundEvent {
+    StepCompleted(ExecutionNodeId, anyhow::Result<()>),
+    ReloadCompleted(Vec<CellHolder>, anyhow::Result<ExecutionState>),
+}
+
 #[derive(Debug)]
 pub enum UserInteractionMessage {
     SetPlaybackState(PlaybackState),
@@ -367,6 +972,18 @@ pub enum UserInteractionMessage {
     Shutdown,
     PushChatMessage(String),
     RunCellInIsolation(CellHolder, RkyvSerializedValue),
+    SetBenchmarkMode(bool),
+    /// Pause before evaluating the cell with this name, see `ChidoriRuntimeInstance::pause_at_breakpoint`
+    SetBreakpoint(String),
+    ClearBreakpoint(String),
+    /// Parameters read by cells evaluated on the branch continuing from `leaf`, see `ExecutionGraph::set_branch_params`
+    SetBranchParams { leaf: ExecutionNodeId, params: RkyvSerializedValue },
+    /// Evaluate a single operation from the execution head, see `ChidoriRuntimeInstance::micro_step`
+    MicroStep,
+    /// Continue from the head of a checkpoint, see `ChidoriRuntimeInstance::restore_checkpoint`
+    RestoreCheckpoint(Checkpoint),
+    /// Request the states at each of the ids, answered with a single `EventsFromRuntime::StatesAtIds`
+    FetchStatesAt(Vec<ExecutionNodeId>),
     Reset
 }
 
@@ -378,4 +995,534 @@ pub enum PlaybackState {
     Paused,
     Step,
     Running,
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cells::{CodeCell, SupportedLanguage, TextRange};
+    use crate::execution::primitives::serialized_value::RkyvObjectBuilder;
+    use indoc::indoc;
+    use crate::utils::redaction::{redact_all, REDACTED};
+    use crate::sdk::event_subscriptions::EventFilter;
+    use crate::sdk::checkpoint::CheckpointConfig;
+    use crate::execution::primitives::agent_trace::AgentTraceStepKind;
+
+    fn code_cell(name: Option<&str>, source_code: &str) -> CellTypes {
+        CellTypes::Code(CodeCell {
+            name: name.map(|name| name.to_string()),
+            language: SupportedLanguage::PyO3,
+            source_code: source_code.to_string(),
+            ..Default::default()
+        }, TextRange::default())
+    }
+
+    #[tokio::test]
+    async fn test_compact_sends_the_remaining_graph() -> anyhow::Result<()> {
+        let mut env = ChidoriRuntimeInstance::new();
+        for source in ["a = 1", "b = 2", "c = 3", "d = 4"] {
+            env.upsert_cell(CellTypes::Code(CodeCell {
+                language: SupportedLanguage::PyO3,
+                source_code: source.to_string(),
+                ..Default::default()
+            }, TextRange::default()), Uuid::now_v7()).await?;
+        }
+        for _ in 0..4 {
+            env.step().await?;
+        }
+        let runtime_event_receiver = env.runtime_events.subscribe(EventFilter::all());
+        env.db.set_compaction_depth(1);
+        env.compact()?;
+
+        let edges = runtime_event_receiver.try_iter().find_map(|event| match event {
+            EventsFromRuntime::ExecutionGraphUpdated(edges) => Some(edges),
+            _ => None,
+        }).expect("Expected the graph to be sent after compacting");
+        assert_eq!(edges, env.db.get_execution_graph_elements());
+        assert!(edges.iter().all(|(from, to)| {
+            (from.is_nil() || env.db.get_state_at_id(*from).is_some()) && env.db.get_state_at_id(*to).is_some()
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_emitted_transient_state_is_redacted() {
+        let mut env = ChidoriRuntimeInstance::new();
+        let runtime_event_receiver = env.runtime_events.subscribe(EventFilter::all());
+        env.redaction = RedactionConfig::new().with_key("email");
+
+        let mut state = ExecutionState::new_with_random_id();
+        let user_op = Uuid::now_v7();
+        let secret_op = Uuid::now_v7();
+        state.cells_by_id.insert(secret_op, CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: Some("secret".to_string()),
+            language: SupportedLanguage::PyO3,
+            source_code: String::from("token = 'abc'"),
+            function_invocation: None,
+            inspect_globals: false,
+            redact_output: true,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
+        }, TextRange::default()));
+        state.state_set_transient(user_op, RkyvObjectBuilder::new()
+            .insert_string("name", "Ada".to_string())
+            .insert_string("email", "ada@example.com".to_string())
+            .build());
+        state.state_set_transient(secret_op, RkyvSerializedValue::String("abc".to_string()));
+        env.push_update_to_client(&state);
+
+        let transient = runtime_event_receiver.try_iter().find_map(|event| match event {
+            EventsFromRuntime::TransientStateChange(_, values) => Some(values),
+            _ => None,
+        }).expect("Expected a transient state event");
+        assert_eq!(transient.get(&user_op), Some(&RkyvObjectBuilder::new()
+            .insert_string("name", "Ada".to_string())
+            .insert_string("email", REDACTED.to_string())
+            .build()));
+        assert_eq!(transient.get(&secret_op), Some(&RkyvSerializedValue::String(REDACTED.to_string())));
+    }
+
+    #[test]
+    fn test_agent_trace_of_redacted_cell_is_redacted() {
+        let mut env = ChidoriRuntimeInstance::new();
+        let runtime_event_receiver = env.runtime_events.subscribe(EventFilter::all());
+        env.redaction = RedactionConfig::new().with_pattern(r"ada@example\.com").unwrap();
+        let secret_op = Uuid::now_v7();
+        let public_op = Uuid::now_v7();
+        let mut cells = ImHashMap::new();
+        cells.insert(secret_op, CellTypes::Code(CodeCell {
+            name: Some("secret".to_string()),
+            language: SupportedLanguage::PyO3,
+            source_code: String::from("token = 'abc'"),
+            redact_output: true,
+            ..Default::default()
+        }, TextRange::default()));
+        let sink = env.agent_trace_sink(cells).expect("Expected a sink while steps are subscribed to");
+        let step = |content: &str| AgentTraceStep {
+            kind: AgentTraceStepKind::Thought,
+            content: content.to_string(),
+            timestamp: 0,
+        };
+        sink(secret_op, &step("The token is abc"));
+        sink(public_op, &step("Mail ada@example.com"));
+
+        let steps: Vec<_> = runtime_event_receiver.try_iter().filter_map(|event| match event {
+            EventsFromRuntime::AgentTraceStep(op_id, step) => Some((op_id, step.content)),
+            _ => None,
+        }).collect();
+        assert_eq!(steps, vec![
+            (secret_op, REDACTED.to_string()),
+            (public_op, format!("Mail {}", REDACTED)),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_states_at_in_one_event() -> anyhow::Result<()> {
+        let mut env = ChidoriRuntimeInstance::new();
+        let runtime_event_receiver = env.runtime_events.subscribe(EventFilter::all());
+        env.redaction = RedactionConfig::new().with_key("email");
+        let op_id = Uuid::now_v7();
+        let mut ids = vec![];
+        let mut parent = Uuid::nil();
+        for i in 0..3 {
+            let mut state = ExecutionState::new_with_random_id();
+            state.parent_state_chronology_id = parent;
+            state.state_insert(op_id, OperationFnOutput::with_value(RkyvObjectBuilder::new()
+                .insert_number("step", i)
+                .insert_string("email", "ada@example.com".to_string())
+                .build()));
+            env.db.insert_state(state.clone());
+            ids.push(state.chronology_id);
+            parent = state.chronology_id;
+        }
+
+        let mut requested = ids.clone();
+        requested.push(Uuid::now_v7());
+        env.handle_user_interaction_message(UserInteractionMessage::FetchStatesAt(requested)).await?;
+        let events: Vec<_> = runtime_event_receiver.try_iter()
+            .filter_map(|event| match event {
+                EventsFromRuntime::StatesAtIds(states) => Some(states),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(events.len(), 1);
+        let states = &events[0];
+        assert_eq!(states.iter().map(|(id, _)| *id).collect::<Vec<_>>(), ids);
+        for (i, (_, state)) in states.iter().enumerate() {
+            assert_eq!(state.state_get_value(&op_id), Some(&Ok(RkyvObjectBuilder::new()
+                .insert_number("step", i as i32)
+                .insert_string("email", REDACTED.to_string())
+                .build())));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_input_from_redacted_producer_is_redacted_for_unredacted_consumer() -> anyhow::Result<()> {
+        let mut env = ChidoriRuntimeInstance::new();
+        env.upsert_cell(CellTypes::Code(CodeCell {
+            language: SupportedLanguage::PyO3,
+            source_code: String::from("x = 'secret'"),
+            redact_output: true,
+            ..Default::default()
+        }, TextRange::default()), Uuid::now_v7()).await?;
+        let (_, y_op) = env.upsert_cell(CellTypes::Code(CodeCell {
+            language: SupportedLanguage::PyO3,
+            source_code: String::from("y = x"),
+            ..Default::default()
+        }, TextRange::default()), Uuid::now_v7()).await?;
+
+        env.step().await?;
+        env.step().await?;
+
+        let state = env.db.get_state_at_id(env.execution_head_state_id).expect("Expected the head state");
+        let recorded_global_x = |state: &ExecutionState| -> Option<RkyvSerializedValue> {
+            let RkyvSerializedValue::Object(input) = &state.state.get(&y_op)?.input.as_ref()?.value else { return None };
+            let RkyvSerializedValue::Object(globals) = input.get("globals")? else { return None };
+            globals.get("x").cloned()
+        };
+        let recorded = recorded_global_x(&state).expect("Expected y to record x as an input");
+        let redacted = recorded_global_x(&env.redacted_state(state)).expect("Expected y to record x as an input");
+        assert_eq!(redacted, redact_all(&recorded));
+        assert_ne!(redacted, recorded);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_each_operation_reports_its_completion() -> anyhow::Result<()> {
+        let mut env = ChidoriRuntimeInstance::new();
+        let runtime_event_receiver = env.runtime_events.subscribe(EventFilter::all());
+        let (_, x_op) = env.upsert_cell(code_cell(None, "x = 1"), Uuid::now_v7()).await?;
+        let (_, y_op) = env.upsert_cell(code_cell(None, "y = 2"), Uuid::now_v7()).await?;
+        runtime_event_receiver.try_iter().for_each(drop);
+
+        env.step().await?;
+        env.step().await?;
+
+        let completed: Vec<_> = runtime_event_receiver.try_iter().filter_map(|event| match event {
+            EventsFromRuntime::OperationCompleted { op_id, output } => Some((op_id, output)),
+            _ => None,
+        }).collect();
+        assert_eq!(completed.len(), 2);
+        assert!(completed.iter().any(|(op_id, output)| *op_id == x_op
+            && output.as_ref().ok() == Some(&RkyvObjectBuilder::new().insert_number("x", 1).build())));
+        assert!(completed.iter().any(|(op_id, output)| *op_id == y_op
+            && output.as_ref().ok() == Some(&RkyvObjectBuilder::new().insert_number("y", 2).build())));
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_operations_report_completion_as_they_finish() -> anyhow::Result<()> {
+        let mut env = ChidoriRuntimeInstance::new();
+        let runtime_event_receiver = env.runtime_events.subscribe(EventFilter::kinds([EventKind::OperationCompleted]));
+        let (_, slow_op) = env.upsert_cell(code_cell(None, indoc! { r#"
+            import asyncio
+            async def slow():
+                await asyncio.sleep(0.5)
+                return 1
+            "#}), Uuid::now_v7()).await?;
+        let (_, fast_op) = env.upsert_cell(code_cell(None, indoc! { r#"
+            async def fast():
+                return 2
+            "#}), Uuid::now_v7()).await?;
+        let (_, gather_op) = env.upsert_cell(code_cell(None, indoc! { r#"
+            import asyncio
+            results = await asyncio.gather(slow(), fast())
+            "#}), Uuid::now_v7()).await?;
+        for _ in 0..2 {
+            env.step().await?;
+        }
+        runtime_event_receiver.try_iter().for_each(drop);
+
+        // Both functions are evaluated within the step of the cell gathering them
+        env.step().await?;
+        let completed: Vec<_> = runtime_event_receiver.try_iter().filter_map(|event| match event {
+            EventsFromRuntime::OperationCompleted { op_id, .. } => Some(op_id),
+            _ => None,
+        }).collect();
+        assert_eq!(completed, vec![fast_op, slow_op, gather_op]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_step_pauses_before_cell_with_breakpoint() -> anyhow::Result<()> {
+        let mut env = ChidoriRuntimeInstance::new();
+        let runtime_event_receiver = env.runtime_events.subscribe(EventFilter::kinds([EventKind::BreakpointHit]));
+        let (_, x_op) = env.upsert_cell(code_cell(None, "x = 1"), Uuid::now_v7()).await?;
+        let (_, y_op) = env.upsert_cell(code_cell(Some("double"), "y = x * 2"), Uuid::now_v7()).await?;
+        env.handle_user_interaction_message(UserInteractionMessage::SetBreakpoint("double".to_string())).await?;
+        env.set_playback_state(PlaybackState::Running);
+
+        let outputs = env.step().await?;
+        assert_eq!(outputs.iter().map(|(op_id, _)| *op_id).collect::<Vec<_>>(), vec![x_op]);
+        assert!(runtime_event_receiver.try_iter().next().is_none());
+
+        // Paused right before `double` runs
+        assert!(env.step().await?.is_empty());
+        assert_eq!(env.playback_state, PlaybackState::Paused);
+        assert!(env.get_state_at_current_execution_head().state_get_value(&y_op).is_none());
+        let hits: Vec<_> = runtime_event_receiver.try_iter().filter_map(|event| match event {
+            EventsFromRuntime::BreakpointHit { op_id, name } => Some((op_id, name)),
+            _ => None,
+        }).collect();
+        assert_eq!(hits, vec![(y_op, "double".to_string())]);
+
+        // Resuming evaluates the cell
+        let outputs = env.step().await?;
+        assert_eq!(outputs.iter().map(|(op_id, _)| *op_id).collect::<Vec<_>>(), vec![y_op]);
+        assert_eq!(
+            env.get_state_at_current_execution_head().state_get_value(&y_op),
+            Some(&Ok(RkyvObjectBuilder::new().insert_number("y", 2).build()))
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_micro_step_evaluates_one_operation_at_a_time() -> anyhow::Result<()> {
+        let mut env = ChidoriRuntimeInstance::new();
+        let mut ops = vec![];
+        for source in ["x = 1", "y = 2", "z = 3", "w = x + y"] {
+            let (_, op_id) = env.upsert_cell(code_cell(None, source), Uuid::now_v7()).await?;
+            ops.push(op_id);
+        }
+
+        // Operations are evaluated in the order they were added, `w` once both its inputs exist
+        for op_id in &ops {
+            let outputs = env.micro_step().await?;
+            assert_eq!(outputs.len(), 1);
+            assert_eq!(outputs[0].0, *op_id);
+        }
+        assert_eq!(
+            env.get_state_at_current_execution_head().state_get_value(&ops[3]),
+            Some(&Ok(RkyvObjectBuilder::new().insert_number("w", 3).build()))
+        );
+        assert!(env.micro_step().await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_user_interactions() -> anyhow::Result<()> {
+        let mut env = ChidoriRuntimeInstance::new();
+        let mut ops = vec![];
+        for source in ["x = 1", "y = 2", "z = 3"] {
+            let (_, op_id) = env.upsert_cell(code_cell(None, source), Uuid::now_v7()).await?;
+            ops.push(op_id);
+        }
+
+        // Play, remain playing for a second step, then pause before the third
+        env.replay_user_interactions(vec![
+            UserInteractionMessage::SetPlaybackState(PlaybackState::Running),
+            UserInteractionMessage::SetPlaybackState(PlaybackState::Running),
+            UserInteractionMessage::SetPlaybackState(PlaybackState::Paused),
+        ]).await?;
+        let state = env.get_state_at_current_execution_head();
+        assert_eq!(state.state_get_value(&ops[0]), Some(&Ok(RkyvObjectBuilder::new().insert_number("x", 1).build())));
+        assert_eq!(state.state_get_value(&ops[1]), Some(&Ok(RkyvObjectBuilder::new().insert_number("y", 2).build())));
+        assert_eq!(state.state_get_value(&ops[2]), None);
+        assert_eq!(env.playback_state, PlaybackState::Paused);
+
+        // Playing with no further messages runs until nothing is left to evaluate
+        env.replay_user_interactions(vec![UserInteractionMessage::SetPlaybackState(PlaybackState::Running)]).await?;
+        assert_eq!(
+            env.get_state_at_current_execution_head().state_get_value(&ops[2]),
+            Some(&Ok(RkyvObjectBuilder::new().insert_number("z", 3).build()))
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_of_cell_that_always_runs_is_bounded() -> anyhow::Result<()> {
+        let mut env = ChidoriRuntimeInstance::new();
+        env.upsert_cell(CellTypes::Code(CodeCell {
+            language: SupportedLanguage::PyO3,
+            source_code: "x = 1".to_string(),
+            always_run: true,
+            ..Default::default()
+        }, TextRange::default()), Uuid::now_v7()).await?;
+        env.max_settle_steps = 3;
+
+        // Pausing ends the session, even though the cell is still ready
+        env.replay_user_interactions(vec![
+            UserInteractionMessage::SetPlaybackState(PlaybackState::Running),
+            UserInteractionMessage::SetPlaybackState(PlaybackState::Paused),
+        ]).await?;
+        assert_eq!(env.playback_state, PlaybackState::Paused);
+
+        // Left playing, the session never settles
+        let err = env.replay_user_interactions(vec![UserInteractionMessage::SetPlaybackState(PlaybackState::Running)])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("within 3 steps"), "{}", err);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_branch_params_diverge_forked_branches() -> anyhow::Result<()> {
+        let mut env = ChidoriRuntimeInstance::new();
+        let (_, op_id) = env.upsert_cell(CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: None,
+            language: SupportedLanguage::PyO3,
+            source_code: String::from(indoc! { r#"
+                import chidori as ch
+                y = ch.param("scale", 1) * 10
+                "#}),
+            function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
+        }, TextRange::default()), Uuid::now_v7()).await?;
+        let fork = env.execution_head_state_id;
+
+        // Evaluate the same cell on two branches forked from the same state
+        let mut heads = vec![];
+        for scale in [2, 3] {
+            env.revert_to_state(fork);
+            env.handle_user_interaction_message(UserInteractionMessage::SetBranchParams {
+                leaf: fork,
+                params: RkyvObjectBuilder::new().insert_number("scale", scale).build(),
+            }).await?;
+            env.step().await?;
+            heads.push(env.execution_head_state_id);
+            assert_eq!(
+                env.get_state_at_current_execution_head().state_get_value(&op_id),
+                Some(&Ok(RkyvObjectBuilder::new().insert_number("y", scale * 10).build()))
+            );
+        }
+
+        let comparison = env.db.compare_branches(heads[0], heads[1]).unwrap();
+        assert_eq!(comparison.params.changes.len(), 1);
+        assert_eq!(comparison.outputs.len(), 1);
+        assert_eq!(comparison.outputs[0].0, op_id);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deno_cell_imports_from_another_cell() -> anyhow::Result<()> {
+        let mut env = ChidoriRuntimeInstance::new();
+        let deno_cell = |name: Option<&str>, source_code: &str| CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: name.map(str::to_string),
+            language: SupportedLanguage::Deno,
+            source_code: source_code.to_string(),
+            function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
+        }, TextRange::default());
+        let id_utils = Uuid::now_v7();
+        env.upsert_cell(deno_cell(Some("utils"), indoc! { r#"
+            export function double(x) { return x * 2; }
+            export const LIMIT = 5;
+            "#}), id_utils).await?;
+        let (_, id_importer) = env.upsert_cell(deno_cell(None, indoc! { r#"
+            import { double, LIMIT } from "cell:utils";
+            const y = double(LIMIT);
+            "#}), Uuid::now_v7()).await?;
+        env.step().await?;
+        env.step().await?;
+        assert_eq!(
+            env.get_state_at_current_execution_head().state_get_value(&id_importer),
+            Some(&Ok(RkyvObjectBuilder::new().insert_number("y", 10).build()))
+        );
+
+        // Reloading the imported cell evaluates the importing cell against its new source
+        env.upsert_cell(deno_cell(Some("utils"), indoc! { r#"
+            export function double(x) { return x * 2; }
+            export const LIMIT = 7;
+            "#}), id_utils).await?;
+        env.step().await?;
+        assert_eq!(
+            env.get_state_at_current_execution_head().state_get_value(&id_importer),
+            Some(&Ok(RkyvObjectBuilder::new().insert_number("y", 14).build()))
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tool_schema_of_function_cell() -> anyhow::Result<()> {
+        let mut env = ChidoriRuntimeInstance::new();
+        let (_, op_id) = env.upsert_cell(CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: None,
+            language: SupportedLanguage::PyO3,
+            source_code: String::from("def add(x: int, y: int):\n    return x + y\n"),
+            function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
+        }, TextRange::default()), Uuid::now_v7()).await?;
+
+        assert_eq!(env.tool_schema(op_id)?, serde_json::json!([{
+            "type": "function",
+            "function": {
+                "name": "add",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "x": { "type": "integer" },
+                        "y": { "type": "integer" },
+                    },
+                },
+            },
+        }]));
+        assert!(env.tool_schema(Uuid::now_v7()).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_id_is_shared_by_events_traces_and_checkpoints() -> anyhow::Result<()> {
+        let (trace_sender, trace_receiver) = std::sync::mpsc::channel();
+        let _guard = tracing::subscriber::set_default(crate::utils::telemetry::init_internal_telemetry(trace_sender));
+        let directory = std::env::temp_dir().join(format!("chidori-run-{}", Uuid::now_v7()));
+        let mut env = ChidoriRuntimeInstance::new();
+        env.checkpointer = Some(Checkpointer::new(CheckpointConfig::every_states(1), directory.clone())?);
+        let events = env.runtime_events.subscribe_runs(EventFilter::kinds([EventKind::OperationCompleted]));
+        env.upsert_cell(CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: None,
+            language: SupportedLanguage::PyO3,
+            source_code: String::from("x = 1"),
+            function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
+        }, TextRange::default()), Uuid::now_v7()).await?;
+        env.step().await?;
+
+        let run_id = Some(env.run_id());
+        assert_eq!(events.try_recv()?.run_id, run_id);
+        let spans: Vec<(String, Option<RunId>)> = trace_receiver.try_iter()
+            .filter_map(|event| match event {
+                TraceEvents::NewSpan { name, run_id, .. } => Some((name, run_id)),
+                _ => None,
+            })
+            .collect();
+        assert!(spans.contains(&("instance_step".to_string(), run_id)));
+        // Spans nested within the step inherit its run
+        assert!(spans.iter().any(|(name, span_run_id)| name != "instance_step" && *span_run_id == run_id));
+        assert_eq!(crate::sdk::checkpoint::read_latest_checkpoint(&directory)?.unwrap().run_id, run_id);
+        assert_eq!(env.parent_run_id(), None);
+        std::fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+}
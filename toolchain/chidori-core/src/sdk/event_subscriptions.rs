@@ -0,0 +1,277 @@
+//! Clients subscribe to the events emitted by the runtime, each with a filter selecting the kinds
+//! of events, and optionally the operations, it is interested in. Events no subscriber is interested
+//! in are never constructed when emitted with `EventSubscribers::send_with`. Subscribers that
+//! receive events from several instances can subscribe with `EventSubscribers::subscribe_runs` to
+//! receive each event alongside the run that emitted it.
+
+use std::collections::HashSet;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use crate::execution::primitives::identifiers::{OperationId, RunId};
+use crate::sdk::interactive_chidori_wrapper::EventsFromRuntime;
+
+/// The variants of `EventsFromRuntime`, without their payloads
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    PlaybackState,
+    DefinitionGraphUpdated,
+    ExecutionGraphUpdated,
+    ExecutionStateChange,
+    EditorCellsUpdated,
+    StateAtId,
+    StatesAtIds,
+    UpdateExecutionHead,
+    ReceivedChatMessage,
+    ExecutionStateCellsViewUpdated,
+    TransientStateChange,
+    WebhookReceived,
+    ReloadRejected,
+    ReloadApplied,
+    OperationCompleted,
+    OperationsChanged,
+    BreakpointHit,
+    StepTiming,
+    AgentTraceStep,
+    DocumentsReloaded,
+    InstanceRestarted,
+}
+
+impl EventsFromRuntime {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            EventsFromRuntime::PlaybackState(_) => EventKind::PlaybackState,
+            EventsFromRuntime::DefinitionGraphUpdated(_) => EventKind::DefinitionGraphUpdated,
+            EventsFromRuntime::ExecutionGraphUpdated(_) => EventKind::ExecutionGraphUpdated,
+            EventsFromRuntime::ExecutionStateChange(_) => EventKind::ExecutionStateChange,
+            EventsFromRuntime::EditorCellsUpdated(_) => EventKind::EditorCellsUpdated,
+            EventsFromRuntime::StateAtId(_, _) => EventKind::StateAtId,
+            EventsFromRuntime::StatesAtIds(_) => EventKind::StatesAtIds,
+            EventsFromRuntime::UpdateExecutionHead(_) => EventKind::UpdateExecutionHead,
+            EventsFromRuntime::ReceivedChatMessage(_) => EventKind::ReceivedChatMessage,
+            EventsFromRuntime::ExecutionStateCellsViewUpdated(_) => EventKind::ExecutionStateCellsViewUpdated,
+            EventsFromRuntime::TransientStateChange(_, _) => EventKind::TransientStateChange,
+            EventsFromRuntime::WebhookReceived(_, _) => EventKind::WebhookReceived,
+            EventsFromRuntime::ReloadRejected(_) => EventKind::ReloadRejected,
+            EventsFromRuntime::ReloadApplied(_) => EventKind::ReloadApplied,
+            EventsFromRuntime::OperationCompleted { .. } => EventKind::OperationCompleted,
+            EventsFromRuntime::OperationsChanged(_, _) => EventKind::OperationsChanged,
+            EventsFromRuntime::BreakpointHit { .. } => EventKind::BreakpointHit,
+            EventsFromRuntime::StepTiming(_, _) => EventKind::StepTiming,
+            EventsFromRuntime::AgentTraceStep(_, _) => EventKind::AgentTraceStep,
+            EventsFromRuntime::DocumentsReloaded { .. } => EventKind::DocumentsReloaded,
+            EventsFromRuntime::InstanceRestarted => EventKind::InstanceRestarted,
+        }
+    }
+
+    /// The operation this event concerns, for events about a single operation
+    pub fn operation_id(&self) -> Option<OperationId> {
+        match self {
+            EventsFromRuntime::WebhookReceived(op_id, _) => Some(*op_id),
+            EventsFromRuntime::OperationCompleted { op_id, .. } => Some(*op_id),
+            EventsFromRuntime::BreakpointHit { op_id, .. } => Some(*op_id),
+            EventsFromRuntime::AgentTraceStep(op_id, _) => Some(*op_id),
+            _ => None,
+        }
+    }
+}
+
+/// Selects the events delivered to a subscriber. Events about a single operation are further
+/// limited to the operations selected, when any are.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    kinds: Option<HashSet<EventKind>>,
+    operation_ids: Option<HashSet<OperationId>>,
+}
+
+impl EventFilter {
+    /// Every event
+    pub fn all() -> Self {
+        EventFilter::default()
+    }
+
+    /// Only events of the given kinds
+    pub fn kinds(kinds: impl IntoIterator<Item = EventKind>) -> Self {
+        EventFilter { kinds: Some(kinds.into_iter().collect()), operation_ids: None }
+    }
+
+    /// Limit events about a single operation to those about the given operations
+    pub fn with_operation_ids(mut self, operation_ids: impl IntoIterator<Item = OperationId>) -> Self {
+        self.operation_ids = Some(operation_ids.into_iter().collect());
+        self
+    }
+
+    pub fn matches(&self, kind: EventKind, operation_id: Option<OperationId>) -> bool {
+        self.kinds.as_ref().map_or(true, |kinds| kinds.contains(&kind))
+            && match (&self.operation_ids, operation_id) {
+                (Some(operation_ids), Some(operation_id)) => operation_ids.contains(&operation_id),
+                _ => true,
+            }
+    }
+}
+
+/// An event alongside the run of the instance that emitted it, None for events emitted by the
+/// wrapper itself
+#[derive(Clone, Debug)]
+pub struct RunEvent {
+    pub run_id: Option<RunId>,
+    pub event: EventsFromRuntime,
+}
+
+enum SubscriberSender {
+    Events(Sender<EventsFromRuntime>),
+    Runs(Sender<RunEvent>),
+}
+
+struct Subscriber {
+    filter: EventFilter,
+    sender: SubscriberSender,
+}
+
+impl Subscriber {
+    /// Returns whether the receiver is still connected
+    fn deliver(&self, run_id: Option<RunId>, event: EventsFromRuntime) -> bool {
+        match &self.sender {
+            SubscriberSender::Events(sender) => sender.send(event).is_ok(),
+            SubscriberSender::Runs(sender) => sender.send(RunEvent { run_id, event }).is_ok(),
+        }
+    }
+}
+
+/// The subscribers to the events of a runtime. Clones share their subscribers, so that instances
+/// deliver events to those subscribed on the wrapper that created them. Subscribers whose receiver
+/// has been dropped are removed the next time an event would be delivered to them.
+#[derive(Clone, Default)]
+pub struct EventSubscribers {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+    /// The run events sent through this handle are attributed to, see `for_run`
+    run_id: Option<RunId>,
+}
+
+impl EventSubscribers {
+    pub fn new() -> Self {
+        EventSubscribers::default()
+    }
+
+    /// A handle sharing these subscribers, attributing the events sent through it to `run_id`
+    pub fn for_run(&self, run_id: RunId) -> Self {
+        EventSubscribers { subscribers: self.subscribers.clone(), run_id: Some(run_id) }
+    }
+
+    pub fn run_id(&self) -> Option<RunId> {
+        self.run_id
+    }
+
+    pub fn subscribe(&self, filter: EventFilter) -> Receiver<EventsFromRuntime> {
+        let (sender, receiver) = channel();
+        self.add_sender(filter, sender);
+        receiver
+    }
+
+    /// Like `subscribe`, receiving each event alongside the run that emitted it
+    pub fn subscribe_runs(&self, filter: EventFilter) -> Receiver<RunEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.lock().unwrap().push(Subscriber { filter, sender: SubscriberSender::Runs(sender) });
+        receiver
+    }
+
+    /// Deliver the events selected by `filter` to an existing channel
+    pub fn add_sender(&self, filter: EventFilter, sender: Sender<EventsFromRuntime>) {
+        self.subscribers.lock().unwrap().push(Subscriber { filter, sender: SubscriberSender::Events(sender) });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.lock().unwrap().is_empty()
+    }
+
+    /// Whether any subscriber would receive an event of this kind, about this operation
+    pub fn wants(&self, kind: EventKind, operation_id: Option<OperationId>) -> bool {
+        self.subscribers.lock().unwrap().iter().any(|s| s.filter.matches(kind, operation_id))
+    }
+
+    pub fn send(&self, event: EventsFromRuntime) {
+        let (kind, operation_id) = (event.kind(), event.operation_id());
+        self.send_with(kind, operation_id, || event);
+    }
+
+    /// Deliver the event built by `event` to the subscribers interested in it. It is only built,
+    /// and only cloned for all but the last of them, when there are any.
+    pub fn send_with(&self, kind: EventKind, operation_id: Option<OperationId>, event: impl FnOnce() -> EventsFromRuntime) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let interested: Vec<usize> = subscribers.iter().enumerate()
+            .filter(|(_, s)| s.filter.matches(kind, operation_id))
+            .map(|(i, _)| i)
+            .collect();
+        let Some((last, rest)) = interested.split_last() else { return };
+        let event = event();
+        let mut disconnected: Vec<usize> = rest.iter().copied()
+            .filter(|i| !subscribers[*i].deliver(self.run_id, event.clone()))
+            .collect();
+        if !subscribers[*last].deliver(self.run_id, event) {
+            disconnected.push(*last);
+        }
+        let mut index = 0;
+        subscribers.retain(|_| {
+            let keep = !disconnected.contains(&index);
+            index += 1;
+            keep
+        });
+    }
+}
+
+impl std::fmt::Debug for EventSubscribers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventSubscribers")
+            .field("subscribers", &self.subscribers.lock().unwrap().len())
+            .field("run_id", &self.run_id)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_filters_select_kinds_and_operations() {
+        let op_id = Uuid::now_v7();
+        let filter = EventFilter::kinds([EventKind::OperationCompleted, EventKind::UpdateExecutionHead])
+            .with_operation_ids([op_id]);
+        assert!(filter.matches(EventKind::OperationCompleted, Some(op_id)));
+        assert!(!filter.matches(EventKind::OperationCompleted, Some(Uuid::now_v7())));
+        assert!(filter.matches(EventKind::UpdateExecutionHead, None));
+        assert!(!filter.matches(EventKind::PlaybackState, None));
+        assert!(EventFilter::all().matches(EventKind::PlaybackState, None));
+    }
+
+    #[test]
+    fn test_disconnected_subscribers_are_removed() {
+        let subscribers = EventSubscribers::new();
+        let kept = subscribers.subscribe(EventFilter::all());
+        drop(subscribers.subscribe(EventFilter::all()));
+        subscribers.send(EventsFromRuntime::InstanceRestarted);
+        assert!(matches!(kept.try_recv(), Ok(EventsFromRuntime::InstanceRestarted)));
+        assert_eq!(subscribers.subscribers.lock().unwrap().len(), 1);
+
+        // Events no subscriber wants are never built
+        let filtered = EventSubscribers::new();
+        let _receiver = filtered.subscribe(EventFilter::kinds([EventKind::InstanceRestarted]));
+        filtered.send_with(EventKind::PlaybackState, None, || panic!("Event should not be built"));
+    }
+
+    #[test]
+    fn test_run_subscribers_receive_the_run_of_each_event() {
+        let subscribers = EventSubscribers::new();
+        let runs = subscribers.subscribe_runs(EventFilter::all());
+        let events = subscribers.subscribe(EventFilter::all());
+        let run_id = Uuid::now_v7();
+        subscribers.for_run(run_id).send(EventsFromRuntime::InstanceRestarted);
+        subscribers.send(EventsFromRuntime::InstanceRestarted);
+
+        let first = runs.try_recv().unwrap();
+        assert_eq!(first.run_id, Some(run_id));
+        assert!(matches!(first.event, EventsFromRuntime::InstanceRestarted));
+        assert_eq!(runs.try_recv().unwrap().run_id, None);
+        assert_eq!(events.try_iter().count(), 2);
+    }
+}
@@ -0,0 +1,144 @@
+use std::path::{Path, PathBuf};
+use crate::library::std::ai::llm::estimated_cells_cost_usd;
+use crate::sdk::md::{extract_code_blocks, interpret_markdown_code_block};
+
+/// A program bundled with Chidori demonstrating one of its features
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Example {
+    /// Identifier of the example, the name of its directory under `examples`
+    pub name: &'static str,
+    pub title: &'static str,
+    pub description: &'static str,
+    /// Markdown source of the program
+    pub source: &'static str,
+}
+
+macro_rules! example {
+    ($name:literal, $title:literal, $description:literal) => {
+        Example {
+            name: $name,
+            title: $title,
+            description: $description,
+            source: include_str!(concat!("../../examples/", $name, "/core.md")),
+        }
+    };
+}
+
+const EXAMPLES: &[Example] = &[
+    example!("core1_simple_math", "Core 1: Simple Math", "Demonstrates simple arithmetic between cells, and that values can be passed between Python and JavaScript runtimes."),
+    example!("core2_marshalling", "Core 2: Marshalling Values", "All of the types that we can successfully pass between runtimes and that are preserved by our execution engine."),
+    example!("core3_function_invocations", "Core 3: Invoking Functions", "Demonstrates what function execution looks like when using Chidori. Explore how states are preserved and the ability to revert between them with re-execution."),
+    example!("core4_async_function_invocations", "Core 4: Invoking Async Functions", "Function invocations default to being asynchronous."),
+    example!("core5_prompts_invoked_as_functions", "Core 5: Invoking Prompts as Functions", "We treat prompts as first class resources, this demonstrates how prompts are invokable as functions."),
+    example!("core6_prompts_leveraging_function_calling", "Core 6: Using Function Calling in Prompts", "Prompts may import functions and invoke those in order to accomplish their instructions."),
+    example!("core7_rag_stateful_memory_cells", "Core 7: Chat With PDF Clone", "Cells preserve their internal state, we provide a specialized API for embeddings which demonstrates this behavior, exposing functions for interacting with that state."),
+    example!("core8_prompt_code_generation_and_execution", "Core 8: Anthropic Artifacts Clone", "Chidori is designed for L4-L5 agents, new behaviors can be generated on the fly via code generation."),
+    example!("core9_multi_agent_simulation", "Core 9: Multi-Agent Social Experiment", "Agents with their own roles and tools plan a trip together, each prompt exposed as a function the others invoke."),
+    example!("core10_concurrency", "Core 10: Demonstrating Our Execution Concurrency", "Async python functions await a prompt invoked by name, with a unit test checking the combined result."),
+    example!("core11_hono", "Core 11: Hono Web Service", "A Hono web service in a javascript cell serves a form, and handles submissions by calling a python function."),
+    example!("core12_dependency_management", "Core 12: Dependency Management", "Javascript cells import third party modules by url, which are fetched when the cell is evaluated."),
+];
+
+/// Estimated cost in USD of evaluating each cell of a markdown program that queries a model once
+fn estimated_source_cost_usd(source: &str) -> f64 {
+    let cells: Vec<_> = extract_code_blocks(source).iter()
+        .filter_map(|block| interpret_markdown_code_block(block, None).ok().flatten())
+        .collect();
+    estimated_cells_cost_usd(&cells)
+}
+
+impl Example {
+    pub fn estimated_cost_usd(&self) -> f64 {
+        estimated_source_cost_usd(self.source)
+    }
+}
+
+/// The bundled example programs, in the order they are presented
+pub fn examples() -> &'static [Example] {
+    EXAMPLES
+}
+
+pub fn find_example(name: &str) -> Option<&'static Example> {
+    EXAMPLES.iter().find(|example| example.name == name)
+}
+
+/// An example program found in a user's examples directory, laid out like the bundled examples
+/// as a directory of markdown files
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalExample {
+    /// Name of the directory holding the example
+    pub name: String,
+    pub path: PathBuf,
+    /// Markdown source of the program, its files concatenated in name order
+    pub source: String,
+}
+
+impl LocalExample {
+    pub fn estimated_cost_usd(&self) -> f64 {
+        estimated_source_cost_usd(&self.source)
+    }
+}
+
+/// The examples in `directory`, one for each subdirectory holding markdown files, sorted by name
+pub fn examples_in_directory(directory: &Path) -> anyhow::Result<Vec<LocalExample>> {
+    let mut examples = vec![];
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let mut files: Vec<PathBuf> = std::fs::read_dir(&path)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|file| file.extension().map_or(false, |ext| ext == "md"))
+            .collect();
+        if files.is_empty() {
+            continue;
+        }
+        files.sort();
+        let source = files.iter()
+            .map(std::fs::read_to_string)
+            .collect::<Result<Vec<_>, _>>()?
+            .join("\n");
+        examples.push(LocalExample {
+            name: path.file_name().unwrap().to_string_lossy().to_string(),
+            path,
+            source,
+        });
+    }
+    examples.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(examples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example_names_are_unique() {
+        let mut names: Vec<_> = examples().iter().map(|e| e.name).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), examples().len());
+        assert!(examples().iter().all(|example| example.description.split_whitespace().count() > 3));
+        assert!(find_example("core1_simple_math").unwrap().source.contains("x = 20"));
+        assert!(find_example("missing").is_none());
+        assert_eq!(find_example("core1_simple_math").unwrap().estimated_cost_usd(), 0.0);
+        assert!(find_example("core5_prompts_invoked_as_functions").unwrap().estimated_cost_usd() > 0.0);
+    }
+
+    #[test]
+    fn test_examples_in_directory() -> anyhow::Result<()> {
+        let directory = std::env::temp_dir().join(format!("chidori-examples-{}", uuid::Uuid::now_v7()));
+        std::fs::create_dir_all(directory.join("team_example"))?;
+        std::fs::create_dir_all(directory.join("empty"))?;
+        std::fs::write(directory.join("team_example").join("core.md"), "```python\nx = 1\n```\n")?;
+        std::fs::write(directory.join("notes.md"), "Not an example")?;
+
+        let examples = examples_in_directory(&directory)?;
+        assert_eq!(examples.len(), 1);
+        assert_eq!(examples[0].name, "team_example");
+        assert!(examples[0].source.contains("x = 1"));
+        std::fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+}
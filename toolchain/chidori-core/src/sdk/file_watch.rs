@@ -0,0 +1,264 @@
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use crate::sdk::interactive_chidori_wrapper::{parse_documented_md_directory, parse_md_directory, CellChanges, InteractiveChidoriWrapper};
+
+pub const DEFAULT_RELOAD_EXTENSIONS: &[&str] = &["md"];
+pub const DEFAULT_IGNORE_PATTERNS: &[&str] = &[".git/**", "**/*.swp", "**/*.swo", "**/*~", "**/.#*"];
+
+/// How long a watched directory must be quiet before a burst of changes to it is reloaded
+pub const DEFAULT_WATCH_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Whether paths on this platform's file systems are typically compared without regard to case
+pub const CASE_INSENSITIVE_PATHS: bool = cfg!(any(windows, target_os = "macos"));
+
+/// Decides which file system events in a watched directory should trigger a reload.
+#[derive(Debug, Clone)]
+pub struct ReloadFilter {
+    extensions: Vec<String>,
+    ignore_patterns: Vec<String>,
+    ignore: GlobSet,
+    case_insensitive: bool,
+}
+
+impl Default for ReloadFilter {
+    fn default() -> Self {
+        ReloadFilter::new(DEFAULT_RELOAD_EXTENSIONS, DEFAULT_IGNORE_PATTERNS)
+            .expect("Default ignore patterns are valid globs")
+    }
+}
+
+impl ReloadFilter {
+    /// Extensions are matched without their leading dot. Ignore patterns are globs matched
+    /// against paths relative to the watched directory.
+    pub fn new<E: AsRef<str>, P: AsRef<str>>(extensions: &[E], ignore_patterns: &[P]) -> anyhow::Result<Self> {
+        let ignore_patterns: Vec<String> = ignore_patterns.iter().map(|p| p.as_ref().to_string()).collect();
+        Ok(ReloadFilter {
+            extensions: extensions.iter().map(|e| e.as_ref().trim_start_matches('.').to_string()).collect(),
+            ignore: build_ignore_set(&ignore_patterns, CASE_INSENSITIVE_PATHS)?,
+            ignore_patterns,
+            case_insensitive: CASE_INSENSITIVE_PATHS,
+        })
+    }
+
+    /// Match paths to the watched directory without regard to case, as on the file systems of
+    /// Windows and macOS. Defaults to `CASE_INSENSITIVE_PATHS`.
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> anyhow::Result<Self> {
+        self.ignore = build_ignore_set(&self.ignore_patterns, case_insensitive)?;
+        self.case_insensitive = case_insensitive;
+        Ok(self)
+    }
+
+    pub fn is_relevant(&self, root: &Path, path: &Path) -> bool {
+        let relative = relative_to_root(root, path, self.case_insensitive);
+        let relative = relative.as_deref().unwrap_or(path);
+        if self.ignore.is_match(relative) {
+            return false;
+        }
+        relative.extension()
+            .and_then(|e| e.to_str())
+            .map_or(false, |e| self.extensions.iter().any(|ext| self.names_match(ext, e)))
+    }
+
+    fn names_match(&self, a: &str, b: &str) -> bool {
+        if self.case_insensitive { a.to_lowercase() == b.to_lowercase() } else { a == b }
+    }
+
+    /// Collapse a burst of events into the sorted, deduplicated set of paths that warrant a reload.
+    pub fn relevant_paths(&self, root: &Path, paths: impl IntoIterator<Item = PathBuf>) -> Vec<PathBuf> {
+        paths.into_iter()
+            .filter(|p| self.is_relevant(root, p))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+fn build_ignore_set(patterns: &[String], case_insensitive: bool) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(GlobBuilder::new(pattern).case_insensitive(case_insensitive).build()?);
+    }
+    Ok(builder.build()?)
+}
+
+/// The portion of `path` within `root`, compared component by component so that differences in
+/// separators, and in case where the file system ignores it, do not prevent a match.
+fn relative_to_root(root: &Path, path: &Path, case_insensitive: bool) -> Option<PathBuf> {
+    let mut components = path.components().filter(|c| !matches!(c, Component::CurDir));
+    for root_component in root.components().filter(|c| !matches!(c, Component::CurDir)) {
+        let component = components.next()?;
+        let (a, b) = (root_component.as_os_str().to_string_lossy(), component.as_os_str().to_string_lossy());
+        let matches = if case_insensitive { a.to_lowercase() == b.to_lowercase() } else { a == b };
+        if !matches {
+            return None;
+        }
+    }
+    Some(components.collect())
+}
+
+/// Reload the program in `root` in response to changes to `paths`. Returns `None` when none of the
+/// paths are relevant. The directory is parsed without holding the lock on the wrapper, which is
+/// only taken briefly to read configuration and to apply the parsed cells.
+pub fn reload_changed_paths(
+    chidori: &Mutex<InteractiveChidoriWrapper>,
+    root: &Path,
+    filter: &ReloadFilter,
+    paths: impl IntoIterator<Item = PathBuf>,
+) -> anyhow::Result<Option<CellChanges>> {
+    let changed_paths = filter.relevant_paths(root, paths);
+    if changed_paths.is_empty() {
+        return Ok(None);
+    }
+    let default_language = chidori.lock().map_err(|e| anyhow::anyhow!("{}", e))?.default_language.clone();
+    let cells = parse_documented_md_directory(root, default_language.as_ref())?;
+    let changes = chidori.lock()
+        .map_err(|e| anyhow::anyhow!("{}", e))?
+        .reload_md_directory(root, cells, changed_paths)?;
+    Ok(Some(changes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::sdk::event_subscriptions::EventFilter;
+    use crate::cells::CellTypes;
+    use crate::sdk::interactive_chidori_wrapper::EventsFromRuntime;
+
+    fn temp_program_dir(contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("chidori-file-watch-{}", uuid::Uuid::now_v7()));
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join("core.md"), contents).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_default_filter() {
+        let root = Path::new("/project");
+        let filter = ReloadFilter::default();
+        assert!(filter.is_relevant(root, Path::new("/project/core.md")));
+        assert!(filter.is_relevant(root, Path::new("/project/nested/agent.md")));
+        assert!(!filter.is_relevant(root, Path::new("/project/.git/index")));
+        assert!(!filter.is_relevant(root, Path::new("/project/.git/notes.md")));
+        assert!(!filter.is_relevant(root, Path::new("/project/nested/core.md.swp")));
+        assert!(!filter.is_relevant(root, Path::new("/project/core.md~")));
+        assert!(!filter.is_relevant(root, Path::new("/project/.#core.md")));
+        assert!(!filter.is_relevant(root, Path::new("/project/image.png")));
+        assert_eq!(
+            filter.relevant_paths(root, vec![
+                PathBuf::from("/project/core.md"),
+                PathBuf::from("/project/.git/index"),
+                PathBuf::from("/project/core.md"),
+            ]),
+            vec![PathBuf::from("/project/core.md")]
+        );
+    }
+
+    #[test]
+    fn test_configured_extensions() {
+        let filter = ReloadFilter::new(&["md", ".py"], DEFAULT_IGNORE_PATTERNS).unwrap();
+        assert!(filter.is_relevant(Path::new("/p"), Path::new("/p/tools.py")));
+        assert!(!filter.is_relevant(Path::new("/p"), Path::new("/p/tools.js")));
+    }
+
+    #[test]
+    fn test_paths_with_spaces_and_unicode() {
+        let root = Path::new("/Users/ada/my agents/日本語");
+        let filter = ReloadFilter::default().case_insensitive(false).unwrap();
+        assert!(filter.is_relevant(root, &root.join("nested dir").join("cœur.md")));
+        assert!(!filter.is_relevant(root, &root.join(".git").join("cœur.md")));
+    }
+
+    #[test]
+    fn test_case_insensitive_paths() {
+        let root = Path::new("/Users/Ada/Agents");
+        let sensitive = ReloadFilter::default().case_insensitive(false).unwrap();
+        let insensitive = ReloadFilter::default().case_insensitive(true).unwrap();
+        // Events may report the watched directory with different casing than it was registered with
+        let path = Path::new("/users/ada/agents/.GIT/notes.md");
+        assert!(sensitive.is_relevant(root, path));
+        assert!(!insensitive.is_relevant(root, path));
+        assert!(insensitive.is_relevant(root, Path::new("/users/ada/agents/CORE.MD")));
+        assert!(!sensitive.is_relevant(root, Path::new("/users/ada/agents/CORE.MD")));
+    }
+
+    #[test]
+    fn test_load_directory_with_spaces_and_unicode() {
+        let dir = std::env::temp_dir().join(format!("chidori docs ü 日本 {}", uuid::Uuid::now_v7()));
+        fs::create_dir_all(dir.join("nested dir")).unwrap();
+        fs::write(dir.join("nested dir").join("cœur.md"), "```python\nx = 1\n```\n").unwrap();
+
+        let mut wrapper = InteractiveChidoriWrapper::new();
+        wrapper.load_md_directory(&dir).unwrap();
+        assert_eq!(wrapper.loaded_path, Some(dir.clone()));
+        match &parse_md_directory(&dir, None).unwrap()[..] {
+            [CellTypes::Code(cell, _)] => assert_eq!(
+                cell.backing_file_reference.as_ref().map(|r| r.path.as_str()),
+                Some("nested dir/cœur.md")
+            ),
+            cells => panic!("Unexpected cells {:?}", cells),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reload_changed_paths() {
+        let dir = temp_program_dir(indoc::indoc! { r#"
+            ```python (unchanged)
+            x = 1
+            ```
+
+            ```python (changed)
+            y = 2
+            ```
+
+            ```python (removed)
+            z = 3
+            ```
+            "#});
+        let mut wrapper = InteractiveChidoriWrapper::new();
+        let rx = wrapper.subscribe(EventFilter::all());
+        wrapper.load_md_directory(&dir).unwrap();
+        let chidori = Mutex::new(wrapper);
+        let filter = ReloadFilter::default();
+
+        // Changes to ignored paths do not reload
+        let ignored = reload_changed_paths(&chidori, &dir, &filter, vec![
+            dir.join(".git/index"),
+            dir.join("core.md.swp"),
+        ]).unwrap();
+        assert_eq!(ignored, None);
+        assert!(rx.try_recv().is_err());
+
+        fs::write(dir.join("core.md"), indoc::indoc! { r#"
+            ```python (unchanged)
+            x = 1
+            ```
+
+            ```python (changed)
+            y = 20
+            ```
+
+            ```python (added)
+            w = 4
+            ```
+            "#}).unwrap();
+        let changes = reload_changed_paths(&chidori, &dir, &filter, vec![
+            dir.join("core.md"),
+            dir.join("core.md"),
+        ]).unwrap();
+        assert_eq!(changes, Some(CellChanges { added: 1, updated: 1, removed: 1 }));
+
+        match rx.try_recv().unwrap() {
+            EventsFromRuntime::DocumentsReloaded { changed_paths, cells_added, cells_updated, cells_removed } => {
+                assert_eq!(changed_paths, vec![dir.join("core.md")]);
+                assert_eq!((cells_added, cells_updated, cells_removed), (1, 1, 1));
+            }
+            e => panic!("Unexpected event {:?}", e),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
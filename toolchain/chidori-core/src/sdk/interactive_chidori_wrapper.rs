@@ -3,22 +3,41 @@ use std::sync::{mpsc, Arc, MutexGuard};
 
 use no_deadlocks::Mutex;
 use uuid::Uuid;
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{Receiver, Sender};
 use tracing::dispatcher::DefaultGuard;
 use std::collections::{HashMap, HashSet};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use futures_util::future::Shared;
 use tracing::info;
 use dashmap::DashMap;
+use im::HashMap as ImHashMap;
 use serde::{Serialize, Serializer};
 use serde::ser::SerializeMap;
 use std::ops::Deref;
-use crate::cells::{CellTypes};
-use crate::execution::execution::execution_graph::{ExecutionGraph, ExecutionNodeId, MergedStateHistory};
+use base64::Engine;
+use rkyv::Deserialize;
+use crate::cells::{CellTypes, ExecutionPolicy, ProxyConfig, SupportedLanguage, SupportedModelProviders};
+use crate::execution::execution::execution_graph::{AnnotatedDependencyEdge, ExecutionGraph, ExecutionNodeId, MergedStateHistory};
 use crate::execution::execution::ExecutionState;
-use crate::execution::primitives::identifiers::{DependencyReference, OperationId};
-use crate::sdk::chidori_runtime_instance::{ChidoriRuntimeInstance, PlaybackState, UserInteractionMessage};
-use crate::sdk::md::{interpret_markdown_code_block, load_folder};
+use crate::execution::execution::html_report::cell_source;
+use crate::execution::execution::repro::{ReproBundle, ReproManifest};
+use crate::execution::execution::execution_state::{DefinitionValidationReport, ExecutionStateErrors, NativeFunction, DEFAULT_MAX_INVOCATION_DEPTH};
+use crate::execution::primitives::identifiers::OperationId;
+use crate::execution::primitives::agent_trace::AgentTraceStep;
+use crate::execution::primitives::serialized_value::RkyvSerializedValue;
+use crate::sdk::chidori_runtime_instance::{ChidoriRuntimeInstance, PlaybackState, DEFAULT_MAX_SETTLE_STEPS, UserInteractionMessage};
+use crate::sdk::checkpoint::{checkpoint_directory, read_latest_checkpoint, Checkpoint, CheckpointConfig, Checkpointer};
+use crate::sdk::examples::find_example;
+use crate::sdk::save::{plan_write_back, write_and_commit, SaveCommitOptions, SaveContext, WriteBack};
+use crate::sdk::registry::{fetch_listed_cells, fetch_registry_cells, manifest_url, registry_imports, registry_imports_in_directory, RegistryImport};
+use crate::sdk::md::{documented_cells_to_markdown, document_path, interpret_documented_code_block, load_folder, DocumentedCell, MarkdownCodeBlock};
+use crate::utils::prompt_audit::PromptAuditLog;
+use crate::library::std::ai::llm::cache::ResponseCache;
+use crate::library::std::ai::llm::estimated_cells_cost_usd;
+use crate::library::std::code::runtime_deno::DenoModuleConfig;
+use crate::utils::redaction::RedactionConfig;
+use crate::sdk::event_subscriptions::{EventFilter, EventSubscribers};
 use crate::utils::telemetry::{init_internal_telemetry, TraceEvents};
 
 /// Chidori is the high level interface for interacting with our runtime.
@@ -28,16 +47,65 @@ pub struct InteractiveChidoriWrapper {
 
     /// Sender to push user requests to the instance, these events result in
     /// state changes within the instance
-    pub instanced_env_tx: Option<Sender<UserInteractionMessage>>,
+    pub instanced_env_tx: Option<tokio::sync::mpsc::UnboundedSender<UserInteractionMessage>>,
 
-    /// Sender to pass changes in state within instances back to the main thread
-    pub runtime_event_sender: Option<Sender<EventsFromRuntime>>,
+    /// Subscribers to changes in state within instances, see `subscribe`
+    pub runtime_events: EventSubscribers,
 
     /// Sender to collect trace events from instances
     pub trace_event_sender: Option<Sender<TraceEvents>>,
 
     pub shared_state: Arc<Mutex<SharedState>>,
-    pub loaded_path: Option<String>,
+    pub loaded_path: Option<PathBuf>,
+
+    /// When set, instances begin running as soon as their cells are loaded
+    pub auto_play: bool,
+
+    /// Language of code fences that do not declare one, these are ignored when unset
+    pub default_language: Option<SupportedLanguage>,
+
+    /// Redaction applied to values emitted by instances created after this is set
+    pub redaction: RedactionConfig,
+
+    /// Timeout, retry and output size settings for cells that do not declare their own
+    pub default_execution_policy: ExecutionPolicy,
+
+    /// Transcript of every prompt and response of instances created after this is set
+    pub prompt_audit: Option<Arc<PromptAuditLog>>,
+
+    /// Responses of models reused by instances created after this is set
+    pub llm_cache: Option<Arc<ResponseCache>>,
+
+    /// When instances created after this is set without an `llm_cache` keep responses in the
+    /// loaded directory, see `set_project_llm_cache`
+    pub project_llm_cache_enabled: bool,
+
+    /// Rust functions callable from code cells of instances created after they are registered
+    pub native_functions: ImHashMap<String, Arc<NativeFunction>>,
+
+    /// Import map and allowed hosts of javascript cells of instances created after this is set
+    pub deno_modules: DenoModuleConfig,
+
+    /// Proxies of model providers for instances created after they are set
+    pub llm_proxies: ImHashMap<SupportedModelProviders, ProxyConfig>,
+
+    /// When instances created after this is set write checkpoints to the loaded directory
+    pub checkpointing: Option<CheckpointConfig>,
+
+    /// The checkpoint found in the loaded directory when it was loaded, see `recover_latest_checkpoint`
+    pub available_checkpoint: Option<Checkpoint>,
+
+    /// Restored by instances created after recovery was requested
+    recovering_checkpoint: Option<Checkpoint>,
+
+    /// Cells the loaded documents import from registries, see `fetch_registry_imports`
+    pub registry_imports: Vec<RegistryImport>,
+
+    /// Cells fetched from registries, held until they are accepted with `accept_registry_cells`
+    pub pending_registry_cells: Vec<CellHolder>,
+
+    /// Names of the cells instances pause before, see `set_breakpoint`
+    pub breakpoints: HashSet<String>,
 
     pub tracing_guard: Option<DefaultGuard>
 }
@@ -63,25 +131,159 @@ impl InteractiveChidoriWrapper {
     pub fn new() -> Self {
         InteractiveChidoriWrapper {
             instanced_env_tx: None,
-            runtime_event_sender: None,
+            runtime_events: EventSubscribers::new(),
             trace_event_sender: None,
             loaded_path: None,
             shared_state: initialize_shared_state_object(),
             tracing_guard: None,
+            auto_play: false,
+            default_language: None,
+            redaction: RedactionConfig::default(),
+            default_execution_policy: ExecutionPolicy::default(),
+            prompt_audit: None,
+            llm_cache: None,
+            project_llm_cache_enabled: false,
+            native_functions: Default::default(),
+            deno_modules: Default::default(),
+            llm_proxies: Default::default(),
+            checkpointing: None,
+            available_checkpoint: None,
+            recovering_checkpoint: None,
+            registry_imports: vec![],
+            pending_registry_cells: vec![],
+            breakpoints: HashSet::new(),
         }
     }
 
+    /// Events of instances are sent to `runtime_event_sender` unfiltered, further clients may `subscribe`
     pub fn new_with_events(sender: Sender<TraceEvents>, runtime_event_sender: Sender<EventsFromRuntime>) -> Self {
+        let runtime_events = EventSubscribers::new();
+        runtime_events.add_sender(EventFilter::all(), runtime_event_sender);
         let init_telemetry = init_internal_telemetry(sender.clone());
         tracing::subscriber::set_global_default(init_internal_telemetry(sender.clone())).expect("Failed to set global default");
         let guard: DefaultGuard = tracing::subscriber::set_default(init_telemetry);
         InteractiveChidoriWrapper {
             instanced_env_tx: None,
-            runtime_event_sender: Some(runtime_event_sender),
+            runtime_events,
             trace_event_sender: Some(sender),
             loaded_path: None,
             shared_state: initialize_shared_state_object(),
-            tracing_guard: Some(guard)
+            tracing_guard: Some(guard),
+            auto_play: false,
+            default_language: None,
+            redaction: RedactionConfig::default(),
+            default_execution_policy: ExecutionPolicy::default(),
+            prompt_audit: None,
+            llm_cache: None,
+            project_llm_cache_enabled: false,
+            native_functions: Default::default(),
+            deno_modules: Default::default(),
+            llm_proxies: Default::default(),
+            checkpointing: None,
+            available_checkpoint: None,
+            recovering_checkpoint: None,
+            registry_imports: vec![],
+            pending_registry_cells: vec![],
+            breakpoints: HashSet::new(),
+        }
+    }
+
+    /// Start playback automatically once cells are loaded into instances created after this call,
+    /// rather than waiting for a `SetPlaybackState` message.
+    pub fn enable_auto_play(&mut self, on: bool) {
+        self.auto_play = on;
+    }
+
+    /// Redact values matching the given rules from the events and reports of subsequently created instances.
+    pub fn set_redaction(&mut self, config: RedactionConfig) {
+        self.redaction = config;
+    }
+
+    /// Apply a timeout, retries and output size limit to cells of subsequently created instances that do not declare their own.
+    pub fn set_default_execution_policy(&mut self, policy: ExecutionPolicy) {
+        self.default_execution_policy = policy;
+    }
+
+    /// Record every prompt sent and response received by subsequently created instances.
+    pub fn set_prompt_audit(&mut self, log: PromptAuditLog) {
+        self.prompt_audit = Some(Arc::new(log));
+    }
+
+    /// Reuse model responses from the given cache in subsequently created instances.
+    pub fn set_llm_cache(&mut self, cache: ResponseCache) {
+        self.llm_cache = Some(Arc::new(cache));
+    }
+
+    /// Keep the responses of models under the loaded directory and reuse them across runs, for
+    /// instances created after this is set that have not been given an `llm_cache`. Off by default.
+    pub fn set_project_llm_cache(&mut self, enabled: bool) {
+        self.project_llm_cache_enabled = enabled;
+    }
+
+    /// Resolve the imports of javascript cells of subsequently created instances with the given
+    /// import map, and restrict the hosts they may fetch from.
+    pub fn set_deno_modules(&mut self, config: DenoModuleConfig) {
+        self.deno_modules = config;
+    }
+
+    /// Send the requests of prompts to `provider` in subsequently created instances through
+    /// `proxy`, unless a prompt sets its own
+    pub fn set_llm_proxy(&mut self, provider: SupportedModelProviders, proxy: ProxyConfig) {
+        self.llm_proxies.insert(provider, proxy);
+    }
+
+    /// Write checkpoints of the states evaluated by instances to the loaded directory, so that the
+    /// session can be recovered with `recover_latest_checkpoint` after a crash
+    pub fn set_checkpointing(&mut self, config: CheckpointConfig) {
+        self.checkpointing = Some(config);
+    }
+
+    /// Continue from the checkpoint of the last session found when the directory was loaded,
+    /// returning the id of its head and the number of steps it holds so that hosts can describe it.
+    /// The running instance restores it immediately, otherwise the next instance created does.
+    pub fn recover_latest_checkpoint(&mut self) -> anyhow::Result<Option<RecoveredCheckpoint>> {
+        let Some(checkpoint) = self.available_checkpoint.take() else {
+            return Ok(None);
+        };
+        let recovered = RecoveredCheckpoint { head_id: checkpoint.head_id, step_count: checkpoint.step_count() };
+        if self.instanced_env_tx.is_some() {
+            self.dispatch_user_interaction_to_instance(UserInteractionMessage::RestoreCheckpoint(checkpoint))?;
+        } else {
+            self.recovering_checkpoint = Some(checkpoint);
+        }
+        Ok(Some(recovered))
+    }
+
+    /// The cache kept under the loaded directory when it has been enabled, programs loaded from a
+    /// string have none
+    fn project_llm_cache(&self) -> Option<Arc<ResponseCache>> {
+        self.loaded_path.as_ref()
+            .filter(|path| self.project_llm_cache_enabled && path.is_dir())
+            .map(|path| Arc::new(ResponseCache::for_project(path)))
+    }
+
+    /// The checkpointer writing to the loaded directory, programs loaded from a string have none
+    fn project_checkpointer(&self) -> anyhow::Result<Option<Checkpointer>> {
+        match (&self.checkpointing, self.loaded_path.as_ref().filter(|path| path.is_dir())) {
+            (Some(config), Some(path)) => Ok(Some(Checkpointer::new(config.clone(), checkpoint_directory(path))?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Expose a Rust function to the code cells of subsequently created instances, python cells call
+    /// it as `ch.native(name, args)` and javascript cells as `await native(name, args)`.
+    pub fn register_native_function(&mut self, name: &str, f: impl Fn(RkyvSerializedValue) -> RkyvSerializedValue + Send + Sync + 'static) {
+        self.native_functions.insert(name.to_string(), Arc::new(f));
+    }
+
+    /// Interpret bare code fences in subsequently loaded programs as the given language.
+    pub fn set_default_language(&mut self, language: Option<SupportedLanguage>) {
+        self.default_language = language;
+    }
+
+    fn apply_default_language(&self, block: &mut MarkdownCodeBlock) {
+        if let Some(language) = &self.default_language {
+            block.apply_default_language(language);
         }
     }
 
@@ -93,84 +295,377 @@ impl InteractiveChidoriWrapper {
         Ok(())
     }
 
-    fn load_cells(&mut self, cells: Vec<CellTypes>) -> anyhow::Result<()>  {
+    /// Pause before evaluating the cell named `name`, or stop pausing before it. Breakpoints are
+    /// kept for instances created later, so they survive restarting the instance.
+    pub fn set_breakpoint(&mut self, name: &str, enabled: bool) -> anyhow::Result<()> {
+        if enabled {
+            self.breakpoints.insert(name.to_string());
+            self.dispatch_user_interaction_to_instance(UserInteractionMessage::SetBreakpoint(name.to_string()))
+        } else {
+            self.breakpoints.remove(name);
+            self.dispatch_user_interaction_to_instance(UserInteractionMessage::ClearBreakpoint(name.to_string()))
+        }
+    }
+
+    fn load_cells(&mut self, cells: Vec<DocumentedCell>) -> anyhow::Result<CellChanges>  {
         // TODO: this overrides the entire shared state object
-        let cell_name_map = {
+        let (cell_name_map, registry_cells) = {
             let previous_cells = &self.shared_state.lock().unwrap().editor_cells;
-            previous_cells.values().map(|cell| {
+            let cell_name_map = previous_cells.values().map(|cell| {
                 let name = cell.cell.name();
                 (name.clone(), cell.clone())
-            }).collect::<HashMap<_, _>>()
+            }).collect::<HashMap<_, _>>();
+            let registry_cells: Vec<CellHolder> = previous_cells.values()
+                .filter(|cell| cell.registry_url.is_some())
+                .cloned()
+                .collect();
+            (cell_name_map, registry_cells)
         };
 
+        let mut changes = CellChanges::default();
         let mut new_cells_state = HashMap::new();
-        for cell in cells {
+        for DocumentedCell { cell, description } in cells {
             let name = cell.name();
             // If the named cell exists in our map already
             if let Some(existing_cell_instance) = cell_name_map.get(&name) {
                 // If it's not the same cell, replace it
                 if existing_cell_instance.cell != cell {
+                    changes.updated += 1;
                     new_cells_state.insert(existing_cell_instance.op_id, CellHolder {
                         cell,
                         applied_at: None,
                         op_id: existing_cell_instance.op_id,
-                        needs_update: true
+                        needs_update: true,
+                        description,
+                        registry_url: None,
                     });
                 } else {
-                    // It's the same cell so just push our existing state
-                    new_cells_state.insert(existing_cell_instance.op_id, existing_cell_instance.clone());
+                    // It's the same cell so just push our existing state, a changed description
+                    // does not change how it evaluates
+                    new_cells_state.insert(existing_cell_instance.op_id, CellHolder {
+                        description,
+                        registry_url: None,
+                        ..existing_cell_instance.clone()
+                    });
                 }
             } else {
                 // This is a new cell, so we push it with a null applied at
+                changes.added += 1;
                 let id = Uuid::now_v7();
                 new_cells_state.insert(id, CellHolder {
                     cell,
                     applied_at: None,
                     op_id: id,
-                    needs_update: true
+                    needs_update: true,
+                    description,
+                    registry_url: None,
                 });
             }
         }
+        // Cells loaded from a registry are not part of the documents, they are kept unless a
+        // document defines a cell of the same name
+        for cell in registry_cells {
+            let name = cell.cell.name();
+            let redefined = name.is_some() && new_cells_state.values().any(|holder| holder.cell.name() == name);
+            if !redefined {
+                new_cells_state.entry(cell.op_id).or_insert(cell);
+            }
+        }
+        changes.removed = cell_name_map.values()
+            .filter(|cell| !new_cells_state.contains_key(&cell.op_id))
+            .count();
         self.shared_state.lock().unwrap().editor_cells = new_cells_state;
         println!("Cells commit to shared state");
         self.dispatch_user_interaction_to_instance(UserInteractionMessage::ReloadCells)?;
-        Ok(())
+        Ok(changes)
     }
 
     pub fn load_md_string(&mut self, s: &str) -> anyhow::Result<()> {
         let mut cells = vec![];
-        crate::sdk::md::extract_code_blocks(s)
-            .iter()
-            .filter_map(|block| interpret_markdown_code_block(block, None).unwrap())
-            .for_each(|block| { cells.push(block); });
-        cells.sort();
-        self.loaded_path = Some("raw_text".to_string());
-        self.load_cells(cells)
+        for mut block in crate::sdk::md::extract_code_blocks(s) {
+            self.apply_default_language(&mut block);
+            if let Some(cell) = interpret_documented_code_block(&block, None)? {
+                cells.push(cell);
+            }
+        }
+        cells.sort_by(|a, b| a.cell.cmp(&b.cell));
+        self.loaded_path = Some(PathBuf::from("raw_text"));
+        self.registry_imports = registry_imports(s)?;
+        self.load_cells(cells)?;
+        Ok(())
     }
 
-    pub fn load_md_directory(&mut self, path: &Path) -> anyhow::Result<()> {
-        let files = load_folder(path)?;
-        let mut cells = vec![];
-        for file in files {
-            for block in file.result {
-                if let Some(block) = interpret_markdown_code_block(&block, Some(path.to_string_lossy().to_string())).unwrap() {
-                    cells.push(block);
-                }
+    /// The loaded program as a markdown document, including edits made since it was loaded.
+    /// Loading the document with `load_md_string` produces the same cells.
+    pub fn to_markdown(&self) -> anyhow::Result<String> {
+        let shared_state = self.shared_state.lock().unwrap();
+        let mut cells: Vec<&CellHolder> = shared_state.editor_cells.values().collect();
+        cells.sort_by_key(|holder| (holder.cell.text_range().start, holder.op_id));
+        documented_cells_to_markdown(cells.into_iter().map(|holder| (&holder.cell, holder.description.as_deref())))
+    }
+
+    /// Write cells edited since they were loaded back to the documents under `loaded_path`,
+    /// replacing the blocks defining them and removing the blocks of cells that were deleted.
+    /// Returns the documents and cells that changed.
+    pub fn save_to_loaded_path(&self) -> anyhow::Result<WriteBack> {
+        let (_, documents, write_back) = self.plan_write_back()?;
+        for (path, source) in documents {
+            std::fs::write(path, source)?;
+        }
+        Ok(write_back)
+    }
+
+    /// `save_to_loaded_path`, committing the documents it touched to the git repository holding
+    /// them. Nothing is written when the index holds other changes, unless `options.allow_mixed`
+    /// is set. Returns the commit, None when no document changed.
+    pub async fn save_to_loaded_path_with_commit(&self, options: SaveCommitOptions) -> anyhow::Result<Option<git2::Oid>> {
+        let (root, documents, write_back) = self.plan_write_back()?;
+        if documents.is_empty() {
+            return Ok(None);
+        }
+        let context = self.save_context();
+        Ok(Some(write_and_commit(&root, documents, &write_back, &context, &options).await?))
+    }
+
+    fn plan_write_back(&self) -> anyhow::Result<(PathBuf, Vec<(PathBuf, String)>, WriteBack)> {
+        let root = self.loaded_path.as_ref()
+            .filter(|path| path.is_dir())
+            .ok_or_else(|| anyhow::anyhow!("Only programs loaded from a directory can be saved"))?;
+        let shared_state = self.shared_state.lock().unwrap();
+        // Registry cells are not defined by the documents
+        let cells: HashMap<String, &CellTypes> = shared_state.editor_cells.values()
+            .filter(|holder| holder.registry_url.is_none())
+            .filter_map(|holder| holder.cell.name().clone().map(|name| (name, &holder.cell)))
+            .collect();
+        let (documents, write_back) = plan_write_back(root, self.default_language.as_ref(), &cells)?;
+        Ok((root.clone(), documents, write_back))
+    }
+
+    /// Whether the edited cells apply to the execution head, and how its cells last evaluated
+    fn save_context(&self) -> SaveContext {
+        let shared_state = self.shared_state.lock().unwrap();
+        let Some(head) = shared_state.execution_id_to_evaluation.get(&shared_state.execution_state_head_id) else {
+            return SaveContext::default();
+        };
+        let edited: Vec<(CellTypes, OperationId)> = shared_state.editor_cells.values()
+            .filter(|holder| holder.needs_update)
+            .map(|holder| (holder.cell.clone(), holder.op_id))
+            .collect();
+        let validation = Some(head.apply_cell_mutations(edited).map(|_| ()).map_err(|report| report.to_string()));
+        let mut evaluated: Vec<(String, bool)> = head.state.iter()
+            .map(|(id, output)| {
+                let name = head.operation_by_id.get(id).and_then(|op| op.name.clone()).unwrap_or_else(|| id.to_string());
+                (name, output.has_error)
+            })
+            .collect();
+        evaluated.sort();
+        SaveContext { validation, last_run: Some(evaluated).filter(|evaluated| !evaluated.is_empty()) }
+    }
+
+    /// The loaded cells, in the order of the program, each as
+    /// `{"op_id", "name", "kind", "description"}` for editors to show alongside them
+    pub fn describe(&self) -> serde_json::Value {
+        let shared_state = self.shared_state.lock().unwrap();
+        let head = shared_state.execution_id_to_evaluation.get(&shared_state.execution_state_head_id);
+        let mut cells: Vec<&CellHolder> = shared_state.editor_cells.values().collect();
+        cells.sort_by_key(|holder| (holder.cell.text_range().start, holder.op_id));
+        serde_json::Value::Array(cells.into_iter().map(|holder| {
+            let mut description = serde_json::json!({
+                "op_id": holder.op_id,
+                "name": holder.cell.name(),
+                "kind": cell_source(&holder.cell).0,
+                "description": holder.description,
+                "registry_url": holder.registry_url,
+            });
+            if let CellTypes::Webhook(cell, _) = &holder.cell {
+                // Handlers are only checked once the cells defining them have been loaded
+                let issues = head.as_ref()
+                    .map(|state| cell.route_table.validate(state))
+                    .unwrap_or_default();
+                description["routes"] = cell.route_table.routes.iter().map(|route| serde_json::json!({
+                    "method": route.method,
+                    "path": route.path,
+                    "handler": route.handler,
+                    "params": route.params,
+                    "range": [route.range.start, route.range.end],
+                    "issues": issues.iter()
+                        .filter(|issue| issue.range == route.range)
+                        .map(|issue| issue.message.clone())
+                        .collect::<Vec<_>>(),
+                })).collect();
             }
+            description
+        }).collect())
+    }
+
+    /// Fetch every cell listed by the registry at `registry_url` and append them to the loaded
+    /// cells, replacing those previously loaded from the same registry under the same name. To
+    /// take only cells pinned to a digest, and review them first, see `stage_cells_from_registry`.
+    pub async fn load_cells_from_registry(&mut self, registry_url: &str) -> anyhow::Result<()> {
+        let cells = fetch_listed_cells(registry_url).await?;
+        let registry_url = manifest_url(registry_url);
+        self.add_registry_cells(cells.into_iter().map(|cell| CellHolder {
+            registry_url: Some(registry_url.clone()),
+            ..cell
+        }).collect())?;
+        Ok(())
+    }
+
+    /// Fetch the cells `import` pins from its registry and hold them for review, replacing those
+    /// held from the same registry under the same name. Fetched cells are not evaluated until they
+    /// are accepted with `accept_registry_cells`. Returns the number of cells fetched.
+    pub async fn stage_cells_from_registry(&mut self, import: &RegistryImport) -> anyhow::Result<usize> {
+        let cells = fetch_registry_cells(import).await?;
+        let count = cells.len();
+        self.stage_registry_cells(&import.manifest_url(), cells);
+        Ok(count)
+    }
+
+    /// Fetch the cells of every registry the loaded documents import from, see `stage_cells_from_registry`
+    pub async fn fetch_registry_imports(&mut self) -> anyhow::Result<usize> {
+        let mut count = 0;
+        for import in self.registry_imports.clone() {
+            count += self.stage_cells_from_registry(&import).await?;
+        }
+        Ok(count)
+    }
+
+    /// Hold cells fetched from the registry at `registry_url` for review, for callers fetching
+    /// them with `registry::fetch_registry_cells` themselves
+    pub fn stage_registry_cells(&mut self, registry_url: &str, cells: Vec<CellHolder>) {
+        info!("Fetched {} cells from the registry {}, awaiting review", cells.len(), registry_url);
+        for cell in cells {
+            let name = cell.cell.name();
+            self.pending_registry_cells.retain(|pending| {
+                !(pending.cell.name() == name && pending.registry_url.as_deref() == Some(registry_url))
+            });
+            self.pending_registry_cells.push(CellHolder {
+                registry_url: Some(registry_url.to_string()),
+                ..cell
+            });
+        }
+    }
+
+    /// Add the cells fetched from registries to the loaded cells, replacing those previously
+    /// accepted from the same registry under the same name. Accepted cells are kept when
+    /// documents are reloaded. Returns the number of cells accepted.
+    pub fn accept_registry_cells(&mut self) -> anyhow::Result<usize> {
+        let cells = std::mem::take(&mut self.pending_registry_cells);
+        self.add_registry_cells(cells)
+    }
+
+    /// Add cells tagged with the registry they were fetched from to the loaded cells, see
+    /// `accept_registry_cells`
+    fn add_registry_cells(&mut self, cells: Vec<CellHolder>) -> anyhow::Result<usize> {
+        let count = cells.len();
+        if count == 0 {
+            return Ok(0);
+        }
+        {
+            let mut shared_state = self.shared_state.lock().unwrap();
+            for cell in cells {
+                let name = cell.cell.name();
+                let existing = shared_state.editor_cells.values()
+                    .find(|holder| name.is_some() && holder.cell.name() == name && holder.registry_url == cell.registry_url)
+                    .map(|holder| holder.op_id);
+                let op_id = existing.unwrap_or_else(Uuid::now_v7);
+                shared_state.editor_cells.insert(op_id, CellHolder {
+                    op_id,
+                    applied_at: None,
+                    needs_update: true,
+                    ..cell
+                });
+            }
+        }
+        self.dispatch_user_interaction_to_instance(UserInteractionMessage::ReloadCells)?;
+        Ok(count)
+    }
+
+    /// Discard the cells fetched from registries without loading them
+    pub fn reject_registry_cells(&mut self) {
+        self.pending_registry_cells.clear();
+    }
+
+    /// Estimated cost in USD of evaluating every cell of the loaded program that queries a model
+    /// once, 0.0 for programs without any
+    pub fn get_execution_cost_estimate(&self) -> f64 {
+        let shared_state = self.shared_state.lock().unwrap();
+        estimated_cells_cost_usd(shared_state.editor_cells.values().map(|holder| &holder.cell))
+    }
+
+    /// Load one of the bundled example programs by its identifier, e.g. `core1_simple_math`.
+    /// See `examples::examples` for those available.
+    pub fn load_example(&mut self, name: &str) -> anyhow::Result<()> {
+        let example = find_example(name)
+            .ok_or_else(|| anyhow::anyhow!("No example named {} is bundled", name))?;
+        self.load_md_string(example.source)
+    }
+
+    /// Ask a model to write a program accomplishing `description`. The markdown is returned
+    /// rather than loaded so that it can be reviewed before being passed to `load_md_string`.
+    #[cfg(feature = "generate_workflow")]
+    pub async fn create_workflow_from_description(&self, description: &str) -> anyhow::Result<String> {
+        crate::sdk::workflow_generation::generate_workflow(description).await
+    }
+
+    pub fn load_md_directory(&mut self, path: &Path) -> anyhow::Result<()> {
+        let cells = parse_documented_md_directory(path, self.default_language.as_ref())?;
+        self.loaded_path = Some(path.to_path_buf());
+        self.registry_imports = registry_imports_in_directory(path)?;
+        self.available_checkpoint = read_latest_checkpoint(&checkpoint_directory(path))?;
+        if let Some(checkpoint) = &self.available_checkpoint {
+            info!("Found a checkpoint of {} steps in {:?}", checkpoint.step_count(), path);
         }
-        self.loaded_path = Some(path.to_str().unwrap().to_string());
-        cells.sort();
         info!("Loading {} cells from {:?}", cells.len(), path);
-        self.load_cells(cells)
+        self.load_cells(cells)?;
+        Ok(())
+    }
+
+    /// Replace the loaded cells with those parsed from a directory after the given files changed on disk,
+    /// emitting a `DocumentsReloaded` event describing what changed.
+    pub fn reload_md_directory(&mut self, path: &Path, cells: Vec<DocumentedCell>, changed_paths: Vec<PathBuf>) -> anyhow::Result<CellChanges> {
+        self.loaded_path = Some(path.to_path_buf());
+        self.registry_imports = registry_imports_in_directory(path)?;
+        info!("Reloading {} cells from {:?} after changes to {:?}", cells.len(), path, changed_paths);
+        let changes = self.load_cells(cells)?;
+        self.runtime_events.send(EventsFromRuntime::DocumentsReloaded {
+            changed_paths,
+            cells_added: changes.added,
+            cells_updated: changes.updated,
+            cells_removed: changes.removed,
+        });
+        Ok(changes)
+    }
+
+    /// Load a bundle written by `ExecutionGraph::export_repro`, so that the failure it captured
+    /// reproduces offline. Instances created afterwards restore the recorded outputs once their
+    /// cells are reloaded, and only replay the recorded model responses.
+    pub fn load_repro(&mut self, path: &Path) -> anyhow::Result<ReproManifest> {
+        let bundle = ReproBundle::read(path)?;
+        info!("Loading a reproduction of {} cells from {:?}", bundle.cells.len(), path);
+        self.loaded_path = Some(path.to_path_buf());
+        self.available_checkpoint = None;
+        self.recovering_checkpoint = Some(bundle.checkpoint);
+        self.default_execution_policy = bundle.default_execution_policy;
+        self.llm_cache = Some(Arc::new(bundle.llm_cache));
+        self.load_cells(bundle.cells)?;
+        Ok(bundle.manifest)
+    }
+
+    /// Receive the events selected by `filter` from instances of this program, including those
+    /// created before subscribing. Subscribers are independent, dropping the receiver unsubscribes.
+    pub fn subscribe(&self, filter: EventFilter) -> Receiver<EventsFromRuntime> {
+        self.runtime_events.subscribe(filter)
     }
 
     pub fn get_instance(&mut self) -> anyhow::Result<ChidoriRuntimeInstance> {
-        let (instanced_env_tx, env_rx) = mpsc::channel();
+        let (instanced_env_tx, env_rx) = tokio::sync::mpsc::unbounded_channel();
         self.instanced_env_tx = Some(instanced_env_tx);
         let mut db = ExecutionGraph::new();
         let execution_event_rx = db.take_execution_event_receiver();
         let state_id = Uuid::nil();
         let playback_state = PlaybackState::Paused;
+        let run_id = Uuid::now_v7();
 
         let mut shared_state = self.shared_state.lock().unwrap();
         shared_state.execution_id_to_evaluation = db.execution_node_id_to_state.clone();
@@ -179,26 +674,172 @@ impl InteractiveChidoriWrapper {
             env_rx,
             db,
             execution_head_state_id: state_id,
-            runtime_event_sender: self.runtime_event_sender.clone(),
+            runtime_events: self.runtime_events.for_run(run_id),
             trace_event_sender: self.trace_event_sender.clone(),
             playback_state,
             shared_state: self.shared_state.clone(),
             rx_execution_states: execution_event_rx,
+            input_resolution_hook: None,
+            auto_play: self.auto_play,
+            scoped_operations: None,
+            max_invocation_depth: DEFAULT_MAX_INVOCATION_DEPTH,
+            max_settle_steps: DEFAULT_MAX_SETTLE_STEPS,
+            redaction: self.redaction.clone(),
+            benchmark_mode: false,
+            default_execution_policy: self.default_execution_policy.clone(),
+            prompt_audit: self.prompt_audit.clone(),
+            llm_cache: self.llm_cache.clone().or_else(|| self.project_llm_cache()),
+            native_functions: self.native_functions.clone(),
+            memory_stores: Default::default(),
+            deno_modules: self.deno_modules.clone(),
+            webhook_servers: Default::default(),
+            llm_proxies: self.llm_proxies.clone(),
+            checkpointer: self.project_checkpointer()?,
+            pending_checkpoint: self.recovering_checkpoint.take(),
+            breakpoints: self.breakpoints.clone(),
+            breakpoint_hit_at: None,
+            run_id,
+            parent_run_id: None,
         })
     }
+
+    /// Create an instance continuing from the execution head of `parent` as a run of its own,
+    /// linked to the run of `parent`. The outputs at the head are restored once its cells are reloaded.
+    pub fn fork_instance(&mut self, parent: &ChidoriRuntimeInstance) -> anyhow::Result<ChidoriRuntimeInstance> {
+        let head = parent.get_state_at_current_execution_head();
+        let mut instance = self.get_instance()?;
+        instance.parent_run_id = Some(parent.run_id());
+        instance.scoped_operations = parent.scoped_operations.clone();
+        if !head.chronology_id.is_nil() {
+            instance.pending_checkpoint = Some(Checkpoint::of_state(&head, Some(parent.run_id())));
+        }
+        Ok(instance)
+    }
+
+    /// Create an instance that only evaluates the named cells and the cells they depend on,
+    /// all other loaded cells are excluded. Cells must be loaded before calling this.
+    pub fn get_instance_for_subgraph(&mut self, roots: &[String]) -> anyhow::Result<ChidoriRuntimeInstance> {
+        let cells: Vec<(OperationId, CellTypes)> = {
+            let shared_state = self.shared_state.lock().unwrap();
+            shared_state.editor_cells.values().map(|holder| (holder.op_id, holder.cell.clone())).collect()
+        };
+
+        // Resolve dependencies between the loaded cells without evaluating them
+        let mut state = ExecutionState::new_with_random_id();
+        for (op_id, cell) in &cells {
+            let op = state.get_operation_from_cell_type(cell)?;
+            state = state.upsert_operation(op, *op_id)?.1;
+        }
+
+        let mut root_ids = vec![];
+        for root in roots {
+            let op_id = cells.iter()
+                .find(|(_, cell)| cell.name().as_ref() == Some(root))
+                .map(|(op_id, _)| *op_id)
+                .ok_or_else(|| anyhow::anyhow!("No cell named {} has been loaded", root))?;
+            root_ids.push(op_id);
+        }
+
+        let mut instance = self.get_instance()?;
+        instance.scoped_operations = Some(state.get_execution_subgraph(&root_ids));
+        Ok(instance)
+    }
 }
 
 #[derive(Clone, Debug)]
 pub enum EventsFromRuntime {
     PlaybackState(PlaybackState),
-    DefinitionGraphUpdated(Vec<(OperationId, OperationId, Vec<DependencyReference>)>),
+    /// Edges of the dependency graph, with the labels attached by `ExecutionGraph::annotate_edge`
+    DefinitionGraphUpdated(Vec<AnnotatedDependencyEdge>),
     ExecutionGraphUpdated(Vec<(ExecutionNodeId, ExecutionNodeId)>),
     ExecutionStateChange(MergedStateHistory),
     EditorCellsUpdated(HashMap<OperationId, CellHolder>),
     StateAtId(ExecutionNodeId, ExecutionState),
+    /// The states requested by `UserInteractionMessage::FetchStatesAt`, in the order they were requested
+    StatesAtIds(Vec<(ExecutionNodeId, ExecutionState)>),
     UpdateExecutionHead(ExecutionNodeId),
     ReceivedChatMessage(String),
     ExecutionStateCellsViewUpdated(Vec<CellHolder>),
+    TransientStateChange(ExecutionNodeId, HashMap<OperationId, RkyvSerializedValue>),
+    WebhookReceived(OperationId, Option<String>),
+    /// Edited cells were not applied, the previous definitions remain in place
+    ReloadRejected(DefinitionValidationReport),
+    /// Edited cells were applied, producing the state that is now the execution head. Reloads
+    /// requested while a step is evaluating are applied once it completes.
+    ReloadApplied(ExecutionNodeId),
+    /// An operation finished evaluating, sent for each operation as it completes within a step
+    OperationCompleted {
+        op_id: OperationId,
+        output: Result<RkyvSerializedValue, ExecutionStateErrors>,
+    },
+    /// The operations whose values were produced, changed or removed since the previous execution
+    /// head, see `ExecutionState::get_changed_ops_since`. Sent in place of `ExecutionStateCellsViewUpdated`
+    /// when the new head has the same cells, and not sent when no value changed.
+    OperationsChanged(ExecutionNodeId, Vec<OperationId>),
+    /// Playback paused before evaluating a cell with a breakpoint, see `UserInteractionMessage::SetBreakpoint`
+    BreakpointHit {
+        op_id: OperationId,
+        name: String,
+    },
+    /// Wall-clock duration of evaluating the step from the given state, only sent in benchmark mode
+    StepTiming(ExecutionNodeId, Duration),
+    /// A reasoning step recorded by an operation while it is evaluated, see `AgentTrace`
+    AgentTraceStep(OperationId, AgentTraceStep),
+    DocumentsReloaded {
+        changed_paths: Vec<PathBuf>,
+        cells_added: usize,
+        cells_updated: usize,
+        cells_removed: usize,
+    },
+    /// The instance was replaced by a fresh one evaluating the loaded cells, discarding the
+    /// states evaluated so far
+    InstanceRestarted,
+}
+
+/// A checkpoint being recovered, see `InteractiveChidoriWrapper::recover_latest_checkpoint`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecoveredCheckpoint {
+    pub head_id: ExecutionNodeId,
+    pub step_count: usize,
+}
+
+/// Counts of the editor cells that were added, modified or removed by loading a program.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CellChanges {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+}
+
+impl CellChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added == 0 && self.updated == 0 && self.removed == 0
+    }
+}
+
+/// Parse the cells of every program file in a directory. This does not touch any loaded state,
+/// so it can be done without holding a lock on the wrapper.
+pub fn parse_md_directory(path: &Path, default_language: Option<&SupportedLanguage>) -> anyhow::Result<Vec<CellTypes>> {
+    Ok(parse_documented_md_directory(path, default_language)?.into_iter().map(|cell| cell.cell).collect())
+}
+
+/// `parse_md_directory`, keeping the description of each cell, see `block_description`
+pub fn parse_documented_md_directory(path: &Path, default_language: Option<&SupportedLanguage>) -> anyhow::Result<Vec<DocumentedCell>> {
+    let files = load_folder(path)?;
+    let mut cells = vec![];
+    for file in files {
+        let file_path = file.filename.as_ref().map(|f| document_path(path, f));
+        for mut block in file.result {
+            if let Some(language) = default_language {
+                block.apply_default_language(language);
+            }
+            if let Some(block) = interpret_documented_code_block(&block, file_path.clone())? {
+                cells.push(block);
+            }
+        }
+    }
+    cells.sort_by(|a, b| a.cell.cmp(&b.cell));
+    Ok(cells)
 }
 
 #[derive(Debug)]
@@ -245,10 +886,265 @@ impl SharedState {
     }
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, PartialEq, Clone)]
+#[archive(check_bytes)]
 pub struct CellHolder {
     pub cell: CellTypes,
     pub op_id: OperationId,
     pub applied_at: Option<ExecutionNodeId>,
-    pub needs_update: bool
-}
\ No newline at end of file
+    pub needs_update: bool,
+    /// The prose documenting the cell, shown alongside it and given to models using it as a tool
+    #[serde(default)]
+    pub description: Option<String>,
+    /// The registry the cell was loaded from, None for cells of the loaded documents. See
+    /// `load_cells_from_registry`
+    #[serde(default)]
+    pub registry_url: Option<String>,
+}
+
+impl CellHolder {
+    /// A compact text form of the cell, for sharing it with other users
+    pub fn serialize_to_base64(&self) -> String {
+        let bytes = rkyv::to_bytes::<_, 4096>(self).expect("Failed to serialize cell");
+        base64::engine::general_purpose::STANDARD.encode(&bytes)
+    }
+
+    /// Read back a cell shared with `serialize_to_base64`. The text may come from anywhere, so the
+    /// archive is validated before it is read.
+    pub fn deserialize_from_base64(s: &str) -> anyhow::Result<CellHolder> {
+        let decoded = base64::engine::general_purpose::STANDARD.decode(s.trim())?;
+        let mut bytes = rkyv::AlignedVec::new();
+        bytes.extend_from_slice(&decoded);
+        let archived = rkyv::check_archived_root::<CellHolder>(&bytes)
+            .map_err(|e| anyhow::anyhow!("The text is not a shared cell: {}", e))?;
+        Ok(archived.deserialize(&mut rkyv::Infallible)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::cells::{CodeCell, LLMPromptCell, SupportedModelProviders, TemplateCell, TextRange};
+    use crate::sdk::registry::{cell_digest, RegistryManifest};
+
+    fn holder(cell: CellTypes) -> CellHolder {
+        CellHolder { cell, op_id: Uuid::now_v7(), applied_at: Some(Uuid::now_v7()), needs_update: true, description: None, registry_url: None }
+    }
+
+    #[test]
+    fn test_cell_holder_base64_round_trip() -> anyhow::Result<()> {
+        let cells = [
+            CellTypes::Code(CodeCell {
+                backing_file_reference: None,
+                name: Some("add".to_string()),
+                language: SupportedLanguage::PyO3,
+                source_code: "def add(x, y):\n    return x + y".to_string(),
+                function_invocation: None,
+                inspect_globals: false,
+                redact_output: false,
+                policy: ExecutionPolicy { timeout_ms: Some(250), ..Default::default() },
+                oom_limit_bytes: None,
+                always_run: false,
+                generate_types: false,
+                package_json: None,
+            }, TextRange { start: 3, end: 40 }),
+            CellTypes::Prompt(LLMPromptCell::Chat {
+                backing_file_reference: None,
+                is_function_invocation: false,
+                configuration: Default::default(),
+                name: Some("greet".to_string()),
+                provider: SupportedModelProviders::OpenAI,
+                complete_body: "Say hello to {{name}}".to_string(),
+                req: "Say hello to {{name}}".to_string(),
+            }, TextRange::default()),
+            CellTypes::Template(TemplateCell {
+                backing_file_reference: None,
+                name: Some("layout".to_string()),
+                body: "<p>{{body}}</p>".to_string(),
+                engine: Default::default(),
+            }, TextRange::default()),
+        ];
+        for cell in cells {
+            let holder = holder(cell);
+            let encoded = holder.serialize_to_base64();
+            assert_eq!(CellHolder::deserialize_from_base64(&encoded)?, holder);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_execution_cost_estimate() {
+        let chidori = InteractiveChidoriWrapper::new();
+        assert_eq!(chidori.get_execution_cost_estimate(), 0.0);
+        let template = holder(CellTypes::Template(TemplateCell {
+            backing_file_reference: None,
+            name: Some("layout".to_string()),
+            body: "<p>{{body}}</p>".to_string(),
+            engine: Default::default(),
+        }, TextRange::default()));
+        let prompt = holder(CellTypes::Prompt(LLMPromptCell::Chat {
+            backing_file_reference: None,
+            is_function_invocation: false,
+            configuration: Default::default(),
+            name: Some("greet".to_string()),
+            provider: SupportedModelProviders::OpenAI,
+            complete_body: "Say hello to {{name}}".to_string(),
+            req: "Say hello to {{name}}".to_string(),
+        }, TextRange::default()));
+        chidori.shared_state.lock().unwrap().editor_cells.insert(template.op_id, template);
+        assert_eq!(chidori.get_execution_cost_estimate(), 0.0);
+        chidori.shared_state.lock().unwrap().editor_cells.insert(prompt.op_id, prompt);
+        assert!(chidori.get_execution_cost_estimate() > 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_load_cells_from_registry() -> anyhow::Result<()> {
+        let registry_cells = [
+            holder(CellTypes::Template(TemplateCell {
+                backing_file_reference: None,
+                name: Some("summary_layout".to_string()),
+                body: "<p>{{summary}}</p>".to_string(),
+                engine: Default::default(),
+            }, TextRange::default())),
+            holder(CellTypes::Prompt(LLMPromptCell::Chat {
+                backing_file_reference: None,
+                is_function_invocation: false,
+                configuration: Default::default(),
+                name: Some("summarize".to_string()),
+                provider: SupportedModelProviders::OpenAI,
+                complete_body: "Summarize {{text}}".to_string(),
+                req: "Summarize {{text}}".to_string(),
+            }, TextRange::default())),
+        ];
+        let encoded: Vec<String> = registry_cells.iter().map(|cell| cell.serialize_to_base64()).collect();
+        let manifest = serde_json::to_string(&RegistryManifest { cells: encoded.clone() })?;
+        let app = axum::Router::new().route("/cells/text-summarizer", axum::routing::get(move || {
+            let manifest = manifest.clone();
+            async move { manifest }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let registry_url = format!("http://{}/cells/text-summarizer", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let pinned = BTreeMap::from([
+            ("summary_layout".to_string(), cell_digest(&encoded[0])?),
+            ("summarize".to_string(), cell_digest(&encoded[1])?),
+        ]);
+        let document = |x: i32| -> anyhow::Result<String> {
+            let frontmatter = serde_yaml::to_string(&serde_json::json!({"registry": [{"url": registry_url, "cells": pinned}]}))?;
+            Ok(format!("---\n{}---\n```python (numbers)\nx = {}\n```\n", frontmatter, x))
+        };
+        let mut chidori = InteractiveChidoriWrapper::new();
+        chidori.load_md_string(&document(1)?)?;
+        assert_eq!(chidori.registry_imports.len(), 1);
+        let registry_urls = |chidori: &InteractiveChidoriWrapper| {
+            let mut cells: Vec<(Option<String>, Option<String>)> = chidori.shared_state.lock().unwrap().editor_cells.values()
+                .map(|holder| (holder.cell.name().clone(), holder.registry_url.clone()))
+                .collect();
+            cells.sort();
+            cells
+        };
+
+        // Fetched cells are held until they are accepted
+        assert_eq!(chidori.fetch_registry_imports().await?, 2);
+        assert_eq!(chidori.pending_registry_cells.len(), 2);
+        assert_eq!(registry_urls(&chidori), vec![(Some("numbers".to_string()), None)]);
+        assert_eq!(chidori.accept_registry_cells()?, 2);
+        assert!(chidori.pending_registry_cells.is_empty());
+        let expected = vec![
+            (Some("numbers".to_string()), None),
+            (Some("summarize".to_string()), Some(registry_url.clone())),
+            (Some("summary_layout".to_string()), Some(registry_url.clone())),
+        ];
+        assert_eq!(registry_urls(&chidori), expected);
+
+        // Accepted cells survive reloading the documents, and accepting them again replaces them
+        chidori.load_md_string(&document(2)?)?;
+        chidori.fetch_registry_imports().await?;
+        chidori.accept_registry_cells()?;
+        assert_eq!(registry_urls(&chidori), expected);
+
+        // Rejected cells are never loaded
+        chidori.fetch_registry_imports().await?;
+        chidori.reject_registry_cells();
+        assert!(chidori.pending_registry_cells.is_empty());
+        assert_eq!(chidori.accept_registry_cells()?, 0);
+
+        // Only pinned cells are taken, and only when they match their digest
+        let import = |cells: BTreeMap<String, String>| RegistryImport { url: registry_url.clone(), cells };
+        let only_layout = import(BTreeMap::from([("summary_layout".to_string(), pinned["summary_layout"].clone())]));
+        assert_eq!(chidori.stage_cells_from_registry(&only_layout).await?, 1);
+        chidori.reject_registry_cells();
+        let tampered = import(BTreeMap::from([("summarize".to_string(), pinned["summary_layout"].clone())]));
+        assert!(chidori.stage_cells_from_registry(&tampered).await.is_err());
+        let missing = import(BTreeMap::from([("translate".to_string(), pinned["summarize"].clone())]));
+        assert!(chidori.stage_cells_from_registry(&missing).await.is_err());
+        assert!(chidori.stage_cells_from_registry(&RegistryImport { url: format!("{}/missing", registry_url), cells: pinned.clone() }).await.is_err());
+        assert!(chidori.pending_registry_cells.is_empty());
+
+        // Loading from a registry url takes every listed cell without review
+        let mut chidori = InteractiveChidoriWrapper::new();
+        chidori.load_md_string("```python (numbers)\nx = 1\n```\n")?;
+        chidori.load_cells_from_registry(&registry_url).await?;
+        assert!(chidori.pending_registry_cells.is_empty());
+        assert_eq!(registry_urls(&chidori), expected);
+        chidori.load_cells_from_registry(&registry_url).await?;
+        assert_eq!(registry_urls(&chidori), expected);
+        assert!(chidori.load_cells_from_registry(&format!("{}/missing", registry_url)).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_invalid_base64_cell_fails() {
+        assert!(CellHolder::deserialize_from_base64("not base64!").is_err());
+        assert!(CellHolder::deserialize_from_base64(&base64::engine::general_purpose::STANDARD.encode(b"garbage")).is_err());
+    }
+
+    #[test]
+    fn test_breakpoints_are_kept_for_new_instances() -> anyhow::Result<()> {
+        let mut chidori = InteractiveChidoriWrapper::new();
+        let mut instance = chidori.get_instance()?;
+        chidori.set_breakpoint("double", true)?;
+        assert!(matches!(instance.env_rx.try_recv(), Ok(UserInteractionMessage::SetBreakpoint(name)) if name == "double"));
+        assert!(chidori.get_instance()?.breakpoints.contains("double"));
+
+        chidori.set_breakpoint("double", false)?;
+        assert!(chidori.get_instance()?.breakpoints.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_llm_cache_is_opt_in() -> anyhow::Result<()> {
+        let directory = std::env::temp_dir().join(format!("chidori-llm-cache-{}", Uuid::now_v7()));
+        std::fs::create_dir_all(&directory)?;
+        std::fs::write(directory.join("main.md"), "```python (numbers)\nx = 1\n```\n")?;
+        let mut chidori = InteractiveChidoriWrapper::new();
+        chidori.load_md_directory(&directory)?;
+        assert!(chidori.get_instance()?.llm_cache.is_none());
+
+        chidori.set_project_llm_cache(true);
+        let cache = chidori.get_instance()?.llm_cache.expect("Expected the cache of the loaded directory");
+        assert_eq!(cache.directory(), crate::library::std::ai::llm::cache::cache_directory(&directory).as_path());
+        std::fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_forked_instance_records_parent_run() -> anyhow::Result<()> {
+        let mut chidori = InteractiveChidoriWrapper::new();
+        chidori.load_md_string("```python (numbers)\nx = 1\n```\n")?;
+        let mut parent = chidori.get_instance()?;
+        parent.reload_cells().await?;
+        parent.step().await?;
+
+        let fork = chidori.fork_instance(&parent)?;
+        assert_ne!(fork.run_id(), parent.run_id());
+        assert_eq!(fork.parent_run_id(), Some(parent.run_id()));
+        assert_eq!(parent.parent_run_id(), None);
+        let checkpoint = fork.pending_checkpoint.as_ref().unwrap();
+        assert_eq!(checkpoint.head_id, parent.execution_head_state_id);
+        assert_eq!(checkpoint.run_id, Some(parent.run_id()));
+        Ok(())
+    }
+}
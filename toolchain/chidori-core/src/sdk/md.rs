@@ -5,7 +5,8 @@ use std::collections::HashMap;
 use std::path::Path;
 use serde_derive::Serialize;
 use thiserror::Error;
-use crate::cells::{BackingFileReference, CellTypes, CodeCell, LLMCodeGenCell, LLMEmbeddingCell, LLMPromptCell, MemoryCell, SupportedLanguage, SupportedMemoryProviders, SupportedModelProviders, TemplateCell, TextRange, WebserviceCell};
+use crate::cells::{BackingFileReference, CellTypes, CodeCell, ExecutionPolicy, OutputOverflow, LLMCodeGenCell, LLMEmbeddingCell, LLMPromptCell, LLMPromptCellChatConfiguration, MemoryCell, ChunkCell, ChunkStrategy, SupportedLanguage, SupportedMemoryProviders, SupportedModelProviders, TemplateCell, TemplateEngine, TextRange, McpCell, FileCell, ExtractCell, WebhookCell, WebserviceCell};
+use crate::cells::web_cell::RouteDiagnostic;
 
 #[derive(PartialEq, Serialize, Debug)]
 pub struct MarkdownCodeBlock {
@@ -13,6 +14,23 @@ pub struct MarkdownCodeBlock {
     pub name: Option<String>,
     pub body: String,
     pub range: TextRange,
+    /// Offset of `body` within the document
+    #[serde(skip)]
+    pub body_start: usize,
+    /// The paragraph of prose directly preceding the block, which usually documents it
+    pub description: Option<String>,
+}
+
+impl MarkdownCodeBlock {
+    /// Bare fences are interpreted as code in the given language, tagged blocks are unchanged.
+    pub fn apply_default_language(&mut self, language: &SupportedLanguage) {
+        if self.tag.is_empty() {
+            self.tag = match language {
+                SupportedLanguage::PyO3 => "python",
+                SupportedLanguage::Deno => "javascript",
+            }.to_string();
+        }
+    }
 }
 
 enum CodeResource {
@@ -23,27 +41,47 @@ enum CodeResource {
 
 #[derive(Debug)]
 pub struct ParsedFile {
-    filename: Option<Box<std::path::PathBuf>>,
-    code: Option<String>,
+    pub(crate) filename: Option<Box<std::path::PathBuf>>,
+    pub(crate) code: Option<String>,
     num_lines: usize,
     pub(crate) result: Vec<MarkdownCodeBlock>,
 }
 
+/// The last paragraph of the prose before a block, headings do not describe the block
+fn preceding_paragraph(prose: &str) -> Option<String> {
+    let lines: Vec<&str> = prose.lines().map(|line| line.trim()).collect();
+    let end = lines.iter().rposition(|line| !line.is_empty())?;
+    let start = lines[..end].iter().rposition(|line| line.is_empty()).map_or(0, |i| i + 1);
+    let paragraph = &lines[start..=end];
+    if paragraph[0].starts_with('#') {
+        return None;
+    }
+    Some(paragraph.join(" "))
+}
+
 pub(crate) fn extract_code_blocks(body: &str) -> Vec<MarkdownCodeBlock> {
     let mut code_blocks = Vec::new();
     let mut start = 0;
+    let mut prose_start = 0;
 
     // Iterate over each occurrence of backticks
     while let Some(end) = body[start..].find("```") {
+        let description = preceding_paragraph(&body[prose_start..start + end]);
         start += end + 3; // Move start to the character after the closing ```
 
         if let Some(end_of_code) = body[start..].find("```") {
-            let code = &body[start..start + end_of_code].trim();
+            let raw_code = &body[start..start + end_of_code];
+            let code = &raw_code.trim();
+
+            // A fence with nothing following the backticks has no tag, its first line is code
+            let is_bare_fence = raw_code.lines().next().map_or(true, |line| line.trim().is_empty());
 
             // Extract first line to separate tag and name
             let mut lines = code.lines();
-            let first_line = lines.next().unwrap_or_default();
+            let first_line = if is_bare_fence { "" } else { lines.next().unwrap_or_default() };
             let rest: String = lines.collect::<Vec<&str>>().join("\n");
+            let leading = raw_code.len() - raw_code.trim_start().len();
+            let body_start = start + leading + if is_bare_fence { 0 } else { (first_line.len() + 1).min(code.len()) };
 
             let tag_and_name: Vec<&str> = first_line.split_whitespace().collect();
             let tag = tag_and_name.get(0).cloned().unwrap_or_default().to_string();
@@ -58,9 +96,12 @@ pub(crate) fn extract_code_blocks(body: &str) -> Vec<MarkdownCodeBlock> {
                     start,
                     end: start + end_of_code
                 },
+                body_start,
+                description,
             });
 
             start += end_of_code + 3; // Move start to the character after the closing ```
+            prose_start = start;
         } else {
             break; // No closing backticks found, exit the loop
         }
@@ -104,7 +145,7 @@ pub fn load_folder(path: &Path) -> anyhow::Result<Vec<ParsedFile>> {
 
         if metadata.is_file() {
             if let Some(extension)  = path.extension().and_then(|s| s.to_str()) {
-                match extension {
+                match extension.to_ascii_lowercase().as_str() {
                     "md" | "py" | "js" | "ts" => {
                         let parsed_file = parse_markdown_file(&path);
                         res.push(parsed_file);
@@ -117,6 +158,18 @@ pub fn load_folder(path: &Path) -> anyhow::Result<Vec<ParsedFile>> {
     Ok(res)
 }
 
+/// Path of a document relative to the directory it was loaded from, joined with `/` on every
+/// platform so that the references recorded on cells are portable.
+pub fn document_path(root: &Path, file: &Path) -> String {
+    match file.strip_prefix(root) {
+        Ok(relative) => relative.components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+        Err(_) => file.to_string_lossy().to_string(),
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum InterpretError {
     #[error("Failed to split frontmatter: {0}")]
@@ -125,8 +178,107 @@ pub enum InterpretError {
     YamlDeserializeError(#[from] serde_yaml::Error),
     #[error("Failed to parse port number")]
     PortParseError,
+    #[error("Invalid routes: {}", .0.iter().map(|d| d.to_string()).collect::<Vec<_>>().join("; "))]
+    RouteTableError(Vec<RouteDiagnostic>),
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Default, PartialEq)]
+#[serde(default)]
+struct CodeCellConfiguration {
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    inspect_globals: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    redact_output: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retries: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_output_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    overflow: Option<OutputOverflow>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    oom_limit_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    always_run: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    generate_types: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    package_json: Option<String>,
+}
+
+impl From<&CodeCell> for CodeCellConfiguration {
+    fn from(cell: &CodeCell) -> Self {
+        Self {
+            inspect_globals: cell.inspect_globals,
+            redact_output: cell.redact_output,
+            timeout_ms: cell.policy.timeout_ms,
+            retries: cell.policy.retries,
+            max_output_bytes: cell.policy.max_output_bytes,
+            overflow: cell.policy.overflow.clone(),
+            oom_limit_bytes: cell.oom_limit_bytes,
+            always_run: cell.always_run,
+            generate_types: cell.generate_types,
+            package_json: cell.package_json.clone(),
+        }
+    }
+}
+
+/// A cell along with the prose documenting it
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocumentedCell {
+    pub cell: CellTypes,
+    pub description: Option<String>,
 }
 
+impl From<CellTypes> for DocumentedCell {
+    fn from(cell: CellTypes) -> Self {
+        DocumentedCell { cell, description: None }
+    }
+}
+
+/// The description of a block, set with a `description:` key in its frontmatter or otherwise
+/// given by the paragraph preceding it
+pub fn block_description(block: &MarkdownCodeBlock) -> Option<String> {
+    let explicit = Some(&block.body)
+        .filter(|body| body.trim_start().starts_with("---"))
+        .and_then(|body| chidori_prompt_format::templating::templates::split_frontmatter(body).ok())
+        .and_then(|(frontmatter, _)| serde_yaml::from_str::<serde_yaml::Value>(&frontmatter).ok())
+        .and_then(|frontmatter| frontmatter.get("description")?.as_str().map(|d| d.trim().to_string()));
+    explicit.or_else(|| block.description.clone())
+}
+
+/// `interpret_markdown_code_block`, keeping the description of the block with its cell
+pub fn interpret_documented_code_block(block: &MarkdownCodeBlock, file_path: Option<String>) -> Result<Option<DocumentedCell>, InterpretError> {
+    Ok(interpret_markdown_code_block(block, file_path)?.map(|cell| DocumentedCell {
+        cell,
+        description: block_description(block),
+    }))
+}
+
+/// Moves ranges within the `routes` of a webhook block to where they are in the document, by
+/// finding each line of the routes in turn within the block, as YAML may have indented them
+fn route_offsets_in_document(block: &MarkdownCodeBlock, routes: &str) -> impl Fn(&mut TextRange) {
+    let mut offsets = HashMap::new();
+    let (mut offset, mut cursor) = (0, block.body.find("routes:").unwrap_or(0));
+    for line in routes.split_inclusive('\n') {
+        let start = offset + (line.len() - line.trim_start().len());
+        offset += line.len();
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if let Some(found) = block.body[cursor..].find(trimmed) {
+            offsets.insert(start, block.body_start + cursor + found);
+            cursor += found + trimmed.len();
+        }
+    }
+    move |range: &mut TextRange| {
+        if let Some(start) = offsets.get(&range.start) {
+            *range = TextRange { start: *start, end: start + range.end - range.start };
+        }
+    }
+}
 
 pub fn interpret_markdown_code_block(block: &MarkdownCodeBlock, file_path: Option<String>) -> Result<Option<CellTypes>, InterpretError> {
     let whole_body = block.body.clone();
@@ -143,23 +295,51 @@ pub fn interpret_markdown_code_block(block: &MarkdownCodeBlock, file_path: Optio
                 "javascript" | "js" | "typescript" | "ts" => SupportedLanguage::Deno,
                 _ => unreachable!(), // Given the outer match, this branch should never be reached
             };
+            // Code cells may open with a frontmatter block configuring the cell
+            let (configuration, source_code) = if block.body.trim_start().starts_with("---") {
+                let configuration: CodeCellConfiguration = if frontmatter.trim().is_empty() {
+                    Default::default()
+                } else {
+                    serde_yaml::from_str(&frontmatter)?
+                };
+                (configuration, body)
+            } else {
+                (CodeCellConfiguration::default(), block.body.clone())
+            };
             Some(CellTypes::Code(CodeCell {
                 backing_file_reference,
                 name: block.name.clone(),
                 language,
-                source_code: block.body.clone(),
+                source_code,
                 function_invocation: None,
+                inspect_globals: configuration.inspect_globals,
+                redact_output: configuration.redact_output,
+                policy: ExecutionPolicy {
+                    timeout_ms: configuration.timeout_ms,
+                    retries: configuration.retries,
+                    max_output_bytes: configuration.max_output_bytes,
+                    overflow: configuration.overflow,
+                },
+                oom_limit_bytes: configuration.oom_limit_bytes,
+                always_run: configuration.always_run,
+                generate_types: configuration.generate_types,
+                package_json: configuration.package_json,
+            }, block.range.clone()))
+        },
+        "prompt" => {
+            let configuration: LLMPromptCellChatConfiguration = serde_yaml::from_str(&frontmatter)?;
+            // The provider is selected with a `provider:` key in the frontmatter, defaulting to OpenAI
+            let provider = configuration.provider.clone().unwrap_or_default();
+            Some(CellTypes::Prompt(LLMPromptCell::Chat {
+                backing_file_reference,
+                is_function_invocation: false,
+                configuration,
+                name: block.name.clone(),
+                provider,
+                complete_body: whole_body,
+                req: body,
             }, block.range.clone()))
         },
-        "prompt" => Some(CellTypes::Prompt(LLMPromptCell::Chat {
-            backing_file_reference,
-            is_function_invocation: false,
-            configuration: serde_yaml::from_str(&frontmatter)?,
-            name: block.name.clone(),
-            provider: SupportedModelProviders::OpenAI,
-            complete_body: whole_body,
-            req: body,
-        }, block.range.clone())),
         "codegen" => Some(CellTypes::CodeGen(LLMCodeGenCell {
             backing_file_reference,
             function_invocation: false,
@@ -169,16 +349,182 @@ pub fn interpret_markdown_code_block(block: &MarkdownCodeBlock, file_path: Optio
             provider: SupportedModelProviders::OpenAI,
             req: body,
         }, block.range.clone())),
-        "html" | "template" => Some(CellTypes::Template(TemplateCell {
-            backing_file_reference,
-            name: block.name.clone(),
-            body: block.body.clone(),
-        }, block.range.clone())),
+        "html" | "template" => {
+            // Frontmatter may choose the engine, templates without any are Handlebars
+            let (engine, body) = if frontmatter.trim().is_empty() {
+                (TemplateEngine::default(), block.body.clone())
+            } else {
+                let configuration: TemplateBlockConfiguration = serde_yaml::from_str(&frontmatter)?;
+                (configuration.engine, body)
+            };
+            Some(CellTypes::Template(TemplateCell {
+                backing_file_reference,
+                name: block.name.clone(),
+                body,
+                engine,
+            }, block.range.clone()))
+        },
+        "webhook" => {
+            let configuration = if frontmatter.trim().is_empty() { &body } else { &frontmatter };
+            let mut cell: WebhookCell = serde_yaml::from_str(configuration)?;
+            cell.name = block.name.clone();
+            let relocate = route_offsets_in_document(block, &cell.routes);
+            if let Err(mut diagnostics) = cell.parse_routes() {
+                diagnostics.iter_mut().for_each(|d| relocate(&mut d.range));
+                return Err(InterpretError::RouteTableError(diagnostics));
+            }
+            cell.route_table.routes.iter_mut().for_each(|route| relocate(&mut route.range));
+            Some(CellTypes::Webhook(cell, block.range.clone()))
+        },
+        "mcp" => {
+            let configuration = if frontmatter.trim().is_empty() { &body } else { &frontmatter };
+            let mut cell: McpCell = serde_yaml::from_str(configuration)?;
+            cell.name = block.name.clone();
+            Some(CellTypes::Mcp(cell, block.range.clone()))
+        },
+        "file" => {
+            let configuration = if frontmatter.trim().is_empty() { &body } else { &frontmatter };
+            let mut cell: FileCell = serde_yaml::from_str(configuration)?;
+            cell.name = block.name.clone();
+            Some(CellTypes::File(cell, block.range.clone()))
+        },
+        "extract" => {
+            let configuration = if frontmatter.trim().is_empty() { &body } else { &frontmatter };
+            let mut cell: ExtractCell = serde_yaml::from_str(configuration)?;
+            cell.name = block.name.clone();
+            Some(CellTypes::Extract(cell, block.range.clone()))
+        },
+        "memory" => {
+            let configuration = if frontmatter.trim().is_empty() { &body } else { &frontmatter };
+            let mut cell: MemoryCell = serde_yaml::from_str(configuration)?;
+            // The store may be named by the block or within its configuration
+            cell.name = block.name.clone().or(cell.name);
+            Some(CellTypes::Memory(cell, block.range.clone()))
+        },
+        "chunk" => {
+            let configuration = if frontmatter.trim().is_empty() { &body } else { &frontmatter };
+            let mut cell: ChunkCell = serde_yaml::from_str(configuration)?;
+            cell.name = block.name.clone();
+            Some(CellTypes::Chunk(cell, block.range.clone()))
+        },
         _ => None,
     })
 }
 
 
+/// The frontmatter of a template block
+#[derive(serde::Serialize, serde::Deserialize)]
+struct TemplateBlockConfiguration {
+    #[serde(default)]
+    engine: TemplateEngine,
+}
+
+fn fenced_block(tag: &str, name: &Option<String>, body: &str) -> String {
+    match name {
+        Some(name) => format!("```{} ({})\n{}\n```\n", tag, name, body),
+        None => format!("```{}\n{}\n```\n", tag, body),
+    }
+}
+
+fn with_frontmatter(configuration: &impl serde::Serialize, body: &str) -> anyhow::Result<String> {
+    Ok(format!("---\n{}---\n{}", serde_yaml::to_string(configuration)?, body))
+}
+
+/// The body a prompt was written with when it still describes the cell, otherwise one generated
+/// from its configuration, so that edits made in code are kept without losing the author's formatting
+fn prompt_body<C: serde::Serialize + serde::de::DeserializeOwned + PartialEq>(configuration: &C, req: &str, complete_body: &str) -> anyhow::Result<String> {
+    let unchanged = chidori_prompt_format::templating::templates::split_frontmatter(complete_body)
+        .ok()
+        .filter(|(_, body)| body == req)
+        .and_then(|(frontmatter, _)| serde_yaml::from_str::<C>(&frontmatter).ok())
+        .map_or(false, |parsed| &parsed == configuration);
+    if unchanged {
+        Ok(complete_body.to_string())
+    } else {
+        with_frontmatter(configuration, req)
+    }
+}
+
+/// The fenced block that `interpret_markdown_code_block` reads back as this cell. Completion
+/// prompts cannot be written in markdown and have none.
+pub fn cell_to_markdown(cell: &CellTypes) -> anyhow::Result<Option<String>> {
+    Ok(Some(match cell {
+        CellTypes::Code(cell, _) => {
+            let tag = match cell.language {
+                SupportedLanguage::PyO3 => "python",
+                SupportedLanguage::Deno => "javascript",
+            };
+            let configuration = CodeCellConfiguration::from(cell);
+            let body = if configuration == CodeCellConfiguration::default() {
+                cell.source_code.clone()
+            } else {
+                with_frontmatter(&configuration, &cell.source_code)?
+            };
+            fenced_block(tag, &cell.name, &body)
+        }
+        CellTypes::Prompt(LLMPromptCell::Chat { configuration, name, provider, req, complete_body, .. }, _) => {
+            let mut configuration = configuration.clone();
+            if configuration.provider.is_none() && *provider != SupportedModelProviders::default() {
+                configuration.provider = Some(provider.clone());
+            }
+            fenced_block("prompt", name, &prompt_body(&configuration, req, complete_body)?)
+        }
+        CellTypes::Prompt(LLMPromptCell::Completion { .. }, _) => return Ok(None),
+        CellTypes::CodeGen(cell, _) => {
+            fenced_block("codegen", &cell.name, &prompt_body(&cell.configuration, &cell.req, &cell.complete_body)?)
+        }
+        CellTypes::Template(cell, _) => match cell.engine {
+            TemplateEngine::Handlebars => fenced_block("template", &cell.name, &cell.body),
+            engine => fenced_block("template", &cell.name, &with_frontmatter(&TemplateBlockConfiguration { engine }, &cell.body)?),
+        },
+        CellTypes::Webhook(cell, _) => fenced_block("webhook", &cell.name, &yaml_configuration(cell)?),
+        CellTypes::Mcp(cell, _) => fenced_block("mcp", &cell.name, &yaml_configuration(cell)?),
+        CellTypes::File(cell, _) => fenced_block("file", &cell.name, &yaml_configuration(cell)?),
+        CellTypes::Extract(cell, _) => fenced_block("extract", &cell.name, &yaml_configuration(cell)?),
+        CellTypes::Memory(cell, _) => fenced_block("memory", &cell.name, &yaml_configuration(cell)?),
+        CellTypes::Chunk(cell, _) => fenced_block("chunk", &cell.name, &yaml_configuration(cell)?),
+    }))
+}
+
+/// The yaml body of a cell configured entirely through yaml, leaving out its name, which is given
+/// by the block, and anything left unset
+fn yaml_configuration(cell: &impl serde::Serialize) -> anyhow::Result<String> {
+    let serde_yaml::Value::Mapping(mut configuration) = serde_yaml::to_value(cell)? else {
+        anyhow::bail!("The cell does not serialize to a mapping")
+    };
+    configuration.remove("name");
+    configuration.remove("function_invocation");
+    configuration.retain(|_, value| !value.is_null() && value.as_sequence().map_or(true, |s| !s.is_empty()));
+    Ok(serde_yaml::to_string(&configuration)?.trim_end().to_string())
+}
+
+/// A markdown document of the given cells, in order, which loads back as the same cells
+pub fn cells_to_markdown<'a>(cells: impl IntoIterator<Item = &'a CellTypes>) -> anyhow::Result<String> {
+    let mut blocks = vec![];
+    for cell in cells {
+        blocks.extend(cell_to_markdown(cell)?);
+    }
+    Ok(blocks.join("\n"))
+}
+
+/// `cell_to_markdown`, preceded by the paragraph describing the cell so that it loads back with
+/// the same description
+pub fn documented_cell_to_markdown(cell: &CellTypes, description: Option<&str>) -> anyhow::Result<Option<String>> {
+    Ok(cell_to_markdown(cell)?.map(|block| match description {
+        Some(description) => format!("{}\n\n{}", description, block),
+        None => block,
+    }))
+}
+
+/// `cells_to_markdown` for cells with their descriptions
+pub fn documented_cells_to_markdown<'a>(cells: impl IntoIterator<Item = (&'a CellTypes, Option<&'a str>)>) -> anyhow::Result<String> {
+    let mut blocks = vec![];
+    for (cell, description) in cells {
+        blocks.extend(documented_cell_to_markdown(cell, description)?);
+    }
+    Ok(blocks.join("\n"))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -285,6 +631,21 @@ mod test {
         });
     }
 
+    #[test]
+    fn test_document_path_is_relative_to_root() {
+        let root = Path::new("/projects/my agents");
+        assert_eq!(document_path(root, &root.join("nested").join("cœur.md")), "nested/cœur.md");
+        assert_eq!(document_path(root, Path::new("/elsewhere/core.md")), "/elsewhere/core.md");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_document_path_normalizes_windows_separators() {
+        let root = Path::new(r"C:\Users\ada\my agents");
+        assert_eq!(document_path(root, Path::new(r"C:\Users\ada\my agents\nested\core.md")), "nested/core.md");
+        assert_eq!(document_path(root, Path::new(r"C:/Users/ada/my agents/nested/core.md")), "nested/core.md");
+    }
+
     #[test]
     fn test_extract_markdown() {
         let extracted = extract_code_blocks(indoc! {  r#"
@@ -320,4 +681,478 @@ mod test {
             insta::assert_yaml_snapshot!(extracted);
         });
     }
+
+    #[test]
+    fn test_interpret_webhook_block() {
+        let blocks = extract_code_blocks(indoc! { r#"
+        ```webhook (github_push)
+        port: 3840
+        path: /github
+        secret_env: GITHUB_WEBHOOK_SECRET
+        concurrency: serial
+        max_body_bytes: 1024
+        middleware:
+          - logging
+          - cors:
+              origins: ["https://example.com"]
+        ```
+        "#
+        });
+        let cell = interpret_markdown_code_block(&blocks[0], None).unwrap();
+        let Some(CellTypes::Webhook(cell, _)) = cell else { panic!("Expected a webhook cell") };
+        assert_eq!(cell.name, Some("github_push".to_string()));
+        assert_eq!(cell.host, "127.0.0.1");
+        assert_eq!(cell.port, 3840);
+        assert_eq!(cell.path, "/github");
+        assert_eq!(cell.secret_env, Some("GITHUB_WEBHOOK_SECRET".to_string()));
+        assert_eq!(cell.signature_header, "X-Hub-Signature-256");
+        assert_eq!(cell.signature_algorithm, crate::cells::SignatureAlgorithm::HmacSha256);
+        assert_eq!(cell.concurrency, crate::cells::RequestConcurrency::Serial);
+        assert_eq!(cell.max_body_bytes, 1024);
+        assert_eq!(cell.middleware, vec![
+            crate::cells::WebMiddlewareConfig::Logging,
+            crate::cells::WebMiddlewareConfig::Cors { origins: vec!["https://example.com".to_string()] },
+        ]);
+    }
+
+    #[test]
+    fn test_interpret_webhook_routes() {
+        let document = indoc! { r#"
+        ```webhook (api)
+        port: 3840
+        path: /hook
+        routes: |
+          GET /users/:id get_user
+          POST /add add(a, b)
+        ```
+
+        ```webhook (broken)
+        port: 3841
+        path: /hook
+        routes: |
+          GET /users/:id get_user
+          FETCH /add add(a, b)
+        ```
+        "#
+        };
+        let blocks = extract_code_blocks(document);
+        let Some(CellTypes::Webhook(cell, _)) = interpret_markdown_code_block(&blocks[0], None).unwrap() else { panic!("Expected a webhook cell") };
+        let lines: Vec<&str> = cell.route_table.routes.iter().map(|r| &document[r.range.start..r.range.end]).collect();
+        assert_eq!(lines, vec!["GET /users/:id get_user", "POST /add add(a, b)"]);
+
+        let Err(InterpretError::RouteTableError(diagnostics)) = interpret_markdown_code_block(&blocks[1], None) else { panic!("Expected invalid routes") };
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(&document[diagnostics[0].range.start..diagnostics[0].range.end], "FETCH /add add(a, b)");
+    }
+
+    #[test]
+    fn test_interpret_mcp_block() {
+        let blocks = extract_code_blocks(indoc! { r#"
+        ```mcp (files)
+        command: npx
+        args: ["-y", "@modelcontextprotocol/server-filesystem", "/tmp"]
+        ```
+        "#
+        });
+        let cell = interpret_markdown_code_block(&blocks[0], None).unwrap();
+        let Some(CellTypes::Mcp(cell, _)) = cell else { panic!("Expected an mcp cell") };
+        assert_eq!(cell.name, Some("files".to_string()));
+        assert_eq!(cell.command, Some("npx".to_string()));
+        assert_eq!(cell.args.len(), 3);
+        assert_eq!(cell.url, None);
+
+        let cell = CellTypes::Mcp(cell, TextRange::default());
+        let exported = cell_to_markdown(&cell).unwrap().unwrap();
+        assert!(exported.starts_with("```mcp (files)\ncommand: npx\n"));
+        assert_eq!(interpret_document(&exported), vec![cell]);
+    }
+
+    #[test]
+    fn test_interpret_extract_block() {
+        let blocks = extract_code_blocks(indoc! { r#"
+        ```extract (article)
+        ---
+        source: page
+        format: html
+        markdown: true
+        ---
+        ```
+        "#
+        });
+        let cell = interpret_markdown_code_block(&blocks[0], None).unwrap();
+        let Some(CellTypes::Extract(cell, _)) = cell else { panic!("Expected an extract cell") };
+        assert_eq!(cell.name, Some("article".to_string()));
+        assert_eq!(cell.source, Some("page".to_string()));
+        assert_eq!(cell.format, crate::cells::ExtractFormat::Html);
+        assert!(cell.markdown);
+
+        let cell = CellTypes::Extract(cell, TextRange::default());
+        let exported = cell_to_markdown(&cell).unwrap().unwrap();
+        assert_eq!(interpret_document(&exported), vec![cell]);
+    }
+
+    #[test]
+    fn test_interpret_memory_block() {
+        let blocks = extract_code_blocks(indoc! { r#"
+        ```memory
+        ---
+        name: notes
+        embedding_model: text-embedding-3-large
+        backend: in_memory
+        ---
+        ```
+        "#
+        });
+        let cell = interpret_markdown_code_block(&blocks[0], None).unwrap();
+        assert_eq!(cell, Some(CellTypes::Memory(MemoryCell {
+            name: Some("notes".to_string()),
+            embedding_model: "text-embedding-3-large".to_string(),
+            backend: SupportedMemoryProviders::InMemory,
+        }, blocks[0].range.clone())));
+
+        // The name given by the block takes precedence, the rest of the configuration has defaults
+        let blocks = extract_code_blocks("```memory (facts)\nbackend: in_memory\n```");
+        let cell = interpret_markdown_code_block(&blocks[0], None).unwrap();
+        let Some(CellTypes::Memory(cell, _)) = cell else { panic!("Expected a memory cell") };
+        assert_eq!(cell, MemoryCell { name: Some("facts".to_string()), ..Default::default() });
+
+        let cell = CellTypes::Memory(cell, TextRange::default());
+        let exported = cell_to_markdown(&cell).unwrap().unwrap();
+        assert_eq!(interpret_document(&exported), vec![cell]);
+    }
+
+    #[test]
+    fn test_interpret_chunk_block() {
+        let blocks = extract_code_blocks(indoc! { r#"
+        ```chunk (sections)
+        ---
+        source: article
+        strategy: markdown
+        size: 128
+        overlap: 16
+        ---
+        ```
+        "#
+        });
+        let cell = interpret_markdown_code_block(&blocks[0], None).unwrap();
+        let Some(CellTypes::Chunk(cell, _)) = cell else { panic!("Expected a chunk cell") };
+        assert_eq!(cell, ChunkCell {
+            name: Some("sections".to_string()),
+            source: Some("article".to_string()),
+            strategy: ChunkStrategy::Markdown,
+            size: 128,
+            overlap: 16,
+            function_invocation: None,
+        });
+
+        let cell = CellTypes::Chunk(cell, TextRange::default());
+        let exported = cell_to_markdown(&cell).unwrap().unwrap();
+        assert_eq!(interpret_document(&exported), vec![cell]);
+    }
+
+    #[test]
+    fn test_interpret_template_block_engine() {
+        let blocks = extract_code_blocks(indoc! { r#"
+        ```template (greeting)
+        ---
+        engine: jinja
+        ---
+        Hello {{ name | title }}
+        ```
+        "#
+        });
+        let cell = interpret_markdown_code_block(&blocks[0], None).unwrap();
+        let Some(CellTypes::Template(cell, _)) = cell else { panic!("Expected a template cell") };
+        assert_eq!(cell.engine, TemplateEngine::Jinja);
+        assert_eq!(cell.body.trim(), "Hello {{ name | title }}");
+
+        let cell = CellTypes::Template(cell, TextRange::default());
+        let exported = cell_to_markdown(&cell).unwrap().unwrap();
+        assert_eq!(interpret_document(&exported), vec![cell]);
+
+        let blocks = extract_code_blocks("```template (greeting)
+Hello {{name}}
+```");
+        let cell = interpret_markdown_code_block(&blocks[0], None).unwrap();
+        let Some(CellTypes::Template(cell, _)) = cell else { panic!("Expected a template cell") };
+        assert_eq!(cell.engine, TemplateEngine::Handlebars);
+    }
+
+    fn interpret_document(markdown: &str) -> Vec<CellTypes> {
+        extract_code_blocks(markdown).iter()
+            .filter_map(|block| interpret_markdown_code_block(block, None).unwrap())
+            .map(|cell| match cell {
+                CellTypes::Code(c, _) => CellTypes::Code(c, TextRange::default()),
+                CellTypes::CodeGen(c, _) => CellTypes::CodeGen(c, TextRange::default()),
+                CellTypes::Prompt(c, _) => CellTypes::Prompt(c, TextRange::default()),
+                CellTypes::Template(c, _) => CellTypes::Template(c, TextRange::default()),
+                CellTypes::Webhook(c, _) => CellTypes::Webhook(c, TextRange::default()),
+                CellTypes::Mcp(c, _) => CellTypes::Mcp(c, TextRange::default()),
+                CellTypes::File(c, _) => CellTypes::File(c, TextRange::default()),
+                CellTypes::Extract(c, _) => CellTypes::Extract(c, TextRange::default()),
+                CellTypes::Memory(c, _) => CellTypes::Memory(c, TextRange::default()),
+                CellTypes::Chunk(c, _) => CellTypes::Chunk(c, TextRange::default()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_cells_to_markdown_round_trip() {
+        let markdown = indoc! { r#"
+        ```python (profile)
+        ---
+        redact_output: true
+        timeout_ms: 250
+        ---
+        ssn = "123-45-6789"
+        ```
+
+        ```javascript
+        const y = 2;
+        ```
+
+        ```prompt (greet)
+        ---
+        model: gpt-4o
+        # Comments in frontmatter are kept
+        fn: greet
+        ---
+        Say hello to {{name}}
+        ```
+
+        ```template (layout)
+        <p>{{body}}</p>
+        ```
+
+        ```webhook (github_push)
+        port: 3840
+        path: /github
+        concurrency: serial
+        ```
+        "#
+        };
+        let cells = interpret_document(markdown);
+        assert_eq!(cells.len(), 5);
+        let exported = cells_to_markdown(&cells).unwrap();
+        assert!(exported.contains("# Comments in frontmatter are kept"));
+        assert_eq!(interpret_document(&exported), cells);
+    }
+
+    #[test]
+    fn test_cell_to_markdown_after_edit() {
+        let mut cells = interpret_document(indoc! { r#"
+        ```prompt (greet)
+        ---
+        model: gpt-4o
+        ---
+        Say hello to {{name}}
+        ```
+        "#
+        });
+        let CellTypes::Prompt(LLMPromptCell::Chat { configuration, req, .. }, _) = &mut cells[0] else { panic!("Expected a prompt cell") };
+        configuration.model = Some("gpt-4o-mini".to_string());
+        *req = "Say goodbye to {{name}}".to_string();
+        let cells = cells.into_iter().chain([CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: Some("limits".to_string()),
+            language: SupportedLanguage::PyO3,
+            source_code: "x = 1".to_string(),
+            function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: ExecutionPolicy { retries: Some(2), ..Default::default() },
+            oom_limit_bytes: None,
+            always_run: true,
+            generate_types: false,
+            package_json: None,
+        }, TextRange::default())]).collect::<Vec<_>>();
+
+        let reloaded = interpret_document(&cells_to_markdown(&cells).unwrap());
+        let CellTypes::Prompt(LLMPromptCell::Chat { configuration, req, .. }, _) = &reloaded[0] else { panic!("Expected a prompt cell") };
+        assert_eq!(configuration.model, Some("gpt-4o-mini".to_string()));
+        assert_eq!(req, "Say goodbye to {{name}}");
+        assert_eq!(reloaded[1], cells[1]);
+    }
+
+    #[test]
+    fn test_bare_fence_has_no_tag() {
+        let mut blocks = extract_code_blocks(indoc! { r#"
+        ```
+        x = 1
+        ```
+        "#
+        });
+        assert_eq!(blocks[0].tag, "");
+        assert_eq!(blocks[0].body, "x = 1");
+        assert_eq!(interpret_markdown_code_block(&blocks[0], None).unwrap(), None);
+
+        blocks[0].apply_default_language(&SupportedLanguage::PyO3);
+        let cell = interpret_markdown_code_block(&blocks[0], None).unwrap();
+        assert!(matches!(cell, Some(CellTypes::Code(CodeCell { language: SupportedLanguage::PyO3, .. }, _))));
+    }
+
+    #[test]
+    fn test_interpret_code_block_frontmatter() {
+        let blocks = extract_code_blocks(indoc! { r#"
+        ```python (profile)
+        ---
+        redact_output: true
+        timeout_ms: 250
+        oom_limit_bytes: 1000000
+        max_output_bytes: 4096
+        overflow: spill
+        ---
+        ssn = "123-45-6789"
+        ```
+        "#
+        });
+        let cell = interpret_markdown_code_block(&blocks[0], None).unwrap();
+        let Some(CellTypes::Code(cell, _)) = cell else { panic!("Expected a code cell") };
+        assert!(cell.redact_output);
+        assert!(!cell.inspect_globals);
+        assert_eq!(cell.policy, ExecutionPolicy {
+            timeout_ms: Some(250),
+            retries: None,
+            max_output_bytes: Some(4096),
+            overflow: Some(OutputOverflow::Spill),
+        });
+        assert_eq!(cell.oom_limit_bytes, Some(1_000_000));
+        assert_eq!(cell.source_code.trim(), "ssn = \"123-45-6789\"");
+    }
+
+    #[test]
+    fn test_interpret_package_json_frontmatter() {
+        let blocks = extract_code_blocks(indoc! { r#"
+        ```javascript (sum)
+        ---
+        package_json: |
+          { "dependencies": { "lodash": "^4.17.21" } }
+        ---
+        import { add } from "npm:lodash";
+        export const sum = add(2, 3);
+        ```
+        "#
+        });
+        let cell = interpret_markdown_code_block(&blocks[0], None).unwrap();
+        let Some(CellTypes::Code(cell, _)) = cell else { panic!("Expected a code cell") };
+        assert_eq!(cell.package_json.as_deref(), Some("{ \"dependencies\": { \"lodash\": \"^4.17.21\" } }\n"));
+
+        // The package is kept when the cell is written back
+        let cells = vec![CellTypes::Code(cell, TextRange::default())];
+        let reloaded = interpret_document(&cells_to_markdown(&cells).unwrap());
+        assert_eq!(reloaded, cells);
+    }
+
+    #[test]
+    fn test_block_descriptions() {
+        let blocks = extract_code_blocks(indoc! { r#"
+            # Weather
+
+            Fetches the forecast for a city,
+            in degrees celsius.
+
+            ```python (forecast)
+            def forecast(city):
+                return 21
+            ```
+            ```python (undocumented)
+            x = 1
+            ```
+
+            ## Summaries
+
+            ```prompt (summarize)
+            ---
+            description: Summarizes the forecast
+            model: gpt-4o
+            ---
+            Summarize {{forecast}}
+            ```
+            "#});
+        let descriptions: Vec<_> = blocks.iter().map(block_description).collect();
+        assert_eq!(descriptions, vec![
+            Some("Fetches the forecast for a city, in degrees celsius.".to_string()),
+            None,
+            Some("Summarizes the forecast".to_string()),
+        ]);
+        // The description is not part of the configuration of the cell
+        let cell = interpret_documented_code_block(&blocks[2], None).unwrap().unwrap();
+        let CellTypes::Prompt(LLMPromptCell::Chat { configuration, .. }, _) = &cell.cell else {
+            panic!("Expected a prompt cell")
+        };
+        assert_eq!(configuration.model, Some("gpt-4o".to_string()));
+        assert_eq!(cell.description, Some("Summarizes the forecast".to_string()));
+    }
+
+    fn interpret_prompt_with_frontmatter(frontmatter: &str) -> (SupportedModelProviders, LLMPromptCellChatConfiguration) {
+        let block = MarkdownCodeBlock {
+            tag: "prompt".to_string(),
+            name: Some("greeting".to_string()),
+            body: format!("---\n{}\n---\nSay hello", frontmatter),
+            range: TextRange::default(),
+            body_start: 0,
+            description: None,
+        };
+        let cell = interpret_markdown_code_block(&block, None).unwrap();
+        let Some(CellTypes::Prompt(LLMPromptCell::Chat { provider, configuration, req, .. }, _)) = cell else {
+            panic!("Expected a prompt cell")
+        };
+        assert_eq!(req.trim(), "Say hello");
+        (provider, configuration)
+    }
+
+    #[test]
+    fn test_interpret_prompt_multiline_frontmatter() {
+        let (_, configuration) = interpret_prompt_with_frontmatter(indoc! { r#"
+            description: |
+              Greets the user.
+              ---
+              Never more than one line.
+            import:
+            - greeting_style
+            - -formal
+            model: gpt-4o"#});
+        assert_eq!(configuration.import, Some(vec!["greeting_style".to_string(), "-formal".to_string()]));
+        assert_eq!(configuration.model, Some("gpt-4o".to_string()));
+    }
+
+    #[test]
+    fn test_interpret_prompt_provider_openai() {
+        let (provider, configuration) = interpret_prompt_with_frontmatter("provider: openai\nmodel: gpt-4o");
+        assert_eq!(provider, SupportedModelProviders::OpenAI);
+        assert_eq!(configuration.model, Some("gpt-4o".to_string()));
+
+        let (provider, _) = interpret_prompt_with_frontmatter("model: gpt-4o");
+        assert_eq!(provider, SupportedModelProviders::OpenAI);
+    }
+
+    #[test]
+    fn test_interpret_prompt_provider_anthropic() {
+        let (provider, configuration) = interpret_prompt_with_frontmatter("provider: anthropic\nmodel: claude-3-haiku-20240307");
+        assert_eq!(provider, SupportedModelProviders::Anthropic);
+        assert_eq!(configuration.provider, Some(SupportedModelProviders::Anthropic));
+        assert_eq!(configuration.model, Some("claude-3-haiku-20240307".to_string()));
+    }
+
+    #[test]
+    fn test_interpret_prompt_provider_ollama() {
+        let (provider, configuration) = interpret_prompt_with_frontmatter("provider: ollama\nmodel: llama3:8b");
+        assert_eq!(provider, SupportedModelProviders::Ollama);
+        assert_eq!(configuration.model, Some("llama3:8b".to_string()));
+        assert_eq!(configuration.api_url, None);
+        assert_eq!(provider.default_api_url(), "http://localhost:11434/v1");
+    }
+
+    #[test]
+    fn test_interpret_prompt_unknown_provider() {
+        let block = MarkdownCodeBlock {
+            tag: "prompt".to_string(),
+            name: None,
+            body: "---\nprovider: nonexistent\n---\nSay hello".to_string(),
+            range: TextRange::default(),
+            body_start: 0,
+            description: None,
+        };
+        assert!(matches!(interpret_markdown_code_block(&block, None), Err(InterpretError::YamlDeserializeError(_))));
+    }
 }
@@ -1,3 +1,12 @@
 pub mod md;
 pub mod interactive_chidori_wrapper;
 pub mod chidori_runtime_instance;
+pub mod file_watch;
+pub mod examples;
+pub mod checkpoint;
+pub mod registry;
+pub mod event_subscriptions;
+pub mod run_summary;
+pub mod save;
+#[cfg(feature = "generate_workflow")]
+pub mod workflow_generation;
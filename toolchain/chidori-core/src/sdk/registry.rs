@@ -0,0 +1,174 @@
+//! Cells shared through a registry. A registry serves a manifest listing cells, and documents
+//! import them from the frontmatter at the top of the document, pinning the digest of each cell
+//! they use:
+//!
+//! ```markdown
+//! ---
+//! registry:
+//!   - url: chidori.registry.io/cells/text-summarizer
+//!     cells:
+//!       summarize: sha256:9c1185a5c5e9fc54612808977ee8f548b2258d31...
+//! ---
+//! ```
+//!
+//! Only the pinned cells are taken from a manifest, and only when their digest matches. Fetched
+//! cells are held for review rather than evaluated, see
+//! `InteractiveChidoriWrapper::accept_registry_cells`. Every cell of a manifest can also be loaded
+//! directly with `InteractiveChidoriWrapper::load_cells_from_registry`.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use crate::sdk::interactive_chidori_wrapper::CellHolder;
+use crate::sdk::md::load_folder;
+
+const DIGEST_PREFIX: &str = "sha256:";
+
+/// The listing of a cell registry, see `fetch_registry_cells`
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct RegistryManifest {
+    /// Cells encoded by `CellHolder::serialize_to_base64`
+    pub cells: Vec<String>,
+}
+
+/// Cells a document imports from a registry, by name, each with the digest it is pinned to
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RegistryImport {
+    /// Location of the manifest, `https://` is assumed when no scheme is given
+    pub url: String,
+    /// Digest of each imported cell as `sha256:<hex>`, see `cell_digest`
+    pub cells: BTreeMap<String, String>,
+}
+
+impl RegistryImport {
+    /// The url the manifest is fetched from
+    pub fn manifest_url(&self) -> String {
+        manifest_url(&self.url)
+    }
+}
+
+/// The url the manifest of the registry at `url` is fetched from
+pub fn manifest_url(url: &str) -> String {
+    if url.contains("://") {
+        url.to_string()
+    } else {
+        format!("https://{}", url)
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct DocumentFrontmatter {
+    #[serde(default)]
+    registry: Vec<RegistryImport>,
+}
+
+/// The digest cells are pinned to, of the archived cell as it is listed in a manifest
+pub fn cell_digest(encoded: &str) -> anyhow::Result<String> {
+    let bytes = base64::engine::general_purpose::STANDARD.decode(encoded.trim())?;
+    Ok(format!("{}{}", DIGEST_PREFIX, hex::encode(Sha256::digest(&bytes))))
+}
+
+/// The registry imports declared by the frontmatter at the top of a document, documents without
+/// frontmatter import nothing
+pub fn registry_imports(document: &str) -> anyhow::Result<Vec<RegistryImport>> {
+    if !document.trim_start().starts_with("---") {
+        return Ok(vec![]);
+    }
+    let (frontmatter, _) = chidori_prompt_format::templating::templates::split_frontmatter(document)
+        .map_err(|e| anyhow::anyhow!("Failed to split frontmatter: {}", e))?;
+    if frontmatter.trim().is_empty() {
+        return Ok(vec![]);
+    }
+    let frontmatter: DocumentFrontmatter = serde_yaml::from_str(&frontmatter)?;
+    for import in &frontmatter.registry {
+        if let Some((name, digest)) = import.cells.iter().find(|(_, digest)| !digest.starts_with(DIGEST_PREFIX)) {
+            anyhow::bail!("Cell {} of {} must be pinned to a {} digest, not {:?}", name, import.url, DIGEST_PREFIX, digest);
+        }
+    }
+    Ok(frontmatter.registry)
+}
+
+/// The registry imports of every document in a directory
+pub fn registry_imports_in_directory(path: &Path) -> anyhow::Result<Vec<RegistryImport>> {
+    let mut imports = vec![];
+    for file in load_folder(path)? {
+        if let Some(source) = &file.code {
+            imports.extend(registry_imports(source)?);
+        }
+    }
+    Ok(imports)
+}
+
+/// Fetch the manifest of `import` and decode the cells it pins. A cell missing from the manifest
+/// or not matching its digest fails the import, cells that are not pinned are left out.
+pub async fn fetch_registry_cells(import: &RegistryImport) -> anyhow::Result<Vec<CellHolder>> {
+    let url = import.manifest_url();
+    let manifest = fetch_manifest(&url).await?;
+    let mut listed = BTreeMap::new();
+    for encoded in &manifest.cells {
+        let cell = CellHolder::deserialize_from_base64(encoded)?;
+        if let Some(name) = cell.cell.name() {
+            listed.insert(name.clone(), (cell_digest(encoded)?, cell));
+        }
+    }
+    let mut cells = vec![];
+    for (name, pinned) in &import.cells {
+        let (digest, cell) = listed.remove(name)
+            .ok_or_else(|| anyhow::anyhow!("The registry {} does not list a cell named {}", url, name))?;
+        if &digest != pinned {
+            anyhow::bail!("Cell {} of {} has digest {}, but is pinned to {}", name, url, digest, pinned);
+        }
+        cells.push(cell);
+    }
+    Ok(cells)
+}
+
+/// Fetch the manifest of the registry at `url` and decode every cell it lists, without checking
+/// them against any digest
+pub async fn fetch_listed_cells(url: &str) -> anyhow::Result<Vec<CellHolder>> {
+    fetch_manifest(&manifest_url(url)).await?.cells.iter()
+        .map(|encoded| CellHolder::deserialize_from_base64(encoded))
+        .collect()
+}
+
+async fn fetch_manifest(url: &str) -> anyhow::Result<RegistryManifest> {
+    reqwest::get(url).await?
+        .error_for_status()?
+        .json().await
+        .map_err(|e| anyhow::anyhow!("{} is not a cell registry manifest: {}", url, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn test_registry_imports_from_frontmatter() -> anyhow::Result<()> {
+        let document = indoc! { r#"
+            ---
+            registry:
+              - url: chidori.registry.io/cells/text-summarizer
+                cells:
+                  summarize: sha256:00ff
+            ---
+            # Summaries
+
+            ```python (numbers)
+            x = 1
+            ```
+            "#};
+        let imports = registry_imports(document)?;
+        assert_eq!(imports, vec![RegistryImport {
+            url: "chidori.registry.io/cells/text-summarizer".to_string(),
+            cells: BTreeMap::from([("summarize".to_string(), "sha256:00ff".to_string())]),
+        }]);
+        assert_eq!(imports[0].manifest_url(), "https://chidori.registry.io/cells/text-summarizer");
+
+        assert!(registry_imports("# No frontmatter\n\n---\nregistry: []\n---\n")?.is_empty());
+        let unpinned = "---\nregistry:\n  - url: http://localhost/cells\n    cells:\n      summarize: latest\n---\n";
+        assert!(registry_imports(unpinned).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+//! A structured summary of a run, the headline artifact for CI and reporting once a batch run has
+//! nothing left to evaluate. It is derived from the states along the ancestry of the execution
+//! head, so evaluations on abandoned branches are not counted.
+
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::execution::execution::execution_graph::{ExecutionGraph, ExecutionNodeId};
+use crate::execution::execution::execution_state::EnclosedState;
+use crate::execution::execution::html_report::evaluation_duration;
+use crate::execution::primitives::identifiers::{OperationId, RunId};
+use crate::execution::primitives::serialized_value::serialized_value_to_json_value;
+use crate::utils::redaction::RedactionConfig;
+
+/// An evaluation that failed during the run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunSummaryError {
+    pub operation_id: OperationId,
+    pub cell: Option<String>,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunSummary {
+    pub run_id: RunId,
+    pub head: ExecutionNodeId,
+    /// Whether no operation is left to evaluate at the head
+    pub quiescent: bool,
+    /// Evaluations along the ancestry of the head, each step evaluates one operation
+    pub steps: usize,
+    /// Distinct operations evaluated at least once
+    pub operations_executed: usize,
+    pub errors: Vec<RunSummaryError>,
+    /// Tokens reported by model providers, responses served from the cache count none
+    pub total_tokens: u64,
+    /// Sum of the wall-clock durations of the steps
+    pub total_duration_ms: u64,
+    /// Outputs at the head of the operations no other operation depends on, by the name of their
+    /// cell, or their operation id for unnamed cells
+    pub leaf_outputs: BTreeMap<String, serde_json::Value>,
+}
+
+impl RunSummary {
+    /// Summarize the run of `graph` ending at `head`, with `redaction` applied to the errors and
+    /// outputs it holds
+    pub fn from_graph(graph: &ExecutionGraph, head: ExecutionNodeId, run_id: RunId, redaction: &RedactionConfig) -> anyhow::Result<Self> {
+        let mut steps = 0;
+        let mut operations = HashSet::new();
+        let mut errors = vec![];
+        let mut total_tokens = 0;
+        let mut total_duration = Duration::ZERO;
+        for id in graph.ancestry(head) {
+            let Some(state) = graph.get_state_at_id(id) else { continue };
+            if !matches!(state.evaluating_enclosed_state, EnclosedState::Close(_)) {
+                continue;
+            }
+            let op_id = state.evaluating_operation_id;
+            steps += 1;
+            operations.insert(op_id);
+            total_duration += evaluation_duration(&state).unwrap_or_default();
+            let Some(output) = state.state.get(&op_id) else { continue };
+            if let Some(metadata) = &output.response_metadata {
+                total_tokens += metadata.usage.total_tokens.max(0) as u64;
+            }
+            if output.has_error || output.output.is_err() {
+                let error = match &output.output {
+                    Err(e) => e.to_string(),
+                    Ok(_) => output.stderr.join("\n"),
+                };
+                errors.push(RunSummaryError {
+                    operation_id: op_id,
+                    cell: state.evaluating_cell.as_ref().and_then(|cell| cell.name().clone()),
+                    error: redaction.redact_text(&error),
+                });
+            }
+        }
+
+        let state = graph.get_state_at_id(head)
+            .ok_or_else(|| anyhow::anyhow!("The execution graph has no state {}", head))?;
+        let mut leaf_outputs = BTreeMap::new();
+        for (op_id, cell) in state.cells_by_id.iter() {
+            if !state.dependents_of(*op_id).is_empty() {
+                continue;
+            }
+            let Some(Ok(value)) = state.state_get_value(op_id) else { continue };
+            let key = cell.name().clone().unwrap_or_else(|| op_id.to_string());
+            leaf_outputs.insert(key, serialized_value_to_json_value(&redaction.redact_cell_output(Some(cell), value)));
+        }
+
+        Ok(RunSummary {
+            run_id,
+            head,
+            quiescent: state.ready_operations()?.is_empty(),
+            steps,
+            operations_executed: operations.len(),
+            errors,
+            total_tokens,
+            total_duration_ms: total_duration.as_millis() as u64,
+            leaf_outputs,
+        })
+    }
+}
@@ -0,0 +1,408 @@
+//! Writing cells edited since they were loaded back to the documents they were loaded from,
+//! optionally committing the result to the git repository holding those documents.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use log::warn;
+use crate::cells::{CellTypes, SupportedLanguage};
+use crate::library::std::ai::llm::{ChatCompletionReq, ChatModelBatch, MessageRole, TemplateMessage};
+use crate::sdk::md::{cell_to_markdown, extract_code_blocks, interpret_markdown_code_block};
+
+/// Model asked for the summary line of commit messages unless another is configured
+const DEFAULT_SUMMARY_MODEL: &str = "gpt-4o-mini";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellChangeKind {
+    Updated,
+    Removed,
+}
+
+impl fmt::Display for CellChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CellChangeKind::Updated => write!(f, "Updated"),
+            CellChangeKind::Removed => write!(f, "Removed"),
+        }
+    }
+}
+
+/// A cell of a document that differs from the loaded program
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellChange {
+    pub name: String,
+    pub kind: CellChangeKind,
+    pub path: PathBuf,
+}
+
+/// The documents rewritten by a write-back and the cells that changed in them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WriteBack {
+    pub touched: Vec<PathBuf>,
+    pub changes: Vec<CellChange>,
+}
+
+/// `document` with the blocks of named cells replaced by their definition in `cells`, and removed
+/// when `cells` no longer defines them. Prose and blocks of unnamed cells are kept as they are,
+/// as unnamed cells cannot be told apart.
+pub fn write_back_document(
+    document: &str,
+    default_language: Option<&SupportedLanguage>,
+    cells: &HashMap<String, &CellTypes>,
+) -> anyhow::Result<(String, Vec<(String, CellChangeKind)>)> {
+    let mut replacements = vec![];
+    for mut block in extract_code_blocks(document) {
+        if let Some(language) = default_language {
+            block.apply_default_language(language);
+        }
+        let Some(written) = interpret_markdown_code_block(&block, None)? else { continue };
+        let Some(name) = written.name().clone() else { continue };
+        // The fence itself, removed blocks also take the blank line that follows them
+        let (start, end) = (block.range.start - 3, block.range.end + 3);
+        match cells.get(&name) {
+            Some(cell) => {
+                let (Some(rendered), Some(previous)) = (cell_to_markdown(cell)?, cell_to_markdown(&written)?) else { continue };
+                if rendered != previous {
+                    replacements.push((start, end, rendered.trim_end().to_string(), name, CellChangeKind::Updated));
+                }
+            }
+            None => {
+                let end = end + document[end..].chars().take_while(|c| *c == '\n').count().min(2);
+                replacements.push((start, end, String::new(), name, CellChangeKind::Removed));
+            }
+        }
+    }
+
+    let mut document = document.to_string();
+    let mut changes = vec![];
+    for (start, end, text, name, kind) in replacements.into_iter().rev() {
+        document.replace_range(start..end, &text);
+        changes.push((name, kind));
+    }
+    changes.reverse();
+    Ok((document, changes))
+}
+
+/// The documents under `root` rewritten with the definitions of `cells`, see `write_back_document`.
+/// Only documents that change are returned, nothing is written.
+pub fn plan_write_back(
+    root: &Path,
+    default_language: Option<&SupportedLanguage>,
+    cells: &HashMap<String, &CellTypes>,
+) -> anyhow::Result<(Vec<(PathBuf, String)>, WriteBack)> {
+    let mut documents = vec![];
+    let mut write_back = WriteBack::default();
+    for path in markdown_documents(root)? {
+        let source = std::fs::read_to_string(&path)?;
+        let (rewritten, changes) = write_back_document(&source, default_language, cells)?;
+        if rewritten == source {
+            continue;
+        }
+        write_back.changes.extend(changes.into_iter().map(|(name, kind)| CellChange { name, kind, path: path.clone() }));
+        write_back.touched.push(path.clone());
+        documents.push((path, rewritten));
+    }
+    Ok((documents, write_back))
+}
+
+fn markdown_documents(directory: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut documents = vec![];
+    for entry in directory.read_dir()? {
+        let path = entry?.path();
+        if path.file_name().map_or(false, |name| name == ".git") {
+            continue;
+        }
+        if path.is_dir() {
+            documents.extend(markdown_documents(&path)?);
+        } else if path.extension().and_then(|e| e.to_str()).map_or(false, |e| e.eq_ignore_ascii_case("md")) {
+            documents.push(path);
+        }
+    }
+    documents.sort();
+    Ok(documents)
+}
+
+/// Options of `InteractiveChidoriWrapper::save_to_loaded_path_with_commit`
+#[derive(Clone, Default)]
+pub struct SaveCommitOptions {
+    /// Commit even when the index holds changes to files the write-back did not touch, which are
+    /// then committed along with it
+    pub allow_mixed: bool,
+    /// Ask this model for the summary line of the commit message, otherwise it lists the changed
+    /// cells. The message falls back to the list when the model fails.
+    pub summary_model: Option<Arc<dyn ChatModelBatch + Send + Sync>>,
+}
+
+/// What is known of the program when it is saved, recorded in the commit message
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SaveContext {
+    /// None when the edited cells were not validated, otherwise the problems found with them
+    pub validation: Option<Result<(), String>>,
+    /// Names of the cells that were evaluated at the execution head, and whether each failed
+    pub last_run: Option<Vec<(String, bool)>>,
+}
+
+/// The body of the commit message, the summary line is added by `commit_message`
+fn commit_body(changes: &[CellChange], context: &SaveContext) -> String {
+    let mut lines: Vec<String> = changes.iter()
+        .map(|change| format!("{} {}", change.kind, change.name))
+        .collect();
+    lines.push(String::new());
+    lines.push(match &context.validation {
+        None => "Validation: not run".to_string(),
+        Some(Ok(())) => "Validation: passed".to_string(),
+        Some(Err(report)) => format!("Validation: failed, {}", report),
+    });
+    lines.push(match &context.last_run {
+        None => "Last run: not run".to_string(),
+        Some(evaluated) => {
+            let failed: Vec<&str> = evaluated.iter().filter(|(_, failed)| *failed).map(|(name, _)| name.as_str()).collect();
+            if failed.is_empty() {
+                format!("Last run: {} cells evaluated, none failed", evaluated.len())
+            } else {
+                format!("Last run: {} cells evaluated, {} failed", evaluated.len(), failed.join(", "))
+            }
+        }
+    });
+    lines.join("\n")
+}
+
+fn default_summary(changes: &[CellChange]) -> String {
+    let names: Vec<&str> = changes.iter().map(|change| change.name.as_str()).collect();
+    format!("Update cells {}", names.join(", "))
+}
+
+async fn model_summary(model: &(dyn ChatModelBatch + Send + Sync), body: &str) -> anyhow::Result<String> {
+    let mut req = ChatCompletionReq {
+        template_messages: vec![TemplateMessage {
+            role: MessageRole::User,
+            content: format!("Write a one line git commit summary, with no other commentary, for these changes to a program:\n{}", body),
+            name: None,
+            function_call: None,
+        }],
+        ..ChatCompletionReq::default()
+    };
+    req.config.model = Some(DEFAULT_SUMMARY_MODEL.to_string());
+    let res = model.batch(req).await.map_err(|e| anyhow::anyhow!(e))?;
+    res.choices.into_iter()
+        .find_map(|choice| choice.text)
+        .and_then(|text| text.lines().map(|line| line.trim()).find(|line| !line.is_empty()).map(|line| line.to_string()))
+        .ok_or_else(|| anyhow::anyhow!("The model did not respond with a summary"))
+}
+
+/// The message of a commit of `changes`, opening with a summary by `model` when one is given
+pub async fn commit_message(changes: &[CellChange], context: &SaveContext, model: Option<&(dyn ChatModelBatch + Send + Sync)>) -> String {
+    let body = commit_body(changes, context);
+    let summary = match model {
+        Some(model) => model_summary(model, &body).await.unwrap_or_else(|e| {
+            warn!("Falling back to listing the changed cells in the commit message: {}", e);
+            default_summary(changes)
+        }),
+        None => default_summary(changes),
+    };
+    format!("{}\n\n{}\n", summary, body)
+}
+
+/// Paths, relative to the working directory, of the changes staged in the index of `repo`
+fn staged_paths(repo: &git2::Repository) -> anyhow::Result<HashSet<PathBuf>> {
+    let staged = git2::Status::INDEX_NEW | git2::Status::INDEX_MODIFIED | git2::Status::INDEX_DELETED
+        | git2::Status::INDEX_RENAMED | git2::Status::INDEX_TYPECHANGE;
+    let mut options = git2::StatusOptions::new();
+    options.include_untracked(false);
+    Ok(repo.statuses(Some(&mut options))?.iter()
+        .filter(|entry| entry.status().intersects(staged))
+        .filter_map(|entry| entry.path().map(PathBuf::from))
+        .collect())
+}
+
+/// Write `documents` and commit them to the repository holding `root`. Fails without writing
+/// anything when other changes are staged, unless `options.allow_mixed` is set.
+pub async fn write_and_commit(
+    root: &Path,
+    documents: Vec<(PathBuf, String)>,
+    write_back: &WriteBack,
+    context: &SaveContext,
+    options: &SaveCommitOptions,
+) -> anyhow::Result<git2::Oid> {
+    let repo = git2::Repository::discover(root)?;
+    let workdir = repo.workdir()
+        .ok_or_else(|| anyhow::anyhow!("The repository holding {:?} has no working directory", root))?
+        .canonicalize()?;
+    let touched: Vec<PathBuf> = write_back.touched.iter()
+        .map(|path| Ok(path.canonicalize()?.strip_prefix(&workdir)?.to_path_buf()))
+        .collect::<anyhow::Result<_>>()?;
+    if !options.allow_mixed {
+        let unrelated: Vec<String> = staged_paths(&repo)?.into_iter()
+            .filter(|path| !touched.contains(path))
+            .map(|path| path.display().to_string())
+            .collect();
+        if !unrelated.is_empty() {
+            anyhow::bail!("Changes to {} are already staged, commit them first or allow mixed commits", unrelated.join(", "));
+        }
+    }
+
+    for (path, source) in documents {
+        std::fs::write(path, source)?;
+    }
+    let mut index = repo.index()?;
+    for path in &touched {
+        index.add_path(path)?;
+    }
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let signature = repo.signature()?;
+    let parent = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit()?),
+        Err(e) if e.code() == git2::ErrorCode::UnbornBranch => None,
+        Err(e) => return Err(e.into()),
+    };
+    let message = commit_message(&write_back.changes, context, options.summary_model.as_deref()).await;
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+    Ok(repo.commit(Some("HEAD"), &signature, &signature, &message, &tree, &parents)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use indoc::indoc;
+    use uuid::Uuid;
+    use crate::cells::{CodeCell, TextRange};
+    use crate::library::std::ai::llm::{ChatCompletionChoice, ChatCompletionRes, Usage};
+
+    const DOCUMENT: &str = indoc! { r#"
+        # Arithmetic
+
+        ```python (a)
+        x = 1
+        ```
+
+        Doubles x
+
+        ```python (b)
+        y = x * 2
+        ```
+        "#
+    };
+
+    fn python(name: &str, source: &str) -> CellTypes {
+        CellTypes::Code(CodeCell {
+            name: Some(name.to_string()),
+            source_code: source.to_string(),
+            ..Default::default()
+        }, TextRange::default())
+    }
+
+    struct SummaryModel;
+
+    #[async_trait]
+    impl ChatModelBatch for SummaryModel {
+        async fn batch(&self, _: ChatCompletionReq) -> Result<ChatCompletionRes, String> {
+            Ok(ChatCompletionRes {
+                id: "mock".to_string(),
+                object: "chat.completion".to_string(),
+                created: 0,
+                model: "mock".to_string(),
+                choices: vec![ChatCompletionChoice {
+                    text: Some("\nDouble x twice over\n".to_string()),
+                    index: 0,
+                    logprobs: None,
+                    finish_reason: "".to_string(),
+                    refusal: None,
+                    tool_calls: None,
+                }],
+                usage: Usage::default(),
+            })
+        }
+    }
+
+    fn temp_repo() -> anyhow::Result<(PathBuf, git2::Repository)> {
+        let directory = std::env::temp_dir().join(format!("chidori-save-{}", Uuid::now_v7()));
+        std::fs::create_dir_all(&directory)?;
+        let repo = git2::Repository::init(&directory)?;
+        let mut config = repo.config()?;
+        config.set_str("user.name", "Chidori")?;
+        config.set_str("user.email", "chidori@example.com")?;
+        std::fs::write(directory.join("main.md"), DOCUMENT)?;
+        std::fs::write(directory.join("notes.txt"), "notes")?;
+        let mut index = repo.index()?;
+        index.add_path(Path::new("main.md"))?;
+        index.add_path(Path::new("notes.txt"))?;
+        index.write()?;
+        let tree = repo.find_tree(index.write_tree()?)?;
+        let signature = repo.signature()?;
+        repo.commit(Some("HEAD"), &signature, &signature, "Initial", &tree, &[])?;
+        drop(tree);
+        Ok((directory, repo))
+    }
+
+    #[test]
+    fn test_write_back_keeps_prose() -> anyhow::Result<()> {
+        let b = python("b", "y = x * 3");
+        let cells = HashMap::from([("b".to_string(), &b)]);
+        let (document, changes) = write_back_document(DOCUMENT, None, &cells)?;
+        assert_eq!(document, indoc! { r#"
+            # Arithmetic
+
+            Doubles x
+
+            ```python (b)
+            y = x * 3
+            ```
+            "#
+        });
+        assert_eq!(changes, vec![("a".to_string(), CellChangeKind::Removed), ("b".to_string(), CellChangeKind::Updated)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_commit_stages_only_the_touched_documents() -> anyhow::Result<()> {
+        let (directory, repo) = temp_repo()?;
+        std::fs::write(directory.join("notes.txt"), "edited notes")?;
+        let (a, b) = (python("a", "x = 1"), python("b", "y = x * 3"));
+        let cells = HashMap::from([("a".to_string(), &a), ("b".to_string(), &b)]);
+        let (documents, write_back) = plan_write_back(&directory, None, &cells)?;
+        let context = SaveContext { validation: Some(Ok(())), last_run: Some(vec![("a".to_string(), false), ("b".to_string(), true)]) };
+        let options = SaveCommitOptions { allow_mixed: false, summary_model: Some(Arc::new(SummaryModel)) };
+        let oid = write_and_commit(&directory, documents, &write_back, &context, &options).await?;
+
+        let commit = repo.find_commit(oid)?;
+        assert_eq!(commit.message().unwrap(), "Double x twice over\n\nUpdated b\n\nValidation: passed\nLast run: 2 cells evaluated, b failed\n");
+        let parent = commit.parent(0)?;
+        let diff = repo.diff_tree_to_tree(Some(&parent.tree()?), Some(&commit.tree()?), None)?;
+        let changed: Vec<PathBuf> = diff.deltas().filter_map(|delta| delta.new_file().path().map(PathBuf::from)).collect();
+        assert_eq!(changed, vec![PathBuf::from("main.md")]);
+        let blob = repo.find_blob(commit.tree()?.get_path(Path::new("main.md"))?.id())?;
+        assert!(std::str::from_utf8(blob.content())?.contains("y = x * 3"));
+        // The edit to the notes was not staged
+        assert!(repo.status_file(Path::new("notes.txt"))?.contains(git2::Status::WT_MODIFIED));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_commit_refuses_other_staged_changes() -> anyhow::Result<()> {
+        let (directory, repo) = temp_repo()?;
+        std::fs::write(directory.join("notes.txt"), "edited notes")?;
+        let mut index = repo.index()?;
+        index.add_path(Path::new("notes.txt"))?;
+        index.write()?;
+
+        let (a, b) = (python("a", "x = 1"), python("b", "y = x * 3"));
+        let cells = HashMap::from([("a".to_string(), &a), ("b".to_string(), &b)]);
+        let (documents, write_back) = plan_write_back(&directory, None, &cells)?;
+        let mut options = SaveCommitOptions::default();
+        let result = write_and_commit(&directory, documents.clone(), &write_back, &SaveContext::default(), &options).await;
+        assert!(result.unwrap_err().to_string().contains("notes.txt"));
+        // Nothing was written
+        assert_eq!(std::fs::read_to_string(directory.join("main.md"))?, DOCUMENT);
+
+        options.allow_mixed = true;
+        let oid = write_and_commit(&directory, documents, &write_back, &SaveContext::default(), &options).await?;
+        let commit = repo.find_commit(oid)?;
+        assert!(commit.message().unwrap().starts_with("Update cells b\n\nUpdated b\n\nValidation: not run\nLast run: not run"));
+        let notes = repo.find_blob(commit.tree()?.get_path(Path::new("notes.txt"))?.id())?;
+        assert_eq!(notes.content(), b"edited notes");
+        Ok(())
+    }
+}
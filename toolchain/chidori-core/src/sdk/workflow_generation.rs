@@ -0,0 +1,169 @@
+use indoc::indoc;
+use crate::cells::{CellTypes, SupportedModelProviders, TemplateCell, TextRange};
+use crate::library::std::ai::llm::openai::OpenAIChatModel;
+use crate::library::std::ai::llm::{ChatCompletionReq, ChatModelBatch, MessageRole, TemplateMessage};
+use crate::library::std::template::TemplateLibrary;
+use crate::sdk::examples::find_example;
+use crate::sdk::md::{extract_code_blocks, interpret_markdown_code_block};
+
+const WORKFLOW_GENERATION_TEMPLATE_NAME: &str = "workflow_generation";
+
+/// Model asked to write programs unless another is given
+const DEFAULT_WORKFLOW_GENERATION_MODEL: &str = "gpt-4o";
+
+/// Examples included in the prompt to demonstrate the format of a program
+const FEW_SHOT_EXAMPLES: &[&str] = &["core1_simple_math", "core2_marshalling", "core3_function_invocations"];
+
+const WORKFLOW_GENERATION_TEMPLATE: &str = indoc! { r#"
+    You write programs for Chidori. A program is a markdown document, every fenced code block in it is a cell.
+    Prose outside of code blocks is documentation and is not evaluated.
+
+    Cells are declared with the language of the fence, optionally followed by a name in parentheses:
+    - ```python or ```javascript: code. Values assigned at the top level are available to other cells by name,
+      and functions defined at the top level may be called from other cells.
+    - ```prompt (name): a prompt sent to a language model, `\{{value}}` interpolates a value from another cell.
+      A frontmatter block between `---` lines configures it, e.g. `model: gpt-4o` or `fn: name` to expose it as a function.
+    - ```template (name): a handlebars template that other templates and prompts include as partials.
+    - ```codegen (name): a prompt whose response is evaluated as new cells.
+    - ```webhook (name): an HTTP listener configured with yaml, such as `port` and `path`.
+
+    Cells run whenever the values they depend on are available, in the order those dependencies allow.
+
+    {{#each examples}}
+    Example program: {{{this.title}}}
+    {{{this.source}}}
+
+    {{/each}}
+    Respond with only the markdown of a program that accomplishes the following, with no other commentary.
+    {{{description}}}
+"#};
+
+/// The prompt template, held as a template cell so that it renders the same way program templates do
+fn workflow_generation_template() -> CellTypes {
+    CellTypes::Template(TemplateCell {
+        backing_file_reference: None,
+        name: Some(WORKFLOW_GENERATION_TEMPLATE_NAME.to_string()),
+        body: WORKFLOW_GENERATION_TEMPLATE.to_string(),
+        engine: Default::default(),
+    }, TextRange::default())
+}
+
+/// Render the request sent to the model to write a program matching `description`
+pub fn workflow_generation_prompt(description: &str) -> anyhow::Result<String> {
+    let examples: Vec<_> = FEW_SHOT_EXAMPLES.iter()
+        .filter_map(|name| find_example(name))
+        .map(|example| serde_json::json!({ "title": example.title, "source": example.source }))
+        .collect();
+    let template = workflow_generation_template();
+    let library = TemplateLibrary::from_cells([&template]);
+    Ok(library.render_json(WORKFLOW_GENERATION_TEMPLATE_NAME, &serde_json::json!({
+        "description": description,
+        "examples": examples,
+    }))?)
+}
+
+/// Models commonly wrap their whole response in a markdown fence, which is removed
+fn strip_enclosing_fence(response: &str) -> &str {
+    let trimmed = response.trim();
+    for fence in ["```markdown\n", "```md\n"] {
+        if let Some(inner) = trimmed.strip_prefix(fence).and_then(|r| r.strip_suffix("```")) {
+            return inner.trim();
+        }
+    }
+    trimmed
+}
+
+/// Ask the given model to write a program matching `description`. The response is checked to
+/// define at least one cell, but is not loaded.
+pub async fn generate_workflow_with_model(model: &impl ChatModelBatch, description: &str) -> anyhow::Result<String> {
+    let mut req = ChatCompletionReq {
+        template_messages: vec![TemplateMessage {
+            role: MessageRole::User,
+            content: workflow_generation_prompt(description)?,
+            name: None,
+            function_call: None,
+        }],
+        ..ChatCompletionReq::default()
+    };
+    req.config.model = Some(DEFAULT_WORKFLOW_GENERATION_MODEL.to_string());
+    let res = model.batch(req).await.map_err(|e| anyhow::anyhow!(e))?;
+    let text = res.choices.into_iter()
+        .find_map(|choice| choice.text)
+        .ok_or_else(|| anyhow::anyhow!("The model did not respond with a program"))?;
+
+    let markdown = strip_enclosing_fence(&text).to_string();
+    let mut cell_count = 0;
+    for block in extract_code_blocks(&markdown) {
+        if interpret_markdown_code_block(&block, None)?.is_some() {
+            cell_count += 1;
+        }
+    }
+    if cell_count == 0 {
+        anyhow::bail!("The generated program does not define any cells");
+    }
+    Ok(markdown)
+}
+
+/// `generate_workflow_with_model` using OpenAI models through the default provider endpoint
+pub async fn generate_workflow(description: &str) -> anyhow::Result<String> {
+    let model = OpenAIChatModel::new(SupportedModelProviders::OpenAI.default_api_url().to_string(), "".to_string());
+    generate_workflow_with_model(&model, description).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+    use crate::library::std::ai::llm::{ChatCompletionChoice, ChatCompletionRes, Usage};
+
+    struct MockModel {
+        response: String,
+        prompts: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl ChatModelBatch for MockModel {
+        async fn batch(&self, req: ChatCompletionReq) -> Result<ChatCompletionRes, String> {
+            self.prompts.lock().unwrap().extend(req.template_messages.into_iter().map(|m| m.content));
+            Ok(ChatCompletionRes {
+                id: "mock".to_string(),
+                object: "chat.completion".to_string(),
+                created: 0,
+                model: "mock".to_string(),
+                choices: vec![ChatCompletionChoice {
+                    text: Some(self.response.clone()),
+                    index: 0,
+                    logprobs: None,
+                    finish_reason: "".to_string(),
+                    refusal: None,
+                    tool_calls: None,
+                }],
+                usage: Usage::default(),
+            })
+        }
+    }
+
+    fn mock_model(response: &str) -> MockModel {
+        MockModel { response: response.to_string(), prompts: Mutex::new(vec![]) }
+    }
+
+    #[tokio::test]
+    async fn test_generate_workflow() -> anyhow::Result<()> {
+        let model = mock_model("```markdown\nAdds two numbers\n\n```python\nx = 1 + 2\n```\n```");
+        let markdown = generate_workflow_with_model(&model, "Add one and two").await?;
+        assert_eq!(markdown, "Adds two numbers\n\n```python\nx = 1 + 2\n```");
+
+        let prompts = model.prompts.lock().unwrap();
+        assert!(prompts[0].contains("Add one and two"));
+        assert!(prompts[0].contains(find_example("core1_simple_math").unwrap().title));
+        assert!(prompts[0].contains(find_example("core3_function_invocations").unwrap().title));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_generate_workflow_without_cells_fails() {
+        let model = mock_model("I cannot help with that.");
+        assert!(generate_workflow_with_model(&model, "Add one and two").await.is_err());
+    }
+}
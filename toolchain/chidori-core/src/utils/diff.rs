@@ -0,0 +1,296 @@
+//! Line based diffs of text and structural diffs of serialized values, used to compare the
+//! outputs of operations across steps.
+use std::collections::{BTreeSet, HashMap};
+use serde::{Deserialize, Serialize};
+use crate::execution::primitives::serialized_value::RkyvSerializedValue as RKV;
+
+/// Number of unchanged lines included around each change in a hunk
+const CONTEXT_LINES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Equal,
+    Insert,
+    Delete,
+}
+
+/// A single line within a hunk. Line numbers are 1-based, `old_line` is absent for inserted
+/// lines and `new_line` is absent for deleted lines.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffLine {
+    pub kind: ChangeKind,
+    pub old_line: Option<usize>,
+    pub new_line: Option<usize>,
+    pub text: String,
+}
+
+/// A contiguous region of changes with surrounding context, equivalent to a hunk of a unified diff.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffHunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+/// Compute the line changes required to turn `a` into `b`, grouped into hunks. Returns no hunks when
+/// the strings have the same lines.
+pub fn diff_strings(a: &str, b: &str) -> Vec<DiffHunk> {
+    let old: Vec<&str> = a.lines().collect();
+    let new: Vec<&str> = b.lines().collect();
+    let ops = diff_lines(&old, &new);
+
+    // Ranges of ops to include in each hunk, changes closer together than twice the context are merged
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for (idx, (kind, _, _)) in ops.iter().enumerate() {
+        if *kind == ChangeKind::Equal {
+            continue;
+        }
+        let start = idx.saturating_sub(CONTEXT_LINES);
+        let end = (idx + 1 + CONTEXT_LINES).min(ops.len());
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    ranges.into_iter().map(|(start, end)| {
+        let lines: Vec<DiffLine> = ops[start..end].iter().map(|(kind, old_idx, new_idx)| DiffLine {
+            kind: *kind,
+            old_line: old_idx.map(|i| i + 1),
+            new_line: new_idx.map(|i| i + 1),
+            text: old_idx.map(|i| old[i]).or_else(|| new_idx.map(|i| new[i])).unwrap_or_default().to_string(),
+        }).collect();
+        // Hunks that only insert start at the line they're inserted after, as in unified diffs
+        let old_start = lines.iter().find_map(|l| l.old_line)
+            .unwrap_or_else(|| ops[..start].iter().filter(|(_, o, _)| o.is_some()).count());
+        let new_start = lines.iter().find_map(|l| l.new_line)
+            .unwrap_or_else(|| ops[..start].iter().filter(|(_, _, n)| n.is_some()).count());
+        DiffHunk {
+            old_start,
+            old_len: lines.iter().filter(|l| l.old_line.is_some()).count(),
+            new_start,
+            new_len: lines.iter().filter(|l| l.new_line.is_some()).count(),
+            lines,
+        }
+    }).collect()
+}
+
+/// Longest common subsequence over lines, yielding each line with its index in the old and new text.
+fn diff_lines(old: &[&str], new: &[&str]) -> Vec<(ChangeKind, Option<usize>, Option<usize>)> {
+    let prefix = old.iter().zip(new.iter()).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let (n, m) = (old_mid.len(), new_mid.len());
+    let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+    let at = |i: usize, j: usize| i * (m + 1) + j;
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[at(i, j)] = if old_mid[i] == new_mid[j] {
+                lcs[at(i + 1, j + 1)] + 1
+            } else {
+                lcs[at(i + 1, j)].max(lcs[at(i, j + 1)])
+            };
+        }
+    }
+
+    let mut ops: Vec<_> = (0..prefix).map(|i| (ChangeKind::Equal, Some(i), Some(i))).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_mid[i] == new_mid[j] {
+            ops.push((ChangeKind::Equal, Some(prefix + i), Some(prefix + j)));
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lcs[at(i + 1, j)] >= lcs[at(i, j + 1)]) {
+            ops.push((ChangeKind::Delete, Some(prefix + i), None));
+            i += 1;
+        } else {
+            ops.push((ChangeKind::Insert, None, Some(prefix + j)));
+            j += 1;
+        }
+    }
+    ops.extend((0..suffix).map(|k| (ChangeKind::Equal, Some(prefix + n + k), Some(prefix + m + k))));
+    ops
+}
+
+/// Addresses a value nested within objects and arrays
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ValueChange {
+    Added { path: Vec<PathSegment>, value: RKV },
+    Removed { path: Vec<PathSegment>, value: RKV },
+    Modified { path: Vec<PathSegment>, old: RKV, new: RKV },
+    /// Multi-line strings are compared line by line
+    TextChanged { path: Vec<PathSegment>, hunks: Vec<DiffHunk> },
+}
+
+impl ValueChange {
+    pub fn path(&self) -> &[PathSegment] {
+        match self {
+            ValueChange::Added { path, .. }
+            | ValueChange::Removed { path, .. }
+            | ValueChange::Modified { path, .. }
+            | ValueChange::TextChanged { path, .. } => path,
+        }
+    }
+}
+
+/// The changes between two values, ordered by path
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ValueDiff {
+    pub changes: Vec<ValueChange>,
+}
+
+impl ValueDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// Compare two values, recursing into objects by key and arrays by index. The order of keys in
+/// objects is not significant.
+pub fn diff_values(a: &RKV, b: &RKV) -> ValueDiff {
+    let mut diff = ValueDiff::default();
+    diff_values_at(&mut vec![], a, b, &mut diff.changes);
+    diff
+}
+
+fn diff_values_at(path: &mut Vec<PathSegment>, a: &RKV, b: &RKV, changes: &mut Vec<ValueChange>) {
+    match (a, b) {
+        (RKV::Object(a), RKV::Object(b)) => diff_objects(path, a, b, changes),
+        (RKV::Array(a), RKV::Array(b)) => {
+            for idx in 0..a.len().max(b.len()) {
+                path.push(PathSegment::Index(idx));
+                match (a.get(idx), b.get(idx)) {
+                    (Some(a), Some(b)) => diff_values_at(path, a, b, changes),
+                    (Some(a), None) => changes.push(ValueChange::Removed { path: path.clone(), value: a.clone() }),
+                    (None, Some(b)) => changes.push(ValueChange::Added { path: path.clone(), value: b.clone() }),
+                    (None, None) => {}
+                }
+                path.pop();
+            }
+        }
+        (RKV::String(a), RKV::String(b)) if a != b && (a.contains('\n') || b.contains('\n')) => {
+            changes.push(ValueChange::TextChanged { path: path.clone(), hunks: diff_strings(a, b) });
+        }
+        (a, b) if a != b => {
+            changes.push(ValueChange::Modified { path: path.clone(), old: a.clone(), new: b.clone() });
+        }
+        _ => {}
+    }
+}
+
+fn diff_objects(path: &mut Vec<PathSegment>, a: &HashMap<String, RKV>, b: &HashMap<String, RKV>, changes: &mut Vec<ValueChange>) {
+    let keys: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+    for key in keys {
+        path.push(PathSegment::Key(key.clone()));
+        match (a.get(key), b.get(key)) {
+            (Some(a), Some(b)) => diff_values_at(path, a, b, changes),
+            (Some(a), None) => changes.push(ValueChange::Removed { path: path.clone(), value: a.clone() }),
+            (None, Some(b)) => changes.push(ValueChange::Added { path: path.clone(), value: b.clone() }),
+            (None, None) => {}
+        }
+        path.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::primitives::serialized_value::RkyvObjectBuilder;
+
+    #[test]
+    fn test_diff_strings_insertion() {
+        let hunks = diff_strings("a\nb\nc", "a\nb\nnew\nc");
+        assert_eq!(hunks.len(), 1);
+        let hunk = &hunks[0];
+        assert_eq!((hunk.old_start, hunk.old_len, hunk.new_start, hunk.new_len), (1, 3, 1, 4));
+        assert_eq!(hunk.lines[2], DiffLine { kind: ChangeKind::Insert, old_line: None, new_line: Some(3), text: "new".to_string() });
+        assert_eq!(hunk.lines[3], DiffLine { kind: ChangeKind::Equal, old_line: Some(3), new_line: Some(4), text: "c".to_string() });
+    }
+
+    #[test]
+    fn test_diff_strings_deletion() {
+        let old = (1..=20).map(|i| i.to_string()).collect::<Vec<_>>().join("\n");
+        let new = old.replace("\n10\n", "\n");
+        let hunks = diff_strings(&old, &new);
+        assert_eq!(hunks.len(), 1);
+        let hunk = &hunks[0];
+        // Three lines of context on either side of the deleted line
+        assert_eq!((hunk.old_start, hunk.old_len, hunk.new_start, hunk.new_len), (7, 7, 7, 6));
+        let deleted: Vec<_> = hunk.lines.iter().filter(|l| l.kind == ChangeKind::Delete).collect();
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].old_line, Some(10));
+        assert_eq!(deleted[0].text, "10");
+    }
+
+    #[test]
+    fn test_diff_strings_separate_hunks() {
+        let old = (1..=20).map(|i| i.to_string()).collect::<Vec<_>>().join("\n");
+        let new = old.replace("\n2\n", "\ntwo\n").replace("\n19\n", "\nnineteen\n");
+        let hunks = diff_strings(&old, &new);
+        assert_eq!(hunks.len(), 2);
+        assert!(diff_strings(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_diff_values_moved_keys_are_equal() {
+        let a = RkyvObjectBuilder::new().insert_number("x", 1).insert_string("y", "a".to_string()).build();
+        let b = RkyvObjectBuilder::new().insert_string("y", "a".to_string()).insert_number("x", 1).build();
+        assert!(diff_values(&a, &b).is_empty());
+    }
+
+    #[test]
+    fn test_diff_values_nested_objects() {
+        let a = RkyvObjectBuilder::new()
+            .insert_value("user", RkyvObjectBuilder::new().insert_string("name", "a".to_string()).insert_number("age", 1).build())
+            .build();
+        let b = RkyvObjectBuilder::new()
+            .insert_value("user", RkyvObjectBuilder::new().insert_string("name", "b".to_string()).insert_boolean("admin", true).build())
+            .build();
+        let diff = diff_values(&a, &b);
+        let user = PathSegment::Key("user".to_string());
+        assert_eq!(diff.changes, vec![
+            ValueChange::Added { path: vec![user.clone(), PathSegment::Key("admin".to_string())], value: RKV::Boolean(true) },
+            ValueChange::Removed { path: vec![user.clone(), PathSegment::Key("age".to_string())], value: RKV::Number(1) },
+            ValueChange::Modified { path: vec![user, PathSegment::Key("name".to_string())], old: RKV::String("a".to_string()), new: RKV::String("b".to_string()) },
+        ]);
+    }
+
+    #[test]
+    fn test_diff_values_array_elements() {
+        let a = RKV::Array(vec![RKV::Number(1), RKV::Number(2), RKV::Number(3)]);
+        let b = RKV::Array(vec![RKV::Number(1), RKV::Number(5)]);
+        let diff = diff_values(&a, &b);
+        assert_eq!(diff.changes, vec![
+            ValueChange::Modified { path: vec![PathSegment::Index(1)], old: RKV::Number(2), new: RKV::Number(5) },
+            ValueChange::Removed { path: vec![PathSegment::Index(2)], value: RKV::Number(3) },
+        ]);
+        let diff = diff_values(&b, &a);
+        assert_eq!(diff.changes[1], ValueChange::Added { path: vec![PathSegment::Index(2)], value: RKV::Number(3) });
+    }
+
+    #[test]
+    fn test_diff_values_multiline_strings() {
+        let diff = diff_values(&RKV::String("a\nb".to_string()), &RKV::String("a\nc".to_string()));
+        let [ValueChange::TextChanged { path, hunks }] = diff.changes.as_slice() else {
+            panic!("Expected a text change, got {:?}", diff.changes);
+        };
+        assert!(path.is_empty());
+        assert_eq!(hunks.len(), 1);
+    }
+}
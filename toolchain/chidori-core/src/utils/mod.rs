@@ -1,4 +1,7 @@
 pub mod telemetry;
+pub mod diff;
+pub mod redaction;
+pub mod prompt_audit;
 mod error;
 
 use std::error::Error;
@@ -0,0 +1,168 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::execution::primitives::identifiers::OperationId;
+use crate::utils::redaction::RedactionConfig;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptAuditMessage {
+    pub role: String,
+    pub content: String,
+}
+
+/// A complete exchange with a model: the rendered prompt that was sent and what came back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptAuditRecord {
+    /// Milliseconds since the unix epoch at which the response was received
+    pub timestamp_ms: u64,
+    pub operation_id: OperationId,
+    pub cell_name: Option<String>,
+    pub model: Option<String>,
+    pub messages: Vec<PromptAuditMessage>,
+    pub response: Option<String>,
+    pub error: Option<String>,
+}
+
+impl PromptAuditRecord {
+    pub fn new(operation_id: OperationId, cell_name: Option<String>, model: Option<String>, messages: Vec<PromptAuditMessage>) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        PromptAuditRecord {
+            timestamp_ms,
+            operation_id,
+            cell_name,
+            model,
+            messages,
+            response: None,
+            error: None,
+        }
+    }
+}
+
+pub type PromptAuditCallback = dyn Fn(&PromptAuditRecord) + Send + Sync;
+
+#[derive(Clone)]
+pub enum PromptAuditSink {
+    /// Append each record to the file as a line of json
+    File(PathBuf),
+    Callback(Arc<PromptAuditCallback>),
+}
+
+impl std::fmt::Debug for PromptAuditSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PromptAuditSink::File(path) => f.debug_tuple("File").field(path).finish(),
+            PromptAuditSink::Callback(_) => f.write_str("Callback"),
+        }
+    }
+}
+
+/// A transcript of every prompt sent to a model and every response received, kept apart from
+/// tracing so that it can be retained for audit. Text matching the redaction patterns is replaced
+/// before a record reaches the sink.
+#[derive(Debug, Clone)]
+pub struct PromptAuditLog {
+    sink: PromptAuditSink,
+    redaction: RedactionConfig,
+    /// Serializes writes so that records from concurrent cells are not interleaved
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl PromptAuditLog {
+    pub fn new(sink: PromptAuditSink) -> Self {
+        PromptAuditLog {
+            sink,
+            redaction: RedactionConfig::default(),
+            write_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn to_file(path: &Path) -> Self {
+        Self::new(PromptAuditSink::File(path.to_path_buf()))
+    }
+
+    pub fn with_callback(callback: impl Fn(&PromptAuditRecord) + Send + Sync + 'static) -> Self {
+        Self::new(PromptAuditSink::Callback(Arc::new(callback)))
+    }
+
+    pub fn with_redaction(mut self, redaction: RedactionConfig) -> Self {
+        self.redaction = redaction;
+        self
+    }
+
+    pub fn record(&self, mut record: PromptAuditRecord) -> anyhow::Result<()> {
+        for message in &mut record.messages {
+            message.content = self.redaction.redact_text(&message.content);
+        }
+        record.response = record.response.map(|r| self.redaction.redact_text(&r));
+        record.error = record.error.map(|e| self.redaction.redact_text(&e));
+
+        let _guard = self.write_lock.lock().map_err(|e| anyhow::anyhow!("{}", e))?;
+        match &self.sink {
+            PromptAuditSink::File(path) => {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                writeln!(file, "{}", serde_json::to_string(&record)?)?;
+            }
+            PromptAuditSink::Callback(callback) => callback(&record),
+        }
+        Ok(())
+    }
+}
+
+/// Read back the records written by a file sink
+pub fn read_prompt_audit_file(path: &Path) -> anyhow::Result<Vec<PromptAuditRecord>> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn record(content: &str, response: &str) -> PromptAuditRecord {
+        let mut record = PromptAuditRecord::new(
+            Uuid::now_v7(),
+            Some("greeting".to_string()),
+            Some("gpt-3.5-turbo".to_string()),
+            vec![PromptAuditMessage { role: "user".to_string(), content: content.to_string() }],
+        );
+        record.response = Some(response.to_string());
+        record
+    }
+
+    #[test]
+    fn test_file_sink_appends_records() {
+        let path = std::env::temp_dir().join(format!("chidori-prompt-audit-{}.jsonl", Uuid::now_v7()));
+        let log = PromptAuditLog::to_file(&path);
+        log.record(record("Say hello", "Hello")).unwrap();
+        log.record(record("Say goodbye", "Goodbye")).unwrap();
+
+        let records = read_prompt_audit_file(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].messages[0].content, "Say hello");
+        assert_eq!(records[1].response.as_deref(), Some("Goodbye"));
+        assert_eq!(records[1].cell_name.as_deref(), Some("greeting"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_secrets_are_redacted_before_reaching_the_sink() {
+        let recorded = Arc::new(Mutex::new(vec![]));
+        let recorded_clone = recorded.clone();
+        let log = PromptAuditLog::with_callback(move |r| recorded_clone.lock().unwrap().push(r.clone()))
+            .with_redaction(RedactionConfig::new().with_pattern(r"sk-[A-Za-z0-9]+").unwrap());
+        log.record(record("Use the key sk-abc123 to call the api", "Done with sk-abc123")).unwrap();
+
+        let recorded = recorded.lock().unwrap();
+        assert_eq!(recorded[0].messages[0].content, "Use the key [REDACTED] to call the api");
+        assert_eq!(recorded[0].response.as_deref(), Some("Done with [REDACTED]"));
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::{HashMap, HashSet};
+use regex::Regex;
+use im::HashMap as ImHashMap;
+use crate::cells::CellTypes;
+use crate::execution::primitives::agent_trace::AgentTraceStep;
+use crate::execution::primitives::identifiers::OperationId;
+use crate::execution::primitives::operation::RecordedInput;
+use crate::execution::primitives::serialized_value::RkyvSerializedValue;
+
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Rules for removing sensitive values from state before it leaves the runtime, through events
+/// or exported reports. Redacted leaves are replaced with `"[REDACTED]"`, preserving the structure
+/// of the value around them.
+#[derive(Debug, Clone, Default)]
+pub struct RedactionConfig {
+    /// Object keys whose values are always redacted, such as `ssn` or `email`
+    pub keys: HashSet<String>,
+    /// String values matching any of these patterns are redacted
+    pub patterns: Vec<Regex>,
+}
+
+impl RedactionConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_key(mut self, key: &str) -> Self {
+        self.keys.insert(key.to_string());
+        self
+    }
+
+    pub fn with_pattern(mut self, pattern: &str) -> anyhow::Result<Self> {
+        self.patterns.push(Regex::new(pattern)?);
+        Ok(self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.patterns.is_empty()
+    }
+
+    pub fn redact(&self, value: &RkyvSerializedValue) -> RkyvSerializedValue {
+        if self.is_empty() {
+            return value.clone();
+        }
+        match value {
+            RkyvSerializedValue::String(s) if self.patterns.iter().any(|p| p.is_match(s)) => {
+                RkyvSerializedValue::String(REDACTED.to_string())
+            }
+            RkyvSerializedValue::Error { kind, message } => RkyvSerializedValue::Error {
+                kind: kind.clone(),
+                message: self.redact_text(message),
+            },
+            RkyvSerializedValue::Table { path, schema, row_count, preview } => RkyvSerializedValue::Table {
+                path: path.clone(),
+                schema: schema.clone(),
+                row_count: *row_count,
+                preview: preview.iter().map(|v| self.redact(v)).collect(),
+            },
+            RkyvSerializedValue::Array(a) => RkyvSerializedValue::Array(a.iter().map(|v| self.redact(v)).collect()),
+            RkyvSerializedValue::Set(s) => RkyvSerializedValue::Set(s.iter().map(|v| self.redact(v)).collect()),
+            RkyvSerializedValue::Object(o) => RkyvSerializedValue::Object(
+                o.iter()
+                    .map(|(k, v)| {
+                        let v = if self.keys.contains(k) { redact_all(v) } else { self.redact(v) };
+                        (k.clone(), v)
+                    })
+                    .collect::<HashMap<_, _>>(),
+            ),
+            v => v.clone(),
+        }
+    }
+
+    /// Replace each match of the redaction patterns within free text, such as a rendered prompt.
+    /// Unlike `redact`, the surrounding text is kept.
+    pub fn redact_text(&self, text: &str) -> String {
+        self.patterns.iter().fold(text.to_string(), |text, pattern| {
+            pattern.replace_all(&text, REDACTED).into_owned()
+        })
+    }
+
+    /// Redact the arguments an operation was evaluated with, each argument entirely so if the cell
+    /// that produced it is marked with `redact_output`
+    pub fn redact_recorded_input(&self, input: &RecordedInput, cells: &ImHashMap<OperationId, CellTypes>) -> RkyvSerializedValue {
+        let redacts = |producer: &OperationId| cells.get(producer).map_or(false, |cell| cell.redacts_output());
+        let RkyvSerializedValue::Object(sections) = &input.value else {
+            // A spilled input cannot be redacted argument by argument
+            return if input.producers.values().flat_map(|p| p.values()).any(redacts) {
+                redact_all(&input.value)
+            } else {
+                self.redact(&input.value)
+            };
+        };
+        RkyvSerializedValue::Object(sections.iter().map(|(section, value)| {
+            let producers = input.producers.get(section);
+            let value = match value {
+                RkyvSerializedValue::Object(arguments) => RkyvSerializedValue::Object(arguments.iter().map(|(name, argument)| {
+                    let argument = if producers.and_then(|p| p.get(name)).map_or(false, redacts) {
+                        redact_all(argument)
+                    } else {
+                        self.redact(argument)
+                    };
+                    (name.clone(), argument)
+                }).collect()),
+                value => self.redact(value),
+            };
+            (section.clone(), value)
+        }).collect())
+    }
+
+    /// Redact the output of a cell, entirely so if the cell is marked with `redact_output`
+    pub fn redact_cell_output(&self, cell: Option<&CellTypes>, value: &RkyvSerializedValue) -> RkyvSerializedValue {
+        if cell.map_or(false, |c| c.redacts_output()) {
+            redact_all(value)
+        } else {
+            self.redact(value)
+        }
+    }
+
+    /// Redact a reasoning step recorded by `cell`, entirely so if the cell is marked with `redact_output`
+    pub fn redact_trace_step(&self, cell: Option<&CellTypes>, step: &AgentTraceStep) -> AgentTraceStep {
+        let content = if cell.map_or(false, |c| c.redacts_output()) {
+            REDACTED.to_string()
+        } else {
+            self.redact_text(&step.content)
+        };
+        AgentTraceStep { content, ..step.clone() }
+    }
+}
+
+/// Replace every leaf of a value with the redaction placeholder. References to functions and
+/// streams are retained since they carry no data themselves.
+pub fn redact_all(value: &RkyvSerializedValue) -> RkyvSerializedValue {
+    match value {
+        RkyvSerializedValue::Array(a) => RkyvSerializedValue::Array(a.iter().map(redact_all).collect()),
+        RkyvSerializedValue::Set(s) => RkyvSerializedValue::Set(s.iter().map(redact_all).collect()),
+        RkyvSerializedValue::Object(o) => RkyvSerializedValue::Object(
+            o.iter().map(|(k, v)| (k.clone(), redact_all(v))).collect(),
+        ),
+        RkyvSerializedValue::Error { kind, .. } => RkyvSerializedValue::Error {
+            kind: kind.clone(),
+            message: REDACTED.to_string(),
+        },
+        // The rows of a table are not held in state, only those previewed are redacted
+        RkyvSerializedValue::Table { path, schema, row_count, preview } => RkyvSerializedValue::Table {
+            path: path.clone(),
+            schema: schema.clone(),
+            row_count: *row_count,
+            preview: preview.iter().map(redact_all).collect(),
+        },
+        RkyvSerializedValue::StreamPointer(_)
+        | RkyvSerializedValue::FunctionPointer(_, _)
+        | RkyvSerializedValue::Null => value.clone(),
+        _ => RkyvSerializedValue::String(REDACTED.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::primitives::serialized_value::RkyvObjectBuilder;
+
+    fn redacted() -> RkyvSerializedValue {
+        RkyvSerializedValue::String(REDACTED.to_string())
+    }
+
+    #[test]
+    fn test_redact_keys_and_patterns() {
+        let config = RedactionConfig::new()
+            .with_key("email")
+            .with_pattern(r"^\d{3}-\d{2}-\d{4}$")
+            .unwrap();
+        let value = RkyvObjectBuilder::new()
+            .insert_string("name", "Ada".to_string())
+            .insert_string("email", "ada@example.com".to_string())
+            .insert_value("ids", RkyvSerializedValue::Array(vec![
+                RkyvSerializedValue::String("123-45-6789".to_string()),
+                RkyvSerializedValue::Number(7),
+            ]))
+            .build();
+        let expected = RkyvObjectBuilder::new()
+            .insert_string("name", "Ada".to_string())
+            .insert_value("email", redacted())
+            .insert_value("ids", RkyvSerializedValue::Array(vec![redacted(), RkyvSerializedValue::Number(7)]))
+            .build();
+        assert_eq!(config.redact(&value), expected);
+    }
+
+    #[test]
+    fn test_redacted_keys_preserve_structure() {
+        let config = RedactionConfig::new().with_key("profile");
+        let value = RkyvObjectBuilder::new()
+            .insert_object("profile", RkyvObjectBuilder::new()
+                .insert_number("age", 36)
+                .insert_value("nickname", RkyvSerializedValue::Null))
+            .build();
+        let expected = RkyvObjectBuilder::new()
+            .insert_object("profile", RkyvObjectBuilder::new()
+                .insert_value("age", redacted())
+                .insert_value("nickname", RkyvSerializedValue::Null))
+            .build();
+        assert_eq!(config.redact(&value), expected);
+    }
+
+    #[test]
+    fn test_redact_text_keeps_surrounding_text() {
+        let config = RedactionConfig::new().with_pattern(r"\d{3}-\d{2}-\d{4}").unwrap();
+        assert_eq!(config.redact_text("My ssn is 123-45-6789."), "My ssn is [REDACTED].");
+    }
+
+    #[test]
+    fn test_empty_config_is_identity() {
+        let value = RkyvSerializedValue::String("ada@example.com".to_string());
+        assert_eq!(RedactionConfig::default().redact(&value), value);
+    }
+}
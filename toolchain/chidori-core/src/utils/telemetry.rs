@@ -1,9 +1,11 @@
 use std::ops::Deref;
 use tracing::{Subscriber, span::{Attributes, Record}, Event, span, Metadata};
 use tracing_subscriber::{layer::Context, Layer, registry::LookupSpan, fmt};
+use std::collections::HashMap;
 use std::sync::mpsc::{channel, Sender};
 use std::sync::Arc;
-use std::time::Instant;
+use std::borrow::Cow;
+use std::time::{Duration, Instant, SystemTime};
 use tracing::subscriber::Interest;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing::field::{ValueSet, Visit, Field};
@@ -11,8 +13,14 @@ use std::fmt::Debug;
 use std::num::NonZero;
 use std::str::FromStr;
 pub use serde::Serialize;
+use opentelemetry::trace::{SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceId, TraceState};
+use opentelemetry::{InstrumentationLibrary, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::export::trace::{SpanData, SpanExporter};
+use opentelemetry_sdk::trace::{SpanEvents, SpanLinks};
 use uuid::Uuid;
 use crate::execution::execution::execution_graph::ExecutionNodeId;
+use crate::execution::primitives::identifiers::{OperationId, RunId};
 
 struct MatchStrVisitor<'a> {
     field: &'a str,
@@ -58,7 +66,10 @@ pub enum TraceEvents{
         target: String,
         location: String,
         line: String,
-        execution_id: Option<ExecutionNodeId>
+        execution_id: Option<ExecutionNodeId>,
+        operation_id: Option<OperationId>,
+        /// The run the span belongs to, recorded on the span or inherited from its parent
+        run_id: Option<RunId>,
     },
     Record,
     Event,
@@ -69,11 +80,188 @@ pub enum TraceEvents{
     Close(String, u128),
 }
 
+/// Convert a captured stream of trace events into the Chrome Trace Event format, viewable in
+/// chrome://tracing or Perfetto. Each span that was closed becomes a single complete ("X") event.
+pub fn export_chrome_trace(events: &[TraceEvents]) -> serde_json::Value {
+    let mut open_spans: HashMap<&str, &TraceEvents> = HashMap::new();
+    let mut trace_events = vec![];
+    for event in events {
+        match event {
+            TraceEvents::NewSpan { id, .. } => {
+                open_spans.insert(id.as_str(), event);
+            }
+            TraceEvents::Close(id, end) => {
+                // Span ids are recycled once closed, so they're removed here rather than looked up later
+                let Some(TraceEvents::NewSpan { weight: start, thread_id, name, target, location, line, execution_id, run_id, .. }) = open_spans.remove(id.as_str()) else {
+                    continue;
+                };
+                let mut args = serde_json::json!({
+                    "location": format!("{}:{}", location, line),
+                });
+                if let Some(execution_id) = execution_id {
+                    args["execution_id"] = serde_json::Value::String(execution_id.to_string());
+                }
+                if let Some(run_id) = run_id {
+                    args["run_id"] = serde_json::Value::String(run_id.to_string());
+                }
+                trace_events.push(serde_json::json!({
+                    "name": name,
+                    "cat": target,
+                    "ph": "X",
+                    // Chrome traces are measured in microseconds
+                    "ts": *start as f64 / 1000.0,
+                    "dur": end.saturating_sub(*start) as f64 / 1000.0,
+                    "pid": std::process::id(),
+                    "tid": thread_id.get(),
+                    "args": args,
+                }));
+            }
+            _ => {}
+        }
+    }
+    serde_json::json!({
+        "traceEvents": trace_events,
+        "displayTimeUnit": "ms",
+    })
+}
+
+/// The OpenTelemetry span id of a tracing span. Spans evaluating an operation take their id from
+/// the operation, mixed with the tracing span id so that evaluating it again yields a new span.
+fn otel_span_id(id: &str, operation_id: Option<OperationId>) -> SpanId {
+    let span_number: u64 = id.trim_start_matches("Id(").trim_end_matches(')').parse().unwrap_or(0);
+    let operation_bits = operation_id.map_or(0, |operation_id| {
+        let (_, low) = operation_id.as_u64_pair();
+        low
+    });
+    SpanId::from_bytes((operation_bits ^ span_number).to_be_bytes())
+}
+
+impl TraceEvents {
+    /// The event as an OpenTelemetry span. A `NewSpan` event opens a span in the trace of its run,
+    /// starting when the event was created, which is left open until the exporter closes it, see
+    /// `OtelSpanConverter`. Spans outside of any run have an invalid trace id. Any other event is
+    /// an instantaneous span named after the event.
+    pub fn to_otel_span(&self) -> SpanData {
+        let (trace_id, span_id, name, start_time, attributes) = match self {
+            TraceEvents::NewSpan { id, created_at, thread_id, name, target, location, line, execution_id, operation_id, run_id, .. } => {
+                let mut attributes = vec![
+                    KeyValue::new("target", target.clone()),
+                    KeyValue::new("code.filepath", location.clone()),
+                    KeyValue::new("code.lineno", line.clone()),
+                    KeyValue::new("thread.id", thread_id.get() as i64),
+                ];
+                if let Some(execution_id) = execution_id {
+                    attributes.push(KeyValue::new("chidori.execution_id", execution_id.to_string()));
+                }
+                if let Some(operation_id) = operation_id {
+                    attributes.push(KeyValue::new("chidori.operation_id", operation_id.to_string()));
+                }
+                if let Some(run_id) = run_id {
+                    attributes.push(KeyValue::new("chidori.run_id", run_id.to_string()));
+                }
+                let trace_id = run_id.map_or(TraceId::INVALID, |run_id| TraceId::from_bytes(run_id.into_bytes()));
+                (trace_id, otel_span_id(id, *operation_id), name.clone(), SystemTime::now() - created_at.elapsed(), attributes)
+            }
+            TraceEvents::Record => (TraceId::INVALID, SpanId::INVALID, "Record".to_string(), SystemTime::now(), vec![]),
+            TraceEvents::Event => (TraceId::INVALID, SpanId::INVALID, "Event".to_string(), SystemTime::now(), vec![]),
+            TraceEvents::Enter(id) => (TraceId::INVALID, otel_span_id(id, None), "Enter".to_string(), SystemTime::now(), vec![]),
+            TraceEvents::Exit(id, _) => (TraceId::INVALID, otel_span_id(id, None), "Exit".to_string(), SystemTime::now(), vec![]),
+            TraceEvents::Close(id, _) => (TraceId::INVALID, otel_span_id(id, None), "Close".to_string(), SystemTime::now(), vec![]),
+        };
+        SpanData {
+            span_context: SpanContext::new(trace_id, span_id, TraceFlags::SAMPLED, false, TraceState::default()),
+            parent_span_id: SpanId::INVALID,
+            span_kind: SpanKind::Internal,
+            name: Cow::Owned(name),
+            start_time,
+            end_time: start_time,
+            attributes,
+            dropped_attributes_count: 0,
+            events: SpanEvents::default(),
+            links: SpanLinks::default(),
+            status: Status::Unset,
+            instrumentation_lib: InstrumentationLibrary::new("chidori-core", None::<&str>, None::<&str>, None),
+        }
+    }
+}
+
+/// Pairs the `NewSpan` and `Close` events of a stream of trace events into OpenTelemetry spans,
+/// parenting each span to the span open when it was created. Spans share the trace of their parent,
+/// and root spans outside of any run land in the trace identified by the session id.
+pub struct OtelSpanConverter {
+    session_id: Uuid,
+    /// Spans not yet closed, with the weight of the event that opened them
+    open_spans: HashMap<String, (SpanData, u128)>,
+}
+
+impl OtelSpanConverter {
+    pub fn new(session_id: Uuid) -> Self {
+        Self { session_id, open_spans: HashMap::new() }
+    }
+
+    /// Take in the next event, returning the span it closes if any
+    pub fn receive(&mut self, event: TraceEvents) -> Option<SpanData> {
+        match &event {
+            TraceEvents::NewSpan { id, parent_id, weight, .. } => {
+                let mut span = event.to_otel_span();
+                let parent = parent_id.as_ref().and_then(|parent_id| self.open_spans.get(parent_id));
+                let trace_id = match parent {
+                    Some((parent, _)) => parent.span_context.trace_id(),
+                    None if span.span_context.trace_id() == TraceId::INVALID => TraceId::from_bytes(self.session_id.into_bytes()),
+                    None => span.span_context.trace_id(),
+                };
+                span.parent_span_id = parent.map_or(SpanId::INVALID, |(parent, _)| parent.span_context.span_id());
+                span.span_context = SpanContext::new(trace_id, span.span_context.span_id(), TraceFlags::SAMPLED, false, TraceState::default());
+                self.open_spans.insert(id.clone(), (span, *weight));
+                None
+            }
+            TraceEvents::Close(id, closed_at) => {
+                // Span ids are recycled once closed, so they're removed here rather than looked up later
+                let (mut span, opened_at) = self.open_spans.remove(id)?;
+                span.end_time = span.start_time + Duration::from_nanos(closed_at.saturating_sub(opened_at) as u64);
+                Some(span)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Export the trace events sent to the returned sender to the OTLP collector at `exporter_endpoint`,
+/// each span as it closes. Spans are converted with `OtelSpanConverter`, so spans outside of any
+/// run land in the trace identified by `session_id`.
+pub fn init_otlp_telemetry(exporter_endpoint: &str, session_id: Uuid) -> anyhow::Result<Sender<TraceEvents>> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let mut exporter = {
+        // The tonic channel is created within the runtime it will be driven by
+        let _guard = runtime.enter();
+        opentelemetry_otlp::new_exporter()
+            .tonic()
+            .with_endpoint(exporter_endpoint)
+            .build_span_exporter()?
+    };
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+        let mut converter = OtelSpanConverter::new(session_id);
+        for event in receiver {
+            if let Some(span) = converter.receive(event) {
+                // Logging through tracing here would feed back into the events being exported
+                if let Err(e) = runtime.block_on(exporter.export(vec![span])) {
+                    eprintln!("Failed to export span: {:?}", e);
+                }
+            }
+        }
+        exporter.shutdown();
+    });
+    Ok(sender)
+}
 
 struct Timing {
     started_at: Instant,
 }
 
+/// The run a span belongs to, kept on the span so that the spans nested within it inherit it
+struct SpanRunId(RunId);
+
 pub struct CustomLayer {
     sender: Sender<TraceEvents>,
     started_at: Instant,
@@ -108,6 +296,12 @@ impl<S> Layer<S> for CustomLayer
         let created_at = Instant::now();
         let weight = (Instant::now() - self.started_at).as_nanos();
         let thread_id = std::thread::current().id().as_u64();
+        let run_id = get_value_in_valueset(attrs.values(), "run_id")
+            .and_then(|s| Uuid::from_str(&s).ok())
+            .or_else(|| span.parent().and_then(|parent| parent.extensions().get::<SpanRunId>().map(|r| r.0)));
+        if let Some(run_id) = run_id {
+            span.extensions_mut().insert(SpanRunId(run_id));
+        }
         self.sender.send(TraceEvents::NewSpan {
             id: format!("{:?}", id),
             parent_id: span.parent().map(|p| format!("{:?}", p.id())),
@@ -121,7 +315,10 @@ impl<S> Layer<S> for CustomLayer
             execution_id: get_value_in_valueset(attrs.values(), "prev_execution_id").map(|s| {
                 // TODO: test this
                 Uuid::from_str(&s).unwrap_or(Uuid::nil())
-            })
+            }),
+            operation_id: get_value_in_valueset(attrs.values(), "operation_id")
+                .and_then(|s| Uuid::from_str(&s).ok()),
+            run_id,
         }).unwrap();
     }
 
@@ -212,3 +409,61 @@ pub fn init_test_telemetry() -> impl Subscriber {
         .with(forwarding_layer);
     subscriber
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_span(id: &str, parent_id: Option<&str>, weight: u128, operation_id: Option<OperationId>) -> TraceEvents {
+        TraceEvents::NewSpan {
+            id: id.to_string(),
+            created_at: Instant::now(),
+            thread_id: NonZero::new(1).unwrap(),
+            parent_id: parent_id.map(|p| p.to_string()),
+            weight,
+            name: "evaluate_staged_operation".to_string(),
+            target: "chidori_core::execution".to_string(),
+            location: "execution_state.rs".to_string(),
+            line: "1358".to_string(),
+            execution_id: None,
+            operation_id,
+            run_id: None,
+        }
+    }
+
+    #[test]
+    fn test_to_otel_span_carries_name_and_timing() {
+        let run_id = Uuid::now_v7();
+        let operation_id = Uuid::now_v7();
+        let mut event = new_span("Id(2)", None, 1_000_000, Some(operation_id));
+        if let TraceEvents::NewSpan { run_id: span_run_id, created_at, .. } = &mut event {
+            *span_run_id = Some(run_id);
+            *created_at -= Duration::from_secs(5);
+        }
+        let span = event.to_otel_span();
+        assert_eq!(span.name, "evaluate_staged_operation");
+        let age = SystemTime::now().duration_since(span.start_time).unwrap();
+        assert!(age >= Duration::from_secs(5) && age < Duration::from_secs(6));
+        assert_eq!(span.end_time, span.start_time);
+        assert_eq!(span.span_context.trace_id(), TraceId::from_bytes(run_id.into_bytes()));
+        assert_eq!(span.span_context.span_id(), otel_span_id("Id(2)", Some(operation_id)));
+        assert!(span.attributes.contains(&KeyValue::new("chidori.operation_id", operation_id.to_string())));
+        assert_eq!(TraceEvents::Enter("Id(2)".to_string()).to_otel_span().name, "Enter");
+    }
+
+    #[test]
+    fn test_converter_parents_spans() {
+        let session_id = Uuid::now_v7();
+        let mut converter = OtelSpanConverter::new(session_id);
+        assert!(converter.receive(new_span("Id(1)", None, 0, None)).is_none());
+        assert!(converter.receive(new_span("Id(2)", Some("Id(1)"), 100, Some(Uuid::now_v7()))).is_none());
+        let child = converter.receive(TraceEvents::Close("Id(2)".to_string(), 400)).unwrap();
+        assert_eq!(child.parent_span_id, otel_span_id("Id(1)", None));
+        assert_eq!(child.end_time.duration_since(child.start_time).unwrap(), Duration::from_nanos(300));
+        let parent = converter.receive(TraceEvents::Close("Id(1)".to_string(), 500)).unwrap();
+        assert_eq!(parent.parent_span_id, SpanId::INVALID);
+        assert_eq!(parent.span_context.trace_id(), TraceId::from_bytes(session_id.into_bytes()));
+        assert_eq!(child.span_context.trace_id(), parent.span_context.trace_id());
+        assert_eq!(parent.end_time.duration_since(parent.start_time).unwrap(), Duration::from_nanos(500));
+    }
+}
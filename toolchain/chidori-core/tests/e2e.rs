@@ -4,10 +4,22 @@ use super::*;
 use chidori_core::execution::primitives::serialized_value::{RkyvObjectBuilder, RkyvSerializedValue};
 use indoc::indoc;
 use uuid::Uuid;
-use chidori_core::cells::{CellTypes, CodeCell, LLMPromptCell, LLMPromptCellChatConfiguration, SupportedLanguage, SupportedModelProviders, TextRange};
-use chidori_core::sdk::interactive_chidori_wrapper::InteractiveChidoriWrapper;
-use chidori_core::sdk::chidori_runtime_instance::ChidoriRuntimeInstance;
+use chidori_core::cells::{CellTypes, CodeCell, ExecutionPolicy, LLMPromptCell, LLMPromptCellChatConfiguration, RequestConcurrency, SignatureAlgorithm, SupportedLanguage, SupportedModelProviders, TemplateCell, TextRange, WebhookCell, DEFAULT_WEBHOOK_MAX_BODY_BYTES};
+#[cfg(feature = "arrow")]
+use chidori_core::cells::{FileCell, TableFileFormat};
+#[cfg(feature = "arrow")]
+use chidori_core::execution::primitives::table::read_table_file;
+use chidori_core::sdk::interactive_chidori_wrapper::{EventsFromRuntime, InteractiveChidoriWrapper};
+use chidori_core::sdk::chidori_runtime_instance::{ChidoriRuntimeInstance, PlaybackState, UserInteractionMessage};
 use chidori_core::utils;
+use chidori_core::execution::execution::html_report::HtmlReportOptions;
+use chidori_core::execution::execution::execution_state::{DefinitionIssue, ExecutionStateErrors};
+use chidori_core::execution::execution::stream::{stream_manifest, STREAM_CHUNK_ITEMS};
+use chidori_core::utils::prompt_audit::PromptAuditLog;
+use chidori_core::sdk::checkpoint::{checkpoint_directory, CheckpointConfig};
+use chidori_core::execution::execution::repro::ReproFailure;
+use chidori_core::sdk::event_subscriptions::{EventFilter, EventKind};
+use chidori_core::library::std::template::TemplateRenderError;
 
 #[tokio::test]
 async fn test_execute_cells_with_global_dependency() -> anyhow::Result<()> {
@@ -20,6 +32,13 @@ async fn test_execute_cells_with_global_dependency() -> anyhow::Result<()> {
                         x = 20
                         "#}),
         function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
     }, TextRange::default()),
                                        Uuid::now_v7())?;
     let (_, op_id_y) = env.upsert_cell(CellTypes::Code(CodeCell {
@@ -30,6 +49,13 @@ async fn test_execute_cells_with_global_dependency() -> anyhow::Result<()> {
                         y = x + 1
                         "#}),
         function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
     }, TextRange::default()),
                                        Uuid::now_v7())?;
     // env.resolve_dependencies_from_input_signature();
@@ -51,6 +77,1416 @@ async fn test_execute_cells_with_global_dependency() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_on_resolve_input_overrides_cell_inputs() -> anyhow::Result<()> {
+    let mut env = ChidoriRuntimeInstance::new();
+    env.wait_until_ready().await?;
+    let (_, op_id_x) = env.upsert_cell(CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: None,
+        language: SupportedLanguage::PyO3,
+        source_code: String::from(indoc! { r#"
+                        x = 20
+                        "#}),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default()),
+                                       Uuid::now_v7()).await?;
+    let (_, op_id_y) = env.upsert_cell(CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: None,
+        language: SupportedLanguage::PyO3,
+        source_code: String::from(indoc! { r#"
+                        y = x + 1
+                        "#}),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default()),
+                                       Uuid::now_v7()).await?;
+
+    // Replace the resolved value of `x` for the downstream cell only
+    env.on_resolve_input(Box::new(move |op_id, payload| {
+        if op_id != op_id_y {
+            return payload;
+        }
+        let RkyvSerializedValue::Object(mut inputs) = payload else { return payload; };
+        if let Some(RkyvSerializedValue::Object(globals)) = inputs.get_mut("globals") {
+            globals.insert("x".to_string(), RkyvSerializedValue::Number(100));
+        }
+        RkyvSerializedValue::Object(inputs)
+    }));
+
+    env.step().await?;
+    assert_eq!(
+        env.get_state_at_current_execution_head().state_get_value(&op_id_x),
+        Some(&Ok(RkyvObjectBuilder::new().insert_number("x", 20).build()))
+    );
+    env.step().await?;
+    assert_eq!(
+        env.get_state_at_current_execution_head().state_get_value(&op_id_y),
+        Some(&Ok(RkyvObjectBuilder::new().insert_number("y", 101).build()))
+    );
+    env.shutdown().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_execution_state_cells_view_reflects_historical_source() -> anyhow::Result<()> {
+    let mut env = ChidoriRuntimeInstance::new();
+    let runtime_event_receiver = env.runtime_events.subscribe(EventFilter::all());
+    env.wait_until_ready().await?;
+
+    let code_cell = |source: &str| CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: None,
+        language: SupportedLanguage::PyO3,
+        source_code: source.to_string(),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default());
+    let last_cells_view = |rx: &std::sync::mpsc::Receiver<EventsFromRuntime>| {
+        rx.try_iter().filter_map(|event| match event {
+            EventsFromRuntime::ExecutionStateCellsViewUpdated(cells) => Some(cells),
+            _ => None,
+        }).last()
+    };
+
+    let (_, op_id) = env.upsert_cell(code_cell("x = 1"), Uuid::now_v7()).await?;
+    env.step().await?;
+    let historical_head = env.execution_head_state_id;
+
+    env.upsert_cell(code_cell("x = 2"), op_id).await?;
+    env.step().await?;
+    let cells = last_cells_view(&runtime_event_receiver).expect("cells view should be emitted when the cell changes");
+    assert!(matches!(&cells[0].cell, CellTypes::Code(c, _) if c.source_code == "x = 2"));
+
+    env.revert_to_state(historical_head);
+    let cells = last_cells_view(&runtime_event_receiver).expect("cells view should be emitted on revert");
+    assert_eq!(cells.len(), 1);
+    assert_eq!(cells[0].op_id, op_id);
+    assert!(matches!(&cells[0].cell, CellTypes::Code(c, _) if c.source_code == "x = 1"));
+    env.shutdown().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_auto_play_starts_running_after_cells_load() -> anyhow::Result<()> {
+    let mut ee = InteractiveChidoriWrapper::new();
+    ee.enable_auto_play(true);
+    ee.load_md_string(indoc! { r#"
+            ```python
+            x = 20
+            ```
+            "#
+            })?;
+    let mut env = ee.get_instance()?;
+    assert_eq!(env.playback_state, PlaybackState::Paused);
+    env.reload_cells().await?;
+    assert_eq!(env.playback_state, PlaybackState::Running);
+
+    let mut ee = InteractiveChidoriWrapper::new();
+    ee.load_md_string(indoc! { r#"
+            ```python
+            x = 20
+            ```
+            "#
+            })?;
+    let mut env = ee.get_instance()?;
+    env.reload_cells().await?;
+    assert_eq!(env.playback_state, PlaybackState::Paused);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cell_description_is_given_to_tool_schema() -> anyhow::Result<()> {
+    let mut ee = InteractiveChidoriWrapper::new();
+    ee.load_md_string(indoc! { r#"
+            # Arithmetic
+
+            Adds two numbers
+            together.
+
+            ```python (add)
+            def add(x: int, y: int):
+                return x + y
+            ```
+
+            ```python (double)
+            def double(x: int):
+                return x * 2
+            ```
+            "#
+            })?;
+    let described = ee.describe();
+    assert_eq!(described[0]["name"], "add");
+    assert_eq!(described[0]["kind"], "python");
+    assert_eq!(described[0]["description"], "Adds two numbers together.");
+    assert_eq!(described[1]["description"], serde_json::Value::Null);
+
+    let mut env = ee.get_instance()?;
+    env.reload_cells().await?;
+    let state = env.get_state_at_current_execution_head();
+    let cells = state.get_cells_in_operation_order();
+    let op_id = |name: &str| cells.iter().find(|(_, cell)| cell.name().as_deref() == Some(name)).unwrap().0;
+    assert_eq!(env.tool_schema(op_id("add"))?[0]["function"]["description"], "Adds two numbers together.");
+    assert!(env.tool_schema(op_id("double"))?[0]["function"].get("description").is_none());
+
+    // The description survives saving the program and loading it back
+    let saved = ee.to_markdown()?;
+    let mut reloaded = InteractiveChidoriWrapper::new();
+    reloaded.load_md_string(&saved)?;
+    assert_eq!(reloaded.describe()[0]["description"], "Adds two numbers together.");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_describe_lists_webhook_routes() -> anyhow::Result<()> {
+    let mut ee = InteractiveChidoriWrapper::new();
+    let document = indoc! { r#"
+            ```webhook (api)
+            port: 0
+            path: /hook
+            routes: |
+              GET /add/:a add(a, b)
+            ```
+            "#
+            };
+    ee.load_md_string(document)?;
+    let described = ee.describe();
+    let route = &described[0]["routes"][0];
+    assert_eq!(route["method"], "GET");
+    assert_eq!(route["handler"], "add");
+    assert_eq!(route["params"], serde_json::json!(["a", "b"]));
+    let (start, end) = (route["range"][0].as_u64().unwrap() as usize, route["range"][1].as_u64().unwrap() as usize);
+    assert_eq!(&document[start..end], "GET /add/:a add(a, b)");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_bare_fence_uses_default_language() -> anyhow::Result<()> {
+    let mut ee = InteractiveChidoriWrapper::new();
+    ee.set_default_language(Some(SupportedLanguage::PyO3));
+    ee.load_md_string(indoc! { r#"
+            ```
+            x = 1
+            ```
+            "#
+            })?;
+    let mut env = ee.get_instance()?;
+    env.reload_cells().await?;
+    env.step().await?;
+    let state = env.get_state_at_current_execution_head();
+    let (op_id, cell) = state.get_cells_in_operation_order().remove(0);
+    assert!(matches!(cell, CellTypes::Code(CodeCell { language: SupportedLanguage::PyO3, .. }, _)));
+    assert_eq!(
+        state.state_get_value(&op_id),
+        Some(&Ok(RkyvObjectBuilder::new().insert_number("x", 1).build()))
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_instance_for_subgraph_excludes_unrelated_cells() -> anyhow::Result<()> {
+    let mut ee = InteractiveChidoriWrapper::new();
+    ee.load_md_string(indoc! { r#"
+            ```python (ingest)
+            raw = 10
+            ```
+
+            ```python (transform)
+            cooked = raw * 2
+            ```
+
+            ```python (unrelated)
+            other = 5
+            ```
+            "#
+            })?;
+    assert!(ee.get_instance_for_subgraph(&["missing".to_string()]).is_err());
+
+    let mut env = ee.get_instance_for_subgraph(&["transform".to_string()])?;
+    env.reload_cells().await?;
+    env.step().await?;
+    env.step().await?;
+    assert!(env.step().await.is_err());
+
+    let state = env.get_state_at_current_execution_head();
+    let cells = state.get_cells_in_operation_order();
+    assert_eq!(cells.len(), 2);
+    let names: HashSet<_> = cells.iter().filter_map(|(_, cell)| cell.name().clone()).collect();
+    assert_eq!(names, HashSet::from(["ingest".to_string(), "transform".to_string()]));
+    let (transform_id, _) = cells.iter().find(|(_, cell)| cell.name().as_deref() == Some("transform")).unwrap();
+    assert_eq!(
+        state.state_get_value(transform_id),
+        Some(&Ok(RkyvObjectBuilder::new().insert_number("cooked", 20).build()))
+    );
+    Ok(())
+}
+
+fn cell_source(state: &chidori_core::execution::execution::ExecutionState, name: &str) -> String {
+    let (_, cell) = state.get_cells_in_operation_order().into_iter()
+        .find(|(_, cell)| cell.name().as_deref() == Some(name))
+        .unwrap();
+    match cell {
+        CellTypes::Code(c, _) => c.source_code.trim().to_string(),
+        _ => panic!("Expected a code cell"),
+    }
+}
+
+#[tokio::test]
+async fn test_reload_applies_coordinated_rename_atomically() -> anyhow::Result<()> {
+    let mut ee = InteractiveChidoriWrapper::new();
+    ee.load_md_string(indoc! { r#"
+            ```python (a)
+            x = 1
+            ```
+
+            ```python (b)
+            y = x + 1
+            ```
+
+            ```python (c)
+            z = y + 1
+            ```
+            "#
+            })?;
+    let mut env = ee.get_instance()?;
+    env.reload_cells().await?;
+    let before = env.get_state_at_current_execution_head();
+
+    ee.load_md_string(indoc! { r#"
+            ```python (a)
+            u = 1
+            ```
+
+            ```python (b)
+            v = u + 1
+            ```
+
+            ```python (c)
+            z = v + 1
+            ```
+            "#
+            })?;
+    env.reload_cells().await?;
+
+    // All three cells are applied in a single revision of the state
+    let after = env.get_state_at_current_execution_head();
+    assert_eq!(after.parent_state_chronology_id, before.chronology_id);
+    assert_eq!(cell_source(&after, "a"), "u = 1");
+    assert_eq!(cell_source(&after, "b"), "v = u + 1");
+    assert_eq!(cell_source(&after, "c"), "z = v + 1");
+
+    env.step().await?;
+    env.step().await?;
+    env.step().await?;
+    let state = env.get_state_at_current_execution_head();
+    let (c_id, _) = state.get_cells_in_operation_order().into_iter()
+        .find(|(_, cell)| cell.name().as_deref() == Some("c"))
+        .unwrap();
+    assert_eq!(state.state_get_value(&c_id), Some(&Ok(RkyvObjectBuilder::new().insert_number("z", 3).build())));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rejected_reload_leaves_running_graph_untouched() -> anyhow::Result<()> {
+    let mut ee = InteractiveChidoriWrapper::new();
+    let runtime_event_receiver = ee.runtime_events.subscribe(EventFilter::all());
+    ee.load_md_string(indoc! { r#"
+            ```python (a)
+            x = 1
+            ```
+
+            ```python (b)
+            y = x + 1
+            ```
+            "#
+            })?;
+    let mut env = ee.get_instance()?;
+    env.reload_cells().await?;
+    let before = env.get_state_at_current_execution_head();
+
+    // Renaming x without updating its dependent leaves b unresolved
+    ee.load_md_string(indoc! { r#"
+            ```python (a)
+            w = 1
+            ```
+
+            ```python (b)
+            y = x + 1
+            ```
+            "#
+            })?;
+    env.reload_cells().await?;
+
+    let after = env.get_state_at_current_execution_head();
+    assert_eq!(after.chronology_id, before.chronology_id);
+    assert_eq!(cell_source(&after, "a"), "x = 1");
+
+    let report = runtime_event_receiver.try_iter().find_map(|event| match event {
+        EventsFromRuntime::ReloadRejected(report) => Some(report),
+        _ => None,
+    }).expect("Expected the reload to be rejected");
+    assert_eq!(report.issues, vec![DefinitionIssue::UnresolvedSymbol { cell: "b".to_string(), symbol: "x".to_string() }]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ancestry_follows_step_sequence() -> anyhow::Result<()> {
+    let mut env = ChidoriRuntimeInstance::new();
+    env.wait_until_ready().await?;
+    for source in ["a = 1", "b = a + 1", "c = b + 1"] {
+        env.upsert_cell(CellTypes::Code(CodeCell {
+            backing_file_reference: None,
+            name: None,
+            language: SupportedLanguage::PyO3,
+            source_code: source.to_string(),
+            function_invocation: None,
+            inspect_globals: false,
+            redact_output: false,
+            policy: Default::default(),
+            oom_limit_bytes: None,
+            always_run: false,
+            generate_types: false,
+            package_json: None,
+        }, TextRange::default()), Uuid::now_v7()).await?;
+    }
+    let loaded_head = env.execution_head_state_id;
+
+    let mut step_heads = vec![];
+    for _ in 0..3 {
+        env.step().await?;
+        step_heads.push(env.execution_head_state_id);
+    }
+
+    let ancestry = env.db.ancestry(env.execution_head_state_id);
+    // The root, one state per upserted cell, then an opening and closing state per step
+    assert_eq!(ancestry.len(), 1 + 3 + 3 * 2);
+    assert_eq!(ancestry[0], Uuid::nil());
+    assert_eq!(ancestry[3], loaded_head);
+    assert_eq!(vec![ancestry[5], ancestry[7], ancestry[9]], step_heads);
+    assert!(env.db.ancestry(Uuid::now_v7()).is_empty());
+    env.shutdown().await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_nested_function_invocations_record_invocation_chain() -> anyhow::Result<()> {
+    // Each level stands in for a prompt invoking a function that in turn invokes another
+    let mut ee = InteractiveChidoriWrapper::new();
+    ee.load_md_string(indoc! { r#"
+            ```python
+            def level_3():
+                return 1
+            ```
+
+            ```python
+            async def level_2():
+                return await level_3() + 1
+            ```
+
+            ```python
+            async def level_1():
+                return await level_2() + 1
+            ```
+
+            ```python
+            result = await level_1()
+            ```
+            "#
+            })?;
+    let mut env = ee.get_instance()?;
+    env.reload_cells().await?;
+    while env.step().await.is_ok() {}
+
+    let state = env.get_state_at_current_execution_head();
+    let (result_id, _) = state.get_cells_in_operation_order().remove(3);
+    assert_eq!(
+        state.state_get_value(&result_id),
+        Some(&Ok(RkyvObjectBuilder::new().insert_number("result", 3).build()))
+    );
+
+    let innermost = env.db.get_execution_graph_elements()
+        .into_iter()
+        .filter_map(|(_, id)| env.db.get_state_at_id(id))
+        .find(|s| s.evaluating_fn.as_deref() == Some("level_3"))
+        .expect("level_3 should have been invoked");
+    assert_eq!(innermost.invocation_chain, vec!["level_1", "level_2", "level_3"]);
+    assert!(env.get_state_at_current_execution_head().invocation_chain.is_empty());
+    env.shutdown().await;
+    Ok(())
+}
+
+/// Serves chat completions, calling the `lookup` tool when the request offers tools and otherwise
+/// answering "sunny"
+async fn spawn_tool_calling_model() -> anyhow::Result<String> {
+    let app = axum::Router::new().route("/v1/chat/completions", axum::routing::post(|axum::Json(request): axum::Json<serde_json::Value>| async move {
+        let message = if request.get("tools").map_or(false, |tools| !tools.is_null()) {
+            serde_json::json!({ "role": "assistant", "content": null, "tool_calls": [{
+                "id": "call_1",
+                "type": "function",
+                "function": { "name": "lookup", "arguments": "{\"city\": \"Paris\"}" },
+            }] })
+        } else {
+            serde_json::json!({ "role": "assistant", "content": "sunny" })
+        };
+        axum::Json(serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "gpt-3.5-turbo",
+            "choices": [{ "index": 0, "message": message, "finish_reason": "stop" }],
+            "usage": { "prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2 },
+        }))
+    }));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let api_url = format!("http://{}/v1", listener.local_addr()?);
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    Ok(api_url)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_code_prompt_code_prompt_invocation_chain() -> anyhow::Result<()> {
+    let api_url = spawn_tool_calling_model().await?;
+    let mut ee = InteractiveChidoriWrapper::new();
+    ee.load_md_string(&format!(indoc! { r#"
+            ```python (entry)
+            result = await plan(city="Paris")
+            ```
+
+            ```prompt (planner)
+            ---
+            fn: plan
+            api_url: {api_url}
+            import:
+              - lookup
+            ---
+            Plan a trip to {{{{city}}}}
+            ```
+
+            ```python (lookup_fn)
+            async def lookup(city):
+                return await weather(city=city)
+            ```
+
+            ```prompt (forecaster)
+            ---
+            fn: weather
+            api_url: {api_url}
+            ---
+            What is the weather in {{{{city}}}}
+            ```
+            "#
+            }, api_url = api_url))?;
+    let mut env = ee.get_instance()?;
+    env.reload_cells().await?;
+    while env.step().await.is_ok() {}
+
+    let state = env.get_state_at_current_execution_head();
+    let entry_id = state.operation_name_to_id["entry"];
+    assert_eq!(
+        state.state_get_value(&entry_id),
+        Some(&Ok(RkyvObjectBuilder::new()
+            .insert_value("result", RkyvObjectBuilder::new().insert_string("lookup", "sunny".to_string()).build())
+            .build()))
+    );
+
+    let innermost = env.db.get_execution_graph_elements()
+        .into_iter()
+        .filter_map(|(_, id)| env.db.get_state_at_id(id))
+        .find(|s| s.evaluating_fn.as_deref() == Some("weather"))
+        .expect("weather should have been invoked");
+    assert_eq!(innermost.invocation_chain, vec!["plan", "lookup", "weather"]);
+    assert!(env.get_state_at_current_execution_head().invocation_chain.is_empty());
+    env.shutdown().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_export_chrome_trace_from_run() -> anyhow::Result<()> {
+    let (trace_event_sender, trace_event_receiver) = std::sync::mpsc::channel();
+    let _guard = tracing::subscriber::set_default(utils::telemetry::init_internal_telemetry(trace_event_sender));
+
+    let mut env = ChidoriRuntimeInstance::new();
+    env.upsert_cell(CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: None,
+        language: SupportedLanguage::PyO3,
+        source_code: String::from("x = 20"),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    env.step().await?;
+    drop(_guard);
+
+    let events: Vec<_> = trace_event_receiver.try_iter().collect();
+    let closed_spans = events.iter().filter(|e| matches!(e, utils::telemetry::TraceEvents::Close(..))).count();
+    assert!(closed_spans > 0);
+
+    let exported = serde_json::to_string(&utils::telemetry::export_chrome_trace(&events))?;
+    let parsed: serde_json::Value = serde_json::from_str(&exported)?;
+    let trace_events = parsed["traceEvents"].as_array().unwrap();
+    assert_eq!(trace_events.len(), closed_spans);
+    for event in trace_events {
+        assert_eq!(event["ph"], "X");
+        assert!(event["name"].is_string());
+        assert!(event["ts"].is_number());
+        assert!(event["dur"].as_f64().unwrap() >= 0.0);
+        assert!(event["tid"].is_u64());
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_export_html_report_for_core1() -> anyhow::Result<()> {
+    let mut ee = InteractiveChidoriWrapper::new();
+    ee.load_md_directory(Path::new("./examples/core1_simple_math"))?;
+    let mut env = ee.get_instance()?;
+    env.reload_cells().await?;
+    env.step().await?;
+    env.step().await?;
+    env.step().await?;
+
+    let path = std::env::temp_dir().join(format!("chidori-report-{}.html", Uuid::now_v7()));
+    env.db.export_html_report(&path, &HtmlReportOptions::default())?;
+    let report = std::fs::read_to_string(&path)?;
+    std::fs::remove_file(&path)?;
+
+    // Cells and their final values are embedded, with no external assets
+    assert!(report.contains("x = 20"));
+    assert!(report.contains("y = x * 20"));
+    assert!(report.contains("const zj = y + 20;"));
+    assert!(report.contains("420"));
+    assert!(report.contains("<style>"));
+    assert!(report.contains("<script>"));
+    assert!(!report.contains("<link"));
+    assert!(!report.contains("src=\""));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_run_summary_of_core1() -> anyhow::Result<()> {
+    let mut ee = InteractiveChidoriWrapper::new();
+    ee.load_md_directory(Path::new("./examples/core1_simple_math"))?;
+    let mut env = ee.get_instance()?;
+    env.reload_cells().await?;
+    env.step().await?;
+    env.step().await?;
+    env.step().await?;
+
+    let summary = env.run_summary()?;
+    assert_eq!(summary.run_id, env.run_id());
+    assert!(summary.quiescent);
+    assert_eq!(summary.steps, 3);
+    assert_eq!(summary.operations_executed, 3);
+    assert!(summary.errors.is_empty());
+    assert_eq!(summary.total_tokens, 0);
+    // Only the javascript cell has no dependents
+    assert_eq!(summary.leaf_outputs.values().collect::<Vec<_>>(), vec![&serde_json::json!({ "zj": 420 })]);
+    let json = serde_json::to_value(&summary)?;
+    assert_eq!(json["steps"], 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_webhook_cell_forwards_only_verified_payloads() -> anyhow::Result<()> {
+    use hmac::{Hmac, Mac};
+
+    std::env::set_var("CHIDORI_TEST_GITHUB_WEBHOOK_SECRET", "shhh");
+    let mut env = ChidoriRuntimeInstance::new();
+    env.wait_until_ready().await?;
+    let (_, webhook_op) = env.upsert_cell(CellTypes::Webhook(WebhookCell {
+        name: Some("github_push".to_string()),
+        host: "127.0.0.1".to_string(),
+        port: 0,
+        path: "/github".to_string(),
+        secret_env: Some("CHIDORI_TEST_GITHUB_WEBHOOK_SECRET".to_string()),
+        signature_header: "X-Hub-Signature-256".to_string(),
+        signature_algorithm: SignatureAlgorithm::HmacSha256,
+        handler: None,
+        record_requests: false,
+        concurrency: RequestConcurrency::Concurrent,
+        max_body_bytes: DEFAULT_WEBHOOK_MAX_BODY_BYTES,
+        middleware: vec![],
+        routes: String::new(),
+        route_table: Default::default(),
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    let (_, op_id_action) = env.upsert_cell(CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: None,
+        language: SupportedLanguage::PyO3,
+        source_code: String::from("action = github_push[\"action\"]"),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default()), Uuid::now_v7()).await?;
+
+    // Starts the listener
+    env.step().await?;
+    while env.rx_execution_states.try_recv().is_ok() {}
+
+    let body = r#"{"action": "opened"}"#;
+    let url = format!("http://127.0.0.1:{}/github", env.webhook_address(webhook_op).expect("the listener should be started").port());
+    let client = reqwest::Client::new();
+    let res = client.post(&url)
+        .header("X-Hub-Signature-256", "sha256=0000")
+        .body(body)
+        .send()
+        .await?;
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    assert!(env.rx_execution_states.try_recv().is_err());
+
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(b"shhh")?;
+    mac.update(body.as_bytes());
+    let signature = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+    let res = client.post(&url)
+        .header("X-Hub-Signature-256", signature)
+        .body(body)
+        .send()
+        .await?;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    let delivery = env.rx_execution_states.recv().await.expect("payload should be recorded as a new state");
+    assert!(delivery.is_webhook_delivery());
+    env.revert_to_state(delivery.chronology_id);
+    env.step().await?;
+    assert_eq!(
+        env.get_state_at_current_execution_head().state_get_value(&op_id_action),
+        Some(&Ok(RkyvObjectBuilder::new().insert_string("action", "opened".to_string()).build()))
+    );
+    env.shutdown().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_webhook_handler_answers_concurrent_requests_on_separate_branches() -> anyhow::Result<()> {
+    let mut env = ChidoriRuntimeInstance::new();
+    env.wait_until_ready().await?;
+    env.upsert_cell(CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: None,
+        language: SupportedLanguage::PyO3,
+        source_code: String::from(indoc! { r#"
+            def add(a, b):
+                return a + b
+            "#}),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    let (_, webhook_op) = env.upsert_cell(CellTypes::Webhook(WebhookCell {
+        name: Some("add_route".to_string()),
+        host: "127.0.0.1".to_string(),
+        port: 0,
+        path: "/add".to_string(),
+        secret_env: None,
+        signature_header: "X-Hub-Signature-256".to_string(),
+        signature_algorithm: SignatureAlgorithm::HmacSha256,
+        handler: Some("add".to_string()),
+        record_requests: true,
+        concurrency: RequestConcurrency::Concurrent,
+        max_body_bytes: DEFAULT_WEBHOOK_MAX_BODY_BYTES,
+        middleware: vec![],
+        routes: String::new(),
+        route_table: Default::default(),
+    }, TextRange::default()), Uuid::now_v7()).await?;
+
+    // Defines the handler and starts the listener
+    env.step().await?;
+    env.step().await?;
+    while env.rx_execution_states.try_recv().is_ok() {}
+
+    let url = format!("http://127.0.0.1:{}/add", env.webhook_address(webhook_op).expect("the listener should be started").port());
+    let client = reqwest::Client::new();
+    let requests = (0..20).map(|i| {
+        let (client, url) = (client.clone(), url.clone());
+        async move {
+            let res = client.post(&url)
+                .json(&serde_json::json!({"a": i, "b": i * 10}))
+                .send()
+                .await?;
+            assert_eq!(res.status(), reqwest::StatusCode::OK);
+            anyhow::Ok((i, res.json::<serde_json::Value>().await?))
+        }
+    });
+    for result in futures_util::future::join_all(requests).await {
+        let (i, response) = result?;
+        assert_eq!(response, serde_json::json!(i * 11));
+    }
+
+    let mut parents = HashSet::new();
+    for _ in 0..20 {
+        let request = tokio::time::timeout(std::time::Duration::from_secs(5), env.rx_execution_states.recv())
+            .await?
+            .expect("each request should be recorded as a new state");
+        assert!(request.is_recorded_request());
+        parents.insert(request.parent_state_chronology_id);
+    }
+    assert_eq!(parents.len(), 1);
+    env.shutdown().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_webhook_routes_are_served_by_their_handlers() -> anyhow::Result<()> {
+    let mut env = ChidoriRuntimeInstance::new();
+    env.wait_until_ready().await?;
+    env.upsert_cell(CellTypes::Code(CodeCell {
+        language: SupportedLanguage::PyO3,
+        source_code: String::from(indoc! { r#"
+            def add(a, b):
+                return int(a) + b
+            "#}),
+        ..Default::default()
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    let (_, webhook_op) = env.upsert_cell(CellTypes::Webhook(WebhookCell {
+        name: Some("api".to_string()),
+        host: "127.0.0.1".to_string(),
+        port: 0,
+        path: "/hook".to_string(),
+        secret_env: None,
+        signature_header: "X-Hub-Signature-256".to_string(),
+        signature_algorithm: SignatureAlgorithm::HmacSha256,
+        handler: None,
+        record_requests: false,
+        concurrency: RequestConcurrency::Concurrent,
+        max_body_bytes: DEFAULT_WEBHOOK_MAX_BODY_BYTES,
+        middleware: vec![],
+        routes: "GET /add/:a add(a, b)\n".to_string(),
+        route_table: Default::default(),
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    env.step().await?;
+    env.step().await?;
+
+    let base = format!("http://127.0.0.1:{}", env.webhook_address(webhook_op).expect("the listener should be started").port());
+    let client = reqwest::Client::new();
+    let res = client.get(format!("{}/add/1?b=2", base)).send().await?;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(res.json::<serde_json::Value>().await?, serde_json::json!(3));
+
+    let res = client.get(format!("{}/add/1", base)).send().await?;
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+    env.shutdown().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_webhook_handler_controls_status_and_headers() -> anyhow::Result<()> {
+    let mut env = ChidoriRuntimeInstance::new();
+    env.wait_until_ready().await?;
+    env.upsert_cell(CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: None,
+        language: SupportedLanguage::PyO3,
+        source_code: String::from(indoc! { r#"
+            def lookup(id):
+                if id == "known":
+                    return {"name": "Known"}
+                return {"status": 404, "headers": {"X-Missing": id}, "body": "Not found"}
+            "#}),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    let (_, webhook_op) = env.upsert_cell(CellTypes::Webhook(WebhookCell {
+        name: Some("lookup_route".to_string()),
+        host: "127.0.0.1".to_string(),
+        port: 0,
+        path: "/lookup".to_string(),
+        secret_env: None,
+        signature_header: "X-Hub-Signature-256".to_string(),
+        signature_algorithm: SignatureAlgorithm::HmacSha256,
+        handler: Some("lookup".to_string()),
+        record_requests: false,
+        concurrency: RequestConcurrency::Concurrent,
+        max_body_bytes: DEFAULT_WEBHOOK_MAX_BODY_BYTES,
+        middleware: vec![],
+        routes: String::new(),
+        route_table: Default::default(),
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    env.step().await?;
+    env.step().await?;
+
+    let url = format!("http://127.0.0.1:{}/lookup", env.webhook_address(webhook_op).expect("the listener should be started").port());
+    let client = reqwest::Client::new();
+    let res = client.post(&url)
+        .json(&serde_json::json!({"id": "unknown"}))
+        .send()
+        .await?;
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+    assert_eq!(res.headers().get("X-Missing").unwrap(), "unknown");
+    assert_eq!(res.text().await?, "Not found");
+
+    let res = client.post(&url)
+        .json(&serde_json::json!({"id": "known"}))
+        .send()
+        .await?;
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(res.json::<serde_json::Value>().await?, serde_json::json!({"name": "Known"}));
+    env.shutdown().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_serial_webhook_route_does_not_interleave_requests() -> anyhow::Result<()> {
+    use std::sync::atomic::{AtomicI32, Ordering};
+    let mut env = ChidoriRuntimeInstance::new();
+    env.wait_until_ready().await?;
+    let active = std::sync::Arc::new(AtomicI32::new(0));
+    let max_active = std::sync::Arc::new(AtomicI32::new(0));
+    let (active_enter, max_active_enter) = (active.clone(), max_active.clone());
+    env.register_native_function("enter", move |_| {
+        let now = active_enter.fetch_add(1, Ordering::SeqCst) + 1;
+        max_active_enter.fetch_max(now, Ordering::SeqCst);
+        RkyvSerializedValue::Number(now)
+    });
+    let active_exit = active.clone();
+    env.register_native_function("exit", move |_| {
+        RkyvSerializedValue::Number(active_exit.fetch_sub(1, Ordering::SeqCst) - 1)
+    });
+    env.upsert_cell(CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: None,
+        language: SupportedLanguage::PyO3,
+        source_code: String::from(indoc! { r#"
+            import chidori as ch
+            import time
+            def update(i):
+                ch.native("enter")
+                time.sleep(0.1)
+                ch.native("exit")
+                return i
+            "#}),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    let (_, webhook_op) = env.upsert_cell(CellTypes::Webhook(WebhookCell {
+        name: Some("update_route".to_string()),
+        host: "127.0.0.1".to_string(),
+        port: 0,
+        path: "/update".to_string(),
+        secret_env: None,
+        signature_header: "X-Hub-Signature-256".to_string(),
+        signature_algorithm: SignatureAlgorithm::HmacSha256,
+        handler: Some("update".to_string()),
+        record_requests: false,
+        concurrency: RequestConcurrency::Serial,
+        max_body_bytes: DEFAULT_WEBHOOK_MAX_BODY_BYTES,
+        middleware: vec![],
+        routes: String::new(),
+        route_table: Default::default(),
+    }, TextRange::default()), Uuid::now_v7()).await?;
+
+    // Defines the handler and starts the listener
+    env.step().await?;
+    env.step().await?;
+
+    let url = format!("http://127.0.0.1:{}/update", env.webhook_address(webhook_op).expect("the listener should be started").port());
+    let client = reqwest::Client::new();
+    let requests = (0..2).map(|i| {
+        let (client, url) = (client.clone(), url.clone());
+        async move {
+            let res = client.post(&url)
+                .json(&serde_json::json!({"i": i}))
+                .send()
+                .await?;
+            assert_eq!(res.status(), reqwest::StatusCode::OK);
+            anyhow::Ok((i, res.json::<serde_json::Value>().await?))
+        }
+    });
+    for result in futures_util::future::join_all(requests).await {
+        let (i, response) = result?;
+        assert_eq!(response, serde_json::json!(i));
+    }
+    assert_eq!(max_active.load(Ordering::SeqCst), 1);
+    assert_eq!(active.load(Ordering::SeqCst), 0);
+    env.shutdown().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_webhook_cell_keeps_one_server_across_evaluations() -> anyhow::Result<()> {
+    let mut env = ChidoriRuntimeInstance::new();
+    env.wait_until_ready().await?;
+    // A fixed port, bound again by each evaluation of the cell
+    let port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+    let webhook = |path: &str| CellTypes::Webhook(WebhookCell {
+        name: Some("hook".to_string()),
+        host: "127.0.0.1".to_string(),
+        port,
+        path: path.to_string(),
+        secret_env: None,
+        signature_header: "X-Hub-Signature-256".to_string(),
+        signature_algorithm: SignatureAlgorithm::HmacSha256,
+        handler: None,
+        record_requests: false,
+        concurrency: RequestConcurrency::Concurrent,
+        max_body_bytes: DEFAULT_WEBHOOK_MAX_BODY_BYTES,
+        middleware: vec![],
+        routes: String::new(),
+        route_table: Default::default(),
+    }, TextRange::default());
+    let (_, webhook_op) = env.upsert_cell(webhook("/first"), Uuid::now_v7()).await?;
+    env.step().await?;
+    assert_eq!(env.webhook_address(webhook_op).map(|address| address.port()), Some(port));
+
+    // The edited cell is evaluated again, its server replaces the previous one on the same port
+    env.upsert_cell(webhook("/second"), webhook_op).await?;
+    env.step().await?;
+    let client = reqwest::Client::new();
+    let post = |path: &str| client.post(format!("http://127.0.0.1:{}{}", port, path)).json(&serde_json::json!({})).send();
+    assert_eq!(post("/second").await?.status(), reqwest::StatusCode::OK);
+    assert_eq!(post("/first").await?.status(), reqwest::StatusCode::NOT_FOUND);
+
+    // Once the cell is no longer a webhook its server is stopped and the port released
+    env.upsert_cell(CellTypes::Code(CodeCell {
+        source_code: String::from("x = 1"),
+        ..Default::default()
+    }, TextRange::default()), webhook_op).await?;
+    assert!(env.webhook_address(webhook_op).is_none());
+    tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        while tokio::net::TcpListener::bind(("127.0.0.1", port)).await.is_err() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    }).await?;
+    env.shutdown().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_await_output_of_downstream_cell() -> anyhow::Result<()> {
+    let mut env = ChidoriRuntimeInstance::new();
+    env.wait_until_ready().await?;
+    env.upsert_cell(CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: None,
+        language: SupportedLanguage::PyO3,
+        source_code: String::from("x = 1"),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    let (_, op_id_y) = env.upsert_cell(CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: None,
+        language: SupportedLanguage::PyO3,
+        source_code: String::from("y = x + 1"),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    let (_, op_id_w) = env.upsert_cell(CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: None,
+        language: SupportedLanguage::PyO3,
+        source_code: String::from("w = z + 1"),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default()), Uuid::now_v7()).await?;
+
+    assert_eq!(
+        env.await_output(op_id_y).await?,
+        RkyvObjectBuilder::new().insert_number("y", 2).build()
+    );
+    assert_eq!(
+        env.await_named_output("y").await?,
+        RkyvObjectBuilder::new().insert_number("y", 2).build()
+    );
+    // `z` is never defined, so this cell can never run
+    assert!(env.await_output(op_id_w).await.is_err());
+
+    // A cell that always runs keeps the graph from settling, waiting is bounded by steps instead
+    env.upsert_cell(CellTypes::Code(CodeCell {
+        language: SupportedLanguage::PyO3,
+        source_code: String::from("t = 1"),
+        always_run: true,
+        ..Default::default()
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    env.max_settle_steps = 5;
+    let err = env.await_output(op_id_w).await.unwrap_err();
+    assert!(err.to_string().contains("within 5 steps"), "{}", err);
+    env.shutdown().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_generated_types_are_checked_for_javascript_cells() -> anyhow::Result<()> {
+    let mut env = ChidoriRuntimeInstance::new();
+    env.wait_until_ready().await?;
+    env.upsert_cell(CellTypes::Code(CodeCell {
+        language: SupportedLanguage::PyO3,
+        source_code: String::from("x = 1"),
+        ..Default::default()
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    let (_, op_id_y) = env.upsert_cell(CellTypes::Code(CodeCell {
+        language: SupportedLanguage::Deno,
+        source_code: String::from("const y: number = x + 1;"),
+        generate_types: true,
+        ..Default::default()
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    let (_, op_id_z) = env.upsert_cell(CellTypes::Code(CodeCell {
+        language: SupportedLanguage::Deno,
+        source_code: String::from("const z: string = x;"),
+        generate_types: true,
+        ..Default::default()
+    }, TextRange::default()), Uuid::now_v7()).await?;
+
+    assert_eq!(
+        env.await_output(op_id_y).await?,
+        RkyvObjectBuilder::new().insert_number("y", 2).build()
+    );
+    // `x` is declared as a number, so assigning it to a string fails the type check
+    let err = env.await_output(op_id_z).await.unwrap_err();
+    assert!(format!("{:?}", err).contains("TS2322"), "{:?}", err);
+    env.shutdown().await;
+    Ok(())
+}
+
+fn many_cells_document(value_offset: usize) -> String {
+    (0..2000)
+        .map(|i| format!("```python (v{})\nv{} = {}\n```\n\n", i, i, i + value_offset))
+        .collect()
+}
+
+/// Runs an instance of the loaded program on its own thread, returning once its initial reload has been applied
+fn run_instance_in_background(
+    chidori: &mut InteractiveChidoriWrapper,
+    events: &std::sync::mpsc::Receiver<EventsFromRuntime>,
+) -> anyhow::Result<()> {
+    let mut instance = chidori.get_instance()?;
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            instance.wait_until_ready().await?;
+            instance.run(PlaybackState::Paused).await
+        })
+    });
+    loop {
+        if let EventsFromRuntime::EditorCellsUpdated(_) = events.recv_timeout(std::time::Duration::from_secs(60))? {
+            return Ok(());
+        }
+    }
+}
+
+#[test]
+fn test_play_while_paused_starts_a_step() -> anyhow::Result<()> {
+    let mut chidori = InteractiveChidoriWrapper::new();
+    let events = chidori.runtime_events.subscribe(EventFilter::all());
+    chidori.load_md_string("```python\nx = 1\n```\n")?;
+    run_instance_in_background(&mut chidori, &events)?;
+
+    chidori.dispatch_user_interaction_to_instance(UserInteractionMessage::SetPlaybackState(PlaybackState::Step))?;
+    // Stepping once returns to Paused as the step begins, and the step then evaluates `x`
+    let mut playback_states = vec![];
+    loop {
+        match events.recv_timeout(std::time::Duration::from_secs(60))? {
+            EventsFromRuntime::PlaybackState(state) => playback_states.push(state),
+            EventsFromRuntime::OperationCompleted { .. } => break,
+            _ => {}
+        }
+    }
+    assert_eq!(playback_states, vec![PlaybackState::Step, PlaybackState::Paused]);
+    chidori.dispatch_user_interaction_to_instance(UserInteractionMessage::Shutdown)?;
+    Ok(())
+}
+
+#[test]
+fn test_slow_reload_does_not_delay_pause() -> anyhow::Result<()> {
+    let mut chidori = InteractiveChidoriWrapper::new();
+    let events = chidori.runtime_events.subscribe(EventFilter::all());
+    chidori.load_md_string(&many_cells_document(0))?;
+    run_instance_in_background(&mut chidori, &events)?;
+
+    // Every cell is edited, so applying them takes a while
+    chidori.load_md_string(&many_cells_document(1))?;
+    chidori.dispatch_user_interaction_to_instance(UserInteractionMessage::ReloadCells)?;
+    chidori.dispatch_user_interaction_to_instance(UserInteractionMessage::SetPlaybackState(PlaybackState::Paused))?;
+    loop {
+        match events.recv_timeout(std::time::Duration::from_secs(60))? {
+            EventsFromRuntime::PlaybackState(PlaybackState::Paused) => break,
+            EventsFromRuntime::EditorCellsUpdated(_) => panic!("Pause was handled after the reload completed"),
+            _ => {}
+        }
+    }
+
+    // The reload is still applied once it completes
+    loop {
+        if let EventsFromRuntime::EditorCellsUpdated(cells) = events.recv_timeout(std::time::Duration::from_secs(60))? {
+            assert!(cells.values().all(|cell| !cell.needs_update));
+            break;
+        }
+    }
+    chidori.dispatch_user_interaction_to_instance(UserInteractionMessage::Shutdown)?;
+    Ok(())
+}
+
+#[test]
+fn test_reload_during_step_applies_once_the_step_completes() -> anyhow::Result<()> {
+    let mut chidori = InteractiveChidoriWrapper::new();
+    let events = chidori.runtime_events.subscribe(EventFilter::all());
+    let document = |y: usize| format!("```python (slow)\nimport time\ntime.sleep(1)\nx = 1\n```\n\n```python (other)\ny = {}\n```\n", y);
+    chidori.load_md_string(&document(2))?;
+    run_instance_in_background(&mut chidori, &events)?;
+
+    // Edit the program while the slow cell is evaluating
+    chidori.dispatch_user_interaction_to_instance(UserInteractionMessage::SetPlaybackState(PlaybackState::Step))?;
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    chidori.load_md_string(&document(3))?;
+
+    let mut step_completed = false;
+    loop {
+        match events.recv_timeout(std::time::Duration::from_secs(60))? {
+            EventsFromRuntime::OperationCompleted { .. } => {
+                step_completed = true;
+            }
+            EventsFromRuntime::ReloadApplied(_) => {
+                assert!(step_completed, "The reload was applied while the step was evaluating");
+                break;
+            }
+            _ => {}
+        }
+    }
+    loop {
+        if let EventsFromRuntime::EditorCellsUpdated(cells) = events.recv_timeout(std::time::Duration::from_secs(60))? {
+            assert!(cells.values().all(|cell| !cell.needs_update && cell.applied_at.is_some()));
+            break;
+        }
+    }
+    chidori.dispatch_user_interaction_to_instance(UserInteractionMessage::Shutdown)?;
+    Ok(())
+}
+
+#[test]
+fn test_pause_during_play_stops_before_the_next_operation() -> anyhow::Result<()> {
+    let mut chidori = InteractiveChidoriWrapper::new();
+    let events = chidori.runtime_events.subscribe(EventFilter::all());
+    // Five slow cells, each depending on the one before it
+    let document: String = (0..5)
+        .map(|i| {
+            let value = if i == 0 { "1".to_string() } else { format!("v{} + 1", i - 1) };
+            format!("```python (slow_{})\nimport time\ntime.sleep(0.5)\nv{} = {}\n```\n\n", i, i, value)
+        })
+        .collect();
+    chidori.load_md_string(&document)?;
+    run_instance_in_background(&mut chidori, &events)?;
+
+    chidori.dispatch_user_interaction_to_instance(UserInteractionMessage::SetPlaybackState(PlaybackState::Running))?;
+    loop {
+        if let EventsFromRuntime::OperationCompleted { .. } = events.recv_timeout(std::time::Duration::from_secs(60))? {
+            break;
+        }
+    }
+    chidori.dispatch_user_interaction_to_instance(UserInteractionMessage::SetPlaybackState(PlaybackState::Paused))?;
+
+    // Long enough for the remaining cells to complete had playback continued
+    let mut executed = 1;
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(4);
+    while let Some(remaining) = deadline.checked_duration_since(std::time::Instant::now()) {
+        match events.recv_timeout(remaining) {
+            Ok(EventsFromRuntime::OperationCompleted { .. }) => executed += 1,
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    // The step underway when Pause arrived may still complete
+    assert!(executed <= 2, "{} cells executed after pausing", executed);
+    chidori.dispatch_user_interaction_to_instance(UserInteractionMessage::Shutdown)?;
+    Ok(())
+}
+
+#[test]
+fn test_shutdown_cancels_the_cell_in_flight() -> anyhow::Result<()> {
+    let directory = std::env::temp_dir().join(format!("chidori-cancel-{}", Uuid::now_v7()));
+    std::fs::create_dir_all(&directory)?;
+    let python_marker = directory.join("python");
+    let javascript_marker = directory.join("javascript");
+    // Each cell appends to its marker for as long as it runs
+    let documents = [
+        (format!("```python\nimport time\nwhile True:\n    with open({:?}, 'a') as f:\n        f.write('x')\n    time.sleep(0.05)\n```\n", python_marker), &python_marker),
+        (format!("```javascript\nwhile (true) {{\n    Deno.writeTextFileSync({:?}, 'x', {{ append: true }});\n}}\n```\n", javascript_marker), &javascript_marker),
+    ];
+    for (document, marker) in documents {
+        let mut chidori = InteractiveChidoriWrapper::new();
+        let events = chidori.runtime_events.subscribe(EventFilter::all());
+        chidori.load_md_string(&document)?;
+        run_instance_in_background(&mut chidori, &events)?;
+        chidori.dispatch_user_interaction_to_instance(UserInteractionMessage::SetPlaybackState(PlaybackState::Step))?;
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(60);
+        while !marker.exists() {
+            assert!(std::time::Instant::now() < deadline, "The cell did not start");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        chidori.dispatch_user_interaction_to_instance(UserInteractionMessage::Shutdown)?;
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        let length = std::fs::metadata(marker)?.len();
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        assert_eq!(std::fs::metadata(marker)?.len(), length, "{} kept running after shutdown", marker.display());
+    }
+    std::fs::remove_dir_all(&directory)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_oversized_output_leaves_the_runtime_healthy() -> anyhow::Result<()> {
+    let mut env = ChidoriRuntimeInstance::new();
+    env.default_execution_policy = ExecutionPolicy { max_output_bytes: Some(1024), ..Default::default() };
+    let code_cell = |name: &str, source_code: &str| CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: Some(name.to_string()),
+        language: SupportedLanguage::PyO3,
+        source_code: source_code.to_string(),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default());
+    let (_, oversized) = env.upsert_cell(code_cell("oversized", "x = 'a' * 10000"), Uuid::now_v7()).await?;
+    let (_, small) = env.upsert_cell(code_cell("small", "y = 1"), Uuid::now_v7()).await?;
+
+    env.step().await?;
+    env.step().await?;
+    let state = env.get_state_at_current_execution_head();
+    assert!(matches!(
+        state.state_get_value(&oversized),
+        Some(Err(ExecutionStateErrors::OutputTooLarge { limit: 1024, .. }))
+    ));
+    assert_eq!(
+        state.state_get_value(&small),
+        Some(&Ok(RkyvObjectBuilder::new().insert_number("y", 1).build()))
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_export_program_to_markdown() -> anyhow::Result<()> {
+    let mut ee = InteractiveChidoriWrapper::new();
+    ee.load_md_string(indoc! { r#"
+        # A program
+
+        ```python (numbers)
+        ---
+        retries: 1
+        ---
+        x = 20
+        ```
+
+        Prose between cells is not kept.
+
+        ```javascript (double)
+        const y = x * 2;
+        ```
+        "#
+    })?;
+    let markdown = ee.to_markdown()?;
+    assert!(markdown.starts_with("```python (numbers)"));
+    assert!(!markdown.contains("Prose"));
+
+    let mut reloaded = InteractiveChidoriWrapper::new();
+    reloaded.load_md_string(&markdown)?;
+    assert_eq!(reloaded.to_markdown()?, markdown);
+    Ok(())
+}
+
+/// Loading a large document should be dominated by parsing and signature analysis, operations
+/// are not constructed until they are executed. Timings are printed with `--nocapture`.
+#[tokio::test]
+async fn bench_load_2000_trivial_cells() -> anyhow::Result<()> {
+    let document: String = (0..2000)
+        .map(|i| format!("```python\nv{} = {}\n```\n\n", i, i))
+        .collect();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let _guard = tracing::subscriber::set_default(utils::telemetry::init_internal_telemetry(sender));
+
+    let started_at = std::time::Instant::now();
+    let mut ee = InteractiveChidoriWrapper::new();
+    ee.load_md_string(&document)?;
+    let parsed_in = started_at.elapsed();
+
+    let started_at = std::time::Instant::now();
+    let mut env = ee.get_instance()?;
+    env.reload_cells().await?;
+    let loaded_in = started_at.elapsed();
+
+    println!("parsed 2000 cells in {:?}, loaded into the graph in {:?}", parsed_in, loaded_in);
+    assert_eq!(env.get_state_at_current_execution_head_result()?.cells_by_id.len(), 2000);
+    let spans: Vec<String> = receiver.try_iter()
+        .filter_map(|event| match event {
+            utils::telemetry::TraceEvents::NewSpan { name, .. } => Some(name),
+            _ => None,
+        })
+        .collect();
+    // Loading is traced, but no operation was constructed along the way
+    assert!(!spans.is_empty());
+    assert!(!spans.iter().any(|name| name == "construct_operation"));
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_execute_cells_between_code_and_llm() -> anyhow::Result<()> {
     dotenv::dotenv().ok();
@@ -63,6 +1499,13 @@ async fn test_execute_cells_between_code_and_llm() -> anyhow::Result<()> {
                         x = "Here is a sample string"
                         "#}),
         function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
     }, TextRange::default()),
                                        Uuid::now_v7())?;
     let (_, op_id_y) = env.upsert_cell(CellTypes::Prompt(LLMPromptCell::Chat {
@@ -90,6 +1533,13 @@ async fn test_execute_cells_between_code_and_llm() -> anyhow::Result<()> {
                         z = await example(x=x)
                         "#}),
         function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
     }, TextRange::default()),
                                        Uuid::now_v7())?;
 
@@ -125,6 +1575,61 @@ async fn test_execute_cells_between_code_and_llm() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_prompt_audit_records_rendered_prompt_and_response() -> anyhow::Result<()> {
+    dotenv::dotenv().ok();
+    let audit_path = std::env::temp_dir().join(format!("chidori-prompt-audit-{}.jsonl", Uuid::now_v7()));
+    let mut env = ChidoriRuntimeInstance::new();
+    env.prompt_audit = Some(std::sync::Arc::new(PromptAuditLog::to_file(&audit_path)
+        .with_redaction(utils::redaction::RedactionConfig::new().with_pattern("sample")?)));
+    env.upsert_cell(CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: None,
+        language: SupportedLanguage::PyO3,
+        source_code: String::from(indoc! { r#"
+                        x = "Here is a sample string"
+                        "#}),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default()),
+                                       Uuid::now_v7())?;
+    let (_, op_id_y) = env.upsert_cell(CellTypes::Prompt(LLMPromptCell::Chat {
+        backing_file_reference: None,
+        is_function_invocation: false,
+        configuration: LLMPromptCellChatConfiguration {
+            model: Some("gpt-3.5-turbo".into()),
+            ..Default::default()
+        },
+        name: Some("example".into()),
+        provider: SupportedModelProviders::OpenAI,
+        complete_body: "".to_string(),
+        req: "\
+                      Say only a single word. Give no additional explanation.
+                      What is the first word of the following: {{x}}.
+                    "
+            .to_string(),
+    }, TextRange::default()),
+                                       Uuid::now_v7())?;
+    env.step().await?;
+    let out = env.step().await?;
+    let RkyvSerializedValue::Object(output) = out[0].1.output.clone().unwrap() else { panic!("Expected an object") };
+
+    let records = utils::prompt_audit::read_prompt_audit_file(&audit_path)?;
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].operation_id, op_id_y);
+    assert_eq!(records[0].cell_name.as_deref(), Some("example"));
+    assert!(records[0].messages.iter().any(|m| m.content.contains("What is the first word of the following: Here is a [REDACTED] string.")));
+    assert_eq!(records[0].response.as_ref().map(|r| RkyvSerializedValue::String(r.clone())), output.get("example").cloned());
+    std::fs::remove_file(&audit_path)?;
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_execute_cells_prompts_as_functions() -> anyhow::Result<()> {
     let mut env = ChidoriRuntimeInstance::new();
@@ -136,6 +1641,13 @@ async fn test_execute_cells_prompts_as_functions() -> anyhow::Result<()> {
                         y = generate_names(x="John")
                         "#}),
         function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
     }, TextRange::default()),
                                        Uuid::now_v7())?;
     let (_, op_id_y) = env.upsert_cell(CellTypes::Prompt(LLMPromptCell::Chat {
@@ -177,12 +1689,26 @@ async fn test_execute_cells_invoking_a_function() -> anyhow::Result<()> {
                             return x + y
                         "#}),
         function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
     }, TextRange::default()),
                                     Uuid::now_v7())?;
     let (_, id_b) = env.upsert_cell(CellTypes::Code(CodeCell {
         backing_file_reference: None,
         name: None,
         function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
         language: SupportedLanguage::PyO3,
         source_code: String::from(indoc! { r#"
                         y = await add(2, 3)
@@ -209,6 +1735,400 @@ async fn test_execute_cells_invoking_a_function() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_errors_returned_as_values_are_filtered_downstream() -> anyhow::Result<()> {
+    let mut env = ChidoriRuntimeInstance::new();
+    env.wait_until_ready().await.unwrap();
+    env.upsert_cell(CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: None,
+        language: SupportedLanguage::PyO3,
+        source_code: String::from(indoc! { r#"
+                        def parse(text):
+                            try:
+                                return int(text)
+                            except ValueError as e:
+                                return e
+                        "#}),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    let (_, id_results) = env.upsert_cell(CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: None,
+        language: SupportedLanguage::PyO3,
+        source_code: String::from(indoc! { r#"
+                        results = []
+                        for text in ["1", "x", "3"]:
+                            results.append(await parse(text))
+                        "#}),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    let (_, id_valid) = env.upsert_cell(CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: None,
+        language: SupportedLanguage::PyO3,
+        source_code: String::from(indoc! { r#"
+                        valid = [r for r in results if not isinstance(r, Exception)]
+                        failures = len(results) - len(valid)
+                        "#}),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    env.step().await?;
+    env.step().await?;
+    let invalid = RkyvSerializedValue::Error {
+        kind: "ValueError".to_string(),
+        message: "invalid literal for int() with base 10: 'x'".to_string(),
+    };
+    assert_eq!(
+        env.get_state_at_current_execution_head().state_get_value(&id_results),
+        Some(&Ok(RkyvObjectBuilder::new().insert_value("results", RkyvSerializedValue::Array(vec![
+            RkyvSerializedValue::Number(1),
+            invalid,
+            RkyvSerializedValue::Number(3),
+        ])).build()))
+    );
+    env.step().await?;
+    assert_eq!(
+        env.get_state_at_current_execution_head().state_get_value(&id_valid),
+        Some(&Ok(RkyvObjectBuilder::new()
+            .insert_value("valid", RkyvSerializedValue::Array(vec![RkyvSerializedValue::Number(1), RkyvSerializedValue::Number(3)]))
+            .insert_number("failures", 1)
+            .build()))
+    );
+    env.shutdown().await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_stream_records_from_python_to_deno() -> anyhow::Result<()> {
+    let mut env = ChidoriRuntimeInstance::new();
+    env.wait_until_ready().await.unwrap();
+    let (_, id_producer) = env.upsert_cell(CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: None,
+        language: SupportedLanguage::PyO3,
+        source_code: String::from(indoc! { r#"
+                        import chidori as ch
+                        records = ch.stream()
+                        for i in range(10000):
+                            records.write({"i": i})
+                        "#}),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    let (_, id_consumer) = env.upsert_cell(CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: None,
+        language: SupportedLanguage::Deno,
+        source_code: String::from(indoc! { r#"
+                        let count = 0;
+                        let total = 0;
+                        for await (const record of records) {
+                            count += 1;
+                            total += record.i;
+                        }
+                        "#}),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    let (_, id_template) = env.upsert_cell(CellTypes::Template(TemplateCell {
+        backing_file_reference: None,
+        name: None,
+        body: "{{records}}".to_string(),
+        engine: Default::default(),
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    env.step().await?;
+    let Some(Ok(RkyvSerializedValue::Object(output))) = env.get_state_at_current_execution_head().state_get_value(&id_producer).cloned() else {
+        panic!("Expected the producer to output an object");
+    };
+    let Some(RkyvSerializedValue::StreamPointer(stream_id)) = output.get("records").cloned() else {
+        panic!("Expected records to be a stream");
+    };
+    env.step().await?;
+    let state = env.get_state_at_current_execution_head();
+    assert_eq!(
+        state.state_get_value(&id_consumer),
+        Some(&Ok(RkyvObjectBuilder::new()
+            .insert_number("count", 10000)
+            .insert_number("total", (0..10000).sum())
+            .build()))
+    );
+    assert_eq!(
+        state.state_get_value(&id_template),
+        Some(&Err(ExecutionStateErrors::StreamInputUnsupported("records".to_string())))
+    );
+
+    // The stream was held in memory a chunk at a time, on both sides
+    let manifest = stream_manifest(&stream_id).unwrap();
+    assert_eq!(manifest.item_count, 10000);
+    assert_eq!(manifest.chunks.len(), 10000 / STREAM_CHUNK_ITEMS);
+    assert_eq!(manifest.chunks_read, manifest.chunks.len());
+    assert!(manifest.peak_resident_items <= STREAM_CHUNK_ITEMS);
+    env.shutdown().await;
+    Ok(())
+}
+
+#[cfg(feature = "arrow")]
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_tables_from_csv_through_pandas_and_deno_to_parquet() -> anyhow::Result<()> {
+    let directory = std::env::temp_dir().join(format!("chidori-tables-{}", Uuid::now_v7()));
+    std::fs::create_dir_all(&directory)?;
+    let csv_path = directory.join("people.csv");
+    let parquet_path = directory.join("adults.parquet");
+    std::fs::write(&csv_path, "name,age\nAda,36\nTim,12\nGrace,85\nSam,9\n")?;
+
+    let mut env = ChidoriRuntimeInstance::new();
+    env.wait_until_ready().await.unwrap();
+    let (_, id_read) = env.upsert_cell(CellTypes::File(FileCell {
+        name: Some("people".to_string()),
+        path: csv_path.to_string_lossy().to_string(),
+        format: None,
+        write: None,
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    let (_, id_filter) = env.upsert_cell(CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: None,
+        language: SupportedLanguage::PyO3,
+        source_code: String::from(indoc! { r#"
+                        import pandas as pd
+                        adults = people[people["age"] >= 18]
+                        "#}),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    let (_, id_names) = env.upsert_cell(CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: None,
+        language: SupportedLanguage::Deno,
+        source_code: String::from(indoc! { r#"
+                        const names = adults.map((row) => row.name);
+                        "#}),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    let (_, id_write) = env.upsert_cell(CellTypes::File(FileCell {
+        name: Some("adults_file".to_string()),
+        path: parquet_path.to_string_lossy().to_string(),
+        format: None,
+        write: Some("adults".to_string()),
+    }, TextRange::default()), Uuid::now_v7()).await?;
+
+    let schema = vec![("name".to_string(), "Utf8".to_string()), ("age".to_string(), "Int64".to_string())];
+    let table = |state: &chidori_core::execution::execution::ExecutionState, id, name: &str| match state.state_get_value(&id) {
+        Some(Ok(RkyvSerializedValue::Object(output))) => match output.get(name) {
+            Some(RkyvSerializedValue::Table { schema, row_count, .. }) => (schema.clone(), *row_count),
+            other => panic!("Expected {} to be a table, got {:?}", name, other),
+        },
+        other => panic!("Expected an output of {}, got {:?}", name, other),
+    };
+
+    env.step().await?;
+    assert_eq!(table(&env.get_state_at_current_execution_head(), id_read, "people"), (schema.clone(), 4));
+    env.step().await?;
+    let (filtered_schema, filtered_rows) = table(&env.get_state_at_current_execution_head(), id_filter, "adults");
+    assert_eq!(filtered_schema.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), vec!["name", "age"]);
+    assert_eq!(filtered_rows, 2);
+    env.step().await?;
+    let state = env.get_state_at_current_execution_head();
+    assert_eq!(
+        state.state_get_value(&id_names),
+        Some(&Ok(RkyvObjectBuilder::new()
+            .insert_value("names", RkyvSerializedValue::Array(vec![
+                RkyvSerializedValue::String("Ada".to_string()),
+                RkyvSerializedValue::String("Grace".to_string()),
+            ]))
+            .build()))
+    );
+    assert_eq!(table(&state, id_write, "adults_file").1, 2);
+
+    let written = read_table_file(&parquet_path, TableFileFormat::Parquet)?;
+    let RkyvSerializedValue::Table { schema: written_schema, row_count, .. } = written else { panic!("Expected a table") };
+    assert_eq!(written_schema.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), vec!["name", "age"]);
+    assert_eq!(row_count, 2);
+    env.shutdown().await;
+    std::fs::remove_dir_all(&directory)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_recover_checkpoint_after_crash() -> anyhow::Result<()> {
+    let directory = std::env::temp_dir().join(format!("chidori-recovery-{}", Uuid::now_v7()));
+    std::fs::create_dir_all(&directory)?;
+    std::fs::copy("./examples/core1_simple_math/core.md", directory.join("core.md"))?;
+
+    let outputs_by_cell = |env: &ChidoriRuntimeInstance| -> anyhow::Result<Vec<(CellTypes, RkyvSerializedValue)>> {
+        let state = env.get_state_at_current_execution_head_result()?;
+        let mut outputs: Vec<_> = state.cells_by_id.iter()
+            .filter_map(|(op_id, cell)| match state.state_get_value(op_id) {
+                Some(Ok(value)) => Some((cell.clone(), value.clone())),
+                _ => None,
+            })
+            .collect();
+        outputs.sort_by_key(|(cell, _)| cell.text_range().start);
+        Ok(outputs)
+    };
+
+    let (head_id, outputs) = {
+        let mut ee = InteractiveChidoriWrapper::new();
+        ee.set_checkpointing(CheckpointConfig::every_states(1).with_compact_after_files(2));
+        ee.load_md_directory(&directory)?;
+        assert!(ee.available_checkpoint.is_none());
+        let mut env = ee.get_instance()?;
+        env.reload_cells().await?;
+        env.step().await?;
+        env.step().await?;
+        env.step().await?;
+        let outputs = outputs_by_cell(&env)?;
+        assert_eq!(outputs.len(), 3);
+        // The instance and wrapper are dropped without shutting down, as if the process crashed
+        (env.execution_head_state_id, outputs)
+    };
+    let checkpoint_files = std::fs::read_dir(checkpoint_directory(&directory))?.count();
+    assert!(checkpoint_files <= 2, "Incremental checkpoints are compacted, found {} files", checkpoint_files);
+
+    let mut ee = InteractiveChidoriWrapper::new();
+    ee.load_md_directory(&directory)?;
+    let recovered = ee.recover_latest_checkpoint()?.expect("A checkpoint is available");
+    assert_eq!(recovered.head_id, head_id);
+    assert!(recovered.step_count >= 3);
+    let mut env = ee.get_instance()?;
+    env.reload_cells().await?;
+    assert_eq!(env.execution_head_state_id, head_id);
+    assert_eq!(outputs_by_cell(&env)?, outputs);
+    assert!(ee.recover_latest_checkpoint()?.is_none());
+    std::fs::remove_dir_all(&directory)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reproduce_failure_from_exported_bundle() -> anyhow::Result<()> {
+    let bundle = std::env::temp_dir().join(format!("chidori-repro-{}.zip", Uuid::now_v7()));
+    let (error, manifest) = {
+        let mut ee = InteractiveChidoriWrapper::new();
+        ee.load_md_string(indoc! { r#"
+                ```python (numbers)
+                x = 21
+                ```
+
+                ```python (divide)
+                y = x / 0
+                ```
+
+                ```python (unrelated)
+                z = 1
+                ```
+                "#})?;
+        let mut env = ee.get_instance()?;
+        env.reload_cells().await?;
+        let mut error = None;
+        for _ in 0..10 {
+            if let Err(e) = env.step().await {
+                error = Some(e.to_string());
+                break;
+            }
+        }
+        let error = error.expect("Dividing by zero fails");
+        let manifest = env.db.export_repro(env.execution_head_state_id, &bundle, &utils::redaction::RedactionConfig::default())?;
+        (error, manifest)
+    };
+    assert_eq!(manifest.cells, 2);
+    assert_eq!(manifest.recorded_values, 1);
+    assert_eq!(manifest.failures, vec![ReproFailure { cell: Some("divide".to_string()), error: None }]);
+
+    // A fresh environment restores the recorded value and fails in the same way
+    let mut ee = InteractiveChidoriWrapper::new();
+    assert_eq!(ee.load_repro(&bundle)?, manifest);
+    let mut env = ee.get_instance()?;
+    env.reload_cells().await?;
+    assert_eq!(env.execution_head_state_id, manifest.node);
+    let state = env.get_state_at_current_execution_head_result()?;
+    assert_eq!(state.cells_by_id.len(), 2);
+    let (numbers, _) = state.cells_by_id.iter()
+        .find(|(_, cell)| cell.name().as_deref() == Some("numbers"))
+        .expect("The cell the failure depends on is included");
+    assert_eq!(state.state_get_value(numbers), Some(&Ok(RkyvObjectBuilder::new().insert_number("x", 21).build())));
+    let reproduced = env.step().await.expect_err("The failure reproduces");
+    assert_eq!(reproduced.to_string(), error);
+    std::fs::remove_file(&bundle)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_subscribers_receive_their_selected_events() -> anyhow::Result<()> {
+    let mut ee = InteractiveChidoriWrapper::new();
+    ee.load_md_string(indoc! { r#"
+            ```python (a)
+            x = 1
+            ```
+
+            ```python (b)
+            y = x + 1
+            ```
+            "#})?;
+    let progress = ee.subscribe(EventFilter::kinds([EventKind::UpdateExecutionHead, EventKind::ReloadRejected]));
+    let completions = ee.subscribe(EventFilter::kinds([EventKind::OperationCompleted, EventKind::PlaybackState]));
+    let dropped = ee.subscribe(EventFilter::all());
+    drop(dropped);
+
+    let mut env = ee.get_instance()?;
+    env.reload_cells().await?;
+    env.step().await?;
+    env.step().await?;
+
+    let progress: Vec<_> = progress.try_iter().map(|event| event.kind()).collect();
+    assert!(progress.contains(&EventKind::UpdateExecutionHead));
+    assert!(progress.iter().all(|kind| *kind == EventKind::UpdateExecutionHead));
+    let completions: Vec<_> = completions.try_iter().map(|event| event.kind()).collect();
+    assert!(completions.contains(&EventKind::OperationCompleted));
+    assert!(completions.iter().all(|kind| matches!(kind, EventKind::OperationCompleted | EventKind::PlaybackState)));
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
 async fn test_execute_inter_runtime_code_plain() -> anyhow::Result<()> {
     let mut env = ChidoriRuntimeInstance::new();
@@ -222,12 +2142,26 @@ async fn test_execute_inter_runtime_code_plain() -> anyhow::Result<()> {
                             return x + y
                         "#}),
         function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
     }, TextRange::default()),
                                     Uuid::now_v7())?;
     let (_, id_b) = env.upsert_cell(CellTypes::Code(CodeCell {
         backing_file_reference: None,
         name: None,
         function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: false,
+        generate_types: false,
+        package_json: None,
         language: SupportedLanguage::Deno,
         source_code: String::from(indoc! { r#"
                         const y = await add(2, 3);
@@ -453,6 +2387,39 @@ async fn test_core1_simple_math() -> anyhow::Result<()>{
     Ok(())
 }
 
+#[tokio::test]
+async fn test_core1_simple_math_networkx_export() -> anyhow::Result<()>{
+    let mut ee = InteractiveChidoriWrapper::new();
+    ee.load_md_directory(Path::new("./examples/core1_simple_math")).unwrap();
+    let mut env = ee.get_instance().unwrap();
+    env.reload_cells().await?;
+    env.step().await?;
+    env.step().await?;
+    env.step().await?;
+
+    let graph: serde_json::Value = serde_json::from_str(&env.db.export_to_networkx_json())?;
+    assert_eq!(graph["directed"], serde_json::json!(true));
+    let nodes = graph["nodes"].as_array().unwrap();
+    let node_ids: HashSet<&str> = nodes.iter().map(|n| n["id"].as_str().unwrap()).collect();
+    assert_eq!(node_ids.len(), nodes.len());
+    for link in graph["links"].as_array().unwrap() {
+        assert!(node_ids.contains(link["source"].as_str().unwrap()));
+        assert!(node_ids.contains(link["target"].as_str().unwrap()));
+    }
+
+    // Only states that closed an evaluation carry a duration, these are the three cells in order
+    let evaluations: Vec<serde_json::Value> = nodes.iter()
+        .filter(|n| !n["last_duration_ms"].is_null())
+        .map(|n| serde_json::json!({ "name": n["name"], "language": n["language"] }))
+        .collect();
+    assert_eq!(serde_json::Value::Array(evaluations), serde_json::json!([
+        { "name": null, "language": "python" },
+        { "name": null, "language": "python" },
+        { "name": null, "language": "javascript" },
+    ]));
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_core2_marshalling() -> anyhow::Result<()> {
     let mut ee = InteractiveChidoriWrapper::new();
@@ -647,3 +2614,93 @@ async fn test_core9_multi_agent_simulation() {
     let out = env.step().await;
     assert_eq!(env.get_state_at_current_execution_head().have_all_operations_been_set_at_least_once(), true);
 }
+
+#[tokio::test]
+async fn test_render_template_from_embedder() -> anyhow::Result<()> {
+    let mut env = ChidoriRuntimeInstance::new();
+    env.upsert_cell(CellTypes::Template(TemplateCell {
+        backing_file_reference: None,
+        name: Some("email_template".to_string()),
+        body: "Dear {{user.name}},\n{{> signature}}".to_string(),
+        engine: Default::default(),
+    }, TextRange::default()), Uuid::now_v7()).await?;
+    env.upsert_cell(CellTypes::Template(TemplateCell {
+        backing_file_reference: None,
+        name: Some("signature".to_string()),
+        body: "Regards".to_string(),
+        engine: Default::default(),
+    }, TextRange::default()), Uuid::now_v7()).await?;
+
+    let user = RkyvObjectBuilder::new().insert_string("name", "Ada".to_string());
+    let rendered = env.render_template("email_template", RkyvObjectBuilder::new().insert_object("user", user).build())?;
+    assert_eq!(rendered, "Dear Ada,\nRegards");
+
+    let err = env.render_template("email_template", RkyvObjectBuilder::new().build()).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<TemplateRenderError>(),
+        Some(TemplateRenderError::MissingVariable { template, path: Some(path), .. }) if template == "email_template" && path == "user.name"
+    ), "{:?}", err);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_load_example_by_name() -> anyhow::Result<()>{
+    let mut ee = InteractiveChidoriWrapper::new();
+    ee.load_example("core1_simple_math")?;
+    let mut sources: Vec<String> = ee.shared_state.lock().unwrap().editor_cells.values()
+        .map(|holder| match &holder.cell {
+            CellTypes::Code(c, _) => c.source_code.trim().to_string(),
+            other => panic!("unexpected cell {:?}", other),
+        })
+        .collect();
+    sources.sort();
+    assert_eq!(sources, vec!["const zj = y + 20;", "x = 20", "y = x * 20"]);
+
+    let mut env = ee.get_instance().unwrap();
+    env.reload_cells().await?;
+    env.step().await?;
+    env.step().await?;
+    let out = env.step().await?;
+    assert_eq!(out[0].1.output, Ok(RkyvObjectBuilder::new().insert_number("zj", 420).build()));
+
+    assert!(ee.load_example("missing_example").is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_always_run_cell_evaluates_on_every_step() -> anyhow::Result<()> {
+    let mut env = ChidoriRuntimeInstance::new();
+    let counter = std::sync::Arc::new(std::sync::atomic::AtomicI32::new(0));
+    let counter_clone = counter.clone();
+    env.register_native_function("next_count", move |_| {
+        RkyvSerializedValue::Number(counter_clone.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1)
+    });
+    let (_, op_id) = env.upsert_cell(CellTypes::Code(CodeCell {
+        backing_file_reference: None,
+        name: Some("counter".to_string()),
+        language: SupportedLanguage::PyO3,
+        source_code: String::from(indoc! { r#"
+                        import chidori as ch
+                        count = ch.native("next_count")
+                        "#}),
+        function_invocation: None,
+        inspect_globals: false,
+        redact_output: false,
+        policy: Default::default(),
+        oom_limit_bytes: None,
+        always_run: true,
+        generate_types: false,
+        package_json: None,
+    }, TextRange::default()), Uuid::now_v7()).await?;
+
+    // The cell has no inputs, without always_run it would only be evaluated once
+    for expected in 1..=3 {
+        env.step().await?;
+        assert_eq!(
+            env.get_state_at_current_execution_head().state_get_value(&op_id),
+            Some(&Ok(RkyvObjectBuilder::new().insert_number("count", expected).build()))
+        );
+    }
+    assert_eq!(counter.load(std::sync::atomic::Ordering::SeqCst), 3);
+    Ok(())
+}
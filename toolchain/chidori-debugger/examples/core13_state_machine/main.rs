@@ -5,7 +5,7 @@ use chidori_core::sdk::chidori_runtime_instance::PlaybackState;
 fn main() {
     let current_file = env!("CARGO_MANIFEST_DIR");
     let current_file_path = Path::new(current_file);
-    let relative_path = current_file_path.join("./");
+    let relative_path = current_file_path.join("../chidori-core/examples/core13_state_machine");
 
     let mut env = InteractiveChidoriWrapper::new();
     env.load_md_directory(&relative_path);
@@ -1,6 +1,6 @@
 use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
@@ -13,7 +13,8 @@ use chidori_core::uuid::Uuid;
 use egui;
 use egui::panel::TopBottomSide;
 use egui::{FontFamily, Frame, Id, Margin, Response, Vec2b, Widget};
-use egui_tiles::{Tile, TileId};
+use egui_tiles::{ContainerKind, Tile, TileId};
+use serde::{Deserialize, Serialize};
 use notify_debouncer_full::{
     new_debouncer,
     notify::{RecommendedWatcher, RecursiveMode, Watcher},
@@ -22,12 +23,19 @@ use notify_debouncer_full::{
 
 use crate::{tokio_tasks, CurrentTheme};
 use chidori_core::execution::execution::execution_graph::{
-    ExecutionNodeId, MergedStateHistory,
+    AnnotatedDependencyEdge, ExecutionNodeId, MergedStateHistory,
 };
 use chidori_core::execution::execution::ExecutionState;
-use chidori_core::execution::primitives::identifiers::{DependencyReference, OperationId};
+use chidori_core::execution::primitives::identifiers::OperationId;
+use chidori_core::execution::primitives::serialized_value::RkyvSerializedValue;
+use chidori_core::execution::primitives::agent_trace::AgentTraceStep;
 use chidori_core::sdk::interactive_chidori_wrapper::{InteractiveChidoriWrapper, EventsFromRuntime};
 use chidori_core::sdk::interactive_chidori_wrapper::CellHolder;
+use chidori_core::sdk::file_watch::{reload_changed_paths, ReloadFilter, DEFAULT_IGNORE_PATTERNS, DEFAULT_RELOAD_EXTENSIONS, DEFAULT_WATCH_DEBOUNCE};
+use chidori_core::sdk::examples::{examples, examples_in_directory, LocalExample};
+use chidori_core::sdk::registry::fetch_registry_cells;
+use chidori_core::library::std::ai::llm::format_cost_usd;
+use chidori_core::tokio::runtime::Handle;
 use chidori_core::tokio::task::JoinHandle;
 use chidori_core::utils::telemetry::TraceEvents;
 use petgraph::graph::NodeIndex;
@@ -77,6 +85,39 @@ impl<'a> egui_tiles::Behavior<Pane> for TreeBehavior<'a> {
     }
 }
 
+/// Wall-clock durations of the steps evaluated while benchmark mode is enabled
+#[derive(Resource, Default, Debug)]
+pub struct ChidoriBenchmarkResults {
+    pub step_durations: Vec<Duration>,
+}
+
+impl ChidoriBenchmarkResults {
+    pub fn record(&mut self, duration: Duration) {
+        self.step_durations.push(duration);
+    }
+
+    pub fn clear(&mut self) {
+        self.step_durations.clear();
+    }
+
+    pub fn total(&self) -> Duration {
+        self.step_durations.iter().sum()
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        if self.step_durations.is_empty() {
+            return None;
+        }
+        Some(self.total() / self.step_durations.len() as u32)
+    }
+
+    pub fn summary(&self) -> Option<String> {
+        self.average().map(|average| {
+            format!("Avg step: {}ms, Total: {}ms", average.as_millis(), self.total().as_millis())
+        })
+    }
+}
+
 #[derive(Resource, Default)]
 pub struct EguiTreeIdentities {
     pub code_tile: Option<TileId>,
@@ -91,6 +132,121 @@ pub struct EguiTree {
     pub tree: egui_tiles::Tree<Pane>,
 }
 
+/// The arrangement of the debugger panes, as stored in the layout file. Panes are named by their
+/// title, e.g. `{"tabs": [{"pane": "Code"}, {"pane": "Logs"}]}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayoutNode {
+    Pane(String),
+    Tabs(Vec<LayoutNode>),
+    Horizontal(Vec<LayoutNode>),
+    Vertical(Vec<LayoutNode>),
+    Grid(Vec<LayoutNode>),
+}
+
+/// Where the layout of the debugger is saved to and loaded from, `~/.chidori/layout.json`
+pub fn layout_path() -> Option<PathBuf> {
+    let home_dir = std::env::var("CHIDORI_HOME_DIRECTORY")
+        .or_else(|_| std::env::var("HOME"))
+        .or_else(|_| std::env::var("USERPROFILE"))
+        .ok()?;
+    Some(PathBuf::from(home_dir).join(".chidori").join("layout.json"))
+}
+
+impl EguiTree {
+    pub fn from_layout(layout: &LayoutNode) -> Self {
+        fn insert(tiles: &mut egui_tiles::Tiles<Pane>, node: &LayoutNode) -> TileId {
+            let mut insert_children = |nodes: &Vec<LayoutNode>| -> Vec<TileId> {
+                nodes.iter().map(|node| insert(tiles, node)).collect()
+            };
+            match node {
+                LayoutNode::Pane(name) => tiles.insert_pane(Pane {
+                    tile_id: None,
+                    nr: name.clone(),
+                    rect: None,
+                }),
+                LayoutNode::Tabs(nodes) => {
+                    let children = insert_children(nodes);
+                    tiles.insert_tab_tile(children)
+                }
+                LayoutNode::Horizontal(nodes) => {
+                    let children = insert_children(nodes);
+                    tiles.insert_horizontal_tile(children)
+                }
+                LayoutNode::Vertical(nodes) => {
+                    let children = insert_children(nodes);
+                    tiles.insert_vertical_tile(children)
+                }
+                LayoutNode::Grid(nodes) => {
+                    let children = insert_children(nodes);
+                    tiles.insert_grid_tile(children)
+                }
+            }
+        }
+
+        let mut tiles = egui_tiles::Tiles::default();
+        let root = insert(&mut tiles, layout);
+        EguiTree {
+            tree: egui_tiles::Tree::new("my_tree", root, tiles),
+        }
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let layout: LayoutNode = serde_json::from_str(json)?;
+        Ok(Self::from_layout(&layout))
+    }
+
+    /// The layout read from the layout file, or the default one if there is no such file
+    pub fn load_or_default() -> Self {
+        let Some(contents) = layout_path().and_then(|path| std::fs::read_to_string(path).ok()) else {
+            return Self::default();
+        };
+        match Self::from_json(&contents) {
+            Ok(tree) => tree,
+            Err(e) => {
+                eprintln!("Ignoring the invalid layout file: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    /// The current arrangement of the panes, None if every pane has been closed
+    pub fn to_layout(&self) -> Option<LayoutNode> {
+        fn layout_of(tiles: &egui_tiles::Tiles<Pane>, tile_id: TileId) -> Option<LayoutNode> {
+            match tiles.get(tile_id)? {
+                Tile::Pane(pane) => Some(LayoutNode::Pane(pane.nr.clone())),
+                Tile::Container(container) => {
+                    let children = container.children()
+                        .filter_map(|child| layout_of(tiles, *child))
+                        .collect();
+                    Some(match container.kind() {
+                        ContainerKind::Tabs => LayoutNode::Tabs(children),
+                        ContainerKind::Horizontal => LayoutNode::Horizontal(children),
+                        ContainerKind::Vertical => LayoutNode::Vertical(children),
+                        ContainerKind::Grid => LayoutNode::Grid(children),
+                    })
+                }
+            }
+        }
+        layout_of(&self.tree.tiles, self.tree.root()?)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.to_layout().unwrap_or(LayoutNode::Tabs(vec![])))
+            .expect("Layouts are always serializable")
+    }
+
+    /// Write the current layout to the layout file, to be used the next time the debugger starts
+    pub fn save(&self) -> anyhow::Result<PathBuf> {
+        let path = layout_path().ok_or_else(|| anyhow::anyhow!("No home directory to save the layout in"))?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, self.to_json())?;
+        Ok(path)
+    }
+}
+
 fn keyboard_shortcut_tab_focus(
     mut identities: ResMut<EguiTreeIdentities>,
     mut tree: ResMut<EguiTree>,
@@ -186,29 +342,6 @@ impl Default for EguiTree {
     }
 }
 
-const EXAMPLES_CORE1: &str = include_str!("../examples/core1_simple_math/core.md");
-const EXAMPLES_CORE2: &str = include_str!("../examples/core2_marshalling/core.md");
-const EXAMPLES_CORE3: &str =
-    include_str!("../examples/core3_function_invocations/core.md");
-const EXAMPLES_CORE4: &str =
-    include_str!("../examples/core4_async_function_invocations/core.md");
-const EXAMPLES_CORE5: &str =
-    include_str!("../examples/core5_prompts_invoked_as_functions/core.md");
-const EXAMPLES_CORE6: &str =
-    include_str!("../examples/core6_prompts_leveraging_function_calling/core.md");
-const EXAMPLES_CORE7: &str =
-    include_str!("../examples/core7_rag_stateful_memory_cells/core.md");
-const EXAMPLES_CORE8: &str =
-    include_str!("../examples/core8_prompt_code_generation_and_execution/core.md");
-const EXAMPLES_CORE9: &str =
-    include_str!("../examples/core9_multi_agent_simulation/core.md");
-const EXAMPLES_CORE10: &str =
-    include_str!("../examples/core10_concurrency/core.md");
-const EXAMPLES_CORE11: &str =
-    include_str!("../examples/core11_hono/core.md");
-const EXAMPLES_CORE12: &str =
-    include_str!("../examples/core12_dependency_management/core.md");
-
 fn hash_graph(input: &Vec<(ExecutionNodeId, ExecutionNodeId)>) -> u64 {
     let mut hasher = std::hash::DefaultHasher::new();
     input.hash(&mut hasher);
@@ -261,24 +394,41 @@ impl ChidoriState {
 }
 
 
+/// The steps an operation has reported, replaced once the operation is evaluated again
+#[derive(Default)]
+pub struct OperationAgentTrace {
+    pub steps: Vec<AgentTraceStep>,
+    pub completed: bool,
+}
+
 #[derive(Default)]
 pub struct CellState {
     pub(crate) is_repl_open: bool,
     pub(crate) is_new_cell_open: bool,
     pub(crate) repl_content: String,
     pub(crate) json_content: serde_json::Value,
-    pub(crate) temp_cell: Option<CellHolder>
+    pub(crate) temp_cell: Option<CellHolder>,
+    pub(crate) is_paste_open: bool,
+    pub(crate) paste_content: String,
+    pub(crate) paste_error: Option<String>,
 }
 
 
 #[derive(Resource)]
 pub struct ChidoriState {
     pub debug_mode: bool,
-    pub(crate) watched_path: Mutex<Option<String>>,
+    pub(crate) watched_path: Mutex<Option<PathBuf>>,
     file_watch: Mutex<Option<Debouncer<RecommendedWatcher, FileIdMap>>>,
+    /// How long the watched directory must be quiet before its changes are reloaded
+    pub file_watch_debounce: Duration,
+    /// Globs, relative to the watched directory, of paths whose changes never trigger a reload
+    file_watch_ignore_patterns: Vec<String>,
     background_thread: Mutex<Option<JoinHandle<()>>>,
     pub chidori: Arc<Mutex<InteractiveChidoriWrapper>>,
     pub display_example_modal: bool,
+    /// Directory scanned for example programs listed alongside the bundled examples
+    pub examples_directory: Option<PathBuf>,
+    pub local_examples: Vec<LocalExample>,
     pub current_playback_state: PlaybackState,
 
 
@@ -290,7 +440,7 @@ pub struct ChidoriState {
 
     pub merged_state_history: Option<MergedStateHistory>,
 
-    pub definition_graph: Vec<(OperationId, OperationId, Vec<DependencyReference>)>,
+    pub definition_graph: Vec<AnnotatedDependencyEdge>,
 
 
     pub execution_graph: Vec<(ExecutionNodeId, ExecutionNodeId)>,
@@ -300,7 +450,16 @@ pub struct ChidoriState {
 
     pub execution_ids_to_states: HashMap<ExecutionNodeId, ExecutionState>,
 
+    /// Ephemeral values reported for the current execution head, these are replaced on every step
+    pub transient_state: HashMap<OperationId, RkyvSerializedValue>,
+
+    /// Reasoning steps reported by each operation during its most recent evaluation
+    pub agent_traces: HashMap<OperationId, OperationAgentTrace>,
+
     pub trace_events: Vec<TraceEvents>,
+
+    /// Step durations are reported into `ChidoriBenchmarkResults` while this is set
+    pub benchmark_enabled: bool,
 }
 
 impl Default for ChidoriState {
@@ -311,7 +470,11 @@ impl Default for ChidoriState {
             watched_path: Mutex::new(None),
             background_thread: Mutex::new(None),
             file_watch: Mutex::new(None),
+            file_watch_debounce: DEFAULT_WATCH_DEBOUNCE,
+            file_watch_ignore_patterns: DEFAULT_IGNORE_PATTERNS.iter().map(|p| p.to_string()).collect(),
             display_example_modal: true,
+            examples_directory: None,
+            local_examples: vec![],
             current_playback_state: PlaybackState::Paused,
 
             editor_cells: HashMap::new(),
@@ -324,18 +487,18 @@ impl Default for ChidoriState {
             grouped_nodes: Default::default(),
             current_execution_head: Default::default(),
             execution_ids_to_states: Default::default(),
+            transient_state: Default::default(),
+            agent_traces: Default::default(),
             trace_events: vec![],
+            benchmark_enabled: false,
         }
     }
 }
 
 impl ChidoriState {
-    pub fn get_loaded_path(&self) -> String {
+    pub fn get_loaded_path(&self) -> Option<PathBuf> {
         let env = self.chidori.lock().unwrap();
-        if env.loaded_path.is_none() {
-            return "".to_string();
-        }
-        env.loaded_path.as_ref().unwrap().to_string()
+        env.loaded_path.clone()
     }
 
     // pub fn move_state_view_to_id(&self, id: ExecutionNodeId) -> anyhow::Result<(), String> {
@@ -383,6 +546,14 @@ impl ChidoriState {
         Ok(())
     }
 
+    /// Evaluate a single operation, in a deterministic order, then pause
+    pub fn micro_step(&self) -> anyhow::Result<(), String> {
+        let env = self.chidori.lock().unwrap();
+        env.dispatch_user_interaction_to_instance(UserInteractionMessage::MicroStep)
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
     pub fn play(&self) -> anyhow::Result<(), String> {
         let env = self.chidori.lock().unwrap();
         env.dispatch_user_interaction_to_instance(UserInteractionMessage::SetPlaybackState(PlaybackState::Running))
@@ -390,6 +561,27 @@ impl ChidoriState {
         Ok(())
     }
 
+    /// Record the wall-clock time of every step taken by the runtime, for profiling in the UI.
+    pub fn benchmark_mode(&mut self, enabled: bool) -> anyhow::Result<(), String> {
+        {
+            let env = self.chidori.lock().unwrap();
+            env.dispatch_user_interaction_to_instance(UserInteractionMessage::SetBenchmarkMode(enabled))
+                .map_err(|e| e.to_string())?;
+        }
+        self.benchmark_enabled = enabled;
+        Ok(())
+    }
+
+    /// Pause execution before the cell named `name` is evaluated, or stop pausing before it
+    pub fn set_breakpoint(&self, name: &str, enabled: bool) -> anyhow::Result<(), String> {
+        let mut env = self.chidori.lock().unwrap();
+        env.set_breakpoint(name, enabled).map_err(|e| e.to_string())
+    }
+
+    pub fn has_breakpoint(&self, name: &str) -> bool {
+        self.chidori.lock().unwrap().breakpoints.contains(name)
+    }
+
     pub fn pause(&self) -> anyhow::Result<(), String> {
         let env = self.chidori.lock().unwrap();
         env.dispatch_user_interaction_to_instance(UserInteractionMessage::SetPlaybackState(PlaybackState::Paused))
@@ -432,10 +624,43 @@ impl ChidoriState {
         self.grouped_nodes = Default::default();
         self.current_execution_head = Default::default();
         self.execution_ids_to_states = Default::default();
+        self.transient_state = Default::default();
         self.trace_events = vec![];
         Ok(())
     }
 
+    /// Abort the background instance and run a fresh one in its place, for recovering from an
+    /// instance that crashed or stopped responding. The loaded cells are kept, the states
+    /// evaluated so far are discarded.
+    pub fn restart_instance(&mut self, handle: &Handle) -> anyhow::Result<(), String> {
+        if let Some(background_thread) = self.background_thread.get_mut().unwrap().take() {
+            background_thread.abort();
+        }
+        self.execution_graph = vec![];
+        self.grouped_nodes = Default::default();
+        self.current_execution_head = Default::default();
+        self.execution_ids_to_states = Default::default();
+        self.merged_state_history = None;
+        self.transient_state = Default::default();
+        self.agent_traces = Default::default();
+        self.current_playback_state = PlaybackState::Paused;
+        *self.background_thread.get_mut().unwrap() = Some(spawn_instance_loop(handle, self.chidori.clone()));
+
+        self.chidori.lock().unwrap().runtime_events.send(EventsFromRuntime::InstanceRestarted);
+        Ok(())
+    }
+
+    /// Load the cells fetched from registries, after the user has reviewed them
+    pub fn accept_registry_cells(&mut self) -> anyhow::Result<(), String> {
+        let accepted = self.chidori.lock().unwrap().accept_registry_cells().map_err(|e| e.to_string())?;
+        self.log_messages.push(format!("Loaded {} cells from registries", accepted));
+        Ok(())
+    }
+
+    pub fn reject_registry_cells(&mut self) {
+        self.chidori.lock().unwrap().reject_registry_cells();
+    }
+
     pub fn update_cell(&self, cell_holder: CellHolder) -> anyhow::Result<(), String> {
         let chidori = self.chidori.clone();
         {
@@ -456,24 +681,55 @@ impl ChidoriState {
         Ok(())
     }
 
-    pub fn load_and_watch_directory(&self, path: String) -> anyhow::Result<(), String> {
+    /// List the examples found in `directory` in the example modal, in addition to the bundled ones
+    pub fn set_examples_directory(&mut self, directory: Option<PathBuf>) -> anyhow::Result<(), String> {
+        self.local_examples = match &directory {
+            Some(directory) => examples_in_directory(directory).map_err(|e| e.to_string())?,
+            None => vec![],
+        };
+        self.examples_directory = directory;
+        Ok(())
+    }
+
+    pub fn file_watch_ignore_patterns(&self) -> &[String] {
+        &self.file_watch_ignore_patterns
+    }
+
+    /// Replace the globs of paths ignored by the file watcher, these take effect the next time
+    /// a directory is watched
+    pub fn set_file_watch_ignore_patterns(&mut self, patterns: Vec<String>) -> anyhow::Result<(), String> {
+        ReloadFilter::new(DEFAULT_RELOAD_EXTENSIONS, patterns.as_slice()).map_err(|e| e.to_string())?;
+        self.file_watch_ignore_patterns = patterns;
+        Ok(())
+    }
+
+    pub fn load_and_watch_directory(&self, path: PathBuf) -> anyhow::Result<(), String> {
         let chidori = self.chidori.clone();
         let mut file_watch_guard = self.file_watch.lock().expect("Failed to lock file_watch");
 
         // Initialize the watcher and set up the event handler within a single block to avoid cloning `path` multiple times.
         let watcher_chidori = chidori.clone();
         let watcher_path = path.clone();
+        let reload_filter = ReloadFilter::new(DEFAULT_RELOAD_EXTENSIONS, self.file_watch_ignore_patterns.as_slice())
+            .map_err(|e| e.to_string())?;
         let mut debouncer = new_debouncer(
-            Duration::from_millis(200),
+            self.file_watch_debounce,
             None,
             move |result: DebounceEventResult| {
-                match result {
-                    Ok(events) => events.iter().for_each(|event| {}),
-                    Err(errors) => errors.iter().for_each(|error| {}),
+                let events = match result {
+                    Ok(events) => events,
+                    Err(errors) => {
+                        errors.iter().for_each(|error| eprintln!("File watch error: {:?}", error));
+                        return;
+                    }
+                };
+                // The debouncer coalesces a burst of events, reload once for all of the paths they touch
+                let paths = events.into_iter()
+                    .filter(|event| !event.kind.is_access())
+                    .flat_map(|event| event.event.paths);
+                if let Err(e) = reload_changed_paths(&watcher_chidori, &watcher_path, &reload_filter, paths) {
+                    eprintln!("Failed to reload {:?}: {}", watcher_path, e);
                 }
-                let path_buf = PathBuf::from(&watcher_path);
-                let mut chidori_guard = watcher_chidori.lock().expect("Failed to lock chidori");
-                chidori_guard.load_md_directory(&path_buf).expect("Failed to load markdown directory");
             },
         )
         .unwrap();
@@ -481,11 +737,11 @@ impl ChidoriState {
         // Watch the directory for changes. Since `path` has not been moved, we can reuse it here.
         debouncer
             .watcher()
-            .watch(Path::new(&path), RecursiveMode::Recursive)
+            .watch(&path, RecursiveMode::Recursive)
             .expect("Failed to watch directory");
         debouncer
             .cache()
-            .add_root(Path::new(&path), RecursiveMode::Recursive);
+            .add_root(&path, RecursiveMode::Recursive);
 
         // Replace the old watcher with the new one.
         *file_watch_guard = Some(debouncer);
@@ -494,14 +750,43 @@ impl ChidoriState {
             let mut chidori_guard = chidori.lock().expect("Failed to lock chidori");
             dbg!("Loading directory");
             chidori_guard
-                .load_md_directory(Path::new(&path))
+                .load_md_directory(&path)
                 .map_err(|e| e.to_string())?;
         }
         Ok(())
     }
 }
 
+/// Run instances of the loaded program in the background, replacing an instance that fails with
+/// a fresh one. The task ends if an instance closes.
+fn spawn_instance_loop(handle: &Handle, chidori: Arc<Mutex<InteractiveChidoriWrapper>>) -> JoinHandle<()> {
+    handle.spawn(async move {
+        loop {
+            // Create an instance within the loop
+            let mut instance = {
+                let mut chidori_guard = chidori.lock().unwrap();
+                let instance = chidori_guard.get_instance().unwrap();
+                drop(chidori_guard); // Drop the lock on chidori to avoid deadlock
+                instance
+            };
+
+            let _ = instance.wait_until_ready().await;
+            let result = instance.run(PlaybackState::Paused).await;
+            match result {
+                Ok(_) => {
+                    println!("Instance completed execution and closed successfully.");
+                    return;
+                }
+                Err(e) => {
+                    println!("Error occurred: {}, retrying...", e);
+                }
+            }
+        }
+    })
+}
+
 fn setup(mut commands: Commands, runtime: ResMut<tokio_tasks::TokioTasksRuntime>) {
+    commands.insert_resource(EguiTree::load_or_default());
     let (trace_event_sender, trace_event_receiver) = std::sync::mpsc::channel();
     let (runtime_event_sender, runtime_event_receiver) = std::sync::mpsc::channel();
     let mut internal_state = ChidoriState {
@@ -513,7 +798,11 @@ fn setup(mut commands: Commands, runtime: ResMut<tokio_tasks::TokioTasksRuntime>
         watched_path: Mutex::new(None),
         background_thread: Mutex::new(None),
         file_watch: Mutex::new(None),
+        file_watch_debounce: DEFAULT_WATCH_DEBOUNCE,
+        file_watch_ignore_patterns: DEFAULT_IGNORE_PATTERNS.iter().map(|p| p.to_string()).collect(),
         display_example_modal: true,
+        examples_directory: None,
+        local_examples: vec![],
         current_playback_state: PlaybackState::Paused,
 
         editor_cells: HashMap::new(),
@@ -526,37 +815,23 @@ fn setup(mut commands: Commands, runtime: ResMut<tokio_tasks::TokioTasksRuntime>
         grouped_nodes: Default::default(),
         current_execution_head: Default::default(),
         execution_ids_to_states: Default::default(),
+        transient_state: Default::default(),
+        agent_traces: Default::default(),
         trace_events: vec![],
+        benchmark_enabled: false,
     };
+    if let Some(directory) = std::env::var_os("CHIDORI_EXAMPLES_DIRECTORY") {
+        if let Err(e) = internal_state.set_examples_directory(Some(PathBuf::from(directory))) {
+            eprintln!("Error reading examples directory: {}", e);
+        }
+    }
 
     {
         let mut background_thread_guard = internal_state
             .background_thread
             .lock()
             .expect("Failed to lock background_thread");
-        let chidori = internal_state.chidori.clone();
-        *background_thread_guard = Some(runtime.spawn_background_task(|mut ctx| async move {
-            loop {
-                // Create an instance within the loop
-                let mut instance = {
-                    let mut chidori_guard = chidori.lock().unwrap();
-                    let instance = chidori_guard.get_instance().unwrap();
-                    drop(chidori_guard); // Drop the lock on chidori to avoid deadlock
-                    instance
-                };
-
-                let _ = instance.wait_until_ready().await;
-                let result = instance.run(PlaybackState::Paused).await;
-                match result {
-                    Ok(_) => {
-                        panic!("Instance completed execution and closed successfully.");
-                    }
-                    Err(e) => {
-                        println!("Error occurred: {}, retrying...", e);
-                    }
-                }
-            }
-        }));
+        *background_thread_guard = Some(spawn_instance_loop(runtime.get_handle(), internal_state.chidori.clone()));
     }
 
     runtime.spawn_background_task(|mut ctx| async move {
@@ -594,6 +869,16 @@ fn setup(mut commands: Commands, runtime: ResMut<tokio_tasks::TokioTasksRuntime>
                             })
                             .await;
                         }
+                        EventsFromRuntime::StatesAtIds(states) => {
+                            ctx.run_on_main_thread(move |ctx| {
+                                if let Some(mut s) =
+                                    ctx.world.get_resource_mut::<ChidoriState>()
+                                {
+                                    s.execution_ids_to_states.extend(states);
+                                }
+                            })
+                            .await;
+                        }
                         EventsFromRuntime::ExecutionStateChange(state) => {
                             ctx.run_on_main_thread(move |ctx| {
                                 if let Some(mut s) =
@@ -638,6 +923,28 @@ fn setup(mut commands: Commands, runtime: ResMut<tokio_tasks::TokioTasksRuntime>
                             .await;
                         }
                         EventsFromRuntime::ReceivedChatMessage(_) => {}
+                        EventsFromRuntime::OperationsChanged(id, op_ids) => {
+                            ctx.run_on_main_thread(move |ctx| {
+                                if let Some(mut s) = ctx.world.get_resource_mut::<ChidoriState>() {
+                                    // Progress of the changed operations is superseded by their values at the new head
+                                    for op_id in &op_ids {
+                                        s.transient_state.remove(op_id);
+                                    }
+                                    if let Some(state) = s.get_execution_state_at_id(&id) {
+                                        s.execution_ids_to_states.insert(id, state);
+                                    }
+                                }
+                            })
+                                .await;
+                        }
+                        EventsFromRuntime::BreakpointHit { op_id, name } => {
+                            ctx.run_on_main_thread(move |ctx| {
+                                if let Some(mut s) = ctx.world.get_resource_mut::<ChidoriState>() {
+                                    s.log_messages.push(format!("Paused at breakpoint {} ({})", name, op_id));
+                                }
+                            })
+                                .await;
+                        }
                         EventsFromRuntime::ExecutionStateCellsViewUpdated(cells) => {
                             ctx.run_on_main_thread(move |ctx| {
                                 if let Some(mut s) = ctx.world.get_resource_mut::<ChidoriState>() {
@@ -651,6 +958,95 @@ fn setup(mut commands: Commands, runtime: ResMut<tokio_tasks::TokioTasksRuntime>
                                 .await;
 
                         }
+                        EventsFromRuntime::TransientStateChange(_id, values) => {
+                            ctx.run_on_main_thread(move |ctx| {
+                                if let Some(mut s) = ctx.world.get_resource_mut::<ChidoriState>() {
+                                    s.transient_state = values;
+                                }
+                            })
+                                .await;
+                        }
+                        EventsFromRuntime::WebhookReceived(op_id, name) => {
+                            ctx.run_on_main_thread(move |ctx| {
+                                if let Some(mut s) = ctx.world.get_resource_mut::<ChidoriState>() {
+                                    let name = name.unwrap_or_else(|| op_id.to_string());
+                                    s.log_messages.push(format!("Webhook received: {}", name));
+                                }
+                            })
+                                .await;
+                        }
+                        EventsFromRuntime::DocumentsReloaded { changed_paths, cells_added, cells_updated, cells_removed } => {
+                            ctx.run_on_main_thread(move |ctx| {
+                                if let Some(mut s) = ctx.world.get_resource_mut::<ChidoriState>() {
+                                    let files = changed_paths.iter()
+                                        .map(|p| p.file_name().map_or_else(|| p.to_string_lossy(), |n| n.to_string_lossy()).to_string())
+                                        .collect::<Vec<_>>()
+                                        .join(", ");
+                                    s.log_messages.push(format!(
+                                        "{} reloaded: {} cells added, {} updated, {} removed",
+                                        files, cells_added, cells_updated, cells_removed
+                                    ));
+                                }
+                            })
+                                .await;
+                        }
+                        EventsFromRuntime::ReloadRejected(report) => {
+                            ctx.run_on_main_thread(move |ctx| {
+                                if let Some(mut s) = ctx.world.get_resource_mut::<ChidoriState>() {
+                                    s.log_messages.push(format!("Reload rejected, {}", report));
+                                }
+                            })
+                                .await;
+                        }
+                        EventsFromRuntime::ReloadApplied(id) => {
+                            ctx.run_on_main_thread(move |ctx| {
+                                if let Some(mut s) = ctx.world.get_resource_mut::<ChidoriState>() {
+                                    s.log_messages.push(format!("Reload applied at {}", id));
+                                }
+                            })
+                                .await;
+                        }
+                        EventsFromRuntime::OperationCompleted { op_id, .. } => {
+                            // Progress reported while the operation ran is superseded by its output
+                            ctx.run_on_main_thread(move |ctx| {
+                                if let Some(mut s) = ctx.world.get_resource_mut::<ChidoriState>() {
+                                    s.transient_state.remove(&op_id);
+                                    if let Some(trace) = s.agent_traces.get_mut(&op_id) {
+                                        trace.completed = true;
+                                    }
+                                }
+                            })
+                                .await;
+                        }
+                        EventsFromRuntime::AgentTraceStep(op_id, step) => {
+                            ctx.run_on_main_thread(move |ctx| {
+                                if let Some(mut s) = ctx.world.get_resource_mut::<ChidoriState>() {
+                                    s.log_messages.push(format!("{} {}: {}", op_id, step.kind, step.content));
+                                    let trace = s.agent_traces.entry(op_id).or_default();
+                                    if trace.completed {
+                                        *trace = OperationAgentTrace::default();
+                                    }
+                                    trace.steps.push(step);
+                                }
+                            })
+                                .await;
+                        }
+                        EventsFromRuntime::StepTiming(_id, duration) => {
+                            ctx.run_on_main_thread(move |ctx| {
+                                if let Some(mut results) = ctx.world.get_resource_mut::<ChidoriBenchmarkResults>() {
+                                    results.record(duration);
+                                }
+                            })
+                                .await;
+                        }
+                        EventsFromRuntime::InstanceRestarted => {
+                            ctx.run_on_main_thread(move |ctx| {
+                                if let Some(mut s) = ctx.world.get_resource_mut::<ChidoriState>() {
+                                    s.log_messages.push("Instance restarted".to_string());
+                                }
+                            })
+                                .await;
+                        }
                         EventsFromRuntime::PlaybackState(state) => {
                             ctx.run_on_main_thread(move |ctx| {
                                 if let Some(mut internal_state) = ctx.world.get_resource_mut::<ChidoriState>() {
@@ -711,8 +1107,11 @@ pub fn update_gui(
     mut egui_tree: ResMut<EguiTree>,
     runtime: ResMut<tokio_tasks::TokioTasksRuntime>,
     mut internal_state: ResMut<ChidoriState>,
+    mut benchmark_results: ResMut<ChidoriBenchmarkResults>,
     mut theme: Res<CurrentTheme>,
-    mut displayed_example_desc: Local<Option<(String, String, String)>>
+    mut displayed_example_desc: Local<Option<(String, String, String)>>,
+    // Estimating the cost of an example parses it, so the formatted estimates are kept between frames
+    mut example_costs: Local<HashMap<String, String>>,
 ) {
     if internal_state.display_example_modal {
         let mut contexts1 = &mut contexts;
@@ -749,7 +1148,7 @@ pub fn update_gui(
                                     let task = rfd::AsyncFileDialog::new().pick_folder();
                                     let folder = task.await;
                                     if let Some(folder) = folder {
-                                        let path = folder.path().to_string_lossy().to_string();
+                                        let path = folder.path().to_path_buf();
                                         ctx.run_on_main_thread(move |ctx| {
                                             if let Some(mut internal_state) =
                                                 ctx.world.get_resource_mut::<ChidoriState>()
@@ -775,28 +1174,6 @@ pub fn update_gui(
                             ui.add_space(16.0);
                             ui.label("Load Example:");
                             ui.style_mut().spacing.item_spacing = egui::vec2(8.0, 8.0);
-                            let buttons_text_load = vec![
-                                ("Core 1: Simple Math", EXAMPLES_CORE1, "Demonstrates simple arithmetic between cells, and that values can be passed between Python and JavaScript runtimes."),
-                                ("Core 2: Marshalling Values", EXAMPLES_CORE2, "All of the types that we can successfully pass between runtimes and that are preserved by our execution engine."),
-                                ("Core 3: Invoking Functions", EXAMPLES_CORE3, "Demonstrates what function execution looks like when using Chidori. Explore how states are preserved and the ability to revert between them with re-execution."),
-                                ("Core 4: Invoking Async Functions", EXAMPLES_CORE4, "Function invocations default to being asynchronous."),
-                                ("Core 5: Invoking Prompts as Functions", EXAMPLES_CORE5, "We treat prompts as first class resources, this demonstrates how prompts are invokable as functions."),
-                                (
-                                    "Core 6: Using Function Calling in Prompts",
-                                    EXAMPLES_CORE6, "Prompts may import functions and invoke those in order to accomplish their instructions."
-
-                                ),
-                                ("Core 7: Chat With PDF Clone", EXAMPLES_CORE7, "Cells preserve their internal state, we provide a specialized API for embeddings which demonstrates this behavior, exposing functions for interacting with that state."),
-                                (
-                                    "Core 8: Anthropic Artifacts Clone",
-                                    EXAMPLES_CORE8, "Chidori is designed for L4-L5 agents, new behaviors can be generated on the fly via code generation."
-                                ),
-                                ("Core 9: Multi-Agent Social Experiment", EXAMPLES_CORE9, "desc"),
-                                ("Core 10: Demonstrating Our Execution Concurrency", EXAMPLES_CORE10, "desc"),
-                                ("Core 11: Hono Web Service", EXAMPLES_CORE11, "desc"),
-                                ("Core 12: Dependency Management", EXAMPLES_CORE12, "desc"),
-                            ];
-
 
                             let available_height = ui.available_height();
                             egui::ScrollArea::vertical()
@@ -812,14 +1189,44 @@ pub fn update_gui(
                                     ui.set_height(available_height);
                                     let mut ui = &mut frame.content_ui;
                                     let mut is_a_button_hovered = false;
-                                    for button in buttons_text_load {
-                                        let res = with_cursor(ui.button(button.0));
+                                    for example in examples() {
+                                        let cost = example_costs.entry(example.name.to_string())
+                                            .or_insert_with(|| format_cost_usd(example.estimated_cost_usd()));
+                                        let res = ui.horizontal(|ui| {
+                                            let res = with_cursor(ui.button(example.title));
+                                            ui.label(format!("Estimated cost: {}", cost));
+                                            res
+                                        }).inner;
+                                        if res.hovered() {
+                                            is_a_button_hovered = true;
+                                            *displayed_example_desc = Some((example.title.to_string(), example.source.to_string(), example.description.to_string()));
+                                        }
+                                        if res.clicked() {
+                                            internal_state1.load_string(example.source);
+                                        }
+                                    }
+                                    let mut load_local_example = None;
+                                    for example in &internal_state1.local_examples {
+                                        let cost = example_costs.entry(example.path.display().to_string())
+                                            .or_insert_with(|| format_cost_usd(example.estimated_cost_usd()));
+                                        let res = ui.horizontal(|ui| {
+                                            let res = with_cursor(ui.button(&example.name));
+                                            ui.label(format!("Estimated cost: {}", cost));
+                                            res
+                                        }).inner;
                                         if res.hovered() {
                                             is_a_button_hovered = true;
-                                            *displayed_example_desc = Some((button.0.to_string(), button.1.to_string(), button.2.to_string()));
+                                            *displayed_example_desc = Some((example.name.clone(), example.source.clone(), example.path.display().to_string()));
                                         }
                                         if res.clicked() {
-                                            internal_state1.load_string(button.1);
+                                            load_local_example = Some(example.path.clone());
+                                        }
+                                    }
+                                    if let Some(path) = load_local_example {
+                                        internal_state1.display_example_modal = false;
+                                        *internal_state1.watched_path.get_mut().unwrap() = Some(path.clone());
+                                        if let Err(e) = internal_state1.load_and_watch_directory(path) {
+                                            eprintln!("Error loading example: {}", e);
                                         }
                                     }
                                     if is_a_button_hovered == false {
@@ -903,6 +1310,14 @@ pub fn update_gui(
                     if with_cursor(ui.button("Reset")).clicked() {
                         internal_state.reset();
                     }
+                    if with_cursor(ui.button("Restart Instance"))
+                        .on_hover_text("Restart the runtime, keeping the loaded cells")
+                        .clicked() {
+                        match internal_state.restart_instance(runtime.get_handle()) {
+                            Ok(()) => internal_state.log_messages.push("Restarted the instance".to_string()),
+                            Err(e) => eprintln!("Error restarting the instance: {}", e),
+                        }
+                    }
                     if with_cursor(ui.button("Open")).clicked() {
                         internal_state.display_example_modal = false;
                         // let sender = self.text_channel.0.clone();
@@ -910,7 +1325,7 @@ pub fn update_gui(
                             let task = rfd::AsyncFileDialog::new().pick_folder();
                             let folder = task.await;
                             if let Some(folder) = folder {
-                                let path = folder.path().to_string_lossy().to_string();
+                                let path = folder.path().to_path_buf();
                                 ctx.run_on_main_thread(move |ctx| {
                                     if let Some(mut internal_state) =
                                         ctx.world.get_resource_mut::<ChidoriState>()
@@ -944,6 +1359,9 @@ pub fn update_gui(
                             if with_cursor(ui.button("Step")).clicked() {
                                 internal_state.step();
                             }
+                            if with_cursor(ui.button("Micro Step")).on_hover_text("Evaluate a single operation").clicked() {
+                                internal_state.micro_step();
+                            }
                         }
                         PlaybackState::Step => {
                             if with_cursor(ui.button("Run")).clicked() {
@@ -961,6 +1379,52 @@ pub fn update_gui(
                     }
                     ui.add_space(8.0);
 
+                    // Cells imported from registries are fetched and loaded only when asked to
+                    let (registry_imports, pending_registry_cells) = {
+                        let chidori = internal_state.chidori.lock().unwrap();
+                        let pending: Vec<String> = chidori.pending_registry_cells.iter()
+                            .map(|holder| format!(
+                                "{} from {}",
+                                holder.cell.name().clone().unwrap_or_default(),
+                                holder.registry_url.clone().unwrap_or_default(),
+                            ))
+                            .collect();
+                        (chidori.registry_imports.clone(), pending)
+                    };
+                    if !pending_registry_cells.is_empty() {
+                        ui.label(format!("{} registry cells to review", pending_registry_cells.len()))
+                            .on_hover_text(pending_registry_cells.join("\n"));
+                        if with_cursor(ui.button("Accept")).clicked() {
+                            if let Err(e) = internal_state.accept_registry_cells() {
+                                eprintln!("Error loading registry cells: {}", e);
+                            }
+                        }
+                        if with_cursor(ui.button("Reject")).clicked() {
+                            internal_state.reject_registry_cells();
+                        }
+                    } else if !registry_imports.is_empty() {
+                        let hover = registry_imports.iter().map(|import| import.url.clone()).collect::<Vec<_>>().join("\n");
+                        if with_cursor(ui.button("Fetch Registry Cells")).on_hover_text(hover).clicked() {
+                            runtime.spawn_background_task(|mut ctx| async move {
+                                let mut fetched = vec![];
+                                for import in registry_imports {
+                                    match fetch_registry_cells(&import).await {
+                                        Ok(cells) => fetched.push((import.manifest_url(), cells)),
+                                        Err(e) => eprintln!("Error fetching cells from {}: {}", import.url, e),
+                                    }
+                                }
+                                ctx.run_on_main_thread(move |ctx| {
+                                    if let Some(internal_state) = ctx.world.get_resource_mut::<ChidoriState>() {
+                                        let mut chidori = internal_state.chidori.lock().unwrap();
+                                        for (registry_url, cells) in fetched {
+                                            chidori.stage_registry_cells(&registry_url, cells);
+                                        }
+                                    }
+                                }).await;
+                            });
+                        }
+                    }
+
                     // let mut my_f32 = 0.0;
                     // ui.add(egui::Slider::new(&mut my_f32, 0.0..=100.0).text("Rate Limit func/s"));
 
@@ -968,6 +1432,25 @@ pub fn update_gui(
                         if with_cursor(ui.button("UI Debug Mode")).clicked() {
                             internal_state.debug_mode = !internal_state.debug_mode;
                         }
+                        if with_cursor(ui.button("Save Layout")).clicked() {
+                            match egui_tree.save() {
+                                Ok(path) => internal_state.log_messages.push(format!("Saved the layout to {}", path.display())),
+                                Err(e) => eprintln!("Error saving the layout: {}", e),
+                            }
+                        }
+                        let benchmark_label = if internal_state.benchmark_enabled { "Stop Benchmark" } else { "Benchmark" };
+                        if with_cursor(ui.button(benchmark_label)).clicked() {
+                            let enabled = !internal_state.benchmark_enabled;
+                            if enabled {
+                                benchmark_results.clear();
+                            }
+                            if let Err(e) = internal_state.benchmark_mode(enabled) {
+                                eprintln!("Failed to set benchmark mode: {}", e);
+                            }
+                        }
+                        if internal_state.benchmark_enabled {
+                            ui.label(benchmark_results.summary().unwrap_or_else(|| "Benchmarking".to_string()));
+                        }
                     });
                 });
 
@@ -980,7 +1463,142 @@ pub fn update_gui(
 pub fn chidori_plugin(app: &mut App) {
     app.init_resource::<EguiTree>()
         .init_resource::<EguiTreeIdentities>()
-        .add_systems(Update, (update_gui, maintain_egui_tree_identities))
+        .init_resource::<ChidoriBenchmarkResults>()
+        .add_systems(Update, (update_gui, maintain_egui_tree_identities, keyboard_shortcut_tab_focus))
         .add_systems(Startup, setup);
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_from_json() {
+        let tree = EguiTree::from_json(r#"{"tabs": [{"pane": "Code"}]}"#).unwrap();
+        let panes: Vec<&str> = tree.tree.tiles.iter()
+            .filter_map(|(_, tile)| match tile {
+                Tile::Pane(pane) => Some(pane.nr.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(panes, vec!["Code"]);
+        assert_eq!(tree.to_layout(), Some(LayoutNode::Tabs(vec![LayoutNode::Pane("Code".to_string())])));
+        assert!(EguiTree::from_json(r#"{"columns": []}"#).is_err());
+
+        let default = EguiTree::default();
+        assert_eq!(EguiTree::from_json(&default.to_json()).unwrap().to_layout(), default.to_layout());
+    }
+
+    #[test]
+    fn test_benchmark_results_after_three_steps() {
+        let runtime = chidori_core::tokio::runtime::Runtime::new().unwrap();
+        let (trace_event_sender, _trace_event_receiver) = std::sync::mpsc::channel();
+        let (runtime_event_sender, runtime_event_receiver) = std::sync::mpsc::channel();
+        let mut state = ChidoriState {
+            chidori: Arc::new(Mutex::new(InteractiveChidoriWrapper::new_with_events(trace_event_sender, runtime_event_sender))),
+            ..Default::default()
+        };
+        state.chidori.lock().unwrap().load_md_string("```python\nx = 1\n```\n\n```python\ny = x + 1\n```\n\n```python\nz = y + 1\n```\n").unwrap();
+        state.restart_instance(runtime.handle()).unwrap();
+        loop {
+            if let EventsFromRuntime::EditorCellsUpdated(_) = runtime_event_receiver.recv_timeout(Duration::from_secs(60)).unwrap() {
+                break;
+            }
+        }
+
+        let mut app = App::new();
+        app.init_resource::<ChidoriBenchmarkResults>();
+        state.benchmark_mode(true).unwrap();
+        for _ in 0..3 {
+            state.step().unwrap();
+            loop {
+                if let EventsFromRuntime::StepTiming(_, duration) = runtime_event_receiver.recv_timeout(Duration::from_secs(60)).unwrap() {
+                    app.world.resource_mut::<ChidoriBenchmarkResults>().record(duration);
+                    break;
+                }
+            }
+        }
+        let results = app.world.resource::<ChidoriBenchmarkResults>();
+        assert_eq!(results.step_durations.len(), 3);
+        assert!(results.step_durations.iter().all(|duration| !duration.is_zero()));
+        assert!(results.summary().unwrap().starts_with("Avg step: "));
+    }
+
+    #[test]
+    fn test_examples_directory_lists_user_examples() {
+        let directory = std::env::temp_dir().join(format!("chidori-debugger-examples-{}", Uuid::now_v7()));
+        std::fs::create_dir_all(directory.join("team_example")).unwrap();
+        std::fs::write(directory.join("team_example").join("core.md"), "```python\ny = 2\n```\n").unwrap();
+
+        let mut state = ChidoriState::default();
+        state.set_examples_directory(Some(directory.clone())).unwrap();
+        assert_eq!(state.local_examples.iter().map(|e| e.name.as_str()).collect::<Vec<_>>(), vec!["team_example"]);
+        state.set_examples_directory(None).unwrap();
+        assert!(state.local_examples.is_empty());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_watcher_ignores_configured_paths() {
+        let directory = std::env::temp_dir().join(format!("chidori-debugger-watch-{}", Uuid::now_v7()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("core.md"), "```python\nx = 1\n```\n").unwrap();
+        let (trace_event_sender, _trace_event_receiver) = std::sync::mpsc::channel();
+        let (runtime_event_sender, runtime_event_receiver) = std::sync::mpsc::channel();
+        let mut state = ChidoriState {
+            chidori: Arc::new(Mutex::new(InteractiveChidoriWrapper::new_with_events(trace_event_sender, runtime_event_sender))),
+            file_watch_debounce: Duration::from_millis(20),
+            ..Default::default()
+        };
+        assert!(state.set_file_watch_ignore_patterns(vec!["[".to_string()]).is_err());
+        state.set_file_watch_ignore_patterns(vec!["drafts/**".to_string()]).unwrap();
+        state.load_and_watch_directory(directory.clone()).unwrap();
+        let reloaded = |receiver: &std::sync::mpsc::Receiver<EventsFromRuntime>| {
+            std::thread::sleep(Duration::from_millis(500));
+            receiver.try_iter().any(|event| matches!(event, EventsFromRuntime::DocumentsReloaded { .. }))
+        };
+        let _ = reloaded(&runtime_event_receiver);
+
+        std::fs::create_dir_all(directory.join("drafts")).unwrap();
+        std::fs::write(directory.join("drafts").join("core.md"), "```python\ny = 2\n```\n").unwrap();
+        std::fs::write(directory.join("notes.txt"), "not a program").unwrap();
+        assert!(!reloaded(&runtime_event_receiver));
+
+        std::fs::write(directory.join("core.md"), "```python\nx = 2\n```\n").unwrap();
+        assert!(reloaded(&runtime_event_receiver));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_restart_instance_after_instance_error() {
+        let runtime = chidori_core::tokio::runtime::Runtime::new().unwrap();
+        let (trace_event_sender, _trace_event_receiver) = std::sync::mpsc::channel();
+        let (runtime_event_sender, runtime_event_receiver) = std::sync::mpsc::channel();
+        let mut state = ChidoriState {
+            chidori: Arc::new(Mutex::new(InteractiveChidoriWrapper::new_with_events(trace_event_sender, runtime_event_sender))),
+            ..Default::default()
+        };
+        state.chidori.lock().unwrap().load_md_string("```python\nx = 1\n```\n").unwrap();
+
+        // An instance loop that has failed
+        let crashed = runtime.spawn(async { panic!("instance error") });
+        *state.background_thread.get_mut().unwrap() = Some(crashed);
+        runtime.block_on(async { chidori_core::tokio::time::sleep(Duration::from_millis(50)).await });
+        assert!(state.background_thread.get_mut().unwrap().as_ref().unwrap().is_finished());
+
+        state.current_execution_head = Uuid::now_v7();
+        state.restart_instance(runtime.handle()).unwrap();
+        runtime.block_on(async { chidori_core::tokio::time::sleep(Duration::from_millis(50)).await });
+        assert!(!state.background_thread.get_mut().unwrap().as_ref().unwrap().is_finished());
+        assert_eq!(state.current_execution_head, Uuid::nil());
+        assert_eq!(state.chidori.lock().unwrap().shared_state.lock().unwrap().editor_cells.len(), 1);
+        assert!(runtime_event_receiver.try_iter().any(|event| matches!(event, EventsFromRuntime::InstanceRestarted)));
+    }
+
+    #[test]
+    fn test_benchmark_results_empty() {
+        let results = ChidoriBenchmarkResults::default();
+        assert_eq!(results.average(), None);
+        assert_eq!(results.summary(), None);
+    }
+}
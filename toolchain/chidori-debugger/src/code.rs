@@ -6,7 +6,7 @@ use crate::{CurrentTheme, GameState, Theme};
 use bevy::app::{App, Update};
 use bevy::prelude::{in_state, Component, IntoSystemConfigs, Local, OnExit, Query, Res, ResMut, Window, With};
 use bevy::window::PrimaryWindow;
-use chidori_core::cells::{CellTypes, CodeCell, LLMCodeGenCell, LLMEmbeddingCell, LLMPromptCell, MemoryCell, SupportedLanguage, SupportedModelProviders, TemplateCell, TextRange};
+use chidori_core::cells::{CellTypes, CodeCell, LLMCodeGenCell, LLMEmbeddingCell, LLMPromptCell, MemoryCell, SupportedLanguage, SupportedModelProviders, RequestConcurrency, TemplateCell, TextRange, WebhookCell};
 use chidori_core::chidori_prompt_format::templating::templates::{SchemaItem, SchemaItemType};
 use chidori_core::execution::primitives::identifiers::OperationId;
 use chidori_core::sdk::interactive_chidori_wrapper::CellHolder;
@@ -40,7 +40,10 @@ impl Default for ViewingWatchedFileCells {
 
 fn file_browser(ui: &mut egui::Ui, path: &Path) {
     let metadata = fs::metadata(path).unwrap();
-    let file_name = path.file_name().unwrap().to_str().unwrap();
+    // Names need not be valid unicode, and the root of a drive has none
+    let file_name = path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string());
 
     if metadata.is_dir() {
         let id = ui.make_persistent_id(path);
@@ -49,7 +52,7 @@ fn file_browser(ui: &mut egui::Ui, path: &Path) {
         state.show_header(ui, |ui| {
             ui.horizontal(|ui| {
                 ui.add(egui::Label::new("📁").sense(egui::Sense::click()));
-                ui.label(file_name);
+                ui.label(file_name.as_str());
             });
         })
             .body(|ui| {
@@ -64,7 +67,7 @@ fn file_browser(ui: &mut egui::Ui, path: &Path) {
     } else {
         ui.horizontal(|ui| {
             ui.add(egui::Label::new("📄").sense(egui::Sense::click()));
-            ui.label(file_name);
+            ui.label(file_name.as_str());
         });
     }
 }
@@ -124,9 +127,9 @@ fn editor_update(
                 ui.push_id("file_browser", |ui| {
                     egui::ScrollArea::vertical().auto_shrink(Vec2b::new(true, false)).show(ui, |ui| {
                         ui.set_max_width(200.0);
-                        let path = &chidori_state.watched_path.lock().unwrap().clone();
-                        if path.is_some() {
-                            file_browser(ui, Path::new(&path.as_ref().unwrap()));
+                        let path = chidori_state.watched_path.lock().unwrap().clone();
+                        if let Some(path) = path {
+                            file_browser(ui, &path);
                         }
                     });
                 });
@@ -240,6 +243,24 @@ pub fn editable_chidori_cell_content(
         if chidori_state.debug_mode {
             ui.label(format!("Operation Id: {:?}", op_id));
         }
+        if let Some(name) = cell_holder.cell.name().clone() {
+            let mut breakpoint = chidori_state.has_breakpoint(&name);
+            if ui.checkbox(&mut breakpoint, "Breakpoint")
+                .on_hover_text("Pause execution before this cell is evaluated")
+                .changed() {
+                if let Err(e) = chidori_state.set_breakpoint(&name, breakpoint) {
+                    eprintln!("Error setting the breakpoint on {}: {}", name, e);
+                }
+            }
+        }
+        if ui.button("Copy as base64").clicked() {
+            let encoded = cell_holder.serialize_to_base64();
+            ui.output_mut(|o| o.copied_text = encoded);
+        }
+        if let Some(description) = &cell_holder.description {
+            ui.label(egui::RichText::new(description).italics().color(theme.muted_foreground))
+                .on_hover_text(description);
+        }
         match &mut cell_holder.cell {
             CellTypes::Code(_, ..) => {
                 render_code_cell(
@@ -261,6 +282,24 @@ pub fn editable_chidori_cell_content(
             CellTypes::Template(..) => {
                 render_template_cell(&mut chidori_state, &op_id, ui, cell_holder, exists_in_current_tree);
             }
+            CellTypes::Webhook(..) => {
+                render_webhook_cell(ui, cell_holder);
+            }
+            CellTypes::Mcp(..) => {
+                render_mcp_cell(ui, cell_holder);
+            }
+            CellTypes::File(..) => {
+                render_file_cell(ui, cell_holder);
+            }
+            CellTypes::Extract(..) => {
+                render_extract_cell(ui, cell_holder);
+            }
+            CellTypes::Memory(..) => {
+                render_memory_cell(ui, cell_holder);
+            }
+            CellTypes::Chunk(..) => {
+                render_chunk_cell(ui, cell_holder);
+            }
         }
 
         let state_binding = chidori_state.local_cell_state.entry(op_id).or_insert(Arc::new(Mutex::new(CellState::default()))).clone();
@@ -292,6 +331,94 @@ pub fn editable_chidori_cell_content(
     frame.end(ui);
 }
 
+fn render_webhook_cell(ui: &mut Ui, cell_holder: &mut CellHolder) {
+    let CellTypes::Webhook(WebhookCell { name, host, port, path, secret_env, signature_header, handler, record_requests, concurrency, route_table, .. }, _) = &cell_holder.cell else { panic!("Must be webhook cell")};
+    ui.horizontal(|ui| {
+        egui_label(ui, "Webhook");
+        if let Some(name) = name {
+            ui.label(name);
+        }
+    });
+    ui.label(format!("POST {}:{}{}", host, port, path));
+    if secret_env.is_some() {
+        ui.label(format!("Verifies signatures in {}", signature_header));
+    }
+    if let Some(handler) = handler {
+        ui.label(format!("Answered by {}{}", handler, if *record_requests { ", requests are recorded" } else { "" }));
+    }
+    if *concurrency == RequestConcurrency::Serial {
+        ui.label("Requests are evaluated one at a time");
+    }
+    if !route_table.routes.is_empty() {
+        ui.collapsing("Routes", |ui| {
+            for route in &route_table.routes {
+                ui.label(format!("{} {} → {}({})", route.method, route.path, route.handler, route.params.join(", ")));
+            }
+        });
+    }
+}
+
+fn render_mcp_cell(ui: &mut Ui, cell_holder: &mut CellHolder) {
+    let CellTypes::Mcp(cell, _) = &cell_holder.cell else { panic!("Must be mcp cell")};
+    ui.horizontal(|ui| {
+        egui_label(ui, "MCP Server");
+        if let Some(name) = &cell.name {
+            ui.label(name);
+        }
+    });
+    ui.label(cell.server_description());
+}
+
+fn render_file_cell(ui: &mut Ui, cell_holder: &mut CellHolder) {
+    let CellTypes::File(cell, _) = &cell_holder.cell else { panic!("Must be file cell")};
+    ui.horizontal(|ui| {
+        egui_label(ui, if cell.write.is_some() { "Write File" } else { "Read File" });
+        if let Some(name) = &cell.name {
+            ui.label(name);
+        }
+    });
+    ui.label(&cell.path);
+}
+
+fn render_extract_cell(ui: &mut Ui, cell_holder: &mut CellHolder) {
+    let CellTypes::Extract(cell, _) = &cell_holder.cell else { panic!("Must be extract cell")};
+    ui.horizontal(|ui| {
+        egui_label(ui, "Extract");
+        if let Some(name) = &cell.name {
+            ui.label(name);
+        }
+    });
+    match &cell.source {
+        Some(source) => ui.label(format!("Text of {} ({:?})", source, cell.format)),
+        None => ui.label("Invoked as a function"),
+    };
+}
+
+fn render_memory_cell(ui: &mut Ui, cell_holder: &mut CellHolder) {
+    let CellTypes::Memory(cell, _) = &cell_holder.cell else { panic!("Must be memory cell")};
+    ui.horizontal(|ui| {
+        egui_label(ui, "Memory");
+        if let Some(name) = &cell.name {
+            ui.label(name);
+        }
+    });
+    ui.label(format!("Embedded with {} ({:?})", cell.embedding_model, cell.backend));
+}
+
+fn render_chunk_cell(ui: &mut Ui, cell_holder: &mut CellHolder) {
+    let CellTypes::Chunk(cell, _) = &cell_holder.cell else { panic!("Must be chunk cell")};
+    ui.horizontal(|ui| {
+        egui_label(ui, "Chunk");
+        if let Some(name) = &cell.name {
+            ui.label(name);
+        }
+    });
+    match &cell.source {
+        Some(source) => ui.label(format!("Chunks of {} ({:?}, {} tokens)", source, cell.strategy, cell.size)),
+        None => ui.label("Invoked as a function"),
+    };
+}
+
 fn render_template_cell(
     execution_state: &ChidoriState,
     op_id: &OperationId,
@@ -339,6 +466,18 @@ fn render_template_cell(
 }
 
 fn render_operation_output(execution_state: &ChidoriState, op_id: &&OperationId, ui: &mut Ui) {
+    if let Some(trace) = execution_state.agent_traces.get(*op_id).filter(|trace| !trace.steps.is_empty()) {
+        ui.push_id(("agent_trace", op_id), |ui| {
+            ui.collapsing("Agent Trace", |ui| {
+                for step in &trace.steps {
+                    ui.horizontal_wrapped(|ui| {
+                        egui_label(ui, &step.kind.to_string());
+                        ui.label(&step.content);
+                    });
+                }
+            });
+        });
+    }
     // if let Some(state) = &execution_state.merged_state_history {
     //     if let Some((exec_id, o)) = state.0.get(op_id) {
     //         if ui.button(format!("View Most Recent Execution")).clicked() {
@@ -753,10 +892,19 @@ fn render_new_cell_interface(
                     language: SupportedLanguage::PyO3,
                     source_code: "".to_string(),
                     function_invocation: None,
+                    inspect_globals: false,
+                    redact_output: false,
+                    policy: Default::default(),
+                    oom_limit_bytes: None,
+                    always_run: false,
+                    generate_types: false,
+                    package_json: None,
                 }, TextRange::default()),
                 op_id,
                 applied_at: Default::default(),
                 needs_update: false,
+                description: None,
+                registry_url: None,
             });
         }
         if ui.button("Add Prompt Cell").clicked() {
@@ -774,6 +922,8 @@ fn render_new_cell_interface(
                 op_id,
                 applied_at: Default::default(),
                 needs_update: false,
+                description: None,
+                registry_url: None,
             });
         }
         if ui.button("Add Template Cell").clicked() {
@@ -783,10 +933,13 @@ fn render_new_cell_interface(
                     backing_file_reference: None,
                     name: None,
                     body: "".to_string(),
+                    engine: Default::default(),
                 }, TextRange::default()),
                 op_id,
                 applied_at: Default::default(),
                 needs_update: false,
+                description: None,
+                registry_url: None,
             });
         }
         if ui.button("Add Code Generation Cell").clicked() {
@@ -804,10 +957,43 @@ fn render_new_cell_interface(
                 op_id,
                 applied_at: Default::default(),
                 needs_update: false,
+                description: None,
+                registry_url: None,
             }));
         }
     }
 
+    if state.temp_cell.is_none() {
+        if ui.button(if state.is_paste_open { "Close Paste Cell" } else { "Paste Cell" }).clicked() {
+            state.is_paste_open = !state.is_paste_open;
+            state.paste_error = None;
+        }
+        if state.is_paste_open {
+            ui.label("Paste a cell copied as base64");
+            ui.text_edit_multiline(&mut state.paste_content);
+            if ui.button("Insert").clicked() {
+                match CellHolder::deserialize_from_base64(&state.paste_content) {
+                    Ok(cell_holder) => {
+                        // The pasted cell is a new cell of this program, independent of the one it was copied from
+                        state.temp_cell = Some(CellHolder {
+                            op_id: Uuid::now_v7(),
+                            applied_at: None,
+                            needs_update: false,
+                            ..cell_holder
+                        });
+                        state.is_paste_open = false;
+                        state.paste_content.clear();
+                        state.paste_error = None;
+                    }
+                    Err(e) => state.paste_error = Some(e.to_string()),
+                }
+            }
+            if let Some(error) = &state.paste_error {
+                ui.colored_label(Color32::RED, error);
+            }
+        }
+    }
+
 let exists_in_current_tree = false;
     let mut temp_cell_created = false;
     if let Some(temp_cell) = state.temp_cell.as_mut() {
@@ -833,6 +1019,24 @@ let exists_in_current_tree = false;
             CellTypes::Template(..) => {
                 render_template_cell(&mut chidori_state, &op_id, ui, temp_cell, exists_in_current_tree);
             }
+            CellTypes::Webhook(..) => {
+                render_webhook_cell(ui, temp_cell);
+            }
+            CellTypes::Mcp(..) => {
+                render_mcp_cell(ui, temp_cell);
+            }
+            CellTypes::File(..) => {
+                render_file_cell(ui, temp_cell);
+            }
+            CellTypes::Extract(..) => {
+                render_extract_cell(ui, temp_cell);
+            }
+            CellTypes::Memory(..) => {
+                render_memory_cell(ui, temp_cell);
+            }
+            CellTypes::Chunk(..) => {
+                render_chunk_cell(ui, temp_cell);
+            }
         }
 
         if ui.button("Save and Push To Graph").clicked() {
@@ -521,6 +521,7 @@ fn egui_execution_state(
                             if execution_state.fresh_values.contains(key) {
                                 match &value.output.clone() {
                                     Ok(o) => {
+                                        ui.label(RichText::new(o.type_name()).small().weak());
                                         let _ = JsonTree::new(format!("{:?}", key), &serialized_value_to_json_value(&o))
                                             // .default_expand(DefaultExpand::SearchResults(&self.search_input))
                                             .show(ui);
@@ -533,6 +534,33 @@ fn egui_execution_state(
                         }
                     });
                 }
+                // The arguments each operation evaluated in this state consumed, to reproduce the evaluation
+                let recorded_inputs: Vec<_> = execution_state.state.iter()
+                    .filter(|(key, _)| execution_state.fresh_values.contains(*key))
+                    .filter_map(|(key, value)| value.input.as_ref().map(|input| (key, input)))
+                    .collect();
+                if !recorded_inputs.is_empty() {
+                    egui::CollapsingHeader::new("Input")
+                        .id_source(("recorded_input", execution_state.chronology_id))
+                        .show(ui, |ui| {
+                            for (key, input) in recorded_inputs {
+                                let _ = JsonTree::new(format!("input {:?}", key), &serialized_value_to_json_value(&input.value))
+                                    .show(ui);
+                            }
+                        });
+                }
+                if !execution_state.state_transient.is_empty() {
+                    // Transient values are not retained in history, render them dimmed to distinguish them
+                    ui.label(RichText::new("Transient:").italics().weak());
+                    ui.horizontal(|ui| {
+                        ui.add_space(10.0);
+                        ui.vertical(|ui| {
+                            for (key, value) in execution_state.state_transient.iter() {
+                                ui.label(RichText::new(format!("{:?}: {}", key, serialized_value_to_json_value(value))).italics().weak());
+                            }
+                        });
+                    });
+                }
             })
         });
 
@@ -1410,6 +1438,15 @@ fn egui_graph_node(
             } else {
                 if let Some(state) = chidori_state.get_execution_state_at_id(&node1) {
                     let state = &state;
+                    // Labels of the dependencies feeding the evaluated operation, drawn where the edge
+                    // from the previous state meets this node
+                    let edge_labels: Vec<&str> = chidori_state.definition_graph.iter()
+                        .filter(|(_, to, _, _)| *to == state.evaluating_operation_id)
+                        .flat_map(|(_, _, _, labels)| labels.iter().map(String::as_str))
+                        .collect();
+                    if !edge_labels.is_empty() {
+                        ui.label(RichText::new(edge_labels.join(" · ")).small().weak());
+                    }
                     if !matches!(state.evaluating_enclosed_state, EnclosedState::Open) {
                         ui.horizontal(|ui| {
                             if chidori_state.debug_mode {
@@ -1120,6 +1120,8 @@ mod tests {
             location: "test_location".to_string(),
             line: "1".to_string(),
             execution_id: None,
+            operation_id: None,
+            run_id: None,
         }
     }
 
@@ -6,7 +6,7 @@ use egui::{Color32, FontFamily, FontId, Frame, Margin, RichText, Ui};
 use egui_extras::syntax_highlighting;
 use egui_extras::syntax_highlighting::CodeTheme;
 use egui_json_tree::value::{BaseValueType, ExpandableType, JsonTreeValue, ToJsonTreeValue};
-use chidori_core::cells::{CellTypes, CodeCell, LLMCodeGenCell, LLMEmbeddingCell, LLMPromptCell, MemoryCell, SupportedLanguage, TemplateCell, WebserviceCell};
+use chidori_core::cells::{CellTypes, CodeCell, LLMCodeGenCell, LLMEmbeddingCell, LLMPromptCell, MemoryCell, SupportedLanguage, TemplateCell, WebhookCell, WebserviceCell};
 use chidori_core::execution::primitives::serialized_value::RkyvSerializedValue;
 
 
@@ -60,6 +60,22 @@ pub fn egui_rkyv(ui: &mut Ui, value: &RkyvSerializedValue, with_clip: bool) {
         RkyvSerializedValue::StreamPointer(_) => {}
         RkyvSerializedValue::FunctionPointer(_, _) => {}
         RkyvSerializedValue::Cell(_) => {}
+        RkyvSerializedValue::Error { kind, message } => {
+            ui.label(format!("{}: {}", kind, message));
+        }
+        RkyvSerializedValue::Table { schema, row_count, preview, .. } => {
+            ui.vertical(|ui| {
+                ui.label(format!("Table of {} rows", row_count));
+                ui.separator();
+                for (name, data_type) in schema {
+                    ui.label(format!("{}: {}", name, data_type));
+                }
+                ui.separator();
+                for row in preview {
+                    egui_rkyv(ui, row, with_clip);
+                }
+            });
+        }
         RkyvSerializedValue::Set(_) => {}
         RkyvSerializedValue::Float(a) => {
             ui.label(format!("{:?}", a));
@@ -216,6 +232,24 @@ pub fn egui_render_cell_read(ui: &mut Ui, cell: &CellTypes, state: &ExecutionSta
             render_text_cell(ui, name, body, "Prompt", "", &theme);
         }
         CellTypes::Prompt(LLMPromptCell::Completion { .. }, _)  => {}
+        CellTypes::Webhook(WebhookCell { name, port, path, .. }, _) => {
+            render_text_cell(ui, name, &format!("POST :{}{}", port, path), "Webhook", "", &theme);
+        }
+        CellTypes::Mcp(cell, _) => {
+            render_text_cell(ui, &cell.name, &cell.server_description(), "MCP Server", "", &theme);
+        }
+        CellTypes::File(cell, _) => {
+            render_text_cell(ui, &cell.name, &cell.path, "File", "", &theme);
+        }
+        CellTypes::Extract(cell, _) => {
+            render_text_cell(ui, &cell.name, cell.source.as_deref().unwrap_or_default(), "Extract", "", &theme);
+        }
+        CellTypes::Memory(cell, _) => {
+            render_text_cell(ui, &cell.name, &cell.embedding_model, "Memory", "", &theme);
+        }
+        CellTypes::Chunk(cell, _) => {
+            render_text_cell(ui, &cell.name, cell.source.as_deref().unwrap_or_default(), "Chunk", "", &theme);
+        }
     }
 }
 
@@ -419,6 +453,14 @@ pub fn serialized_value_to_json_value(v: &RkyvSerializedValue) -> serde_json::Va
         RkyvSerializedValue::FunctionPointer(_, _) => Value::Null,
         RkyvSerializedValue::StreamPointer(_) => Value::Null,
         RkyvSerializedValue::Cell(_) => Value::Null,
+        RkyvSerializedValue::Error { kind, message } => serde_json::json!({
+            "error": { "kind": kind, "message": message }
+        }),
+        RkyvSerializedValue::Table { preview, .. } => Value::Array(
+            preview.iter()
+                .map(|v| serialized_value_to_json_value(v))
+                .collect(),
+        ),
         RkyvSerializedValue::Null => Value::Null,
         RkyvSerializedValue::Set(a) => {
             a.iter()
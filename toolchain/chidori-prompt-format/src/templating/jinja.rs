@@ -0,0 +1,113 @@
+//! Templates with Jinja2 semantics, rendered by minijinja, as an alternative to the Handlebars
+//! templates of `templates`. Other templates are included with `{% include "name" %}`.
+
+use std::collections::HashMap;
+use minijinja::{AutoEscape, Environment, ErrorKind, UndefinedBehavior};
+use crate::templating::templates::{ChatModelRoles, SchemaItem, SchemaItemType, TemplateRenderError, TemplateWithSource};
+
+/// Functions available to every template, which are not variables the template expects to be given
+const BUILTIN_GLOBALS: &[&str] = &["range", "dict", "namespace", "debug", "loop", "self"];
+
+/// The variables a Jinja template expects to be given, each as a string item of the schema. Only
+/// the root of a path is reported, `{{ user.name }}` expects `user`.
+pub fn analyze_jinja_referenced_variables(template: &str) -> anyhow::Result<SchemaItem> {
+    let env = Environment::new();
+    let template = env.template_from_str(template).map_err(|e| anyhow::Error::msg(e.to_string()))?;
+    let items = template.undeclared_variables(false).into_iter()
+        .filter(|name| !BUILTIN_GLOBALS.contains(&name.as_str()))
+        .map(|name| (name, Box::new(SchemaItem { ty: SchemaItemType::String, items: HashMap::new() })))
+        .collect();
+    Ok(SchemaItem { ty: SchemaItemType::Object, items })
+}
+
+/// Jinja prompts have no role blocks, the whole template is sent as a single user message
+pub fn jinja_template_as_user_message(template: &str) -> Vec<(ChatModelRoles, Option<TemplateWithSource>)> {
+    vec![(ChatModelRoles::User, Some(TemplateWithSource {
+        template: Default::default(),
+        source: template.to_string(),
+    }))]
+}
+
+impl TemplateRenderError {
+    fn from_jinja_error(name: &str, error: minijinja::Error) -> Self {
+        let template = error.name().unwrap_or(name).to_string();
+        match error.kind() {
+            ErrorKind::UndefinedError => TemplateRenderError::MissingVariable {
+                template,
+                path: None,
+                line: error.line(),
+            },
+            ErrorKind::TemplateNotFound => TemplateRenderError::MissingPartial {
+                template: name.to_string(),
+                partial: error.detail().unwrap_or_default().to_string(),
+            },
+            ErrorKind::SyntaxError => TemplateRenderError::InvalidTemplate {
+                template,
+                message: error.to_string(),
+            },
+            _ => TemplateRenderError::Render {
+                template,
+                message: error.to_string(),
+            },
+        }
+    }
+}
+
+/// Render the Jinja template `name` with the given templates available to include, by name. In
+/// strict mode a variable missing from the data is an error, otherwise it renders as empty.
+pub fn render_named_jinja_template(
+    name: &str,
+    template_str: &str,
+    json_value: &serde_json::Value,
+    partials: &HashMap<String, String>,
+    strict: bool,
+) -> Result<String, TemplateRenderError> {
+    let mut env = Environment::new();
+    env.set_undefined_behavior(if strict { UndefinedBehavior::Strict } else { UndefinedBehavior::Chainable });
+    env.set_auto_escape_callback(|_| AutoEscape::None);
+    for (partial_name, source) in partials.iter() {
+        env.add_template(partial_name, source)
+            .map_err(|e| TemplateRenderError::from_jinja_error(partial_name, e))?;
+    }
+    env.add_template(name, template_str)
+        .map_err(|e| TemplateRenderError::from_jinja_error(name, e))?;
+    env.get_template(name)
+        .and_then(|template| template.render(json_value))
+        .map_err(|e| TemplateRenderError::from_jinja_error(name, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_analyze_jinja_variables() {
+        let schema = analyze_jinja_referenced_variables(
+            "{% for item in items %}{{ item.name | upper }}{% endfor %} {{ user.name }} {{ range(3) | length }}"
+        ).unwrap();
+        let mut names: Vec<&String> = schema.items.keys().collect();
+        names.sort();
+        assert_eq!(names, vec!["items", "user"]);
+    }
+
+    #[test]
+    fn test_render_jinja_with_includes() {
+        let partials = HashMap::from([("signature".to_string(), "Regards, {{ sender }}".to_string())]);
+        let rendered = render_named_jinja_template(
+            "email",
+            "Dear {{ user.name | title }},\n{% include \"signature\" %}",
+            &json!({"user": {"name": "ada"}, "sender": "Chidori"}),
+            &partials,
+            true,
+        );
+        assert_eq!(rendered, Ok("Dear Ada,\nRegards, Chidori".to_string()));
+    }
+
+    #[test]
+    fn test_missing_jinja_variable() {
+        let err = render_named_jinja_template("email", "Dear {{ name }}", &json!({}), &HashMap::new(), true).unwrap_err();
+        assert!(matches!(err, TemplateRenderError::MissingVariable { ref template, .. } if template == "email"), "{:?}", err);
+        assert_eq!(render_named_jinja_template("email", "Dear {{ name }}", &json!({}), &HashMap::new(), false), Ok("Dear ".to_string()));
+    }
+}
@@ -2,11 +2,12 @@
 ///! trace how the final prompt was assembled and why.
 use anyhow::Result;
 use handlebars::template::{Parameter, Subexpression, TemplateElement, TemplateMapping};
-use handlebars::{Handlebars, Path, Template};
+use handlebars::{handlebars_helper, Handlebars, Path, Template};
 use serde::{Deserialize, Serialize};
 use serde_json::value::Map as JsonMap;
 use serde_json::{Map, Value};
 use std::collections::HashMap;
+use std::fmt::Write;
 use wasm_bindgen::prelude::wasm_bindgen;
 use wasm_bindgen::JsValue;
 
@@ -225,42 +226,41 @@ pub fn analyze_referenced_partials_inner<F>(
     }
 }
 
+/// Splits a leading YAML frontmatter block, delimited by `---` lines, from the rest of the markdown.
+/// Only an unindented `---` may close the block, and the first one after which the frontmatter is
+/// valid YAML is used, so block scalars and lists containing `---` or starting with `-` are kept intact.
 pub fn split_frontmatter(
     markdown: &str,
 ) -> std::result::Result<(String, String), Box<dyn std::error::Error>> {
-    let mut front_matter = String::default();
-    let mut sentinel = false;
-    let mut front_matter_lines = 0;
     let lines: Vec<&str> = markdown.lines().collect();
-
-    for line in &lines {
-        front_matter_lines += 1;
-
-        if line.trim() == "---" {
-            if sentinel {
-                // If we've encountered the second `---`, break the loop
-                break;
-            }
-            sentinel = true; // Mark that we've encountered the first `---`
-            continue;
-        }
-
-        if sentinel {
-            front_matter.push_str(line);
-            front_matter.push('\n');
-        }
-    }
-
-    if sentinel {
-        // Return front matter and the rest of the markdown if front matter was found
-        Ok((
-            front_matter.trim_end().to_string(), // Trim the trailing newline from the front matter
-            lines[front_matter_lines..].join("\n"),
-        ))
-    } else {
+    let Some(opening) = lines.iter().position(|line| line.trim() == "---") else {
         // Return the entire markdown as content with an empty front matter if no front matter was found
-        Ok((String::default(), markdown.to_string()))
-    }
+        return Ok((String::default(), markdown.to_string()));
+    };
+
+    let closing_candidates: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .skip(opening + 1)
+        .filter(|(_, line)| line.trim_end() == "---")
+        .map(|(i, _)| i)
+        .collect();
+    let front_matter_of = |closing: usize| lines[opening + 1..closing].join("\n");
+    let closing = closing_candidates
+        .iter()
+        .copied()
+        .find(|&closing| serde_yaml::from_str::<serde_yaml::Value>(&front_matter_of(closing)).is_ok())
+        // Invalid frontmatter is reported by whoever deserializes it
+        .or(closing_candidates.first().copied());
+
+    Ok(match closing {
+        Some(closing) => (
+            front_matter_of(closing).trim_end().to_string(),
+            lines[closing + 1..].join("\n"),
+        ),
+        // An unterminated block is all frontmatter
+        None => (lines[opening + 1..].join("\n").trim_end().to_string(), String::default()),
+    })
 }
 
 #[wasm_bindgen]
@@ -385,8 +385,63 @@ pub struct PromptLibraryRecord {
     description: Option<String>,
 }
 
+handlebars_helper!(length: |value: Json| match value {
+    Value::Array(items) => items.len(),
+    Value::Object(fields) => fields.len(),
+    Value::String(s) => s.chars().count(),
+    _ => 0,
+});
+
+fn is_template_path(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '/' | '@' | '[' | ']'))
+}
+
+/// Handlebars does not expose a `length` property on arrays, so `{{items.length}}` and
+/// `{{#if items.length}}` are rewritten to use the `length` helper instead.
+fn rewrite_length_accessors(template: &str) -> String {
+    let mut rewritten = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}") else {
+            break;
+        };
+        let end = start + len;
+        let expression = &rest[start + 2..end];
+        rewritten.push_str(&rest[..start + 2]);
+        if expression.trim_start().starts_with('!') {
+            rewritten.push_str(expression);
+        } else {
+            let mut is_name = true;
+            for piece in expression.split_inclusive(char::is_whitespace) {
+                let token = piece.trim_end();
+                if token.is_empty() {
+                    rewritten.push_str(piece);
+                    continue;
+                }
+                let trailing = &piece[token.len()..];
+                match token.strip_suffix(".length") {
+                    Some(path) if is_template_path(path) && is_name => {
+                        let _ = write!(rewritten, "length {}{}", path, trailing);
+                    }
+                    Some(path) if is_template_path(path) => {
+                        let _ = write!(rewritten, "(length {}){}", path, trailing);
+                    }
+                    _ => rewritten.push_str(piece),
+                }
+                is_name = false;
+            }
+        }
+        rest = &rest[end..];
+    }
+    rewritten.push_str(rest);
+    rewritten
+}
+
 /// Render a template string, placing in partials (names that map to prompts in the prompt library) and values from the query paths
 /// as records of changes that are made to the event log
+///
+/// Values may be nested objects and arrays. Arrays are indexed with `{{items.[0]}}` or `{{lookup items 0}}`,
+/// and the `length` helper, or `{{items.length}}`, gives the number of elements in an array or object.
 pub fn render_template_prompt(
     template_str: &str,
     json_value: &serde_json::Value,
@@ -397,21 +452,152 @@ pub fn render_template_prompt(
         reg.register_partial(name, prompt.template.as_str())
             .unwrap();
     }
-    reg.register_template_string("tpl_1", template_str).unwrap();
+    reg.register_helper("length", Box::new(length));
+    reg.register_template_string("tpl_1", rewrite_length_accessors(template_str)).unwrap();
     reg.register_escape_fn(handlebars::no_escape);
     let render = reg.render("tpl_1", &json_value).unwrap();
     Ok(render)
 }
 
+/// Why a template could not be rendered by `render_named_template`
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateRenderError {
+    /// No template with this name is known
+    UnknownTemplate {
+        template: String,
+    },
+    /// The data has no value at a path referenced by the template, `template` is the template or
+    /// partial in which the reference appears
+    MissingVariable {
+        template: String,
+        path: Option<String>,
+        line: Option<usize>,
+    },
+    /// The template includes a partial that was not supplied
+    MissingPartial {
+        template: String,
+        partial: String,
+    },
+    /// The template or one of its partials failed to parse
+    InvalidTemplate {
+        template: String,
+        message: String,
+    },
+    Render {
+        template: String,
+        message: String,
+    },
+}
+
+impl std::fmt::Display for TemplateRenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TemplateRenderError::UnknownTemplate { template } => {
+                write!(f, "no template named `{}` is defined", template)
+            }
+            TemplateRenderError::MissingVariable { template, path, line } => {
+                write!(f, "template `{}` references ", template)?;
+                match path {
+                    Some(path) => write!(f, "`{}`", path)?,
+                    None => write!(f, "a variable")?,
+                }
+                write!(f, ", which is missing from the supplied data")?;
+                if let Some(line) = line {
+                    write!(f, " (line {})", line)?;
+                }
+                Ok(())
+            }
+            TemplateRenderError::MissingPartial { template, partial } => {
+                write!(f, "template `{}` includes the partial `{}`, which is not defined", template, partial)
+            }
+            TemplateRenderError::InvalidTemplate { template, message } => {
+                write!(f, "template `{}` is invalid: {}", template, message)
+            }
+            TemplateRenderError::Render { template, message } => {
+                write!(f, "failed to render template `{}`: {}", template, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TemplateRenderError {}
+
+impl TemplateRenderError {
+    fn from_render_error(name: &str, error: handlebars::RenderError) -> Self {
+        let template = error.template_name.clone().unwrap_or_else(|| name.to_string());
+        match error.reason() {
+            handlebars::RenderErrorReason::MissingVariable(path) => TemplateRenderError::MissingVariable {
+                template,
+                path: path.clone(),
+                line: error.line_no,
+            },
+            handlebars::RenderErrorReason::PartialNotFound(partial) => TemplateRenderError::MissingPartial {
+                template,
+                partial: partial.clone(),
+            },
+            _ => TemplateRenderError::Render {
+                template,
+                message: error.to_string(),
+            },
+        }
+    }
+}
+
+/// Render the template `name` with the given partials, which are template sources by name. In
+/// strict mode a variable missing from the data is an error, otherwise it renders as empty.
+pub fn render_named_template(
+    name: &str,
+    template_str: &str,
+    json_value: &serde_json::Value,
+    partials: &HashMap<String, String>,
+    strict: bool,
+) -> std::result::Result<String, TemplateRenderError> {
+    let mut reg = Handlebars::new();
+    reg.set_strict_mode(strict);
+    reg.register_helper("length", Box::new(length));
+    reg.register_escape_fn(handlebars::no_escape);
+    for (partial_name, source) in partials.iter() {
+        reg.register_partial(partial_name, rewrite_length_accessors(source))
+            .map_err(|e| TemplateRenderError::InvalidTemplate { template: partial_name.clone(), message: e.to_string() })?;
+    }
+    reg.register_template_string(name, rewrite_length_accessors(template_str))
+        .map_err(|e| TemplateRenderError::InvalidTemplate { template: name.to_string(), message: e.to_string() })?;
+    reg.render(name, json_value)
+        .map_err(|e| TemplateRenderError::from_render_error(name, e))
+}
+
 fn get_source_string_from_template(source: &str, template: &Template) -> String {
     let start_index = template.span.0;
     let end_index = template.span.1;
     source[start_index..end_index].to_string()
 }
 
+/// Characters per token of English text, a rough rule for estimating token counts
+pub const CHARACTERS_PER_TOKEN: usize = 4;
+
+/// What can be known about a template without rendering it
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TemplateAnalysis {
+    /// Approximate number of tokens of the template's own text, not counting what its
+    /// expressions render to
+    pub estimated_tokens: usize,
+}
+
 /// Apply all analysis to template
-fn analyze_template(source: &str) -> String {
-    String::new()
+pub fn analyze_template(source: &str) -> TemplateAnalysis {
+    let mut literal_characters = 0;
+    let mut rest = source;
+    while let Some(start) = rest.find("{{") {
+        literal_characters += rest[..start].chars().count();
+        rest = match rest[start..].find("}}") {
+            Some(end) => &rest[start + end + 2..],
+            None => "",
+        };
+    }
+    literal_characters += rest.chars().count();
+    TemplateAnalysis {
+        estimated_tokens: literal_characters.div_ceil(CHARACTERS_PER_TOKEN),
+    }
 }
 
 #[cfg(test)]
@@ -445,6 +631,12 @@ mod tests {
     //     );
     // }
 
+    #[test]
+    fn test_analyze_template_counts_literal_text() {
+        assert_eq!(analyze_template("").estimated_tokens, 0);
+        assert_eq!(analyze_template("{{#system}}Say hi{{/system}} to {{name}}").estimated_tokens, 3);
+    }
+
     #[test]
     fn test_rendering_template() {
         let value = json! {
@@ -461,6 +653,37 @@ mod tests {
         assert_eq!(rendered.unwrap(), "Basic template FirstName");
     }
 
+    #[test]
+    fn test_rendering_template_with_structured_values() {
+        let value = json! {
+            {
+                "items": [{"name": "first"}, {"name": "second"}],
+                "user": {"tags": ["a", "b", "c"]}
+            }
+        };
+
+        let rendered = render_template_prompt(
+            &"There are {{ items.length }} items, {{items.[1].name}} and {{lookup user.tags 0}} of {{length user.tags}}",
+            &value,
+            &HashMap::new(),
+        );
+        assert_eq!(rendered.unwrap(), "There are 2 items, second and a of 3");
+
+        let rendered = render_template_prompt(
+            &"{{#if items.length}}{{#each items}}{{name}};{{/each}}{{/if}}",
+            &value,
+            &HashMap::new(),
+        );
+        assert_eq!(rendered.unwrap(), "first;second;");
+    }
+
+    #[test]
+    fn test_rewrite_length_accessors() {
+        assert_eq!(rewrite_length_accessors("{{ items.length }}"), "{{ length items }}");
+        assert_eq!(rewrite_length_accessors("{{#if a.b.length}}x{{/if}}"), "{{#if (length a.b)}}x{{/if}}");
+        assert_eq!(rewrite_length_accessors("{{! items.length }} {{length}}"), "{{! items.length }} {{length}}");
+    }
+
     #[test]
     fn test_rendering_template_with_roles() {
         let value = json! {
@@ -528,6 +751,36 @@ mod tests {
             "Basic template [FirstName inside partial]"
         );
     }
+
+    #[test]
+    fn test_render_named_template_with_partials() {
+        let partials = HashMap::from([("signature".to_string(), "Regards, {{sender}}".to_string())]);
+        let value = json!({ "user": { "name": "Ada" }, "sender": "Chidori" });
+        let rendered = render_named_template("email", "Hi {{user.name}}. {{> signature}}", &value, &partials, true);
+        assert_eq!(rendered, Ok("Hi Ada. Regards, Chidori".to_string()));
+    }
+
+    #[test]
+    fn test_render_named_template_missing_variable() {
+        let value = json!({ "user": {} });
+        let rendered = render_named_template("email", "Hi {{user.name}}", &value, &HashMap::new(), true);
+        assert!(matches!(
+            rendered,
+            Err(TemplateRenderError::MissingVariable { ref template, path: Some(ref path), .. }) if template == "email" && path == "user.name"
+        ), "{:?}", rendered);
+        // Outside of strict mode missing values render as empty
+        let rendered = render_named_template("email", "Hi {{user.name}}", &value, &HashMap::new(), false);
+        assert_eq!(rendered, Ok("Hi ".to_string()));
+    }
+
+    #[test]
+    fn test_render_named_template_missing_partial() {
+        let rendered = render_named_template("email", "{{> signature}}", &json!({}), &HashMap::new(), true);
+        assert_eq!(rendered, Err(TemplateRenderError::MissingPartial {
+            template: "email".to_string(),
+            partial: "signature".to_string(),
+        }));
+    }
     #[test]
     fn test_extraction_of_variable_references() {
         let template = "Basic template {{var}} {{dot.notation}}";
@@ -682,6 +935,48 @@ Summarize content you are provided with for a second-grade student.
         }
     }
 
+    #[test]
+    fn test_extracting_frontmatter_with_literal_block() {
+        let template_string = indoc! {"
+                ---
+                description: |
+                  Summarises a document.
+                  ---
+                  Sections are separated by rules.
+                ---
+                actual body
+            "};
+        let (frontmatter, body) = split_frontmatter(&template_string).unwrap();
+        assert_eq!(body, "actual body");
+        let value: serde_yaml::Value = serde_yaml::from_str(&frontmatter).unwrap();
+        assert_eq!(
+            value["description"].as_str(),
+            Some("Summarises a document.\n---\nSections are separated by rules.\n")
+        );
+    }
+
+    #[test]
+    fn test_extracting_frontmatter_with_list_and_nested_object() {
+        let template_string = indoc! {"
+                ---
+                examples:
+                - ---
+                - -1
+                model:
+                  name: gpt-4o
+                  temperature: 0.5
+                ---
+                actual body
+            "};
+        let (frontmatter, body) = split_frontmatter(&template_string).unwrap();
+        assert_eq!(body, "actual body");
+        let value: serde_yaml::Value = serde_yaml::from_str(&frontmatter).unwrap();
+        assert_eq!(value["examples"].as_sequence().unwrap().len(), 2);
+        assert_eq!(value["examples"][1].as_i64(), Some(-1));
+        assert_eq!(value["model"]["name"].as_str(), Some("gpt-4o"));
+        assert_eq!(value["model"]["temperature"].as_f64(), Some(0.5));
+    }
+
     #[test]
     fn test_constructing_schema() {
         let schema = referenced_variable_list_to_schema(vec![
@@ -1,7 +1,7 @@
 extern crate swc_ecma_parser;
 
 use crate::language::javascript::parse::ContextPath::Constant;
-use crate::language::{InternalCallGraph, python, TextRange};
+use crate::language::{extract_reads_directives, InternalCallGraph, python, TextRange};
 use crate::language::{Report, ReportItem, ReportTriggerableFunctions};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
@@ -756,8 +756,7 @@ fn traverse_stmts(stmts: &[Stmt], machine: &mut ASTWalkContext) {
     }
 }
 
-pub fn extract_dependencies_js(source: &str) -> Result<Vec<Vec<ContextPath>>, ChidoriStaticAnalysisError> {
-    let mut machine = ASTWalkContext::new();
+fn parse_module(source: &str) -> Result<ast::Module, ChidoriStaticAnalysisError> {
     let cm: Lrc<SourceMap> = Default::default();
     let handler = Handler::with_tty_emitter(ColorConfig::Auto, true, false, Some(cm.clone()));
     let fm = cm.new_source_file(Lrc::new(FileName::Custom("test.js".into())), source.to_string());
@@ -778,31 +777,55 @@ pub fn extract_dependencies_js(source: &str) -> Result<Vec<Vec<ContextPath>>, Ch
         parser.parse_module()
     };
 
-    let module = parse_module(Syntax::Es(Default::default()))
+    parse_module(Syntax::Es(Default::default()))
         .or_else(|_| parse_module(Syntax::Typescript(Default::default())))
-        .map_err(|mut e| {
+        .map_err(|e| {
             // Unrecoverable fatal error occurred
-            e
-            // e.into_diagnostic(&handler).emit()
-        });
-
-    match module {
-        Ok(module) => {
-            for item in module.body {
-                traverse_module(item, &mut machine);
-            }
-            Ok(machine.context_stack_references)
-        },
-        Err(e) => {
-            Err(ChidoriStaticAnalysisError::ParseError {
+            ChidoriStaticAnalysisError::ParseError {
                 msg: format!("{:?}", e),
                 offset: 0,
                 source_path: "".to_string(),
                 source_code: "".to_string(),
-            })
+            }
+        })
+}
 
+pub fn extract_dependencies_js(source: &str) -> Result<Vec<Vec<ContextPath>>, ChidoriStaticAnalysisError> {
+    let mut machine = ASTWalkContext::new();
+    let module = parse_module(source)?;
+    for item in module.body {
+        traverse_module(item, &mut machine);
+    }
+    for name in extract_reads_directives(source, "//") {
+        machine.context_stack_references.push(vec![ContextPath::IdentifierReferredTo {
+            name,
+            in_scope: false,
+            exposed: false,
+        }]);
+    }
+    Ok(machine.context_stack_references)
+}
+
+/// The names of the cells imported as modules by this source, `import { f } from "cell:utils"`
+/// imports the cell named `utils`. Re-exports from a cell are included.
+pub fn extract_cell_imports(source: &str) -> Result<Vec<String>, ChidoriStaticAnalysisError> {
+    let module = parse_module(source)?;
+    let mut imports = vec![];
+    for item in module.body {
+        let ModuleItem::ModuleDecl(mod_decl) = item else { continue; };
+        let specifier = match mod_decl {
+            ModuleDecl::Import(ast::ImportDecl { src, .. }) => Some(src),
+            ModuleDecl::ExportAll(ast::ExportAll { src, .. }) => Some(src),
+            ModuleDecl::ExportNamed(ast::NamedExport { src, .. }) => src,
+            _ => None,
+        };
+        if let Some(name) = specifier.and_then(|src| src.value.strip_prefix("cell:").map(str::to_string)) {
+            if !imports.contains(&name) {
+                imports.push(name);
+            }
         }
     }
+    Ok(imports)
 }
 
 
@@ -828,6 +851,7 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
                         .entry(name.clone())
                         .or_insert_with(|| ReportTriggerableFunctions {
                             arguments: vec![],
+                            argument_types: Default::default(),
                             emit_event: vec![],
                             trigger_on: vec![],
                         });
@@ -851,6 +875,7 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
                         .or_insert_with(|| ReportTriggerableFunctions {
 
                             arguments: vec![],
+                            argument_types: Default::default(),
                             emit_event: vec![], // Initialize with an empty string or a default value
                             trigger_on: vec![],
                         });
@@ -882,6 +907,7 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
                                 .entry(function_name.clone())
                                 .or_insert_with(|| ReportTriggerableFunctions {
                                     arguments: vec![],
+                                    argument_types: Default::default(),
                                     emit_event: vec![], // Initialize with an empty string or a default value
                                     trigger_on: vec![],
                                 });
@@ -958,6 +984,18 @@ mod tests {
     use crate::language::{Report, ReportItem, ReportTriggerableFunctions};
     use indoc::indoc;
 
+    #[test]
+    fn test_extraction_of_cell_imports() {
+        let js_source = indoc! { r#"
+            import { double, LIMIT } from "cell:utils";
+            import * as ch from "@1kbirds/chidori";
+            export * from 'cell:constants';
+            export { triple } from "cell:utils";
+            const y = double(LIMIT);
+        "#};
+        assert_eq!(extract_cell_imports(js_source).unwrap(), vec!["utils".to_string(), "constants".to_string()]);
+    }
+
     #[test]
     fn test_extraction_of_ch_statements() {
         let js_source = indoc! { r#"
@@ -1159,6 +1197,18 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_report_generation_reads_directive() {
+        let js_source = indoc! { r#"
+        // chidori: reads x
+        const y = globalThis["x"] + 1;
+            "#};
+        let context_stack_references = extract_dependencies_js(js_source).unwrap();
+        let result = build_report(&context_stack_references);
+        assert!(result.cell_depended_values.contains_key("x"));
+        assert!(result.cell_exposed_values.contains_key("y"));
+    }
+
     #[test]
     fn test_report_for_simple_function() {
         let js_source = indoc! { r#"
@@ -1267,6 +1317,7 @@ x = random.randint(0, 10)
                     ReportTriggerableFunctions {
 
                         arguments: vec![],
+                        argument_types: Default::default(),
                         emit_event: vec![],
                         trigger_on: vec![],
                     },
@@ -27,6 +27,9 @@ pub struct ReportItem {
 #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ReportTriggerableFunctions {
     pub arguments: Vec<String>,
+    /// The annotated type of each argument that has one, as written in the source e.g. `int`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub argument_types: HashMap<String, String>,
     // pub context_path: Vec<ContextPath>,
     // TODO: these need their own set of depended values
     // TODO: we need to extract signatures for triggerable functions
@@ -125,6 +128,8 @@ pub enum ContextPath {
     InAnonFunction,
     FunctionArguments,
     FunctionArgument(String),
+    // argument name, annotated type
+    ArgumentAnnotation(String, String),
     InClass(String),
     InFunctionDecorator(usize),
     InCallExpression,
@@ -142,6 +147,36 @@ pub enum ContextPath {
 }
 
 
+/// Collects the names listed in `chidori: reads a, b` comment directives. These are an escape hatch
+/// for dependencies that static analysis cannot see, e.g. values only referenced through `eval`.
+pub fn extract_reads_directives(source: &str, comment_prefix: &str) -> Vec<String> {
+    let mut names = vec![];
+    for line in source.lines() {
+        let Some(pos) = line.find(comment_prefix) else {
+            continue;
+        };
+        let comment = line[pos + comment_prefix.len()..].trim();
+        let Some(directive) = comment.strip_prefix("chidori:") else {
+            continue;
+        };
+        let Some(reads) = directive
+            .trim_start()
+            .strip_prefix("reads")
+            .filter(|rest| rest.starts_with(char::is_whitespace))
+        else {
+            continue;
+        };
+        for name in reads.split(',') {
+            let name = name.trim();
+            if !name.is_empty() && !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+
 #[derive(Error, Debug, Serialize, Deserialize)]
 pub enum ChidoriStaticAnalysisError {
     #[error("Unknown chidori analysis error")]
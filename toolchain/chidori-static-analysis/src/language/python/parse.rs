@@ -1,4 +1,4 @@
-use crate::language::{ChidoriStaticAnalysisError, InternalCallGraph, Report, ReportItem, ReportTriggerableFunctions, TextRange};
+use crate::language::{extract_reads_directives, ChidoriStaticAnalysisError, InternalCallGraph, Report, ReportItem, ReportTriggerableFunctions, TextRange};
 use rustpython_parser::ast::{Constant, Expr, Identifier, Stmt};
 use rustpython_parser::{ast, Parse};
 use serde::{Deserialize, Serialize};
@@ -102,6 +102,17 @@ impl ASTWalkContext {
             .push(self.context_stack.clone());
     }
 
+    fn encounter_argument_annotation(&mut self, name: &Identifier, annotation: &Expr) {
+        // Only simple annotations such as `int` or `str` are recorded
+        if let Expr::Name(ast::ExprName { id, .. }) = annotation {
+            self.context_stack
+                .push(ContextPath::ArgumentAnnotation(name.to_string(), id.to_string()));
+            self.context_stack_references
+                .push(self.context_stack.clone());
+            self.context_stack.pop();
+        }
+    }
+
     fn enter_decorator_expression(&mut self, idx: &usize) -> usize {
         self.context_stack
             .push(ContextPath::InFunctionDecorator(idx.clone()));
@@ -187,9 +198,17 @@ fn traverse_expression(expr: &ast::Expr, machine: &mut ASTWalkContext) {
             traverse_expression(value, machine);
         }
         ast::Expr::BinOp(expr) => {
-            let ast::ExprBinOp { left, right, .. } = expr;
+            let ast::ExprBinOp { left, op, right, .. } = expr;
             traverse_expression(left, machine);
             traverse_expression(right, machine);
+            // "%(name)s" % locals() reads every name in the template
+            if let (ast::Operator::Mod, Some(template)) = (op, string_constant(left)) {
+                if is_scope_dict_call(right) {
+                    for name in percent_placeholder_names(template) {
+                        machine.encounter_named_reference(&Identifier::new(name));
+                    }
+                }
+            }
         }
         ast::Expr::UnaryOp(ast::ExprUnaryOp { operand, .. }) => {
             traverse_expression(operand, machine);
@@ -294,6 +313,19 @@ fn traverse_expression(expr: &ast::Expr, machine: &mut ASTWalkContext) {
                 traverse_expression(&keyword.value, machine);
             }
             traverse_expression(func, machine);
+            // "{name}".format(**locals()) reads every name in the template that isn't passed explicitly
+            if let ast::Expr::Attribute(ast::ExprAttribute { value, attr, .. }) = func.as_ref() {
+                if let (true, Some(template)) = (attr.as_str() == "format", string_constant(value)) {
+                    if keywords.iter().any(|k| k.arg.is_none() && is_scope_dict_call(&k.value)) {
+                        for name in format_placeholder_names(template) {
+                            if keywords.iter().any(|k| k.arg.as_ref().map(|a| a.as_str()) == Some(name.as_str())) {
+                                continue;
+                            }
+                            machine.encounter_named_reference(&Identifier::new(name));
+                        }
+                    }
+                }
+            }
             machine.pop_until(idx);
         }
         ast::Expr::FormattedValue(ast::ExprFormattedValue { value, .. }) => {
@@ -349,6 +381,80 @@ fn traverse_expression(expr: &ast::Expr, machine: &mut ASTWalkContext) {
     }
 }
 
+fn string_constant(expr: &ast::Expr) -> Option<&str> {
+    if let ast::Expr::Constant(ast::ExprConstant { value: Constant::Str(s), .. }) = expr {
+        Some(s.as_str())
+    } else {
+        None
+    }
+}
+
+/// A bare `locals()`, `globals()` or `vars()` call hands every name in scope to a formatting operation.
+fn is_scope_dict_call(expr: &ast::Expr) -> bool {
+    if let ast::Expr::Call(ast::ExprCall { func, args, keywords, .. }) = expr {
+        if let ast::Expr::Name(ast::ExprName { id, .. }) = func.as_ref() {
+            return args.is_empty()
+                && keywords.is_empty()
+                && matches!(id.as_str(), "locals" | "globals" | "vars");
+        }
+    }
+    false
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => chars.all(|c| c.is_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+/// Names of the fields referenced by a `str.format` template, e.g. `{article.title}` yields `article`.
+fn format_placeholder_names(template: &str) -> Vec<String> {
+    let mut names = vec![];
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '{' {
+            continue;
+        }
+        if chars.peek() == Some(&'{') {
+            chars.next();
+            continue;
+        }
+        let field: String = chars.by_ref().take_while(|c| *c != '}').collect();
+        let name = field.split(|c| matches!(c, '.' | '[' | '!' | ':')).next().unwrap_or("");
+        if is_identifier(name) && !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+/// Names of the mapping keys referenced by a `%` template, e.g. `%(article)s` yields `article`.
+fn percent_placeholder_names(template: &str) -> Vec<String> {
+    let mut names = vec![];
+    let mut rest = template;
+    while let Some(pos) = rest.find('%') {
+        rest = &rest[pos + 1..];
+        if let Some(stripped) = rest.strip_prefix('%') {
+            rest = stripped;
+            continue;
+        }
+        let Some(stripped) = rest.strip_prefix('(') else {
+            continue;
+        };
+        let Some(end) = stripped.find(')') else {
+            break;
+        };
+        let name = &stripped[..end];
+        if is_identifier(name) && !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+        rest = &stripped[end + 1..];
+    }
+    names
+}
+
 fn extract_python_comments(code: &str) -> Vec<String> {
     let mut comments = Vec::new();
     let mut current_block = Vec::new();
@@ -402,6 +508,13 @@ pub fn extract_dependencies_python(source_code: &str) -> Result<Vec<Vec<ContextP
         })?;
     let mut machine = ASTWalkContext::default();
     traverse_statements(&ast, &mut machine);
+    for name in extract_reads_directives(source_code, "#") {
+        machine.context_stack_references.push(vec![ContextPath::IdentifierReferredTo {
+            name,
+            in_scope: false,
+            exposed: false,
+        }]);
+    }
     Ok(machine.context_stack_references)
 }
 
@@ -455,7 +568,10 @@ pub fn traverse_statements(statements: &[ast::Stmt], machine: &mut ASTWalkContex
                     ..
                 } in &args.args
                 {
-                    if let ast::Arg { arg, .. } = def {
+                    if let ast::Arg { arg, annotation, .. } = def {
+                        if let Some(annotation) = annotation {
+                            machine.encounter_argument_annotation(arg, annotation);
+                        }
                         machine.encounter_named_reference(arg);
                     }
                 }
@@ -492,7 +608,10 @@ pub fn traverse_statements(statements: &[ast::Stmt], machine: &mut ASTWalkContex
                     ..
                 } in &args.args
                 {
-                    if let ast::Arg { arg, .. } = def {
+                    if let ast::Arg { arg, annotation, .. } = def {
+                        if let Some(annotation) = annotation {
+                            machine.encounter_argument_annotation(arg, annotation);
+                        }
                         machine.encounter_named_reference(arg);
                     }
                 }
@@ -699,6 +818,7 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
                             
                             // context_path: context_path.clone(),
                             arguments: vec![],
+                            argument_types: Default::default(),
                             emit_event: vec![],
                             trigger_on: vec![],
                         });
@@ -715,6 +835,7 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
                             .entry(function_name.clone())
                             .or_insert_with(|| ReportTriggerableFunctions {
                                 arguments: vec![],
+                                argument_types: Default::default(),
                                 emit_event: vec![], // Initialize with an empty string or a default value
                                 trigger_on: vec![],
                             });
@@ -723,6 +844,16 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
                 }
             }
 
+            // Annotations of function arguments are recorded as their types
+            if let ContextPath::ArgumentAnnotation(name, annotation) = context_path_unit {
+                if let Some(ContextPath::InFunction(function_name, _)) = encountered.iter().rev().find(|x| matches!(x, ContextPath::InFunction(_, _))) {
+                    let x = triggerable_functions
+                        .entry(function_name.clone())
+                        .or_insert_with(|| ReportTriggerableFunctions::default());
+                    x.argument_types.insert(name.clone(), annotation.clone());
+                }
+            }
+
             // If an identifier is referred to, and it has not been assigned to earlier during our interpreting
             if let ContextPath::IdentifierReferredTo{name: identifier, exposed: false, in_scope: false} = context_path_unit {
                 // If we encounter both FunctionArguments and InFunction, then this is a function argument
@@ -735,6 +866,7 @@ pub fn build_report(context_paths: &Vec<Vec<ContextPath>>) -> Report {
                                 .entry(function_name.clone())
                                 .or_insert_with(|| ReportTriggerableFunctions {
                                     arguments: vec![],
+                                    argument_types: Default::default(),
                                     emit_event: vec![], // Initialize with an empty string or a default value
                                     trigger_on: vec![],
                                 });
@@ -1005,6 +1137,7 @@ mod tests {
                         
                         // context_path: vec![ContextPath::InFunction("testing".to_string())],
                         arguments: vec![],
+                        argument_types: Default::default(),
                         emit_event: vec![],
                         trigger_on: vec![],
                     },
@@ -1067,6 +1200,7 @@ x = random.randint(0, 10)
                         
                         // context_path: vec![ContextPath::InFunction("fun_name".to_string())],
                         arguments: vec![],
+                        argument_types: Default::default(),
                         emit_event: vec![],
                         trigger_on: vec![],
                     },
@@ -1107,6 +1241,7 @@ x = random.randint(0, 10)
 
                         // context_path: vec![ContextPath::InFunction("testing".to_string())],
                         arguments: vec!["a", "b", "c", "d"].into_iter().map(|a| a.to_string()).collect(),
+                        argument_types: Default::default(),
                         emit_event: vec![],
                         trigger_on: vec![],
                     },
@@ -1119,6 +1254,20 @@ x = random.randint(0, 10)
         Ok(())
     }
 
+    #[test]
+    fn test_report_generation_function_with_annotated_arguments() -> anyhow::Result<()>  {
+        let python_source = indoc! { r#"
+        def add(x: int, y: int, label: "str" = "sum"):
+            return x + y
+            "#};
+        let context_stack_references = extract_dependencies_python(python_source).map_err(|e| anyhow::Error::msg(format!("{:?}", e)))?;
+        let result = build_report(&context_stack_references);
+        let add = &result.triggerable_functions["add"];
+        assert_eq!(add.arguments, vec!["x", "y", "label"].into_iter().map(|a| a.to_string()).collect::<Vec<_>>());
+        assert_eq!(add.argument_types, vec![("x", "int"), ("y", "int")].into_iter().map(|(a, t)| (a.to_string(), t.to_string())).collect::<HashMap<_, _>>());
+        Ok(())
+    }
+
     #[test]
     fn test_report_generation_for_loop_variable_assignment() -> anyhow::Result<()>  {
         let python_source = indoc! { r#"
@@ -1171,6 +1320,49 @@ x = random.randint(0, 10)
         Ok(())
     }
 
+    #[test]
+    fn test_report_generation_fstring_nested_in_call() -> anyhow::Result<()> {
+        let python_source = indoc! { r#"
+            summary = summarize(f"Summarize {article} in {len(words)} words")
+            "#};
+        let context_stack_references = extract_dependencies_python(python_source).map_err(|e| anyhow::Error::msg(format!("{:?}", e)))?;
+        let result = build_report(&context_stack_references);
+        assert!(result.cell_exposed_values.contains_key("summary"));
+        assert!(result.cell_depended_values.contains_key("article"));
+        assert!(result.cell_depended_values.contains_key("words"));
+        assert!(result.cell_depended_values.contains_key("summarize"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_generation_format_with_locals() -> anyhow::Result<()> {
+        let python_source = indoc! { r#"
+            title = "Report"
+            a = "{title}: {article.body} ({count}, {{literal}})".format(count=3, **locals())
+            b = "%(corpus)s and %%(escaped)s" % locals()
+            "#};
+        let context_stack_references = extract_dependencies_python(python_source).map_err(|e| anyhow::Error::msg(format!("{:?}", e)))?;
+        let result = build_report(&context_stack_references);
+        let mut depended: Vec<_> = result.cell_depended_values.keys().cloned().collect();
+        depended.sort();
+        assert_eq!(depended, vec!["article".to_string(), "corpus".to_string()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_generation_reads_directive() -> anyhow::Result<()> {
+        let python_source = indoc! { r#"
+            # chidori: reads article, corpus
+            result = eval("article + corpus")
+            "#};
+        let context_stack_references = extract_dependencies_python(python_source).map_err(|e| anyhow::Error::msg(format!("{:?}", e)))?;
+        let result = build_report(&context_stack_references);
+        assert!(result.cell_depended_values.contains_key("article"));
+        assert!(result.cell_depended_values.contains_key("corpus"));
+        assert!(result.cell_exposed_values.contains_key("result"));
+        Ok(())
+    }
+
     #[test]
     fn test_report_generation_with_class() -> anyhow::Result<()>  {
         let python_source = indoc! { r#"
@@ -1211,6 +1403,7 @@ x = random.randint(0, 10)
                     "test_addTwo".to_string(),
                     ReportTriggerableFunctions {
                         arguments: vec!["self".to_string()],
+                        argument_types: Default::default(),
                         emit_event: vec![],
                         trigger_on: vec![],
                     },